@@ -0,0 +1,298 @@
+use clap::{value_t_or_exit, App, Arg};
+use failure::ResultExt;
+use hdrhistogram::Histogram;
+use noria::consensus::Authority;
+use noria::{ControllerHandle, ZookeeperAuthority};
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod workloads;
+use self::workloads::{Keys, Request, Workload};
+
+/// Latencies (in microseconds) of the requests of one kind completed in each second of the run.
+type Samples = Vec<Histogram<u64>>;
+
+struct Settings {
+    workload: Workload,
+    keys: usize,
+    skew: f64,
+    read_ratio: f64,
+    clients: usize,
+    warmup: Duration,
+    runtime: Duration,
+    prime: bool,
+}
+
+fn record(samples: &mut Samples, second: usize, took: Duration) {
+    while samples.len() <= second {
+        samples.push(Histogram::new_with_bounds(1, 60_000_000, 3).unwrap());
+    }
+    samples[second].saturating_record(took.as_micros() as u64);
+}
+
+fn merge(into: &mut Samples, from: Samples) {
+    for (second, h) in from.into_iter().enumerate() {
+        if second < into.len() {
+            into[second].add(&h).expect("same bounds");
+        } else {
+            into.push(h);
+        }
+    }
+}
+
+async fn client<A: Authority + 'static>(
+    mut ch: ControllerHandle<A>,
+    settings: Arc<Settings>,
+    next_id: Arc<AtomicUsize>,
+) -> Result<(Samples, Samples), failure::Error> {
+    let mut rng = StdRng::from_entropy();
+    let keys = Keys::new(settings.keys, settings.skew);
+    let mut views = HashMap::new();
+    let mut tables = HashMap::new();
+    let (mut reads, mut writes) = (Vec::new(), Vec::new());
+
+    let start = Instant::now();
+    let end = start + settings.warmup + settings.runtime;
+    while Instant::now() < end {
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        let req = settings
+            .workload
+            .next(&mut rng, &keys, settings.read_ratio, id);
+        let issued = Instant::now();
+        let samples = match req {
+            Request::Read(view, key) => {
+                if !views.contains_key(view) {
+                    views.insert(view, ch.view(view).await?);
+                }
+                views.get_mut(view).unwrap().lookup(&key, true).await?;
+                &mut reads
+            }
+            Request::Write(writes_to) => {
+                for (table, rows) in writes_to {
+                    if !tables.contains_key(table) {
+                        tables.insert(table, ch.table(table).await?);
+                    }
+                    tables.get_mut(table).unwrap().perform_all(rows).await?;
+                }
+                &mut writes
+            }
+        };
+
+        if let Some(since) = issued.checked_duration_since(start + settings.warmup) {
+            record(samples, since.as_secs() as usize, issued.elapsed());
+        }
+    }
+
+    Ok((reads, writes))
+}
+
+async fn run<A: Authority + 'static>(
+    mut ch: ControllerHandle<A>,
+    settings: Settings,
+) -> Result<(Samples, Samples), failure::Error> {
+    if settings.prime {
+        eprintln!("installing {:?} recipe", settings.workload);
+        ch.install_recipe(settings.workload.recipe())
+            .await
+            .context("failed to install recipe")?;
+        for (table, rows) in settings.workload.prime(settings.keys) {
+            eprintln!("priming {} with {} rows", table, rows.len());
+            ch.table(table)
+                .await?
+                .perform_all(rows)
+                .await
+                .context("failed to prime table")?;
+        }
+    }
+
+    eprintln!("running {} clients", settings.clients);
+    let settings = Arc::new(settings);
+    let next_id = Arc::new(AtomicUsize::new(settings.keys + 1));
+    let clients: Vec<_> = (0..settings.clients)
+        .map(|_| tokio::spawn(client(ch.clone(), settings.clone(), next_id.clone())))
+        .collect();
+
+    let (mut reads, mut writes) = (Vec::new(), Vec::new());
+    for c in clients {
+        let (r, w) = c.await??;
+        merge(&mut reads, r);
+        merge(&mut writes, w);
+    }
+    Ok((reads, writes))
+}
+
+fn write_csv<W: Write>(out: &mut W, reads: &Samples, writes: &Samples) -> std::io::Result<()> {
+    writeln!(out, "second,op,count,p50_us,p90_us,p99_us,max_us")?;
+    for second in 0..reads.len().max(writes.len()) {
+        for (op, samples) in &[("read", reads), ("write", writes)] {
+            if let Some(h) = samples.get(second) {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    second,
+                    op,
+                    h.len(),
+                    h.value_at_quantile(0.5),
+                    h.value_at_quantile(0.9),
+                    h.value_at_quantile(0.99),
+                    h.max()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn summarize(op: &str, samples: &Samples, runtime: Duration) {
+    let mut all = Histogram::<u64>::new_with_bounds(1, 60_000_000, 3).unwrap();
+    for h in samples {
+        all.add(h).expect("same bounds");
+    }
+    eprintln!(
+        "{}: {:.0} ops/s, p50 {}us, p99 {}us, max {}us",
+        op,
+        all.len() as f64 / runtime.as_secs_f64(),
+        all.value_at_quantile(0.5),
+        all.value_at_quantile(0.99),
+        all.max()
+    );
+}
+
+fn main() {
+    let args = App::new("noria-bench")
+        .version("0.1")
+        .about("Runs a standard workload against Noria, and reports latency and throughput.")
+        .arg(
+            Arg::with_name("workload")
+                .long("workload")
+                .short("w")
+                .takes_value(true)
+                .possible_values(&["vote", "lobsters", "tpcc"])
+                .default_value("vote")
+                .help("Workload to run"),
+        )
+        .arg(
+            Arg::with_name("keys")
+                .long("keys")
+                .takes_value(true)
+                .default_value("100000")
+                .help("Number of distinct keys (articles, stories, or customers)"),
+        )
+        .arg(
+            Arg::with_name("skew")
+                .long("skew")
+                .takes_value(true)
+                .default_value("0")
+                .help("Zipf exponent of the key distribution (0 for uniform)"),
+        )
+        .arg(
+            Arg::with_name("read-ratio")
+                .long("read-ratio")
+                .takes_value(true)
+                .default_value("0.95")
+                .help("Fraction of requests that are reads"),
+        )
+        .arg(
+            Arg::with_name("clients")
+                .long("clients")
+                .short("c")
+                .takes_value(true)
+                .default_value("16")
+                .help("Number of concurrent clients"),
+        )
+        .arg(
+            Arg::with_name("warmup")
+                .long("warmup")
+                .takes_value(true)
+                .default_value("10")
+                .help("Seconds to run before measuring"),
+        )
+        .arg(
+            Arg::with_name("runtime")
+                .long("runtime")
+                .short("r")
+                .takes_value(true)
+                .default_value("30")
+                .help("Seconds to measure for"),
+        )
+        .arg(
+            Arg::with_name("shards")
+                .long("shards")
+                .takes_value(true)
+                .help("Shard the graph this many ways (local deployments only)"),
+        )
+        .arg(
+            Arg::with_name("zookeeper")
+                .long("zookeeper")
+                .short("z")
+                .takes_value(true)
+                .help("Run against the deployment managed by this Zookeeper, rather than locally"),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .takes_value(true)
+                .default_value("bench")
+                .help("Name of the remote deployment"),
+        )
+        .arg(
+            Arg::with_name("no-prime")
+                .long("no-prime")
+                .help("Assume the remote deployment is already set up and populated"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help("Write per-second latencies and throughput to this CSV file"),
+        )
+        .get_matches();
+
+    let workload = Workload::from_name(args.value_of("workload").unwrap()).unwrap();
+    let settings = Settings {
+        workload,
+        keys: value_t_or_exit!(args, "keys", usize),
+        skew: value_t_or_exit!(args, "skew", f64),
+        read_ratio: value_t_or_exit!(args, "read-ratio", f64),
+        clients: value_t_or_exit!(args, "clients", usize),
+        warmup: Duration::from_secs(value_t_or_exit!(args, "warmup", u64)),
+        runtime: Duration::from_secs(value_t_or_exit!(args, "runtime", u64)),
+        prime: !args.is_present("no-prime"),
+    };
+    let runtime = settings.runtime;
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let (reads, writes) = rt
+        .block_on(async {
+            if let Some(zk) = args.value_of("zookeeper") {
+                let zk = format!("{}/{}", zk, args.value_of("deployment").unwrap());
+                let zk = ZookeeperAuthority::new(&zk)?;
+                let ch = ControllerHandle::new(zk).await?;
+                run(ch, settings).await
+            } else {
+                let mut builder = noria::Builder::default();
+                if let Some(shards) = args.value_of("shards") {
+                    builder.set_sharding(Some(shards.parse().expect("shards must be a number")));
+                }
+                let (g, _) = builder.start_local().await?;
+                let ch = (*g).clone();
+                let res = run(ch, settings).await;
+                drop(g);
+                res
+            }
+        })
+        .unwrap();
+
+    summarize("reads", &reads, runtime);
+    summarize("writes", &writes, runtime);
+    if let Some(output) = args.value_of("output") {
+        let mut f = File::create(output).expect("failed to create output file");
+        write_csv(&mut f, &reads, &writes).expect("failed to write output file");
+    }
+}
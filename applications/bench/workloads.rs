@@ -0,0 +1,186 @@
+use noria::DataType;
+use rand::prelude::*;
+
+/// A single request issued by a benchmark client.
+pub(crate) enum Request {
+    /// Look up `key` in the view with the given name.
+    Read(&'static str, Vec<DataType>),
+    /// Insert the given rows into each of the named tables.
+    Write(Vec<(&'static str, Vec<Vec<DataType>>)>),
+}
+
+/// Picks keys in `1..=n`, either uniformly or following a Zipf distribution.
+#[derive(Clone)]
+pub(crate) enum Keys {
+    Uniform(usize),
+    Zipf(zipf::ZipfDistribution),
+}
+
+impl Keys {
+    pub(crate) fn new(n: usize, skew: f64) -> Self {
+        if skew > 0.0 {
+            Keys::Zipf(zipf::ZipfDistribution::new(n, skew).unwrap())
+        } else {
+            Keys::Uniform(n)
+        }
+    }
+
+    pub(crate) fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match *self {
+            Keys::Uniform(n) => rng.gen_range(1, n + 1),
+            Keys::Zipf(ref z) => z.sample(rng),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Workload {
+    /// The article voting workload from the Noria paper.
+    Vote,
+    /// A cut-down version of the Lobsters news aggregator: stories, votes, and comments.
+    Lobsters,
+    /// The new-order, payment, and order-status transactions of TPC-C, without transactions.
+    TpcC,
+}
+
+impl Workload {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vote" => Some(Workload::Vote),
+            "lobsters" => Some(Workload::Lobsters),
+            "tpcc" => Some(Workload::TpcC),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn recipe(self) -> &'static str {
+        match self {
+            Workload::Vote => {
+                "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+                 CREATE TABLE Vote (article_id int, user int);
+                 VoteCount: SELECT Vote.article_id, COUNT(user) AS votes \
+                            FROM Vote GROUP BY Vote.article_id;
+                 QUERY ArticleWithVoteCount: \
+                            SELECT Article.id, title, VoteCount.votes AS votes \
+                            FROM Article LEFT JOIN VoteCount \
+                                         ON (Article.id = VoteCount.article_id) \
+                            WHERE Article.id = ?;"
+            }
+            Workload::Lobsters => {
+                "CREATE TABLE stories (id int, user_id int, title text, PRIMARY KEY(id));
+                 CREATE TABLE votes (story_id int, user_id int);
+                 CREATE TABLE comments (id int, story_id int, user_id int, body text, \
+                                        PRIMARY KEY(id));
+                 StoryVotes: SELECT votes.story_id, COUNT(votes.user_id) AS score \
+                             FROM votes GROUP BY votes.story_id;
+                 QUERY StoryWithScore: \
+                             SELECT stories.id, stories.title, StoryVotes.score \
+                             FROM stories LEFT JOIN StoryVotes \
+                                          ON (stories.id = StoryVotes.story_id) \
+                             WHERE stories.id = ?;
+                 QUERY StoryComments: \
+                             SELECT comments.id, comments.user_id, comments.body \
+                             FROM comments WHERE comments.story_id = ?;"
+            }
+            Workload::TpcC => {
+                "CREATE TABLE customer (c_id int, c_name varchar(16), PRIMARY KEY(c_id));
+                 CREATE TABLE orders (o_id int, o_c_id int, o_ol_cnt int, PRIMARY KEY(o_id));
+                 CREATE TABLE order_line (ol_o_id int, ol_number int, ol_i_id int, \
+                                          ol_quantity int);
+                 CREATE TABLE history (h_c_id int, h_amount int);
+                 QUERY OrderStatus: SELECT o_id, o_ol_cnt FROM orders WHERE o_c_id = ?;
+                 QUERY CustomerPayments: \
+                             SELECT h_c_id, SUM(h_amount) AS paid FROM history \
+                             WHERE h_c_id = ? GROUP BY h_c_id;
+                 QUERY ItemDemand: \
+                             SELECT ol_i_id, SUM(ol_quantity) AS demand FROM order_line \
+                             WHERE ol_i_id = ? GROUP BY ol_i_id;"
+            }
+        }
+    }
+
+    /// The rows to load before the benchmark starts, by table.
+    pub(crate) fn prime(self, keys: usize) -> Vec<(&'static str, Vec<Vec<DataType>>)> {
+        let rows = |f: &dyn Fn(usize) -> Vec<DataType>| (1..=keys).map(f).collect();
+        match self {
+            Workload::Vote => vec![(
+                "Article",
+                rows(&|i| vec![i.into(), format!("Article #{}", i).into()]),
+            )],
+            Workload::Lobsters => vec![(
+                "stories",
+                rows(&|i| vec![i.into(), (i % 100).into(), format!("Story #{}", i).into()]),
+            )],
+            Workload::TpcC => vec![(
+                "customer",
+                rows(&|i| vec![i.into(), format!("Customer #{}", i).into()]),
+            )],
+        }
+    }
+
+    /// Generate the next request. `id` is unique to this request across all clients, and can be
+    /// used for primary keys.
+    pub(crate) fn next<R: Rng>(
+        self,
+        rng: &mut R,
+        keys: &Keys,
+        read_ratio: f64,
+        id: usize,
+    ) -> Request {
+        let key = keys.sample(rng);
+        let read = rng.gen_bool(read_ratio);
+        match self {
+            Workload::Vote => {
+                if read {
+                    Request::Read("ArticleWithVoteCount", vec![key.into()])
+                } else {
+                    Request::Write(vec![("Vote", vec![vec![key.into(), id.into()]])])
+                }
+            }
+            Workload::Lobsters => match (read, rng.gen_bool(0.7)) {
+                (true, true) => Request::Read("StoryWithScore", vec![key.into()]),
+                (true, false) => Request::Read("StoryComments", vec![key.into()]),
+                (false, true) => Request::Write(vec![("votes", vec![vec![key.into(), id.into()]])]),
+                (false, false) => Request::Write(vec![(
+                    "comments",
+                    vec![vec![
+                        id.into(),
+                        key.into(),
+                        rng.gen_range(0i32, 100).into(),
+                        "a comment".into(),
+                    ]],
+                )]),
+            },
+            Workload::TpcC => {
+                if read {
+                    if rng.gen_bool(0.5) {
+                        Request::Read("OrderStatus", vec![key.into()])
+                    } else if rng.gen_bool(0.5) {
+                        Request::Read("CustomerPayments", vec![key.into()])
+                    } else {
+                        Request::Read("ItemDemand", vec![keys.sample(rng).into()])
+                    }
+                } else if rng.gen_bool(0.5) {
+                    // new-order: an order with 5-15 lines
+                    let lines: usize = rng.gen_range(5, 16);
+                    let order = vec![vec![id.into(), key.into(), lines.into()]];
+                    let order_lines = (0..lines)
+                        .map(|n| {
+                            vec![
+                                id.into(),
+                                n.into(),
+                                keys.sample(rng).into(),
+                                rng.gen_range(1i32, 11).into(),
+                            ]
+                        })
+                        .collect();
+                    Request::Write(vec![("orders", order), ("order_line", order_lines)])
+                } else {
+                    // payment
+                    let amount: i32 = rng.gen_range(1, 5000);
+                    Request::Write(vec![("history", vec![vec![key.into(), amount.into()]])])
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,518 @@
+//! A bridge that lets browsers subscribe to Noria query results over WebSockets.
+//!
+//! Clients connect to the bridge with a WebSocket and exchange JSON text frames with it. Each
+//! frame is an object whose `type` field says what it is. A client sends
+//!
+//!  - `{"type": "auth", "token": "..."}` to present a token to the authorization hook,
+//!  - `{"type": "subscribe", "id": 1, "view": "ArticleById", "key": [1]}` to subscribe to the
+//!    rows of `key` in `view` under an id of its choosing, and
+//!  - `{"type": "unsubscribe", "id": 1}` to end a subscription.
+//!
+//! The bridge replies with `{"type": "rows", "id": 1, "rows": [{"aid": 1, ...}]}` once the rows
+//! for a subscription are available, and again every time they change. Rows are objects keyed by
+//! column name. Problems are reported with `{"type": "error", "id": 1, "message": "..."}`, where
+//! `id` is `null` if the problem isn't tied to a subscription; a subscription that fails is
+//! ended.
+//!
+//! The bridge checks for changes at a fixed interval, and only sends rows that differ from the
+//! last rows it sent. For fully materialized views, it follows the changes that each shard of the
+//! view keeps (see [`View::changes`]), and only looks up the subscribed keys that changed.
+//! Partially materialized views do not keep their changes, so every key subscribed to in them is
+//! looked up again at every interval: a client then costs a lookup per subscription per interval,
+//! even if nothing changes. To keep that in check, each client may only have so many
+//! subscriptions (see [`Bridge::set_max_subscriptions`]). Lookups do not block, so subscribing to
+//! a key that is not yet materialized triggers a replay, and the rows are sent once it completes.
+//!
+//! Which subscriptions a client may make is decided by an [`Authorize`] hook, which is asked
+//! about every key a client subscribes to.
+#![deny(missing_docs)]
+#![warn(rust_2018_idioms)]
+
+#[macro_use]
+extern crate serde_derive;
+
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use noria::consensus::Authority;
+use noria::error::ViewError;
+use noria::{ChangeCursor, ControllerHandle, DataType, View, ViewChanges};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+
+/// How many subscriptions a client may have by default.
+pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 1000;
+
+/// The most changes read from each shard of a view every poll interval. Any further changes are
+/// read at the next interval.
+const CHANGES_PER_POLL: usize = 1024;
+
+/// Decides which keys of which views a client may subscribe to.
+///
+/// Any `Fn(Option<&str>, &str, &[DataType]) -> bool` can be used as a hook, in which case it is
+/// called with the client's token (if it has sent one), the name of the view, and the key.
+pub trait Authorize: Send + Sync + 'static {
+    /// Whether a client that presented `token` may subscribe to `key` in `view`.
+    fn subscribe(&self, token: Option<&str>, view: &str, key: &[DataType]) -> bool;
+}
+
+impl<F> Authorize for F
+where
+    F: Fn(Option<&str>, &str, &[DataType]) -> bool + Send + Sync + 'static,
+{
+    fn subscribe(&self, token: Option<&str>, view: &str, key: &[DataType]) -> bool {
+        (self)(token, view, key)
+    }
+}
+
+/// A frame sent by a client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Auth {
+        token: String,
+    },
+    Subscribe {
+        id: u64,
+        view: String,
+        key: Vec<Value>,
+    },
+    Unsubscribe {
+        id: u64,
+    },
+}
+
+/// A frame sent to a client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    Rows {
+        id: u64,
+        rows: Vec<Map<String, Value>>,
+    },
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+}
+
+struct Subscription {
+    view: String,
+    key: Vec<DataType>,
+    /// The rows last sent to the client, sorted so that reordering alone isn't reported.
+    last: Option<Vec<Vec<DataType>>>,
+}
+
+/// A view that a client has subscribed to, and how far its changes have been followed.
+struct Followed {
+    view: View,
+    /// Where to continue reading the changes of each shard from, or `None` if the view does not
+    /// keep its changes, and all its subscribed keys have to be looked up again every time.
+    cursors: Option<Vec<Option<ChangeCursor>>>,
+}
+
+impl Followed {
+    fn new(view: View) -> Self {
+        let cursors = Some(vec![None; view.shards()]);
+        Followed { view, cursors }
+    }
+
+    /// The keys of the view that may have changed since the last call, or `None` if any of them
+    /// may have.
+    async fn changed_keys(&mut self) -> Option<HashSet<Vec<DataType>>> {
+        // the values of echoed parameters don't show up in rows, so rows can't be matched to keys
+        let unmatched = !self.view.echoed().is_empty() || self.view.key_columns().is_empty();
+        let mut keys = HashSet::new();
+        let mut all = unmatched;
+        let mut kept = true;
+        let cursors = self.cursors.as_mut()?;
+        for (shard, cursor) in cursors.iter_mut().enumerate() {
+            match self.view.changes(shard, *cursor, CHANGES_PER_POLL).await {
+                Ok(ViewChanges::Since { rows, next }) => {
+                    *cursor = Some(next);
+                    let key_columns = self.view.key_columns();
+                    keys.extend(
+                        rows.into_iter()
+                            .map(|(row, _)| key_columns.iter().map(|&c| row[c].clone()).collect()),
+                    );
+                }
+                Ok(ViewChanges::Lost { next }) => {
+                    *cursor = Some(next);
+                    all = true;
+                }
+                Err(ViewError::NoChanges) => {
+                    kept = false;
+                    break;
+                }
+                Err(_) => {
+                    // the lookups will report the problem
+                    *cursor = None;
+                    all = true;
+                }
+            }
+        }
+        if !kept {
+            // partially materialized views don't keep their changes
+            self.cursors = None;
+            None
+        } else if all {
+            None
+        } else {
+            Some(keys)
+        }
+    }
+}
+
+fn to_json(v: &DataType) -> Value {
+    match *v {
+        DataType::None => Value::Null,
+        DataType::Int(n) => n.into(),
+        DataType::UnsignedInt(n) => n.into(),
+        DataType::BigInt(n) => n.into(),
+        DataType::UnsignedBigInt(n) => n.into(),
+        DataType::Real(..) => serde_json::Number::from_f64(v.into())
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        DataType::Text(..) | DataType::TinyText(..) => {
+            let s: &str = v.into();
+            s.into()
+        }
+        DataType::Timestamp(ts) => ts.format("%Y-%m-%dT%H:%M:%S%.f").to_string().into(),
+    }
+}
+
+fn from_json(v: Value) -> Result<DataType, String> {
+    Ok(match v {
+        Value::Null => DataType::None,
+        Value::Bool(b) => (b as i32).into(),
+        Value::Number(n) => {
+            if let Some(n) = n.as_i64() {
+                n.into()
+            } else if let Some(n) = n.as_u64() {
+                n.into()
+            } else {
+                n.as_f64().unwrap().into()
+            }
+        }
+        Value::String(s) => s.into(),
+        Value::Array(_) | Value::Object(_) => {
+            return Err("keys cannot contain nested values".to_owned())
+        }
+    })
+}
+
+/// Check that `key` has as many values as lookups into `view` expect.
+fn check_key(view: &str, expected: usize, key: &[DataType]) -> Result<(), String> {
+    if key.is_empty() {
+        Err("keys must have at least one value".to_owned())
+    } else if key.len() != expected {
+        Err(format!(
+            "{} is keyed by {} value(s), but the key has {}",
+            view,
+            expected,
+            key.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Serves WebSocket clients on behalf of a Noria deployment.
+pub struct Bridge<A: 'static + Authority> {
+    handle: ControllerHandle<A>,
+    auth: Arc<dyn Authorize>,
+    poll_interval: Duration,
+    max_subscriptions: usize,
+}
+
+impl<A: 'static + Authority> Bridge<A> {
+    /// Create a bridge that reads through the given handle.
+    ///
+    /// By default, any client may make up to `DEFAULT_MAX_SUBSCRIPTIONS` subscriptions to any
+    /// keys, and subscriptions are checked for changes every 100ms.
+    pub fn new(handle: ControllerHandle<A>) -> Self {
+        Bridge {
+            handle,
+            auth: Arc::new(|_: Option<&str>, _: &str, _: &[DataType]| true),
+            poll_interval: Duration::from_millis(100),
+            max_subscriptions: DEFAULT_MAX_SUBSCRIPTIONS,
+        }
+    }
+
+    /// Set the hook that decides which subscriptions clients may make.
+    pub fn set_authorizer(&mut self, auth: impl Authorize) {
+        self.auth = Arc::new(auth);
+    }
+
+    /// Set how often subscriptions are checked for changes.
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Set how many subscriptions each client may have at once.
+    ///
+    /// Keys in partially materialized views are looked up at every poll interval, so this bounds
+    /// the lookups each client makes.
+    pub fn set_max_subscriptions(&mut self, max: usize) {
+        self.max_subscriptions = max;
+    }
+
+    /// Accept WebSocket clients on the given listener until it fails.
+    pub async fn serve(self, mut listener: TcpListener) -> Result<(), failure::Error>
+    where
+        A: Send + Sync,
+    {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let client = Client {
+                handle: self.handle.clone(),
+                auth: Arc::clone(&self.auth),
+                token: None,
+                views: HashMap::new(),
+                subscriptions: HashMap::new(),
+                max_subscriptions: self.max_subscriptions,
+            };
+            let interval = self.poll_interval;
+            tokio::spawn(async move {
+                if let Err(e) = client.run(stream, interval).await {
+                    tracing::debug!(%addr, error = %e, "websocket client failed");
+                }
+            });
+        }
+    }
+}
+
+/// The state of a single WebSocket connection.
+struct Client<A: 'static + Authority> {
+    handle: ControllerHandle<A>,
+    auth: Arc<dyn Authorize>,
+    token: Option<String>,
+    views: HashMap<String, Followed>,
+    subscriptions: HashMap<u64, Subscription>,
+    max_subscriptions: usize,
+}
+
+impl<A: 'static + Authority> Client<A> {
+    async fn run(mut self, stream: TcpStream, interval: Duration) -> Result<(), failure::Error> {
+        let ws = tokio_tungstenite::accept_async(stream).await?;
+        let (mut tx, mut rx) = ws.split();
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            let responses = tokio::select! {
+                msg = rx.next() => {
+                    let msg = match msg {
+                        Some(msg) => msg?,
+                        None => return Ok(()),
+                    };
+                    match msg {
+                        Message::Text(text) => self.handle_request(&text).await,
+                        Message::Close(_) => return Ok(()),
+                        _ => continue,
+                    }
+                }
+                _ = ticks.tick() => self.poll_all().await,
+            };
+            for r in responses {
+                tx.send(Message::Text(serde_json::to_string(&r)?)).await?;
+            }
+        }
+    }
+
+    async fn handle_request(&mut self, text: &str) -> Vec<Response> {
+        let request = match serde_json::from_str(text) {
+            Ok(r) => r,
+            Err(e) => {
+                return vec![Response::Error {
+                    id: None,
+                    message: format!("malformed request: {}", e),
+                }]
+            }
+        };
+        match request {
+            Request::Auth { token } => {
+                self.token = Some(token);
+                Vec::new()
+            }
+            Request::Unsubscribe { id } => {
+                self.subscriptions.remove(&id);
+                Vec::new()
+            }
+            Request::Subscribe { id, view, key } => {
+                let error = |message| {
+                    vec![Response::Error {
+                        id: Some(id),
+                        message,
+                    }]
+                };
+                if !self.subscriptions.contains_key(&id)
+                    && self.subscriptions.len() >= self.max_subscriptions
+                {
+                    return error(format!(
+                        "clients may have at most {} subscriptions",
+                        self.max_subscriptions
+                    ));
+                }
+                let key = match key
+                    .into_iter()
+                    .map(from_json)
+                    .collect::<Result<Vec<_>, _>>()
+                {
+                    Ok(key) => key,
+                    Err(e) => return error(e),
+                };
+                if !self.auth.subscribe(self.token.as_deref(), &view, &key) {
+                    return error(format!("not authorized to subscribe to {}", view));
+                }
+                if !self.views.contains_key(&view) {
+                    match self.handle.view(&view).await {
+                        Ok(v) => {
+                            self.views.insert(view.clone(), Followed::new(v));
+                        }
+                        Err(e) => return error(e.to_string()),
+                    }
+                }
+                let v = &self.views[&view].view;
+                let expected = v.echoed().len() + v.key_columns().len();
+                if let Err(e) = check_key(&view, expected, &key) {
+                    return error(e);
+                }
+
+                let mut sub = Subscription {
+                    view,
+                    key,
+                    last: None,
+                };
+                let response = self.poll(id, &mut sub).await;
+                if let Some(Response::Error { .. }) = response {
+                    // the subscription is dead already
+                } else {
+                    self.subscriptions.insert(id, sub);
+                }
+                response.into_iter().collect()
+            }
+        }
+    }
+
+    async fn poll_all(&mut self) -> Vec<Response> {
+        let mut changed = HashMap::new();
+        for sub in self.subscriptions.values() {
+            if !changed.contains_key(&sub.view) {
+                let followed = self.views.get_mut(&sub.view).unwrap();
+                changed.insert(sub.view.clone(), followed.changed_keys().await);
+            }
+        }
+
+        let mut subscriptions = std::mem::take(&mut self.subscriptions);
+        let mut responses = Vec::new();
+        for (&id, sub) in &mut subscriptions {
+            // keys that missed last time are looked up until their rows arrive
+            let stale = sub.last.is_none()
+                || match changed[&sub.view] {
+                    Some(ref keys) => keys.contains(&sub.key),
+                    None => true,
+                };
+            if stale {
+                responses.extend(self.poll(id, sub).await);
+            }
+        }
+        subscriptions.retain(|id, _| {
+            !responses
+                .iter()
+                .any(|r| matches!(r, Response::Error { id: Some(e), .. } if e == id))
+        });
+        self.subscriptions = subscriptions;
+        responses
+    }
+
+    /// Look up the subscribed key, and return its rows if they have changed since last time.
+    async fn poll(&mut self, id: u64, sub: &mut Subscription) -> Option<Response> {
+        let view = &mut self
+            .views
+            .get_mut(&sub.view)
+            .expect("subscribed to unknown view")
+            .view;
+        let results = match view.lookup(&sub.key, false).await {
+            Ok(rs) => rs,
+            Err(e) => {
+                return Some(Response::Error {
+                    id: Some(id),
+                    message: e.to_string(),
+                })
+            }
+        };
+        if results.is_miss() {
+            // a replay has been triggered, and we'll pick up the rows on a later poll
+            return None;
+        }
+
+        let columns = view.columns();
+        let rows: Vec<Vec<DataType>> = results.into();
+        let mut sorted = rows.clone();
+        sorted.sort();
+        if sub.last.as_ref() == Some(&sorted) {
+            return None;
+        }
+        sub.last = Some(sorted);
+
+        let rows = rows
+            .iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .zip(row)
+                    .map(|(c, v)| (c.clone(), to_json(v)))
+                    .collect()
+            })
+            .collect();
+        Some(Response::Rows { id, rows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_requests() {
+        let r: Request =
+            serde_json::from_str(r#"{"type": "subscribe", "id": 3, "view": "v", "key": [1, "a"]}"#)
+                .unwrap();
+        match r {
+            Request::Subscribe { id, view, key } => {
+                assert_eq!(id, 3);
+                assert_eq!(view, "v");
+                let key: Vec<_> = key.into_iter().map(|k| from_json(k).unwrap()).collect();
+                assert_eq!(key, vec![1.into(), "a".into()]);
+            }
+            r => unreachable!("{:?}", r),
+        }
+        assert!(serde_json::from_str::<Request>(r#"{"type": "subscribe", "id": 3}"#).is_err());
+        assert!(from_json(serde_json::json!([1])).is_err());
+    }
+
+    #[test]
+    fn it_checks_key_lengths() {
+        assert!(check_key("v", 1, &[1.into()]).is_ok());
+        assert!(check_key("v", 2, &[1.into(), "a".into()]).is_ok());
+        assert!(check_key("v", 1, &[]).is_err());
+        assert!(check_key("v", 1, &[1.into(), 2.into()]).is_err());
+        assert!(check_key("v", 2, &[1.into()]).is_err());
+    }
+
+    #[test]
+    fn it_formats_responses() {
+        let mut row = Map::new();
+        row.insert("aid".to_owned(), to_json(&1.into()));
+        row.insert("title".to_owned(), to_json(&"Soup".into()));
+        let r = Response::Rows {
+            id: 1,
+            rows: vec![row],
+        };
+        assert_eq!(
+            serde_json::to_value(&r).unwrap(),
+            serde_json::json!({"type": "rows", "id": 1, "rows": [{"aid": 1, "title": "Soup"}]})
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use clap::value_t_or_exit;
+use noria::{ControllerHandle, ZookeeperAuthority};
+use noria_bridge::Bridge;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("noria-bridge")
+        .version("0.0.1")
+        .about("Serves Noria query results to WebSocket clients.")
+        .arg(
+            Arg::with_name("address")
+                .short("a")
+                .long("address")
+                .takes_value(true)
+                .default_value("127.0.0.1:6034")
+                .help("Address to accept WebSocket connections on."),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .required(true)
+                .takes_value(true)
+                .help("Noria deployment ID."),
+        )
+        .arg(
+            Arg::with_name("zookeeper")
+                .short("z")
+                .long("zookeeper")
+                .takes_value(true)
+                .default_value("127.0.0.1:2181")
+                .help("Zookeeper connection info."),
+        )
+        .arg(
+            Arg::with_name("poll-interval")
+                .long("poll-interval")
+                .takes_value(true)
+                .default_value("100")
+                .help("How often to check subscriptions for changes [in milliseconds]."),
+        )
+        .arg(
+            Arg::with_name("max-subscriptions")
+                .long("max-subscriptions")
+                .takes_value(true)
+                .default_value("1000")
+                .help("How many subscriptions each client may have at once."),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .help("Only allow clients that present this token to subscribe."),
+        )
+        .get_matches();
+
+    let addr = value_t_or_exit!(matches, "address", SocketAddr);
+    let poll_interval = value_t_or_exit!(matches, "poll-interval", u64);
+    let max_subscriptions = value_t_or_exit!(matches, "max-subscriptions", usize);
+    let zookeeper_addr = format!(
+        "{}/{}",
+        matches.value_of("zookeeper").unwrap(),
+        matches.value_of("deployment").unwrap()
+    );
+
+    let authority = ZookeeperAuthority::new(&zookeeper_addr).unwrap();
+    let handle = ControllerHandle::new(authority).await.unwrap();
+    let mut bridge = Bridge::new(handle);
+    bridge.set_poll_interval(Duration::from_millis(poll_interval));
+    bridge.set_max_subscriptions(max_subscriptions);
+    if let Some(token) = matches.value_of("token") {
+        let token = token.to_owned();
+        bridge.set_authorizer(move |t: Option<&str>, _: &str, _: &[noria::DataType]| {
+            t == Some(&*token)
+        });
+    }
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    bridge.serve(listener).await.unwrap();
+}
@@ -0,0 +1,181 @@
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use noria_bridge::Bridge;
+use noria_server::{Builder, ControllerHandle, DataType};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+async fn next_frame<S>(ws: &mut S) -> Value
+where
+    S: futures_util::stream::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+        + Unpin,
+{
+    let msg = tokio::time::timeout(Duration::from_secs(5), ws.next())
+        .await
+        .expect("no frame from bridge")
+        .unwrap()
+        .unwrap();
+    serde_json::from_str(msg.to_text().unwrap()).unwrap()
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn subscriptions_see_writes() {
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    let (mut g, _) = builder.start_local().await.unwrap();
+    g.install_recipe(
+        "CREATE TABLE Article (aid int, title varchar(255), PRIMARY KEY(aid));
+         QUERY ArticleById: SELECT aid, title FROM Article WHERE aid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    article
+        .insert(vec![1.into(), "I love Soup".into()])
+        .await
+        .unwrap();
+
+    let mut bridge = Bridge::new(ControllerHandle::clone(&g));
+    bridge.set_poll_interval(Duration::from_millis(10));
+    bridge.set_authorizer(|token: Option<&str>, _: &str, key: &[DataType]| {
+        token == Some("secret") && key[0] != DataType::from(2)
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(bridge.serve(listener));
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr).as_str())
+        .await
+        .unwrap();
+    let text = |frame: Value| Message::Text(frame.to_string());
+
+    // without a token, nothing is allowed
+    ws.send(text(
+        json!({"type": "subscribe", "id": 1, "view": "ArticleById", "key": [1]}),
+    ))
+    .await
+    .unwrap();
+    let frame = next_frame(&mut ws).await;
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["id"], 1);
+
+    ws.send(text(json!({"type": "auth", "token": "secret"})))
+        .await
+        .unwrap();
+    ws.send(text(
+        json!({"type": "subscribe", "id": 1, "view": "ArticleById", "key": [1]}),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(
+        next_frame(&mut ws).await,
+        json!({"type": "rows", "id": 1, "rows": [{"aid": 1, "title": "I love Soup"}]})
+    );
+
+    // the hook can refuse individual keys
+    ws.send(text(
+        json!({"type": "subscribe", "id": 2, "view": "ArticleById", "key": [2]}),
+    ))
+    .await
+    .unwrap();
+    let frame = next_frame(&mut ws).await;
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["id"], 2);
+
+    // writes are pushed to subscribers
+    article.delete(vec![1.into()]).await.unwrap();
+    article
+        .insert(vec![1.into(), "I really love Soup".into()])
+        .await
+        .unwrap();
+    let mut frame = next_frame(&mut ws).await;
+    if frame["rows"] == json!([]) {
+        // we may observe the delete before the insert
+        frame = next_frame(&mut ws).await;
+    }
+    assert_eq!(
+        frame,
+        json!({"type": "rows", "id": 1, "rows": [{"aid": 1, "title": "I really love Soup"}]})
+    );
+
+    // unknown views are reported
+    ws.send(text(
+        json!({"type": "subscribe", "id": 3, "view": "Nope", "key": [1]}),
+    ))
+    .await
+    .unwrap();
+    let frame = next_frame(&mut ws).await;
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["id"], 3);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn subscriptions_follow_view_changes() {
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    // fully materialized views keep their changes, so only changed keys are looked up
+    builder.disable_partial();
+    let (mut g, _) = builder.start_local().await.unwrap();
+    g.install_recipe(
+        "CREATE TABLE Article (aid int, title varchar(255), PRIMARY KEY(aid));
+         QUERY ArticleById: SELECT aid, title FROM Article WHERE aid = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    article
+        .insert(vec![1.into(), "I love Soup".into()])
+        .await
+        .unwrap();
+
+    let mut bridge = Bridge::new(ControllerHandle::clone(&g));
+    bridge.set_poll_interval(Duration::from_millis(10));
+    bridge.set_max_subscriptions(2);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(bridge.serve(listener));
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr).as_str())
+        .await
+        .unwrap();
+    let text = |frame: Value| Message::Text(frame.to_string());
+
+    ws.send(text(
+        json!({"type": "subscribe", "id": 1, "view": "ArticleById", "key": [1]}),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(
+        next_frame(&mut ws).await,
+        json!({"type": "rows", "id": 1, "rows": [{"aid": 1, "title": "I love Soup"}]})
+    );
+    ws.send(text(
+        json!({"type": "subscribe", "id": 2, "view": "ArticleById", "key": [2]}),
+    ))
+    .await
+    .unwrap();
+    assert_eq!(
+        next_frame(&mut ws).await,
+        json!({"type": "rows", "id": 2, "rows": []})
+    );
+
+    // clients can only have so many subscriptions
+    ws.send(text(
+        json!({"type": "subscribe", "id": 3, "view": "ArticleById", "key": [3]}),
+    ))
+    .await
+    .unwrap();
+    let frame = next_frame(&mut ws).await;
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["id"], 3);
+
+    // a write to one key only reaches its subscription
+    article
+        .insert(vec![2.into(), "Soup is great".into()])
+        .await
+        .unwrap();
+    assert_eq!(
+        next_frame(&mut ws).await,
+        json!({"type": "rows", "id": 2, "rows": [{"aid": 2, "title": "Soup is great"}]})
+    );
+}
@@ -0,0 +1,69 @@
+/*
+ * A C interface to the Noria client.
+ *
+ * Link against the libnoria_ffi shared library built by `cargo build -p noria-ffi --release`.
+ * Functions that create an object return NULL on failure, and functions that perform an
+ * operation return 0 on success and -1 on failure. In either case, noria_last_error() describes
+ * the most recent failure on the calling thread. Every object must be released with its matching
+ * _free function, and strings returned by the library remain valid until the object they came
+ * from is freed.
+ */
+
+#ifndef NORIA_H
+#define NORIA_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define NORIA_NULL 0
+#define NORIA_INT 1
+#define NORIA_UINT 2
+#define NORIA_REAL 3
+/* timestamps are returned as text of the form YYYY-MM-DD HH:MM:SS */
+#define NORIA_TEXT 4
+
+typedef struct noria_value {
+    /* one of the NORIA_* constants, saying which of the other fields holds the value */
+    int ty;
+    int64_t int64;
+    uint64_t uint64;
+    double real;
+    /* nul-terminated UTF-8 */
+    const char *text;
+} noria_value;
+
+typedef struct noria_handle noria_handle;
+typedef struct noria_view noria_view;
+typedef struct noria_table noria_table;
+typedef struct noria_rows noria_rows;
+
+const char *noria_last_error(void);
+
+noria_handle *noria_connect(const char *zookeeper);
+void noria_close(noria_handle *handle);
+int noria_extend_recipe(noria_handle *handle, const char *recipe);
+
+noria_view *noria_get_view(noria_handle *handle, const char *name);
+void noria_view_free(noria_view *view);
+noria_rows *noria_view_lookup(noria_view *view, const noria_value *key, size_t key_len, int block);
+
+noria_table *noria_get_table(noria_handle *handle, const char *name);
+void noria_table_free(noria_table *table);
+int noria_table_insert(noria_table *table, const noria_value *row, size_t len);
+int noria_table_delete(noria_table *table, const noria_value *key, size_t key_len);
+
+size_t noria_rows_len(const noria_rows *rows);
+size_t noria_rows_columns(const noria_rows *rows);
+const char *noria_rows_column_name(const noria_rows *rows, size_t column);
+int noria_rows_get(const noria_rows *rows, size_t row, size_t column, noria_value *out);
+void noria_rows_free(noria_rows *rows);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* NORIA_H */
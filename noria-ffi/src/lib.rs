@@ -0,0 +1,531 @@
+//! A C interface to the Noria client.
+//!
+//! This lets programs in languages other than Rust read views and write tables without
+//! reimplementing the Noria protocol. The functions here wrap the blocking handles in
+//! [`noria::sync`], and are declared in `include/noria.h`. The crate is built as a `cdylib`, so
+//! `cargo build -p noria-ffi --release` produces a `libnoria_ffi` shared library to link against.
+//!
+//! Functions that create an object return a null pointer on failure, and functions that perform
+//! an operation return 0 on success and -1 on failure. In either case, [`noria_last_error`]
+//! describes the most recent failure on the calling thread. Every object must be released with
+//! its matching `_free` function, and strings returned by the library remain valid until the
+//! object they came from is freed.
+
+use noria::sync::{SyncControllerHandle, SyncTable, SyncView};
+use noria::{DataType, ZookeeperAuthority};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// The value is SQL `NULL`.
+pub const NORIA_NULL: c_int = 0;
+/// The value is a signed integer, held in `int64`.
+pub const NORIA_INT: c_int = 1;
+/// The value is an unsigned integer, held in `uint64`.
+pub const NORIA_UINT: c_int = 2;
+/// The value is a floating-point number, held in `real`.
+pub const NORIA_REAL: c_int = 3;
+/// The value is a nul-terminated UTF-8 string, pointed to by `text`.
+///
+/// Timestamps are returned as text of the form `YYYY-MM-DD HH:MM:SS`.
+pub const NORIA_TEXT: c_int = 4;
+
+/// A single value in a row or key.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NoriaValue {
+    /// Which of the other fields holds the value; one of the `NORIA_*` constants.
+    pub ty: c_int,
+    /// The value if `ty` is `NORIA_INT`.
+    pub int64: i64,
+    /// The value if `ty` is `NORIA_UINT`.
+    pub uint64: u64,
+    /// The value if `ty` is `NORIA_REAL`.
+    pub real: f64,
+    /// The value if `ty` is `NORIA_TEXT`.
+    pub text: *const c_char,
+}
+
+/// A connection to a Noria controller.
+pub struct NoriaHandle(SyncControllerHandle<ZookeeperAuthority>);
+
+/// A handle for reading a view.
+pub struct NoriaView(SyncView);
+
+/// A handle for writing to a base table.
+pub struct NoriaTable(SyncTable);
+
+#[derive(Debug)]
+enum Cell {
+    Null,
+    Int(i64),
+    UInt(u64),
+    Real(f64),
+    Text(CString),
+}
+
+/// The rows returned by a lookup.
+#[derive(Debug)]
+pub struct NoriaRows {
+    columns: Vec<CString>,
+    rows: Vec<Vec<Cell>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_error(e: String) {
+    // interior nul bytes can't be represented, so cut the message short there
+    let e = CString::new(e).unwrap_or_else(|e| {
+        let nul = e.nul_position();
+        CString::new(&e.into_vec()[..nul]).unwrap()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(e));
+}
+
+/// Run `f`, recording its error (or panic) and returning `failed` if it does not succeed.
+fn guard<T>(failed: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(t)) => t,
+        Ok(Err(e)) => {
+            set_error(e);
+            failed
+        }
+        Err(_) => {
+            set_error("the noria client panicked".to_owned());
+            failed
+        }
+    }
+}
+
+unsafe fn string<'a>(s: *const c_char) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err("unexpected null string".to_owned());
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| "strings must be valid UTF-8".to_owned())
+}
+
+unsafe fn object<'a, T>(o: *mut T) -> Result<&'a mut T, String> {
+    o.as_mut()
+        .ok_or_else(|| "unexpected null handle".to_owned())
+}
+
+unsafe fn values(vs: *const NoriaValue, len: usize) -> Result<Vec<DataType>, String> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if vs.is_null() {
+        return Err("unexpected null values".to_owned());
+    }
+    std::slice::from_raw_parts(vs, len)
+        .iter()
+        .map(|v| match v.ty {
+            NORIA_NULL => Ok(DataType::None),
+            NORIA_INT => Ok(v.int64.into()),
+            NORIA_UINT => Ok(v.uint64.into()),
+            NORIA_REAL => Ok(v.real.into()),
+            NORIA_TEXT => string(v.text).map(DataType::from),
+            ty => Err(format!("unknown value type {}", ty)),
+        })
+        .collect()
+}
+
+fn cell(v: &DataType) -> Cell {
+    match *v {
+        DataType::None => Cell::Null,
+        DataType::Int(n) => Cell::Int(n.into()),
+        DataType::BigInt(n) => Cell::Int(n),
+        DataType::UnsignedInt(n) => Cell::UInt(n.into()),
+        DataType::UnsignedBigInt(n) => Cell::UInt(n),
+        DataType::Real(..) => Cell::Real(v.into()),
+        DataType::Text(..) | DataType::TinyText(..) => {
+            let s: &str = v.into();
+            Cell::Text(CString::new(s.replace('\0', "")).unwrap())
+        }
+        DataType::Timestamp(ts) => {
+            Cell::Text(CString::new(ts.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap())
+        }
+    }
+}
+
+fn rows(columns: &[String], rows: &[Vec<DataType>]) -> NoriaRows {
+    NoriaRows {
+        columns: columns
+            .iter()
+            .map(|c| CString::new(c.as_str()).unwrap())
+            .collect(),
+        rows: rows
+            .iter()
+            .map(|r| r.iter().take(columns.len()).map(cell).collect())
+            .collect(),
+    }
+}
+
+/// The message describing the most recent failure on this thread, or null if nothing has failed.
+///
+/// The message remains valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn noria_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|e| e.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+/// Connect to the Noria deployment registered at the given ZooKeeper location, such as
+/// `"127.0.0.1:2181/noria"`.
+///
+/// # Safety
+///
+/// `zookeeper` must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn noria_connect(zookeeper: *const c_char) -> *mut NoriaHandle {
+    guard(ptr::null_mut(), || {
+        let handle =
+            SyncControllerHandle::from_zk(string(zookeeper)?).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(NoriaHandle(handle))))
+    })
+}
+
+/// Close a connection returned by `noria_connect`.
+///
+/// Views and tables obtained through the connection remain usable.
+///
+/// # Safety
+///
+/// `handle` must have been returned by `noria_connect`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn noria_close(handle: *mut NoriaHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Extend the current recipe with the given tables and queries.
+///
+/// # Safety
+///
+/// `handle` must be a live connection, and `recipe` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn noria_extend_recipe(
+    handle: *mut NoriaHandle,
+    recipe: *const c_char,
+) -> c_int {
+    guard(-1, || {
+        let handle = object(handle)?;
+        handle
+            .0
+            .extend_recipe(string(recipe)?)
+            .map_err(|e| e.to_string())?;
+        Ok(0)
+    })
+}
+
+/// Get a handle for reading the view with the given name.
+///
+/// # Safety
+///
+/// `handle` must be a live connection, and `name` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn noria_get_view(
+    handle: *mut NoriaHandle,
+    name: *const c_char,
+) -> *mut NoriaView {
+    guard(ptr::null_mut(), || {
+        let handle = object(handle)?;
+        let view = handle.0.view(string(name)?).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(NoriaView(view))))
+    })
+}
+
+/// Release a view handle returned by `noria_get_view`.
+///
+/// # Safety
+///
+/// `view` must have been returned by `noria_get_view`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn noria_view_free(view: *mut NoriaView) {
+    if !view.is_null() {
+        drop(Box::from_raw(view));
+    }
+}
+
+/// Get a handle for writing to the base table with the given name.
+///
+/// # Safety
+///
+/// `handle` must be a live connection, and `name` a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn noria_get_table(
+    handle: *mut NoriaHandle,
+    name: *const c_char,
+) -> *mut NoriaTable {
+    guard(ptr::null_mut(), || {
+        let handle = object(handle)?;
+        let table = handle.0.table(string(name)?).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(NoriaTable(table))))
+    })
+}
+
+/// Release a table handle returned by `noria_get_table`.
+///
+/// # Safety
+///
+/// `table` must have been returned by `noria_get_table`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn noria_table_free(table: *mut NoriaTable) {
+    if !table.is_null() {
+        drop(Box::from_raw(table));
+    }
+}
+
+/// Look up the rows for the key made up of the `key_len` values at `key`.
+///
+/// If `block` is zero, keys that are not yet materialized return no rows. The returned rows must
+/// be released with `noria_rows_free`.
+///
+/// # Safety
+///
+/// `view` must be a live view handle, and `key` must point to `key_len` valid values.
+#[no_mangle]
+pub unsafe extern "C" fn noria_view_lookup(
+    view: *mut NoriaView,
+    key: *const NoriaValue,
+    key_len: usize,
+    block: c_int,
+) -> *mut NoriaRows {
+    guard(ptr::null_mut(), || {
+        let view = object(view)?;
+        let key = values(key, key_len)?;
+        let results = view.0.lookup(&key, block != 0).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(rows(
+            view.0.columns(),
+            results.as_ref(),
+        ))))
+    })
+}
+
+/// Insert the row made up of the `len` values at `row`.
+///
+/// # Safety
+///
+/// `table` must be a live table handle, and `row` must point to `len` valid values.
+#[no_mangle]
+pub unsafe extern "C" fn noria_table_insert(
+    table: *mut NoriaTable,
+    row: *const NoriaValue,
+    len: usize,
+) -> c_int {
+    guard(-1, || {
+        let table = object(table)?;
+        let row = values(row, len)?;
+        table.0.insert(row).map_err(|e| e.to_string())?;
+        Ok(0)
+    })
+}
+
+/// Delete the row whose primary key is made up of the `key_len` values at `key`.
+///
+/// # Safety
+///
+/// `table` must be a live table handle, and `key` must point to `key_len` valid values.
+#[no_mangle]
+pub unsafe extern "C" fn noria_table_delete(
+    table: *mut NoriaTable,
+    key: *const NoriaValue,
+    key_len: usize,
+) -> c_int {
+    guard(-1, || {
+        let table = object(table)?;
+        let key = values(key, key_len)?;
+        table.0.delete(key).map_err(|e| e.to_string())?;
+        Ok(0)
+    })
+}
+
+/// The number of rows.
+///
+/// # Safety
+///
+/// `rows` must have been returned by `noria_view_lookup` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn noria_rows_len(rows: *const NoriaRows) -> usize {
+    rows.as_ref().map(|rs| rs.rows.len()).unwrap_or(0)
+}
+
+/// The number of columns in each row.
+///
+/// # Safety
+///
+/// `rows` must have been returned by `noria_view_lookup` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn noria_rows_columns(rows: *const NoriaRows) -> usize {
+    rows.as_ref().map(|rs| rs.columns.len()).unwrap_or(0)
+}
+
+/// The name of the given column, or null if there is no such column.
+///
+/// # Safety
+///
+/// `rows` must have been returned by `noria_view_lookup` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn noria_rows_column_name(
+    rows: *const NoriaRows,
+    column: usize,
+) -> *const c_char {
+    rows.as_ref()
+        .and_then(|rs| rs.columns.get(column))
+        .map(|c| c.as_ptr())
+        .unwrap_or(ptr::null())
+}
+
+/// Store the value of the given column in the given row in `out`.
+///
+/// # Safety
+///
+/// `rows` must have been returned by `noria_view_lookup` and not yet freed, and `out` must point
+/// to writable memory for one value.
+#[no_mangle]
+pub unsafe extern "C" fn noria_rows_get(
+    rows: *const NoriaRows,
+    row: usize,
+    column: usize,
+    out: *mut NoriaValue,
+) -> c_int {
+    guard(-1, || {
+        let rows = rows
+            .as_ref()
+            .ok_or_else(|| "unexpected null rows".to_owned())?;
+        let out = object(out)?;
+        let cell = rows
+            .rows
+            .get(row)
+            .and_then(|r| r.get(column))
+            .ok_or_else(|| format!("no value at row {}, column {}", row, column))?;
+        *out = NoriaValue {
+            ty: NORIA_NULL,
+            int64: 0,
+            uint64: 0,
+            real: 0.0,
+            text: ptr::null(),
+        };
+        match *cell {
+            Cell::Null => {}
+            Cell::Int(n) => {
+                out.ty = NORIA_INT;
+                out.int64 = n;
+            }
+            Cell::UInt(n) => {
+                out.ty = NORIA_UINT;
+                out.uint64 = n;
+            }
+            Cell::Real(f) => {
+                out.ty = NORIA_REAL;
+                out.real = f;
+            }
+            Cell::Text(ref s) => {
+                out.ty = NORIA_TEXT;
+                out.text = s.as_ptr();
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Release rows returned by `noria_view_lookup`.
+///
+/// # Safety
+///
+/// `rows` must have been returned by `noria_view_lookup`, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn noria_rows_free(rows: *mut NoriaRows) {
+    if !rows.is_null() {
+        drop(Box::from_raw(rows));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let text = CString::new("I love Soup").unwrap();
+        let key = [
+            NoriaValue {
+                ty: NORIA_INT,
+                int64: -1,
+                uint64: 0,
+                real: 0.0,
+                text: ptr::null(),
+            },
+            NoriaValue {
+                ty: NORIA_TEXT,
+                int64: 0,
+                uint64: 0,
+                real: 0.0,
+                text: text.as_ptr(),
+            },
+            NoriaValue {
+                ty: NORIA_NULL,
+                int64: 0,
+                uint64: 0,
+                real: 0.0,
+                text: ptr::null(),
+            },
+        ];
+        let row = unsafe { values(key.as_ptr(), key.len()) }.unwrap();
+        assert_eq!(row, vec![(-1).into(), "I love Soup".into(), DataType::None]);
+
+        let columns = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        let rs = Box::into_raw(Box::new(rows(&columns, &[row])));
+        unsafe {
+            assert_eq!(noria_rows_len(rs), 1);
+            assert_eq!(noria_rows_columns(rs), 3);
+            assert_eq!(
+                CStr::from_ptr(noria_rows_column_name(rs, 1)).to_str(),
+                Ok("b")
+            );
+            assert!(noria_rows_column_name(rs, 3).is_null());
+
+            let mut out = key[2];
+            assert_eq!(noria_rows_get(rs, 0, 0, &mut out), 0);
+            assert_eq!((out.ty, out.int64), (NORIA_INT, -1));
+            assert_eq!(noria_rows_get(rs, 0, 1, &mut out), 0);
+            assert_eq!(out.ty, NORIA_TEXT);
+            assert_eq!(CStr::from_ptr(out.text).to_str(), Ok("I love Soup"));
+            assert_eq!(noria_rows_get(rs, 0, 2, &mut out), 0);
+            assert_eq!(out.ty, NORIA_NULL);
+            noria_rows_free(rs);
+        }
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let bad = NoriaValue {
+            ty: 42,
+            int64: 0,
+            uint64: 0,
+            real: 0.0,
+            text: ptr::null(),
+        };
+        let r = unsafe { noria_table_insert(ptr::null_mut(), &bad, 1) };
+        assert_eq!(r, -1);
+        let e = unsafe { CStr::from_ptr(noria_last_error()) };
+        assert_eq!(e.to_str(), Ok("unexpected null handle"));
+
+        let rs = Box::into_raw(Box::new(rows(&[], &[])));
+        let mut out = bad;
+        assert_eq!(unsafe { noria_rows_get(rs, 0, 0, &mut out) }, -1);
+        let e = unsafe { CStr::from_ptr(noria_last_error()) };
+        assert_eq!(e.to_str(), Ok("no value at row 0, column 0"));
+        unsafe { noria_rows_free(rs) };
+    }
+}
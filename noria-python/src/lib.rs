@@ -0,0 +1,449 @@
+//! Python bindings for Noria.
+//!
+//! This wraps the blocking client in [`noria::sync`] in a Pythonic API: rows are dictionaries
+//! keyed by column name, keys are plain values (or tuples for compound keys), and every handle is
+//! a context manager. See the README for an example.
+
+use chrono::{Datelike, NaiveDate, Timelike};
+use noria::sync::{SyncControllerHandle, SyncTable, SyncView};
+use noria::{DataType, Modification, ZookeeperAuthority};
+use pyo3::create_exception;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyAny, PyBool, PyDateAccess, PyDateTime, PyDict, PyFloat, PyList, PyLong, PyString,
+    PyTimeAccess, PyTuple,
+};
+
+create_exception!(noria_python, NoriaError, exceptions::Exception);
+
+fn error<E: std::fmt::Display>(e: E) -> PyErr {
+    PyErr::new::<NoriaError, _>(e.to_string())
+}
+
+fn closed(what: &str) -> PyErr {
+    PyErr::new::<NoriaError, _>(format!("the {} has been closed", what))
+}
+
+fn to_py(py: Python, v: &DataType) -> PyResult<PyObject> {
+    Ok(match *v {
+        DataType::None => py.None(),
+        DataType::Int(n) => n.into_py(py),
+        DataType::UnsignedInt(n) => n.into_py(py),
+        DataType::BigInt(n) => n.into_py(py),
+        DataType::UnsignedBigInt(n) => n.into_py(py),
+        DataType::Real(..) => f64::from(v).into_py(py),
+        DataType::Text(..) | DataType::TinyText(..) => {
+            let s: &str = v.into();
+            s.into_py(py)
+        }
+        DataType::Timestamp(ts) => PyDateTime::new(
+            py,
+            ts.year(),
+            ts.month() as u8,
+            ts.day() as u8,
+            ts.hour() as u8,
+            ts.minute() as u8,
+            ts.second() as u8,
+            ts.timestamp_subsec_micros(),
+            None,
+        )?
+        .to_object(py),
+    })
+}
+
+fn from_py(v: &PyAny) -> PyResult<DataType> {
+    if v.is_none() {
+        Ok(DataType::None)
+    } else if let Ok(b) = v.downcast::<PyBool>() {
+        Ok(DataType::from(b.is_true() as i32))
+    } else if v.downcast::<PyLong>().is_ok() {
+        match v.extract::<i64>() {
+            Ok(n) => Ok(n.into()),
+            Err(_) => Ok(v.extract::<u64>()?.into()),
+        }
+    } else if v.downcast::<PyFloat>().is_ok() {
+        Ok(v.extract::<f64>()?.into())
+    } else if let Ok(s) = v.downcast::<PyString>() {
+        Ok(s.to_str()?.into())
+    } else if let Ok(dt) = v.downcast::<PyDateTime>() {
+        NaiveDate::from_ymd_opt(
+            dt.get_year(),
+            u32::from(dt.get_month()),
+            u32::from(dt.get_day()),
+        )
+        .and_then(|d| {
+            d.and_hms_micro_opt(
+                u32::from(dt.get_hour()),
+                u32::from(dt.get_minute()),
+                u32::from(dt.get_second()),
+                dt.get_microsecond(),
+            )
+        })
+        .map(DataType::from)
+        .ok_or_else(|| PyErr::new::<exceptions::ValueError, _>("invalid datetime"))
+    } else {
+        Err(PyErr::new::<exceptions::TypeError, _>(format!(
+            "cannot store values of type {} in Noria",
+            v.get_type().name()
+        )))
+    }
+}
+
+/// A key is either a single value, or a tuple or list of values for compound keys.
+fn key_from_py(v: &PyAny) -> PyResult<Vec<DataType>> {
+    if let Ok(t) = v.downcast::<PyTuple>() {
+        t.iter().map(from_py).collect()
+    } else if let Ok(l) = v.downcast::<PyList>() {
+        l.iter().map(from_py).collect()
+    } else {
+        Ok(vec![from_py(v)?])
+    }
+}
+
+fn column(columns: &[String], name: &PyAny) -> PyResult<usize> {
+    let name: &str = name.extract()?;
+    columns
+        .iter()
+        .position(|c| c == name)
+        .ok_or_else(|| PyErr::new::<exceptions::KeyError, _>(format!("no column named {}", name)))
+}
+
+/// A row is either a dict keyed by column name, or a sequence of values in column order.
+///
+/// Columns that are missing from a dict are left empty.
+fn row_from_py(columns: &[String], v: &PyAny) -> PyResult<Vec<DataType>> {
+    if let Ok(d) = v.downcast::<PyDict>() {
+        let mut row = vec![DataType::None; columns.len()];
+        for (k, v) in d.iter() {
+            row[column(columns, k)?] = from_py(v)?;
+        }
+        Ok(row)
+    } else {
+        let row = key_from_py(v)?;
+        if row.len() != columns.len() {
+            return Err(PyErr::new::<exceptions::ValueError, _>(format!(
+                "expected {} values, got {}",
+                columns.len(),
+                row.len()
+            )));
+        }
+        Ok(row)
+    }
+}
+
+fn row_to_py(py: Python, columns: &[String], row: &[DataType]) -> PyResult<PyObject> {
+    let d = PyDict::new(py);
+    for (c, v) in columns.iter().zip(row) {
+        d.set_item(c, to_py(py, v)?)?;
+    }
+    Ok(d.to_object(py))
+}
+
+fn rows_to_py<'a, I>(py: Python, columns: &[String], rows: I) -> PyResult<PyObject>
+where
+    I: IntoIterator<Item = &'a Vec<DataType>>,
+{
+    let rows = rows
+        .into_iter()
+        .map(|r| row_to_py(py, columns, r))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(PyList::new(py, rows).to_object(py))
+}
+
+/// Connect to the Noria deployment whose controller is registered in the given ZooKeeper
+/// location, such as `"127.0.0.1:2181/noria"`.
+#[pyfunction]
+fn connect(py: Python, zookeeper: &str) -> PyResult<Controller> {
+    let handle = py
+        .allow_threads(|| SyncControllerHandle::from_zk(zookeeper))
+        .map_err(error)?;
+    Ok(Controller {
+        handle: Some(handle),
+    })
+}
+
+/// A connection to a Noria controller.
+#[pyclass]
+struct Controller {
+    handle: Option<SyncControllerHandle<ZookeeperAuthority>>,
+}
+
+impl Controller {
+    fn handle(&mut self) -> PyResult<&mut SyncControllerHandle<ZookeeperAuthority>> {
+        self.handle.as_mut().ok_or_else(|| closed("controller"))
+    }
+}
+
+#[pymethods]
+impl Controller {
+    /// The names of all base tables.
+    fn inputs(&mut self, py: Python) -> PyResult<Vec<String>> {
+        let handle = self.handle()?;
+        let inputs = py.allow_threads(|| handle.inputs()).map_err(error)?;
+        Ok(inputs.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// The names of all views.
+    fn outputs(&mut self, py: Python) -> PyResult<Vec<String>> {
+        let handle = self.handle()?;
+        let outputs = py.allow_threads(|| handle.outputs()).map_err(error)?;
+        Ok(outputs.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Get a handle for reading the view with the given name.
+    fn view(&mut self, py: Python, name: &str) -> PyResult<View> {
+        let handle = self.handle()?;
+        let view = py.allow_threads(|| handle.view(name)).map_err(error)?;
+        Ok(View { view: Some(view) })
+    }
+
+    /// Get a handle for writing to the base table with the given name.
+    fn table(&mut self, py: Python, name: &str) -> PyResult<Table> {
+        let handle = self.handle()?;
+        let table = py.allow_threads(|| handle.table(name)).map_err(error)?;
+        Ok(Table { table: Some(table) })
+    }
+
+    /// Extend the current recipe with the given tables and queries.
+    fn extend_recipe(&mut self, py: Python, recipe: &str) -> PyResult<()> {
+        let handle = self.handle()?;
+        py.allow_threads(|| handle.extend_recipe(recipe))
+            .map(|_| ())
+            .map_err(error)
+    }
+
+    /// Replace the current recipe with the given one.
+    fn install_recipe(&mut self, py: Python, recipe: &str) -> PyResult<()> {
+        let handle = self.handle()?;
+        py.allow_threads(|| handle.install_recipe(recipe))
+            .map(|_| ())
+            .map_err(error)
+    }
+
+    /// Remove the query with the given name.
+    fn remove_query(&mut self, py: Python, name: &str) -> PyResult<()> {
+        let handle = self.handle()?;
+        py.allow_threads(|| handle.remove_query(name))
+            .map_err(error)
+    }
+
+    /// Close the connection. Views and tables obtained from it remain usable.
+    fn close(&mut self) {
+        self.handle = None;
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _ty: Option<&PyAny>,
+        _value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+/// A handle for reading a view.
+#[pyclass]
+struct View {
+    view: Option<SyncView>,
+}
+
+impl View {
+    fn view(&mut self) -> PyResult<&mut SyncView> {
+        self.view.as_mut().ok_or_else(|| closed("view"))
+    }
+}
+
+#[pymethods]
+impl View {
+    /// The names of the view's columns.
+    #[getter]
+    fn columns(&mut self) -> PyResult<Vec<String>> {
+        Ok(self.view()?.columns().to_vec())
+    }
+
+    /// The number of keys in the view.
+    fn len(&mut self, py: Python) -> PyResult<usize> {
+        let view = self.view()?;
+        py.allow_threads(|| view.len()).map_err(error)
+    }
+
+    /// The number of rows for the given key.
+    fn count(&mut self, py: Python, key: &PyAny) -> PyResult<usize> {
+        let key = key_from_py(key)?;
+        let view = self.view()?;
+        py.allow_threads(|| view.count(&key)).map_err(error)
+    }
+
+    /// Whether there are any rows for the given key.
+    fn contains(&mut self, py: Python, key: &PyAny) -> PyResult<bool> {
+        let key = key_from_py(key)?;
+        let view = self.view()?;
+        py.allow_threads(|| view.contains(&key)).map_err(error)
+    }
+
+    /// The rows for the given key, as a list of dicts.
+    ///
+    /// If `block` is false, keys that are not yet materialized return no rows.
+    #[args(block = "true")]
+    fn lookup(&mut self, py: Python, key: &PyAny, block: bool) -> PyResult<PyObject> {
+        let key = key_from_py(key)?;
+        let view = self.view()?;
+        let rows = py
+            .allow_threads(|| view.lookup(&key, block))
+            .map_err(error)?;
+        rows_to_py(py, view.columns(), rows.as_ref())
+    }
+
+    /// The rows for each of the given keys, as a list of lists of dicts.
+    #[args(block = "true")]
+    fn multi_lookup(&mut self, py: Python, keys: &PyAny, block: bool) -> PyResult<PyObject> {
+        let keys = keys
+            .iter()?
+            .map(|k| key_from_py(k?))
+            .collect::<PyResult<Vec<_>>>()?;
+        let view = self.view()?;
+        let results = py
+            .allow_threads(|| view.multi_lookup(keys, block))
+            .map_err(error)?;
+        let results = results
+            .iter()
+            .map(|rows| rows_to_py(py, view.columns(), rows.as_ref()))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(PyList::new(py, results).to_object(py))
+    }
+
+    /// Every row in the view, as a list of dicts, read `chunk_size` keys at a time.
+    ///
+    /// For partially materialized views, only keys that are currently materialized are included.
+    #[args(chunk_size = "1024")]
+    fn scan(&mut self, py: Python, chunk_size: usize) -> PyResult<PyObject> {
+        let view = self.view()?;
+        let chunks = py
+            .allow_threads(|| view.iter(chunk_size).collect::<Result<Vec<_>, _>>())
+            .map_err(error)?;
+        rows_to_py(
+            py,
+            view.columns(),
+            chunks.iter().flat_map(|rows| rows.as_ref()),
+        )
+    }
+
+    /// Close the view handle.
+    fn close(&mut self) {
+        self.view = None;
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _ty: Option<&PyAny>,
+        _value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+/// A handle for writing to a base table.
+#[pyclass]
+struct Table {
+    table: Option<SyncTable>,
+}
+
+impl Table {
+    fn table(&mut self) -> PyResult<&mut SyncTable> {
+        self.table.as_mut().ok_or_else(|| closed("table"))
+    }
+}
+
+#[pymethods]
+impl Table {
+    /// The name of the table.
+    #[getter]
+    fn name(&mut self) -> PyResult<String> {
+        Ok(self.table()?.table_name().to_owned())
+    }
+
+    /// The names of the table's columns.
+    #[getter]
+    fn columns(&mut self) -> PyResult<Vec<String>> {
+        Ok(self.table()?.columns().to_vec())
+    }
+
+    /// Insert a row, given either as a dict keyed by column name or as a sequence of values.
+    fn insert(&mut self, py: Python, row: &PyAny) -> PyResult<()> {
+        let table = self.table()?;
+        let row = row_from_py(table.columns(), row)?;
+        py.allow_threads(|| table.insert(row)).map_err(error)
+    }
+
+    /// Insert several rows in one write.
+    fn insert_many(&mut self, py: Python, rows: &PyAny) -> PyResult<()> {
+        let table = self.table()?;
+        let rows = rows
+            .iter()?
+            .map(|r| row_from_py(table.columns(), r?))
+            .collect::<PyResult<Vec<_>>>()?;
+        py.allow_threads(|| table.perform_all(rows)).map_err(error)
+    }
+
+    /// Delete the row with the given primary key.
+    fn delete(&mut self, py: Python, key: &PyAny) -> PyResult<()> {
+        let key = key_from_py(key)?;
+        let table = self.table()?;
+        py.allow_threads(|| table.delete(key)).map_err(error)
+    }
+
+    /// Set the columns named in `changes`, a dict, on the row with the given primary key.
+    fn update(&mut self, py: Python, key: &PyAny, changes: &PyDict) -> PyResult<()> {
+        let key = key_from_py(key)?;
+        let table = self.table()?;
+        let changes = changes
+            .iter()
+            .map(|(k, v)| Ok((column(table.columns(), k)?, Modification::Set(from_py(v)?))))
+            .collect::<PyResult<Vec<_>>>()?;
+        py.allow_threads(|| table.update(key, changes))
+            .map_err(error)
+    }
+
+    /// Close the table handle.
+    fn close(&mut self) {
+        self.table = None;
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _ty: Option<&PyAny>,
+        _value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+/// Python bindings for Noria.
+#[pymodule]
+fn noria_python(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_wrapped(wrap_pyfunction!(connect))?;
+    m.add_class::<Controller>()?;
+    m.add_class::<View>()?;
+    m.add_class::<Table>()?;
+    m.add("NoriaError", py.get_type::<NoriaError>())?;
+    Ok(())
+}
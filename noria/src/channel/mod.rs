@@ -6,6 +6,7 @@ use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::io::{self, Write};
+use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::sync::RwLock;
 use std::{
@@ -15,7 +16,7 @@ use std::{
 
 use async_bincode::{AsyncBincodeWriter, AsyncDestination};
 use futures_util::sink::{Sink, SinkExt};
-use tokio::io::BufWriter;
+use tokio::io::{AsyncWrite, BufWriter};
 
 pub mod tcp;
 
@@ -23,6 +24,13 @@ pub use self::tcp::{DualTcpStream, TcpSender};
 
 pub const CONNECTION_FROM_BASE: u8 = 1;
 pub const CONNECTION_FROM_DOMAIN: u8 = 2;
+pub const CONNECTION_FROM_DOMAIN_COMPRESSED: u8 = 3;
+
+/// Size of the write buffer of connections to domains.
+///
+/// Senders queue up everything they have for a domain before flushing, so the buffer should be
+/// large enough for all of that to go out in a single write.
+const DOMAIN_WRITE_BUFFER: usize = 256 * 1024;
 
 pub struct Remote;
 pub struct MaybeLocal;
@@ -32,6 +40,7 @@ pub struct DomainConnectionBuilder<D, T> {
     addr: SocketAddr,
     chan: Option<tokio::sync::mpsc::UnboundedSender<T>>,
     is_for_base: bool,
+    compression: Option<i32>,
     _marker: D,
 }
 
@@ -64,6 +73,7 @@ impl<T> DomainConnectionBuilder<Remote, T> {
             chan: None,
             addr,
             is_for_base: true,
+            compression: None,
             _marker: Remote,
         }
     }
@@ -94,11 +104,43 @@ where
         let s = self.build_sync()?.into_inner().into_inner()?;
 
         tokio::net::TcpStream::from_std(s)
-            .map(BufWriter::new)
+            .map(|s| BufWriter::with_capacity(DOMAIN_WRITE_BUFFER, s))
             .map(AsyncBincodeWriter::from)
             .map(AsyncBincodeWriter::for_async)
     }
 
+    /// Connect to a domain, and compress the messages sent to it at the given zstd `level` unless
+    /// the domain runs on the same host as we do.
+    pub fn build_async_compressed(
+        self,
+        level: i32,
+    ) -> io::Result<Box<dyn Sink<T, Error = bincode::Error> + Send + Unpin>>
+    where
+        T: Send + 'static,
+    {
+        assert!(!self.is_for_base);
+        let mut s = TcpSender::<T>::connect_from(self.sport, &self.addr)?;
+        let same_host = s.local_addr()?.ip() == s.peer_addr()?.ip();
+        {
+            let s = s.get_mut();
+            s.write_all(&[if same_host {
+                CONNECTION_FROM_DOMAIN
+            } else {
+                CONNECTION_FROM_DOMAIN_COMPRESSED
+            }])?;
+            s.flush()?;
+        }
+
+        let s = tokio::net::TcpStream::from_std(s.into_inner().into_inner()?)
+            .map(|s| BufWriter::with_capacity(DOMAIN_WRITE_BUFFER, s))?;
+        if same_host {
+            // compressing would only cost cpu time
+            Ok(Box::new(AsyncBincodeWriter::from(s).for_async()))
+        } else {
+            Ok(Box::new(CompressedSink::new(s, level)))
+        }
+    }
+
     pub fn build_sync(self) -> io::Result<TcpSender<T>> {
         let mut s = TcpSender::connect_from(self.sport, &self.addr)?;
         {
@@ -115,6 +157,49 @@ where
     }
 }
 
+/// Sends each message as a zstd-compressed frame, to be read by `DualTcpStream::compressed`.
+pub struct CompressedSink<W, T> {
+    inner: AsyncBincodeWriter<W, Vec<u8>, AsyncDestination>,
+    level: i32,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<W, T> CompressedSink<W, T> {
+    pub fn new(w: W, level: i32) -> Self {
+        CompressedSink {
+            inner: AsyncBincodeWriter::from(w).for_async(),
+            level,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<W, T> Sink<T> for CompressedSink<W, T>
+where
+    W: AsyncWrite + Unpin,
+    T: serde::Serialize,
+{
+    type Error = bincode::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let frame = zstd::encode_all(&bincode::serialize(&item)?[..], self.level)
+            .map_err(|e| Box::new(bincode::ErrorKind::Io(e)))?;
+        Pin::new(&mut self.inner).start_send(frame)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
 pub trait Sender {
     type Item;
 
@@ -147,15 +232,18 @@ where
                     .sink_map_err(|_| serde::de::Error::custom("failed to do local send")),
             ) as Box<_>)
         } else {
-            DomainConnectionBuilder {
+            let remote = DomainConnectionBuilder {
                 sport: self.sport,
                 chan: None,
                 addr: self.addr,
                 is_for_base: false,
+                compression: None,
                 _marker: Remote,
+            };
+            match self.compression {
+                Some(level) => remote.build_async_compressed(level),
+                None => remote.build_async().map(|c| Box::new(c) as Box<_>),
             }
-            .build_async()
-            .map(|c| Box::new(c) as Box<_>)
         }
     }
 
@@ -168,6 +256,7 @@ where
                 chan: None,
                 addr: self.addr,
                 is_for_base: false,
+                compression: None,
                 _marker: Remote,
             }
             .build_sync()
@@ -181,8 +270,15 @@ struct ChannelCoordinatorInner<K: Eq + Hash + Clone, T> {
     addrs: HashMap<K, SocketAddr>,
     /// Map from key to channel sender for local connections.
     locals: HashMap<K, tokio::sync::mpsc::UnboundedSender<T>>,
+    /// The zstd level to compress traffic to domains on other hosts at, if any.
+    compression: Option<i32>,
 }
 
+/// Keeps track of how to reach each domain.
+///
+/// Domains running in this process register an in-process channel with `insert_local`, and
+/// connections built with `builder_for` use that channel when there is one, so packets between
+/// co-located domains are never serialized or sent over loopback TCP.
 pub struct ChannelCoordinator<K: Eq + Hash + Clone, T> {
     inner: RwLock<ChannelCoordinatorInner<K, T>>,
 }
@@ -199,6 +295,7 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
             inner: RwLock::new(ChannelCoordinatorInner {
                 addrs: Default::default(),
                 locals: Default::default(),
+                compression: None,
             }),
         }
     }
@@ -213,6 +310,14 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
         inner.locals.insert(key, chan);
     }
 
+    /// Compress the messages sent by asynchronous connections to domains on other hosts at the
+    /// given zstd level, or stop compressing them if `None`.
+    ///
+    /// Only connections built after this is called are affected.
+    pub fn set_compression(&self, level: Option<i32>) {
+        self.inner.write().unwrap().compression = level;
+    }
+
     pub fn has<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
@@ -248,7 +353,32 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
             addr: *inner.addrs.get(key)?,
             chan: inner.locals.get(key).cloned(),
             is_for_base: false,
+            compression: inner.compression,
             _marker: MaybeLocal,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream::StreamExt;
+
+    #[tokio::test]
+    async fn compressed_roundtrip() {
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let msgs: Vec<String> = (0..10).map(|i| i.to_string().repeat(1000)).collect();
+        let mut tx = CompressedSink::new(tokio::net::TcpStream::connect(addr).await.unwrap(), 3);
+        let (rx, _) = listener.accept().await.unwrap();
+        let mut rx: DualTcpStream<_, String, String, _> = DualTcpStream::compressed(rx);
+
+        for m in &msgs {
+            tx.send(m.clone()).await.unwrap();
+        }
+        for m in msgs {
+            assert_eq!(rx.next().await.unwrap().unwrap(), m);
+        }
+    }
+}
@@ -163,6 +163,8 @@ pub enum DualTcpStream<S, T, T2, D> {
         #[pin] AsyncBincodeStream<S, T2, Tagged<()>, D>,
         Box<dyn FnMut(T2) -> T + Send + Sync>,
     ),
+    /// Each message is a zstd-compressed frame, as sent by `CompressedSink`.
+    Compressed(#[pin] AsyncBincodeStream<S, Vec<u8>, Tagged<()>, D>),
 }
 
 impl<S, T, T2> From<S> for DualTcpStream<S, T, T2, AsyncDestination> {
@@ -178,10 +180,15 @@ impl<S, T, T2> DualTcpStream<S, T, T2, AsyncDestination> {
         DualTcpStream::Upgrade(s, Box::new(f))
     }
 
+    pub fn compressed(stream: S) -> Self {
+        DualTcpStream::Compressed(AsyncBincodeStream::from(stream).for_async())
+    }
+
     pub fn get_ref(&self) -> &S {
         match *self {
             DualTcpStream::Passthrough(ref abs) => abs.get_ref(),
             DualTcpStream::Upgrade(ref abs, _) => abs.get_ref(),
+            DualTcpStream::Compressed(ref abs) => abs.get_ref(),
         }
     }
 }
@@ -191,6 +198,7 @@ where
     S: AsyncWrite,
     AsyncBincodeStream<S, T, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
     AsyncBincodeStream<S, T2, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
+    AsyncBincodeStream<S, Vec<u8>, Tagged<()>, D>: Sink<Tagged<()>, Error = bincode::Error>,
 {
     type Error = bincode::Error;
 
@@ -198,6 +206,7 @@ where
         match self.project() {
             DualTcpStreamProj::Passthrough(abs) => abs.poll_ready(cx),
             DualTcpStreamProj::Upgrade(abs, _) => abs.poll_ready(cx),
+            DualTcpStreamProj::Compressed(abs) => abs.poll_ready(cx),
         }
     }
 
@@ -205,6 +214,7 @@ where
         match self.project() {
             DualTcpStreamProj::Passthrough(abs) => abs.start_send(item),
             DualTcpStreamProj::Upgrade(abs, _) => abs.start_send(item),
+            DualTcpStreamProj::Compressed(abs) => abs.start_send(item),
         }
     }
 
@@ -212,6 +222,7 @@ where
         match self.project() {
             DualTcpStreamProj::Passthrough(abs) => abs.poll_flush(cx),
             DualTcpStreamProj::Upgrade(abs, _) => abs.poll_flush(cx),
+            DualTcpStreamProj::Compressed(abs) => abs.poll_flush(cx),
         }
     }
 
@@ -219,6 +230,7 @@ where
         match self.project() {
             DualTcpStreamProj::Passthrough(abs) => abs.poll_close(cx),
             DualTcpStreamProj::Upgrade(abs, _) => abs.poll_close(cx),
+            DualTcpStreamProj::Compressed(abs) => abs.poll_close(cx),
         }
     }
 }
@@ -230,6 +242,7 @@ where
     S: AsyncRead,
     AsyncBincodeStream<S, T, Tagged<()>, D>: Stream<Item = Result<T, bincode::Error>>,
     AsyncBincodeStream<S, T2, Tagged<()>, D>: Stream<Item = Result<T2, bincode::Error>>,
+    AsyncBincodeStream<S, Vec<u8>, Tagged<()>, D>: Stream<Item = Result<Vec<u8>, bincode::Error>>,
 {
     type Item = Result<T, bincode::Error>;
 
@@ -241,6 +254,13 @@ where
             DualTcpStreamProj::Upgrade(abr, upgrade) => {
                 Poll::Ready(ready!(abr.poll_next(cx)).transpose()?.map(upgrade).map(Ok))
             }
+            DualTcpStreamProj::Compressed(abr) => {
+                Poll::Ready(ready!(abr.poll_next(cx)).transpose()?.map(|frame| {
+                    let bytes = zstd::decode_all(&frame[..])
+                        .map_err(|e| Box::new(bincode::ErrorKind::Io(e)))?;
+                    bincode::deserialize(&bytes)
+                }))
+            }
         }
     }
 }
@@ -1,10 +1,18 @@
 use crate::consensus::{self, Authority};
-use crate::debug::stats;
+use crate::debug::{migration, plan, stats};
+use crate::errors::NoriaError;
+use crate::information_schema;
+use crate::internal::DomainIndex;
+use crate::results::Results;
 use crate::table::{Table, TableBuilder, TableRpc};
-use crate::view::{View, ViewBuilder, ViewRpc};
-use crate::ActivationResult;
+use crate::view::{RefreshPolicy, View, ViewBuilder, ViewRpc};
+use crate::{
+    ActivationResult, DataType, Dependencies, DryRunResult, NodeName, Provenance, RecipeValidation,
+    RetryPolicy, TableDescription, TableOperation, ViewComparison, ViewDescription,
+    WorkerConfigUpdate,
+};
 use failure::{self, ResultExt};
-use futures_util::future;
+use futures_util::{future, stream, Stream, StreamExt};
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
@@ -18,6 +26,36 @@ use std::{
 use tower_buffer::Buffer;
 use tower_service::Service;
 
+/// How many rows of each shard `ControllerHandle::compare_views` takes the keys of when it is not
+/// given any keys.
+pub const COMPARE_SAMPLE_KEYS: usize = 100;
+
+/// The keys of the first `COMPARE_SAMPLE_KEYS` rows of each shard of `view`.
+async fn sample_keys(view: &mut View) -> Result<Vec<Vec<DataType>>, failure::Error> {
+    // the values of echoed parameters don't show up in the view's rows
+    if !view.echoed().is_empty() || view.key_columns().is_empty() {
+        return Err(format_err!(
+            "keys must be given to compare views that echo parameters or have no known key"
+        ));
+    }
+    let key_columns = view.key_columns().to_vec();
+    let mut keys = Vec::new();
+    for shard in 0..view.shards() {
+        let chunk = Box::pin(view.iter_shard(shard, COMPARE_SAMPLE_KEYS))
+            .next()
+            .await;
+        if let Some(rows) = chunk {
+            let rows: Vec<Vec<DataType>> = rows?.into();
+            keys.extend(
+                rows.into_iter()
+                    .take(COMPARE_SAMPLE_KEYS)
+                    .map(|r| key_columns.iter().map(|&c| r[c].clone()).collect()),
+            );
+        }
+    }
+    Ok(keys)
+}
+
 /// Describes a running controller instance.
 ///
 /// A serialized version of this struct is stored in ZooKeeper so that clients can reach the
@@ -55,7 +93,7 @@ impl<A> Service<ControllerRequest> for Controller<A>
 where
     A: 'static + Authority,
 {
-    type Response = hyper::body::Bytes;
+    type Response = Result<hyper::body::Bytes, NoriaError>;
     type Error = failure::Error;
 
     #[cfg(not(doc))]
@@ -103,12 +141,17 @@ where
                     .map_err(|he| failure::Error::from(he).context("hyper response failed"))?;
 
                 match status {
-                    hyper::StatusCode::OK => return Ok(body),
-                    hyper::StatusCode::INTERNAL_SERVER_ERROR => bail!(
-                        "rpc call to {} failed: {}",
-                        path,
-                        String::from_utf8_lossy(&*body)
-                    ),
+                    hyper::StatusCode::OK => return Ok(Ok(body)),
+                    hyper::StatusCode::INTERNAL_SERVER_ERROR => {
+                        let e = serde_json::from_slice(&body).unwrap_or_else(|_| {
+                            NoriaError::Runtime(format!(
+                                "rpc call to {} failed: {}",
+                                path,
+                                String::from_utf8_lossy(&*body)
+                            ))
+                        });
+                        return Ok(Err(e));
+                    }
                     s => {
                         if s == hyper::StatusCode::SERVICE_UNAVAILABLE {
                             url = None;
@@ -138,6 +181,9 @@ where
 /// none of your operations will ever complete! Furthermore, you *must* use the `Runtime` to
 /// execute any futures returned from `ControllerHandle` (that is, you cannot just call `.wait()`
 /// on them).
+///
+/// The errors returned by methods that talk to the controller can be downcast to a
+/// [`NoriaError`](crate::error::NoriaError), which says what kind of error occurred.
 // TODO: this should be renamed to NoriaHandle, or maybe just Connection, since it also provides
 // reads and writes, which aren't controller actions!
 pub struct ControllerHandle<A>
@@ -147,6 +193,11 @@ where
     handle: Buffer<Controller<A>, ControllerRequest>,
     domains: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
     views: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    write_retry: RetryPolicy,
+    /// The zone that views prefer to read from.
+    zone: Option<String>,
     tracer: tracing::Dispatch,
 }
 
@@ -159,6 +210,10 @@ where
             handle: self.handle.clone(),
             domains: self.domains.clone(),
             views: self.views.clone(),
+            timeout: self.timeout,
+            retry: self.retry.clone(),
+            write_retry: self.write_retry.clone(),
+            zone: self.zone.clone(),
             tracer: self.tracer.clone(),
         }
     }
@@ -182,18 +237,19 @@ type RpcFuture<A, R> = crate::doc_mock::FutureWithExtra<Result<R, failure::Error
 
 // Needed b/c of https://github.com/rust-lang/rust/issues/65442
 async fn finalize<R, E>(
-    fut: impl Future<Output = Result<hyper::body::Bytes, E>>,
+    fut: impl Future<Output = Result<Result<hyper::body::Bytes, NoriaError>, E>>,
     err: &'static str,
 ) -> Result<R, failure::Error>
 where
     for<'de> R: Deserialize<'de>,
     E: std::fmt::Display + Send + Sync + 'static,
 {
-    let body: hyper::body::Bytes = fut.await.map_err(failure::Context::new).context(err)?;
+    let body = fut
+        .await
+        .map_err(|e| NoriaError::Network(format!("{}: {}", err, e)))??;
 
     serde_json::from_slice::<R>(&body)
-        .context("failed to response")
-        .context(err)
+        .map_err(|e| NoriaError::Network(format!("{}: failed to parse response: {}", err, e)))
         .map_err(failure::Error::from)
 }
 
@@ -205,6 +261,10 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         Ok(ControllerHandle {
             views: Default::default(),
             domains: Default::default(),
+            timeout: None,
+            retry: RetryPolicy::default(),
+            write_retry: RetryPolicy::never(),
+            zone: None,
             handle: Buffer::new(
                 Controller {
                     authority,
@@ -245,6 +305,40 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         Self::make(Arc::new(authority)).await
     }
 
+    /// Set how long each read or write may take before it gives up.
+    ///
+    /// This applies to `View`s and `Table`s obtained after this call, and can be changed for each
+    /// of them with `View::set_timeout` and `Table::set_timeout`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Set how reads that fail because of a broken connection are retried.
+    ///
+    /// This applies to `View`s obtained after this call. See [`RetryPolicy`] for details.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Set how writes that fail because of a broken connection are retried.
+    ///
+    /// This applies to `Table`s obtained after this call. Writes are not retried by default, since
+    /// a retried write may be applied twice. See [`RetryPolicy`] for details.
+    pub fn set_write_retry_policy(&mut self, retry: RetryPolicy) {
+        self.write_retry = retry;
+    }
+
+    /// Set the zone that this client is in.
+    ///
+    /// If views have replicas of their readers (see `Builder::set_reader_replicas` in
+    /// `noria-server`), `View`s obtained after this call read from a replica on workers whose
+    /// [`ZONE_LABEL`](crate::ZONE_LABEL) label is `zone`, and fail over to replicas in other zones
+    /// when reads from it fail because of a broken connection. By default, views read from the
+    /// first replica of each reader.
+    pub fn set_zone(&mut self, zone: Option<&str>) {
+        self.zone = zone.map(str::to_owned);
+    }
+
     /// Enumerate all known base tables.
     ///
     /// These have all been created in response to a `CREATE TABLE` statement in a recipe.
@@ -253,20 +347,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
     pub fn inputs(
         &mut self,
     ) -> impl Future<Output = Result<BTreeMap<String, NodeIndex>, failure::Error>> {
-        let fut = self
-            .handle
-            .call(ControllerRequest::new("inputs", &()).unwrap());
-
-        async move {
-            let body: hyper::body::Bytes = fut
-                .await
-                .map_err(failure::Context::new)
-                .context("failed to fetch inputs")?;
-
-            serde_json::from_slice(&body)
-                .context("couldn't parse input response")
-                .map_err(failure::Error::from)
-        }
+        self.rpc("inputs", (), "failed to fetch inputs")
     }
 
     /// Enumerate all known external views.
@@ -277,22 +358,100 @@ impl<A: Authority + 'static> ControllerHandle<A> {
     pub fn outputs(
         &mut self,
     ) -> impl Future<Output = Result<BTreeMap<String, NodeIndex>, failure::Error>> {
-        let fut = self
-            .handle
-            .call(ControllerRequest::new("outputs", &()).unwrap());
+        self.rpc("outputs", (), "failed to fetch outputs")
+    }
 
+    /// Describe the columns and key of the base table `name`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn describe_table(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Output = Result<TableDescription, failure::Error>> {
+        let fut = self.rpc::<_, Option<TableDescription>>(
+            "describe_table",
+            name,
+            "failed to describe table",
+        );
+        let name = name.to_string();
         async move {
-            let body: hyper::body::Bytes = fut
-                .await
-                .map_err(failure::Context::new)
-                .context("failed to fetch outputs")?;
-
-            serde_json::from_slice(&body)
-                .context("couldn't parse output response")
-                .map_err(failure::Error::from)
+            Ok(fut
+                .await?
+                .ok_or_else(|| NoriaError::NotFound(format!("table {}", name)))?)
         }
     }
 
+    /// Describe the columns and parameters of the view `name`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn describe_view(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Output = Result<ViewDescription, failure::Error>> {
+        let fut = self.rpc::<_, Option<ViewDescription>>(
+            "describe_view",
+            name,
+            "failed to describe view",
+        );
+        let name = name.to_string();
+        async move {
+            Ok(fut
+                .await?
+                .ok_or_else(|| NoriaError::NotFound(format!("view {}", name)))?)
+        }
+    }
+
+    /// List every base table and view, along with the tables and views each of them reads from
+    /// directly.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn list_dependencies(
+        &mut self,
+    ) -> impl Future<Output = Result<Dependencies, failure::Error>> {
+        self.rpc("list_dependencies", (), "failed to list dependencies")
+    }
+
+    /// Answer a query that a MySQL client issues to introspect the schema of `database`.
+    ///
+    /// `SHOW [FULL] TABLES`, `DESCRIBE t`, `SHOW COLUMNS FROM t`, and simple `SELECT`s from
+    /// `information_schema.tables` and `information_schema.columns` are answered from the
+    /// descriptions of this deployment's base tables and views. Returns `None` for any other
+    /// query, which should then be handled as usual.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub async fn information_schema(
+        &mut self,
+        database: &str,
+        query: &str,
+    ) -> Result<Option<Results>, failure::Error> {
+        let q = match information_schema::recognize(query)? {
+            Some(q) => q,
+            None => return Ok(None),
+        };
+
+        let mut relations = Vec::new();
+        for (name, _) in self.inputs().await? {
+            let t = self.describe_table(&name).await?;
+            let primary_key = if t.key_is_primary { t.key } else { vec![] };
+            relations.push(information_schema::Relation {
+                name,
+                is_view: false,
+                columns: t.columns,
+                primary_key,
+            });
+        }
+        for (name, _) in self.outputs().await? {
+            let v = self.describe_view(&name).await?;
+            relations.push(information_schema::Relation {
+                name,
+                is_view: true,
+                columns: v.columns,
+                primary_key: vec![],
+            });
+        }
+        q.answer(database, &relations).map(Some)
+    }
+
     /// Obtain a `View` that allows you to query the given external view.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -304,28 +463,35 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         assert_infrequent::at_most(200);
 
         let views = self.views.clone();
+        let timeout = self.timeout;
+        let retry = self.retry.clone();
+        let zone = self.zone.clone();
         let name = name.to_string();
-        let fut = self
-            .handle
-            .call(ControllerRequest::new("view_builder", &name).unwrap());
+        let fut = self.rpc::<_, Option<ViewBuilder>>(
+            "view_builder",
+            &name,
+            "failed to fetch view builder",
+        );
         async move {
-            let body: hyper::body::Bytes = fut
-                .await
-                .map_err(failure::Context::new)
-                .context("failed to fetch view builder")?;
-
-            match serde_json::from_slice::<Option<ViewBuilder>>(&body) {
-                Ok(Some(vb)) => Ok(vb.build(views)?),
-                Ok(None) => Err(failure::err_msg("view does not exist")),
-                Err(e) => Err(failure::Error::from(e)),
-            }
-            .map_err(move |e| e.context(format!("building view for {}", name)).into())
+            let vb = fut
+                .await?
+                .ok_or_else(|| NoriaError::NotFound(format!("view {}", name)))?;
+            let mut view = vb
+                .build_in_zone(views, zone.as_deref())
+                .map_err(|e| NoriaError::Network(format!("building view for {}: {}", name, e)))?;
+            view.set_timeout(timeout);
+            view.set_retry_policy(retry);
+            Ok(view)
         }
     }
 
     /// Obtain a `Table` that allows you to perform writes, deletes, and other operations on the
     /// given base table.
     ///
+    /// `name` may also be a view that filters and projects the columns of a single base table,
+    /// and that includes all of that table's primary key columns. Writes through such a handle
+    /// are given in terms of the view's columns, and are applied to the base table.
+    ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn table(&mut self, name: &str) -> impl Future<Output = Result<Table, failure::Error>> {
         // This call attempts to detect if this function is being called in a loop. If this
@@ -334,23 +500,25 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         assert_infrequent::at_most(200);
 
         let domains = self.domains.clone();
+        let timeout = self.timeout;
+        let retry = self.write_retry.clone();
         let name = name.to_string();
-        let fut = self
-            .handle
-            .call(ControllerRequest::new("table_builder", &name).unwrap());
+        let fut = self.rpc::<_, Option<TableBuilder>>(
+            "table_builder",
+            &name,
+            "failed to fetch table builder",
+        );
 
         async move {
-            let body: hyper::body::Bytes = fut
-                .await
-                .map_err(failure::Context::new)
-                .context("failed to fetch table builder")?;
-
-            match serde_json::from_slice::<Option<TableBuilder>>(&body) {
-                Ok(Some(tb)) => Ok(tb.build(domains)?),
-                Ok(None) => Err(failure::err_msg("view table not exist")),
-                Err(e) => Err(failure::Error::from(e)),
-            }
-            .map_err(move |e| e.context(format!("building table for {}", name)).into())
+            let tb = fut
+                .await?
+                .ok_or_else(|| NoriaError::NotFound(format!("table {}", name)))?;
+            let mut table = tb
+                .build(domains)
+                .map_err(|e| NoriaError::Network(format!("building table for {}: {}", name, e)))?;
+            table.set_timeout(timeout);
+            table.set_retry_policy(retry);
+            Ok(table)
         }
     }
 
@@ -379,6 +547,87 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("get_statistics", (), "failed to get stats")
     }
 
+    /// Get the name of every node in the graph, along with the names of the queries that use it.
+    ///
+    /// The nodes that the controller adds for a query are named after the query's structure,
+    /// what the node does, and the tables it reads from, so adding the same query again gives its
+    /// nodes the same names. The same names are used in [`Self::graphviz`] and
+    /// [`Self::statistics`].
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn node_names(
+        &mut self,
+    ) -> impl Future<Output = Result<HashMap<NodeIndex, NodeName>, failure::Error>> {
+        self.rpc("node_names", (), "failed to get node names")
+    }
+
+    /// Get the health that each worker reported in its most recent heartbeat.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn workers(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<stats::WorkerStats>, failure::Error>> {
+        self.rpc("workers", (), "failed to get worker health")
+    }
+
+    /// Sample the state of every node to find out how many rows it holds, roughly how many
+    /// distinct values each of its columns has, and which keys of each index have the most rows.
+    ///
+    /// The results are also kept by the controller, which uses them to plan later migrations.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn state_statistics(
+        &mut self,
+    ) -> impl Future<Output = Result<HashMap<NodeIndex, stats::StateStats>, failure::Error>> {
+        self.rpc("state_statistics", (), "failed to get state statistics")
+    }
+
+    /// Fetch the progress of the current (or most recent) migration.
+    ///
+    /// Unlike most other controller calls, this is answered even while a migration is running.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn migration_status(
+        &mut self,
+    ) -> impl Future<Output = Result<migration::MigrationStatus, failure::Error>> {
+        self.rpc("migration_status", (), "failed to fetch migration status")
+    }
+
+    /// Fetch the number of recipe changes that are waiting to be applied.
+    ///
+    /// Like `Self::migration_status`, this is answered even while a migration is running.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn migration_queue(
+        &mut self,
+    ) -> impl Future<Output = Result<migration::MigrationQueueStatus, failure::Error>> {
+        self.rpc("migration_queue", (), "failed to fetch migration queue")
+    }
+
+    /// Poll the progress of the current migration every `interval`.
+    ///
+    /// The stream ends after the first status that shows no migration in progress, or after the
+    /// first error.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn watch_migration(
+        &mut self,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<migration::MigrationStatus, failure::Error>> + '_ {
+        stream::unfold(Some(self), move |this| async move {
+            let this = this?;
+            let status = this.migration_status().await;
+            let next = match status {
+                Ok(ref s) if s.in_progress => {
+                    tokio::time::delay_for(interval).await;
+                    Some(this)
+                }
+                _ => None,
+            };
+            Some((status, next))
+        })
+    }
+
     /// Flush all partial state, evicting all rows present.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -386,6 +635,71 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         self.rpc("flush_partial", (), "failed to flush partial")
     }
 
+    /// Set how soon writes to the view `name` become visible to reads.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_view_refresh(
+        &mut self,
+        name: &str,
+        policy: RefreshPolicy,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "set_view_refresh",
+            (name, policy),
+            "failed to set view refresh policy",
+        )
+    }
+
+    /// Cap how many updates per second each shard of `domain` processes, or lift the cap with
+    /// `None`.
+    ///
+    /// A rate limited domain holds back packets it receives once it is over its limit, which
+    /// delays propagation through it rather than letting it starve other domains on the same
+    /// worker. How many packets a domain is holding back is reported as its `queue_depth` in
+    /// [`Self::statistics`]. The limit is not kept if the domain is restarted.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn set_domain_rate_limit(
+        &mut self,
+        domain: DomainIndex,
+        records_per_sec: Option<u64>,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "set_domain_rate_limit",
+            (domain, records_per_sec),
+            "failed to set domain rate limit",
+        )
+    }
+
+    /// Change runtime parameters of all workers without restarting them.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn update_worker_config(
+        &mut self,
+        update: WorkerConfigUpdate,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc(
+            "update_worker_config",
+            update,
+            "failed to update worker configuration",
+        )
+    }
+
+    /// Check whether `recipe` could replace the current recipe, without changing anything.
+    ///
+    /// The recipe is parsed and planned against an empty graph, and the views it would provide
+    /// are described along with rough estimates of how large the new ones would be. Queries that
+    /// cannot be planned, including ones that use SQL that Noria does not support, are reported as
+    /// errors rather than failing the call.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn validate_recipe(
+        &mut self,
+        recipe: &str,
+    ) -> impl Future<Output = Result<RecipeValidation, failure::Error>> {
+        self.rpc("validate_recipe", recipe, "failed to validate recipe")
+    }
+
     /// Extend the existing recipe with the given set of queries.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -424,6 +738,239 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         )
     }
 
+    /// Fetch a graphviz description of the dataflow graph where every node is annotated with its
+    /// shard count, materialization type, state size, and throughput.
+    ///
+    /// Nodes are colored by the domain they belong to.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn annotated_graphviz(&mut self) -> impl Future<Output = Result<String, failure::Error>> {
+        self.rpc(
+            "annotated_graphviz",
+            (),
+            "failed to fetch annotated graphviz output",
+        )
+    }
+
+    /// Install `query` as a new version of the existing named query `name`, side-by-side with
+    /// the current version.
+    ///
+    /// The new version is fully set up and backfilled, but reads for `name` keep going to the
+    /// current version. The returned name can be used to read from the new version, for example
+    /// through `Self::compare_views`, before it is swapped in with `Self::promote_view`.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn stage_view(
+        &mut self,
+        name: &str,
+        query: &str,
+    ) -> impl Future<Output = Result<String, failure::Error>> {
+        self.rpc("stage_view", (name, query), "failed to stage view")
+    }
+
+    /// Atomically replace the view `name` with the staged version `staged` returned by
+    /// `Self::stage_view`, and tear down the version that was replaced.
+    ///
+    /// `View` handles for `name` obtained before the swap keep reading from the old version; get
+    /// a new handle to read from the new one.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn promote_view(
+        &mut self,
+        name: &str,
+        staged: &str,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        self.rpc("promote_view", (name, staged), "failed to promote view")
+    }
+
+    /// Look up each of `keys` in both views `a` and `b`, and report how their results differ,
+    /// along with how many rows each view holds in total.
+    ///
+    /// Rows are compared without regard to order. Without `keys`, the keys of the first
+    /// `COMPARE_SAMPLE_KEYS` rows of each shard of either view are compared. Keys can only be
+    /// sampled from views that do not echo their parameters.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub async fn compare_views(
+        &mut self,
+        a: &str,
+        b: &str,
+        keys: Option<Vec<Vec<DataType>>>,
+    ) -> Result<ViewComparison, failure::Error> {
+        let mut va = self.view(a).await?;
+        let mut vb = self.view(b).await?;
+        let keys = match keys {
+            Some(keys) => keys,
+            None => {
+                let mut keys = sample_keys(&mut va).await?;
+                keys.extend(sample_keys(&mut vb).await?);
+                keys.sort();
+                keys.dedup();
+                keys
+            }
+        };
+        let ra = va.multi_lookup(keys.clone(), true).await?;
+        let rb = vb.multi_lookup(keys.clone(), true).await?;
+
+        let mut cmp = ViewComparison::default();
+        for ((key, ra), rb) in keys.into_iter().zip(ra).zip(rb) {
+            let mut ra: Vec<Vec<DataType>> = ra.into();
+            let mut rb: Vec<Vec<DataType>> = rb.into();
+            cmp.keys += 1;
+            cmp.rows_a += ra.len();
+            cmp.rows_b += rb.len();
+            ra.sort();
+            rb.sort();
+            if ra == rb {
+                cmp.matching += 1;
+            } else {
+                cmp.differing.push(key);
+            }
+        }
+        cmp.total_rows_a = va.len().await?;
+        cmp.total_rows_b = vb.len().await?;
+        Ok(cmp)
+    }
+
+    /// Find out what writing `ops` to the table `name` would change, without applying them.
+    ///
+    /// The operations are given as they would be to [`Table::perform_all`], and are run through
+    /// the table's base node against its current state, but the changes that come out are not
+    /// kept or sent on to any views. Instead, the result lists the rows the base would remove and
+    /// add, and which views below the table, and which keys of them, those rows would reach. This
+    /// is meant for tracking down why views are being invalidated unexpectedly.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub async fn dry_run(
+        &mut self,
+        name: &str,
+        ops: Vec<TableOperation>,
+    ) -> Result<DryRunResult, failure::Error> {
+        let table = self.table(name).await?;
+        let (base, ops) = table.prepare(ops)?;
+        self.ready().await?;
+        self.rpc("dry_run", (base, ops), "failed to dry-run write")
+            .await
+    }
+
+    /// Find out which base table rows `row`, a row read from the view `name`, was computed from.
+    ///
+    /// The row's values are followed back through the operators of the view to the base tables,
+    /// and the rows of each table that have the values the row took from it are returned. Through
+    /// filters and joins, these are the rows that produced the row. Values that an operator
+    /// computes, like aggregates, cannot be followed, so for an aggregated row this finds all the
+    /// rows of its group, and [`Provenance::exact`] is `false`. For a union, the matching
+    /// rows of each of its inputs are included. The row may leave out trailing columns, which
+    /// are then not used to narrow down the rows found.
+    ///
+    /// This looks at every row of each base table involved, and is meant for debugging.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn provenance(
+        &mut self,
+        name: &str,
+        row: Vec<DataType>,
+    ) -> impl Future<Output = Result<Provenance, failure::Error>> {
+        self.rpc("provenance", (name, row), "failed to trace row")
+    }
+
+    /// Get the plan for computing the named query or base table.
+    ///
+    /// The plan holds the query's MIR graph, what it reused of the queries that were already
+    /// there when it was added, and the dataflow nodes that compute it along with how they are
+    /// sharded and which domains they are in. It is meant for reviewing plans and for bug reports,
+    /// and its `Display` form is what `noria-plan` prints.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn query_plan(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Output = Result<plan::QueryPlan, failure::Error>> {
+        self.rpc("query_plan", name, "failed to get query plan")
+    }
+
+    /// Build a new generation of the dataflow graph from `recipe` next to the current one.
+    ///
+    /// The new generation shares the current base tables: tables in `recipe` that already exist
+    /// must be identical, and other tables are added. Every query in `recipe` must be named, and
+    /// should only read from base tables. Each query `q` is built as `q_g<generation>` alongside
+    /// the current `q`, where `<generation>` is the returned generation number, and can be read
+    /// under that name. Once this returns, the new generation's full materializations are warm.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn stage_generation(
+        &mut self,
+        recipe: &str,
+    ) -> impl Future<Output = Result<usize, failure::Error>> {
+        self.rpc("stage_generation", recipe, "failed to stage generation")
+    }
+
+    /// Atomically point every query name at the given generation staged with
+    /// `Self::stage_generation`, and tear down queries that are not part of it.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn switch_generation(
+        &mut self,
+        generation: usize,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        self.rpc(
+            "switch_generation",
+            generation,
+            "failed to switch generation",
+        )
+    }
+
+    /// Return to an earlier version of the recipe.
+    ///
+    /// `version` is a version number previously returned in `ActivationResult::version`. Queries
+    /// added since that version are removed, and queries removed since then are added back. The
+    /// rollback itself produces a new recipe version, which is returned.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn rollback(
+        &mut self,
+        version: usize,
+    ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
+        self.rpc("rollback", version, "failed to roll back recipe")
+    }
+
+    /// Remove the named query from the recipe, and tear down all dataflow nodes that are only
+    /// reachable from that query, freeing their state.
+    ///
+    /// Base tables cannot be removed this way, nor can queries that other queries depend on.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn remove_query(&mut self, name: &str) -> impl Future<Output = Result<(), failure::Error>> {
+        self.rpc("remove_query", name, "failed to remove query")
+    }
+
+    /// Run `query` once and return its results, without keeping it around as a view.
+    ///
+    /// The query is evaluated directly over the current rows of the tables that it reads, with
+    /// `params` for its placeholders, so no dataflow is built for it and the recipe is left
+    /// alone. This is meant for the occasional query from an admin console or a migration check:
+    /// each call reads the whole of every table that the query mentions. Only joins of tables,
+    /// filters, projections, simple aggregations, `DISTINCT`, `ORDER BY`, and `LIMIT` are
+    /// supported; other queries fail with `NoriaError::UnsupportedSql`, and should be installed
+    /// as a view instead.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn query_once(
+        &mut self,
+        query: &str,
+        params: &[DataType],
+    ) -> impl Future<Output = Result<Results, failure::Error>> {
+        let result = self.rpc::<_, (Vec<String>, Vec<Vec<DataType>>)>(
+            "query_once",
+            (query, params),
+            "failed to run query",
+        );
+        async move {
+            let (columns, rows) = result.await?;
+            Ok(Results::new(rows, Arc::from(columns)))
+        }
+    }
+
     /// Remove the given external view from the graph.
     ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
@@ -243,14 +243,23 @@ impl Ord for DataType {
             (&DataType::None, &DataType::None) => Ordering::Equal,
 
             // order Ints, Reals, Text, Timestamps, None
-            (&DataType::Int(..), _)
-            | (&DataType::UnsignedInt(..), _)
-            | (&DataType::BigInt(..), _)
-            | (&DataType::UnsignedBigInt(..), _) => Ordering::Greater,
-            (&DataType::Real(..), _) => Ordering::Greater,
-            (&DataType::Text(..), _) | (&DataType::TinyText(..), _) => Ordering::Greater,
-            (&DataType::Timestamp(..), _) => Ordering::Greater,
-            (&DataType::None, _) => Ordering::Greater,
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl DataType {
+    /// Where values of this type sort relative to values of other types.
+    fn rank(&self) -> u8 {
+        match *self {
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => 0,
+            DataType::Real(..) => 1,
+            DataType::Text(..) | DataType::TinyText(..) => 2,
+            DataType::Timestamp(..) => 3,
+            DataType::None => 4,
         }
     }
 }
@@ -670,6 +679,48 @@ pub enum Operation {
     Add,
     /// Subtract the given value from the existing value.
     Sub,
+    /// Keep the smaller of the existing value and the given one.
+    Min,
+    /// Keep the larger of the existing value and the given one.
+    Max,
+    /// Append the given value to the existing text.
+    Append,
+}
+
+impl Operation {
+    /// Combine the existing value `old` with `v`.
+    ///
+    /// `Add` and `Sub` only apply to integers. `Min` and `Max` ignore `NULL`s, so a `NULL` is
+    /// replaced by the given value. `Append` treats a `NULL` as empty text, and values that are
+    /// not text as their textual form.
+    pub fn apply(&self, old: &DataType, v: DataType) -> DataType {
+        match *self {
+            Operation::Add | Operation::Sub => {
+                let old: i128 = old.into();
+                let delta: i128 = v.into();
+                match *self {
+                    Operation::Add => (old + delta).into(),
+                    _ => (old - delta).into(),
+                }
+            }
+            Operation::Min | Operation::Max if old.is_none() => v,
+            Operation::Min | Operation::Max if v.is_none() => old.clone(),
+            Operation::Min => std::cmp::min(old.clone(), v),
+            Operation::Max => std::cmp::max(old.clone(), v),
+            Operation::Append => {
+                fn text(d: &DataType) -> String {
+                    match *d {
+                        DataType::None => String::new(),
+                        DataType::Text(..) | DataType::TinyText(..) => <&str>::from(d).to_owned(),
+                        _ => d.to_string(),
+                    }
+                }
+                let mut s = text(old);
+                s.push_str(&text(&v));
+                s.into()
+            }
+        }
+    }
 }
 
 /// A modification to make to a column in an existing row.
@@ -717,6 +768,34 @@ pub enum TableOperation {
         /// The key used to identify the row to update.
         key: Vec<DataType>,
     },
+    /// Update an existing row with the given `key`, but only if its version is `version`.
+    ///
+    /// Only applies to tables that keep a version counter for each row.
+    UpdateIf {
+        /// The modifications to make to each column of the existing row.
+        set: Vec<Modification>,
+        /// The key used to identify the row to update.
+        key: Vec<DataType>,
+        /// The version the row must be at for the update to be applied.
+        version: u64,
+    },
+    /// Delete a row with the contained key for good, even if the table uses soft deletes.
+    Purge {
+        /// The key.
+        key: Vec<DataType>,
+    },
+    /// Make the row with the given `key` be exactly `row`, or remove it for good if `row` is
+    /// `None`.
+    ///
+    /// This is how a deployment ships the effect of its writes to a standby: the row is taken
+    /// verbatim, so the table does not fill in any columns it would otherwise maintain itself.
+    #[doc(hidden)]
+    Replicate {
+        /// The key of the row. Empty for tables without a primary key.
+        key: Vec<DataType>,
+        /// The row as it should now be stored.
+        row: Option<Vec<DataType>>,
+    },
 }
 
 impl TableOperation {
@@ -1192,4 +1271,55 @@ mod tests {
         assert_ne!(hash(&long), hash(&time));
         assert_ne!(hash(&long), hash(&shrt6));
     }
+
+    #[test]
+    fn operations() {
+        let n = DataType::None;
+        assert_eq!(Operation::Add.apply(&3.into(), 2.into()), 5.into());
+        assert_eq!(Operation::Sub.apply(&3.into(), 2.into()), 1.into());
+
+        assert_eq!(Operation::Min.apply(&3.into(), 2.into()), 2.into());
+        assert_eq!(Operation::Min.apply(&3.into(), 4.into()), 3.into());
+        assert_eq!(Operation::Max.apply(&3.into(), 4.into()), 4.into());
+        assert_eq!(Operation::Max.apply(&n, 4.into()), 4.into());
+        assert_eq!(Operation::Min.apply(&3.into(), n.clone()), 3.into());
+        assert_eq!(Operation::Max.apply(&"a".into(), "b".into()), "b".into());
+
+        assert_eq!(
+            Operation::Append.apply(&"a,".into(), "b".into()),
+            "a,b".into()
+        );
+        assert_eq!(Operation::Append.apply(&n, "b".into()), "b".into());
+        assert_eq!(Operation::Append.apply(&"a".into(), 1.into()), "a1".into());
+    }
+
+    #[test]
+    fn data_type_order_is_total() {
+        let time = DataType::Timestamp(NaiveDateTime::from_timestamp(0, 0));
+        let mut vs: Vec<DataType> = vec![
+            DataType::None,
+            time.clone(),
+            "a".into(),
+            DataType::Real(1, 0),
+            2.into(),
+            DataType::BigInt(1),
+        ];
+        vs.sort();
+        assert_eq!(
+            vs,
+            vec![
+                DataType::BigInt(1),
+                2.into(),
+                DataType::Real(1, 0),
+                "a".into(),
+                time,
+                DataType::None,
+            ]
+        );
+        for a in &vs {
+            for b in &vs {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+            }
+        }
+    }
 }
@@ -0,0 +1,44 @@
+use crate::internal::DomainIndex;
+use serde::{Deserialize, Serialize};
+
+/// The progress of the controller's current (or most recent) migration.
+///
+/// All times are in milliseconds.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    /// Whether a migration is currently running.
+    pub in_progress: bool,
+    /// Time elapsed since the migration started, or how long it took if it has completed.
+    pub elapsed: u64,
+    /// New domains that the migration is creating.
+    pub new_domains: Vec<DomainIndex>,
+    /// How many of `new_domains` have been booted so far.
+    pub domains_booted: usize,
+    /// Number of new nodes that must be readied, which may involve replaying state into them.
+    pub materializations: usize,
+    /// How many of `materializations` have been completed so far.
+    pub materializations_done: usize,
+    /// Number of records replayed into the new materializations completed so far.
+    pub records_replayed: usize,
+    /// Estimated time until the migration completes, extrapolated from how quickly
+    /// materializations have been completed so far.
+    pub eta: Option<u64>,
+}
+
+/// The recipe changes that are waiting for the controller to apply them.
+///
+/// Recipe changes are applied one at a time, in the order in which they arrived.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MigrationQueueStatus {
+    /// Number of recipe changes that are queued, including the one that is being applied.
+    pub queued: usize,
+    /// The most recipe changes that are queued at once, if there is such a limit.
+    pub limit: Option<usize>,
+    /// Number of recipe changes that have left the queue so far, whether they were applied or
+    /// not.
+    pub completed: u64,
+    /// Number of recipe changes that were turned away because the queue was full.
+    ///
+    /// Clients retry these after a short delay.
+    pub deferred: u64,
+}
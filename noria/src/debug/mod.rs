@@ -1,2 +1,6 @@
+/// Types related to migration progress.
+pub mod migration;
+/// Types related to query plans.
+pub mod plan;
 /// Types related to graph statistics.
 pub mod stats;
@@ -0,0 +1,126 @@
+use crate::internal::DomainIndex;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How Noria computes the results of a query, as reported by `ControllerHandle::query_plan`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryPlan {
+    /// The name of the query.
+    pub name: String,
+    /// Which of the queries that were already there the query reused when it was added.
+    pub reuse: QueryReuse,
+    /// The query's MIR graph, in topological order from the base tables to the query's leaf.
+    pub mir: Vec<MirNodePlan>,
+    /// The dataflow nodes that compute the query, in topological order.
+    pub dataflow: Vec<DataflowNodePlan>,
+}
+
+/// What a query reused when it was added.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QueryReuse {
+    /// The query was planned from scratch, although its MIR nodes may still have been merged with
+    /// identical nodes of other queries.
+    None,
+    /// The query is the same as the named query, and is served by that query's view.
+    ExactMatch(String),
+    /// The query only differs from an existing query in its parameters, so its view was added
+    /// below the named MIR node of that query.
+    ReaderOntoExisting(String),
+    /// The query was planned on top of the named queries, which compute part of its results.
+    ExtendExisting(Vec<String>),
+}
+
+/// A node of a query's MIR graph.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MirNodePlan {
+    /// The node's name, including the recipe version it was added in.
+    pub name: String,
+    /// What the node does.
+    pub operator: String,
+    /// The names of the node's columns.
+    pub columns: Vec<String>,
+    /// The names of the node's parents.
+    pub ancestors: Vec<String>,
+    /// The dataflow node that the node was turned into, if any.
+    pub flow_node: Option<NodeIndex>,
+    /// Whether the node reuses an existing dataflow node, rather than having added its own.
+    pub reused: bool,
+}
+
+/// A node of the dataflow graph that computes a query.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DataflowNodePlan {
+    /// The node's index in the dataflow graph.
+    pub index: NodeIndex,
+    /// The node's name.
+    pub name: String,
+    /// What the node does.
+    pub operator: String,
+    /// The indices of the node's parents.
+    pub parents: Vec<NodeIndex>,
+    /// The column that the node is sharded by, if it is sharded by a column.
+    pub sharded_by: Option<String>,
+    /// How many shards the node is split into.
+    pub shards: usize,
+    /// The domain that the node is in.
+    pub domain: Option<DomainIndex>,
+}
+
+impl fmt::Display for QueryReuse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryReuse::None => write!(f, "none"),
+            QueryReuse::ExactMatch(ref q) => write!(f, "exact match of {}", q),
+            QueryReuse::ReaderOntoExisting(ref n) => write!(f, "new reader below {}", n),
+            QueryReuse::ExtendExisting(ref qs) => write!(f, "extends {}", qs.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "query: {}", self.name)?;
+        writeln!(f, "reuse: {}", self.reuse)?;
+
+        writeln!(f, "mir:")?;
+        for n in &self.mir {
+            write!(f, "  {}: {} [{}]", n.name, n.operator, n.columns.join(", "))?;
+            if !n.ancestors.is_empty() {
+                write!(f, " <- {}", n.ancestors.join(", "))?;
+            }
+            if let Some(ni) = n.flow_node {
+                write!(
+                    f,
+                    " => n{}{}",
+                    ni.index(),
+                    if n.reused { " (reused)" } else { "" }
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "dataflow:")?;
+        for n in &self.dataflow {
+            write!(f, "  n{} {}: {}", n.index.index(), n.name, n.operator)?;
+            if !n.parents.is_empty() {
+                let parents: Vec<_> = n
+                    .parents
+                    .iter()
+                    .map(|p| format!("n{}", p.index()))
+                    .collect();
+                write!(f, " <- {}", parents.join(", "))?;
+            }
+            match (n.shards, &n.sharded_by) {
+                (1, _) => write!(f, "; unsharded")?,
+                (s, Some(c)) => write!(f, "; {} shards by {}", s, c)?,
+                (s, None) => write!(f, "; {} shards, randomly", s)?,
+            }
+            if let Some(d) = n.domain {
+                write!(f, "; domain {}", d.index())?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
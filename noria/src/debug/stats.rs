@@ -1,8 +1,9 @@
 use crate::internal::*;
-use crate::MaterializationStatus;
+use crate::{DataType, MaterializationStatus};
 use petgraph::graph::NodeIndex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 
 type DomainMap = HashMap<(DomainIndex, usize), (DomainStats, HashMap<NodeIndex, NodeStats>)>;
 
@@ -21,6 +22,28 @@ pub struct DomainStats {
     pub total_forward_time: u64,
     /// Total wall-clock time spent waiting for work in this domain.
     pub wait_time: u64,
+    /// The number of packets that have been received but are yet to be processed, because the
+    /// domain is rate limited.
+    #[serde(default)]
+    pub queue_depth: usize,
+    /// The most forward updates per second the domain processes, if it is rate limited.
+    #[serde(default)]
+    pub rate_limit: Option<u64>,
+    /// The number of chunks of full replays that the domain has sent.
+    #[serde(default)]
+    pub full_replay_chunks: u64,
+    /// The most chunks of a full replay that the domain has had queued up at once, if the number
+    /// of queued chunks is limited.
+    #[serde(default)]
+    pub full_replay_backlog: u64,
+    /// The number of chunks of full replays that the domain held back so that other work could go
+    /// first.
+    #[serde(default)]
+    pub deferred_replay_chunks: u64,
+    /// The number of updates for other domains that were sent as part of an update queued before
+    /// them.
+    #[serde(default)]
+    pub coalesced_updates: u64,
 }
 
 /// Statistics about a node.
@@ -30,16 +53,119 @@ pub struct DomainStats {
 pub struct NodeStats {
     /// A textual description of this node.
     pub desc: String,
+    /// The name of this node.
+    #[serde(default)]
+    pub name: String,
+    /// The names of the queries whose results this node helps compute, including the other
+    /// names that the queries are known by, in order.
+    #[serde(default)]
+    pub queries: Vec<String>,
     /// Total wall-clock time elapsed while processing in this node.
     pub process_time: u64,
     /// Total thread time elapsed while processing in this node.
     pub process_ptime: u64,
+    /// Total number of records emitted by this node during forward processing.
+    #[serde(default)]
+    pub records: u64,
     /// Total memory size of this node's state.
     pub mem_size: u64,
     /// The materialization type of this node's state.
     pub materialized: MaterializationStatus,
     /// The value returned from Ingredient::probe.
     pub probe_result: HashMap<String, String>,
+    /// For nodes that shard their output, the keys that recently made up more than one shard's
+    /// fair share of the output, and roughly how many of the recent records had each key.
+    #[serde(default)]
+    pub hot_keys: Vec<(DataType, u64)>,
+    /// For persisted base tables, the number of records written that have not yet been synced to
+    /// disk.
+    #[serde(default)]
+    pub unsynced_records: usize,
+    /// For persisted base tables, how long the oldest record that has not yet been synced to disk
+    /// has been waiting.
+    #[serde(default)]
+    pub durability_lag: u64,
+    /// For Reader nodes, the number of milliseconds since the view was last read from.
+    #[serde(default)]
+    pub idle: Option<u64>,
+    /// For Reader nodes, whether the view's state was dropped because it was not read for a
+    /// while. The next reads of a hibernating view fill the keys they need again.
+    #[serde(default)]
+    pub hibernating: bool,
+}
+
+/// What is known about the contents of a node's state, found by sampling it.
+///
+/// Only some of the rows of a large state are sampled, so `distinct` and `hot_keys` describe the
+/// sampled rows rather than all of them. Partially materialized state only counts the rows and
+/// keys that are currently present.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateStats {
+    /// The number of rows in the state.
+    pub rows: usize,
+    /// The estimated number of distinct values in each column.
+    pub distinct: Vec<u64>,
+    /// Statistics about each of the state's indices.
+    pub indices: Vec<IndexStats>,
+}
+
+/// What is known about the contents of one index of a node's state.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IndexStats {
+    /// The columns the index is keyed by.
+    pub columns: Vec<usize>,
+    /// The number of keys in the index.
+    pub keys: usize,
+    /// The keys with the most rows, along with how many rows they have, most rows first.
+    pub hot_keys: Vec<(Vec<DataType>, usize)>,
+}
+
+impl StateStats {
+    /// The estimated number of distinct values in `column`, if it is known.
+    pub fn distinct(&self, column: usize) -> Option<u64> {
+        self.distinct.get(column).cloned()
+    }
+}
+
+/// The health of a worker, as of the most recent heartbeat it sent the controller.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkerStats {
+    /// The address the worker registered from.
+    pub addr: SocketAddr,
+    /// Whether the controller considers the worker to be alive.
+    pub healthy: bool,
+    /// The labels the worker registered with.
+    pub labels: HashMap<String, String>,
+    /// The version of the coordination protocol the controller and the worker agreed on.
+    pub version: u32,
+    /// Time since the worker's last heartbeat, in milliseconds.
+    pub last_heartbeat: u64,
+    /// One-minute load average of the worker's host.
+    pub cpu: f64,
+    /// Estimated number of bytes held in the state of the domains the worker runs.
+    pub memory: u64,
+    /// Resident memory of the worker's process, in bytes.
+    pub rss: u64,
+    /// Free space on the disk that base tables are persisted to, in bytes, if it is known.
+    pub disk_free: Option<u64>,
+    /// How many packets each domain shard on the worker has yet to deal with.
+    pub queues: Vec<DomainQueue>,
+    /// Rows written to bases on the worker that have yet to be applied to the standby deployment,
+    /// if writes are shipped to one.
+    #[serde(default)]
+    pub standby_lag: u64,
+}
+
+/// The packets a domain shard has yet to deal with: those held back by its rate limit, and
+/// those it has produced but not yet sent on to other domains.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DomainQueue {
+    /// The index of the domain.
+    pub domain: DomainIndex,
+    /// The shard of the domain.
+    pub shard: usize,
+    /// The number of packets queued.
+    pub queued: usize,
 }
 
 /// Statistics about the Soup data-flow.
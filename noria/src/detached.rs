@@ -0,0 +1,496 @@
+//! A compatibility shim that lets the Tokio 0.2 client be awaited from other executors.
+//!
+//! The client is not async/await-native on Tokio 1.x: the handles in the crate root are still
+//! built on Tokio 0.2, and must be driven by the 0.2 runtime that they were created on. The
+//! handles in this module wrap them instead. They own a private 0.2 runtime that drives all of
+//! their connections, much like the blocking handles in [`sync`](crate::sync), and spawn each
+//! call onto it. Their `async fn`s can therefore be awaited from Tokio 1.x, `async-std`, or any
+//! other executor, at the cost of that extra runtime and a task spawn per call. The shim goes
+//! away once the client itself moves to Tokio 1.x.
+//!
+//! Connections are pooled just like for the other handles: the [`DetachedView`]s and
+//! [`DetachedTable`]s obtained from one [`DetachedControllerHandle`] share one pool of connections
+//! for each shard of each view and table, and timeouts and retry policies apply to each call.
+//!
+//! Code that already runs on a Tokio 0.2 runtime should use [`ControllerHandle`] and friends
+//! directly.
+//!
+//! ```no_run
+//! # use noria::detached::DetachedControllerHandle;
+//! # async fn f() -> Result<(), failure::Error> {
+//! let mut db = DetachedControllerHandle::from_zk("127.0.0.1:2181/noria")?;
+//! db.extend_recipe("CREATE TABLE Article (aid int, title varchar(255), PRIMARY KEY(aid));")
+//!     .await?;
+//! let mut article = db.table("Article").await?;
+//! article.insert(vec![1.into(), "I love Soup".into()]).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::consensus::{Authority, ZookeeperAuthority};
+use crate::data::{DataType, Modification, TableOperation};
+use crate::error::{TableError, ViewError};
+use crate::results::{Results, Row};
+use crate::{ActivationResult, ControllerHandle, RetryPolicy, Table, View};
+use petgraph::graph::NodeIndex;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Run `f` on `rt`, and wait for it on whatever executor polls the returned future.
+async fn spawn<F>(rt: &Runtime, f: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    rt.spawn(f)
+        .await
+        .expect("noria client task panicked or was cancelled")
+}
+
+/// A version of [`ControllerHandle`] that can be used from any executor.
+///
+/// Cloning a `DetachedControllerHandle` yields another handle that uses the same runtime. The
+/// runtime shuts down once the last handle that uses it is dropped, which blocks the dropping
+/// thread until the runtime's threads have exited.
+pub struct DetachedControllerHandle<A>
+where
+    A: 'static + Authority,
+{
+    rt: Arc<Runtime>,
+    handle: ControllerHandle<A>,
+}
+
+impl<A> Clone for DetachedControllerHandle<A>
+where
+    A: 'static + Authority,
+{
+    fn clone(&self) -> Self {
+        DetachedControllerHandle {
+            rt: self.rt.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl DetachedControllerHandle<ZookeeperAuthority> {
+    /// Fetch information about the current Soup controller from Zookeeper running at the given
+    /// address, and create a `DetachedControllerHandle` from that.
+    pub fn from_zk(zookeeper_address: &str) -> Result<Self, failure::Error> {
+        let auth = ZookeeperAuthority::new(zookeeper_address)?;
+        DetachedControllerHandle::new(auth)
+    }
+}
+
+impl<A: Authority + 'static> DetachedControllerHandle<A> {
+    #[doc(hidden)]
+    pub fn make(authority: Arc<A>) -> Result<Self, failure::Error> {
+        let mut rt = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .thread_name("noria-detached")
+            .build()?;
+        let handle = rt.block_on(ControllerHandle::make(authority))?;
+        Ok(DetachedControllerHandle {
+            rt: Arc::new(rt),
+            handle,
+        })
+    }
+
+    /// Create a `DetachedControllerHandle` that bootstraps a connection to Noria via the
+    /// configuration stored in the given `authority`.
+    ///
+    /// This starts a new runtime to drive the connection. Since it blocks until the runtime is up,
+    /// call it before entering the executor, or from a thread that may block.
+    pub fn new(authority: A) -> Result<Self, failure::Error>
+    where
+        A: Send + 'static,
+    {
+        Self::make(Arc::new(authority))
+    }
+
+    /// Set how long each read or write may take before it gives up.
+    ///
+    /// See [`ControllerHandle::set_timeout`].
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.handle.set_timeout(timeout);
+    }
+
+    /// Set how reads that fail because of a broken connection are retried.
+    ///
+    /// See [`ControllerHandle::set_retry_policy`].
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.handle.set_retry_policy(retry);
+    }
+
+    /// Set how writes that fail because of a broken connection are retried.
+    ///
+    /// See [`ControllerHandle::set_write_retry_policy`].
+    pub fn set_write_retry_policy(&mut self, retry: RetryPolicy) {
+        self.handle.set_write_retry_policy(retry);
+    }
+
+    /// Set the zone that this client is in.
+    ///
+    /// See [`ControllerHandle::set_zone`].
+    pub fn set_zone(&mut self, zone: Option<&str>) {
+        self.handle.set_zone(zone);
+    }
+
+    /// Enumerate all known base tables.
+    pub async fn inputs(&mut self) -> Result<BTreeMap<String, NodeIndex>, failure::Error> {
+        let mut handle = self.handle.clone();
+        spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.inputs().await
+        })
+        .await
+    }
+
+    /// Enumerate all known external views.
+    pub async fn outputs(&mut self) -> Result<BTreeMap<String, NodeIndex>, failure::Error> {
+        let mut handle = self.handle.clone();
+        spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.outputs().await
+        })
+        .await
+    }
+
+    /// Obtain a `DetachedView` that allows you to query the given external view.
+    pub async fn view(&mut self, name: &str) -> Result<DetachedView, failure::Error> {
+        let mut handle = self.handle.clone();
+        let name = name.to_owned();
+        let view = spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.view(&name).await
+        })
+        .await?;
+        Ok(DetachedView {
+            rt: self.rt.clone(),
+            view,
+        })
+    }
+
+    /// Obtain a `DetachedTable` that allows you to perform writes, deletes, and other operations
+    /// on the given base table.
+    pub async fn table(&mut self, name: &str) -> Result<DetachedTable, failure::Error> {
+        let mut handle = self.handle.clone();
+        let name = name.to_owned();
+        let table = spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.table(&name).await
+        })
+        .await?;
+        Ok(DetachedTable {
+            rt: self.rt.clone(),
+            table,
+        })
+    }
+
+    /// Extend the existing recipe with the given set of queries.
+    pub async fn extend_recipe(
+        &mut self,
+        recipe_addition: &str,
+    ) -> Result<ActivationResult, failure::Error> {
+        let mut handle = self.handle.clone();
+        let recipe_addition = recipe_addition.to_owned();
+        spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.extend_recipe(&recipe_addition).await
+        })
+        .await
+    }
+
+    /// Replace the existing recipe with this one.
+    pub async fn install_recipe(
+        &mut self,
+        new_recipe: &str,
+    ) -> Result<ActivationResult, failure::Error> {
+        let mut handle = self.handle.clone();
+        let new_recipe = new_recipe.to_owned();
+        spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.install_recipe(&new_recipe).await
+        })
+        .await
+    }
+
+    /// Remove the named query from the recipe.
+    ///
+    /// See [`ControllerHandle::remove_query`].
+    pub async fn remove_query(&mut self, name: &str) -> Result<(), failure::Error> {
+        let mut handle = self.handle.clone();
+        let name = name.to_owned();
+        spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.remove_query(&name).await
+        })
+        .await
+    }
+
+    /// Fetch a graphviz description of the dataflow graph.
+    pub async fn graphviz(&mut self) -> Result<String, failure::Error> {
+        let mut handle = self.handle.clone();
+        spawn(&self.rt, async move {
+            handle.ready().await?;
+            handle.graphviz().await
+        })
+        .await
+    }
+
+    /// Run the given future on this handle's runtime, and wait for its result.
+    ///
+    /// This gives access to operations that do not have a wrapper here. `f` is typically an
+    /// `async move` block that uses a clone of the underlying [`ControllerHandle`], which
+    /// [`DetachedControllerHandle::handle`] gives access to.
+    pub async fn run<F>(&self, f: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        spawn(&self.rt, f).await
+    }
+
+    /// Get the underlying asynchronous handle.
+    ///
+    /// Futures that use it must be run with [`DetachedControllerHandle::run`].
+    pub fn handle(&mut self) -> &mut ControllerHandle<A> {
+        &mut self.handle
+    }
+}
+
+/// A version of [`View`] that can be used from any executor.
+#[derive(Clone)]
+pub struct DetachedView {
+    rt: Arc<Runtime>,
+    view: View,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl DetachedView {
+    /// Run `f` with the view on the runtime, and keep the view it hands back, so that connections
+    /// that it re-established along the way are used by later calls.
+    async fn call<F, Fut, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(View) -> Fut,
+        Fut: Future<Output = (View, T)> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (view, r) = spawn(&self.rt, f(self.view.clone())).await;
+        self.view = view;
+        r
+    }
+
+    /// Get the list of columns in this view.
+    pub fn columns(&self) -> &[String] {
+        self.view.columns()
+    }
+
+    /// Set how long each lookup may take before it gives up.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.view.set_timeout(timeout);
+    }
+
+    /// Get the current size of this view.
+    pub async fn len(&mut self) -> Result<usize, ViewError> {
+        self.call(|mut view| async move {
+            let r = view.len().await;
+            (view, r)
+        })
+        .await
+    }
+
+    /// Get the number of rows for the given parameter value.
+    ///
+    /// See [`View::count`].
+    pub async fn count(&mut self, key: &[DataType]) -> Result<usize, ViewError> {
+        let key = key.to_vec();
+        self.call(|mut view| async move {
+            let r = view.count(&key).await;
+            (view, r)
+        })
+        .await
+    }
+
+    /// Check whether there are any rows for the given parameter value.
+    pub async fn contains(&mut self, key: &[DataType]) -> Result<bool, ViewError> {
+        let key = key.to_vec();
+        self.call(|mut view| async move {
+            let r = view.contains(&key).await;
+            (view, r)
+        })
+        .await
+    }
+
+    /// Retrieve the query results for the given parameter values.
+    ///
+    /// See [`View::multi_lookup`].
+    pub async fn multi_lookup(
+        &mut self,
+        keys: Vec<Vec<DataType>>,
+        block: bool,
+    ) -> Result<Vec<Results>, ViewError> {
+        self.call(|mut view| async move {
+            let r = view.multi_lookup(keys, block).await;
+            (view, r)
+        })
+        .await
+    }
+
+    /// Retrieve the query results for the given parameter value.
+    ///
+    /// See [`View::lookup`].
+    pub async fn lookup(&mut self, key: &[DataType], block: bool) -> Result<Results, ViewError> {
+        let key = key.to_vec();
+        self.call(|mut view| async move {
+            let r = view.lookup(&key, block).await;
+            (view, r)
+        })
+        .await
+    }
+
+    /// Retrieve the query results for every integer key in the given range.
+    ///
+    /// See [`View::lookup_range`].
+    pub async fn lookup_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        self.call(|mut view| async move {
+            let r = view.lookup_range(range, block).await;
+            (view, r)
+        })
+        .await
+    }
+
+    /// Retrieve the first query result for the given parameter value.
+    ///
+    /// See [`View::lookup_first`].
+    pub async fn lookup_first(
+        &mut self,
+        key: &[DataType],
+        block: bool,
+    ) -> Result<Option<Row>, ViewError> {
+        let key = key.to_vec();
+        self.call(|mut view| async move {
+            let r = view.lookup_first(&key, block).await;
+            (view, r)
+        })
+        .await
+    }
+}
+
+/// A version of [`Table`] that can be used from any executor.
+#[derive(Clone)]
+pub struct DetachedTable {
+    rt: Arc<Runtime>,
+    table: Table,
+}
+
+impl DetachedTable {
+    /// Run `f` with the table on the runtime, and keep the table it hands back, so that
+    /// connections that it re-established along the way are used by later calls.
+    async fn call<F, Fut>(&mut self, f: F) -> Result<(), TableError>
+    where
+        F: FnOnce(Table) -> Fut,
+        Fut: Future<Output = (Table, Result<(), TableError>)> + Send + 'static,
+    {
+        let (table, r) = spawn(&self.rt, f(self.table.clone())).await;
+        self.table = table;
+        r
+    }
+
+    /// Get the name of this base table.
+    pub fn table_name(&self) -> &str {
+        self.table.table_name()
+    }
+
+    /// Get the list of columns in this base table.
+    pub fn columns(&self) -> &[String] {
+        self.table.columns()
+    }
+
+    /// Set how long each write may take before it gives up.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.table.set_timeout(timeout);
+    }
+
+    /// Insert a single row of data into this base table.
+    pub async fn insert<V>(&mut self, u: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        let u = u.into();
+        self.call(|mut table| async move {
+            let r = table.insert(u).await;
+            (table, r)
+        })
+        .await
+    }
+
+    /// Perform multiple operation on this base table.
+    pub async fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        let ops: Vec<TableOperation> = i.into_iter().map(Into::into).collect();
+        self.call(|mut table| async move {
+            let r = table.perform_all(ops).await;
+            (table, r)
+        })
+        .await
+    }
+
+    /// Delete the row with the given key from this base table.
+    pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+    {
+        let key = key.into();
+        self.call(|mut table| async move {
+            let r = table.delete(key).await;
+            (table, r)
+        })
+        .await
+    }
+
+    /// Update the row with the given key in this base table.
+    ///
+    /// See [`Table::update`].
+    pub async fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let u: Vec<_> = u.into_iter().collect();
+        self.call(|mut table| async move {
+            let r = table.update(key, u).await;
+            (table, r)
+        })
+        .await
+    }
+
+    /// Perform a insert-or-update on this base table.
+    ///
+    /// See [`Table::insert_or_update`].
+    pub async fn insert_or_update<V>(
+        &mut self,
+        insert: Vec<DataType>,
+        update: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let update: Vec<_> = update.into_iter().collect();
+        self.call(|mut table| async move {
+            let r = table.insert_or_update(insert, update).await;
+            (table, r)
+        })
+        .await
+    }
+}
@@ -0,0 +1,61 @@
+/// A failed request to the Noria controller.
+///
+/// Errors are classified by what went wrong, so that clients can react to them without matching
+/// on error messages. [`NoriaError::code`] gives a stable, machine-readable name for each class.
+///
+/// The `failure::Error`s returned by the methods on
+/// [`ControllerHandle`](crate::ControllerHandle) that talk to the controller can be downcast to a
+/// `NoriaError`:
+///
+/// ```no_run
+/// # use noria::error::NoriaError;
+/// # async fn f(mut ch: noria::ControllerHandle<noria::ZookeeperAuthority>) {
+/// if let Err(e) = ch.extend_recipe("QUERY q: SELECT a FROM nope WHERE b = ?;").await {
+///     match e.downcast_ref::<NoriaError>() {
+///         Some(NoriaError::Planning(_)) => eprintln!("fix your query: {}", e),
+///         _ => eprintln!("try again later: {}", e),
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Fail, Serialize, Deserialize)]
+pub enum NoriaError {
+    /// A query or recipe could not be parsed.
+    #[fail(display = "parse error: {}", _0)]
+    Parse(String),
+    /// A query uses SQL that Noria does not support.
+    #[fail(display = "unsupported SQL: {}", _0)]
+    UnsupportedSql(String),
+    /// A query was parsed, but could not be turned into dataflow.
+    #[fail(display = "planning failed: {}", _0)]
+    Planning(String),
+    /// The named table, view, query, or domain does not exist.
+    #[fail(display = "{} does not exist", _0)]
+    NotFound(String),
+    /// The controller could not be reached, or its reply could not be understood.
+    #[fail(display = "network error: {}", _0)]
+    Network(String),
+    /// The controller failed to carry out the request.
+    #[fail(display = "{}", _0)]
+    Runtime(String),
+}
+
+impl NoriaError {
+    /// A short, stable name for the class of this error.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            NoriaError::Parse(_) => "parse_error",
+            NoriaError::UnsupportedSql(_) => "unsupported_sql",
+            NoriaError::Planning(_) => "planning_error",
+            NoriaError::NotFound(_) => "not_found",
+            NoriaError::Network(_) => "network_error",
+            NoriaError::Runtime(_) => "runtime_error",
+        }
+    }
+}
+
+impl From<String> for NoriaError {
+    fn from(e: String) -> Self {
+        NoriaError::Runtime(e)
+    }
+}
@@ -0,0 +1,134 @@
+//! Serving queries that Noria cannot plan from an upstream database.
+//!
+//! A [`Fallback`] lets an application move its reads to Noria one query at a time, without first
+//! working out which of its queries Noria supports. Each query is installed in Noria when it is
+//! first prepared. If Noria rejects the query because it cannot parse, support, or plan it, the
+//! query is instead sent to the upstream database every time it is executed. Other errors, such
+//! as the controller being unreachable, are returned as usual, so that a transient failure does
+//! not move a query to the upstream for good.
+//!
+//! The upstream is anything that implements [`Upstream`], usually a thin wrapper around a MySQL
+//! or PostgreSQL client. It is only ever given the query as it was prepared and the values of the
+//! query's parameters.
+//!
+//! ```no_run
+//! # use noria::fallback::{Fallback, Upstream};
+//! # async fn f<U: Upstream>(upstream: U) -> Result<(), failure::Error> {
+//! let ch = noria::ControllerHandle::from_zk("10.0.0.1:2181/noria").await?;
+//! let mut db = Fallback::new(ch, upstream);
+//! db.prepare("title", "SELECT title FROM article WHERE id = ?").await?;
+//! let rows = db.execute("title", &[42.into()]).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::consensus::Authority;
+use crate::errors::NoriaError;
+use crate::{ControllerHandle, DataType, View};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+/// A database that queries Noria cannot plan are sent to.
+pub trait Upstream {
+    /// Run `query` with its parameters set to `params`, and return the resulting rows.
+    fn query<'a>(
+        &'a mut self,
+        query: &'a str,
+        params: &'a [DataType],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Vec<DataType>>, failure::Error>> + Send + 'a>>;
+}
+
+/// Where the reads for a prepared query go.
+enum Route {
+    Noria(View),
+    Upstream { query: String, reason: NoriaError },
+}
+
+/// Runs queries in Noria where it can, and in an upstream database where it cannot.
+pub struct Fallback<A: Authority + 'static, U> {
+    ch: ControllerHandle<A>,
+    upstream: U,
+    routes: HashMap<String, Route>,
+}
+
+impl<A: Authority + 'static, U: Upstream> Fallback<A, U> {
+    /// Serve queries from the Noria deployment behind `ch`, and fall back to `upstream`.
+    pub fn new(ch: ControllerHandle<A>, upstream: U) -> Self {
+        Fallback {
+            ch,
+            upstream,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Prepare `query` to be executed under the name `name`.
+    ///
+    /// Returns `true` if the query will be served by Noria, and `false` if it will be sent to the
+    /// upstream database.
+    pub async fn prepare(&mut self, name: &str, query: &str) -> Result<bool, failure::Error> {
+        let query = query.trim().trim_end_matches(';');
+        self.ch.ready().await?;
+        let installed = self
+            .ch
+            .extend_recipe(&format!("QUERY {}: {};", name, query))
+            .await;
+        let route = match installed {
+            Ok(_) => {
+                self.ch.ready().await?;
+                Route::Noria(self.ch.view(name).await?)
+            }
+            Err(e) => match e.downcast::<NoriaError>() {
+                Ok(reason @ NoriaError::Parse(_))
+                | Ok(reason @ NoriaError::UnsupportedSql(_))
+                | Ok(reason @ NoriaError::Planning(_)) => Route::Upstream {
+                    query: query.to_owned(),
+                    reason,
+                },
+                Ok(e) => return Err(e.into()),
+                Err(e) => return Err(e),
+            },
+        };
+
+        let in_noria = match route {
+            Route::Noria(_) => true,
+            Route::Upstream { .. } => false,
+        };
+        self.routes.insert(name.to_owned(), route);
+        Ok(in_noria)
+    }
+
+    /// Execute the query prepared under the name `name` with its parameters set to `params`.
+    pub async fn execute(
+        &mut self,
+        name: &str,
+        params: &[DataType],
+    ) -> Result<Vec<Vec<DataType>>, failure::Error> {
+        match self.routes.get_mut(name) {
+            Some(Route::Noria(view)) => {
+                // views without parameters are keyed by a single, constant value
+                let rows = if params.is_empty() {
+                    view.lookup(&[0.into()], true).await?
+                } else {
+                    view.lookup(params, true).await?
+                };
+                Ok(rows.into())
+            }
+            Some(Route::Upstream { query, .. }) => self.upstream.query(query, params).await,
+            None => Err(NoriaError::NotFound(format!("prepared query {}", name)).into()),
+        }
+    }
+
+    /// Why the query prepared under the name `name` is sent to the upstream database, if it is.
+    pub fn fallback_reason(&self, name: &str) -> Option<&NoriaError> {
+        match self.routes.get(name) {
+            Some(Route::Upstream { reason, .. }) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// The handle to the Noria deployment that queries are served from.
+    pub fn handle(&mut self) -> &mut ControllerHandle<A> {
+        &mut self.ch
+    }
+}
@@ -0,0 +1,461 @@
+//! Answers to the queries that MySQL clients issue to find out about a database's schema.
+//!
+//! ORMs and GUI clients typically run `SHOW TABLES`, `DESCRIBE t`, or read from
+//! `information_schema.tables` and `information_schema.columns` when they connect. Noria has no
+//! such tables, so a MySQL adapter can hand these queries to
+//! [`ControllerHandle::information_schema`](crate::ControllerHandle::information_schema), which
+//! answers them from the descriptions of the deployment's base tables and views.
+
+use crate::data::DataType;
+use crate::results::Results;
+use crate::ColumnDescription;
+use nom_sql::{
+    ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression, Operator,
+    OrderType, SelectStatement, SqlQuery,
+};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+const TABLES: &[&str] = &["TABLE_CATALOG", "TABLE_SCHEMA", "TABLE_NAME", "TABLE_TYPE"];
+const COLUMNS: &[&str] = &[
+    "TABLE_CATALOG",
+    "TABLE_SCHEMA",
+    "TABLE_NAME",
+    "COLUMN_NAME",
+    "ORDINAL_POSITION",
+    "COLUMN_DEFAULT",
+    "IS_NULLABLE",
+    "DATA_TYPE",
+    "COLUMN_TYPE",
+    "COLUMN_KEY",
+];
+const DESCRIBE: &[&str] = &["Field", "Type", "Null", "Key", "Default", "Extra"];
+
+/// A base table or view, as seen by schema introspection queries.
+#[derive(Clone, Debug)]
+pub(crate) struct Relation {
+    pub(crate) name: String,
+    pub(crate) is_view: bool,
+    pub(crate) columns: Vec<ColumnDescription>,
+    /// The indices of the columns of the primary key, if any.
+    pub(crate) primary_key: Vec<usize>,
+}
+
+impl Relation {
+    fn column_type(&self, column: usize) -> String {
+        match self.columns[column].sql_type {
+            Some(ref t) => t.to_string().to_lowercase(),
+            None => "unknown".to_owned(),
+        }
+    }
+
+    fn column_key(&self, column: usize) -> &'static str {
+        if self.primary_key.contains(&column) {
+            "PRI"
+        } else {
+            ""
+        }
+    }
+}
+
+/// A schema introspection query.
+#[derive(Clone, Debug)]
+pub(crate) enum Introspection {
+    /// `SHOW [FULL] TABLES`
+    ShowTables { full: bool },
+    /// `DESCRIBE t`, or `SHOW COLUMNS FROM t`
+    Describe(String),
+    /// `SELECT ... FROM information_schema.tables`
+    Tables(SelectStatement),
+    /// `SELECT ... FROM information_schema.columns`
+    Columns(SelectStatement),
+}
+
+/// Strip quotes and any database name from a table name.
+fn table_name(name: &str) -> String {
+    let name = name.rsplit('.').next().unwrap();
+    name.trim_matches(|c| c == '`' || c == '"' || c == '\'')
+        .to_owned()
+}
+
+/// Find out whether `query` is a schema introspection query.
+///
+/// Returns `None` for any other query. `LIKE` and `WHERE` clauses of `SHOW` statements are
+/// ignored.
+pub(crate) fn recognize(query: &str) -> Result<Option<Introspection>, failure::Error> {
+    let query = query.trim().trim_end_matches(';').trim();
+    let words: Vec<_> = query.split_whitespace().collect();
+    let lower: Vec<_> = words.iter().map(|w| w.to_ascii_lowercase()).collect();
+    let lower: Vec<_> = lower.iter().map(String::as_str).collect();
+    match lower[..] {
+        ["show", "tables", ..] => return Ok(Some(Introspection::ShowTables { full: false })),
+        ["show", "full", "tables", ..] => {
+            return Ok(Some(Introspection::ShowTables { full: true }));
+        }
+        ["describe", _, ..] | ["desc", _, ..] => {
+            return Ok(Some(Introspection::Describe(table_name(words[1]))));
+        }
+        ["show", "columns", "from", _, ..] | ["show", "fields", "from", _, ..] => {
+            return Ok(Some(Introspection::Describe(table_name(words[3]))));
+        }
+        ["select", ..] => {}
+        _ => return Ok(None),
+    }
+
+    // nom_sql does not know about schema-qualified table names, so drop the schema
+    const SCHEMA: &str = "information_schema.";
+    let lowered = query.to_ascii_lowercase();
+    if !lowered.contains(SCHEMA) {
+        return Ok(None);
+    }
+    let mut stripped = String::with_capacity(query.len());
+    let mut rest = 0;
+    for (i, _) in lowered.match_indices(SCHEMA) {
+        stripped.push_str(&query[rest..i]);
+        rest = i + SCHEMA.len();
+    }
+    stripped.push_str(&query[rest..]);
+
+    let select = match nom_sql::parse_query(&stripped) {
+        Ok(SqlQuery::Select(select)) => select,
+        _ => bail!("unsupported information_schema query: {}", query),
+    };
+    if select.tables.len() != 1 || !select.join.is_empty() {
+        bail!("information_schema queries must read from a single table");
+    }
+    match &*select.tables[0].name.to_ascii_lowercase() {
+        "tables" => Ok(Some(Introspection::Tables(select))),
+        "columns" => Ok(Some(Introspection::Columns(select))),
+        t => bail!("information_schema.{} is not supported", t),
+    }
+}
+
+impl Introspection {
+    /// Answer this query for the database `database`, which holds `relations`.
+    pub(crate) fn answer(
+        &self,
+        database: &str,
+        relations: &[Relation],
+    ) -> Result<Results, failure::Error> {
+        match *self {
+            Introspection::ShowTables { full } => {
+                let mut columns = vec![format!("Tables_in_{}", database)];
+                if full {
+                    columns.push("Table_type".to_owned());
+                }
+                let rows = relations
+                    .iter()
+                    .map(|r| {
+                        let mut row: Vec<DataType> = vec![r.name.as_str().into()];
+                        if full {
+                            row.push(table_type(r).into());
+                        }
+                        row
+                    })
+                    .collect();
+                Ok(Results::new(rows, Arc::from(columns)))
+            }
+            Introspection::Describe(ref name) => {
+                let r = relations
+                    .iter()
+                    .find(|r| &r.name == name)
+                    .ok_or_else(|| format_err!("table {} does not exist", name))?;
+                let rows = (0..r.columns.len())
+                    .map(|c| {
+                        vec![
+                            r.columns[c].name.as_str().into(),
+                            r.column_type(c).into(),
+                            "YES".into(),
+                            r.column_key(c).into(),
+                            DataType::None,
+                            "".into(),
+                        ]
+                    })
+                    .collect();
+                Ok(Results::new(rows, names(DESCRIBE)))
+            }
+            Introspection::Tables(ref select) => {
+                let rows = relations
+                    .iter()
+                    .map(|r| {
+                        vec![
+                            "def".into(),
+                            database.into(),
+                            r.name.as_str().into(),
+                            table_type(r).into(),
+                        ]
+                    })
+                    .collect();
+                evaluate(select, TABLES, rows)
+            }
+            Introspection::Columns(ref select) => {
+                let rows = relations
+                    .iter()
+                    .flat_map(|r| {
+                        (0..r.columns.len()).map(move |c| {
+                            let column_type = r.column_type(c);
+                            let data_type = column_type.split('(').next().unwrap().to_owned();
+                            vec![
+                                "def".into(),
+                                database.into(),
+                                r.name.as_str().into(),
+                                r.columns[c].name.as_str().into(),
+                                (c + 1).into(),
+                                DataType::None,
+                                "YES".into(),
+                                data_type.into(),
+                                column_type.into(),
+                                r.column_key(c).into(),
+                            ]
+                        })
+                    })
+                    .collect();
+                evaluate(select, COLUMNS, rows)
+            }
+        }
+    }
+}
+
+fn table_type(r: &Relation) -> &'static str {
+    if r.is_view {
+        "VIEW"
+    } else {
+        "BASE TABLE"
+    }
+}
+
+fn names(columns: &[&str]) -> Arc<[String]> {
+    columns
+        .iter()
+        .map(|&c| c.to_owned())
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn column_index(columns: &[&str], name: &str) -> Result<usize, failure::Error> {
+    columns
+        .iter()
+        .position(|c| c.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format_err!("unknown information_schema column {}", name))
+}
+
+/// Whether `row` satisfies `cond`, which may only compare columns to literals.
+fn matches(
+    cond: &ConditionExpression,
+    columns: &[&str],
+    row: &[DataType],
+) -> Result<bool, failure::Error> {
+    match *cond {
+        ConditionExpression::Bracketed(ref inner) => matches(inner, columns, row),
+        ConditionExpression::NegationOp(ref inner) => Ok(!matches(inner, columns, row)?),
+        ConditionExpression::LogicalOp(ConditionTree {
+            ref operator,
+            ref left,
+            ref right,
+        }) => match *operator {
+            Operator::And => Ok(matches(left, columns, row)? && matches(right, columns, row)?),
+            Operator::Or => Ok(matches(left, columns, row)? || matches(right, columns, row)?),
+            _ => bail!(
+                "unsupported operator {:?} in information_schema query",
+                operator
+            ),
+        },
+        ConditionExpression::ComparisonOp(ConditionTree {
+            ref operator,
+            ref left,
+            ref right,
+        }) => {
+            use ConditionBase::{Field, Literal};
+            use ConditionExpression::Base;
+            let (column, literal, flipped) = match (&**left, &**right) {
+                (Base(Field(c)), Base(Literal(l))) => (c, l, false),
+                (Base(Literal(l)), Base(Field(c))) => (c, l, true),
+                _ => bail!("information_schema queries may only compare columns to literals"),
+            };
+            let v = &row[column_index(columns, &column.name)?];
+            let l = DataType::from(literal);
+            let ord = if flipped { l.cmp(v) } else { v.cmp(&l) };
+            Ok(match *operator {
+                Operator::Equal => ord == Ordering::Equal,
+                Operator::NotEqual => ord != Ordering::Equal,
+                Operator::Less => ord == Ordering::Less,
+                Operator::LessOrEqual => ord != Ordering::Greater,
+                Operator::Greater => ord == Ordering::Greater,
+                Operator::GreaterOrEqual => ord != Ordering::Less,
+                _ => bail!(
+                    "unsupported operator {:?} in information_schema query",
+                    operator
+                ),
+            })
+        }
+        _ => bail!("unsupported condition in information_schema query"),
+    }
+}
+
+/// Run `select` over the rows of a virtual table with the given columns.
+fn evaluate(
+    select: &SelectStatement,
+    columns: &[&str],
+    mut rows: Vec<Vec<DataType>>,
+) -> Result<Results, failure::Error> {
+    if let Some(ref cond) = select.where_clause {
+        let mut kept = Vec::with_capacity(rows.len());
+        for row in rows {
+            if matches(cond, columns, &row)? {
+                kept.push(row);
+            }
+        }
+        rows = kept;
+    }
+
+    if let Some(ref order) = select.order {
+        let order = order
+            .columns
+            .iter()
+            .map(|(c, o)| Ok((column_index(columns, &c.name)?, o.clone())))
+            .collect::<Result<Vec<_>, failure::Error>>()?;
+        rows.sort_by(|a, b| {
+            order
+                .iter()
+                .map(|&(c, ref o)| match *o {
+                    OrderType::OrderAscending => a[c].cmp(&b[c]),
+                    OrderType::OrderDescending => b[c].cmp(&a[c]),
+                })
+                .find(|&o| o != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+
+    if let Some(ref limit) = select.limit {
+        rows = rows
+            .into_iter()
+            .skip(limit.offset as usize)
+            .take(limit.limit as usize)
+            .collect();
+    }
+
+    let mut project = Vec::new();
+    let mut names = Vec::new();
+    for field in &select.fields {
+        match *field {
+            FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => {
+                project.extend(0..columns.len());
+                names.extend(columns.iter().map(|&c| c.to_owned()));
+            }
+            FieldDefinitionExpression::Col(ref c) if c.function.is_none() => {
+                project.push(column_index(columns, &c.name)?);
+                names.push(c.alias.clone().unwrap_or_else(|| c.name.clone()));
+            }
+            _ => bail!("information_schema queries may only select columns"),
+        }
+    }
+    let rows = rows
+        .into_iter()
+        .map(|row| project.iter().map(|&c| row[c].clone()).collect())
+        .collect();
+    Ok(Results::new(rows, Arc::from(names)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom_sql::SqlType;
+
+    fn relations() -> Vec<Relation> {
+        vec![
+            Relation {
+                name: "article".to_owned(),
+                is_view: false,
+                columns: vec![
+                    ColumnDescription {
+                        name: "id".to_owned(),
+                        sql_type: Some(SqlType::Int(32)),
+                    },
+                    ColumnDescription {
+                        name: "title".to_owned(),
+                        sql_type: Some(SqlType::Varchar(255)),
+                    },
+                ],
+                primary_key: vec![0],
+            },
+            Relation {
+                name: "awvc".to_owned(),
+                is_view: true,
+                columns: vec![ColumnDescription {
+                    name: "votes".to_owned(),
+                    sql_type: None,
+                }],
+                primary_key: vec![],
+            },
+        ]
+    }
+
+    fn answer(query: &str) -> Vec<Vec<DataType>> {
+        recognize(query)
+            .unwrap()
+            .unwrap()
+            .answer("news", &relations())
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn it_ignores_other_queries() {
+        assert!(recognize("SELECT * FROM article").unwrap().is_none());
+        assert!(recognize("INSERT INTO article VALUES (1, 'a')")
+            .unwrap()
+            .is_none());
+        assert!(recognize("SELECT * FROM information_schema.routines").is_err());
+    }
+
+    #[test]
+    fn it_shows_tables() {
+        assert_eq!(
+            answer("SHOW TABLES;"),
+            vec![vec!["article".into()], vec!["awvc".into()]]
+        );
+        assert_eq!(
+            answer("show full tables"),
+            vec![
+                vec!["article".into(), "BASE TABLE".into()],
+                vec!["awvc".into(), "VIEW".into()]
+            ]
+        );
+    }
+
+    #[test]
+    fn it_describes() {
+        let described = answer("DESCRIBE `news`.`article`");
+        assert_eq!(described.len(), 2);
+        assert_eq!(described[0][0], "id".into());
+        assert_eq!(described[0][3], "PRI".into());
+        assert_eq!(described[1][1], "varchar(255)".into());
+        assert_eq!(answer("SHOW COLUMNS FROM awvc")[0][1], "unknown".into());
+        assert!(recognize("DESC nope")
+            .unwrap()
+            .unwrap()
+            .answer("news", &relations())
+            .is_err());
+    }
+
+    #[test]
+    fn it_selects_from_information_schema() {
+        assert_eq!(
+            answer(
+                "SELECT table_name, table_type FROM information_schema.tables \
+                 WHERE table_schema = 'news' ORDER BY table_name DESC"
+            ),
+            vec![
+                vec!["awvc".into(), "VIEW".into()],
+                vec!["article".into(), "BASE TABLE".into()]
+            ]
+        );
+        assert_eq!(
+            answer(
+                "SELECT COLUMN_NAME, DATA_TYPE, COLUMN_KEY FROM INFORMATION_SCHEMA.COLUMNS \
+                 WHERE TABLE_NAME = 'article' AND ORDINAL_POSITION > 1"
+            ),
+            vec![vec!["title".into(), "varchar".into(), "".into()]]
+        );
+    }
+}
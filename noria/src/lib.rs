@@ -200,13 +200,19 @@ pub(crate) const VIEW_POOL_SIZE: usize = 16;
 /// batch less work, which means lower overall efficiency.
 pub(crate) const PENDING_LIMIT: usize = 8192;
 
+use nom_sql::SqlType;
 use petgraph::graph::NodeIndex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use tokio_tower::multiplex;
 
 mod controller;
 mod data;
+mod errors;
+mod information_schema;
+mod retry;
 mod table;
+mod transaction;
+mod typed;
 mod view;
 
 #[doc(hidden)]
@@ -214,11 +220,15 @@ pub mod channel;
 #[doc(hidden)]
 #[allow(unreachable_pub)] // https://github.com/rust-lang/rust/issues/57411
 pub mod consensus;
+pub mod detached;
 #[doc(hidden)]
 pub mod doc_mock;
+pub mod fallback;
 #[doc(hidden)]
 #[allow(unreachable_pub)] // https://github.com/rust-lang/rust/issues/57411
 pub mod internal;
+pub mod replicate;
+pub mod sync;
 
 // for the row! macro
 #[doc(hidden)]
@@ -243,8 +253,14 @@ pub mod results {
     pub use super::view::results::{ResultRow, Results, Row};
 }
 
+/// Ways of putting together the rows of reads that span several shards of a view.
+pub mod merge {
+    pub use super::view::merge::{Combine, Merge};
+}
+
 /// Noria errors.
 pub mod error {
+    pub use crate::errors::NoriaError;
     pub use crate::table::TableError;
     pub use crate::view::ViewError;
 }
@@ -297,19 +313,22 @@ impl<T> From<T> for Tagged<T> {
 
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
 pub use crate::data::{DataType, Modification, Operation, TableOperation};
-pub use crate::table::Table;
-pub use crate::view::View;
+pub use crate::retry::RetryPolicy;
+pub use crate::table::{AckLevel, Table};
+pub use crate::transaction::Transaction;
+pub use crate::view::{ChangeCursor, RefreshPolicy, View, ViewChanges, MAX_RANGE_KEYS, ZONE_LABEL};
+pub use nom_sql::OrderType;
 
 #[doc(hidden)]
 pub use crate::table::Input;
 
 #[doc(hidden)]
-pub use crate::view::{ReadQuery, ReadReply, ReadReplyBatch};
+pub use crate::view::{cmp_rows, ReadQuery, ReadReply, ReadReplyBatch};
 
 #[doc(hidden)]
 pub mod builders {
-    pub use super::table::TableBuilder;
-    pub use super::view::ViewBuilder;
+    pub use super::table::{TableBuilder, ViewColumns};
+    pub use super::view::{ReaderReplica, ViewBuilder};
 }
 
 /// Types used when debugging Noria.
@@ -326,8 +345,207 @@ pub struct ActivationResult {
     pub expressions_added: usize,
     /// Number of expressions the recipe removed compared to the prior recipe.
     pub expressions_removed: usize,
+    /// Version of the recipe that the activation produced.
+    ///
+    /// This can later be passed to `ControllerHandle::rollback` to return to this recipe.
+    #[serde(default)]
+    pub version: usize,
+    /// Map of query names whose queries turned out to be identical to an existing view to the
+    /// name of that view.
+    ///
+    /// No new nodes are added for such queries, and all clients that installed them should read
+    /// from the existing view.
+    #[serde(default)]
+    pub duplicates: HashMap<String, String>,
+    /// Names of lazy views that the recipe added, which are only built when they are first read.
+    #[serde(default)]
+    pub deferred: Vec<String>,
+}
+
+/// Changes to the runtime parameters of running workers, made with
+/// `ControllerHandle::update_worker_config`.
+///
+/// Parameters that are `None` are left as they are. Workers that join later are also given the
+/// parameters that have been changed so far.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct WorkerConfigUpdate {
+    /// Memory, in bytes, available for partially materialized state. `Some(0)` lifts the limit.
+    pub memory_limit: Option<usize>,
+    /// How often state sizes are checked against the memory limit.
+    pub eviction_interval: Option<std::time::Duration>,
+    /// How long sends to other domains may be held back while there is input to process.
+    pub max_batch_delay: Option<std::time::Duration>,
+    /// The least severe level of log messages that workers emit, such as `"info"` or `"debug"`.
+    ///
+    /// Workers never log more than the logger they were started with allows.
+    pub log_level: Option<String>,
+}
+
+impl WorkerConfigUpdate {
+    /// Apply the changes in `other` on top of these.
+    pub fn merge(&mut self, other: &WorkerConfigUpdate) {
+        if other.memory_limit.is_some() {
+            self.memory_limit = other.memory_limit;
+        }
+        if other.eviction_interval.is_some() {
+            self.eviction_interval = other.eviction_interval;
+        }
+        if other.max_batch_delay.is_some() {
+            self.max_batch_delay = other.max_batch_delay;
+        }
+        if other.log_level.is_some() {
+            self.log_level = other.log_level.clone();
+        }
+    }
+}
+
+/// The outcome of comparing two views on a sample of keys using
+/// `ControllerHandle::compare_views`.
+#[derive(Clone, Debug, Default)]
+pub struct ViewComparison {
+    /// Number of keys that were compared.
+    pub keys: usize,
+    /// Number of keys for which both views returned the same set of rows.
+    pub matching: usize,
+    /// Keys for which the two views disagreed.
+    pub differing: Vec<Vec<DataType>>,
+    /// Total number of rows returned by the first view.
+    pub rows_a: usize,
+    /// Total number of rows returned by the second view.
+    pub rows_b: usize,
+    /// Number of rows in all of the first view. For partially materialized views, this only
+    /// counts the rows of keys that are currently materialized.
+    pub total_rows_a: usize,
+    /// Number of rows in all of the second view.
+    pub total_rows_b: usize,
 }
 
+impl ViewComparison {
+    /// How many more rows the second view holds than the first.
+    pub fn row_delta(&self) -> i64 {
+        self.total_rows_b as i64 - self.total_rows_a as i64
+    }
+}
+
+/// What a write to a base table would change, as reported by `ControllerHandle::dry_run`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DryRunResult {
+    /// The rows that the write would remove from the table.
+    pub removed: Vec<Vec<DataType>>,
+    /// The rows that the write would add to the table.
+    pub added: Vec<Vec<DataType>>,
+    /// The views downstream of the table whose results the write may change, by name.
+    ///
+    /// The changed rows are pushed through the filters and projections below the table, so views
+    /// that filter out every changed row are left out, and the others are given with the keys
+    /// whose results change. Below operators that need their state to tell what they emit, such
+    /// as joins and aggregations, a view is given with the keys that the changed rows would have,
+    /// or with `None` if that cannot be told from the changed rows alone, such as when its key
+    /// comes from another table.
+    pub views: BTreeMap<String, Option<Vec<Vec<DataType>>>>,
+}
+
+/// The base table rows that a row of a view was computed from, as reported by
+/// `ControllerHandle::provenance`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The rows of each base table that the row was computed from, by table name.
+    pub tables: BTreeMap<String, Vec<Vec<DataType>>>,
+    /// Whether `tables` holds exactly the rows that the row was computed from.
+    ///
+    /// This is `false` if some value in the row was computed rather than taken from a base table,
+    /// such as an aggregate or a literal, or if the view filters rows or leaves out columns of the
+    /// rows it is computed from. `tables` then holds every row that has the values that could be
+    /// traced back, such as all the rows of an aggregated group.
+    pub exact: bool,
+}
+
+/// The names of a node in the dataflow graph, as reported by `ControllerHandle::node_names`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeName {
+    /// The node's own name.
+    pub name: String,
+    /// The names of the queries whose results the node helps compute, including the other names
+    /// that the queries are known by, in order.
+    pub queries: Vec<String>,
+}
+
+/// A column of a base table or view, as described by `ControllerHandle::describe_table` and
+/// `ControllerHandle::describe_view`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnDescription {
+    /// The name of the column.
+    pub name: String,
+    /// The SQL type of the column, if it is known.
+    ///
+    /// The type of a view column is traced back to the base table column it comes from, and may
+    /// not be known for columns that are computed by the query.
+    pub sql_type: Option<SqlType>,
+}
+
+/// The schema of a base table, as returned by `ControllerHandle::describe_table`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableDescription {
+    /// The name of the table.
+    pub name: String,
+    /// The columns of the table, in the order rows are written in.
+    pub columns: Vec<ColumnDescription>,
+    /// The indices of the columns that deletes and updates identify rows by.
+    pub key: Vec<usize>,
+    /// Whether `key` is the table's primary key. If it is not, rows are identified by all their
+    /// columns, and `key` is only the column the table is sharded by, if any.
+    pub key_is_primary: bool,
+}
+
+/// The schema of a view, as returned by `ControllerHandle::describe_view`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewDescription {
+    /// The name of the view.
+    pub name: String,
+    /// The columns of the rows the view returns.
+    pub columns: Vec<ColumnDescription>,
+    /// The indices of the columns that the view's parameters are matched against, in the order
+    /// the parameters are given in lookups.
+    pub parameters: Vec<usize>,
+    /// The columns that the rows returned for each key are sorted by, as given by the `ORDER BY`
+    /// of the view's query.
+    #[serde(default)]
+    pub order: Vec<(usize, OrderType)>,
+}
+
+/// The outcome of checking a recipe with `ControllerHandle::validate_recipe`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecipeValidation {
+    /// Why the recipe could not be installed. The recipe is valid if this is empty.
+    pub errors: Vec<crate::errors::NoriaError>,
+    /// Things that would not stop the recipe from being installed, but may not work as intended.
+    pub warnings: Vec<String>,
+    /// The views the recipe would provide.
+    pub views: Vec<PlannedView>,
+}
+
+/// A view that a recipe checked with `ControllerHandle::validate_recipe` would provide.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlannedView {
+    /// The view's columns and parameters.
+    pub view: ViewDescription,
+    /// Whether the view is not in the current recipe, and so would have to be built.
+    pub new: bool,
+    /// Roughly how many rows the view would hold if it were fully materialized.
+    ///
+    /// This is the number of rows in the largest table the view reads from, which joins may
+    /// exceed and aggregations usually fall well short of. It is only known for new views, and
+    /// only once `ControllerHandle::state_statistics` has counted the rows of those tables.
+    pub estimated_rows: Option<usize>,
+}
+
+/// The tables and views of a deployment, each with the tables and views it reads from directly,
+/// as returned by `ControllerHandle::list_dependencies`.
+///
+/// Base tables read from nothing. Following the dependencies from any view leads to the tables it
+/// is computed from.
+pub type Dependencies = BTreeMap<String, BTreeSet<String>>;
+
 #[doc(hidden)]
 #[inline]
 pub fn shard_by(dt: &DataType, shards: usize) -> usize {
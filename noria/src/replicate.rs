@@ -0,0 +1,205 @@
+//! Replication of a view in one Noria deployment into a base table of another.
+//!
+//! A [`Replicator`] keeps a base table in a remote deployment up to date with the contents of a
+//! view, so that the remote deployment can serve reads from (and build further queries on top of)
+//! the view's results without reaching across datacenters for each read. The table acts as an
+//! external base: it is only written to by the replicator.
+//!
+//! The replicator reads the whole view once, and from then on follows the changes made to it (see
+//! [`View::changes`]), checking for new ones at a fixed interval. It only reads the view in full
+//! again if it has fallen so far behind that the changes it missed are no longer kept. Rows of
+//! keys that changed are deleted from the table and written to it again. The table must have a
+//! primary key, and the view's rows must be unique on that key and start with the same columns as
+//! the table; any columns past those, such as the hidden key of a view without parameters, are
+//! left out. The view must be fully materialized, since partially materialized views do not keep
+//! their changes.
+//!
+//! ```no_run
+//! # use noria::ControllerHandle;
+//! # use noria::replicate::Replicator;
+//! # async fn f() -> Result<(), failure::Error> {
+//! let mut here = ControllerHandle::from_zk("10.0.0.1:2181/noria").await?;
+//! let mut there = ControllerHandle::from_zk("10.1.0.1:2181/noria").await?;
+//! let view = here.view("ArticleWithVoteCount").await?;
+//! let table = there.table("ArticleWithVoteCount").await?;
+//! tokio::spawn(Replicator::new(view, table)?.run());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::data::{DataType, TableOperation};
+use crate::view::{ChangeCursor, ViewChanges};
+use crate::{Table, View};
+use futures_util::TryStreamExt;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Each shard of the view is read in chunks of the rows of at most this many keys, and its
+/// changes in chunks of at most this many rows.
+const READ_CHUNK: usize = 1024;
+
+/// The rows of a view, by the key of the table they are written to.
+type Rows = HashMap<Vec<DataType>, Vec<Vec<DataType>>>;
+
+/// Copies the contents of a view into a base table, possibly of a different deployment.
+pub struct Replicator {
+    source: View,
+    target: Table,
+    key: Vec<usize>,
+    interval: Duration,
+    /// How far the changes to each shard of the view have been followed.
+    cursors: Vec<Option<ChangeCursor>>,
+    /// The rows of each shard of the view, as of its cursor.
+    shards: Vec<Rows>,
+    /// The rows that the table was last brought up to date with.
+    last: Rows,
+}
+
+impl Replicator {
+    /// Make a replicator that copies `source` into `target`.
+    ///
+    /// Fails if `target` does not have a primary key. The replicator assumes that `target` starts
+    /// out empty, and checks for changes every second by default.
+    pub fn new(source: View, target: Table) -> Result<Self, failure::Error> {
+        let key = match target.primary_key() {
+            Some(key) => key.to_vec(),
+            None => failure::bail!(
+                "cannot replicate into {}, which has no primary key",
+                target.table_name()
+            ),
+        };
+        let shards = source.shards();
+        Ok(Replicator {
+            source,
+            target,
+            key,
+            interval: Duration::from_secs(1),
+            cursors: vec![None; shards],
+            shards: vec![Rows::new(); shards],
+            last: Rows::new(),
+        })
+    }
+
+    /// Set how often the view is checked for changes.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    fn key_of(&self, row: &[DataType]) -> Vec<DataType> {
+        self.key.iter().map(|&c| row[c].clone()).collect()
+    }
+
+    /// Catch up with the changes to one shard of the view, and add the keys whose rows changed
+    /// to `changed`.
+    async fn follow(
+        &mut self,
+        shardi: usize,
+        changed: &mut HashSet<Vec<DataType>>,
+    ) -> Result<(), failure::Error> {
+        loop {
+            let since = self.cursors[shardi];
+            match self.source.changes(shardi, since, READ_CHUNK).await? {
+                ViewChanges::Since { rows, next } => {
+                    let caught_up = rows.len() < READ_CHUNK;
+                    for (row, added) in rows {
+                        let key = self.key_of(&row);
+                        let rows = self.shards[shardi].entry(key.clone()).or_default();
+                        // changes made while the shard was last read in full may already be there
+                        match rows.iter().position(|r| *r == row) {
+                            Some(i) if !added => {
+                                rows.swap_remove(i);
+                            }
+                            None if added => rows.push(row),
+                            _ => continue,
+                        }
+                        if rows.is_empty() {
+                            self.shards[shardi].remove(&key);
+                        }
+                        changed.insert(key);
+                    }
+                    self.cursors[shardi] = Some(next);
+                    if caught_up {
+                        return Ok(());
+                    }
+                }
+                ViewChanges::Lost { next } => {
+                    let chunks: Vec<_> = self
+                        .source
+                        .iter_shard(shardi, READ_CHUNK)
+                        .try_collect()
+                        .await?;
+                    let mut rows = Rows::new();
+                    let all = chunks
+                        .into_iter()
+                        .flat_map(|rs| -> Vec<Vec<DataType>> { rs.into() });
+                    for row in all {
+                        rows.entry(self.key_of(&row)).or_default().push(row);
+                    }
+                    let old = std::mem::replace(&mut self.shards[shardi], rows);
+                    changed.extend(old.into_iter().map(|(key, _)| key));
+                    changed.extend(self.shards[shardi].keys().cloned());
+                    self.cursors[shardi] = Some(next);
+                }
+            }
+        }
+    }
+
+    /// Bring the table up to date with the view once, and return how many rows were written.
+    pub async fn sync_once(&mut self) -> Result<usize, failure::Error> {
+        let mut changed = HashSet::new();
+        for shardi in 0..self.shards.len() {
+            self.follow(shardi, &mut changed).await?;
+        }
+
+        // rows that changed are deleted and then inserted again, so all the deletes go first.
+        let ncols = self.target.columns().len();
+        let mut deletes = Vec::new();
+        let mut inserts = Vec::new();
+        for key in changed {
+            let mut now: Vec<_> = self
+                .source
+                .merge()
+                .apply(
+                    self.shards
+                        .iter()
+                        .map(|rows| rows.get(&key).cloned().unwrap_or_default())
+                        .collect(),
+                )
+                .into_iter()
+                .map(|mut row| {
+                    row.truncate(ncols);
+                    row
+                })
+                .collect();
+            now.sort();
+
+            let last = self.last.remove(&key).unwrap_or_default();
+            if now != last {
+                if !last.is_empty() {
+                    deletes.push(TableOperation::Delete { key: key.clone() });
+                }
+                inserts.extend(now.iter().cloned().map(TableOperation::Insert));
+            }
+            if !now.is_empty() {
+                self.last.insert(key, now);
+            }
+        }
+        let written = deletes.len() + inserts.len();
+
+        if !deletes.is_empty() {
+            self.target.perform_all(deletes).await?;
+        }
+        if !inserts.is_empty() {
+            self.target.perform_all(inserts).await?;
+        }
+        Ok(written)
+    }
+
+    /// Keep the table up to date with the view until reading the view or writing the table fails.
+    pub async fn run(mut self) -> Result<(), failure::Error> {
+        loop {
+            self.sync_once().await?;
+            tokio::time::delay_for(self.interval).await;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How `View` and `Table` handles retry requests that fail because the connection to a worker
+/// broke, for example because the worker restarted.
+///
+/// Before each retry, the handle waits for an exponentially increasing, jittered backoff, and then
+/// reconnects to the worker. Views whose reader is replicated reconnect to the next replica
+/// instead. Errors that are not caused by the connection, such as a view that no longer exists,
+/// are never retried.
+///
+/// Note that a write that is retried may be applied twice if the first attempt reached the worker
+/// before the connection broke. `View`s therefore retry with `RetryPolicy::default()` unless told
+/// otherwise, whereas `Table`s never retry unless a policy is set with
+/// `ControllerHandle::set_write_retry_policy` or `Table::set_retry_policy`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of times a request is retried before its error is returned.
+    pub max_retries: usize,
+    /// The backoff before the first retry.
+    pub initial_backoff: Duration,
+    /// The longest backoff between two retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries, and returns the first error.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The time to wait before retry number `attempt` (counting from 0).
+    ///
+    /// The backoff doubles with each attempt, up to `max_backoff`, and is then scaled by a random
+    /// factor between one half and one so that clients that fail together don't retry together.
+    pub(crate) fn backoff(&self, attempt: usize) -> Duration {
+        let mut backoff = self.initial_backoff;
+        for _ in 0..attempt {
+            if backoff >= self.max_backoff {
+                break;
+            }
+            backoff *= 2;
+        }
+        let backoff = std::cmp::min(backoff, self.max_backoff);
+
+        // a freshly keyed hasher is a cheap source of randomness
+        let r = RandomState::new().build_hasher().finish();
+        backoff.mul_f64(0.5 + (r as f64 / std::u64::MAX as f64) / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_only_changes_retries() {
+        let p = RetryPolicy::never();
+        assert_eq!(p.max_retries, 0);
+        assert_eq!(p.initial_backoff, RetryPolicy::default().initial_backoff);
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let p = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        for attempt in 0..10 {
+            let expected = std::cmp::min(
+                Duration::from_millis(100) * 2u32.pow(attempt as u32),
+                Duration::from_secs(1),
+            );
+            let b = p.backoff(attempt);
+            assert!(b <= expected, "{:?} > {:?}", b, expected);
+            assert!(b >= expected / 2, "{:?} < {:?}", b, expected / 2);
+        }
+    }
+}
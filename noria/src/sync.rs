@@ -0,0 +1,406 @@
+//! Blocking wrappers around the Noria client handles.
+//!
+//! These are for programs that do not otherwise use async Rust, such as scripts and command-line
+//! tools. A [`SyncControllerHandle`] owns a Tokio runtime that drives all connections to Noria,
+//! and the [`SyncView`]s and [`SyncTable`]s obtained from it share that runtime. Each method
+//! blocks the calling thread until the corresponding asynchronous operation completes.
+//!
+//! None of these methods may be called from within an asynchronous context, since they would then
+//! block the executor that is supposed to drive them. Use [`ControllerHandle`] and friends there
+//! instead.
+//!
+//! ```no_run
+//! # use noria::sync::SyncControllerHandle;
+//! let mut db = SyncControllerHandle::from_zk("127.0.0.1:2181/noria").unwrap();
+//! db.extend_recipe("CREATE TABLE Article (aid int, title varchar(255), PRIMARY KEY(aid));")
+//!     .unwrap();
+//! let mut article = db.table("Article").unwrap();
+//! article.insert(vec![1.into(), "I love Soup".into()]).unwrap();
+//! ```
+
+use crate::consensus::{Authority, ZookeeperAuthority};
+use crate::data::{DataType, Modification, TableOperation};
+use crate::error::{TableError, ViewError};
+use crate::results::{Results, Row};
+use crate::{ActivationResult, ControllerHandle, RetryPolicy, Table, View};
+use futures_util::stream::StreamExt;
+use petgraph::graph::NodeIndex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+fn block_on<F: Future>(rt: &Runtime, f: F) -> F::Output {
+    // the runtime's own threads drive the connections, so any number of threads can wait here at
+    // the same time, and a panic in one of them does not affect the others.
+    rt.handle().enter(|| futures_executor::block_on(f))
+}
+
+/// A blocking version of [`ControllerHandle`].
+///
+/// Cloning a `SyncControllerHandle` yields another handle that uses the same runtime.
+pub struct SyncControllerHandle<A>
+where
+    A: 'static + Authority,
+{
+    rt: Arc<Runtime>,
+    handle: ControllerHandle<A>,
+}
+
+impl<A> Clone for SyncControllerHandle<A>
+where
+    A: 'static + Authority,
+{
+    fn clone(&self) -> Self {
+        SyncControllerHandle {
+            rt: self.rt.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl SyncControllerHandle<ZookeeperAuthority> {
+    /// Fetch information about the current Soup controller from Zookeeper running at the given
+    /// address, and create a `SyncControllerHandle` from that.
+    pub fn from_zk(zookeeper_address: &str) -> Result<Self, failure::Error> {
+        let auth = ZookeeperAuthority::new(zookeeper_address)?;
+        SyncControllerHandle::new(auth)
+    }
+}
+
+impl<A: Authority + 'static> SyncControllerHandle<A> {
+    #[doc(hidden)]
+    pub fn make(authority: Arc<A>) -> Result<Self, failure::Error> {
+        let rt = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .thread_name("noria-sync")
+            .build()?;
+        let rt = Arc::new(rt);
+        let handle = block_on(&rt, ControllerHandle::make(authority))?;
+        Ok(SyncControllerHandle { rt, handle })
+    }
+
+    /// Create a `SyncControllerHandle` that bootstraps a connection to Noria via the
+    /// configuration stored in the given `authority`.
+    ///
+    /// This starts a new runtime to drive the connection.
+    pub fn new(authority: A) -> Result<Self, failure::Error>
+    where
+        A: Send + 'static,
+    {
+        Self::make(Arc::new(authority))
+    }
+
+    /// Set how long each read or write may take before it gives up.
+    ///
+    /// See [`ControllerHandle::set_timeout`].
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.handle.set_timeout(timeout);
+    }
+
+    /// Set how reads that fail because of a broken connection are retried.
+    ///
+    /// See [`ControllerHandle::set_retry_policy`].
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.handle.set_retry_policy(retry);
+    }
+
+    /// Set how writes that fail because of a broken connection are retried.
+    ///
+    /// See [`ControllerHandle::set_write_retry_policy`].
+    pub fn set_write_retry_policy(&mut self, retry: RetryPolicy) {
+        self.handle.set_write_retry_policy(retry);
+    }
+
+    /// Set the zone that this client is in.
+    ///
+    /// See [`ControllerHandle::set_zone`].
+    pub fn set_zone(&mut self, zone: Option<&str>) {
+        self.handle.set_zone(zone);
+    }
+
+    /// Enumerate all known base tables.
+    pub fn inputs(&mut self) -> Result<BTreeMap<String, NodeIndex>, failure::Error> {
+        let handle = &mut self.handle;
+        block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.inputs().await
+        })
+    }
+
+    /// Enumerate all known external views.
+    pub fn outputs(&mut self) -> Result<BTreeMap<String, NodeIndex>, failure::Error> {
+        let handle = &mut self.handle;
+        block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.outputs().await
+        })
+    }
+
+    /// Obtain a `SyncView` that allows you to query the given external view.
+    pub fn view(&mut self, name: &str) -> Result<SyncView, failure::Error> {
+        let handle = &mut self.handle;
+        let view = block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.view(name).await
+        })?;
+        Ok(SyncView {
+            rt: self.rt.clone(),
+            view,
+        })
+    }
+
+    /// Obtain a `SyncTable` that allows you to perform writes, deletes, and other operations on
+    /// the given base table.
+    pub fn table(&mut self, name: &str) -> Result<SyncTable, failure::Error> {
+        let handle = &mut self.handle;
+        let table = block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.table(name).await
+        })?;
+        Ok(SyncTable {
+            rt: self.rt.clone(),
+            table,
+        })
+    }
+
+    /// Extend the existing recipe with the given set of queries.
+    pub fn extend_recipe(
+        &mut self,
+        recipe_addition: &str,
+    ) -> Result<ActivationResult, failure::Error> {
+        let handle = &mut self.handle;
+        block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.extend_recipe(recipe_addition).await
+        })
+    }
+
+    /// Replace the existing recipe with this one.
+    pub fn install_recipe(&mut self, new_recipe: &str) -> Result<ActivationResult, failure::Error> {
+        let handle = &mut self.handle;
+        block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.install_recipe(new_recipe).await
+        })
+    }
+
+    /// Remove the named query from the recipe.
+    ///
+    /// See [`ControllerHandle::remove_query`].
+    pub fn remove_query(&mut self, name: &str) -> Result<(), failure::Error> {
+        let handle = &mut self.handle;
+        block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.remove_query(name).await
+        })
+    }
+
+    /// Fetch a graphviz description of the dataflow graph.
+    pub fn graphviz(&mut self) -> Result<String, failure::Error> {
+        let handle = &mut self.handle;
+        block_on(&self.rt, async move {
+            handle.ready().await?;
+            handle.graphviz().await
+        })
+    }
+
+    /// Get the underlying asynchronous handle.
+    ///
+    /// Futures that use it must be run with [`SyncControllerHandle::run`].
+    pub fn handle(&mut self) -> &mut ControllerHandle<A> {
+        &mut self.handle
+    }
+
+    /// Run the given future to completion on this handle's runtime.
+    ///
+    /// This gives access to operations that do not have a blocking wrapper.
+    pub fn run<F: Future>(&self, f: F) -> F::Output {
+        block_on(&self.rt, f)
+    }
+}
+
+/// A blocking version of [`View`].
+#[derive(Clone)]
+pub struct SyncView {
+    rt: Arc<Runtime>,
+    view: View,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl SyncView {
+    /// Get the list of columns in this view.
+    pub fn columns(&self) -> &[String] {
+        self.view.columns()
+    }
+
+    /// Set how long each lookup may take before it gives up.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.view.set_timeout(timeout);
+    }
+
+    /// Get the current size of this view.
+    pub fn len(&mut self) -> Result<usize, ViewError> {
+        block_on(&self.rt, self.view.len())
+    }
+
+    /// Get the number of rows for the given parameter value.
+    ///
+    /// See [`View::count`].
+    pub fn count(&mut self, key: &[DataType]) -> Result<usize, ViewError> {
+        block_on(&self.rt, self.view.count(key))
+    }
+
+    /// Check whether there are any rows for the given parameter value.
+    pub fn contains(&mut self, key: &[DataType]) -> Result<bool, ViewError> {
+        block_on(&self.rt, self.view.contains(key))
+    }
+
+    /// Retrieve the query results for the given parameter values.
+    ///
+    /// See [`View::multi_lookup`].
+    pub fn multi_lookup(
+        &mut self,
+        keys: Vec<Vec<DataType>>,
+        block: bool,
+    ) -> Result<Vec<Results>, ViewError> {
+        block_on(&self.rt, self.view.multi_lookup(keys, block))
+    }
+
+    /// Retrieve the query results for the given parameter value.
+    ///
+    /// See [`View::lookup`].
+    pub fn lookup(&mut self, key: &[DataType], block: bool) -> Result<Results, ViewError> {
+        block_on(&self.rt, self.view.lookup(key, block))
+    }
+
+    /// Retrieve the query results for every integer key in the given range.
+    ///
+    /// See [`View::lookup_range`].
+    pub fn lookup_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        block_on(&self.rt, self.view.lookup_range(range, block))
+    }
+
+    /// Read every row in this view, in chunks of the rows of at most `chunk_size` keys.
+    ///
+    /// See [`View::iter`].
+    pub fn iter(
+        &mut self,
+        chunk_size: usize,
+    ) -> impl Iterator<Item = Result<Results, ViewError>> + '_ {
+        let rt = &self.rt;
+        let mut chunks = Box::pin(self.view.iter(chunk_size));
+        std::iter::from_fn(move || block_on(rt, chunks.next()))
+    }
+
+    /// Retrieve the first query result for the given parameter value.
+    ///
+    /// See [`View::lookup_first`].
+    pub fn lookup_first(
+        &mut self,
+        key: &[DataType],
+        block: bool,
+    ) -> Result<Option<Row>, ViewError> {
+        block_on(&self.rt, self.view.lookup_first(key, block))
+    }
+
+    /// Retrieve the query results for the given parameter value, with each row mapped to a `T`.
+    ///
+    /// See [`View::lookup_as`].
+    pub fn lookup_as<T: DeserializeOwned>(
+        &mut self,
+        key: &[DataType],
+        block: bool,
+    ) -> Result<Vec<T>, ViewError> {
+        block_on(&self.rt, self.view.lookup_as(key, block))
+    }
+}
+
+/// A blocking version of [`Table`].
+#[derive(Clone)]
+pub struct SyncTable {
+    rt: Arc<Runtime>,
+    table: Table,
+}
+
+impl SyncTable {
+    /// Get the name of this base table.
+    pub fn table_name(&self) -> &str {
+        self.table.table_name()
+    }
+
+    /// Get the list of columns in this base table.
+    pub fn columns(&self) -> &[String] {
+        self.table.columns()
+    }
+
+    /// Set how long each write may take before it gives up.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.table.set_timeout(timeout);
+    }
+
+    /// Insert a single row of data into this base table.
+    pub fn insert<V>(&mut self, u: V) -> Result<(), TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        block_on(&self.rt, self.table.insert(u))
+    }
+
+    /// Insert a single row into this base table, taking its values from the fields of `row`.
+    ///
+    /// See [`Table::insert_row`].
+    pub fn insert_row<T: Serialize>(&mut self, row: &T) -> Result<(), TableError> {
+        block_on(&self.rt, self.table.insert_row(row))
+    }
+
+    /// Perform multiple operation on this base table.
+    pub fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        block_on(&self.rt, self.table.perform_all(i))
+    }
+
+    /// Delete the row with the given key from this base table.
+    pub fn delete<I>(&mut self, key: I) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+    {
+        block_on(&self.rt, self.table.delete(key))
+    }
+
+    /// Update the row with the given key in this base table.
+    ///
+    /// See [`Table::update`].
+    pub fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        block_on(&self.rt, self.table.update(key, u))
+    }
+
+    /// Perform a insert-or-update on this base table.
+    ///
+    /// See [`Table::insert_or_update`].
+    pub fn insert_or_update<V>(
+        &mut self,
+        insert: Vec<DataType>,
+        update: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        block_on(&self.rt, self.table.insert_or_update(insert, update))
+    }
+}
@@ -2,6 +2,7 @@ use crate::channel::CONNECTION_FROM_BASE;
 use crate::data::*;
 use crate::internal::*;
 use crate::LocalOrNot;
+use crate::RetryPolicy;
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
@@ -10,11 +11,14 @@ use futures_util::{
 };
 use nom_sql::CreateTableStatement;
 use petgraph::graph::NodeIndex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use std::{fmt, io};
 use tokio::io::AsyncWriteExt;
 use tokio_tower::multiplex;
@@ -289,6 +293,21 @@ pub enum TableError {
     )]
     WrongKeyColumnCount(usize, usize),
 
+    /// The request did not complete within the timeout set for the table.
+    #[fail(display = "the request timed out")]
+    Timeout,
+
+    /// A transaction was started on a sharded table, whose shards cannot apply it atomically.
+    #[fail(
+        display = "transactions are not supported on a table with {} shards",
+        _0
+    )]
+    ShardedTransaction(usize),
+
+    /// A value could not be mapped to a row of the table.
+    #[fail(display = "value cannot be mapped to a row: {}", _0)]
+    Mapping(String),
+
     /// The underlying connection to Noria produced an error.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -300,11 +319,42 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
     }
 }
 
+/// How far a write through a [`Table`] must have come before it is acknowledged.
+///
+/// Stronger levels make writes take longer, but make it less likely that an acknowledged write is
+/// lost or not yet visible.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AckLevel {
+    /// Return as soon as the write has been handed to the connection to Noria.
+    ///
+    /// The write is sent in the background, and if it fails, nobody finds out. Writes sent this
+    /// way are still applied in the order they were made.
+    Sent,
+    /// Return once the write has been applied and synced to the base table's persistent log.
+    ///
+    /// The write is synced even if the server is configured to sync writes less often. For tables
+    /// that are not persisted, this is the same as `Applied`.
+    Logged,
+    /// Return once the base table has applied the write.
+    ///
+    /// Applied writes are visible to reads of the base table's domain, but may not have reached
+    /// every view yet. Whether they have also been synced to disk depends on how the server is
+    /// configured to sync writes.
+    Applied,
+}
+
+impl Default for AckLevel {
+    fn default() -> Self {
+        AckLevel::Applied
+    }
+}
+
 #[doc(hidden)]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
+    pub ack: AckLevel,
 }
 
 impl fmt::Debug for Input {
@@ -312,6 +362,7 @@ impl fmt::Debug for Input {
         fmt.debug_struct("Input")
             .field("dst", &self.dst)
             .field("data", &self.data)
+            .field("ack", &self.ack)
             .finish()
     }
 }
@@ -324,11 +375,65 @@ pub struct TableBuilder {
     pub addr: LocalNodeIndex,
     pub key_is_primary: bool,
     pub key: Vec<usize>,
+    /// The column the base is sharded by, if it is sharded.
+    pub shard_by: Option<usize>,
     pub dropped: VecMap<DataType>,
+    /// The columns that the base fills in itself, such as row timestamps.
+    pub generated: Vec<usize>,
 
     pub table_name: String,
     pub columns: Vec<String>,
     pub schema: Option<CreateTableStatement>,
+    /// If the handle is for a view that projects this base table, how to map writes to the view
+    /// onto the table.
+    #[serde(default)]
+    pub view: Option<ViewColumns>,
+}
+
+/// The columns of a writable view, which projects the columns of a single base table.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewColumns {
+    /// The name of the view.
+    pub name: String,
+    /// The names of the view's columns.
+    pub columns: Vec<String>,
+    /// For each of the view's columns, the base table column it holds, or `None` if the view
+    /// computes it.
+    pub base_columns: Vec<Option<usize>>,
+}
+
+fn table_rpc(
+    rpcs: &Mutex<HashMap<(SocketAddr, usize), TableRpc>>,
+    addr: SocketAddr,
+    shardi: usize,
+) -> TableRpc {
+    use std::collections::hash_map::Entry;
+
+    // one entry per shard so that we can send sharded requests in parallel even if
+    // they happen to be targeting the same machine.
+    let mut rpcs = rpcs.lock().unwrap();
+    match rpcs.entry((addr, shardi)) {
+        Entry::Occupied(e) => e.get().clone(),
+        Entry::Vacant(h) => {
+            // TODO: maybe always use the same local port?
+            let (c, w) = Buffer::pair(
+                ConcurrencyLimit::new(
+                    Balance::from_entropy(make_table_discover(addr)),
+                    crate::PENDING_LIMIT,
+                ),
+                crate::BUFFER_TO_POOL,
+            );
+            use tracing_futures::Instrument;
+            tokio::spawn(w.instrument(tracing::debug_span!(
+                "table_worker",
+                addr = %addr,
+                shard = shardi
+            )));
+            h.insert(c.clone());
+            c
+        }
+    }
 }
 
 impl TableBuilder {
@@ -339,51 +444,33 @@ impl TableBuilder {
         let mut addrs = Vec::with_capacity(self.txs.len());
         let mut conns = Vec::with_capacity(self.txs.len());
         for (shardi, &addr) in self.txs.iter().enumerate() {
-            use std::collections::hash_map::Entry;
-
             addrs.push(addr);
-
-            // one entry per shard so that we can send sharded requests in parallel even if
-            // they happen to be targeting the same machine.
-            let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
-                Entry::Occupied(e) => e.get().clone(),
-                Entry::Vacant(h) => {
-                    // TODO: maybe always use the same local port?
-                    let (c, w) = Buffer::pair(
-                        ConcurrencyLimit::new(
-                            Balance::from_entropy(make_table_discover(addr)),
-                            crate::PENDING_LIMIT,
-                        ),
-                        crate::BUFFER_TO_POOL,
-                    );
-                    use tracing_futures::Instrument;
-                    tokio::spawn(w.instrument(tracing::debug_span!(
-                        "table_worker",
-                        addr = %addr,
-                        shard = shardi
-                    )));
-                    h.insert(c.clone());
-                    c
-                }
-            };
-            conns.push(s);
+            conns.push(table_rpc(&rpcs, addr, shardi));
         }
 
         let dispatch = tracing::dispatcher::get_default(|d| d.clone());
         Ok(Table {
             ni: self.ni,
             node: self.addr,
+            shard_by: self
+                .shard_by
+                .map(|col| (col, self.key.iter().position(|&k| k == col))),
             key: self.key,
             key_is_primary: self.key_is_primary,
             columns: self.columns,
             dropped: self.dropped,
+            generated: self.generated,
             table_name: self.table_name,
             schema: self.schema,
+            view: self.view,
             dst_is_local: false,
 
             shard_addrs: addrs,
             shards: conns,
+            rpcs,
+            timeout: None,
+            retry: RetryPolicy::never(),
+            ack: AckLevel::default(),
 
             dispatch,
         })
@@ -402,14 +489,24 @@ pub struct Table {
     node: LocalNodeIndex,
     key_is_primary: bool,
     key: Vec<usize>,
+    /// The column the base is sharded by, and where that column is in `key`, if it is there.
+    shard_by: Option<(usize, Option<usize>)>,
     columns: Vec<String>,
     dropped: VecMap<DataType>,
+    generated: Vec<usize>,
     table_name: String,
     schema: Option<CreateTableStatement>,
+    view: Option<ViewColumns>,
     dst_is_local: bool,
 
     shards: Vec<TableRpc>,
     shard_addrs: Vec<SocketAddr>,
+    /// The connection pool shared with the `ControllerHandle`, used to reconnect.
+    rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
+    /// How long each write may take before it fails with `TableError::Timeout`.
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    ack: AckLevel,
 
     dispatch: tracing::Dispatch,
 }
@@ -421,10 +518,13 @@ impl fmt::Debug for Table {
             .field("node", &self.node)
             .field("key_is_primary", &self.key_is_primary)
             .field("key", &self.key)
+            .field("shard_by", &self.shard_by)
             .field("columns", &self.columns)
             .field("dropped", &self.dropped)
+            .field("generated", &self.generated)
             .field("table_name", &self.table_name)
             .field("schema", &self.schema)
+            .field("view", &self.view)
             .field("dst_is_local", &self.dst_is_local)
             .field("shard_addrs", &self.shard_addrs)
             .finish()
@@ -432,6 +532,64 @@ impl fmt::Debug for Table {
 }
 
 impl Table {
+    /// Check that `ops`, as they are about to be sent to the base table, have the right number of
+    /// columns.
+    fn check_ops(&self, ops: &[TableOperation]) -> Result<(), TableError> {
+        let ncols = self.columns.len() + self.dropped.len();
+        for op in ops {
+            match op {
+                TableOperation::Insert(ref row) => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                }
+                TableOperation::Delete { ref key } | TableOperation::Purge { ref key } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                }
+                TableOperation::InsertOrUpdate {
+                    ref row,
+                    ref update,
+                } => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                    if update.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(
+                            self.columns.len(),
+                            update.len(),
+                        ));
+                    }
+                }
+                TableOperation::Update { ref set, ref key }
+                | TableOperation::UpdateIf {
+                    ref set, ref key, ..
+                } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                    if set.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(self.columns.len(), set.len()));
+                    }
+                }
+                TableOperation::Replicate { ref row, ref key } => match row {
+                    Some(row) if row.len() != ncols => {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                    Some(_) => {}
+                    None if key.len() != self.key.len() => {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                    None => {}
+                },
+            }
+        }
+        Ok(())
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn input(
         &mut self,
@@ -446,54 +604,7 @@ impl Table {
             None
         };
 
-        // NOTE: this is really just a try block
-        let immediate_err = || {
-            let ncols = self.columns.len() + self.dropped.len();
-            for op in &i.data {
-                match op {
-                    TableOperation::Insert(ref row) => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                    }
-                    TableOperation::Delete { ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                    }
-                    TableOperation::InsertOrUpdate {
-                        ref row,
-                        ref update,
-                    } => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                        if update.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                update.len(),
-                            ));
-                        }
-                    }
-                    TableOperation::Update { ref set, ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                        if set.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                set.len(),
-                            ));
-                        }
-                    }
-                }
-            }
-            Ok(())
-        };
-
-        if let Err(e) = immediate_err() {
+        if let Err(e) = self.check_ops(&i.data) {
             return future::Either::Left(async move { Err(e) });
         }
 
@@ -510,30 +621,14 @@ impl Table {
                 self.shards[0].call(request).map_err(TableError::from),
             ))
         } else {
-            if self.key.is_empty() {
-                unreachable!("sharded base without a key?");
-            }
-            if self.key.len() != 1 {
-                // base sharded by complex key
-                unimplemented!();
-            }
-            let key_col = self.key[0];
+            // we pick the shard of each write here, and send it straight to the worker that
+            // owns that shard, so that writes never have to be re-sharded on the way in.
+            let (shard_col, key_i) = self.shard_by.expect("sharded base without a shard column");
 
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("shard request");
-            let mut shard_writes = vec![Vec::new(); self.shards.len()];
-            for r in i.data.drain(..) {
-                let shard = {
-                    let key = match r {
-                        TableOperation::Insert(ref r) => &r[key_col],
-                        TableOperation::Delete { ref key } => &key[0],
-                        TableOperation::Update { ref key, .. } => &key[0],
-                        TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
-                    };
-                    crate::shard_by(key, self.shards.len())
-                };
-                shard_writes[shard].push(r);
-            }
+            let mut shard_writes =
+                shard_writes(shard_col, key_i, self.shards.len(), i.data.drain(..));
 
             let wait_for = FuturesUnordered::new();
             for (s, rs) in shard_writes.drain(..).enumerate() {
@@ -543,12 +638,14 @@ impl Table {
                             LocalOrNot::for_local_transfer(Input {
                                 dst: i.dst,
                                 data: rs,
+                                ack: i.ack,
                             })
                         }
                     } else {
                         LocalOrNot::new(Input {
                             dst: i.dst,
                             data: rs,
+                            ack: i.ack,
                         })
                     };
                     let request = Tagged::from(p);
@@ -581,6 +678,47 @@ impl Table {
     }
 }
 
+/// Split `ops` into the writes for each of `nshards` shards of a base sharded by `shard_col`.
+///
+/// `key_i` is where `shard_col` is in the base's key, if it is there. Operations that only carry
+/// a key that does not include the shard column go to every shard.
+fn shard_writes(
+    shard_col: usize,
+    key_i: Option<usize>,
+    nshards: usize,
+    ops: impl IntoIterator<Item = TableOperation>,
+) -> Vec<Vec<TableOperation>> {
+    let mut shard_writes = vec![Vec::new(); nshards];
+    for r in ops {
+        let key = match (&r, key_i) {
+            (TableOperation::Insert(row), _) => &row[shard_col],
+            (TableOperation::InsertOrUpdate { row, .. }, _) => &row[shard_col],
+            (TableOperation::Delete { key }, Some(ki)) => &key[ki],
+            (TableOperation::Purge { key }, Some(ki)) => &key[ki],
+            (TableOperation::Update { key, .. }, Some(ki)) => &key[ki],
+            (TableOperation::UpdateIf { key, .. }, Some(ki)) => &key[ki],
+            (TableOperation::Replicate { row: Some(row), .. }, _) => &row[shard_col],
+            (TableOperation::Replicate { key, row: None }, Some(ki)) => &key[ki],
+            (TableOperation::Delete { .. }, None)
+            | (TableOperation::Purge { .. }, None)
+            | (TableOperation::Update { .. }, None)
+            | (TableOperation::UpdateIf { .. }, None)
+            | (TableOperation::Replicate { .. }, None) => {
+                // the key doesn't tell us which shard has the row, so we ask them all.
+                // the shards that do not have the row will ignore the operation.
+                for w in &mut shard_writes[..nshards - 1] {
+                    w.push(r.clone());
+                }
+                shard_writes[nshards - 1].push(r);
+                continue;
+            }
+        };
+        let shard = crate::shard_by(key, nshards);
+        shard_writes[shard].push(r);
+    }
+    shard_writes
+}
+
 impl Service<Vec<TableOperation>> for Table {
     type Error = TableError;
     type Response = <TableRpc as Service<Tagged<LocalOrNot<Input>>>>::Response;
@@ -598,17 +736,80 @@ impl Service<Vec<TableOperation>> for Table {
     }
 
     fn call(&mut self, ops: Vec<TableOperation>) -> Self::Future {
-        let i = self.prep_records(ops);
-        self.input(i)
+        match self.to_base(ops) {
+            Ok(ops) => {
+                let i = self.prep_records(ops, self.ack);
+                future::Either::Left(self.input(i))
+            }
+            Err(e) => future::Either::Right(async move { Err(e) }),
+        }
     }
 }
 
 impl Table {
     /// Get the name of this base table.
+    ///
+    /// For handles on writable views, this is the name of the base table that the view projects.
     pub fn table_name(&self) -> &str {
         &self.table_name
     }
 
+    /// Get the name of the view that this handle writes through, if it is not for a base table.
+    pub fn view_name(&self) -> Option<&str> {
+        self.view.as_ref().map(|v| &*v.name)
+    }
+
+    /// Set how long each write through this `Table` may take before it gives up.
+    ///
+    /// A write that times out fails with `TableError::Timeout`, but may still be applied. Writes
+    /// do not time out by default.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Get the timeout set for writes through this `Table`, if any.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Set how writes that fail because of a broken connection are retried.
+    ///
+    /// Writes are not retried by default. See [`RetryPolicy`] for when retrying may cause a write
+    /// to be applied twice.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Get the policy for retrying writes through this `Table`.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Set how far writes through this `Table` must have come before they are acknowledged.
+    ///
+    /// Writes wait until the base table has applied them by default. See [`AckLevel`] for the
+    /// other options, and [`Table::perform_all_with_ack`] to choose for a single write.
+    pub fn set_ack_level(&mut self, ack: AckLevel) {
+        self.ack = ack;
+    }
+
+    /// Get how far writes through this `Table` must have come before they are acknowledged.
+    pub fn ack_level(&self) -> AckLevel {
+        self.ack
+    }
+
+    /// Replace the connection to each shard with a fresh one.
+    fn reconnect(&mut self) {
+        let mut rpcs = self.rpcs.lock().unwrap();
+        for (shardi, &addr) in self.shard_addrs.iter().enumerate() {
+            rpcs.remove(&(addr, shardi));
+        }
+        drop(rpcs);
+        for (shardi, &addr) in self.shard_addrs.iter().enumerate() {
+            self.shards[shardi] = table_rpc(&self.rpcs, addr, shardi);
+        }
+    }
+
     #[doc(hidden)]
     pub fn i_promise_dst_is_same_process(&mut self) {
         self.dst_is_local = true;
@@ -619,7 +820,19 @@ impl Table {
     /// Note that this will *not* be updated if the underlying recipe changes and adds or removes
     /// columns!
     pub fn columns(&self) -> &[String] {
-        &self.columns
+        match self.view {
+            Some(ref view) => &view.columns,
+            None => &self.columns,
+        }
+    }
+
+    /// Get the indices of the columns of this base table's primary key, if it has one.
+    pub(crate) fn primary_key(&self) -> Option<&[usize]> {
+        if self.key_is_primary && !self.key.is_empty() {
+            Some(&self.key)
+        } else {
+            None
+        }
     }
 
     /// Get the schema that was used to create this base table.
@@ -630,6 +843,95 @@ impl Table {
         self.schema.as_ref()
     }
 
+    /// Turn operations on the columns of a writable view into operations on the columns of its
+    /// base table.
+    ///
+    /// Rows are given in the view's columns, and base columns that the view does not include are
+    /// set to NULL. Columns that the view computes cannot be written to, and their values in
+    /// inserted rows are ignored. Keys are those of the base table.
+    fn to_base(&self, ops: Vec<TableOperation>) -> Result<Vec<TableOperation>, TableError> {
+        let view = match self.view {
+            Some(ref view) => view,
+            None => return Ok(ops),
+        };
+        let row = |row: Vec<DataType>| {
+            if row.len() != view.columns.len() {
+                return Err(TableError::WrongColumnCount(view.columns.len(), row.len()));
+            }
+            let mut base = vec![DataType::None; self.columns.len()];
+            for (v, bc) in row.into_iter().zip(&view.base_columns) {
+                if let Some(bc) = *bc {
+                    base[bc] = v;
+                }
+            }
+            Ok(base)
+        };
+        let set = |set: Vec<Modification>| {
+            let mut base = vec![Modification::None; self.columns.len()];
+            for (i, m) in set.into_iter().enumerate() {
+                if let Modification::None = m {
+                    continue;
+                }
+                match view.base_columns.get(i) {
+                    Some(Some(bc)) => base[*bc] = m,
+                    Some(None) => {
+                        return Err(TableError::Mapping(format!(
+                            "column {} is computed by view {}",
+                            view.columns[i], view.name
+                        )))
+                    }
+                    None => return Err(TableError::WrongColumnCount(view.columns.len(), i + 1)),
+                }
+            }
+            Ok(base)
+        };
+
+        ops.into_iter()
+            .map(|op| {
+                Ok(match op {
+                    TableOperation::Insert(r) => TableOperation::Insert(row(r)?),
+                    TableOperation::InsertOrUpdate { row: r, update } => {
+                        TableOperation::InsertOrUpdate {
+                            row: row(r)?,
+                            update: set(update)?,
+                        }
+                    }
+                    TableOperation::Update { key, set: s } => {
+                        TableOperation::Update { key, set: set(s)? }
+                    }
+                    TableOperation::UpdateIf {
+                        key,
+                        set: s,
+                        version,
+                    } => TableOperation::UpdateIf {
+                        key,
+                        set: set(s)?,
+                        version,
+                    },
+                    op @ TableOperation::Delete { .. }
+                    | op @ TableOperation::Purge { .. }
+                    | op @ TableOperation::Replicate { .. } => op,
+                })
+            })
+            .collect()
+    }
+
+    /// Columns that the base fills in itself may be left out of inserted rows, in which case
+    /// they are sent as NULL.
+    fn inject_generated_cols(&self, r: &mut TableOperation) {
+        let row = match *r {
+            TableOperation::Insert(ref mut row)
+            | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
+            _ => return,
+        };
+        if self.generated.is_empty() || row.len() + self.generated.len() != self.columns.len() {
+            return;
+        }
+        for &col in &self.generated {
+            row.insert(col, DataType::None);
+        }
+    }
+
     fn inject_dropped_cols(&self, r: &mut TableOperation) {
         use std::mem;
         let ndropped = self.dropped.len();
@@ -641,6 +943,8 @@ impl Table {
             let r = match *r {
                 TableOperation::Insert(ref mut row)
                 | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
+                // replicated rows are shipped as the base stores them, dropped columns and all
+                TableOperation::Replicate { .. } => return,
                 _ => unimplemented!("we need to shift the update/delete cols!"),
             };
             // TODO: what about updates? do we need to rewrite the set vector?
@@ -716,27 +1020,140 @@ impl Table {
         }
     }
 
-    fn prep_records(&self, mut ops: Vec<TableOperation>) -> Input {
+    fn prep_records(&self, mut ops: Vec<TableOperation>, ack: AckLevel) -> Input {
         for r in &mut ops {
+            self.inject_generated_cols(r);
             self.inject_dropped_cols(r);
         }
 
         Input {
             dst: self.node,
             data: ops,
+            ack,
         }
     }
 
-    async fn quick_n_dirty<Request, R>(
+    async fn quick_n_dirty(
         &mut self,
-        r: Request,
-    ) -> Result<R, <Self as Service<Request>>::Error>
+        ops: Vec<TableOperation>,
+        ack: AckLevel,
+        retry: bool,
+    ) -> Result<(), TableError> {
+        let timeout = self.timeout;
+        let max_retries = if retry { self.retry.max_retries } else { 0 };
+        let fut = async move {
+            let mut ops = Some(ops);
+            let mut attempt = 0;
+            loop {
+                let res = match future::poll_fn(|cx| self.poll_ready(cx)).await {
+                    Ok(()) => {
+                        // only keep a copy around if we may have to send the operations again
+                        let ops = if attempt < max_retries {
+                            ops.clone().unwrap()
+                        } else {
+                            ops.take().unwrap()
+                        };
+                        self.input(self.prep_records(ops, ack)).await
+                    }
+                    Err(e) => Err(e),
+                };
+                match res {
+                    Ok(reply) => return Ok(reply.v),
+                    Err(TableError::TransportError(e)) if attempt < max_retries => {
+                        let backoff = self.retry.backoff(attempt);
+                        tracing::debug!(error = %e, ?backoff, attempt, "write failed; retrying");
+                        tokio::time::delay_for(backoff).await;
+                        attempt += 1;
+                        self.reconnect();
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        match timeout {
+            Some(t) => tokio::time::timeout(t, fut)
+                .await
+                .unwrap_or(Err(TableError::Timeout)),
+            None => fut.await,
+        }
+    }
+
+    /// Perform the given operations, and return once they have been acknowledged at `ack`.
+    async fn write(&mut self, ops: Vec<TableOperation>, ack: AckLevel) -> Result<(), TableError> {
+        self.write_with(ops, ack, true).await
+    }
+
+    /// Perform the given operations at the table's ack level, without retrying them if they fail,
+    /// whatever the table's retry policy.
+    pub(crate) async fn write_once(&mut self, ops: Vec<TableOperation>) -> Result<(), TableError> {
+        self.write_with(ops, self.ack, false).await
+    }
+
+    async fn write_with(
+        &mut self,
+        ops: Vec<TableOperation>,
+        ack: AckLevel,
+        retry: bool,
+    ) -> Result<(), TableError> {
+        let ops = self.to_base(ops)?;
+        if ack != AckLevel::Sent {
+            return self.quick_n_dirty(ops, ack, retry).await;
+        }
+
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        // the request is handed to the connection right away, so it is not overtaken by later
+        // writes even though nobody waits for the reply.
+        let reply = self.input(self.prep_records(ops, ack));
+        tokio::spawn(async move {
+            if let Err(e) = reply.await {
+                tracing::debug!(error = %e, "unacknowledged write failed");
+            }
+        });
+        Ok(())
+    }
+
+    /// Turn `ops` into the operations this handle would send to its base table, without sending
+    /// them, and return them along with the base table's node.
+    pub(crate) fn prepare(
+        &self,
+        ops: Vec<TableOperation>,
+    ) -> Result<(NodeIndex, Vec<TableOperation>), TableError> {
+        let ops = self.prep_records(self.to_base(ops)?, self.ack).data;
+        self.check_ops(&ops)?;
+        Ok((self.ni, ops))
+    }
+
+    /// Turn column-modification pairs into the modification of each column of a row.
+    pub(crate) fn modifications<V>(&self, u: V) -> Result<Vec<Modification>, TableError>
     where
-        Request: Send + 'static,
-        Self: Service<Request, Response = Tagged<R>>,
+        V: IntoIterator<Item = (usize, Modification)>,
     {
-        future::poll_fn(|cx| self.poll_ready(cx)).await?;
-        Ok(self.call(r).await?.v)
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "update operations can only be applied to base nodes with key columns"
+        );
+
+        let ncols = self.columns().len();
+        let mut set = vec![Modification::None; ncols];
+        for (coli, m) in u {
+            if coli >= ncols {
+                return Err(TableError::WrongColumnCount(ncols, coli + 1));
+            }
+            set[coli] = m;
+        }
+        Ok(set)
+    }
+
+    /// Start buffering writes to this base table, to be applied together by
+    /// [`Transaction::commit`](crate::Transaction::commit).
+    ///
+    /// The shards of a sharded table apply their writes independently of one another, so this
+    /// returns [`TableError::ShardedTransaction`] if the table has more than one shard.
+    pub fn begin(&mut self) -> Result<crate::Transaction<'_>, TableError> {
+        if self.shards.len() > 1 {
+            return Err(TableError::ShardedTransaction(self.shards.len()));
+        }
+        Ok(crate::Transaction::new(self))
     }
 
     /// Insert a single row of data into this base table.
@@ -744,7 +1161,7 @@ impl Table {
     where
         V: Into<Vec<DataType>>,
     {
-        self.quick_n_dirty(vec![TableOperation::Insert(u.into())])
+        self.write(vec![TableOperation::Insert(u.into())], self.ack)
             .await
     }
 
@@ -754,16 +1171,62 @@ impl Table {
         I: IntoIterator<Item = V>,
         V: Into<TableOperation>,
     {
-        self.quick_n_dirty(i.into_iter().map(Into::into).collect::<Vec<_>>())
+        self.perform_all_with_ack(i, self.ack).await
+    }
+
+    /// Perform multiple operations on this base table, and return once they have been
+    /// acknowledged at `ack`, regardless of the table's ack level.
+    pub async fn perform_all_with_ack<I, V>(
+        &mut self,
+        i: I,
+        ack: AckLevel,
+    ) -> Result<(), TableError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        self.write(i.into_iter().map(Into::into).collect(), ack)
             .await
     }
 
+    /// Check that values of type `T` can be inserted with [`Table::insert_row`].
+    ///
+    /// This is the case if `T` is a struct, and each of its fields (after any renaming through
+    /// serde attributes) has the name of one of the table's columns. Call this right after
+    /// obtaining the `Table` to catch mismatches between the type and the schema early. The field
+    /// names are found through `T`'s `Deserialize` implementation, so `T` must implement it.
+    pub fn check_type<T: DeserializeOwned>(&self) -> Result<(), TableError> {
+        crate::typed::check::<T>(self.columns()).map_err(TableError::Mapping)
+    }
+
+    /// Insert a single row into this base table, taking the value of each column from the field
+    /// of `row` with the same name.
+    ///
+    /// Columns that `T` has no field for are set to `NULL`.
+    pub async fn insert_row<T: Serialize>(&mut self, row: &T) -> Result<(), TableError> {
+        let row = crate::typed::to_row(self.columns(), row).map_err(TableError::Mapping)?;
+        self.insert(row).await
+    }
+
     /// Delete the row with the given key from this base table.
+    ///
+    /// If the table uses soft deletes, the row is only marked as deleted, and stays in the table
+    /// until it is purged with [`Table::purge`]. Re-inserting a row with the same key restores it.
     pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
     where
         I: Into<Vec<DataType>>,
     {
-        self.quick_n_dirty(vec![TableOperation::Delete { key: key.into() }])
+        self.write(vec![TableOperation::Delete { key: key.into() }], self.ack)
+            .await
+    }
+
+    /// Remove the row with the given key from this base table for good, whether or not it has
+    /// been deleted already.
+    pub async fn purge<I>(&mut self, key: I) -> Result<(), TableError>
+    where
+        I: Into<Vec<DataType>>,
+    {
+        self.write(vec![TableOperation::Purge { key: key.into() }], self.ack)
             .await
     }
 
@@ -775,23 +1238,40 @@ impl Table {
     where
         V: IntoIterator<Item = (usize, Modification)>,
     {
-        assert!(
-            !self.key.is_empty() && self.key_is_primary,
-            "update operations can only be applied to base nodes with key columns"
-        );
-
-        let mut set = vec![Modification::None; self.columns.len()];
-        for (coli, m) in u {
-            if coli >= self.columns.len() {
-                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
-            }
-            set[coli] = m;
-        }
-
-        self.quick_n_dirty(vec![TableOperation::Update { key, set }])
+        let set = self.modifications(u)?;
+        self.write(vec![TableOperation::Update { key, set }], self.ack)
             .await
     }
 
+    /// Update the row with the given key in this base table, but only if the row's version is
+    /// still `version`.
+    ///
+    /// This only works for tables that keep a version counter for each row, which are created
+    /// with the `version_column = '...'` table option. The table sets the version of each new row
+    /// to 1, and increments it whenever the row is updated. Read the row's version along with the
+    /// values the update is based on, and pass it here: if another write has changed the row in
+    /// the meantime, the update is not applied, and no update is lost. Whether the update was
+    /// applied can be seen by reading the row's version again. Updates to tables without a
+    /// version column are never applied.
+    ///
+    /// `u` is applied as documented in [`Table::update`].
+    pub async fn update_if<V>(
+        &mut self,
+        key: Vec<DataType>,
+        version: u64,
+        u: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let set = self.modifications(u)?;
+        self.write(
+            vec![TableOperation::UpdateIf { key, set, version }],
+            self.ack,
+        )
+        .await
+    }
+
     /// Perform a insert-or-update on this base table.
     ///
     /// If a row already exists for the key in `insert`, the existing row will instead be updated
@@ -804,23 +1284,67 @@ impl Table {
     where
         V: IntoIterator<Item = (usize, Modification)>,
     {
-        assert!(
-            !self.key.is_empty() && self.key_is_primary,
-            "update operations can only be applied to base nodes with key columns"
+        let set = self.modifications(update)?;
+        self.write(
+            vec![TableOperation::InsertOrUpdate {
+                row: insert,
+                update: set,
+            }],
+            self.ack,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_shards_compound_keys_by_the_shard_column() {
+        // keyed by (a, b), sharded by b
+        let ops = vec![
+            TableOperation::Insert(vec![1.into(), 2.into(), "x".into()]),
+            TableOperation::Insert(vec![2.into(), 3.into(), "y".into()]),
+            TableOperation::Delete {
+                key: vec![1.into(), 2.into()],
+            },
+            TableOperation::Update {
+                key: vec![9.into(), 3.into()],
+                set: vec![Modification::Set("z".into())],
+            },
+        ];
+        let writes = shard_writes(1, Some(1), 2, ops.clone());
+        assert_eq!(
+            writes,
+            vec![
+                vec![ops[0].clone(), ops[2].clone()],
+                vec![ops[1].clone(), ops[3].clone()],
+            ]
         );
+    }
 
-        let mut set = vec![Modification::None; self.columns.len()];
-        for (coli, m) in update {
-            if coli >= self.columns.len() {
-                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+    #[test]
+    fn it_broadcasts_ops_whose_key_lacks_the_shard_column() {
+        // keyed by a, sharded by b
+        let ops = vec![
+            TableOperation::Insert(vec![1.into(), 3.into()]),
+            TableOperation::Delete {
+                key: vec![1.into()],
+            },
+            TableOperation::Update {
+                key: vec![1.into()],
+                set: vec![Modification::Set(4.into())],
+            },
+        ];
+        let writes = shard_writes(1, None, 4, ops.clone());
+        for (shard, w) in writes.into_iter().enumerate() {
+            let mut expected = Vec::new();
+            if shard == 3 {
+                expected.push(ops[0].clone());
             }
-            set[coli] = m;
+            expected.extend(ops[1..].iter().cloned());
+            assert_eq!(w, expected, "shard {}", shard);
         }
-
-        self.quick_n_dirty(vec![TableOperation::InsertOrUpdate {
-            row: insert,
-            update: set,
-        }])
-        .await
     }
 }
@@ -0,0 +1,124 @@
+use crate::data::*;
+use crate::table::{Table, TableError};
+
+/// A set of writes to one unsharded base table that the table applies as a whole.
+///
+/// A transaction is started with [`Table::begin`], and buffers its writes in the client until
+/// [`Transaction::commit`] sends them to the table in a single batch. Nothing is written if the
+/// transaction is rolled back or dropped instead.
+///
+/// The table processes the batch in one go, so reads never observe only some of its writes. Only
+/// unsharded tables support transactions: the shards of a sharded table would each apply their
+/// part of the batch independently, so [`Table::begin`] refuses to start one on such a table.
+///
+/// ```no_run
+/// # async fn f(mut table: noria::Table) -> Result<(), noria::error::TableError> {
+/// let mut tx = table.begin()?;
+/// tx.insert(vec![1.into(), "a".into()]);
+/// tx.delete(vec![2.into()]);
+/// tx.commit().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[must_use = "a transaction does nothing unless it is committed"]
+pub struct Transaction<'t> {
+    table: &'t mut Table,
+    ops: Vec<TableOperation>,
+}
+
+impl<'t> Transaction<'t> {
+    pub(crate) fn new(table: &'t mut Table) -> Self {
+        Transaction {
+            table,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Buffer the insert of a single row.
+    pub fn insert<V>(&mut self, u: V)
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.ops.push(TableOperation::Insert(u.into()));
+    }
+
+    /// Buffer the delete of the row with the given key.
+    pub fn delete<I>(&mut self, key: I)
+    where
+        I: Into<Vec<DataType>>,
+    {
+        self.ops.push(TableOperation::Delete { key: key.into() });
+    }
+
+    /// Buffer the purge of the row with the given key, as documented in [`Table::purge`].
+    pub fn purge<I>(&mut self, key: I)
+    where
+        I: Into<Vec<DataType>>,
+    {
+        self.ops.push(TableOperation::Purge { key: key.into() });
+    }
+
+    /// Buffer an update of the row with the given key, as documented in [`Table::update`].
+    pub fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let set = self.table.modifications(u)?;
+        self.ops.push(TableOperation::Update { key, set });
+        Ok(())
+    }
+
+    /// Buffer a conditional update of the row with the given key, as documented in
+    /// [`Table::update_if`].
+    pub fn update_if<V>(&mut self, key: Vec<DataType>, version: u64, u: V) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let set = self.table.modifications(u)?;
+        self.ops
+            .push(TableOperation::UpdateIf { key, set, version });
+        Ok(())
+    }
+
+    /// Buffer an insert-or-update, as documented in [`Table::insert_or_update`].
+    pub fn insert_or_update<V>(
+        &mut self,
+        insert: Vec<DataType>,
+        update: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let update = self.table.modifications(update)?;
+        self.ops.push(TableOperation::InsertOrUpdate {
+            row: insert,
+            update,
+        });
+        Ok(())
+    }
+
+    /// The number of writes buffered so far.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether no writes have been buffered yet.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Apply all the buffered writes to the table as one batch.
+    ///
+    /// Returns once the batch has been acknowledged at the table's ack level. The batch is sent
+    /// only once, whatever the table's retry policy: a retry after a broken connection could
+    /// apply the batch twice. If the commit fails, it is unknown whether the batch was applied.
+    pub async fn commit(self) -> Result<(), TableError> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        self.table.write_once(self.ops).await
+    }
+
+    /// Discard all the buffered writes.
+    pub fn rollback(self) {}
+}
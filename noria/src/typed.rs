@@ -0,0 +1,180 @@
+//! Mapping between rows and Rust types, based on column names.
+//!
+//! Values are converted through `serde_json::Value`, which is a little wasteful, but saves us from
+//! implementing a full `Serializer` and `Deserializer` for rows.
+
+use crate::data::DataType;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// A deserializer that only records the field names of the struct it is asked to deserialize.
+struct FieldNames<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de, 'a> Deserializer<'de> for FieldNames<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// The names of the fields of `T`, which must be a struct with named fields.
+fn fields<T: DeserializeOwned>() -> Result<&'static [&'static str], String> {
+    let mut fields = None;
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields.ok_or_else(|| "only structs with named fields can be mapped to rows".to_owned())
+}
+
+/// Check that every field of `T` has a corresponding column.
+pub(crate) fn check<T: DeserializeOwned>(columns: &[String]) -> Result<(), String> {
+    let missing: Vec<_> = fields::<T>()?
+        .iter()
+        .filter(|f| !columns.iter().any(|c| c == *f))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("no columns for fields {:?}", missing))
+    }
+}
+
+fn to_json(v: &DataType) -> Value {
+    match *v {
+        DataType::None => Value::Null,
+        DataType::Int(n) => n.into(),
+        DataType::UnsignedInt(n) => n.into(),
+        DataType::BigInt(n) => n.into(),
+        DataType::UnsignedBigInt(n) => n.into(),
+        DataType::Real(..) => serde_json::Number::from_f64(v.into())
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        DataType::Text(..) | DataType::TinyText(..) => {
+            let s: &str = v.into();
+            s.into()
+        }
+        DataType::Timestamp(ref ts) => serde_json::to_value(ts).unwrap_or(Value::Null),
+    }
+}
+
+fn from_json(column: &str, v: Value) -> Result<DataType, String> {
+    Ok(match v {
+        Value::Null => DataType::None,
+        Value::Bool(b) => (b as i32).into(),
+        Value::Number(n) => {
+            if let Some(n) = n.as_i64() {
+                n.into()
+            } else if let Some(n) = n.as_u64() {
+                n.into()
+            } else {
+                n.as_f64().unwrap().into()
+            }
+        }
+        Value::String(s) => s.into(),
+        Value::Array(_) | Value::Object(_) => {
+            return Err(format!("column {} cannot hold a nested value", column))
+        }
+    })
+}
+
+/// Turn a row with the given columns into a `T`, matching fields to columns by name.
+pub(crate) fn from_row<T: DeserializeOwned>(
+    columns: &[String],
+    row: &[DataType],
+) -> Result<T, String> {
+    let map: Map<String, Value> = columns
+        .iter()
+        .zip(row)
+        .map(|(c, v)| (c.clone(), to_json(v)))
+        .collect();
+    serde_json::from_value(Value::Object(map)).map_err(|e| e.to_string())
+}
+
+/// Turn `value` into a row with the given columns, matching fields to columns by name.
+///
+/// Columns that `T` has no field for are left empty.
+pub(crate) fn to_row<T: Serialize>(columns: &[String], value: &T) -> Result<Vec<DataType>, String> {
+    let mut map = match serde_json::to_value(value).map_err(|e| e.to_string())? {
+        Value::Object(map) => map,
+        _ => return Err("only structs with named fields can be mapped to rows".to_owned()),
+    };
+    let row = columns
+        .iter()
+        .map(|c| match map.remove(c) {
+            Some(v) => from_json(c, v),
+            None => Ok(DataType::None),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if !map.is_empty() {
+        let extra: Vec<_> = map.keys().collect();
+        return Err(format!("no columns for fields {:?}", extra));
+    }
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Article {
+        aid: i32,
+        title: String,
+        score: Option<f64>,
+    }
+
+    fn columns() -> Vec<String> {
+        vec!["aid".to_owned(), "title".to_owned(), "score".to_owned()]
+    }
+
+    #[test]
+    fn it_finds_fields() {
+        assert_eq!(fields::<Article>().unwrap(), &["aid", "title", "score"]);
+        assert!(fields::<i32>().is_err());
+        assert!(check::<Article>(&columns()).is_ok());
+        assert!(check::<Article>(&columns()[..2]).is_err());
+    }
+
+    #[test]
+    fn it_round_trips() {
+        let a = Article {
+            aid: 1,
+            title: "I love Soup".to_owned(),
+            score: None,
+        };
+        let row = to_row(&columns(), &a).unwrap();
+        assert_eq!(row, vec![1.into(), "I love Soup".into(), DataType::None]);
+        assert_eq!(from_row::<Article>(&columns(), &row).unwrap(), a);
+
+        let row = vec![2.into(), "Soup".into(), 0.5.into()];
+        assert_eq!(
+            from_row::<Article>(&columns(), &row).unwrap().score,
+            Some(0.5)
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields() {
+        let a = Article {
+            aid: 1,
+            title: "I love Soup".to_owned(),
+            score: None,
+        };
+        assert!(to_row(&columns()[..2], &a).is_err());
+    }
+}
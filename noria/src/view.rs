@@ -1,19 +1,23 @@
 use crate::data::*;
+use crate::RetryPolicy;
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
-    future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
-    stream::StreamExt, stream::TryStreamExt,
+    future, future::TryFutureExt, ready, stream, stream::futures_unordered::FuturesUnordered,
+    stream::StreamExt, stream::TryStreamExt, Stream,
 };
-use nom_sql::ColumnSpecification;
+use nom_sql::{ColumnSpecification, OrderType};
 use petgraph::graph::NodeIndex;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio_tower::multiplex;
 use tower_balance::p2c::Balance;
 use tower_buffer::Buffer;
@@ -101,6 +105,29 @@ pub enum ViewError {
     /// The given view is not yet available.
     #[fail(display = "the view is not yet available")]
     NotYetAvailable,
+    /// The request did not complete within the timeout set for the view.
+    #[fail(display = "the request timed out")]
+    Timeout,
+    /// A range lookup spanned more than `MAX_RANGE_KEYS` keys.
+    #[fail(display = "range spans {} keys, which is too many", _0)]
+    RangeTooLarge(u64),
+    /// A page reached past the rows that the view keeps for each key, which is given.
+    #[fail(display = "page reaches past the {} rows kept for each key", _0)]
+    PageOutOfBounds(usize),
+    /// Changes were asked for of a view that does not keep them, because it is partially
+    /// materialized.
+    #[fail(display = "the view does not keep track of its changes")]
+    NoChanges,
+    /// A shard was asked for by an index that is not below the number of shards of the view.
+    #[fail(display = "the view has no shard {}", _0)]
+    NoSuchShard(usize),
+    /// A key did not have the given number of values, one for each echoed parameter and key
+    /// column of the view, but the number of values that follows.
+    #[fail(display = "expected a key of {} values, but got {}", _0, _1)]
+    KeyLength(usize, usize),
+    /// The view's rows could not be mapped to the requested type.
+    #[fail(display = "rows cannot be mapped to the requested type: {}", _0)]
+    Mapping(String),
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -112,6 +139,34 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for ViewError {
     }
 }
 
+/// The largest number of keys that a single [`View::lookup_range`] may span.
+pub const MAX_RANGE_KEYS: u64 = 1 << 14;
+
+/// How soon writes to a view become visible to reads.
+///
+/// Readers see a snapshot of the view that is refreshed by swapping in the writes that have been
+/// applied since the last refresh. Refreshing less often lets many writes share a single swap,
+/// which makes heavily written views cheaper to maintain, at the cost of staler reads. Set with
+/// [`ControllerHandle::set_view_refresh`](crate::ControllerHandle::set_view_refresh).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefreshPolicy {
+    /// Refresh after every batch of writes. This is the default.
+    Immediate,
+    /// Refresh at most this often.
+    ///
+    /// Reads that block on a missing key wait for the next refresh after the key is filled.
+    Periodic(Duration),
+    /// Like `Periodic`, but refresh at once when a key that a blocked read is waiting for is
+    /// filled.
+    OnDemand(Duration),
+}
+
+impl Default for RefreshPolicy {
+    fn default() -> Self {
+        RefreshPolicy::Immediate
+    }
+}
+
 #[doc(hidden)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ReadQuery {
@@ -129,6 +184,44 @@ pub enum ReadQuery {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Count the rows for each key, without reading the rows themselves
+    Count {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Keys to count the rows of
+        keys: Vec<Vec<DataType>>,
+    },
+    /// Read the rows of a range of keys from a leaf view, in key order
+    Scan {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// The key to resume after, or `None` to start a new scan
+        after: Option<Vec<DataType>>,
+        /// How many keys to read the rows of
+        limit: usize,
+    },
+    /// Read the changes made to a leaf view since a given point
+    Changes {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Where the client is up to, or `None` if it has yet to start following the changes
+        since: Option<ChangeCursor>,
+        /// How many changes to read at most
+        limit: usize,
+    },
+    /// Read a page of the rows of a single key from a leaf view, after sorting them
+    Page {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// The key to read the rows of
+        key: Vec<DataType>,
+        /// The columns to sort the rows by before paging through them
+        order: Vec<(usize, OrderType)>,
+        /// How many of the sorted rows to skip
+        offset: usize,
+        /// How many rows to read at most
+        limit: usize,
+    },
 }
 
 #[doc(hidden)]
@@ -138,6 +231,51 @@ pub enum ReadReply<D = ReadReplyBatch> {
     Normal(Result<Vec<D>, ()>),
     /// Read size of view
     Size(usize),
+    /// A non-blocking read where some keys missed, and have had replays triggered.
+    Partial {
+        /// The rows for each key, which are empty for keys that missed.
+        rows: Vec<D>,
+        /// The indices of the keys that missed.
+        misses: Vec<usize>,
+    },
+    /// The number of rows for each key, or `None` for keys that missed and have had replays
+    /// triggered. Errors if view isn't ready yet.
+    Counts(Result<Vec<Option<usize>>, ()>),
+    /// The rows of a chunk of a scan, and the key to resume the scan after if there are more keys
+    /// after it. Errors if view isn't ready yet.
+    Chunk(Result<(D, Option<Vec<DataType>>), ()>),
+    /// The changes made to a view, or `None` if the view does not keep track of its changes.
+    Changes(Option<ViewChanges>),
+}
+
+/// How far a client that follows the changes to one shard of a view has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeCursor {
+    /// Identifies the log of changes of the shard, which starts afresh if the shard's reader is
+    /// replaced.
+    pub log: u64,
+    /// How many changes were made to the shard before the cursor.
+    pub seq: u64,
+}
+
+/// The changes made to one shard of a view, as returned by [`View::changes`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ViewChanges {
+    /// The rows that were added (`true`) or removed (`false`) since the cursor, in the order they
+    /// became visible to reads, and the cursor to ask for the changes after them with.
+    Since {
+        /// The rows that changed.
+        rows: Vec<(Vec<DataType>, bool)>,
+        /// Where to continue from.
+        next: ChangeCursor,
+    },
+    /// No cursor was given, or the changes since it are no longer kept. The shard must be read in
+    /// full, and the changes followed from `next` on. Changes after `next` may already show up in
+    /// that read.
+    Lost {
+        /// Where to continue from.
+        next: ChangeCursor,
+    },
 }
 
 #[doc(hidden)]
@@ -147,6 +285,94 @@ pub struct ViewBuilder {
     pub columns: Vec<String>,
     pub schema: Option<Vec<ColumnSpecification>>,
     pub shards: Vec<SocketAddr>,
+    /// How the rows of the view must be put together.
+    #[serde(default)]
+    pub merge: Merge,
+    /// The columns that the rows of each key are sorted by.
+    #[serde(default)]
+    pub order: Vec<(usize, OrderType)>,
+    /// The most rows the view keeps for each key, if its query has a `LIMIT`.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// The columns that echo parameters of the view's projection.
+    #[serde(default)]
+    pub echoed: Vec<usize>,
+    /// The columns that the view is keyed by.
+    #[serde(default)]
+    pub key_columns: Vec<usize>,
+    /// Every replica of the view's reader, including the one given by `node` and `shards`, if the
+    /// reader is replicated.
+    #[serde(default)]
+    pub replicas: Vec<ReaderReplica>,
+}
+
+/// The worker label that says which zone a worker is in.
+///
+/// Readers in the zone set with `ControllerHandle::set_zone` are preferred over readers elsewhere.
+pub const ZONE_LABEL: &str = "zone";
+
+/// One replica of the reader of a view.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReaderReplica {
+    pub node: NodeIndex,
+    pub shards: Vec<SocketAddr>,
+    /// The zone of the worker that runs each shard, if it has one.
+    pub zones: Vec<Option<String>>,
+}
+
+impl ReaderReplica {
+    /// How many of the replica's shards run in `zone`.
+    fn shards_in(&self, zone: Option<&str>) -> usize {
+        match zone {
+            Some(zone) => self
+                .zones
+                .iter()
+                .filter(|z| z.as_deref() == Some(zone))
+                .count(),
+            None => 0,
+        }
+    }
+}
+
+/// Order `replicas` so that the ones with the most shards in `zone` come first. Otherwise, the
+/// order the controller gave them in is kept.
+fn by_zone(mut replicas: Vec<ReaderReplica>, zone: Option<&str>) -> Vec<ReaderReplica> {
+    replicas.sort_by_key(|r| std::cmp::Reverse(r.shards_in(zone)));
+    replicas
+}
+
+fn view_rpc(
+    rpcs: &Mutex<HashMap<(SocketAddr, usize), ViewRpc>>,
+    addr: SocketAddr,
+    shardi: usize,
+) -> ViewRpc {
+    use std::collections::hash_map::Entry;
+
+    // one entry per shard so that we can send sharded requests in parallel even if
+    // they happen to be targeting the same machine.
+    let mut rpcs = rpcs.lock().unwrap();
+    match rpcs.entry((addr, shardi)) {
+        Entry::Occupied(e) => e.get().clone(),
+        Entry::Vacant(h) => {
+            // TODO: maybe always use the same local port?
+            let (c, w) = Buffer::pair(
+                ConcurrencyLimit::new(
+                    Balance::from_entropy(make_views_discover(addr)),
+                    crate::PENDING_LIMIT,
+                ),
+                crate::BUFFER_TO_POOL,
+            );
+            use tracing_futures::Instrument;
+            tokio::spawn(w.instrument(tracing::debug_span!(
+                "view_worker",
+                addr = %addr,
+                shard = shardi
+            )));
+            h.insert(c.clone());
+            c
+        }
+    }
 }
 
 impl ViewBuilder {
@@ -156,45 +382,35 @@ impl ViewBuilder {
         &self,
         rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
     ) -> Result<View, io::Error> {
-        let node = self.node;
+        self.build_in_zone(rpcs, None)
+    }
+
+    /// Build a `View` out of a `ViewBuilder` that reads from the replica of the reader with the
+    /// most shards in `zone`, and fails over to the other replicas.
+    #[doc(hidden)]
+    pub fn build_in_zone(
+        &self,
+        rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
+        zone: Option<&str>,
+    ) -> Result<View, io::Error> {
+        let replicas = if self.replicas.is_empty() {
+            vec![ReaderReplica {
+                node: self.node,
+                shards: self.shards.clone(),
+                zones: vec![None; self.shards.len()],
+            }]
+        } else {
+            by_zone(self.replicas.clone(), zone)
+        };
+        let node = replicas[0].node;
         let columns = self.columns.clone();
-        let shards = self.shards.clone();
         let schema = self.schema.clone();
-
-        let mut addrs = Vec::with_capacity(shards.len());
-        let mut conns = Vec::with_capacity(shards.len());
-
-        for (shardi, &addr) in shards.iter().enumerate() {
-            use std::collections::hash_map::Entry;
-
-            addrs.push(addr);
-
-            // one entry per shard so that we can send sharded requests in parallel even if
-            // they happen to be targeting the same machine.
-            let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
-                Entry::Occupied(e) => e.get().clone(),
-                Entry::Vacant(h) => {
-                    // TODO: maybe always use the same local port?
-                    let (c, w) = Buffer::pair(
-                        ConcurrencyLimit::new(
-                            Balance::from_entropy(make_views_discover(addr)),
-                            crate::PENDING_LIMIT,
-                        ),
-                        crate::BUFFER_TO_POOL,
-                    );
-                    use tracing_futures::Instrument;
-                    tokio::spawn(w.instrument(tracing::debug_span!(
-                        "view_worker",
-                        addr = %addr,
-                        shard = shardi
-                    )));
-                    h.insert(c.clone());
-                    c
-                }
-            };
-            conns.push(s);
-        }
+        let addrs = replicas[0].shards.clone();
+        let conns = addrs
+            .iter()
+            .enumerate()
+            .map(|(shardi, &addr)| view_rpc(&rpcs, addr, shardi))
+            .collect();
 
         let tracer = tracing::dispatcher::get_default(|d| d.clone());
         Ok(View {
@@ -203,6 +419,16 @@ impl ViewBuilder {
             columns,
             shard_addrs: addrs,
             shards: conns,
+            replicas,
+            replica: 0,
+            rpcs,
+            timeout: None,
+            retry: RetryPolicy::default(),
+            merge: self.merge.clone(),
+            order: self.order.clone(),
+            limit: self.limit,
+            echoed: self.echoed.clone(),
+            key_columns: self.key_columns.clone(),
             tracer,
         })
     }
@@ -220,6 +446,25 @@ pub struct View {
 
     shards: Vec<ViewRpc>,
     shard_addrs: Vec<SocketAddr>,
+    /// The replicas of the view's reader, in the order they are tried in.
+    replicas: Vec<ReaderReplica>,
+    /// The replica that `node` and `shard_addrs` belong to.
+    replica: usize,
+    /// The connection pool shared with the `ControllerHandle`, used to reconnect.
+    rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
+    /// How long each lookup may take before it fails with `ViewError::Timeout`.
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    /// How the rows of reads that span several shards are put together.
+    merge: Merge,
+    /// The columns that the rows of each key are sorted by.
+    order: Vec<(usize, OrderType)>,
+    /// The most rows the view keeps for each key.
+    limit: Option<usize>,
+    /// The columns that are filled with the values bound to parameters of the projection.
+    echoed: Vec<usize>,
+    /// The columns that lookups are keyed by.
+    key_columns: Vec<usize>,
 
     tracer: tracing::Dispatch,
 }
@@ -234,9 +479,58 @@ impl fmt::Debug for View {
     }
 }
 
+pub(crate) mod merge;
 pub(crate) mod results;
+use self::merge::Merge;
 use self::results::{Results, Row};
 
+/// The rows for each key in a reply, along with whether the key missed.
+fn batches(reply: ReadReply) -> Result<Vec<(ReadReplyBatch, bool)>, ViewError> {
+    match reply {
+        ReadReply::Normal(Ok(rows)) => Ok(rows.into_iter().map(|rows| (rows, false)).collect()),
+        ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
+        ReadReply::Partial { rows, misses } => Ok(rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, rows)| (rows, misses.contains(&i)))
+            .collect()),
+        ReadReply::Size(_) | ReadReply::Counts(_) | ReadReply::Chunk(_) | ReadReply::Changes(_) => {
+            unreachable!()
+        }
+    }
+}
+
+/// Compare two rows by the given columns, as for an `ORDER BY`.
+#[doc(hidden)]
+pub fn cmp_rows(
+    order: &[(usize, OrderType)],
+    a: &[DataType],
+    b: &[DataType],
+) -> std::cmp::Ordering {
+    order
+        .iter()
+        .map(|&(c, ref o)| match *o {
+            OrderType::OrderAscending => a[c].cmp(&b[c]),
+            OrderType::OrderDescending => b[c].cmp(&a[c]),
+        })
+        .find(|&o| o != std::cmp::Ordering::Equal)
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// Sort `rows` by the given columns, as for an `ORDER BY`.
+fn sort_rows(rows: &mut [Vec<DataType>], order: &[(usize, OrderType)]) {
+    rows.sort_by(|a, b| cmp_rows(order, a, b));
+}
+
+fn results(rows: ReadReplyBatch, missed: bool, columns: &Arc<[String]>) -> Results {
+    let results = Results::new(rows.into(), Arc::clone(columns));
+    if missed {
+        results.into_miss()
+    } else {
+        results
+    }
+}
+
 impl Service<(Vec<Vec<DataType>>, bool)> for View {
     type Response = Vec<Results>;
     type Error = ViewError;
@@ -280,14 +574,10 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                     .call(request)
                     .map_err(ViewError::from)
                     .and_then(move |reply| async move {
-                        match reply.v {
-                            ReadReply::Normal(Ok(rows)) => Ok(rows
-                                .into_iter()
-                                .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                                .collect()),
-                            ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                            _ => unreachable!(),
-                        }
+                        Ok(batches(reply.v)?
+                            .into_iter()
+                            .map(|(rows, missed)| results(rows, missed, &columns))
+                            .collect())
                     }),
             );
         }
@@ -297,9 +587,12 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
         }
         assert!(keys.iter().all(|k| k.len() == 1));
         let mut shard_queries = vec![Vec::new(); self.shards.len()];
-        for key in keys {
+        // the index of each key in `keys`, so that results can be returned in the same order
+        let mut shard_indices = vec![Vec::new(); self.shards.len()];
+        for (i, key) in keys.into_iter().enumerate() {
             let shard = crate::shard_by(&key[0], self.shards.len());
             shard_queries[shard].push(key);
+            shard_indices[shard].push(i);
         }
 
         let node = self.node;
@@ -307,8 +600,8 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
             self.shards
                 .iter_mut()
                 .enumerate()
-                .zip(shard_queries.into_iter())
-                .filter_map(|((shardi, shard), shard_queries)| {
+                .zip(shard_queries.into_iter().zip(shard_indices))
+                .filter_map(|((shardi, shard), (shard_queries, indices))| {
                     if shard_queries.is_empty() {
                         // poll_ready reserves a sender slot which we have to release
                         // we do that by dropping the old handle and replacing it with a clone
@@ -316,10 +609,10 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                         *shard = shard.clone();
                         None
                     } else {
-                        Some(((shardi, shard), shard_queries))
+                        Some(((shardi, shard), (shard_queries, indices)))
                     }
                 })
-                .map(move |((shardi, shard), shard_queries)| {
+                .map(move |((shardi, shard), (shard_queries, indices))| {
                     let request = Tagged::from(ReadQuery::Normal {
                         target: (node, shardi),
                         keys: shard_queries,
@@ -340,18 +633,19 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
                         .call(request)
                         .map_err(ViewError::from)
                         .and_then(|reply| async move {
-                            match reply.v {
-                                ReadReply::Normal(Ok(rows)) => Ok(rows),
-                                ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                                _ => unreachable!(),
-                            }
+                            Ok(indices
+                                .into_iter()
+                                .zip(batches(reply.v)?)
+                                .collect::<Vec<_>>())
                         })
                 })
                 .collect::<FuturesUnordered<_>>()
                 .try_concat()
-                .map_ok(move |rows| {
+                .map_ok(move |mut rows| {
+                    // shards reply in whatever order they finish
+                    rows.sort_by_key(|&(i, _)| i);
                     rows.into_iter()
-                        .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
+                        .map(|(_, (rows, missed))| results(rows, missed, &columns))
                         .collect()
                 }),
         )
@@ -370,10 +664,123 @@ impl View {
         self.schema.as_deref()
     }
 
+    /// Set how long each call on this `View` may take before it gives up.
+    ///
+    /// A call that times out fails with `ViewError::Timeout`. Calls do not time out by default.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Get the timeout set for calls on this `View`, if any.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Set how lookups that fail because of a broken connection are retried.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Set how the rows of reads that span several shards are put together.
+    ///
+    /// This applies to [`View::lookup_range`] and [`View::read_all`]. Rows are concatenated by
+    /// default, which is not what you want if, say, the view is an aggregation that each shard
+    /// computes over only its own rows; see [`Merge`] for the alternatives. A [`Merge::Combine`]
+    /// is also applied to the rows of each key returned by a lookup.
+    ///
+    /// Views of aggregations that split hot keys across their shards start out with the
+    /// `Merge::Combine` that adds up the parts of each key, which should then be kept.
+    pub fn set_merge(&mut self, merge: Merge) {
+        self.merge = merge;
+    }
+
+    /// Get how the rows of reads that span several shards are put together.
+    pub fn merge(&self) -> &Merge {
+        &self.merge
+    }
+
+    /// Get the columns that the rows returned for each key are sorted by, and in which direction.
+    ///
+    /// This is the `ORDER BY` of the view's query, if it has one. The rows of a key are returned
+    /// in no particular order otherwise.
+    pub fn order(&self) -> &[(usize, OrderType)] {
+        &self.order
+    }
+
+    /// Get the most rows that this view keeps for each key, which is the `LIMIT` of the view's
+    /// query if it has one.
+    ///
+    /// For queries with `LIMIT ?`, this is a bound chosen by the planner, and
+    /// [`View::lookup_page`] reads the pages within it.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Get the columns that echo the parameters of the view's projection, as for
+    /// `SELECT ?, ...`, in the order of the parameters.
+    ///
+    /// The keys of lookups on such a view start with the values of these parameters, followed by
+    /// the values of the view's key columns. The rows returned for a key hold the given values in
+    /// these columns.
+    pub fn echoed(&self) -> &[usize] {
+        &self.echoed
+    }
+
+    /// Get the columns that this view is keyed by.
+    ///
+    /// Keys passed to lookups hold the values of the echoed parameters (see [`View::echoed`]),
+    /// followed by a value for each of these columns.
+    pub fn key_columns(&self) -> &[usize] {
+        &self.key_columns
+    }
+
+    /// Check that `key` has a value for each echoed parameter and key column.
+    fn check_key(&self, key: &[DataType]) -> Result<(), ViewError> {
+        let expected = self.echoed.len() + self.key_columns.len();
+        // views built by older controllers do not say what they are keyed by
+        let known = !self.key_columns.is_empty();
+        if (known && key.len() != expected) || key.len() <= self.echoed.len() {
+            return Err(ViewError::KeyLength(
+                expected.max(self.echoed.len() + 1),
+                key.len(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fill the columns that echo parameters of the view's projection with `values`.
+    fn echo(&self, rs: Results, values: &[DataType]) -> Results {
+        let missed = rs.is_miss();
+        let mut rows: Vec<Vec<DataType>> = rs.into();
+        for row in &mut rows {
+            for (&c, v) in self.echoed.iter().zip(values) {
+                row[c] = v.clone();
+            }
+        }
+        results(rows.into(), missed, &Arc::from(&self.columns[..]))
+    }
+
+    async fn with_timeout<R>(
+        timeout: Option<Duration>,
+        fut: impl Future<Output = Result<R, ViewError>>,
+    ) -> Result<R, ViewError> {
+        match timeout {
+            Some(t) => tokio::time::timeout(t, fut)
+                .await
+                .unwrap_or(Err(ViewError::Timeout)),
+            None => fut.await,
+        }
+    }
+
     /// Get the current size of this view.
     ///
     /// Note that you must also continue to poll this `View` for the returned future to resolve.
     pub async fn len(&mut self) -> Result<usize, ViewError> {
+        let timeout = self.timeout;
+        Self::with_timeout(timeout, self.len_inner()).await
+    }
+
+    async fn len_inner(&mut self) -> Result<usize, ViewError> {
         future::poll_fn(|cx| self.poll_ready(cx)).await?;
 
         let node = self.node;
@@ -400,29 +807,459 @@ impl View {
         Ok(nrows)
     }
 
+    /// Get the number of rows for the given parameter value.
+    ///
+    /// Only the count is sent back by the reader, so this is cheaper than a lookup when the rows
+    /// themselves aren't needed. Like a blocking lookup, this waits for missing state to be
+    /// filled in.
+    pub async fn count(&mut self, key: &[DataType]) -> Result<usize, ViewError> {
+        let timeout = self.timeout;
+        Self::with_timeout(timeout, self.count_inner(key)).await
+    }
+
+    async fn count_inner(&mut self, key: &[DataType]) -> Result<usize, ViewError> {
+        self.check_key(key)?;
+        // the values of parameters that the view echoes are not part of its keys
+        let (_, key) = key.split_at(self.echoed.len());
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            let shard = &mut self.shards[shardi];
+            future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+            let reply = shard
+                .call(Tagged::from(ReadQuery::Count {
+                    target: (self.node, shardi),
+                    keys: vec![key.to_vec()],
+                }))
+                .await?;
+            match reply.v {
+                ReadReply::Counts(Ok(counts)) => {
+                    if let Some(n) = counts[0] {
+                        return Ok(n);
+                    }
+                }
+                ReadReply::Counts(Err(())) => return Err(ViewError::NotYetAvailable),
+                _ => unreachable!(),
+            }
+
+            // the key missed, and the replay it triggered has yet to finish
+            tokio::time::delay_for(backoff).await;
+            backoff = std::cmp::min(backoff * 2, Duration::from_millis(100));
+        }
+    }
+
+    /// Check whether there are any rows for the given parameter value.
+    ///
+    /// See [`View::count`].
+    pub async fn contains(&mut self, key: &[DataType]) -> Result<bool, ViewError> {
+        Ok(self.count(key).await? > 0)
+    }
+
+    /// Read every row in this view, in chunks of the rows of at most `chunk_size` keys.
+    ///
+    /// Each chunk is read from a single, consistent snapshot of one shard, but the view may change
+    /// between chunks. Each shard is read in key order, and resumes after the last key of the
+    /// previous chunk, so no key is returned twice, and every key that is in the view throughout
+    /// is returned. Keys that are added while the scan is in progress may be missed. For partially
+    /// materialized views, only the keys that are currently materialized are returned, and no
+    /// replays are triggered.
+    ///
+    /// The stream ends after the last chunk, or after the first error.
+    pub fn iter(
+        &mut self,
+        chunk_size: usize,
+    ) -> impl Stream<Item = Result<Results, ViewError>> + '_ {
+        let shards = self.shards.len();
+        self.scan(0..shards, chunk_size)
+    }
+
+    /// Read every row in the given shard of this view, in chunks of the rows of at most
+    /// `chunk_size` keys.
+    ///
+    /// This allows several shards to be read in parallel. See [`View::iter`]. If the view has no
+    /// shard `shard`, the stream yields [`ViewError::NoSuchShard`].
+    pub fn iter_shard(
+        &mut self,
+        shard: usize,
+        chunk_size: usize,
+    ) -> impl Stream<Item = Result<Results, ViewError>> + '_ {
+        self.scan(shard..shard + 1, chunk_size)
+    }
+
+    /// The number of shards this view is split into.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Read every row in this view, and put the rows of all the shards together as set with
+    /// [`View::set_merge`].
+    ///
+    /// The same caveats as for [`View::iter`] apply, and each shard is read in chunks of the rows
+    /// of at most `chunk_size` keys.
+    pub async fn read_all(&mut self, chunk_size: usize) -> Result<Results, ViewError> {
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for shardi in 0..self.shards.len() {
+            let chunks: Vec<Results> = self.iter_shard(shardi, chunk_size).try_collect().await?;
+            shards.push(
+                chunks
+                    .into_iter()
+                    .flat_map(|rs| -> Vec<Vec<DataType>> { rs.into() })
+                    .collect(),
+            );
+        }
+        let rows = self.merge.apply(shards);
+        Ok(Results::new(rows, Arc::from(&self.columns[..])))
+    }
+
+    fn scan(
+        &mut self,
+        shards: std::ops::Range<usize>,
+        chunk_size: usize,
+    ) -> impl Stream<Item = Result<Results, ViewError>> + '_ {
+        assert_ne!(chunk_size, 0);
+        let end = shards.end;
+        stream::unfold(Some((self, shards.start, None)), move |state| async move {
+            let (this, mut shardi, mut after) = state?;
+            while shardi < end {
+                let timeout = this.timeout;
+                let chunk = this.scan_chunk(shardi, after.take(), chunk_size);
+                let (rows, next) = match Self::with_timeout(timeout, chunk).await {
+                    Ok(chunk) => chunk,
+                    Err(e) => return Some((Err(e), None)),
+                };
+                after = next;
+                if after.is_none() {
+                    shardi += 1;
+                }
+                // the last chunk of a shard may well be empty
+                if !rows.is_empty() {
+                    return Some((Ok(rows), Some((this, shardi, after))));
+                }
+            }
+            None
+        })
+    }
+
+    async fn scan_chunk(
+        &mut self,
+        shardi: usize,
+        after: Option<Vec<DataType>>,
+        limit: usize,
+    ) -> Result<(Results, Option<Vec<DataType>>), ViewError> {
+        let shard = self
+            .shards
+            .get_mut(shardi)
+            .ok_or(ViewError::NoSuchShard(shardi))?;
+        future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+        let reply = shard
+            .call(Tagged::from(ReadQuery::Scan {
+                target: (self.node, shardi),
+                after,
+                limit,
+            }))
+            .await?;
+        match reply.v {
+            ReadReply::Chunk(Ok((rows, next))) => Ok((
+                Results::new(rows.into(), Arc::from(&self.columns[..])),
+                next,
+            )),
+            ReadReply::Chunk(Err(())) => Err(ViewError::NotYetAvailable),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get up to `limit` of the changes made to the given shard of this view since `since`.
+    ///
+    /// Each shard of a fully materialized view keeps the most recent changes made to it, so that a
+    /// client can follow the view without reading all of it over and over. Start with `None`,
+    /// which gives [`ViewChanges::Lost`], and read the shard in full with [`View::iter_shard`]
+    /// before you follow the changes from the cursor you were given. Since changes that were made
+    /// after the cursor may already show up in that read, apply them as a set: skip added rows
+    /// that are already there, and removed rows that are not. Partially materialized views do
+    /// not keep their changes, and give [`ViewError::NoChanges`]. Asking for a shard that the
+    /// view does not have gives [`ViewError::NoSuchShard`].
+    pub async fn changes(
+        &mut self,
+        shard: usize,
+        since: Option<ChangeCursor>,
+        limit: usize,
+    ) -> Result<ViewChanges, ViewError> {
+        let timeout = self.timeout;
+        Self::with_timeout(timeout, self.changes_inner(shard, since, limit)).await
+    }
+
+    async fn changes_inner(
+        &mut self,
+        shardi: usize,
+        since: Option<ChangeCursor>,
+        limit: usize,
+    ) -> Result<ViewChanges, ViewError> {
+        let shard = self
+            .shards
+            .get_mut(shardi)
+            .ok_or(ViewError::NoSuchShard(shardi))?;
+        future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+        let reply = shard
+            .call(Tagged::from(ReadQuery::Changes {
+                target: (self.node, shardi),
+                since,
+                limit,
+            }))
+            .await?;
+        match reply.v {
+            ReadReply::Changes(Some(changes)) => Ok(changes),
+            ReadReply::Changes(None) => Err(ViewError::NoChanges),
+            _ => unreachable!(),
+        }
+    }
+
     /// Retrieve the query results for the given parameter values.
     ///
+    /// All the keys are read in a single round trip to each shard, and the results are returned in
+    /// the same order as the keys. Keys that appear more than once are only sent once.
+    /// The rows of each key are sorted as given by [`View::order`].
+    ///
     /// The method will block if the results are not yet available only when `block` is `true`.
-    /// If `block` is false, misses will be returned as empty results. Any requested keys that have
-    /// missing state will be backfilled (asynchronously if `block` is `false`).
+    /// If `block` is false, misses will be returned as empty results for which
+    /// [`Results::is_miss`] is `true`. Any requested keys that have missing state will be
+    /// backfilled (asynchronously if `block` is `false`), with the replays for all of them
+    /// requested together.
     pub async fn multi_lookup(
+        &mut self,
+        mut keys: Vec<Vec<DataType>>,
+        block: bool,
+    ) -> Result<Vec<Results>, ViewError> {
+        for key in &keys {
+            self.check_key(key)?;
+        }
+        // the values of parameters that the view echoes are not part of its keys
+        let echoes: Vec<Vec<DataType>> = if self.echoed.is_empty() {
+            Vec::new()
+        } else {
+            let n = self.echoed.len();
+            keys.iter_mut().map(|k| k.drain(..n).collect()).collect()
+        };
+
+        let mut unique = Vec::with_capacity(keys.len());
+        let mut index = Vec::with_capacity(keys.len());
+        let mut seen = HashMap::with_capacity(keys.len());
+        for key in keys {
+            let i = *seen.entry(key.clone()).or_insert_with(|| {
+                unique.push(key);
+                unique.len() - 1
+            });
+            index.push(i);
+        }
+
+        let timeout = self.timeout;
+        let deduplicated = unique.len() != index.len();
+        let mut results =
+            Self::with_timeout(timeout, self.multi_lookup_inner(unique, block)).await?;
+        if let Merge::Combine { .. } = self.merge {
+            // the value of a key may be split over several of its rows
+            results = results
+                .into_iter()
+                .map(|rs| {
+                    if rs.len() > 1 {
+                        let rows = self.merge.apply(vec![rs.into()]);
+                        Results::new(rows, Arc::from(&self.columns[..]))
+                    } else {
+                        rs
+                    }
+                })
+                .collect();
+        }
+        if !self.order.is_empty() {
+            for rs in &mut results {
+                sort_rows(rs, &self.order);
+            }
+        }
+        if deduplicated {
+            results = index.into_iter().map(|i| results[i].clone()).collect();
+        }
+        if !echoes.is_empty() {
+            results = results
+                .into_iter()
+                .zip(echoes)
+                .map(|(rs, values)| self.echo(rs, &values))
+                .collect();
+        }
+        Ok(results)
+    }
+
+    /// Retrieve the query results for every integer key in the given range, in a single round
+    /// trip.
+    ///
+    /// The view must have a single integer key column. Since readers are hash-indexed, the range
+    /// is expanded into the individual keys it contains, and so may not span more than
+    /// `MAX_RANGE_KEYS` keys. The rows for all the keys are returned in key order, unless a
+    /// different [`Merge`] has been set with [`View::set_merge`]. See [`View::lookup`] for the
+    /// meaning of `block`.
+    pub async fn lookup_range(
+        &mut self,
+        range: RangeInclusive<i64>,
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        let (lo, hi) = range.into_inner();
+        let n = if hi < lo {
+            0
+        } else {
+            i128::from(hi) - i128::from(lo) + 1
+        };
+        if n > i128::from(MAX_RANGE_KEYS) {
+            let n = std::cmp::min(n, i128::from(std::u64::MAX)) as u64;
+            return Err(ViewError::RangeTooLarge(n));
+        }
+
+        let keys = (lo..=hi).map(|k| vec![DataType::from(k)]).collect();
+        let results = self.multi_lookup(keys, block).await?;
+        let rows = results
+            .into_iter()
+            .flat_map(|rs| -> Vec<Vec<DataType>> { rs.into() })
+            .collect();
+        let rows = self.merge.apply(vec![rows]);
+        Ok(Results::new(rows, Arc::from(&self.columns[..])))
+    }
+
+    async fn multi_lookup_inner(
         &mut self,
         keys: Vec<Vec<DataType>>,
         block: bool,
     ) -> Result<Vec<Results>, ViewError> {
-        future::poll_fn(|cx| self.poll_ready(cx)).await?;
-        self.call((keys, block)).await
+        let mut attempt = 0;
+        loop {
+            if attempt >= self.retry.max_retries {
+                future::poll_fn(|cx| self.poll_ready(cx)).await?;
+                return self.call((keys, block)).await;
+            }
+
+            let res = match future::poll_fn(|cx| self.poll_ready(cx)).await {
+                Ok(()) => self.call((keys.clone(), block)).await,
+                Err(e) => Err(e),
+            };
+            if let Err(ViewError::TransportError(e)) = res {
+                let backoff = self.retry.backoff(attempt);
+                tracing::debug!(error = %e, ?backoff, attempt, "view lookup failed; retrying");
+                tokio::time::delay_for(backoff).await;
+                attempt += 1;
+                self.reconnect();
+            } else {
+                return res;
+            }
+        }
+    }
+
+    /// Replace the connection to each shard's current reader with a fresh one.
+    ///
+    /// If the reader is replicated, the connections go to the next replica instead, so that reads
+    /// fail over to readers in other zones once the preferred replica can't be reached.
+    fn reconnect(&mut self) {
+        let mut rpcs = self.rpcs.lock().unwrap();
+        for (shardi, &addr) in self.shard_addrs.iter().enumerate() {
+            rpcs.remove(&(addr, shardi));
+        }
+        drop(rpcs);
+        if self.replicas.len() > 1 {
+            self.replica = (self.replica + 1) % self.replicas.len();
+            let replica = &self.replicas[self.replica];
+            tracing::debug!(
+                node = replica.node.index(),
+                "failing over to reader replica"
+            );
+            self.node = replica.node;
+            self.shard_addrs = replica.shards.clone();
+        }
+        for (shardi, &addr) in self.shard_addrs.iter().enumerate() {
+            self.shards[shardi] = view_rpc(&self.rpcs, addr, shardi);
+        }
     }
 
     /// Retrieve the query results for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
+    /// Otherwise, it returns right away, and if the key's state is missing, the returned results
+    /// are empty, [`Results::is_miss`] is `true`, and a replay to fill the state has been started.
+    /// Latency-sensitive callers can use this to serve a fallback instead of waiting.
     pub async fn lookup(&mut self, key: &[DataType], block: bool) -> Result<Results, ViewError> {
         // TODO: Optimized version of this function?
         let rs = self.multi_lookup(vec![Vec::from(key)], block).await?;
         Ok(rs.into_iter().next().unwrap())
     }
 
+    /// Retrieve a page of the query results for the given parameter value.
+    ///
+    /// The rows of the key are sorted as given by [`View::order`], and the `limit` rows that
+    /// follow the first `offset` are returned, just like for `LIMIT limit OFFSET offset`. Only
+    /// that page is sent back by the reader. This is what views of queries ending in `LIMIT ?
+    /// OFFSET ?` are read with, but works for any view.
+    ///
+    /// Views that only keep some rows of each key, as given by [`View::limit`], cannot serve
+    /// pages that reach past those rows, and fail with `ViewError::PageOutOfBounds` instead.
+    /// Like a blocking lookup, this waits for missing state to be filled in.
+    pub async fn lookup_page(
+        &mut self,
+        key: &[DataType],
+        offset: usize,
+        limit: usize,
+    ) -> Result<Results, ViewError> {
+        if let Some(max) = self.limit {
+            if offset.saturating_add(limit) > max {
+                return Err(ViewError::PageOutOfBounds(max));
+            }
+        }
+        let timeout = self.timeout;
+        Self::with_timeout(timeout, self.lookup_page_inner(key, offset, limit)).await
+    }
+
+    async fn lookup_page_inner(
+        &mut self,
+        key: &[DataType],
+        offset: usize,
+        limit: usize,
+    ) -> Result<Results, ViewError> {
+        self.check_key(key)?;
+        let (values, key) = key.split_at(self.echoed.len());
+        let shardi = if self.shards.len() == 1 {
+            0
+        } else {
+            assert_eq!(key.len(), 1);
+            crate::shard_by(&key[0], self.shards.len())
+        };
+
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            let shard = &mut self.shards[shardi];
+            future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+            let reply = shard
+                .call(Tagged::from(ReadQuery::Page {
+                    target: (self.node, shardi),
+                    key: key.to_vec(),
+                    order: self.order.clone(),
+                    offset,
+                    limit,
+                }))
+                .await?;
+            let (rows, missed) = batches(reply.v)?
+                .into_iter()
+                .next()
+                .expect("a page is read for a single key");
+            if !missed {
+                let rs = Results::new(rows.into(), Arc::from(&self.columns[..]));
+                return Ok(self.echo(rs, values));
+            }
+
+            // the key missed, and the replay it triggered has yet to finish
+            tokio::time::delay_for(backoff).await;
+            backoff = std::cmp::min(backoff * 2, Duration::from_millis(100));
+        }
+    }
+
     /// Retrieve the first query result for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
@@ -435,6 +1272,33 @@ impl View {
         let rs = self.multi_lookup(vec![Vec::from(key)], block).await?;
         Ok(rs.into_iter().next().unwrap().into_iter().next())
     }
+
+    /// Check that rows from this view can be mapped to a `T` with [`View::lookup_as`].
+    ///
+    /// This is the case if `T` is a struct, and each of its fields (after any renaming through
+    /// serde attributes) has the name of one of the view's columns. Call this right after
+    /// obtaining the `View` to catch mismatches between the type and the query early.
+    pub fn check_type<T: DeserializeOwned>(&self) -> Result<(), ViewError> {
+        crate::typed::check::<T>(&self.columns).map_err(ViewError::Mapping)
+    }
+
+    /// Retrieve the query results for the given parameter value, with each row mapped to a `T`.
+    ///
+    /// Each field of `T` is read from the column with the same name. Columns that `T` has no field
+    /// for are ignored. See [`View::lookup`] for the meaning of `block`.
+    pub async fn lookup_as<T: DeserializeOwned>(
+        &mut self,
+        key: &[DataType],
+        block: bool,
+    ) -> Result<Vec<T>, ViewError> {
+        self.check_type::<T>()?;
+        let rs = self.lookup(key, block).await?;
+        rs.as_ref()
+            .iter()
+            .map(|row| crate::typed::from_row(&self.columns, row))
+            .collect::<Result<_, _>>()
+            .map_err(ViewError::Mapping)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -530,3 +1394,40 @@ impl std::ops::DerefMut for ReadReplyBatch {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replica(node: usize, zones: &[Option<&str>]) -> ReaderReplica {
+        ReaderReplica {
+            node: NodeIndex::new(node),
+            shards: zones
+                .iter()
+                .map(|_| SocketAddr::from(([127, 0, 0, 1], 0)))
+                .collect(),
+            zones: zones.iter().map(|z| z.map(str::to_owned)).collect(),
+        }
+    }
+
+    fn order(replicas: Vec<ReaderReplica>, zone: Option<&str>) -> Vec<usize> {
+        by_zone(replicas, zone)
+            .into_iter()
+            .map(|r| r.node.index())
+            .collect()
+    }
+
+    #[test]
+    fn replicas_in_zone_go_first() {
+        let replicas = vec![
+            replica(1, &[Some("a"), Some("a")]),
+            replica(2, &[Some("b"), Some("a")]),
+            replica(3, &[Some("b"), Some("b")]),
+        ];
+        assert_eq!(order(replicas.clone(), Some("b")), vec![3, 2, 1]);
+        assert_eq!(order(replicas.clone(), Some("a")), vec![1, 2, 3]);
+        // without a zone, or in a zone with no replicas, the controller's order is kept
+        assert_eq!(order(replicas.clone(), None), vec![1, 2, 3]);
+        assert_eq!(order(replicas, Some("c")), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::data::DataType;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::mem;
+
+/// How the rows of a read that spans several shards of a view are put together.
+///
+/// Each shard of a sharded view only holds some of its rows, and reads that cover more than one
+/// shard, like [`View::lookup_range`](crate::View::lookup_range) and
+/// [`View::read_all`](crate::View::read_all), get a fragment from each of them. By default, those
+/// fragments are simply concatenated.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Merge {
+    /// Concatenate the rows of each shard.
+    Concat,
+    /// Sort all the rows by the given column.
+    ///
+    /// The sort is stable, so rows that compare equal keep the order they had in their shard.
+    SortBy {
+        /// The index of the column to sort by.
+        column: usize,
+        /// Whether the largest values come first.
+        descending: bool,
+    },
+    /// Combine rows that agree on the `group` columns into a single row.
+    ///
+    /// This is what you want for aggregations that are computed independently on each shard, such
+    /// as a `COUNT` that is not grouped by the column the view is sharded by. The combined row
+    /// takes the first row's value for every column that is neither in `group` nor in
+    /// `aggregates`. Groups are returned in the order they first appear.
+    Combine {
+        /// The indices of the columns that identify a group.
+        group: Vec<usize>,
+        /// The columns to combine, and how to combine them.
+        aggregates: Vec<(usize, Combine)>,
+    },
+}
+
+impl Default for Merge {
+    fn default() -> Self {
+        Merge::Concat
+    }
+}
+
+/// How the values of one column are combined by [`Merge::Combine`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Combine {
+    /// Add up the values. Use this for `COUNT` and `SUM` columns.
+    Sum,
+    /// Keep the smallest value.
+    Min,
+    /// Keep the largest value.
+    Max,
+}
+
+impl Combine {
+    fn apply(self, into: &mut DataType, v: DataType) {
+        match self {
+            Combine::Sum => *into = &*into + &v,
+            Combine::Min => {
+                if v < *into {
+                    *into = v;
+                }
+            }
+            Combine::Max => {
+                if v > *into {
+                    *into = v;
+                }
+            }
+        }
+    }
+}
+
+impl Merge {
+    /// Put together the rows read from each shard.
+    pub(crate) fn apply(&self, shards: Vec<Vec<Vec<DataType>>>) -> Vec<Vec<DataType>> {
+        let rows = shards.into_iter().flatten();
+        match *self {
+            Merge::Concat => rows.collect(),
+            Merge::SortBy { column, descending } => {
+                let mut rows: Vec<_> = rows.collect();
+                if descending {
+                    rows.sort_by(|a, b| b[column].cmp(&a[column]));
+                } else {
+                    rows.sort_by(|a, b| a[column].cmp(&b[column]));
+                }
+                rows
+            }
+            Merge::Combine {
+                ref group,
+                ref aggregates,
+            } => {
+                let mut combined: Vec<Vec<DataType>> = Vec::new();
+                let mut groups = HashMap::new();
+                for mut row in rows {
+                    let key: Vec<_> = group.iter().map(|&c| row[c].clone()).collect();
+                    match groups.get(&key) {
+                        Some(&i) => {
+                            let into = &mut combined[i];
+                            for &(c, op) in aggregates {
+                                op.apply(&mut into[c], mem::replace(&mut row[c], DataType::None));
+                            }
+                        }
+                        None => {
+                            groups.insert(key, combined.len());
+                            combined.push(row);
+                        }
+                    }
+                }
+                combined
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rs: &[&[i32]]) -> Vec<Vec<DataType>> {
+        rs.iter()
+            .map(|r| r.iter().map(|&v| v.into()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn it_concatenates() {
+        let merged = Merge::Concat.apply(vec![rows(&[&[2, 1]]), rows(&[&[1, 1]])]);
+        assert_eq!(merged, rows(&[&[2, 1], &[1, 1]]));
+    }
+
+    #[test]
+    fn it_sorts() {
+        let shards = vec![rows(&[&[1, 3], &[2, 5]]), rows(&[&[3, 4], &[4, 5]])];
+        let asc = Merge::SortBy {
+            column: 1,
+            descending: false,
+        };
+        assert_eq!(
+            asc.apply(shards.clone()),
+            rows(&[&[1, 3], &[3, 4], &[2, 5], &[4, 5]])
+        );
+        let desc = Merge::SortBy {
+            column: 1,
+            descending: true,
+        };
+        assert_eq!(
+            desc.apply(shards),
+            rows(&[&[2, 5], &[4, 5], &[3, 4], &[1, 3]])
+        );
+    }
+
+    #[test]
+    fn it_combines() {
+        // group, count, min, max, other
+        let shards = vec![
+            rows(&[&[1, 2, 5, 5, 0], &[2, 1, 3, 3, 0]]),
+            rows(&[&[1, 3, 4, 9, 1]]),
+        ];
+        let merge = Merge::Combine {
+            group: vec![0],
+            aggregates: vec![(1, Combine::Sum), (2, Combine::Min), (3, Combine::Max)],
+        };
+        assert_eq!(
+            merge.apply(shards),
+            rows(&[&[1, 5, 4, 9, 0], &[2, 1, 3, 3, 0]])
+        );
+    }
+}
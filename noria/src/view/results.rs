@@ -4,10 +4,11 @@ use std::ops::Deref;
 use std::sync::Arc;
 
 /// A result set from a Noria query.
-#[derive(PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Results {
     results: Vec<Vec<DataType>>,
     columns: Arc<[String]>,
+    missed: bool,
 }
 
 impl Results {
@@ -15,7 +16,25 @@ impl Results {
     // https://github.com/rust-lang/rust/issues/69785
     #[doc(hidden)]
     pub fn new(results: Vec<Vec<DataType>>, columns: Arc<[String]>) -> Self {
-        Self { results, columns }
+        Self {
+            results,
+            columns,
+            missed: false,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn into_miss(mut self) -> Self {
+        self.missed = true;
+        self
+    }
+
+    /// Whether the key these results are for was missing from the view's state.
+    ///
+    /// This can only be `true` for non-blocking lookups, and the results are then empty. A replay
+    /// to fill in the missing state has been triggered, so a later lookup will likely succeed.
+    pub fn is_miss(&self) -> bool {
+        self.missed
     }
 
     /// Iterate over references to the returned rows.
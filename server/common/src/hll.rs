@@ -0,0 +1,112 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The number of hash bits used to pick a register.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch, which estimates how many distinct values it has seen.
+///
+/// With 4096 registers, estimates are typically within 2% of the true count. Values are hashed
+/// the same way in every process, so sketches built on different shards or machines can be
+/// merged.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// Make an empty sketch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `value` has been seen.
+    pub fn insert<T: Hash + ?Sized>(&mut self, value: &T) {
+        let mut h = DefaultHasher::new();
+        value.hash(&mut h);
+        let hash = h.finish();
+
+        let register = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION).leading_zeros() + 1).min(64 - PRECISION + 1) as u8;
+        if rank > self.registers[register] {
+            self.registers[register] = rank;
+        }
+    }
+
+    /// Add the values seen by `other` to this sketch.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (r, &o) in self.registers.iter_mut().zip(&other.registers) {
+            if o > *r {
+                *r = o;
+            }
+        }
+    }
+
+    /// Estimate the number of distinct values seen.
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros != 0 {
+            // the raw estimate is biased for small counts, where linear counting does better
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(estimate: u64, actual: u64) {
+        let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+        assert!(error < 0.05, "estimated {} for {}", estimate, actual);
+    }
+
+    #[test]
+    fn it_estimates_distinct_values() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.estimate(), 0);
+        for i in 0..100_000u64 {
+            // duplicates do not count
+            hll.insert(&(i % 50_000));
+        }
+        assert_close(hll.estimate(), 50_000);
+
+        let mut small = HyperLogLog::new();
+        for i in 0..100u64 {
+            small.insert(&i);
+        }
+        assert_close(small.estimate(), 100);
+    }
+
+    #[test]
+    fn it_merges() {
+        let mut a = HyperLogLog::new();
+        let mut b = HyperLogLog::new();
+        for i in 0..20_000u64 {
+            a.insert(&i);
+            b.insert(&(i + 10_000));
+        }
+        a.merge(&b);
+        assert_close(a.estimate(), 30_000);
+    }
+}
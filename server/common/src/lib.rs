@@ -1,10 +1,12 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod hll;
 mod local;
 mod map;
 mod records;
 
+pub use self::hll::HyperLogLog;
 pub use self::local::*;
 pub use self::map::*;
 pub use self::records::*;
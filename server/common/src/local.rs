@@ -136,14 +136,35 @@ impl std::fmt::Display for Tag {
     }
 }
 
+/// A key to look up in a state index.
+///
+/// The key borrows its values from wherever they already live (usually the record being
+/// processed), so that a lookup does not have to allocate or clone anything.
 #[derive(Clone, Debug, Serialize)]
 pub enum KeyType<'a> {
     Single(&'a DataType),
-    Double((DataType, DataType)),
-    Tri((DataType, DataType, DataType)),
-    Quad((DataType, DataType, DataType, DataType)),
-    Quin((DataType, DataType, DataType, DataType, DataType)),
-    Sex((DataType, DataType, DataType, DataType, DataType, DataType)),
+    Double((&'a DataType, &'a DataType)),
+    Tri((&'a DataType, &'a DataType, &'a DataType)),
+    Quad((&'a DataType, &'a DataType, &'a DataType, &'a DataType)),
+    Quin(
+        (
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+        ),
+    ),
+    Sex(
+        (
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+            &'a DataType,
+        ),
+    ),
 }
 
 impl<'a> KeyType<'a> {
@@ -158,29 +179,11 @@ impl<'a> KeyType<'a> {
         match len {
             0 => unreachable!(),
             1 => KeyType::Single(more()),
-            2 => KeyType::Double((more().clone(), more().clone())),
-            3 => KeyType::Tri((more().clone(), more().clone(), more().clone())),
-            4 => KeyType::Quad((
-                more().clone(),
-                more().clone(),
-                more().clone(),
-                more().clone(),
-            )),
-            5 => KeyType::Quin((
-                more().clone(),
-                more().clone(),
-                more().clone(),
-                more().clone(),
-                more().clone(),
-            )),
-            6 => KeyType::Sex((
-                more().clone(),
-                more().clone(),
-                more().clone(),
-                more().clone(),
-                more().clone(),
-                more().clone(),
-            )),
+            2 => KeyType::Double((more(), more())),
+            3 => KeyType::Tri((more(), more(), more())),
+            4 => KeyType::Quad((more(), more(), more(), more())),
+            5 => KeyType::Quin((more(), more(), more(), more(), more())),
+            6 => KeyType::Sex((more(), more(), more(), more(), more(), more())),
             _ => unimplemented!(),
         }
     }
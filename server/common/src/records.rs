@@ -1,6 +1,7 @@
 use noria::DataType;
 use std::borrow::Borrow;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 /// A record is a single positive or negative data record with an associated time stamp.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
@@ -68,7 +69,7 @@ impl From<(Vec<DataType>, bool)> for Record {
 
 impl Into<Vec<Record>> for Records {
     fn into(self) -> Vec<Record> {
-        self.0
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
     }
 }
 
@@ -78,7 +79,7 @@ impl FromIterator<Record> for Records {
     where
         I: IntoIterator<Item = Record>,
     {
-        Records(iter.into_iter().collect())
+        Records(Arc::new(iter.into_iter().collect()))
     }
 }
 impl FromIterator<Vec<DataType>> for Records {
@@ -86,7 +87,7 @@ impl FromIterator<Vec<DataType>> for Records {
     where
         I: IntoIterator<Item = Vec<DataType>>,
     {
-        Records(iter.into_iter().map(Record::Positive).collect())
+        Records(Arc::new(iter.into_iter().map(Record::Positive).collect()))
     }
 }
 
@@ -94,7 +95,8 @@ impl IntoIterator for Records {
     type Item = Record;
     type IntoIter = ::std::vec::IntoIter<Record>;
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        let records: Vec<Record> = self.into();
+        records.into_iter()
     }
 }
 impl<'a> IntoIterator for &'a Records {
@@ -105,8 +107,13 @@ impl<'a> IntoIterator for &'a Records {
     }
 }
 
+/// A batch of records.
+///
+/// The records are shared between clones, so that an update that fans out to many children does
+/// not have to be copied for each of them. A clone only copies the records once it is modified or
+/// taken apart while another clone is still around.
 #[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct Records(Vec<Record>);
+pub struct Records(Arc<Vec<Record>>);
 
 impl Records {
     pub fn has<Q: ?Sized>(&self, q: &Q, positive: bool) -> bool
@@ -114,7 +121,7 @@ impl Records {
         Vec<DataType>: Borrow<Q>,
         Q: Eq,
     {
-        self.iter().any(|r| match r {
+        self.0.iter().any(|r| match r {
             Record::Positive(ref r) if positive => r.borrow() == q,
             Record::Negative(ref r) if !positive => r.borrow() == q,
             _ => false,
@@ -147,30 +154,47 @@ impl Deref for Records {
 
 impl DerefMut for Records {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        Arc::make_mut(&mut self.0)
     }
 }
 
 impl Into<Records> for Record {
     fn into(self) -> Records {
-        Records(vec![self])
+        Records(Arc::new(vec![self]))
     }
 }
 
 impl Into<Records> for Vec<Record> {
     fn into(self) -> Records {
-        Records(self)
+        Records(Arc::new(self))
     }
 }
 
 impl Into<Records> for Vec<Vec<DataType>> {
     fn into(self) -> Records {
-        Records(self.into_iter().map(Into::into).collect())
+        Records(Arc::new(self.into_iter().map(Into::into).collect()))
     }
 }
 
 impl Into<Records> for Vec<(Vec<DataType>, bool)> {
     fn into(self) -> Records {
-        Records(self.into_iter().map(Into::into).collect())
+        Records(Arc::new(self.into_iter().map(Into::into).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_records_until_modified() {
+        let a: Records = vec![vec![DataType::from(1)]].into();
+        let mut b = a.clone();
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+
+        b.push(Record::Negative(vec![2.into()]));
+        assert!(!Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a.len(), 1);
+        assert_eq!(b.len(), 2);
     }
 }
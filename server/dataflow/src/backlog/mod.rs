@@ -1,13 +1,82 @@
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
+use noria::{ChangeCursor, ViewChanges};
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time;
+
+/// How long a replay requested through a reader is assumed to still be underway. Misses on a key
+/// whose replay was requested more recently than this do not request another one.
+const UPQUERY_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// A replay that has been requested through a reader, but that has yet to fill its key.
+struct Upquery {
+    sent: time::Instant,
+    /// reads to wake up once the key has been filled
+    waiters: Vec<Waker>,
+}
+
+/// The replays that are underway for a partial reader, shared between its read and write handles.
+type InFlight = Arc<Mutex<HashMap<Vec<DataType>, Upquery>>>;
+
+/// The keys of a straight-through reader that have been read since they were last evicted.
+type Consumed = Arc<Mutex<HashSet<Vec<DataType>>>>;
+
+/// The keys of a reader, in order, as of when the last scan of it started.
+type ScanKeys = Arc<Mutex<Option<Arc<Vec<Vec<DataType>>>>>>;
+
+/// How many of the records most recently swapped into a fully materialized reader are kept for
+/// clients that follow its changes.
+const CHANGES_KEPT: usize = 1 << 16;
+
+/// The records that have been swapped into a fully materialized reader, numbered in the order
+/// they became visible to reads. Only the most recent `CHANGES_KEPT` of them are kept.
+struct ChangeLog {
+    /// tells this log apart from that of any reader that comes before or after this one
+    id: u64,
+    /// the number of the first record in `records`
+    first: u64,
+    records: VecDeque<Record>,
+}
+
+type Changes = Arc<Mutex<ChangeLog>>;
+
+/// When a reader was last read from, in milliseconds since it was created.
+#[derive(Clone)]
+struct LastRead {
+    created: time::Instant,
+    at: Arc<AtomicU64>,
+}
+
+impl LastRead {
+    fn new() -> Self {
+        LastRead {
+            created: time::Instant::now(),
+            at: Arc::default(),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    fn touch(&self) {
+        self.at.store(self.now(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.at.load(Ordering::Relaxed)
+    }
+}
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
-    new_inner(cols, key, None)
+    new_inner(cols, key, None, false)
 }
 
 /// Allocate a new partially materialized end-user facing result table.
@@ -21,13 +90,30 @@ pub(crate) fn new_partial<F>(
 where
     F: Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + 'static + Send + Sync,
 {
-    new_inner(cols, key, Some(Arc::new(trigger)))
+    new_inner(cols, key, Some(Arc::new(trigger)), false)
+}
+
+/// Allocate a new partially materialized result table that does not hold on to keys once they
+/// have been read.
+///
+/// Every read of a key that is not already held triggers a replay from upstream state, and the
+/// key is evicted again by the next `WriteHandle::evict_consumed` after it has been read.
+pub(crate) fn new_straight_through<F>(
+    cols: usize,
+    key: &[usize],
+    trigger: F,
+) -> (SingleReadHandle, WriteHandle)
+where
+    F: Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + 'static + Send + Sync,
+{
+    new_inner(cols, key, Some(Arc::new(trigger)), true)
 }
 
 fn new_inner(
     cols: usize,
     key: &[usize],
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    straight_through: bool,
 ) -> (SingleReadHandle, WriteHandle) {
     let contiguous = {
         let mut contiguous = true;
@@ -63,17 +149,48 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    let in_flight = InFlight::default();
+    let last_read = LastRead::new();
+    let consumed = if straight_through {
+        Some(Consumed::default())
+    } else {
+        None
+    };
+    // the contents of partial readers also change as keys are filled and evicted, so only full
+    // readers keep track of their changes
+    let changes = if trigger.is_none() {
+        Some(Arc::new(Mutex::new(ChangeLog {
+            id: rand::random(),
+            first: 0,
+            records: VecDeque::new(),
+        })))
+    } else {
+        None
+    };
     let w = WriteHandle {
         partial: trigger.is_some(),
+        in_flight: Arc::clone(&in_flight),
+        consumed: consumed.clone(),
+        last_read: last_read.clone(),
+        hibernated_at: None,
+        filled: HashSet::new(),
         handle: w,
         key: Vec::from(key),
         cols,
         contiguous,
         mem_size: 0,
+        pending: Vec::new(),
+        changes: changes.clone(),
+        unpublished: Vec::new(),
     };
     let r = SingleReadHandle {
         handle: r,
         trigger,
+        in_flight,
+        consumed,
+        last_read,
+        scan_keys: ScanKeys::default(),
+        changes,
         key: Vec::from(key),
     };
 
@@ -104,13 +221,36 @@ fn key_to_double(k: Key) -> Cow<(DataType, DataType)> {
     }
 }
 
+/// The memory charged for a key in partial state, on top of the memory used by its rows.
+///
+/// This is what makes keys that are known to have no rows show up in the state size, so that they
+/// are evicted like any other key once the state grows too large.
+fn key_size(key: &[DataType]) -> usize {
+    key.iter().map(|k| k.deep_size_of() as usize).sum()
+}
+
 pub(crate) struct WriteHandle {
     handle: multiw::Handle,
     partial: bool,
+    in_flight: InFlight,
+    /// keys that have been read since they were filled, if keys are evicted once they are read
+    consumed: Option<Consumed>,
+    last_read: LastRead,
+    /// when all keys were last evicted because the table was not read for a while
+    hibernated_at: Option<u64>,
+    /// keys that have been filled since the last swap
+    filled: HashSet<Vec<DataType>>,
     cols: usize,
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+    /// records added since the last swap, which have yet to be handed to the map
+    pending: Vec<Record>,
+    /// the changes that have been made visible to reads, if they are kept
+    changes: Option<Changes>,
+    /// records handed to the map since the last swap, which go into the change log once they are
+    /// visible
+    unpublished: Vec<Record>,
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -125,11 +265,22 @@ pub(crate) struct WriteHandleEntry<'a> {
 
 impl<'a> MutWriteHandleEntry<'a> {
     pub(crate) fn mark_filled(self) {
+        self.handle.flush();
+        assert!(
+            !self.handle.filled.contains(&*self.key),
+            "attempted to fill already-filled key"
+        );
         if let Some((None, _)) = self
             .handle
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
         {
+            // the key may well have no rows, in which case this entry remembers that it is empty
+            // so that further reads of it do not trigger replays
+            self.handle.mem_size += key_size(&self.key);
+            // the replay stays in flight until the key is swapped in, so that reads that miss on
+            // it in the meantime wait for it rather than request it again
+            self.handle.filled.insert(self.key.to_vec());
             self.handle.handle.clear(self.key)
         } else {
             unreachable!("attempted to fill already-filled key");
@@ -137,20 +288,34 @@ impl<'a> MutWriteHandleEntry<'a> {
     }
 
     pub(crate) fn mark_hole(self) {
+        self.handle.flush();
         let size = self
             .handle
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| {
-                rs.iter().map(SizeOf::deep_size_of).sum()
+                rs.iter().map(SizeOf::deep_size_of).sum::<u64>() + key_size(&self.key) as u64
             })
             .map(|r| r.0.unwrap_or(0))
             .unwrap_or(0);
         self.handle.mem_size = self.handle.mem_size.checked_sub(size as usize).unwrap();
+        if self.handle.filled.remove(&*self.key) {
+            // the fill never became visible, so reads waiting for it have to ask again
+            if let Some(upquery) = self.handle.in_flight.lock().unwrap().remove(&*self.key) {
+                for waiter in upquery.waiters {
+                    waiter.wake();
+                }
+            }
+        }
         self.handle.handle.empty(self.key)
     }
 }
 
 impl<'a> WriteHandleEntry<'a> {
+    /// Whether the key has been filled since the last swap, and so is not yet visible to reads.
+    pub(crate) fn filled_since_swap(&self) -> bool {
+        self.handle.filled.contains(&*self.key)
+    }
+
     pub(crate) fn try_find_and<F, T>(self, mut then: F) -> Result<(Option<T>, i64), ()>
     where
         F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
@@ -229,17 +394,61 @@ impl WriteHandle {
     }
 
     pub(crate) fn swap(&mut self) {
+        self.flush();
         self.handle.refresh();
+        if let Some(ref changes) = self.changes {
+            if !self.unpublished.is_empty() {
+                let mut log = changes.lock().unwrap();
+                log.records.extend(self.unpublished.drain(..));
+                let excess = log.records.len().saturating_sub(CHANGES_KEPT);
+                log.records.drain(..excess);
+                log.first += excess as u64;
+            }
+        }
+        // the keys are now visible to readers
+        if !self.filled.is_empty() {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            for key in self.filled.drain() {
+                if let Some(upquery) = in_flight.remove(&key) {
+                    for waiter in upquery.waiters {
+                        waiter.wake();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether any reads are blocked waiting for a key that has been filled since the last swap.
+    pub(crate) fn has_waiters(&self) -> bool {
+        if self.filled.is_empty() {
+            return false;
+        }
+        let in_flight = self.in_flight.lock().unwrap();
+        self.filled.iter().any(|key| {
+            in_flight
+                .get(key)
+                .map(|upquery| !upquery.waiters.is_empty())
+                .unwrap_or(false)
+        })
     }
 
     /// Add a new set of records to the backlog.
     ///
-    /// These will be made visible to readers after the next call to `swap()`.
+    /// These will be made visible to readers after the next call to `swap()`. Until then, they are
+    /// held back, so that a row that is added and removed again before the swap never reaches the
+    /// map at all.
     pub(crate) fn add<I>(&mut self, rs: I)
     where
         I: IntoIterator<Item = Record>,
     {
-        let mem_delta = self.handle.add(&self.key[..], self.cols, rs);
+        let mut mem_delta = 0isize;
+        for r in rs {
+            match r {
+                Record::Positive(ref r) => mem_delta += r.deep_size_of() as isize,
+                Record::Negative(ref r) => mem_delta -= r.deep_size_of() as isize,
+            }
+            self.pending.push(r);
+        }
         if mem_delta > 0 {
             self.mem_size += mem_delta as usize;
         } else if mem_delta < 0 {
@@ -250,21 +459,77 @@ impl WriteHandle {
         }
     }
 
+    /// Hand the records added since the last swap to the map, leaving out those that cancel out.
+    ///
+    /// This must happen before any other operation on the map, so that they are applied in the
+    /// order they were issued in.
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let rs = compact(std::mem::replace(&mut self.pending, Vec::new()));
+        if self.changes.is_some() {
+            self.unpublished.extend(rs.iter().cloned());
+        }
+        // the memory was accounted for when the records were added
+        self.handle.add(&self.key[..], self.cols, rs);
+    }
+
     pub(crate) fn is_partial(&self) -> bool {
         self.partial
     }
 
+    /// Evict the keys that have been read since they were filled, if this table does not hold on
+    /// to keys once they have been read. Returns the number of bytes that are freed.
+    ///
+    /// The evictions become visible to readers with the next call to `swap()`.
+    pub(crate) fn evict_consumed(&mut self) -> u64 {
+        let keys: Vec<_> = match self.consumed {
+            Some(ref consumed) => consumed.lock().unwrap().drain().collect(),
+            None => return 0,
+        };
+        let before = self.mem_size;
+        for key in keys {
+            self.mut_with_key(key).mark_hole();
+        }
+        (before - self.mem_size) as u64
+    }
+
+    /// How long it has been since the table was last read from, or since it was created if it
+    /// has never been read from.
+    pub(crate) fn idle_for(&self) -> time::Duration {
+        time::Duration::from_millis(self.last_read.now() - self.last_read.get())
+    }
+
+    /// Record that all keys are being evicted because the table has not been read for a while.
+    pub(crate) fn mark_hibernated(&mut self) {
+        self.hibernated_at = Some(self.last_read.now());
+    }
+
+    /// Whether all keys were evicted because the table was not read for a while, and it has not
+    /// been read from since.
+    pub(crate) fn is_hibernating(&self) -> bool {
+        self.hibernated_at
+            .map(|at| self.last_read.get() <= at)
+            .unwrap_or(false)
+    }
+
     /// Evict `count` randomly selected keys from state and return them along with the number of
     /// bytes that will be freed once the underlying `evmap` applies the operation.
     pub(crate) fn evict_random_keys(&mut self, rng: &mut ThreadRng, mut n: usize) -> u64 {
+        self.flush();
         let mut bytes_to_be_freed = 0;
         if self.mem_size > 0 {
             if self.handle.is_empty() {
                 unreachable!("mem size is {}, but map is empty", self.mem_size);
             }
 
-            self.handle.empty_random_for_each(rng, n, |vs| {
-                let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
+            let partial = self.partial;
+            self.handle.empty_random_for_each(rng, n, |k, vs| {
+                let mut size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
+                if partial {
+                    size += key_size(k) as u64;
+                }
                 bytes_to_be_freed += size;
                 n -= 1;
             });
@@ -278,6 +543,40 @@ impl WriteHandle {
     }
 }
 
+/// Drop every positive record that is later followed by a negative record for the same row,
+/// along with that negative record.
+///
+/// Applying what is left has the same effect as applying all of `rs`. A negative record that is
+/// followed by a positive one is kept, since the removal may well be of a row that was added
+/// before `rs`.
+fn compact(rs: Vec<Record>) -> Vec<Record> {
+    let mut dropped = vec![false; rs.len()];
+    {
+        // the positive records for each row that have yet to be cancelled out
+        let mut added: HashMap<&[DataType], Vec<usize>> = HashMap::new();
+        for (i, r) in rs.iter().enumerate() {
+            match *r {
+                Record::Positive(ref row) => added.entry(&row[..]).or_default().push(i),
+                Record::Negative(ref row) => {
+                    if let Some(j) = added.get_mut(&row[..]).and_then(Vec::pop) {
+                        dropped[j] = true;
+                        dropped[i] = true;
+                    }
+                }
+            }
+        }
+    }
+
+    if !dropped.contains(&true) {
+        return rs;
+    }
+    rs.into_iter()
+        .zip(dropped)
+        .filter(|&(_, dropped)| !dropped)
+        .map(|(r, _)| r)
+        .collect()
+}
+
 impl SizeOf for WriteHandle {
     fn size_of(&self) -> u64 {
         use std::mem::size_of;
@@ -299,6 +598,11 @@ impl SizeOf for WriteHandle {
 pub struct SingleReadHandle {
     handle: multir::Handle,
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    in_flight: InFlight,
+    consumed: Option<Consumed>,
+    last_read: LastRead,
+    scan_keys: ScanKeys,
+    changes: Option<Changes>,
     key: Vec<usize>,
 }
 
@@ -307,6 +611,7 @@ impl std::fmt::Debug for SingleReadHandle {
         f.debug_struct("SingleReadHandle")
             .field("handle", &self.handle)
             .field("has_trigger", &self.trigger.is_some())
+            .field("straight_through", &self.consumed.is_some())
             .field("key", &self.key)
             .finish()
     }
@@ -314,6 +619,9 @@ impl std::fmt::Debug for SingleReadHandle {
 
 impl SingleReadHandle {
     /// Trigger a replay of a missing key from a partially materialized view.
+    ///
+    /// Keys that a replay has recently been requested for are skipped, so that concurrent misses
+    /// on the same key only cause a single replay.
     pub fn trigger<'a, I>(&self, keys: I) -> bool
     where
         I: Iterator<Item = &'a [DataType]>,
@@ -323,12 +631,46 @@ impl SingleReadHandle {
             "tried to trigger a replay for a fully materialized view"
         );
 
-        let mut it = keys;
+        let now = time::Instant::now();
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let mut it = keys.filter(|&key| match in_flight.get_mut(key) {
+            Some(upquery) if now.duration_since(upquery.sent) < UPQUERY_TIMEOUT => false,
+            Some(upquery) => {
+                // the replay is taking suspiciously long, so ask again
+                upquery.sent = now;
+                true
+            }
+            None => {
+                in_flight.insert(
+                    Vec::from(key),
+                    Upquery {
+                        sent: now,
+                        waiters: Vec::new(),
+                    },
+                );
+                true
+            }
+        });
 
         // trigger a replay to populate
         (*self.trigger.as_ref().unwrap())(&mut it)
     }
 
+    /// Wake `waker` once any of the given keys is filled by a replay that is underway.
+    pub fn notify_on_fill<'a, I>(&self, keys: I, waker: &Waker)
+    where
+        I: Iterator<Item = &'a [DataType]>,
+    {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        for key in keys {
+            if let Some(upquery) = in_flight.get_mut(key) {
+                if !upquery.waiters.iter().any(|w| w.will_wake(waker)) {
+                    upquery.waiters.push(waker.clone());
+                }
+            }
+        }
+    }
+
     /// Find all entries that matched the given conditions.
     ///
     /// Returned records are passed to `then` before being returned.
@@ -341,6 +683,7 @@ impl SingleReadHandle {
     where
         F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
     {
+        self.last_read.touch();
         self.handle
             .meta_get_and(key, &mut then)
             .ok_or(())
@@ -348,10 +691,92 @@ impl SingleReadHandle {
                 if records.is_none() && self.trigger.is_none() {
                     records = Some(then(&evmap::Values::default()));
                 }
+                if let (Some(_), Some(ref consumed)) = (&records, &self.consumed) {
+                    consumed.lock().unwrap().insert(Vec::from(key));
+                }
                 (records, meta)
             })
     }
 
+    /// Pass the rows of up to `limit` keys, in key order, to `then`, starting with the first key
+    /// after `after`, or with the first key if `after` is `None`.
+    ///
+    /// Returns what `then` returned, and the last key that was visited if there are keys after
+    /// it. Pass that key as `after` to get the next chunk. Each key is visited at most once per
+    /// scan, and every key that is present throughout the scan is visited. The keys are taken
+    /// from the state as of when the scan started (or restarted, if another scan of the same
+    /// reader has finished in the meantime), while the rows of each chunk are read from the
+    /// current state. For partially materialized state, only keys that are filled are visited.
+    pub fn scan_and<F, T>(
+        &self,
+        after: Option<&[DataType]>,
+        limit: usize,
+        then: F,
+    ) -> Result<(T, Option<Vec<DataType>>), ()>
+    where
+        F: FnOnce(&mut dyn Iterator<Item = &Vec<DataType>>) -> T,
+    {
+        self.last_read.touch();
+        let keys = {
+            let mut scan_keys = self.scan_keys.lock().unwrap();
+            if after.is_none() || scan_keys.is_none() {
+                *scan_keys = Some(Arc::new(self.handle.sorted_keys().ok_or(())?));
+            }
+            Arc::clone(scan_keys.as_ref().unwrap())
+        };
+
+        let start = match after {
+            None => 0,
+            Some(after) => match keys.binary_search_by(|k| k[..].cmp(after)) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            },
+        };
+        let end = std::cmp::min(start + limit, keys.len());
+        let chunk = &keys[start..end];
+        let t = self.handle.rows_of_and(chunk, then).ok_or(())?;
+        if end < keys.len() {
+            Ok((t, chunk.last().cloned()))
+        } else {
+            // the scan is over, so there's no need to hold on to the keys any longer
+            *self.scan_keys.lock().unwrap() = None;
+            Ok((t, None))
+        }
+    }
+
+    /// The records that were swapped in after `since`, up to `limit` of them, in the order they
+    /// became visible.
+    ///
+    /// If `since` is `None`, or its changes are no longer kept, the changes are lost, and the
+    /// client is told where to follow them from instead. Returns `None` for partially
+    /// materialized readers, which do not keep their changes.
+    pub fn changes(&self, since: Option<ChangeCursor>, limit: usize) -> Option<ViewChanges> {
+        let log = self.changes.as_ref()?.lock().unwrap();
+        let end = log.first + log.records.len() as u64;
+        Some(match since {
+            Some(since) if since.log == log.id && since.seq >= log.first && since.seq <= end => {
+                let rows: Vec<_> = log
+                    .records
+                    .iter()
+                    .skip((since.seq - log.first) as usize)
+                    .take(limit)
+                    .map(|r| (r.rec().to_vec(), r.is_positive()))
+                    .collect();
+                let next = ChangeCursor {
+                    log: log.id,
+                    seq: since.seq + rows.len() as u64,
+                };
+                ViewChanges::Since { rows, next }
+            }
+            _ => ViewChanges::Lost {
+                next: ChangeCursor {
+                    log: log.id,
+                    seq: end,
+                },
+            },
+        })
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
@@ -397,6 +822,204 @@ mod tests {
             .unwrap());
     }
 
+    #[test]
+    fn compacts_pending_updates() {
+        let row = |v: i32| vec![1.into(), v.into()];
+        let rs = compact(vec![
+            Record::Positive(row(1)),
+            Record::Negative(row(1)),
+            Record::Positive(row(2)),
+            Record::Negative(row(2)),
+            Record::Negative(row(3)),
+            Record::Positive(row(3)),
+            Record::Positive(row(4)),
+        ]);
+        // an update that removes a row before adding it back may remove an older copy of the row
+        assert_eq!(
+            rs,
+            vec![
+                Record::Negative(row(3)),
+                Record::Positive(row(3)),
+                Record::Positive(row(4))
+            ]
+        );
+
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        w.add(vec![Record::Positive(row(1))]);
+        w.add(vec![Record::Negative(row(1)), Record::Positive(row(2))]);
+        w.add(vec![Record::Negative(row(2)), Record::Positive(row(3))]);
+        assert_eq!(w.pending.len(), 5);
+        w.swap();
+        assert!(w.pending.is_empty());
+        assert_eq!(
+            r.try_find_and(&[1.into()], |rs| rs.iter().cloned().collect::<Vec<_>>())
+                .unwrap()
+                .0,
+            Some(vec![row(3)])
+        );
+        assert_eq!(w.deep_size_of(), row(3).deep_size_of());
+    }
+
+    #[test]
+    fn scan_visits_every_key() {
+        let (r, mut w) = new(2, &[0]);
+        assert!(r.scan_and(None, 10, |rows| rows.count()).is_err());
+
+        w.add((0..10).map(|i: i32| Record::Positive(vec![i.into(), (i * 2).into()])));
+        w.swap();
+
+        let mut seen = Vec::new();
+        let mut after = None;
+        loop {
+            let (rows, next) = r
+                .scan_and(after.as_deref(), 3, |rows| {
+                    rows.cloned().collect::<Vec<_>>()
+                })
+                .unwrap();
+            seen.extend(rows);
+            after = next;
+
+            // keys that come and go while we scan don't throw the scan off
+            if after.is_some() {
+                let i: i32 = seen.len() as i32;
+                w.add(vec![
+                    Record::Negative(vec![i.into(), (i * 2).into()]),
+                    Record::Positive(vec![(100 + i).into(), 0.into()]),
+                    Record::Positive(vec![i.into(), (i * 2).into()]),
+                ]);
+                w.swap();
+            } else {
+                break;
+            }
+        }
+        // keys are visited in order
+        assert_eq!(
+            seen,
+            (0..10)
+                .map(|i: i32| vec![i.into(), (i * 2).into()])
+                .collect::<Vec<Vec<DataType>>>()
+        );
+    }
+
+    #[test]
+    fn tracks_idle_time() {
+        let (r, mut w) = new_partial(2, &[0], |_| true);
+        w.swap();
+        std::thread::sleep(time::Duration::from_millis(20));
+        assert!(w.idle_for() >= time::Duration::from_millis(20));
+
+        // reads reset the idle time, whether or not they hit
+        r.try_find_and(&[1.into()], |rs| rs.len()).unwrap();
+        assert!(w.idle_for() < time::Duration::from_millis(20));
+
+        // hibernation lasts until the next read
+        std::thread::sleep(time::Duration::from_millis(5));
+        w.mark_hibernated();
+        assert!(w.is_hibernating());
+        std::thread::sleep(time::Duration::from_millis(5));
+        r.try_find_and(&[1.into()], |rs| rs.len()).unwrap();
+        assert!(!w.is_hibernating());
+    }
+
+    #[test]
+    fn remembers_empty_keys() {
+        let (r, mut w) = new_partial(2, &[0], |_| true);
+        w.swap();
+        let k: Vec<DataType> = vec![1.into()];
+
+        // a hole before the key is replayed
+        assert_eq!(r.try_find_and(&k, |rs| rs.len()).unwrap().0, None);
+
+        // a replay that found no rows leaves the key filled, but empty
+        w.mut_with_key(&k[..]).mark_filled();
+        w.swap();
+        assert_eq!(r.try_find_and(&k, |rs| rs.len()).unwrap().0, Some(0));
+
+        // empty keys still count towards the state size, so that they can be evicted
+        assert!(w.deep_size_of() > 0);
+        let freed = w.evict_random_keys(&mut rand::thread_rng(), 1);
+        w.swap();
+        assert!(freed > 0);
+        assert_eq!(w.deep_size_of(), 0);
+        assert_eq!(r.try_find_and(&k, |rs| rs.len()).unwrap().0, None);
+    }
+
+    #[test]
+    fn straight_through_evicts_read_keys() {
+        let (r, mut w) = new_straight_through(2, &[0], |_| true);
+        w.swap();
+        let k: Vec<DataType> = vec![1.into()];
+        let row: Vec<DataType> = vec![1.into(), 2.into()];
+
+        w.mut_with_key(&k[..]).mark_filled();
+        w.add(vec![Record::Positive(row.clone())]);
+        w.swap();
+
+        // keys that have not been read are kept
+        assert_eq!(w.evict_consumed(), 0);
+        w.swap();
+        assert_eq!(r.try_find_and(&k, |rs| rs.len()).unwrap().0, Some(1));
+
+        // but once read, they are evicted again
+        assert!(w.evict_consumed() > 0);
+        w.swap();
+        assert_eq!(w.deep_size_of(), 0);
+        assert_eq!(r.try_find_and(&k, |rs| rs.len()).unwrap().0, None);
+        assert_eq!(w.evict_consumed(), 0);
+    }
+
+    #[test]
+    fn coalesces_replays() {
+        use futures_util::task::{waker, ArcWake};
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        struct Flag(AtomicBool);
+        impl ArcWake for Flag {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let sent = Arc::new(AtomicUsize::new(0));
+        let (r, mut w) = {
+            let sent = Arc::clone(&sent);
+            new_partial(2, &[0], move |keys| {
+                sent.fetch_add(keys.count(), Ordering::SeqCst);
+                true
+            })
+        };
+        w.swap();
+        let k: Vec<DataType> = vec![1.into()];
+
+        // concurrent misses only request the key once
+        assert!(r.trigger(std::iter::once(&k[..])));
+        assert!(r.clone().trigger(std::iter::once(&k[..])));
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+
+        // waiters are woken once the filled key is visible
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        r.notify_on_fill(std::iter::once(&k[..]), &waker(Arc::clone(&flag)));
+        w.mut_with_key(&k[..]).mark_filled();
+        assert!(!flag.0.load(Ordering::SeqCst));
+        assert!(w.has_waiters());
+        assert!(w.with_key(&k[..]).filled_since_swap());
+
+        // until the fill is visible, misses on the key keep waiting for it
+        assert!(r.trigger(std::iter::once(&k[..])));
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+
+        w.swap();
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert!(!w.has_waiters());
+
+        // once the replay has completed, another miss requests the key again
+        w.mut_with_key(&k[..]).mark_hole();
+        w.swap();
+        assert!(r.trigger(std::iter::once(&k[..])));
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+    }
+
     #[test]
     fn busybusybusy() {
         use std::thread;
@@ -564,4 +1187,59 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn follows_changes() {
+        let a = vec![1.into(), "a".into()];
+        let b = vec![2.into(), "b".into()];
+
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        let next = match r.changes(None, 10).unwrap() {
+            ViewChanges::Lost { next } => next,
+            c => unreachable!("{:?}", c),
+        };
+        assert_eq!(next.seq, 0);
+
+        // changes only show up once they have been swapped in
+        w.add(vec![
+            Record::Positive(a.clone()),
+            Record::Positive(b.clone()),
+        ]);
+        assert_eq!(
+            r.changes(Some(next), 10).unwrap(),
+            ViewChanges::Since { rows: vec![], next }
+        );
+        w.swap();
+        w.add(vec![Record::Negative(b.clone())]);
+        w.swap();
+        let (rows, next) = match r.changes(Some(next), 2).unwrap() {
+            ViewChanges::Since { rows, next } => (rows, next),
+            c => unreachable!("{:?}", c),
+        };
+        assert_eq!(rows, vec![(a.clone(), true), (b.clone(), true)]);
+        assert_eq!(
+            r.changes(Some(next), 10).unwrap(),
+            ViewChanges::Since {
+                rows: vec![(b, false)],
+                next: ChangeCursor { seq: 3, ..next },
+            }
+        );
+
+        // a cursor for another reader's changes is of no use
+        let other = ChangeCursor {
+            log: next.log.wrapping_add(1),
+            ..next
+        };
+        assert_eq!(
+            r.changes(Some(other), 10).unwrap(),
+            ViewChanges::Lost {
+                next: ChangeCursor { seq: 3, ..next }
+            }
+        );
+
+        // partial readers do not keep their changes
+        let (r, _) = new_partial(2, &[0], |_| true);
+        assert!(r.changes(None, 10).is_none());
+    }
 }
@@ -18,6 +18,55 @@ impl Handle {
         }
     }
 
+    /// The keys of the map, in order.
+    pub(super) fn sorted_keys(&self) -> Option<Vec<Vec<DataType>>> {
+        let mut keys: Vec<Vec<DataType>> = match *self {
+            Handle::Single(ref h) => h.read()?.iter().map(|(k, _)| vec![k.clone()]).collect(),
+            Handle::Double(ref h) => h
+                .read()?
+                .iter()
+                .map(|((k1, k2), _)| vec![k1.clone(), k2.clone()])
+                .collect(),
+            Handle::Many(ref h) => h.read()?.iter().map(|(k, _)| k.clone()).collect(),
+        };
+        keys.sort();
+        Some(keys)
+    }
+
+    /// Pass the rows of the given keys to `then`. All the rows come from the same snapshot of the
+    /// map, and keys that aren't in it are skipped.
+    pub(super) fn rows_of_and<F, T>(&self, keys: &[Vec<DataType>], then: F) -> Option<T>
+    where
+        F: FnOnce(&mut dyn Iterator<Item = &Vec<DataType>>) -> T,
+    {
+        match *self {
+            Handle::Single(ref h) => {
+                let map = h.read()?;
+                let mut rows = keys
+                    .iter()
+                    .filter_map(|k| map.get(&k[0]))
+                    .flat_map(|vs| vs.iter());
+                Some(then(&mut rows))
+            }
+            Handle::Double(ref h) => {
+                let map = h.read()?;
+                let mut rows = keys
+                    .iter()
+                    .filter_map(|k| map.get(&(k[0].clone(), k[1].clone())))
+                    .flat_map(|vs| vs.iter());
+                Some(then(&mut rows))
+            }
+            Handle::Many(ref h) => {
+                let map = h.read()?;
+                let mut rows = keys
+                    .iter()
+                    .filter_map(|k| map.get(&k[..]))
+                    .flat_map(|vs| vs.iter());
+                Some(then(&mut rows))
+            }
+        }
+    }
+
     pub(super) fn meta_get_and<F, T>(&self, key: &[DataType], then: F) -> Option<(Option<T>, i64)>
     where
         F: FnOnce(&evmap::Values<Vec<DataType>, RandomState>) -> T,
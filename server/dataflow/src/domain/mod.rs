@@ -7,19 +7,23 @@ use std::mem;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time;
 
 use crate::group_commit::GroupCommitQueueSet;
-use crate::payload::{ControlReplyPacket, ReplayPieceContext, SourceSelection};
+use crate::payload::{ControlReplyPacket, ReplayPieceContext, ShippedWrites, SourceSelection};
 use crate::prelude::*;
 use ahash::RandomState;
 use futures_util::{future::FutureExt, stream::StreamExt};
 use noria::channel::{self, TcpSender};
 pub use noria::internal::DomainIndex as Index;
+use noria::RefreshPolicy;
 use slog::Logger;
 use stream_cancel::Valve;
 
 use crate::Readers;
+
+mod scatter;
 use timekeeper::{RealTime, SimpleTracker, ThreadTime, Timer, TimerSet};
 use tokio;
 
@@ -41,10 +45,103 @@ pub enum ProcessResult {
 pub struct Config {
     pub concurrent_replays: usize,
     pub replay_batch_timeout: time::Duration,
+    /// Number of records sent in each chunk of a full (non-partial) replay.
+    #[serde(default = "default_full_replay_batch_size")]
+    pub full_replay_batch_size: usize,
+    /// Maximum number of full replay chunks that may be queued at the source domain before the
+    /// chunker waits for it to catch up. A value of 0 means chunks are never held back.
+    #[serde(default)]
+    pub full_replay_window: usize,
+    /// Number of threads that prepare the chunks of a full replay in parallel, each working on a
+    /// disjoint range of keys.
+    #[serde(default = "default_full_replay_threads")]
+    pub full_replay_threads: usize,
+    /// Largest share of a domain's time that may be spent processing the chunks of full replays
+    /// while other work is waiting. A value of 1 means chunks are never held back.
+    #[serde(default = "default_background_replay_share")]
+    pub background_replay_share: f64,
+    /// Longest time updates for other domains may be held back while the domain is busy, so that
+    /// more of them can be sent together. Updates are never held back when the domain is idle.
+    #[serde(default)]
+    pub max_batch_delay: time::Duration,
 }
 
 const BATCH_SIZE: usize = 256;
 
+/// How often bases with a TTL are checked for expired rows.
+const EXPIRY_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+fn has_ttl(n: &Node) -> bool {
+    n.get_base().map(|b| b.ttl().is_some()).unwrap_or(false)
+}
+
+fn default_full_replay_batch_size() -> usize {
+    BATCH_SIZE
+}
+
+fn default_full_replay_threads() -> usize {
+    1
+}
+
+fn default_background_replay_share() -> f64 {
+    0.5
+}
+
+/// How much time spent on full replays a domain may bank while it is not doing any.
+const REPLAY_BUDGET_WINDOW: time::Duration = time::Duration::from_millis(100);
+
+/// Limits the share of a domain's time spent on the chunks of full replays.
+///
+/// This is a token bucket that fills up with `share` seconds of replay time for every second
+/// that passes, up to `share` of `REPLAY_BUDGET_WINDOW`. Processing a chunk drains the bucket by
+/// however long that took, and chunks are held back while the bucket is empty.
+#[derive(Debug)]
+struct ReplayBudget {
+    share: f64,
+    /// seconds of replay time left; negative if we have overspent.
+    left: f64,
+    refilled: time::Instant,
+}
+
+impl ReplayBudget {
+    fn new(share: f64) -> Self {
+        let share = share.max(0.01);
+        ReplayBudget {
+            share,
+            left: share * REPLAY_BUDGET_WINDOW.as_secs_f64(),
+            refilled: time::Instant::now(),
+        }
+    }
+
+    fn is_limited(&self) -> bool {
+        self.share < 1.0
+    }
+
+    fn refill(&mut self, now: time::Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        let max = self.share * REPLAY_BUDGET_WINDOW.as_secs_f64();
+        self.left = (self.left + self.share * elapsed).min(max);
+        self.refilled = now;
+    }
+
+    fn charge(&mut self, spent: time::Duration) {
+        self.left -= spent.as_secs_f64();
+    }
+
+    /// How long until more replay time is available.
+    fn wait(&mut self, now: time::Instant) -> time::Duration {
+        if !self.is_limited() {
+            return time::Duration::from_millis(0);
+        }
+        self.refill(now);
+        if self.left > 0.0 {
+            time::Duration::from_millis(0)
+        } else {
+            time::Duration::from_secs_f64(-self.left / self.share)
+        }
+    }
+}
+
 #[derive(Debug)]
 enum DomainMode {
     Forwarding,
@@ -147,6 +244,7 @@ impl DomainBuilder {
         control_addr: SocketAddr,
         shutdown_valve: &Valve,
         state_size: Arc<AtomicUsize>,
+        shipper: Option<tokio::sync::mpsc::Sender<ShippedWrites>>,
     ) -> Domain {
         // initially, all nodes are not ready
         let not_ready = self
@@ -155,6 +253,8 @@ impl DomainBuilder {
             .map(|n| n.borrow().local_addr())
             .collect();
 
+        let expiring_bases = self.nodes.values().any(|n| has_ttl(&n.borrow()));
+
         let log = log.new(o!("domain" => self.index.index(), "shard" => self.shard.unwrap_or(0)));
         let control_reply_tx = TcpSender::connect(&control_addr).unwrap();
         let group_commit_queues = GroupCommitQueueSet::new(&self.persistence_parameters);
@@ -185,12 +285,33 @@ impl DomainBuilder {
             buffered_replay_requests: Default::default(),
             replay_batch_timeout: self.config.replay_batch_timeout,
             timed_purges: Default::default(),
+            deferred_refreshes: Default::default(),
+            next_expiry: time::Instant::now(),
+            expiring_bases,
 
             concurrent_replays: 0,
             max_concurrent_replays: self.config.concurrent_replays,
             replay_request_queue: Default::default(),
+            scatter: Default::default(),
             delayed_for_self: Default::default(),
 
+            full_replay_batch_size: cmp::max(self.config.full_replay_batch_size, 1),
+            full_replay_window: self.config.full_replay_window,
+            full_replay_threads: cmp::max(self.config.full_replay_threads, 1),
+            full_replay_progress: Default::default(),
+            full_replay_chunks: Default::default(),
+            coalesced_updates: Default::default(),
+            full_replay_backlog: Default::default(),
+            deferred_replay_chunks: 0,
+            full_replays: Default::default(),
+            deferred_replays: Default::default(),
+            replay_budget: ReplayBudget::new(self.config.background_replay_share),
+            max_batch_delay: self.config.max_batch_delay,
+            rate_limit: None,
+            queue_depth: 0,
+            shipper,
+            unshipped: Default::default(),
+
             group_commit_queues,
 
             state_size,
@@ -199,6 +320,7 @@ impl DomainBuilder {
             wait_time: Timer::new(),
             process_times: TimerSet::new(),
             process_ptimes: TimerSet::new(),
+            process_records: Default::default(),
 
             total_replay_time: Timer::new(),
             total_forward_time: Timer::new(),
@@ -234,12 +356,20 @@ pub struct Domain {
     replay_paths: HashMap<Tag, ReplayPath>,
     reader_triggered: Map<HashSet<Vec<DataType>, RandomState>>,
     timed_purges: VecDeque<TimedPurge>,
+    /// readers that may hold back writes until their next refresh
+    deferred_refreshes: HashSet<LocalNodeIndex>,
+    /// when bases with a TTL should next be checked for expired rows
+    next_expiry: time::Instant,
+    /// whether any of this domain's bases have a TTL
+    expiring_bases: bool,
 
     replay_paths_by_dst: Map<HashMap<Vec<usize>, Vec<Tag>>>,
 
     concurrent_replays: usize,
     max_concurrent_replays: usize,
     replay_request_queue: VecDeque<(Tag, Vec<Vec<DataType>>)>,
+    /// upqueries that have to ask all shards of an upstream domain.
+    scatter: scatter::ScatterGather,
 
     shutdown_valve: Valve,
     readers: Readers,
@@ -250,6 +380,37 @@ pub struct Domain {
     replay_batch_timeout: time::Duration,
     delayed_for_self: VecDeque<Box<Packet>>,
 
+    full_replay_batch_size: usize,
+    full_replay_window: usize,
+    full_replay_threads: usize,
+    /// number of chunks of each ongoing full replay that this domain has processed so far. shared
+    /// with the chunker thread so that it can hold back chunks until we have caught up.
+    full_replay_progress: HashMap<Tag, Arc<AtomicUsize>>,
+    /// number of full replay chunks sent by our chunker threads, and the most chunks any of them
+    /// has had queued up at once. kept for statistics.
+    full_replay_chunks: Arc<AtomicUsize>,
+    full_replay_backlog: Arc<AtomicUsize>,
+    /// number of updates for other domains that were merged into an update queued before them.
+    /// shared with the replica that does the sending. kept for statistics.
+    coalesced_updates: Arc<AtomicUsize>,
+    /// number of full replay chunks that were held back by the replay budget.
+    deferred_replay_chunks: usize,
+    /// full replays that pass through this domain and whose first chunk we have processed.
+    full_replays: HashSet<Tag>,
+    /// chunks of full replays held back so that other work can go first.
+    deferred_replays: VecDeque<Box<Packet>>,
+    replay_budget: ReplayBudget,
+    max_batch_delay: time::Duration,
+    /// the most forward updates per second this domain should process, if it is limited.
+    rate_limit: Option<u64>,
+    /// the number of packets waiting to be processed by this domain, as last reported by whoever
+    /// feeds it packets.
+    queue_depth: usize,
+    /// where to send the effects of writes to bases, if they are shipped to a standby deployment.
+    shipper: Option<tokio::sync::mpsc::Sender<ShippedWrites>>,
+    /// writes waiting for room in the channel to the shipper.
+    unshipped: VecDeque<ShippedWrites>,
+
     group_commit_queues: GroupCommitQueueSet,
 
     state_size: Arc<AtomicUsize>,
@@ -258,6 +419,8 @@ pub struct Domain {
     wait_time: Timer<SimpleTracker, RealTime>,
     process_times: TimerSet<LocalNodeIndex, SimpleTracker, RealTime>,
     process_ptimes: TimerSet<LocalNodeIndex, SimpleTracker, ThreadTime>,
+    /// number of records emitted by each node during forward processing
+    process_records: Map<u64>,
 
     /// time spent processing replays
     total_replay_time: Timer<SimpleTracker, RealTime>,
@@ -383,6 +546,8 @@ impl Domain {
 
     fn send_partial_replay_request(&mut self, tag: Tag, keys: Vec<Vec<DataType>>) {
         debug_assert!(self.concurrent_replays < self.max_concurrent_replays);
+        let first = self.replay_paths[&tag].path[0].node;
+        let merged_here = self.nodes[first].borrow().is_shard_merger();
         if let TriggerEndpoint::End {
             source,
             ref mut options,
@@ -402,6 +567,37 @@ impl Domain {
             if ask_shard_by_key_i.is_none() && options.len() != 1 {
                 // source is sharded by a different key than we are doing lookups for,
                 // so we need to trigger on all the shards.
+                let keys = if merged_here {
+                    let (keys, known_empty) = self.scatter.plan(tag, keys, time::Instant::now());
+                    if !known_empty.is_empty() {
+                        // all the shards recently told us that there's nothing there, so we
+                        // answer on their behalf, as if their answers had already been merged.
+                        trace!(self.log, "answering shuffled shard replay request locally";
+                               "tag" => ?tag,
+                               "keys" => ?known_empty);
+                        self.concurrent_replays += 1;
+                        self.delayed_for_self
+                            .push_back(Box::new(Packet::ReplayPiece {
+                                link: Link::new(first, first),
+                                tag,
+                                context: ReplayPieceContext::Partial {
+                                    for_keys: known_empty.into_iter().collect(),
+                                    unishard: true,
+                                    ignore: false,
+                                    requesting_shard: self.shard.unwrap_or(0),
+                                },
+                                data: Records::default(),
+                            }));
+                    }
+                    if keys.is_empty() {
+                        // the remaining keys have already been requested
+                        return;
+                    }
+                    keys
+                } else {
+                    keys
+                };
+
                 self.concurrent_replays += 1;
                 trace!(self.log, "sending shuffled shard replay request";
                 "tag" => ?tag,
@@ -562,6 +758,17 @@ impl Domain {
         let src = m.src();
         let me = m.dst();
 
+        if let Packet::Message { ref data, .. } = *m {
+            // keys that updates arrive for may no longer be empty upstream
+            for tag in self.scatter.empty_tags() {
+                let first = &self.replay_paths[&tag].path[0];
+                if first.node == me {
+                    self.scatter
+                        .invalidate(tag, first.partial_key.as_ref().unwrap(), data);
+                }
+            }
+        }
+
         match self.mode {
             DomainMode::Forwarding => (),
             DomainMode::Replaying {
@@ -579,7 +786,12 @@ impl Domain {
             return;
         }
 
-        let (mut m, evictions) = {
+        let written = match *m {
+            Packet::Input { .. } => true,
+            _ => false,
+        };
+
+        let (mut m, evictions, shipped) = {
             let mut n = self.nodes[me].borrow_mut();
             self.process_times.start(me);
             self.process_ptimes.start(me);
@@ -600,10 +812,40 @@ impl Domain {
             self.process_times.stop();
 
             if m.is_none() {
+                // a sharder sends its output itself, and may have started to split keys
+                let mut split = Vec::new();
+                if n.is_sharder() {
+                    n.with_sharder_mut(|s| split = s.take_newly_split());
+                }
+                drop(n);
+                self.evict_split_keys(src, me, split, executor);
+
                 // no need to deal with our children if we're not sending them anything
                 return;
             }
 
+            let mut emitted = 0;
+            m.as_mut().unwrap().map_data(|rs| emitted = rs.len() as u64);
+            *self.process_records.entry(me).or_insert(0) += emitted;
+
+            // the standby gets the rows as the base ended up with them, rather than the writes
+            // that produced them, so that it does not have to apply them the same way we did.
+            let shipped = match n.get_base() {
+                Some(b) if written && self.shipper.is_some() => {
+                    let mut ops = Vec::new();
+                    m.as_mut().unwrap().map_data(|rs| ops = b.replicate(rs));
+                    if ops.is_empty() {
+                        None
+                    } else {
+                        Some(ShippedWrites {
+                            table: n.name().to_owned(),
+                            ops,
+                        })
+                    }
+                }
+                _ => None,
+            };
+
             // normally, we ignore misses during regular forwarding.
             // however, we have to be a little careful in the case of joins.
             let evictions = if n.is_internal() && n.is_join() && !misses.is_empty() {
@@ -682,27 +924,62 @@ impl Domain {
                 for miss in misses {
                     for &(tag, ref keys) in &deps {
                         evictions
-                            .entry(tag)
+                            .entry((tag, me))
                             .or_insert_with(HashSet::new)
                             .insert(keys.iter().map(|&key| miss.record[key].clone()).collect());
                     }
                 }
 
+                Some(evictions)
+            } else if n.beyond_mat_frontier() && !misses.is_empty() {
+                // we purge keys once they have been replayed through us, but materializations
+                // further down, like the reader of a cached view, may still hold on to them. we
+                // cannot forward writes for such keys, so we have to evict them downstream
+                // instead, starting with the node that follows us on each replay path that we
+                // are the source of.
+                let mut evictions = HashMap::new();
+                for (&tag, rp) in &self.replay_paths {
+                    if rp.source != Some(me) || rp.path.is_empty() {
+                        continue;
+                    }
+                    let key_cols = match rp.trigger {
+                        TriggerEndpoint::Local(ref key) | TriggerEndpoint::Start(ref key) => key,
+                        _ => continue,
+                    };
+                    for miss in misses.iter().filter(|miss| miss.on == me) {
+                        if miss.lookup_idx == *key_cols {
+                            evictions
+                                .entry((tag, rp.path[0].node))
+                                .or_insert_with(HashSet::new)
+                                .insert(
+                                    miss.lookup_cols
+                                        .iter()
+                                        .map(|&c| miss.record[c].clone())
+                                        .collect(),
+                                );
+                        }
+                    }
+                }
+
                 Some(evictions)
             } else {
                 None
             };
 
-            (m, evictions)
+            (m, evictions, shipped)
         };
 
+        if let Some(writes) = shipped {
+            self.ship(writes);
+        }
+
         if let Some(evictions) = evictions {
             // now send evictions for all the (tag, [key]) things in evictions
-            for (tag, keys) in evictions {
+            for ((tag, dst), keys) in evictions {
                 self.handle_eviction(
                     Box::new(Packet::EvictKeys {
                         keys: keys.into_iter().collect(),
-                        link: Link::new(src, me),
+                        link: Link::new(src, dst),
                         tag,
                     }),
                     executor,
@@ -785,13 +1062,32 @@ impl Domain {
                                 .borrow_mut()
                                 .add_child(node.local_addr());
                         }
+                        if let Ok(policy) = node.with_reader(|r| r.refresh_policy()) {
+                            if policy != RefreshPolicy::Immediate {
+                                self.deferred_refreshes.insert(addr);
+                            }
+                        }
+                        self.expiring_bases |= has_ttl(&node);
                         self.nodes.insert(addr, cell::RefCell::new(node));
                         trace!(self.log, "new node incorporated"; "local" => addr.id());
                     }
                     Packet::RemoveNodes { nodes } => {
                         for &node in &nodes {
+                            {
+                                // free the read handle for a removed reader so that its state
+                                // is dropped, and lookups against it no longer succeed
+                                let n = self.nodes[node].borrow();
+                                if n.is_reader() {
+                                    let gid = n.global_addr();
+                                    self.readers
+                                        .lock()
+                                        .unwrap()
+                                        .remove(&(gid, *self.shard.as_ref().unwrap_or(&0)));
+                                }
+                            }
                             self.nodes[node].borrow_mut().remove();
                             self.state.remove(node);
+                            self.deferred_refreshes.remove(&node);
                             trace!(self.log, "node removed"; "local" => node.id());
                         }
 
@@ -802,6 +1098,7 @@ impl Domain {
                                 // important to update parent pointers here
                             }
                         }
+                        self.expiring_bases = self.nodes.values().any(|n| has_ttl(&n.borrow()));
                     }
                     Packet::AddBaseColumn {
                         node,
@@ -849,6 +1146,23 @@ impl Domain {
                             }
                         });
                     }
+                    Packet::UpdateRefreshPolicy { node, policy } => {
+                        self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| r.set_refresh_policy(policy))
+                            .expect("refresh policy set for non-reader node");
+                        if policy == RefreshPolicy::Immediate {
+                            self.deferred_refreshes.remove(&node);
+                        } else {
+                            self.deferred_refreshes.insert(node);
+                        }
+                    }
+                    Packet::UpdateRateLimit { records_per_sec } => {
+                        self.rate_limit = records_per_sec;
+                    }
+                    Packet::Hibernate { after } => {
+                        self.hibernate_idle_readers(after);
+                    }
                     Packet::UpdateSharder { node, new_txs } => {
                         let mut n = self.nodes[node].borrow_mut();
                         n.with_sharder_mut(move |s| {
@@ -870,22 +1184,32 @@ impl Domain {
                                     self.state.insert(node, Box::new(MemoryState::default()));
                                 }
                                 let state = self.state.get_mut(node).unwrap();
-                                for (key, tags) in index {
+                                for (key, index_type, tags) in index {
                                     info!(self.log, "told to prepare partial state";
                                            "key" => ?key,
+                                           "type" => ?index_type,
                                            "tags" => ?tags);
-                                    state.add_key(&key[..], Some(tags));
+                                    state.add_index(&key[..], index_type, Some(tags));
                                 }
                             }
-                            InitialState::IndexedLocal(index) => {
+                            InitialState::IndexedLocal {
+                                indices: index,
+                                bloom,
+                            } => {
                                 if !self.state.contains_key(node) {
                                     self.state.insert(node, Box::new(MemoryState::default()));
                                 }
                                 let state = self.state.get_mut(node).unwrap();
-                                for idx in index {
+                                for (idx, index_type) in index {
                                     info!(self.log, "told to prepare full state";
+                                           "key" => ?idx,
+                                           "type" => ?index_type);
+                                    state.add_index(&idx[..], index_type, None);
+                                }
+                                for idx in bloom {
+                                    info!(self.log, "told to guard index with bloom filter";
                                            "key" => ?idx);
-                                    state.add_key(&idx[..], None);
+                                    state.add_bloom_filter(&idx[..]);
                                 }
                             }
                             InitialState::PartialGlobal {
@@ -932,9 +1256,7 @@ impl Domain {
                                         tx
                                     })
                                     .collect::<Vec<_>>();
-                                let (r_part, w_part) = backlog::new_partial(
-                                    cols,
-                                    &k[..],
+                                let trigger =
                                     move |misses: &mut dyn Iterator<Item = &[DataType]>| {
                                         let n = txs.len();
                                         if n == 1 {
@@ -962,10 +1284,16 @@ impl Domain {
                                                 .into_iter()
                                                 .all(|(shard, keys)| txs[shard].send(keys).is_ok())
                                         }
-                                    },
-                                );
+                                    };
 
                                 let mut n = self.nodes[node].borrow_mut();
+                                let straight_through =
+                                    n.with_reader(|r| r.is_straight_through()).unwrap_or(false);
+                                let (r_part, w_part) = if straight_through {
+                                    backlog::new_straight_through(cols, &k[..], trigger)
+                                } else {
+                                    backlog::new_partial(cols, &k[..], trigger)
+                                };
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
                                         assert!(self
@@ -1183,8 +1511,9 @@ impl Domain {
                         let state = self
                             .state
                             .get(from)
-                            .expect("migration replay path started with non-materialized node")
-                            .cloned_records();
+                            .expect("migration replay path started with non-materialized node");
+                        let key_column = state.keys().get(0).and_then(|k| k.get(0).cloned());
+                        let state = state.cloned_records();
 
                         debug!(self.log,
                                "current state cloned for replay";
@@ -1209,8 +1538,18 @@ impl Domain {
                             data: Vec::<Record>::new().into(),
                         });
 
+                        let mut processed = None;
                         if !state.is_empty() {
                             let log = self.log.new(o!());
+                            let batch_size = self.full_replay_batch_size;
+                            let window = self.full_replay_window;
+                            let threads = self.full_replay_threads;
+                            let progress = Arc::new(AtomicUsize::new(0));
+                            if window != 0 {
+                                processed = Some(progress.clone());
+                            }
+                            let sent = self.full_replay_chunks.clone();
+                            let backlog = self.full_replay_backlog.clone();
 
                             let added_cols = self.ingress_inject.get(from).cloned();
                             let default = {
@@ -1239,37 +1578,103 @@ impl Domain {
                                 .builder_for(&(self.index, self.shard.unwrap_or(0)))
                                 .unwrap();
 
+                            let name = format!(
+                                "replay{}.{}",
+                                self.nodes
+                                    .values()
+                                    .next()
+                                    .unwrap()
+                                    .borrow()
+                                    .domain()
+                                    .index(),
+                                link.src
+                            );
                             thread::Builder::new()
-                                .name(format!(
-                                    "replay{}.{}",
-                                    self.nodes
-                                        .values()
-                                        .next()
-                                        .unwrap()
-                                        .borrow()
-                                        .domain()
-                                        .index(),
-                                    link.src
-                                ))
+                                .name(name.clone())
                                 .spawn(move || {
                                     use itertools::Itertools;
+                                    use std::sync::mpsc;
 
                                     // TODO: make async
                                     let mut chunked_replay_tx =
                                         replay_tx_desc.build_sync().unwrap();
 
                                     let start = time::Instant::now();
-                                    debug!(log, "starting state chunker"; "node" => %link.dst);
+                                    debug!(log,
+                                       "starting state chunker";
+                                       "node" => %link.dst,
+                                       "threads" => threads
+                                    );
+
+                                    // split the state into disjoint key ranges, and have a
+                                    // separate thread turn each range into replay chunks. all the
+                                    // chunks are funneled back through this thread, since it is
+                                    // the only one that can tell which chunk is the last one.
+                                    let ranges = if threads == 1 {
+                                        vec![state]
+                                    } else {
+                                        let mut ranges = vec![Vec::new(); threads];
+                                        for (i, r) in state.into_iter().enumerate() {
+                                            let range = match key_column {
+                                                Some(c) => noria::shard_by(&r[c], threads),
+                                                None => i % threads,
+                                            };
+                                            ranges[range].push(r);
+                                        }
+                                        ranges
+                                    };
+
+                                    let (chunks_tx, chunks_rx) = mpsc::sync_channel(2 * threads);
+                                    for (i, range) in ranges.into_iter().enumerate() {
+                                        if range.is_empty() {
+                                            continue;
+                                        }
 
-                                    let iter = state.into_iter().chunks(BATCH_SIZE);
-                                    let mut iter = iter.into_iter().enumerate().peekable();
+                                        let chunks_tx = chunks_tx.clone();
+                                        let fix = fix.clone();
+                                        thread::Builder::new()
+                                            .name(format!("{}.{}", name, i))
+                                            .spawn(move || {
+                                                use std::iter::FromIterator;
+                                                for chunk in &range.into_iter().chunks(batch_size) {
+                                                    let chunk = Records::from_iter(chunk.map(&fix));
+                                                    if chunks_tx.send(chunk).is_err() {
+                                                        // the chunker has given up
+                                                        break;
+                                                    }
+                                                }
+                                            })
+                                            .unwrap();
+                                    }
+                                    drop(chunks_tx);
 
                                     // process all records in state to completion within domain
                                     // and then forward on tx (if there is one)
+                                    let mut iter = chunks_rx.into_iter().enumerate().peekable();
                                     while let Some((i, chunk)) = iter.next() {
-                                        use std::iter::FromIterator;
-                                        let chunk = Records::from_iter(chunk.map(&fix));
+                                        // don't let the replay run arbitrarily far ahead of the
+                                        // domain. if we did, any writes that arrive during the
+                                        // replay would be queued up behind the entire remaining
+                                        // state, and their latency would grow with the size of the
+                                        // table being backfilled.
+                                        if window != 0 {
+                                            while i >= progress.load(Ordering::Acquire) + window {
+                                                if Arc::strong_count(&progress) == 1 {
+                                                    // the domain has forgotten about this replay,
+                                                    // so it must have gone away.
+                                                    warn!(log, "replayer noticed domain shutdown");
+                                                    return;
+                                                }
+                                                thread::sleep(time::Duration::from_millis(1));
+                                            }
+                                            let queued = (i + 1)
+                                                .saturating_sub(progress.load(Ordering::Acquire));
+                                            backlog.fetch_max(queued, Ordering::AcqRel);
+                                        }
+
                                         let len = chunk.len();
+                                        // blocks until the next chunk has been produced, or until
+                                        // all the key ranges have been exhausted
                                         let last = iter.peek().is_none();
                                         let p = Box::new(Packet::ReplayPiece {
                                             tag,
@@ -1283,6 +1688,7 @@ impl Domain {
                                             warn!(log, "replayer noticed domain shutdown");
                                             break;
                                         }
+                                        sent.fetch_add(1, Ordering::AcqRel);
                                     }
 
                                     debug!(log,
@@ -1294,6 +1700,11 @@ impl Domain {
                                 .unwrap();
                         }
                         self.handle_replay(p, executor);
+                        if let Some(processed) = processed {
+                            // only chunks sent by the chunker are counted, so this must happen
+                            // after the initial (empty) piece above has been handled.
+                            self.full_replay_progress.insert(tag, processed);
+                        }
 
                         self.total_replay_time.stop();
                     }
@@ -1369,6 +1780,15 @@ impl Domain {
                             total_replay_time: self.total_replay_time.num_nanoseconds(),
                             total_forward_time: self.total_forward_time.num_nanoseconds(),
                             wait_time: self.wait_time.num_nanoseconds(),
+                            queue_depth: self.queue_depth,
+                            rate_limit: self.rate_limit,
+                            full_replay_chunks: self.full_replay_chunks.load(Ordering::Acquire)
+                                as u64,
+                            full_replay_backlog: self.full_replay_backlog.load(Ordering::Acquire)
+                                as u64,
+                            deferred_replay_chunks: self.deferred_replay_chunks as u64,
+                            coalesced_updates: self.coalesced_updates.load(Ordering::Acquire)
+                                as u64,
                         };
 
                         let node_stats = self
@@ -1425,16 +1845,46 @@ impl Domain {
                                     Default::default()
                                 };
 
+                                let (unsynced_records, durability_lag) = self
+                                    .state
+                                    .get(local_index)
+                                    .and_then(|s| s.unsynced())
+                                    .map(|(records, since)| {
+                                        (records, since.elapsed().as_nanos() as u64)
+                                    })
+                                    .unwrap_or((0, 0));
+
                                 if time.is_some() && ptime.is_some() {
                                     Some((
                                         node_index,
                                         noria::debug::stats::NodeStats {
                                             desc: format!("{:?}", n),
+                                            name: n.name().to_owned(),
+                                            // the controller knows which queries use the node
+                                            queries: Vec::new(),
                                             process_time: time.unwrap(),
                                             process_ptime: ptime.unwrap(),
+                                            records: self
+                                                .process_records
+                                                .get(local_index)
+                                                .cloned()
+                                                .unwrap_or(0),
                                             mem_size,
                                             materialized: mat_state,
                                             probe_result,
+                                            hot_keys: n
+                                                .with_sharder(|s| s.hot_keys())
+                                                .unwrap_or_default(),
+                                            unsynced_records,
+                                            durability_lag,
+                                            idle: n
+                                                .with_reader(|r| r.idle_for())
+                                                .ok()
+                                                .and_then(|i| i)
+                                                .map(|i| i.as_millis() as u64),
+                                            hibernating: n
+                                                .with_reader(|r| r.is_hibernating())
+                                                .unwrap_or(false),
                                         },
                                     ))
                                 } else {
@@ -1450,6 +1900,63 @@ impl Domain {
                     Packet::UpdateStateSize => {
                         self.update_state_sizes();
                     }
+                    Packet::SampleState { rows } => {
+                        let samples = self
+                            .state
+                            .iter()
+                            .map(|(local, state)| {
+                                (self.nodes[local].borrow().global_addr(), state.sample(rows))
+                            })
+                            .collect();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::StateSamples(samples))
+                            .unwrap();
+                    }
+                    Packet::DryRun { node, ops } => {
+                        let rs = self.nodes[node]
+                            .borrow()
+                            .get_base()
+                            .expect("dry run of non-base node")
+                            .preview(node, ops, &self.state);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::DryRun(rs.into()))
+                            .unwrap();
+                    }
+                    Packet::FindRows { node, columns } => {
+                        let rows = match self.state.get(node) {
+                            Some(s) => {
+                                // look the rows up in an index on some of the columns if there is
+                                // one, and only scan all the rows if there is not
+                                let index = s.keys().into_iter().find(|cols| {
+                                    !cols.is_empty()
+                                        && cols
+                                            .iter()
+                                            .all(|c| columns.iter().any(|(cc, _)| cc == c))
+                                });
+                                let found = index.and_then(|cols| {
+                                    let key: Vec<_> = cols
+                                        .iter()
+                                        .map(|c| &columns.iter().find(|(cc, _)| cc == c).unwrap().1)
+                                        .collect();
+                                    match s.lookup(&cols[..], &KeyType::from(key)) {
+                                        LookupResult::Some(rs) => {
+                                            Some(rs.into_iter().map(Cow::into_owned).collect())
+                                        }
+                                        LookupResult::Missing => None,
+                                    }
+                                });
+                                Ok(found
+                                    .unwrap_or_else(|| s.cloned_records())
+                                    .into_iter()
+                                    .filter(|r| columns.iter().all(|(c, v)| r[*c] == *v))
+                                    .collect())
+                            }
+                            None => Err(format!("node {} has no state", node.id())),
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Rows(rows))
+                            .unwrap();
+                    }
                     Packet::Quit => unreachable!("Quit messages are handled by event loop"),
                     Packet::Spin => {
                         // spinning as instructed
@@ -1460,6 +1967,8 @@ impl Domain {
         }
 
         if top {
+            self.expire_rows();
+
             let mut elapsed_replays = Vec::new();
             loop {
                 while let Some(m) = self.delayed_for_self.pop_front() {
@@ -1535,6 +2044,16 @@ impl Domain {
                         .unwrap();
                 }
 
+                if !self.deferred_refreshes.is_empty() {
+                    let now = time::Instant::now();
+                    for &n in &self.deferred_refreshes {
+                        self.nodes[n]
+                            .borrow_mut()
+                            .with_reader_mut(|r| r.refresh_if_due(now))
+                            .unwrap();
+                    }
+                }
+
                 if self.delayed_for_self.is_empty() {
                     break;
                 }
@@ -1782,6 +2301,24 @@ impl Domain {
     #[allow(clippy::cognitive_complexity)]
     fn handle_replay(&mut self, m: Box<Packet>, ex: &mut dyn Executor) {
         let tag = m.tag().unwrap();
+        if let Packet::ReplayPiece {
+            context: ReplayPieceContext::Regular { last },
+            ..
+        } = *m
+        {
+            // let the chunker of a full replay we are the source of know it can send more
+            if let Some(processed) = self.full_replay_progress.get(&tag) {
+                processed.fetch_add(1, Ordering::AcqRel);
+                if last {
+                    self.full_replay_progress.remove(&tag);
+                }
+            }
+            if last {
+                self.full_replays.remove(&tag);
+            } else {
+                self.full_replays.insert(tag);
+            }
+        }
         if self.nodes[self.replay_paths[&tag].path.last().unwrap().node]
             .borrow()
             .is_dropped()
@@ -2000,6 +2537,36 @@ impl Domain {
                             &self.log,
                         );
 
+                        // all shards have answered for the keys the shard merger released
+                        if i == 0 && n.is_shard_merger() {
+                            if let Some(&Packet::ReplayPiece {
+                                ref data,
+                                context:
+                                    ReplayPieceContext::Partial {
+                                        unishard: false, ..
+                                    },
+                                ..
+                            }) = m.as_deref()
+                            {
+                                // an empty answer can only be reused if nothing but the shard
+                                // merger feeds records for the key into the target
+                                let cacheable = path[1..]
+                                    .iter()
+                                    .all(|s| self.nodes[s.node].borrow().parents().len() <= 1);
+                                self.scatter.answered(
+                                    tag,
+                                    backfill_keys
+                                        .iter()
+                                        .flatten()
+                                        .filter(|k| !captured.contains(*k)),
+                                    partial_key_cols.unwrap(),
+                                    data,
+                                    cacheable,
+                                    time::Instant::now(),
+                                );
+                            }
+                        }
+
                         // ignore duplicate misses
                         misses.sort_unstable_by(|a, b| {
                             a.on.cmp(&b.on)
@@ -2041,13 +2608,8 @@ impl Domain {
                                     .unwrap();
                                 }
                             } else if is_reader {
-                                // we filled a hole! swap the reader.
-                                n.with_reader_mut(|r| {
-                                    if let Some(wh) = r.writer_mut() {
-                                        wh.swap();
-                                    }
-                                })
-                                .unwrap();
+                                // we filled a hole! make it visible to the reader.
+                                n.with_reader_mut(|r| r.refresh(true)).unwrap();
                                 // and also unmark the replay request
                                 if let Some(ref mut prev) =
                                     self.reader_triggered.get_mut(segment.node)
@@ -2404,7 +2966,13 @@ impl Domain {
                         } => {
                             assert!(!ignore);
                             if dst_is_reader {
-                                if self.nodes[dst].borrow().beyond_mat_frontier() {
+                                let purge = {
+                                    let n = self.nodes[dst].borrow();
+                                    // cached views keep their keys until a write evicts them
+                                    n.beyond_mat_frontier()
+                                        && !n.with_reader(|r| r.is_cached()).unwrap_or(false)
+                                };
+                                if purge {
                                     // make sure we eventually evict these from here
                                     self.timed_purges.push_back(TimedPurge {
                                         time: time::Instant::now()
@@ -2639,6 +3207,48 @@ impl Domain {
         }
     }
 
+    /// Evict `keys`, which the sharder `me` has just started to spread over its shards, from the
+    /// partial state below it. Until now their records were all sent to the shard that owns the
+    /// key, so that shard's state for them would not add up with what the other shards are
+    /// replayed from now on.
+    fn evict_split_keys(
+        &mut self,
+        src: LocalNodeIndex,
+        me: LocalNodeIndex,
+        keys: Vec<DataType>,
+        ex: &mut dyn Executor,
+    ) {
+        if keys.is_empty() {
+            return;
+        }
+
+        let shard_by = self.nodes[me]
+            .borrow()
+            .with_sharder(|s| s.sharded_by())
+            .unwrap();
+        // TODO: this is a linear walk of replay paths -- we should make that not linear
+        let tags: Vec<_> = self
+            .replay_paths
+            .iter()
+            .filter(|(_, rp)| {
+                rp.path
+                    .iter()
+                    .any(|rps| rps.node == me && rps.partial_key == Some(vec![shard_by]))
+            })
+            .map(|(&tag, _)| tag)
+            .collect();
+        for tag in tags {
+            self.handle_eviction(
+                Box::new(Packet::EvictKeys {
+                    keys: keys.iter().map(|k| vec![k.clone()]).collect(),
+                    link: Link::new(src, me),
+                    tag,
+                }),
+                ex,
+            );
+        }
+    }
+
     pub fn handle_eviction(&mut self, m: Box<Packet>, ex: &mut dyn Executor) {
         #[allow(clippy::too_many_arguments)]
         fn trigger_downstream_evictions(
@@ -2946,6 +3556,234 @@ impl Domain {
         // no response sent, as worker will read the atomic
     }
 
+    fn has_expiring_bases(&self) -> bool {
+        self.expiring_bases
+    }
+
+    /// Evict all keys of partial readers that have not been read from for `after`, so that idle
+    /// views do not hold on to memory. Their keys are filled again as they are read.
+    fn hibernate_idle_readers(&mut self, after: time::Duration) {
+        let mut freed = 0;
+        for (local, node) in self.nodes.iter() {
+            if self.not_ready.contains(&local) {
+                continue;
+            }
+            let mut n = node.borrow_mut();
+            let idle = n
+                .with_reader(|r| {
+                    r.is_partial()
+                        && !r.is_hibernating()
+                        && !r.is_empty()
+                        && r.idle_for().map(|i| i >= after).unwrap_or(false)
+                })
+                .unwrap_or(false);
+            if !idle {
+                continue;
+            }
+            let bytes = n.with_reader_mut(|r| r.hibernate()).unwrap();
+            debug!(self.log, "hibernating idle reader";
+                   "node" => n.global_addr().index(),
+                   "bytes" => bytes);
+            freed += bytes;
+        }
+        self.state_size.fetch_sub(freed as usize, Ordering::AcqRel);
+    }
+
+    /// Delete the rows of bases with a TTL that have expired, if it is time to check for them.
+    ///
+    /// The deletes are queued as writes to this domain, so they are processed like any other
+    /// write and propagate to all downstream views.
+    fn expire_rows(&mut self) {
+        let now = time::Instant::now();
+        if now < self.next_expiry {
+            return;
+        }
+        self.next_expiry = now + EXPIRY_INTERVAL;
+
+        let wall = time::SystemTime::now();
+        for (local, node) in self.nodes.iter() {
+            if self.not_ready.contains(&local) {
+                continue;
+            }
+            let node = node.borrow();
+            let base = match node.get_base() {
+                Some(b) if b.ttl().is_some() => b,
+                _ => continue,
+            };
+            let state = match self.state.get(local) {
+                Some(s) => s,
+                None => continue,
+            };
+            let deletes = base.expire(&**state, wall);
+            if deletes.is_empty() {
+                continue;
+            }
+
+            debug!(self.log, "expiring rows from base";
+                   "node" => node.global_addr().index(),
+                   "rows" => deletes.len());
+            self.delayed_for_self.push_back(Box::new(Packet::Input {
+                inner: LocalOrNot::new(Input {
+                    dst: local,
+                    data: deletes,
+                    ack: AckLevel::Applied,
+                }),
+                src: None,
+                senders: Vec::new(),
+            }));
+        }
+    }
+
+    /// Send writes to the standby deployment.
+    ///
+    /// If the shipper has fallen behind, the writes wait here until `poll_shipped` finds room
+    /// for them.
+    fn ship(&mut self, writes: ShippedWrites) {
+        use tokio::sync::mpsc::error::TrySendError;
+
+        let tx = match self.shipper {
+            Some(ref mut tx) => tx,
+            None => return,
+        };
+        if !self.unshipped.is_empty() {
+            // keep the writes in order
+            self.unshipped.push_back(writes);
+            return;
+        }
+        match tx.try_send(writes) {
+            Ok(()) => {}
+            Err(TrySendError::Full(writes)) => {
+                warn!(self.log, "holding back writes until the shipper catches up");
+                self.unshipped.push_back(writes);
+            }
+            Err(TrySendError::Closed(_)) => {
+                // the worker is shutting down, and has stopped shipping writes
+                self.shipper = None;
+            }
+        }
+    }
+
+    /// Hand the writes that were held back by `ship` to the shipper.
+    ///
+    /// Returns `Poll::Pending` while the shipper has not caught up, in which case whoever feeds
+    /// the domain packets should stop giving it more writes; they are woken up once the shipper
+    /// has room again.
+    pub fn poll_shipped(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        while !self.unshipped.is_empty() {
+            let tx = match self.shipper {
+                Some(ref mut tx) => tx,
+                None => {
+                    self.unshipped.clear();
+                    break;
+                }
+            };
+            match tx.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    let writes = self.unshipped.pop_front().unwrap();
+                    if tx.try_send(writes).is_err() {
+                        self.shipper = None;
+                    }
+                }
+                Poll::Ready(Err(_)) => {
+                    // the worker is shutting down, and has stopped shipping writes
+                    self.shipper = None;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(())
+    }
+
+    /// How long until the oldest write to a base in this domain that has not been synced to disk
+    /// must be synced, if the domain syncs its bases periodically.
+    fn sync_due(&self, now: time::Instant) -> Option<time::Duration> {
+        let every = match self.persistence_parameters.fsync {
+            FsyncPolicy::Interval(every) => every,
+            _ => return None,
+        };
+        self.state
+            .values()
+            .filter_map(|s| s.unsynced())
+            .map(|(_, since)| {
+                every
+                    .checked_sub(now.duration_since(since))
+                    .unwrap_or(time::Duration::from_millis(0))
+            })
+            .min()
+    }
+
+    /// Sync every base whose oldest unsynced write has waited for longer than the sync interval.
+    fn sync_overdue(&mut self, now: time::Instant) {
+        let every = match self.persistence_parameters.fsync {
+            FsyncPolicy::Interval(every) => every,
+            _ => return,
+        };
+        for (_, s) in self.state.iter_mut() {
+            if let Some((_, since)) = s.unsynced() {
+                if now.duration_since(since) >= every {
+                    s.sync();
+                }
+            }
+        }
+    }
+
+    /// How long updates for other domains may be held back while this domain is busy.
+    pub fn max_batch_delay(&self) -> time::Duration {
+        self.max_batch_delay
+    }
+
+    /// A counter for the updates for other domains that were merged into an earlier update.
+    pub fn coalesced_updates(&self) -> Arc<AtomicUsize> {
+        self.coalesced_updates.clone()
+    }
+
+    /// The most forward updates per second this domain should process, if it is limited.
+    ///
+    /// The domain does not enforce the limit itself; whoever feeds it packets should hold them
+    /// back once it has been handed that many updates in a second.
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit
+    }
+
+    /// Record how many packets are waiting to be processed by this domain.
+    pub fn set_queue_depth(&mut self, packets: usize) {
+        self.queue_depth = packets;
+    }
+
+    /// Whether this packet is a chunk of a full replay that may be held back.
+    ///
+    /// The first chunk of a full replay is never held back, since it tells the target domain to
+    /// start buffering the updates that follow the replayed state. Partial replays are always
+    /// processed immediately, as reads may be waiting for them.
+    fn is_deferrable_replay(&self, m: &Packet) -> bool {
+        match *m {
+            Packet::ReplayPiece {
+                tag,
+                context: ReplayPieceContext::Regular { .. },
+                ..
+            } => self.full_replays.contains(&tag),
+            _ => false,
+        }
+    }
+
+    /// Process a chunk of a full replay, and charge the time it took to the replay budget.
+    fn handle_full_replay(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        let start = time::Instant::now();
+        self.handle(m, executor, true);
+        self.replay_budget.charge(start.elapsed());
+    }
+
+    /// Process held back chunks of full replays for as long as the replay budget allows.
+    fn handle_deferred_replays(&mut self, executor: &mut dyn Executor) {
+        while !self.deferred_replays.is_empty() {
+            if self.replay_budget.wait(time::Instant::now()) > time::Duration::from_millis(0) {
+                break;
+            }
+            let m = self.deferred_replays.pop_front().unwrap();
+            self.handle_full_replay(m, executor);
+        }
+    }
+
     pub fn on_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
         if self.wait_time.is_running() {
             self.wait_time.stop();
@@ -2974,14 +3812,51 @@ impl Domain {
                         time::Duration::from_millis(0)
                     }
                 });
+                let opt4 = if self.has_expiring_bases() {
+                    Some(if self.next_expiry > now {
+                        self.next_expiry - now
+                    } else {
+                        time::Duration::from_millis(0)
+                    })
+                } else {
+                    None
+                };
+                let opt5 = if self.deferred_replays.is_empty() {
+                    None
+                } else {
+                    Some(self.replay_budget.wait(now))
+                };
+                let opt6 = self
+                    .deferred_refreshes
+                    .iter()
+                    .filter_map(|&n| {
+                        self.nodes[n]
+                            .borrow()
+                            .with_reader(|r| r.refresh_due(now))
+                            .unwrap()
+                    })
+                    .min();
+                let opt7 = self.sync_due(now);
 
-                let mut timeout = opt1.or(opt2).or(opt3);
+                let mut timeout = opt1.or(opt2).or(opt3).or(opt4).or(opt5).or(opt6).or(opt7);
                 if let Some(opt2) = opt2 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt2));
                 }
                 if let Some(opt3) = opt3 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt3));
                 }
+                if let Some(opt4) = opt4 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt4));
+                }
+                if let Some(opt5) = opt5 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt5));
+                }
+                if let Some(opt6) = opt6 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt6));
+                }
+                if let Some(opt7) = opt7 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt7));
+                }
                 ProcessResult::KeepPolling(timeout)
             }
             PollEvent::Process(packet) => {
@@ -2991,7 +3866,19 @@ impl Domain {
 
                 // TODO: Initialize tracer here, and when flushing group commit
                 // queue.
-                if self.group_commit_queues.should_append(&packet, &self.nodes) {
+                if self.replay_budget.is_limited() && self.is_deferrable_replay(&packet) {
+                    // chunks of a full replay must be processed in order, so once one has been
+                    // held back, so are all the ones that follow it.
+                    if self.deferred_replays.is_empty()
+                        && self.replay_budget.wait(time::Instant::now())
+                            == time::Duration::from_millis(0)
+                    {
+                        self.handle_full_replay(packet, executor);
+                    } else {
+                        self.deferred_replay_chunks += 1;
+                        self.deferred_replays.push_back(packet);
+                    }
+                } else if self.group_commit_queues.should_append(&packet, &self.nodes) {
                     if let Some(packet) = self.group_commit_queues.append(packet) {
                         self.handle(packet, executor, true);
                     }
@@ -3010,7 +3897,15 @@ impl Domain {
                     self.handle(m, executor, true);
                 }
 
-                if !self.buffered_replay_requests.is_empty() || !self.timed_purges.is_empty() {
+                // we only get here once there is nothing else to process
+                self.handle_deferred_replays(executor);
+                self.sync_overdue(time::Instant::now());
+
+                if !self.buffered_replay_requests.is_empty()
+                    || !self.timed_purges.is_empty()
+                    || !self.deferred_refreshes.is_empty()
+                    || self.has_expiring_bases()
+                {
                     self.handle(Box::new(Packet::Spin), executor, true);
                 }
 
@@ -3023,3 +3918,52 @@ impl Domain {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> time::Duration {
+        time::Duration::from_millis(n)
+    }
+
+    fn assert_close(a: time::Duration, b: time::Duration) {
+        let diff = if a > b { a - b } else { b - a };
+        assert!(diff < time::Duration::from_micros(10), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn replay_budget_holds_back_overspent_time() {
+        let mut budget = ReplayBudget::new(0.5);
+        let start = budget.refilled;
+
+        // a fresh budget has half of the window to spend
+        assert_eq!(budget.wait(start), ms(0));
+
+        // overspending by 50ms takes 100ms to earn back at a 50% share
+        budget.charge(ms(100));
+        assert_close(budget.wait(start), ms(100));
+        assert_close(budget.wait(start + ms(60)), ms(40));
+        assert_eq!(budget.wait(start + ms(110)), ms(0));
+    }
+
+    #[test]
+    fn replay_budget_banks_at_most_one_window() {
+        let mut budget = ReplayBudget::new(0.5);
+        let start = budget.refilled;
+
+        // idling for a long time only banks half of the window
+        assert_eq!(budget.wait(start + time::Duration::from_secs(10)), ms(0));
+        budget.charge(ms(60));
+        assert_close(budget.wait(start + time::Duration::from_secs(10)), ms(20));
+    }
+
+    #[test]
+    fn replay_budget_is_unlimited_at_full_share() {
+        let mut budget = ReplayBudget::new(1.0);
+        let start = budget.refilled;
+        assert!(!budget.is_limited());
+        budget.charge(time::Duration::from_secs(1));
+        assert_eq!(budget.wait(start), ms(0));
+    }
+}
@@ -0,0 +1,184 @@
+//! Bookkeeping for partial replays that have to ask every shard of an upstream domain.
+//!
+//! When the upstream domain is sharded by a different column than the one we are looking up,
+//! each key we miss on turns into one upquery per upstream shard, and the shard merger at the
+//! start of the replay path waits for all of their answers. Popular keys that do not exist are
+//! particularly expensive: every time their (empty) entry is evicted, all the shards are asked
+//! again, only to all say that there is nothing there.
+//!
+//! `ScatterGather` keeps track of which keys are currently being asked for, so that the same key
+//! is not requested from all shards twice at once, and of which keys every shard recently said
+//! were empty, so that those can be answered without asking the shards again. The latter is only
+//! done for replay paths where every record that could end up in the target for a key has to pass
+//! through the shard merger, and a key is forgotten as soon as any update for it does.
+
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::time;
+
+/// How long to answer upqueries for keys that every shard said were empty without asking again.
+const NEGATIVE_TTL: time::Duration = time::Duration::from_secs(1);
+
+/// How long to wait for the answers to a request before assuming they got lost.
+const IN_FLIGHT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+#[derive(Default)]
+pub(super) struct ScatterGather {
+    /// Keys that have been requested from all shards, but that not all shards have answered.
+    in_flight: HashMap<Tag, HashMap<Vec<DataType>, time::Instant>>,
+    /// Keys that all shards recently answered with no records.
+    empty: HashMap<Tag, HashMap<Vec<DataType>, time::Instant>>,
+}
+
+impl ScatterGather {
+    /// Decide which of `keys` to request from all shards along `tag`.
+    ///
+    /// Returns the keys that must be requested, and the keys that are known to be empty. Keys
+    /// that have already been requested are in neither.
+    pub(super) fn plan(
+        &mut self,
+        tag: Tag,
+        keys: Vec<Vec<DataType>>,
+        now: time::Instant,
+    ) -> (Vec<Vec<DataType>>, Vec<Vec<DataType>>) {
+        let in_flight = self.in_flight.entry(tag).or_default();
+        in_flight.retain(|_, &mut sent| now.duration_since(sent) < IN_FLIGHT_TIMEOUT);
+        let mut empty = self.empty.get_mut(&tag);
+        if let Some(ref mut empty) = empty {
+            empty.retain(|_, &mut seen| now.duration_since(seen) < NEGATIVE_TTL);
+        }
+
+        let mut request = Vec::new();
+        let mut known_empty = Vec::new();
+        for key in keys {
+            if in_flight.contains_key(&key) {
+                continue;
+            }
+            if empty
+                .as_ref()
+                .map(|e| e.contains_key(&key))
+                .unwrap_or(false)
+            {
+                known_empty.push(key);
+            } else {
+                in_flight.insert(key.clone(), now);
+                request.push(key);
+            }
+        }
+        (request, known_empty)
+    }
+
+    /// All shards have answered the request for `keys` along `tag` with `records`, whose key is
+    /// in `key_cols`.
+    ///
+    /// If `cacheable`, the keys that had no records are remembered as empty for a little while.
+    pub(super) fn answered<'a>(
+        &mut self,
+        tag: Tag,
+        keys: impl IntoIterator<Item = &'a Vec<DataType>>,
+        key_cols: &[usize],
+        records: &Records,
+        cacheable: bool,
+        now: time::Instant,
+    ) {
+        let in_flight = self.in_flight.entry(tag).or_default();
+        let empty = self.empty.entry(tag).or_default();
+        for key in keys {
+            in_flight.remove(key);
+            if cacheable
+                && !records
+                    .iter()
+                    .any(|r| key_cols.iter().enumerate().all(|(i, &c)| r[c] == key[i]))
+            {
+                empty.insert(key.clone(), now);
+            }
+        }
+    }
+
+    /// The tags along which some keys are remembered as empty.
+    pub(super) fn empty_tags(&self) -> Vec<Tag> {
+        self.empty
+            .iter()
+            .filter(|(_, e)| !e.is_empty())
+            .map(|(&tag, _)| tag)
+            .collect()
+    }
+
+    /// Records with the given keys were sent through the start of the replay path for `tag`, so
+    /// those keys may no longer be empty.
+    pub(super) fn invalidate(&mut self, tag: Tag, key_cols: &[usize], records: &Records) {
+        if let Some(empty) = self.empty.get_mut(&tag) {
+            for r in records.iter() {
+                let key: Vec<_> = key_cols.iter().map(|&c| r[c].clone()).collect();
+                empty.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(k: i32) -> Vec<DataType> {
+        vec![k.into()]
+    }
+
+    #[test]
+    fn dedups_in_flight_requests() {
+        let mut sg = ScatterGather::default();
+        let tag = Tag::new(1);
+        let now = time::Instant::now();
+
+        let (request, empty) = sg.plan(tag, vec![key(1), key(2)], now);
+        assert_eq!(request, vec![key(1), key(2)]);
+        assert!(empty.is_empty());
+
+        // asking again while the first request is outstanding sends nothing new
+        let (request, empty) = sg.plan(tag, vec![key(1), key(3)], now);
+        assert_eq!(request, vec![key(3)]);
+        assert!(empty.is_empty());
+
+        // until it has been answered
+        let records: Records = vec![vec![DataType::from(1), 10.into()]]
+            .into_iter()
+            .collect();
+        sg.answered(tag, &[key(1)], &[0], &records, false, now);
+        let (request, _) = sg.plan(tag, vec![key(1)], now);
+        assert_eq!(request, vec![key(1)]);
+    }
+
+    #[test]
+    fn remembers_empty_answers_briefly() {
+        let mut sg = ScatterGather::default();
+        let tag = Tag::new(1);
+        let now = time::Instant::now();
+
+        sg.plan(tag, vec![key(1), key(2)], now);
+        let records: Records = vec![vec![DataType::from(1), 10.into()]]
+            .into_iter()
+            .collect();
+        sg.answered(tag, &[key(1), key(2)], &[0], &records, true, now);
+        assert_eq!(sg.empty_tags(), vec![tag]);
+
+        // key 2 had no records, so it can be answered locally
+        let (request, empty) = sg.plan(tag, vec![key(1), key(2)], now);
+        assert_eq!(request, vec![key(1)]);
+        assert_eq!(empty, vec![key(2)]);
+
+        // but not once an update for it has come through
+        let update: Records = vec![vec![DataType::from(2), 20.into()]]
+            .into_iter()
+            .collect();
+        sg.invalidate(tag, &[0], &update);
+        let (request, empty) = sg.plan(tag, vec![key(2)], now);
+        assert_eq!(request, vec![key(2)]);
+        assert!(empty.is_empty());
+
+        // nor after a while
+        sg.answered(tag, &[key(2)], &[0], &Records::default(), true, now);
+        let (request, empty) = sg.plan(tag, vec![key(2)], now + NEGATIVE_TTL);
+        assert_eq!(request, vec![key(2)]);
+        assert!(empty.is_empty());
+    }
+}
@@ -93,6 +93,7 @@ impl GroupCommitQueueSet {
         let merged_dst = packets.peek().as_mut().unwrap().dst();
 
         let mut all_senders = vec![];
+        let mut merged_ack = AckLevel::Applied;
         let merged_data = packets.fold(Vec::new(), |mut acc, p| {
             match *p {
                 Packet::Input {
@@ -100,12 +101,17 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data } = unsafe { inner.take() };
+                    let Input { dst, data, ack } = unsafe { inner.take() };
 
                     assert_eq!(senders.len(), 0);
                     assert_eq!(merged_dst, dst);
                     acc.extend(data);
 
+                    // if any of the writers waits for its write to be synced, they all are
+                    if ack == AckLevel::Logged {
+                        merged_ack = AckLevel::Logged;
+                    }
+
                     if let Some(src) = src {
                         all_senders.push(src);
                     }
@@ -119,6 +125,7 @@ impl GroupCommitQueueSet {
             inner: LocalOrNot::new(Input {
                 dst: merged_dst,
                 data: merged_data,
+                ack: merged_ack,
             }),
             src: None,
             senders: all_senders,
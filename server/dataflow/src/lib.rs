@@ -72,6 +72,27 @@ pub enum DurabilityMode {
     Permanent,
 }
 
+/// When writes to persisted base tables are synced to disk.
+///
+/// Syncing less often makes writes cheaper, but a crash may lose the writes that were made since
+/// the last sync. Writes made with [`noria::AckLevel::Logged`] are always synced before they are
+/// acknowledged.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum FsyncPolicy {
+    /// Sync every batch of writes before it is applied.
+    EveryWrite,
+    /// Sync writes at least this often, but don't wait for the sync to apply them.
+    Interval(time::Duration),
+    /// Never explicitly sync writes, and leave it to the operating system to write them out.
+    OsBuffered,
+}
+
+impl Default for FsyncPolicy {
+    fn default() -> Self {
+        FsyncPolicy::EveryWrite
+    }
+}
+
 /// Parameters to control the operation of GroupCommitQueue.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct PersistenceParameters {
@@ -85,6 +106,9 @@ pub struct PersistenceParameters {
     pub log_dir: Option<PathBuf>,
     /// Number of background threads PersistentState can use (shared acrosss all worker threads).
     pub persistence_threads: i32,
+    /// When writes to persisted base tables are synced to disk.
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
 }
 
 impl Default for PersistenceParameters {
@@ -95,6 +119,7 @@ impl Default for PersistenceParameters {
             log_prefix: String::from("soup"),
             log_dir: None,
             persistence_threads: 1,
+            fsync: FsyncPolicy::default(),
         }
     }
 }
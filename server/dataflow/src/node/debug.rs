@@ -23,6 +23,7 @@ impl Node {
         idx: NodeIndex,
         detailed: bool,
         materialization_status: MaterializationStatus,
+        annotation: Option<&str>,
     ) -> String {
         let mut s = String::new();
         let border = match self.sharded_by {
@@ -118,6 +119,10 @@ impl Node {
                     .map(|d| format!("\"/set312/{}\"", (d % 12) + 1))
                     .unwrap_or_else(|| "white".into())
             ));
+            if annotation.is_some() {
+                // wrap the node's record so that the annotation gets its own row at the bottom
+                s.push_str("{ ");
+            }
 
             let materialized = match materialization_status {
                 MaterializationStatus::Not => "",
@@ -210,6 +215,9 @@ impl Node {
                     s.push_str("}");
                 }
             };
+            if let Some(annotation) = annotation {
+                s.push_str(&format!(" | {} }}", Self::escape(annotation)));
+            }
             s.push_str("\"]\n");
         }
 
@@ -140,6 +140,10 @@ impl Node {
         Ingredient::can_query_through(&**self)
     }
 
+    pub fn additive_column(&self) -> Option<usize> {
+        Ingredient::additive_column(&**self)
+    }
+
     pub fn is_join(&self) -> bool {
         Ingredient::is_join(&**self)
     }
@@ -214,7 +218,7 @@ impl Node {
 
 // derefs
 impl Node {
-    pub(crate) fn with_sharder_mut<F>(&mut self, f: F)
+    pub fn with_sharder_mut<F>(&mut self, f: F)
     where
         F: FnOnce(&mut special::Sharder),
     {
@@ -282,6 +286,13 @@ impl Node {
             _ => HashMap::new(),
         }
     }
+
+    pub fn suggest_index_type(&self, on: NodeIndex) -> IndexType {
+        match self.inner {
+            NodeType::Internal(ref i) => i.suggest_index_type(on),
+            _ => IndexType::HashMap,
+        }
+    }
 }
 
 impl Deref for Node {
@@ -35,7 +35,7 @@ impl Node {
                     Some(Packet::Input {
                         inner, mut senders, ..
                     }) => {
-                        let Input { dst, data } = unsafe { inner.take() };
+                        let Input { dst, data, ack } = unsafe { inner.take() };
                         let mut rs = b.process(addr, data, &*state);
 
                         // When a replay originates at a base node, we replay the data *through* that
@@ -47,6 +47,12 @@ impl Node {
                         // So: only materialize if the message we're processing is not a replay!
                         if keyed_by.is_none() {
                             materialize(&mut rs, None, state.get_mut(addr));
+                            if ack == AckLevel::Logged {
+                                // the writer is waiting for the write to be durable
+                                if let Some(s) = state.get_mut(addr) {
+                                    s.sync();
+                                }
+                            }
                         }
 
                         // Send write-ACKs to all the clients with updates that made
@@ -1,8 +1,12 @@
 use crate::prelude::*;
-use noria::{Modification, Operation, TableOperation};
+use chrono::naive::{NaiveDateTime, MIN_DATETIME};
+use nom_sql::Literal;
+use noria::{Modification, TableOperation};
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::ops::Bound;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use vec_map::VecMap;
 
 /// Base is used to represent the root nodes of the Noria data flow graph.
@@ -13,6 +17,10 @@ use vec_map::VecMap;
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Base {
     primary_key: Option<Vec<usize>>,
+    ttl: Option<(usize, Duration)>,
+    version: Option<usize>,
+    timestamps: Option<(usize, usize)>,
+    tombstone: Option<usize>,
 
     defaults: Vec<DataType>,
     dropped: Vec<usize>,
@@ -37,6 +45,161 @@ impl Base {
         self.primary_key.as_ref().map(|cols| &cols[..])
     }
 
+    /// Builder that expires rows once the time in column `column` lies more than `ttl` in the
+    /// past.
+    ///
+    /// The column should hold timestamps, or integer seconds since the epoch; rows where it is
+    /// NULL never expire. Expiring rows requires a primary key.
+    pub fn with_ttl(mut self, column: usize, ttl: Duration) -> Base {
+        self.ttl = Some((column, ttl));
+        self
+    }
+
+    pub fn ttl(&self) -> Option<(usize, Duration)> {
+        self.ttl
+    }
+
+    /// Builder that keeps a version counter for each row in column `column`.
+    ///
+    /// New rows start out at version 1, and each update of a row increments its version. Updates
+    /// made with `TableOperation::UpdateIf` are only applied to rows that are at the expected
+    /// version. Keeping versions requires a primary key.
+    pub fn with_version_column(mut self, column: usize) -> Base {
+        self.version = Some(column);
+        self
+    }
+
+    pub fn version_column(&self) -> Option<usize> {
+        self.version
+    }
+
+    /// Builder that records when each row was inserted in column `created`, and when it was last
+    /// inserted or updated in column `updated`.
+    ///
+    /// Both columns are filled in by the base as writes are processed, so any values given for
+    /// them in writes are ignored.
+    pub fn with_timestamp_columns(mut self, created: usize, updated: usize) -> Base {
+        self.timestamps = Some((created, updated));
+        self
+    }
+
+    pub fn timestamp_columns(&self) -> Option<(usize, usize)> {
+        self.timestamps
+    }
+
+    /// Builder that marks deleted rows by setting column `column` to 1, rather than removing
+    /// them.
+    ///
+    /// Live rows have 0 in the column. Deleted rows are ignored by updates, replaced by inserts
+    /// with the same key, and only removed by `TableOperation::Purge`. Soft deletes require a
+    /// primary key.
+    pub fn with_tombstone_column(mut self, column: usize) -> Base {
+        self.tombstone = Some(column);
+        self
+    }
+
+    pub fn tombstone_column(&self) -> Option<usize> {
+        self.tombstone
+    }
+
+    /// Whether `row` has been soft-deleted.
+    fn is_deleted(&self, row: &[DataType]) -> bool {
+        match self.tombstone.and_then(|col| row.get(col)) {
+            None | Some(DataType::None) => false,
+            Some(t) => i128::from(t) != 0,
+        }
+    }
+
+    /// Fill in the columns the base maintains itself in a row that is about to be inserted.
+    fn prepare_insert(&self, row: &mut [DataType], now: &DataType) {
+        if let Some(col) = self.version {
+            row[col] = 1.into();
+        }
+        if let Some((created, updated)) = self.timestamps {
+            row[created] = now.clone();
+            row[updated] = now.clone();
+        }
+        if let Some(col) = self.tombstone {
+            row[col] = 0.into();
+        }
+    }
+
+    /// The version of `row`, if this base keeps row versions.
+    fn version_of(&self, row: &[DataType]) -> Option<i128> {
+        let col = self.version?;
+        match row.get(col) {
+            None | Some(DataType::None) => Some(0),
+            Some(v) => Some(v.into()),
+        }
+    }
+
+    /// Produce deletes for all rows in `state` that have expired as of `now`.
+    ///
+    /// Bases that soft-delete rows get purges instead, since a delete would only mark an expired
+    /// row (and do nothing for one that is marked already), so the row would never go away.
+    ///
+    /// Expired rows are found with range scans over the ordered index that every base with a TTL
+    /// keeps on its TTL column, one for integer and one for timestamp values, so rows that have
+    /// not expired are never visited.
+    pub(crate) fn expire(&self, state: &dyn State, now: SystemTime) -> Vec<TableOperation> {
+        let (col, ttl) = match self.ttl {
+            Some(ttl) => ttl,
+            None => return Vec::new(),
+        };
+        let key_cols = self
+            .primary_key
+            .as_ref()
+            .expect("bases with a ttl must have a primary key");
+        let cutoff = match now
+            .checked_sub(ttl)
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        {
+            Some(d) => d.as_secs() as i64,
+            None => return Vec::new(),
+        };
+
+        let numeric = [DataType::BigInt(cutoff)];
+        let timestamps = (
+            [DataType::Timestamp(MIN_DATETIME)],
+            [DataType::Timestamp(NaiveDateTime::from_timestamp(
+                cutoff + 1,
+                0,
+            ))],
+        );
+        let ranges = vec![
+            (Bound::Unbounded, Bound::Included(&numeric[..])),
+            (
+                Bound::Included(&timestamps.0[..]),
+                Bound::Excluded(&timestamps.1[..]),
+            ),
+        ];
+
+        let mut deletes = Vec::new();
+        for range in ranges {
+            let rs = match state.lookup_range(&[col], range) {
+                LookupResult::Some(rs) => rs,
+                LookupResult::Missing => continue,
+            };
+            deletes.extend(
+                rs.into_iter()
+                    .filter(|r| match r[col] {
+                        DataType::None => false,
+                        DataType::Timestamp(ts) => ts.timestamp() <= cutoff,
+                        ref t => i64::from(t) <= cutoff,
+                    })
+                    .map(|r| {
+                        let key = key_cols.iter().map(|&c| r[c].clone()).collect();
+                        if self.tombstone.is_some() {
+                            TableOperation::Purge { key }
+                        } else {
+                            TableOperation::Delete { key }
+                        }
+                    }),
+            );
+        }
+        deletes
+    }
+
     /// Add a new column to this base node.
     pub fn add_column(&mut self, default: DataType) -> usize {
         assert!(
@@ -91,6 +254,10 @@ impl Clone for Base {
     fn clone(&self) -> Base {
         Base {
             primary_key: self.primary_key.clone(),
+            ttl: self.ttl,
+            version: self.version,
+            timestamps: self.timestamps,
+            tombstone: self.tombstone,
 
             defaults: self.defaults.clone(),
             dropped: self.dropped.clone(),
@@ -103,6 +270,10 @@ impl Default for Base {
     fn default() -> Self {
         Base {
             primary_key: None,
+            ttl: None,
+            version: None,
+            timestamps: None,
+            tombstone: None,
 
             defaults: Vec::new(),
             dropped: Vec::new(),
@@ -115,8 +286,11 @@ fn key_val(i: usize, col: usize, r: &TableOperation) -> &DataType {
     match *r {
         TableOperation::Insert(ref row) => &row[col],
         TableOperation::Delete { ref key } => &key[i],
+        TableOperation::Purge { ref key } => &key[i],
         TableOperation::Update { ref key, .. } => &key[i],
+        TableOperation::UpdateIf { ref key, .. } => &key[i],
         TableOperation::InsertOrUpdate { ref row, .. } => &row[col],
+        TableOperation::Replicate { ref key, .. } => &key[i],
     }
 }
 
@@ -132,22 +306,57 @@ impl Base {
         Clone::clone(self)
     }
 
+    /// Work out the records that processing `ops` would produce, without changing this base.
+    pub(crate) fn preview(
+        &self,
+        us: LocalNodeIndex,
+        ops: Vec<TableOperation>,
+        state: &StateMap,
+    ) -> Records {
+        self.clone().process(us, ops, state)
+    }
+
+    /// The operations that make another copy of this base end up with the rows this base
+    /// produced as `records`.
+    pub(crate) fn replicate(&self, records: &[Record]) -> Vec<TableOperation> {
+        let key_cols = self.primary_key.as_ref().map(|k| &k[..]).unwrap_or(&[]);
+        records
+            .iter()
+            .filter_map(|r| {
+                let key = key_cols.iter().map(|&c| r[c].clone()).collect();
+                match r {
+                    Record::Positive(row) => Some(TableOperation::Replicate {
+                        key,
+                        row: Some(row.clone()),
+                    }),
+                    // unkeyed bases never remove rows
+                    Record::Negative(_) if key_cols.is_empty() => None,
+                    Record::Negative(_) => Some(TableOperation::Replicate { key, row: None }),
+                }
+            })
+            .collect()
+    }
+
     pub(in crate::node) fn process(
         &mut self,
         us: LocalNodeIndex,
         mut ops: Vec<TableOperation>,
         state: &StateMap,
     ) -> Records {
+        // all the writes in a batch are stamped with the same time
+        let now = DataType::from(&Literal::CurrentTimestamp);
+
         if self.primary_key.is_none() || ops.is_empty() {
             return ops
                 .into_iter()
-                .map(|r| {
-                    if let TableOperation::Insert(mut r) = r {
+                .map(|r| match r {
+                    TableOperation::Insert(mut r) => {
                         self.fix(&mut r);
+                        self.prepare_insert(&mut r, &now);
                         Record::Positive(r)
-                    } else {
-                        unreachable!("unkeyed base got non-insert operation {:?}", r);
                     }
+                    TableOperation::Replicate { row: Some(r), .. } => Record::Positive(r),
+                    r => unreachable!("unkeyed base got non-insert operation {:?}", r),
                 })
                 .collect();
         }
@@ -199,28 +408,64 @@ impl Base {
                 was = current.clone();
             }
 
+            // updates skip soft-deleted rows, while inserts replace them
+            let deleted = current
+                .as_ref()
+                .map(|r| self.is_deleted(r))
+                .unwrap_or(false);
+
             let update = match op {
-                TableOperation::Insert(row) => {
-                    if let Some(ref was) = was {
-                        eprintln!("base ignoring {:?} since it already has {:?}", row, was);
-                    } else {
-                        //assert!(was.is_none());
-                        current = Some(Cow::Owned(row));
+                TableOperation::Insert(mut row) => {
+                    match was {
+                        Some(ref was) if !deleted => {
+                            eprintln!("base ignoring {:?} since it already has {:?}", row, was);
+                        }
+                        _ => {
+                            self.prepare_insert(&mut row, &now);
+                            current = Some(Cow::Owned(row));
+                        }
                     }
                     continue;
                 }
                 TableOperation::Delete { .. } => {
-                    if current.is_some() {
-                        current = None;
-                    } else {
-                        // supposed to delete a non-existing row?
-                        // TODO: warn?
+                    match self.tombstone {
+                        Some(col) if current.is_some() && !deleted => {
+                            let mut row = current.take().unwrap().into_owned();
+                            row[col] = 1.into();
+                            current = Some(Cow::Owned(row));
+                        }
+                        Some(_) => {}
+                        None if current.is_some() => current = None,
+                        None => {
+                            // supposed to delete a non-existing row?
+                            // TODO: warn?
+                        }
                     }
                     continue;
                 }
+                TableOperation::Purge { .. } => {
+                    current = None;
+                    continue;
+                }
+                TableOperation::Replicate { row, .. } => {
+                    current = row.map(Cow::Owned);
+                    continue;
+                }
+                TableOperation::Update { .. } | TableOperation::UpdateIf { .. } if deleted => {
+                    continue
+                }
                 TableOperation::Update { set, .. } => set,
-                TableOperation::InsertOrUpdate { row, update } => {
-                    if current.is_none() {
+                TableOperation::UpdateIf { set, version, .. } => {
+                    let at = current.as_ref().and_then(|r| self.version_of(r));
+                    if at != Some(i128::from(version)) {
+                        // someone else updated the row first (or it has no version at all)
+                        continue;
+                    }
+                    set
+                }
+                TableOperation::InsertOrUpdate { mut row, update } => {
+                    if current.is_none() || deleted {
+                        self.prepare_insert(&mut row, &now);
                         current = Some(Cow::Owned(row));
                         continue;
                     }
@@ -235,21 +480,21 @@ impl Base {
             }
 
             let mut future = current.unwrap().into_owned();
+            let next_version = self.version_of(&future).map(|v| v + 1);
             for (col, op) in update.into_iter().enumerate() {
                 // XXX: make sure user doesn't update primary key?
                 match op {
                     Modification::Set(v) => future[col] = v,
-                    Modification::Apply(op, v) => {
-                        let old: i128 = future[col].clone().into();
-                        let delta: i128 = v.into();
-                        future[col] = match op {
-                            Operation::Add => (old + delta).into(),
-                            Operation::Sub => (old - delta).into(),
-                        };
-                    }
+                    Modification::Apply(op, v) => future[col] = op.apply(&future[col], v),
                     Modification::None => {}
                 }
             }
+            if let Some(version) = next_version {
+                future[self.version.unwrap()] = version.into();
+            }
+            if let Some((_, updated)) = self.timestamps {
+                future[updated] = now.clone();
+            }
             current = Some(Cow::Owned(future));
         }
 
@@ -307,7 +552,12 @@ mod tests {
         assert_eq!(b.unmodified, true);
     }
 
-    fn test_lots_of_changes_in_same_batch(mut state: Box<dyn State>) {
+    /// Set up `b` as the only node of a graph, and return a function that feeds it writes.
+    fn one_base(
+        b: Base,
+        fields: &[&str],
+        mut state: Box<dyn State>,
+    ) -> impl FnMut(Vec<TableOperation>) -> Records {
         use crate::node;
         use crate::prelude::*;
 
@@ -319,8 +569,7 @@ mod tests {
             node::NodeType::Source,
         ));
 
-        let b = Base::new(vec![]).with_key(vec![0, 2]);
-        let global = graph.add_node(Node::new("b", &["x", "y", "z"], b));
+        let global = graph.add_node(Node::new("b", fields, b));
         graph.add_edge(source, global, ());
         let local = unsafe { LocalNodeIndex::make(0 as u32) };
         let mut ip: IndexPair = global.into();
@@ -344,11 +593,16 @@ mod tests {
         let n = graph[global].take();
         let mut n = n.finalize(&graph);
 
-        let mut one = move |u: Vec<TableOperation>| {
+        move |u: Vec<TableOperation>| {
             let mut m = n.get_base_mut().unwrap().process(local, u, &states);
             node::materialize(&mut m, None, states.get_mut(local));
             m
-        };
+        }
+    }
+
+    fn test_lots_of_changes_in_same_batch(state: Box<dyn State>) {
+        let b = Base::new(vec![]).with_key(vec![0, 2]);
+        let mut one = one_base(b, &["x", "y", "z"], state);
 
         assert_eq!(
             one(vec![
@@ -401,6 +655,271 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_keeps_row_versions() {
+        let b = Base::new(vec![]).with_key(vec![0]).with_version_column(2);
+        let mut one = one_base(b, &["id", "x", "v"], Box::new(MemoryState::default()));
+        let set = |x: i32| vec![Modification::None, Modification::Set(x.into())];
+        let rs = |rs: Vec<(Vec<DataType>, bool)>| -> Records { rs.into() };
+
+        // new rows start at version 1, whatever version they are inserted with
+        assert_eq!(
+            one(vec![TableOperation::Insert(vec![
+                1.into(),
+                10.into(),
+                7.into()
+            ])]),
+            rs(vec![(vec![1.into(), 10.into(), 1.into()], true)])
+        );
+
+        // conditional updates only apply at the expected version, and bump it
+        let update_if = |version, x| TableOperation::UpdateIf {
+            key: vec![1.into()],
+            set: set(x),
+            version,
+        };
+        assert_eq!(one(vec![update_if(2, 20)]), Records::default());
+        assert_eq!(
+            one(vec![update_if(1, 20)]),
+            rs(vec![
+                (vec![1.into(), 10.into(), 1.into()], false),
+                (vec![1.into(), 20.into(), 2.into()], true),
+            ])
+        );
+
+        // so of two updates based on the same version, only the first one is applied
+        assert_eq!(
+            one(vec![update_if(2, 30), update_if(2, 40)]),
+            rs(vec![
+                (vec![1.into(), 20.into(), 2.into()], false),
+                (vec![1.into(), 30.into(), 3.into()], true),
+            ])
+        );
+
+        // unconditional updates bump the version too
+        assert_eq!(
+            one(vec![TableOperation::Update {
+                key: vec![1.into()],
+                set: set(50),
+            }]),
+            rs(vec![
+                (vec![1.into(), 30.into(), 3.into()], false),
+                (vec![1.into(), 50.into(), 4.into()], true),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_stamps_rows() {
+        let b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_timestamp_columns(2, 3);
+        let mut one = one_base(
+            b,
+            &["id", "x", "created", "updated"],
+            Box::new(MemoryState::default()),
+        );
+        let positive = |rs: Records| -> Vec<Vec<DataType>> {
+            rs.into_iter()
+                .filter(|r| r.is_positive())
+                .map(|r| r.extract().0)
+                .collect()
+        };
+
+        // inserts set both timestamps, whatever they are inserted with
+        let rows = positive(one(vec![TableOperation::Insert(vec![
+            1.into(),
+            10.into(),
+            DataType::None,
+            7.into(),
+        ])]));
+        assert_eq!(rows.len(), 1);
+        let created = rows[0][2].clone();
+        assert!(matches!(created, DataType::Timestamp(_)));
+        assert_eq!(rows[0][3], created);
+
+        // updates only move the update timestamp
+        std::thread::sleep(Duration::from_millis(10));
+        let rows = positive(one(vec![TableOperation::Update {
+            key: vec![1.into()],
+            set: vec![Modification::None, Modification::Set(20.into())],
+        }]));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][1], 20.into());
+        assert_eq!(rows[0][2], created);
+        assert!(rows[0][3] > created);
+    }
+
+    #[test]
+    fn it_replicates_rows() {
+        let b = || {
+            Base::new(vec![])
+                .with_key(vec![0])
+                .with_version_column(2)
+                .with_timestamp_columns(3, 4)
+        };
+        let fields = &["id", "x", "v", "created", "updated"];
+        let mut primary = one_base(b(), fields, Box::new(MemoryState::default()));
+        let mut standby = one_base(b(), fields, Box::new(MemoryState::default()));
+        let set = |x: i32| vec![Modification::None, Modification::Set(x.into())];
+
+        // the standby ends up with exactly the primary's rows: the same timestamps, and none of
+        // the updates that the primary did not apply
+        let batches = vec![
+            vec![
+                TableOperation::Insert(vec![1.into(), 10.into(), 0.into(), 0.into(), 0.into()]),
+                TableOperation::Insert(vec![2.into(), 10.into(), 0.into(), 0.into(), 0.into()]),
+            ],
+            vec![
+                TableOperation::UpdateIf {
+                    key: vec![1.into()],
+                    set: set(20),
+                    version: 1,
+                },
+                TableOperation::UpdateIf {
+                    key: vec![1.into()],
+                    set: set(30),
+                    version: 1,
+                },
+            ],
+            vec![TableOperation::Delete {
+                key: vec![2.into()],
+            }],
+        ];
+        for ops in batches {
+            std::thread::sleep(Duration::from_millis(10));
+            let rs = primary(ops);
+            assert_eq!(standby(b().replicate(&rs)), rs);
+        }
+    }
+
+    #[test]
+    fn it_soft_deletes_rows() {
+        let b = Base::new(vec![]).with_key(vec![0]).with_tombstone_column(2);
+        let mut one = one_base(b, &["id", "x", "deleted"], Box::new(MemoryState::default()));
+        let rs = |rs: Vec<(Vec<DataType>, bool)>| -> Records { rs.into() };
+        let key = || vec![1.into()];
+
+        assert_eq!(
+            one(vec![TableOperation::Insert(vec![
+                1.into(),
+                10.into(),
+                DataType::None
+            ])]),
+            rs(vec![(vec![1.into(), 10.into(), 0.into()], true)])
+        );
+
+        // deletes only mark the row
+        assert_eq!(
+            one(vec![TableOperation::Delete { key: key() }]),
+            rs(vec![
+                (vec![1.into(), 10.into(), 0.into()], false),
+                (vec![1.into(), 10.into(), 1.into()], true),
+            ])
+        );
+
+        // deleted rows cannot be updated, but are replaced by inserts
+        assert_eq!(
+            one(vec![TableOperation::Update {
+                key: key(),
+                set: vec![Modification::None, Modification::Set(20.into())],
+            }]),
+            Records::default()
+        );
+        assert_eq!(
+            one(vec![TableOperation::Insert(vec![
+                1.into(),
+                30.into(),
+                DataType::None
+            ])]),
+            rs(vec![
+                (vec![1.into(), 10.into(), 1.into()], false),
+                (vec![1.into(), 30.into(), 0.into()], true),
+            ])
+        );
+
+        // purges remove rows for good
+        assert_eq!(
+            one(vec![TableOperation::Purge { key: key() }]),
+            rs(vec![(vec![1.into(), 30.into(), 0.into()], false)])
+        );
+    }
+
+    #[test]
+    fn it_expires_rows() {
+        let b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_ttl(1, Duration::from_secs(100));
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_index(&[1], IndexType::BTreeMap, None);
+        let ts = |secs| DataType::Timestamp(NaiveDateTime::from_timestamp(secs, 0));
+        let mut rs: Records = vec![
+            vec![1.into(), 850.into()],
+            vec![2.into(), 900.into()],
+            vec![3.into(), 950.into()],
+            vec![4.into(), DataType::None],
+            vec![5.into(), ts(899)],
+            vec![6.into(), ts(901)],
+        ]
+        .into_iter()
+        .collect();
+        state.process_records(&mut rs, None);
+
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let mut expired: Vec<_> = b
+            .expire(&state, now)
+            .into_iter()
+            .map(|op| match op {
+                TableOperation::Delete { key } => key,
+                op => unreachable!("{:?}", op),
+            })
+            .collect();
+        expired.sort();
+        assert_eq!(
+            expired,
+            vec![vec![1.into()], vec![2.into()], vec![5.into()]]
+        );
+
+        // bases without a ttl keep everything
+        assert!(Base::new(vec![])
+            .with_key(vec![0])
+            .expire(&state, now)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_purges_expired_soft_deleted_rows() {
+        let b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_ttl(1, Duration::from_secs(100))
+            .with_tombstone_column(2);
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_index(&[1], IndexType::BTreeMap, None);
+        let mut rs: Records = vec![
+            vec![1.into(), 850.into(), 0.into()],
+            vec![2.into(), 850.into(), 1.into()],
+            vec![3.into(), 950.into(), 1.into()],
+        ]
+        .into_iter()
+        .collect();
+        state.process_records(&mut rs, None);
+
+        // expired rows go away for good, whether or not they have been soft-deleted already
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let mut expired: Vec<_> = b
+            .expire(&state, now)
+            .into_iter()
+            .map(|op| match op {
+                TableOperation::Purge { key } => key,
+                op => unreachable!("{:?}", op),
+            })
+            .collect();
+        expired.sort();
+        assert_eq!(expired, vec![vec![1.into()], vec![2.into()]]);
+    }
+
     #[test]
     fn lots_of_changes_in_same_batch() {
         let state = MemoryState::default();
@@ -86,7 +86,9 @@ impl Egress {
                 m.take().unwrap()
             } else {
                 // we know this is a data (not a replay)
-                // because, a replay will force a take
+                // because, a replay will force a take.
+                // the clone shares the records with the original, so they are only copied if
+                // the receiving domain ends up modifying them.
                 m.as_ref().map(|m| Box::new(m.clone_data())).unwrap()
             };
 
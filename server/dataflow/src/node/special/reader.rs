@@ -1,5 +1,8 @@
 use crate::backlog;
 use crate::prelude::*;
+use nom_sql::OrderType;
+use noria::RefreshPolicy;
+use std::time;
 
 #[derive(Serialize, Deserialize)]
 pub struct Reader {
@@ -8,6 +11,33 @@ pub struct Reader {
 
     for_node: NodeIndex,
     state: Option<Vec<usize>>,
+    #[serde(default)]
+    refresh: RefreshPolicy,
+    /// the column that holds part of the value of a key in each of the key's rows, if the key's
+    /// records were split across the shards of an additive node above us
+    #[serde(default)]
+    split: Option<usize>,
+    /// the columns that clients should sort the rows of each key by
+    #[serde(default)]
+    order: Vec<(usize, OrderType)>,
+    /// the most rows that are kept for each key, if the query has a `LIMIT`
+    #[serde(default)]
+    limit: Option<usize>,
+    /// the columns that clients fill with the values bound to the parameters of the query's
+    /// projection, in the order of the parameters
+    #[serde(default)]
+    echoed: Vec<usize>,
+    /// whether keys are only held until they have been read, as the view merely passes on the
+    /// rows of a base table, which can be looked up again cheaply
+    #[serde(default)]
+    straight_through: bool,
+    /// whether keys are kept until a write evicts them, even though the view is computed beyond
+    /// the materialization frontier
+    #[serde(default)]
+    cached: bool,
+    /// when writes that have yet to be swapped in were first applied
+    #[serde(skip)]
+    stale_since: Option<time::Instant>,
 }
 
 impl Clone for Reader {
@@ -17,6 +47,14 @@ impl Clone for Reader {
             writer: None,
             state: self.state.clone(),
             for_node: self.for_node,
+            refresh: self.refresh,
+            split: self.split,
+            order: self.order.clone(),
+            limit: self.limit,
+            echoed: self.echoed.clone(),
+            straight_through: self.straight_through,
+            cached: self.cached,
+            stale_since: None,
         }
     }
 }
@@ -27,6 +65,14 @@ impl Reader {
             writer: None,
             state: None,
             for_node,
+            refresh: RefreshPolicy::default(),
+            split: None,
+            order: Vec::new(),
+            limit: None,
+            echoed: Vec::new(),
+            straight_through: false,
+            cached: false,
+            stale_since: None,
         }
     }
 
@@ -36,6 +82,71 @@ impl Reader {
         self.for_node
     }
 
+    /// Note that a key may have several rows, each holding part of the key's value in `column`,
+    /// which must be added up when the key is read.
+    pub fn set_split(&mut self, column: usize) {
+        self.split = Some(column);
+    }
+
+    /// The column whose values must be added up across the rows of a key, if any.
+    pub fn split(&self) -> Option<usize> {
+        self.split
+    }
+
+    /// Have clients sort the rows of each key by the given columns, as for an `ORDER BY`.
+    pub fn set_order(&mut self, order: Vec<(usize, OrderType)>) {
+        self.order = order;
+    }
+
+    /// The columns that the rows of each key are sorted by, if any.
+    pub fn order(&self) -> &[(usize, OrderType)] {
+        &self.order
+    }
+
+    /// Record that the query only keeps the first `limit` rows of each key, which bounds the
+    /// pages that clients can read.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = Some(limit);
+    }
+
+    /// The most rows that are kept for each key, if there is such a bound.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Have clients fill the given columns with the values bound to the parameters of the query's
+    /// projection, as for `SELECT ?, ...`.
+    pub fn set_echoed(&mut self, columns: Vec<usize>) {
+        self.echoed = columns;
+    }
+
+    /// The columns that echo parameters of the query's projection, if any.
+    pub fn echoed(&self) -> &[usize] {
+        &self.echoed
+    }
+
+    /// Only hold on to keys until they have been read, and look them up in the base table again
+    /// the next time they are read.
+    pub fn set_straight_through(&mut self) {
+        self.straight_through = true;
+    }
+
+    /// Whether keys are only held until they have been read.
+    pub fn is_straight_through(&self) -> bool {
+        self.straight_through
+    }
+
+    /// Keep keys until a write to them arrives, rather than purging them shortly after they were
+    /// replayed, since the view is computed beyond the materialization frontier.
+    pub fn set_cached(&mut self) {
+        self.cached = true;
+    }
+
+    /// Whether keys are kept until they are evicted by a write.
+    pub fn is_cached(&self) -> bool {
+        self.cached
+    }
+
     #[allow(dead_code)]
     fn writer(&self) -> Option<&backlog::WriteHandle> {
         self.writer.as_ref()
@@ -50,6 +161,14 @@ impl Reader {
             writer: self.writer.take(),
             state: self.state.clone(),
             for_node: self.for_node,
+            refresh: self.refresh,
+            split: self.split,
+            order: self.order.clone(),
+            limit: self.limit,
+            echoed: self.echoed.clone(),
+            straight_through: self.straight_through,
+            cached: self.cached,
+            stale_since: self.stale_since.take(),
         }
     }
 
@@ -81,6 +200,69 @@ impl Reader {
         }
     }
 
+    pub fn refresh_policy(&self) -> RefreshPolicy {
+        self.refresh
+    }
+
+    pub fn set_refresh_policy(&mut self, policy: RefreshPolicy) {
+        self.refresh = policy;
+        if policy == RefreshPolicy::Immediate {
+            self.swap();
+        }
+    }
+
+    fn swap(&mut self) {
+        if let Some(ref mut w) = self.writer {
+            w.evict_consumed();
+            w.swap();
+        }
+        self.stale_since = None;
+    }
+
+    /// Make the writes applied so far visible to reads, or hold them back until the next
+    /// refresh if the refresh policy allows it.
+    ///
+    /// `filled` says whether keys have been filled by a replay since the last refresh.
+    pub(crate) fn refresh(&mut self, filled: bool) {
+        let now = match self.refresh {
+            RefreshPolicy::Immediate => true,
+            RefreshPolicy::Periodic(_) => false,
+            RefreshPolicy::OnDemand(_) => {
+                filled
+                    && self
+                        .writer
+                        .as_ref()
+                        .map(|w| w.has_waiters())
+                        .unwrap_or(false)
+            }
+        };
+        if now {
+            self.swap();
+        } else if self.stale_since.is_none() {
+            self.stale_since = Some(time::Instant::now());
+        }
+    }
+
+    /// How long until held back writes are due to be made visible, if there are any.
+    pub(crate) fn refresh_due(&self, now: time::Instant) -> Option<time::Duration> {
+        let interval = match self.refresh {
+            RefreshPolicy::Immediate => time::Duration::from_millis(0),
+            RefreshPolicy::Periodic(interval) | RefreshPolicy::OnDemand(interval) => interval,
+        };
+        self.stale_since.map(|since| {
+            interval
+                .checked_sub(now.duration_since(since))
+                .unwrap_or(time::Duration::from_millis(0))
+        })
+    }
+
+    /// Make held back writes visible if they are due.
+    pub(crate) fn refresh_if_due(&mut self, now: time::Instant) {
+        if self.refresh_due(now) == Some(time::Duration::from_millis(0)) {
+            self.swap();
+        }
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.writer.as_ref().map(|w| w.is_empty()).unwrap_or(true)
     }
@@ -97,7 +279,38 @@ impl Reader {
         if let Some(ref mut handle) = self.writer {
             let mut rng = rand::thread_rng();
             bytes_freed = handle.evict_random_keys(&mut rng, n);
-            handle.swap();
+            self.swap();
+        }
+        bytes_freed
+    }
+
+    /// How long it has been since the reader was last read from.
+    pub(crate) fn idle_for(&self) -> Option<time::Duration> {
+        self.writer.as_ref().map(|w| w.idle_for())
+    }
+
+    /// Whether the reader's keys were all evicted because it was not read for a while, and it
+    /// has not been read from since.
+    pub fn is_hibernating(&self) -> bool {
+        self.writer
+            .as_ref()
+            .map(|w| w.is_hibernating())
+            .unwrap_or(false)
+    }
+
+    /// Evict all keys of a partial reader that has not been read for a while, returning the
+    /// number of bytes evicted. Reads that follow fill the keys they need again.
+    pub(crate) fn hibernate(&mut self) -> u64 {
+        if !self.is_partial() {
+            return 0;
+        }
+        self.writer.as_mut().unwrap().mark_hibernated();
+        let mut bytes_freed = 0;
+        while !self.is_empty() {
+            match self.evict_random_keys(16) {
+                0 => break,
+                freed => bytes_freed += freed,
+            }
         }
         bytes_freed
     }
@@ -108,7 +321,7 @@ impl Reader {
             for k in keys {
                 w.mut_with_key(&k[..]).mark_hole();
             }
-            w.swap();
+            self.swap();
         }
     }
 
@@ -120,7 +333,12 @@ impl Reader {
             if m.is_regular() && state.is_partial() {
                 m.map_data(|data| {
                     data.retain(|row| {
-                        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
+                        let entry = state.entry_from_record(&row[..]);
+                        if entry.filled_since_swap() {
+                            // the key has been filled, it just isn't visible yet
+                            return true;
+                        }
+                        match entry.try_find_and(|_| ()) {
                             Ok((None, _)) => {
                                 // row would miss in partial state.
                                 // leave it blank so later lookup triggers replay.
@@ -165,11 +383,11 @@ impl Reader {
             }
 
             state.add(m.take_data());
+        }
 
-            if swap {
-                // TODO: avoid doing the pointer swap if we didn't modify anything (inc. ts)
-                state.swap();
-            }
+        if swap && self.writer.is_some() {
+            // TODO: avoid doing the pointer swap if we didn't modify anything (inc. ts)
+            self.refresh(false);
         }
     }
 }
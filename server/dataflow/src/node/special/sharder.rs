@@ -1,12 +1,106 @@
 use crate::payload;
 use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use vec_map::VecMap;
 
+/// The number of keys whose update counts are tracked by each sharder.
+const TRACKED_KEYS: usize = 64;
+
+/// Once this many updates have been counted, all the counts are halved, so that the counts
+/// reflect recent updates more than old ones.
+const DECAY_EVERY: u64 = 1 << 16;
+
+/// The fewest updates that must have been counted before any key is considered hot.
+const MIN_UPDATES: u64 = 1024;
+
+/// Approximate update counts for the most frequently updated keys, using the space-saving
+/// algorithm.
+///
+/// A key that is not tracked takes the place of the tracked key with the lowest count, and
+/// inherits that count. Counts may therefore overestimate, but never underestimate, how often a
+/// key has been updated, and any key that makes up more than `1 / TRACKED_KEYS` of the updates is
+/// sure to be tracked.
+#[derive(Clone, Debug, Default)]
+struct HotKeys {
+    counts: HashMap<DataType, u64>,
+    total: u64,
+}
+
+impl HotKeys {
+    fn count(&mut self, key: &DataType) {
+        if let Some(n) = self.counts.get_mut(key) {
+            *n += 1;
+        } else if self.counts.len() < TRACKED_KEYS {
+            self.counts.insert(key.clone(), 1);
+        } else {
+            let (coldest, n) = self
+                .counts
+                .iter()
+                .min_by_key(|&(_, &n)| n)
+                .map(|(k, &n)| (k.clone(), n))
+                .unwrap();
+            self.counts.remove(&coldest);
+            self.counts.insert(key.clone(), n + 1);
+        }
+
+        self.total += 1;
+        if self.total >= DECAY_EVERY {
+            self.total /= 2;
+            for n in self.counts.values_mut() {
+                *n /= 2;
+            }
+            self.counts.retain(|_, &mut n| n != 0);
+        }
+    }
+
+    /// Whether `key` alone accounts for more than one shard's fair share of the updates.
+    fn is_hot(&self, key: &DataType, shards: usize) -> bool {
+        self.total >= MIN_UPDATES
+            && self
+                .counts
+                .get(key)
+                .map(|&n| n * shards as u64 > self.total)
+                .unwrap_or(false)
+    }
+
+    /// The keys that are hot, and roughly how many of the recent updates were to them.
+    fn hot(&self, shards: usize) -> Vec<(DataType, u64)> {
+        let mut hot: Vec<_> = self
+            .counts
+            .iter()
+            .filter(|&(k, _)| self.is_hot(k, shards))
+            .map(|(k, &n)| (k.clone(), n))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1));
+        hot
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Sharder {
     txs: Vec<(LocalNodeIndex, ReplicaAddr)>,
     sharded: VecMap<Box<Packet>>,
     shard_by: usize,
+
+    /// Whether the records of hot keys are spread over all the shards rather than being sent to
+    /// the shard that owns their key.
+    ///
+    /// This is only correct if the child adds up the values each of its shards computes for a
+    /// key; see `Ingredient::additive_column`.
+    #[serde(default)]
+    split_hot_keys: bool,
+    #[serde(skip)]
+    hot_keys: HotKeys,
+    /// The keys whose records are spread over the shards. A key stays split once it has turned
+    /// hot, so that the shards are always replayed the same parts of it that they are sent
+    /// updates for.
+    #[serde(skip)]
+    split_keys: HashSet<DataType>,
+    /// The keys that were split since the domain last took them. The shards' state for these
+    /// keys was built from the records routed by key, and must be evicted.
+    #[serde(skip)]
+    newly_split: Vec<DataType>,
 }
 
 impl Clone for Sharder {
@@ -17,6 +111,10 @@ impl Clone for Sharder {
             txs: Vec::new(),
             sharded: Default::default(),
             shard_by: self.shard_by,
+            split_hot_keys: self.split_hot_keys,
+            hot_keys: HotKeys::default(),
+            split_keys: HashSet::new(),
+            newly_split: Vec::new(),
         }
     }
 }
@@ -27,6 +125,10 @@ impl Sharder {
             txs: Default::default(),
             shard_by: by,
             sharded: VecMap::default(),
+            split_hot_keys: false,
+            hot_keys: HotKeys::default(),
+            split_keys: HashSet::new(),
+            newly_split: Vec::new(),
         }
     }
 
@@ -37,9 +139,33 @@ impl Sharder {
             txs,
             sharded: VecMap::default(),
             shard_by: self.shard_by,
+            split_hot_keys: self.split_hot_keys,
+            hot_keys: mem::take(&mut self.hot_keys),
+            split_keys: mem::take(&mut self.split_keys),
+            newly_split: mem::take(&mut self.newly_split),
         }
     }
 
+    /// Spread the records of hot keys over all the shards.
+    pub fn split_hot_keys(&mut self) {
+        self.split_hot_keys = true;
+    }
+
+    pub fn splits_hot_keys(&self) -> bool {
+        self.split_hot_keys
+    }
+
+    /// The keys that have recently made up more than one shard's fair share of the records that
+    /// passed through this sharder, and roughly how many of those records had each key.
+    pub fn hot_keys(&self) -> Vec<(DataType, u64)> {
+        self.hot_keys.hot(self.txs.len())
+    }
+
+    /// The keys that this sharder started to split since it was last asked.
+    pub fn take_newly_split(&mut self) -> Vec<DataType> {
+        std::mem::replace(&mut self.newly_split, Vec::new())
+    }
+
     pub fn add_sharded_child(&mut self, dst: LocalNodeIndex, txs: Vec<ReplicaAddr>) {
         assert_eq!(self.txs.len(), 0);
         // TODO: add support for "shared" sharder?
@@ -54,7 +180,14 @@ impl Sharder {
 
     #[inline]
     fn to_shard(&self, r: &Record) -> usize {
-        self.shard(&r[self.shard_by])
+        let key = &r[self.shard_by];
+        if self.split_keys.contains(key) {
+            // the same row always goes to the same shard, so that its removal undoes its addition
+            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+            r.rec().hash(&mut hasher);
+            return hasher.finish() as usize % self.txs.len();
+        }
+        self.shard(key)
     }
 
     #[inline]
@@ -72,7 +205,21 @@ impl Sharder {
     ) {
         // we need to shard the records inside `m` by their key,
         let mut m = m.take().unwrap();
+        // replays must not change which keys are hot, or the shards that ask for the same key
+        // could each be sent their share of it under a different split.
+        let count = m.is_regular();
         for record in m.take_data() {
+            if count {
+                let key = &record[self.shard_by];
+                self.hot_keys.count(key);
+                if self.split_hot_keys
+                    && !self.split_keys.contains(key)
+                    && self.hot_keys.is_hot(key, self.txs.len())
+                {
+                    self.split_keys.insert(key.clone());
+                    self.newly_split.push(key.clone());
+                }
+            }
             let shard = self.to_shard(&record);
             let p = self
                 .sharded
@@ -171,7 +318,7 @@ impl Sharder {
     ) {
         assert!(!is_sharded);
 
-        if key_columns.len() == 1 && key_columns[0] == self.shard_by {
+        if key_columns.len() == 1 && key_columns[0] == self.shard_by && !self.split_hot_keys {
             // Send only to the shards that must evict something.
             for key in keys {
                 let shard = self.shard(&key[0]);
@@ -196,9 +343,9 @@ impl Sharder {
             }
         } else {
             assert_eq!(!key_columns.len(), 0);
-            assert!(!key_columns.contains(&self.shard_by));
+            assert!(self.split_hot_keys || !key_columns.contains(&self.shard_by));
 
-            // send to all shards
+            // send to all shards, since any of them may hold part of a split key
             for &mut (dst, addr) in self.txs.iter_mut() {
                 output.send(
                     addr,
@@ -212,3 +359,43 @@ impl Sharder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_finds_hot_keys() {
+        let mut hk = HotKeys::default();
+        for i in 0..10_000 {
+            // one key in three is the hot one, and the rest are all different
+            let key = if i % 3 == 0 {
+                0.into()
+            } else {
+                DataType::from(i)
+            };
+            hk.count(&key);
+        }
+        assert!(hk.is_hot(&0.into(), 4));
+        assert!(!hk.is_hot(&1.into(), 4));
+        // a third of the updates is less than a single shard's share if there are only two
+        assert!(!hk.is_hot(&0.into(), 2));
+
+        let hot = hk.hot(4);
+        assert_eq!(hot.len(), 1);
+        assert_eq!(hot[0].0, 0.into());
+        assert!(hot[0].1 >= 3333);
+    }
+
+    #[test]
+    fn it_forgets_old_updates() {
+        let mut hk = HotKeys::default();
+        for _ in 0..DECAY_EVERY {
+            hk.count(&0.into());
+        }
+        for i in 0..4 * DECAY_EVERY {
+            hk.count(&DataType::from(i as i64 + 1));
+        }
+        assert!(!hk.is_hot(&0.into(), 4));
+    }
+}
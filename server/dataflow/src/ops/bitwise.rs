@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::prelude::*;
+
+/// Supported bitwise and boolean aggregations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bitwise {
+    /// The bitwise AND of the `over` column for all records of each group.
+    BitAnd,
+    /// The bitwise OR of the `over` column for all records of each group.
+    BitOr,
+    /// Whether the `over` column is true (i.e., non-zero) for all records of each group.
+    BoolAnd,
+    /// Whether the `over` column is true (i.e., non-zero) for any record of each group.
+    BoolOr,
+}
+
+impl Bitwise {
+    /// Construct a new `BitwiseOperator` that performs this operation.
+    ///
+    /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
+    /// from the `src` node in the graph), and use the columns in the `group_by` array as a group
+    /// identifier. The `over` column should not be in the `group_by` array.
+    pub fn over(self, src: NodeIndex, over: usize, group_by: &[usize]) -> BitwiseOperator {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by aggregation column"
+        );
+        BitwiseOperator {
+            src: src.into(),
+            us: None,
+            op: self,
+            over,
+            group: group_by.into(),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// The number of bits that are tracked for each value.
+    fn width(self) -> usize {
+        match self {
+            Bitwise::BitAnd | Bitwise::BitOr => 64,
+            Bitwise::BoolAnd | Bitwise::BoolOr => 1,
+        }
+    }
+}
+
+/// How many of a group's records have each bit set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Group {
+    /// the number of records in the group, including those with `NULL` values
+    rows: usize,
+    /// the number of records in the group with non-`NULL` values
+    values: usize,
+    /// for every bit, the number of values that have it set
+    bits: Vec<usize>,
+}
+
+/// `BitwiseOperator` computes `BIT_AND`, `BIT_OR`, `BOOL_AND`, and `BOOL_OR` for each group.
+///
+/// `BitwiseOperator` nodes are constructed through `Bitwise` variants using `Bitwise::over`.
+///
+/// The current AND or OR of a group is not enough to tell what it becomes when a record is
+/// removed, so the operator instead counts, for every bit, how many of the group's values have
+/// that bit set. A bit is set in the AND if every value has it set, and in the OR if any value
+/// does. Those counts are kept in memory alongside the operator's materialization, so it must be
+/// fully materialized.
+///
+/// The output records consist of the group columns followed by the aggregated value. `NULL`
+/// values are ignored, and a group whose values are all `NULL` aggregates to `NULL`. Groups that
+/// have no records left are removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BitwiseOperator {
+    src: IndexPair,
+    us: Option<IndexPair>,
+
+    op: Bitwise,
+    over: usize,
+    group: Vec<usize>,
+
+    groups: HashMap<Vec<DataType>, Group>,
+}
+
+impl BitwiseOperator {
+    /// The aggregation that this operator computes.
+    pub fn op(&self) -> Bitwise {
+        self.op
+    }
+
+    /// The bits of the given value, or `None` if it is `NULL`.
+    fn bits(&self, v: &DataType) -> Option<u64> {
+        let n = match *v {
+            DataType::None => return None,
+            DataType::Int(n) => n as u64,
+            DataType::UnsignedInt(n) => u64::from(n),
+            DataType::BigInt(n) => n as u64,
+            DataType::UnsignedBigInt(n) => n,
+            ref x => unreachable!("tried to aggregate over {:?}", x),
+        };
+        match self.op {
+            Bitwise::BitAnd | Bitwise::BitOr => Some(n),
+            Bitwise::BoolAnd | Bitwise::BoolOr => Some((n != 0) as u64),
+        }
+    }
+
+    fn update(&self, g: &mut Group, r: &[DataType], positive: bool) {
+        if positive {
+            g.rows += 1;
+        } else {
+            g.rows -= 1;
+        }
+
+        let n = match self.bits(&r[self.over]) {
+            Some(n) => n,
+            None => return,
+        };
+        if g.bits.is_empty() {
+            g.bits = vec![0; self.op.width()];
+        }
+        if positive {
+            g.values += 1;
+        } else {
+            g.values -= 1;
+        }
+        for (bit, count) in g.bits.iter_mut().enumerate() {
+            if n & (1 << bit) != 0 {
+                if positive {
+                    *count += 1;
+                } else {
+                    *count -= 1;
+                }
+            }
+        }
+    }
+
+    fn value(&self, g: &Group) -> DataType {
+        if g.values == 0 {
+            return DataType::None;
+        }
+
+        let set = |count: usize| match self.op {
+            Bitwise::BitAnd | Bitwise::BoolAnd => count == g.values,
+            Bitwise::BitOr | Bitwise::BoolOr => count != 0,
+        };
+        let n = g
+            .bits
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| set(count))
+            .fold(0u64, |n, (bit, _)| n | (1 << bit));
+        match self.op {
+            Bitwise::BitAnd | Bitwise::BitOr => (n as i64).into(),
+            Bitwise::BoolAnd | Bitwise::BoolOr => (n as i32).into(),
+        }
+    }
+}
+
+fn output(group: &[DataType], value: DataType) -> Vec<DataType> {
+    let mut row = Vec::with_capacity(group.len() + 1);
+    row.extend(group.iter().cloned());
+    row.push(value);
+    row
+}
+
+impl Ingredient for BitwiseOperator {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[self.src.as_global()];
+        assert!(
+            self.over < srcn.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.src.remap(remap);
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        // collect the records for each group, so that each group's value changes only once
+        let mut batches: BTreeMap<Vec<DataType>, Vec<Record>> = BTreeMap::new();
+        for r in rs {
+            let group: Vec<_> = self.group.iter().map(|&c| r[c].clone()).collect();
+            batches.entry(group).or_insert_with(Vec::new).push(r);
+        }
+
+        let mut out = Vec::new();
+        for (group, rs) in batches {
+            let mut g = self.groups.remove(&group).unwrap_or_default();
+            let old = if g.rows != 0 {
+                Some(self.value(&g))
+            } else {
+                None
+            };
+            for r in &rs {
+                debug_assert!(
+                    r.is_positive() || g.rows != 0,
+                    "negative for a group that has no records"
+                );
+                self.update(&mut g, r, r.is_positive());
+            }
+            let new = if g.rows != 0 {
+                Some(self.value(&g))
+            } else {
+                None
+            };
+
+            if old != new {
+                if let Some(old) = old {
+                    out.push(Record::Negative(output(&group, old)));
+                }
+                if let Some(new) = new {
+                    out.push(Record::Positive(output(&group, new)));
+                }
+            }
+            if g.rows != 0 {
+                self.groups.insert(group, g);
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // index by our primary key
+        Some((this, (0..self.group.len()).collect()))
+            .into_iter()
+            .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.group.len() {
+            return None;
+        }
+        Some(vec![(self.src.as_global(), self.group[col])])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        let op = match self.op {
+            Bitwise::BitAnd => "&",
+            Bitwise::BitOr => "|",
+            Bitwise::BoolAnd => "∧",
+            Bitwise::BoolOr => "∨",
+        };
+        if !detailed {
+            return String::from(op);
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({}) γ[{}]", op, self.over, group_cols)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if column == self.group.len() {
+            vec![(self.src.as_global(), None)]
+        } else {
+            vec![(self.src.as_global(), Some(self.group[column]))]
+        }
+    }
+
+    fn is_selective(&self) -> bool {
+        true
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(op: Bitwise) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op("agg", &["x", "ys"], op.over(s.as_global(), 1, &[0]), true);
+        g
+    }
+
+    fn latest(rs: Records) -> Option<DataType> {
+        rs.into_iter()
+            .find(|r| r.is_positive())
+            .map(|r| r.extract().0[1].clone())
+    }
+
+    #[test]
+    fn it_describes() {
+        let s = 0.into();
+        assert_eq!(
+            Bitwise::BitAnd.over(s, 1, &[0, 2]).description(true),
+            "&(1) γ[0, 2]"
+        );
+        assert_eq!(Bitwise::BoolOr.over(s, 1, &[0]).description(false), "∨");
+    }
+
+    #[test]
+    fn it_ands_bits() {
+        let mut c = setup(Bitwise::BitAnd);
+
+        let rs = c.narrow_one_row(vec![1.into(), 0b111.into()], true);
+        assert_eq!(latest(rs), Some(0b111.into()));
+        let rs = c.narrow_one_row(vec![1.into(), 0b101.into()], true);
+        assert_eq!(latest(rs), Some(0b101.into()));
+        let rs = c.narrow_one_row(vec![1.into(), 0b100.into()], true);
+        assert_eq!(latest(rs), Some(0b100.into()));
+
+        // removing a value brings back the bits it had cleared
+        let rs = c.narrow_one_row((vec![1.into(), 0b100.into()], false), true);
+        assert_eq!(latest(rs), Some(0b101.into()));
+
+        // NULLs do not clear any bits
+        let rs = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert!(rs.is_empty());
+
+        // other groups are unaffected
+        let rs = c.narrow_one_row(vec![2.into(), 0b010.into()], true);
+        assert_eq!(latest(rs), Some(0b010.into()));
+    }
+
+    #[test]
+    fn it_ors_bits() {
+        let mut c = setup(Bitwise::BitOr);
+
+        c.narrow_one_row(vec![1.into(), 0b001.into()], true);
+        c.narrow_one_row(vec![1.into(), 0b011.into()], true);
+        let rs = c.narrow_one_row(vec![1.into(), 0b100.into()], true);
+        assert_eq!(latest(rs), Some(0b111.into()));
+
+        // a bit stays set as long as any value has it
+        let rs = c.narrow_one_row((vec![1.into(), 0b001.into()], false), true);
+        assert!(rs.is_empty());
+        let rs = c.narrow_one_row((vec![1.into(), 0b011.into()], false), true);
+        assert_eq!(latest(rs), Some(0b100.into()));
+
+        // negative values use all the bits
+        let rs = c.narrow_one_row(vec![1.into(), (-1).into()], true);
+        assert_eq!(latest(rs), Some((-1).into()));
+    }
+
+    #[test]
+    fn it_aggregates_bools() {
+        let mut c = setup(Bitwise::BoolAnd);
+        let rs = c.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(latest(rs), Some(1.into()));
+        let rs = c.narrow_one_row(vec![1.into(), 0.into()], true);
+        assert_eq!(latest(rs), Some(0.into()));
+        let rs = c.narrow_one_row((vec![1.into(), 0.into()], false), true);
+        assert_eq!(latest(rs), Some(1.into()));
+
+        let mut c = setup(Bitwise::BoolOr);
+        let rs = c.narrow_one_row(vec![1.into(), 0.into()], true);
+        assert_eq!(latest(rs), Some(0.into()));
+        let rs = c.narrow_one_row(vec![1.into(), 5.into()], true);
+        assert_eq!(latest(rs), Some(1.into()));
+
+        // a group with only NULLs is NULL
+        let mut c = setup(Bitwise::BoolOr);
+        let rs = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert_eq!(latest(rs), Some(DataType::None));
+
+        // and removing its last record removes it
+        let rs = c.narrow_one_row((vec![1.into(), DataType::None], false), true);
+        assert_eq!(rs.len(), 1);
+        assert!(!rs.iter().next().unwrap().is_positive());
+    }
+}
@@ -4,7 +4,7 @@ use std::fmt::{self, Display};
 use std::sync;
 
 use crate::prelude::*;
-pub use nom_sql::Operator;
+pub use nom_sql::{ArithmeticOperator, Operator};
 
 /// Filters incoming records according to some filter.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +17,37 @@ pub struct Filter {
 pub enum Value {
     Constant(DataType),
     Column(usize),
+    /// An arithmetic expression over two other values, such as `col: 0 + col: 1`.
+    Expression(ArithmeticOperator, Box<Value>, Box<Value>),
+}
+
+impl Value {
+    /// The value for row `r`.
+    ///
+    /// Expressions over a `NULL` or over anything but numbers are `NULL`, as are divisions by
+    /// zero.
+    pub fn eval<'a>(&'a self, r: &'a [DataType]) -> Cow<'a, DataType> {
+        match *self {
+            Value::Constant(ref dt) => Cow::Borrowed(dt),
+            Value::Column(c) => Cow::Borrowed(&r[c]),
+            Value::Expression(ref op, ref left, ref right) => {
+                Cow::Owned(arithmetic(op, &left.eval(r), &right.eval(r)))
+            }
+        }
+    }
+
+    /// The columns of the row that the value is computed from.
+    pub fn columns(&self) -> Vec<usize> {
+        match *self {
+            Value::Constant(_) => vec![],
+            Value::Column(c) => vec![c],
+            Value::Expression(_, ref left, ref right) => {
+                let mut cols = left.columns();
+                cols.extend(right.columns());
+                cols
+            }
+        }
+    }
 }
 
 impl From<DataType> for Value {
@@ -30,6 +61,15 @@ impl Display for Value {
         match *self {
             Value::Constant(ref c) => write!(f, "{}", c),
             Value::Column(ref ci) => write!(f, "col: {}", ci),
+            Value::Expression(ref op, ref left, ref right) => {
+                let op = match *op {
+                    ArithmeticOperator::Add => "+",
+                    ArithmeticOperator::Subtract => "-",
+                    ArithmeticOperator::Multiply => "*",
+                    ArithmeticOperator::Divide => "/",
+                };
+                write!(f, "({} {} {})", left, op, right)
+            }
         }
     }
 }
@@ -38,6 +78,151 @@ impl Display for Value {
 pub enum FilterCondition {
     Comparison(Operator, Value),
     In(Vec<DataType>),
+    /// Matches if any of the conditions on the same column does.
+    Any(Vec<FilterCondition>),
+    /// Matches if the first value compares to the second as given, whatever the value of the
+    /// column that the condition is on. This is used when the left-hand side of a comparison is
+    /// an expression rather than a column.
+    Expression(Value, Operator, Value),
+    /// Matches if the given columns of the row hold one of the tuples, whatever the value of the
+    /// column that the condition is on. This is used for row comparisons such as
+    /// `(a, b) IN ((1, 2), (3, 4))`.
+    InTuples(Vec<usize>, Vec<Vec<DataType>>),
+}
+
+fn compare(op: &Operator, d: &DataType, v: &DataType) -> bool {
+    match *op {
+        Operator::Equal => d == v,
+        Operator::NotEqual => d != v,
+        Operator::Greater => d > v,
+        Operator::GreaterOrEqual => d >= v,
+        Operator::Less => d < v,
+        Operator::LessOrEqual => d <= v,
+        Operator::In => unreachable!(),
+        _ => unimplemented!(),
+    }
+}
+
+impl FilterCondition {
+    /// Check whether `d`, the value of a column in row `r`, satisfies this condition.
+    pub fn matches(&self, d: &DataType, r: &[DataType]) -> bool {
+        match *self {
+            FilterCondition::Comparison(ref op, ref f) => compare(op, d, &f.eval(r)),
+            FilterCondition::In(ref fs) => fs.contains(d),
+            FilterCondition::Any(ref cs) => cs.iter().any(|c| c.matches(d, r)),
+            FilterCondition::Expression(ref left, ref op, ref right) => {
+                compare(op, &left.eval(r), &right.eval(r))
+            }
+            FilterCondition::InTuples(ref cols, ref tuples) => tuples
+                .iter()
+                .any(|t| cols.iter().zip(t).all(|(&c, v)| r[c] == *v)),
+        }
+    }
+}
+
+impl Display for FilterCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FilterCondition::Comparison(ref op, ref x) => write!(f, "{} {}", op, x),
+            FilterCondition::In(ref xs) => write!(
+                f,
+                "IN ({})",
+                xs.iter()
+                    .map(|d| format!("{}", d))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            FilterCondition::Any(ref cs) => write!(
+                f,
+                "({})",
+                cs.iter()
+                    .map(|c| format!("{}", c))
+                    .collect::<Vec<_>>()
+                    .join(" OR ")
+            ),
+            FilterCondition::Expression(ref left, ref op, ref right) => {
+                write!(f, "{} {} {}", left, op, right)
+            }
+            FilterCondition::InTuples(ref cols, ref tuples) => {
+                let join = |xs: Vec<String>| xs.join(", ");
+                write!(
+                    f,
+                    "({}) IN ({})",
+                    join(cols.iter().map(|c| format!("col: {}", c)).collect()),
+                    join(
+                        tuples
+                            .iter()
+                            .map(|t| format!(
+                                "({})",
+                                join(t.iter().map(|d| format!("{}", d)).collect())
+                            ))
+                            .collect()
+                    )
+                )
+            }
+        }
+    }
+}
+
+/// `left op right`, or `NULL` if either is not a number, when dividing by zero, and when the
+/// result does not fit a `DataType`. Integers stay integers, of the type of the operands if the
+/// result fits it.
+fn arithmetic(op: &ArithmeticOperator, left: &DataType, right: &DataType) -> DataType {
+    use std::convert::TryFrom;
+
+    let int = |v: &DataType| match *v {
+        DataType::Int(i) => Some(i128::from(i)),
+        DataType::UnsignedInt(i) => Some(i128::from(i)),
+        DataType::BigInt(i) => Some(i128::from(i)),
+        DataType::UnsignedBigInt(i) => Some(i128::from(i)),
+        _ => None,
+    };
+    let real = |v: &DataType| match *v {
+        DataType::Real(..) => Some(f64::from(v)),
+        _ => int(v).map(|i| i as f64),
+    };
+
+    if let (Some(a), Some(b)) = (int(left), int(right)) {
+        let r = match *op {
+            ArithmeticOperator::Add => a.checked_add(b),
+            ArithmeticOperator::Subtract => a.checked_sub(b),
+            ArithmeticOperator::Multiply => a.checked_mul(b),
+            ArithmeticOperator::Divide => a.checked_div(b),
+        };
+        let r = match r {
+            Some(r) => r,
+            None => return DataType::None,
+        };
+        let same = match (left, right) {
+            (&DataType::Int(_), &DataType::Int(_)) => i32::try_from(r).ok().map(DataType::from),
+            (&DataType::UnsignedInt(_), &DataType::UnsignedInt(_)) => {
+                u32::try_from(r).ok().map(DataType::from)
+            }
+            _ => None,
+        };
+        return same
+            .or_else(|| i64::try_from(r).ok().map(DataType::from))
+            .or_else(|| u64::try_from(r).ok().map(DataType::from))
+            .unwrap_or(DataType::None);
+    }
+
+    match (real(left), real(right)) {
+        (Some(a), Some(b)) => {
+            let r = match *op {
+                ArithmeticOperator::Add => a + b,
+                ArithmeticOperator::Subtract => a - b,
+                ArithmeticOperator::Multiply => a * b,
+                ArithmeticOperator::Divide if b == 0.0 => return DataType::None,
+                ArithmeticOperator::Divide => a / b,
+            };
+            if r.is_finite() {
+                DataType::from(r)
+            } else {
+                DataType::None
+            }
+        }
+        _ => DataType::None,
+    }
 }
 
 impl Filter {
@@ -50,6 +235,11 @@ impl Filter {
             filter: sync::Arc::new(Vec::from(filter)),
         }
     }
+
+    /// Whether the row `r` passes the filter.
+    pub fn admits(&self, r: &[DataType]) -> bool {
+        self.filter.iter().all(|(i, cond)| cond.matches(&r[*i], r))
+    }
 }
 
 impl Ingredient for Filter {
@@ -81,31 +271,7 @@ impl Ingredient for Filter {
         _: &DomainNodes,
         _: &StateMap,
     ) -> ProcessingResult {
-        rs.retain(|r| {
-            self.filter.iter().all(|(i, cond)| {
-                // check if this filter matches
-                let d = &r[*i];
-                match cond {
-                    FilterCondition::Comparison(ref op, ref f) => {
-                        let v = match *f {
-                            Value::Constant(ref dt) => dt,
-                            Value::Column(c) => &r[c],
-                        };
-                        match *op {
-                            Operator::Equal => d == v,
-                            Operator::NotEqual => d != v,
-                            Operator::Greater => d > v,
-                            Operator::GreaterOrEqual => d >= v,
-                            Operator::Less => d < v,
-                            Operator::LessOrEqual => d <= v,
-                            Operator::In => unreachable!(),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    FilterCondition::In(ref fs) => fs.contains(d),
-                }
-            })
-        });
+        rs.retain(|r| self.admits(r));
 
         ProcessingResult {
             results: rs,
@@ -150,6 +316,12 @@ impl Ingredient for Filter {
                             .collect::<Vec<_>>()
                             .join(", ")
                     )),
+                    FilterCondition::Any(_) => {
+                        Some(format!("f{} {}", i, escape(&format!("{}", cond))))
+                    }
+                    FilterCondition::Expression(..) | FilterCondition::InTuples(..) => {
+                        Some(escape(&format!("{}", cond)))
+                    }
                 })
                 .collect::<Vec<_>>()
                 .as_slice()
@@ -172,30 +344,8 @@ impl Ingredient for Filter {
         self.lookup(*self.src, columns, key, nodes, states)
             .and_then(|result| {
                 let f = self.filter.clone();
-                let filter = move |r: &[DataType]| {
-                    f.iter().all(|(i, ref cond)| {
-                        // check if this filter matches
-                        let d = &r[*i];
-                        match *cond {
-                            FilterCondition::Comparison(ref op, ref f) => {
-                                let v = match *f {
-                                    Value::Constant(ref dt) => dt,
-                                    Value::Column(c) => &r[c],
-                                };
-                                match *op {
-                                    Operator::Equal => d == v,
-                                    Operator::NotEqual => d != v,
-                                    Operator::Greater => d > v,
-                                    Operator::GreaterOrEqual => d >= v,
-                                    Operator::Less => d < v,
-                                    Operator::LessOrEqual => d <= v,
-                                    _ => unimplemented!(),
-                                }
-                            }
-                            FilterCondition::In(ref fs) => fs.contains(d),
-                        }
-                    })
-                };
+                let filter =
+                    move |r: &[DataType]| f.iter().all(|(i, cond)| cond.matches(&r[*i], r));
 
                 match result {
                     Some(rs) => {
@@ -392,6 +542,78 @@ mod tests {
         assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
     }
 
+    #[test]
+    fn it_works_with_expressions() {
+        // x > y * 2
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(
+                    Operator::Greater,
+                    Value::Expression(
+                        ArithmeticOperator::Multiply,
+                        Box::new(Value::Column(1)),
+                        Box::new(Value::Constant(2.into())),
+                    ),
+                ),
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+        left = vec![5.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![4.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
+        left = vec![4.into(), DataType::None];
+        assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
+
+        // x + y = 3
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Expression(
+                    Value::Expression(
+                        ArithmeticOperator::Add,
+                        Box::new(Value::Column(0)),
+                        Box::new(Value::Column(1)),
+                    ),
+                    Operator::Equal,
+                    Value::Constant(3.into()),
+                ),
+            )]),
+        );
+        left = vec![1.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![2.into(), 2.into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
+    }
+
+    #[test]
+    fn it_works_with_tuples() {
+        // (x, y) IN ((1, "a"), (2, "b"))
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::InTuples(
+                    vec![0, 1],
+                    vec![vec![1.into(), "a".into()], vec![2.into(), "b".into()]],
+                ),
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+        left = vec![1.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![2.into(), "b".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        // each value matches, but not in the same tuple
+        left = vec![1.into(), "b".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), Records::default());
+    }
+
     #[test]
     fn it_works_with_in_list() {
         let mut g = setup(
@@ -420,4 +642,81 @@ mod tests {
         left = vec![42.into(), "b".into()];
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
+
+    #[test]
+    fn it_evaluates_bad_arithmetic_to_null() {
+        let expr = |op, left, right| {
+            Value::Expression(
+                op,
+                Box::new(Value::Column(left)),
+                Box::new(Value::Column(right)),
+            )
+        };
+        let r: Vec<DataType> = vec![6.into(), 0.into(), "a".into(), 0.0.into(), 4.into()];
+
+        assert_eq!(*expr(ArithmeticOperator::Divide, 0, 4).eval(&r), 1.into());
+        assert_eq!(
+            *expr(ArithmeticOperator::Divide, 0, 1).eval(&r),
+            DataType::None
+        );
+        assert_eq!(
+            *expr(ArithmeticOperator::Divide, 0, 3).eval(&r),
+            DataType::None
+        );
+        assert_eq!(
+            *expr(ArithmeticOperator::Add, 0, 2).eval(&r),
+            DataType::None
+        );
+        assert_eq!(
+            *expr(ArithmeticOperator::Multiply, 2, 2).eval(&r),
+            DataType::None
+        );
+
+        // and a filter over them drops the row rather than panicking
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Comparison(
+                    Operator::Equal,
+                    Value::Expression(
+                        ArithmeticOperator::Divide,
+                        Box::new(Value::Column(0)),
+                        Box::new(Value::Column(1)),
+                    ),
+                ),
+            )]),
+        );
+        assert!(g.narrow_one_row(vec![1.into(), 0.into()], false).is_empty());
+        assert!(g
+            .narrow_one_row(vec![1.into(), "a".into()], false)
+            .is_empty());
+    }
+
+    #[test]
+    fn it_works_with_disjunctions() {
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Any(vec![
+                    FilterCondition::Comparison(Operator::Equal, Value::Constant(2.into())),
+                    FilterCondition::Comparison(Operator::Greater, Value::Constant(40.into())),
+                ]),
+            )]),
+        );
+        assert_eq!(g.node().description(true), "σ[f0 (= 2 OR \\> 40)]");
+
+        let mut left: Vec<DataType>;
+
+        // either condition is enough, and a row that matches both is only emitted once
+        left = vec![2.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+        left = vec![42.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // neither condition matches
+        left = vec![3.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
 }
@@ -135,6 +135,10 @@ impl GroupedOperation for Aggregator {
     fn over_columns(&self) -> Vec<usize> {
         vec![self.over]
     }
+
+    fn is_additive(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -412,4 +416,28 @@ mod tests {
         );
         assert_eq!(c.node().resolve(1), None);
     }
+
+    #[test]
+    fn it_is_additive() {
+        let c = setup(false);
+        assert_eq!(c.node().additive_column(), Some(1));
+        let c = setup_multicolumn(false);
+        assert_eq!(c.node().additive_column(), Some(2));
+    }
+
+    #[test]
+    fn it_is_order_independent() {
+        let expected: Vec<Vec<DataType>> = vec![vec![1.into(), 2.into()], vec![2.into(), 1.into()]];
+        for seed in ops::test::Simulation::seeds() {
+            let c = setup(true);
+            let s = c.narrow_base_id();
+            let mut sim = ops::test::Simulation::new(c, seed);
+            sim.enqueue(0, s, vec![1.into(), 1.into()]);
+            sim.enqueue(0, s, vec![1.into(), 2.into()]);
+            sim.enqueue(0, s, (vec![1.into(), 1.into()], false));
+            sim.enqueue(1, s, vec![1.into(), 3.into()]);
+            sim.enqueue(1, s, vec![2.into(), 4.into()]);
+            assert_eq!(sim.run(), expected, "diverged with seed {}", seed);
+        }
+    }
 }
@@ -61,6 +61,12 @@ pub trait GroupedOperation: fmt::Debug + Clone {
 
     fn description(&self, detailed: bool) -> String;
     fn over_columns(&self) -> Vec<usize>;
+
+    /// Whether the value of a group is the sum of the values computed over any split of its
+    /// records.
+    fn is_additive(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -312,4 +318,12 @@ where
     fn is_selective(&self) -> bool {
         true
     }
+
+    fn additive_column(&self) -> Option<usize> {
+        if self.inner.is_additive() {
+            Some(self.colfix.len())
+        } else {
+            None
+        }
+    }
 }
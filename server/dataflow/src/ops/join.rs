@@ -30,8 +30,8 @@ pub struct Join {
     left: IndexPair,
     right: IndexPair,
 
-    // Key column in the left and right parents respectively
-    on: (usize, usize),
+    // Key columns in the left and right parents respectively
+    on: (Vec<usize>, Vec<usize>),
 
     // Which columns to emit. True means the column is from the left parent, false means from the
     // right
@@ -54,10 +54,9 @@ enum Preprocessed {
 impl Join {
     /// Create a new instance of Join
     ///
-    /// `left` and `right` are the left and right parents respectively. `on` is a tuple specifying
-    /// the join columns: (left_parent_column, right_parent_column) and `emit` dictates for each
-    /// output colunm, which source and column should be used (true means left parent, and false
-    /// means right parent).
+    /// `left` and `right` are the left and right parents respectively. `emit` dictates for each
+    /// output colunm, which source and column should be used; the columns that occur in both
+    /// parents (`JoinSource::B`) make up the join key, and rows join if they agree on all of them.
     pub fn new(left: NodeIndex, right: NodeIndex, kind: JoinType, emit: Vec<JoinSource>) -> Self {
         let mut join_columns = Vec::new();
         let emit: Vec<_> = emit
@@ -72,8 +71,11 @@ impl Join {
             })
             .collect();
 
-        assert_eq!(join_columns.len(), 1, "only supports single column joins");
-        let on = *join_columns.iter().next().unwrap();
+        assert!(
+            !join_columns.is_empty(),
+            "joins need at least one join column"
+        );
+        let on = join_columns.into_iter().unzip();
 
         let (in_place_left_emit, in_place_right_emit) = {
             let compute_in_place_emit = |left| {
@@ -246,10 +248,11 @@ impl Ingredient for Join {
         }
 
         let (other, from_key, other_key) = if from == *self.left {
-            (*self.right, self.on.0, self.on.1)
+            (*self.right, &self.on.0[..], &self.on.1[..])
         } else {
-            (*self.left, self.on.1, self.on.0)
+            (*self.left, &self.on.1[..], &self.on.0[..])
         };
+        let has_key = |r: &Record, key: &[DataType]| from_key.iter().map(|&c| &r[c]).eq(key);
 
         let replay_key_cols = replay_key_cols.map(|cols| {
             cols.iter()
@@ -257,14 +260,14 @@ impl Ingredient for Join {
                     match self.emit[col] {
                         (true, l) if from == *self.left => l,
                         (false, r) if from == *self.right => r,
-                        (true, l) if l == self.on.0 => {
+                        (true, l) if self.on.0.contains(&l) => {
                             // since we didn't hit the case above, we know that the message
                             // *isn't* from left.
-                            self.on.1
+                            self.on.1[self.on.0.iter().position(|&c| c == l).unwrap()]
                         }
-                        (false, r) if r == self.on.1 => {
+                        (false, r) if self.on.1.contains(&r) => {
                             // same
-                            self.on.0
+                            self.on.0[self.on.1.iter().position(|&c| c == r).unwrap()]
                         }
                         _ => {
                             // we're getting a partial replay, but the replay key doesn't exist
@@ -281,7 +284,10 @@ impl Ingredient for Join {
         // two queries. We'll do this by sorting the batch by our join key.
         let mut rs: Vec<_> = rs.into();
         {
-            let cmp = |a: &Record, b: &Record| a[from_key].cmp(&b[from_key]);
+            let cmp = |a: &Record, b: &Record| {
+                let a_key = from_key.iter().map(|&c| &a[c]);
+                a_key.cmp(from_key.iter().map(|&c| &b[c]))
+            };
             rs.sort_by(cmp);
         }
 
@@ -290,14 +296,15 @@ impl Ingredient for Join {
         while at != rs.len() {
             let mut old_right_count = None;
             let mut new_right_count = None;
-            let prev_join_key = rs[at][from_key].clone();
+            let prev_join_key: Vec<DataType> =
+                from_key.iter().map(|&c| rs[at][c].clone()).collect();
 
             if from == *self.right && self.kind == JoinType::Left {
                 let rc = self
                     .lookup(
                         *self.right,
-                        &[self.on.1],
-                        &KeyType::Single(&prev_join_key),
+                        &self.on.1[..],
+                        &KeyType::from(&prev_join_key[..]),
                         nodes,
                         state,
                     )
@@ -315,7 +322,7 @@ impl Ingredient for Join {
                     // (possibly several times over for each a).
                     at = rs[at..]
                         .iter()
-                        .position(|r| r[from_key] != prev_join_key)
+                        .position(|r| !has_key(r, &prev_join_key))
                         .map(|p| at + p)
                         .unwrap_or_else(|| rs.len());
                     continue;
@@ -323,8 +330,8 @@ impl Ingredient for Join {
                     if replay_key_cols.is_some() {
                         lookups.push(Lookup {
                             on: *self.right,
-                            cols: vec![self.on.1],
-                            key: vec![prev_join_key.clone()],
+                            cols: self.on.1.clone(),
+                            key: prev_join_key.clone(),
                         });
                     }
 
@@ -338,8 +345,8 @@ impl Ingredient for Join {
             let mut other_rows = self
                 .lookup(
                     other,
-                    &[other_key],
-                    &KeyType::Single(&prev_join_key),
+                    other_key,
+                    &KeyType::from(&prev_join_key[..]),
                     nodes,
                     state,
                 )
@@ -350,13 +357,13 @@ impl Ingredient for Join {
                 let from = at;
                 at = rs[at..]
                     .iter()
-                    .position(|r| r[from_key] != prev_join_key)
+                    .position(|r| !has_key(r, &prev_join_key))
                     .map(|p| at + p)
                     .unwrap_or_else(|| rs.len());
                 misses.extend((from..at).map(|i| Miss {
                     on: other,
-                    lookup_idx: vec![other_key],
-                    lookup_cols: vec![from_key],
+                    lookup_idx: other_key.to_vec(),
+                    lookup_cols: from_key.to_vec(),
                     replay_cols: replay_key_cols.clone(),
                     // NOTE: we're stealing data here!
                     record: mem::replace(&mut *rs[i], Vec::new()),
@@ -367,8 +374,8 @@ impl Ingredient for Join {
             if replay_key_cols.is_some() {
                 lookups.push(Lookup {
                     on: other,
-                    cols: vec![other_key],
-                    key: vec![prev_join_key.clone()],
+                    cols: other_key.to_vec(),
+                    key: prev_join_key.clone(),
                 });
             }
 
@@ -379,7 +386,7 @@ impl Ingredient for Join {
                 // records that existed *before* this batch of records was processed so we know
                 // whether or not to generate +/- NULL rows.
                 if let Some(mut old_rc) = old_right_count {
-                    while at != rs.len() && rs[at][from_key] == prev_join_key {
+                    while at != rs.len() && has_key(&rs[at], &prev_join_key) {
                         if rs[at].is_positive() {
                             old_rc -= 1
                         } else {
@@ -402,13 +409,13 @@ impl Ingredient for Join {
                     let start = at;
                     at = rs[at..]
                         .iter()
-                        .position(|r| r[from_key] != prev_join_key)
+                        .position(|r| !has_key(r, &prev_join_key))
                         .map(|p| at + p)
                         .unwrap_or_else(|| rs.len());
                     misses.extend((start..at).map(|i| Miss {
                         on: from,
-                        lookup_idx: vec![self.on.1],
-                        lookup_cols: vec![from_key],
+                        lookup_idx: self.on.1.clone(),
+                        lookup_cols: from_key.to_vec(),
                         replay_cols: replay_key_cols.clone(),
                         // NOTE: we're stealing data here!
                         record: mem::replace(&mut *rs[i], Vec::new()),
@@ -421,7 +428,7 @@ impl Ingredient for Join {
                 // we didn't find the end above, so find it now
                 at = rs[at..]
                     .iter()
-                    .position(|r| r[from_key] != prev_join_key)
+                    .position(|r| !has_key(r, &prev_join_key))
                     .map(|p| at + p)
                     .unwrap_or_else(|| rs.len());
             }
@@ -540,8 +547,8 @@ impl Ingredient for Join {
 
     fn suggest_indexes(&self, _this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         vec![
-            (self.left.as_global(), vec![self.on.0]),
-            (self.right.as_global(), vec![self.on.1]),
+            (self.left.as_global(), self.on.0.clone()),
+            (self.right.as_global(), self.on.1.clone()),
         ]
         .into_iter()
         .collect()
@@ -579,24 +586,32 @@ impl Ingredient for Join {
             JoinType::Inner => "⋈",
         };
 
+        let on = |cols: &[usize]| {
+            cols.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
         format!(
             "[{}] {}:{} {} {}:{}",
             emit,
             self.left.as_global().index(),
-            self.on.0,
+            on(&self.on.0),
             op,
             self.right.as_global().index(),
-            self.on.1
+            on(&self.on.1)
         )
     }
 
     fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
         let pcol = self.emit[col];
-        if (pcol.0 && pcol.1 == self.on.0) || (!pcol.0 && pcol.1 == self.on.1) {
+        let key = if pcol.0 { &self.on.0 } else { &self.on.1 };
+        if let Some(i) = key.iter().position(|&c| c == pcol.1) {
             // Join column comes from both parents
             vec![
-                (self.left.as_global(), Some(self.on.0)),
-                (self.right.as_global(), Some(self.on.1)),
+                (self.left.as_global(), Some(self.on.0[i])),
+                (self.right.as_global(), Some(self.on.1[i])),
             ]
         } else {
             vec![(
@@ -737,6 +752,50 @@ mod tests {
         assert_eq!(rs.len(), 0);
     }
 
+    #[test]
+    fn it_joins_on_multiple_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1", "l2"]);
+        let r = g.add_base("right", &["r0", "r1", "r2"]);
+
+        use self::JoinSource::*;
+        let j = Join::new(
+            l.as_global(),
+            r.as_global(),
+            JoinType::Inner,
+            vec![B(0, 0), B(1, 1), L(2), R(2)],
+        );
+        g.set_op("join", &["j0", "j1", "j2", "j3"], j, false);
+        assert_eq!(
+            g.node().description(true),
+            format!("[{}:0, {}:1, {}:2, {}:2] {}:0,1 ⋈ {}:0,1", l, l, l, r, l, r)
+        );
+
+        let r_1a = vec![1.into(), "a".into(), "x".into()];
+        let r_1b = vec![1.into(), "b".into(), "y".into()];
+        g.seed(r, r_1a.clone());
+        g.seed(r, r_1b.clone());
+        g.one_row(r, r_1a, false);
+        g.one_row(r, r_1b, false);
+
+        // only the right row that agrees on both columns joins
+        let l_1a = vec![1.into(), "a".into(), "l".into()];
+        g.seed(l, l_1a.clone());
+        assert_eq!(
+            g.one_row(l, l_1a, false),
+            vec![(vec![1.into(), "a".into(), "l".into(), "x".into()], true)].into()
+        );
+
+        let l_1c = vec![1.into(), "c".into(), "l".into()];
+        g.seed(l, l_1c.clone());
+        assert!(g.one_row(l, l_1c, false).is_empty());
+
+        assert_eq!(
+            g.node().parent_columns(1),
+            vec![(l.as_global(), Some(1)), (r.as_global(), Some(1))]
+        );
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
@@ -758,4 +817,27 @@ mod tests {
         assert_eq!(g.node().resolve(1), Some(vec![(l.as_global(), 1)]));
         assert_eq!(g.node().resolve(2), Some(vec![(r.as_global(), 1)]));
     }
+
+    #[test]
+    fn it_is_order_independent() {
+        let expected = vec![
+            vec![1.into(), "a".into(), "x".into()],
+            vec![1.into(), "a".into(), "y".into()],
+            vec![2.into(), "b".into(), DataType::None],
+        ];
+        for seed in ops::test::Simulation::seeds() {
+            let (j, l, r) = setup();
+            let mut sim = ops::test::Simulation::new(j, seed);
+            sim.enqueue(0, l, vec![1.into(), "a".into()]);
+            sim.enqueue(0, l, vec![2.into(), "b".into()]);
+            sim.enqueue(0, l, vec![3.into(), "c".into()]);
+            sim.enqueue(0, l, (vec![3.into(), "c".into()], false));
+            sim.enqueue(1, r, vec![1.into(), "x".into()]);
+            sim.enqueue(1, r, vec![3.into(), "z".into()]);
+            sim.enqueue(2, r, vec![1.into(), "y".into()]);
+            sim.enqueue(2, r, vec![2.into(), "w".into()]);
+            sim.enqueue(2, r, (vec![2.into(), "w".into()], false));
+            assert_eq!(sim.run(), expected, "diverged with seed {}", seed);
+        }
+    }
 }
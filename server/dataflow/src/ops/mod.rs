@@ -4,6 +4,7 @@ use std::collections::{HashMap, HashSet};
 
 use crate::prelude::*;
 
+pub mod bitwise;
 pub mod distinct;
 pub mod filter;
 pub mod grouped;
@@ -12,9 +13,11 @@ pub mod join;
 pub mod latest;
 pub mod project;
 pub mod rewrite;
+pub mod sketch;
 pub mod topk;
 pub mod trigger;
 pub mod union;
+pub mod window;
 
 #[derive(Clone, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
@@ -33,6 +36,9 @@ pub enum NodeOperator {
     Trigger(trigger::Trigger),
     Rewrite(rewrite::Rewrite),
     Distinct(distinct::Distinct),
+    Window(window::Window),
+    Sketch(sketch::Sketch),
+    Bitwise(bitwise::BitwiseOperator),
 }
 
 macro_rules! nodeop_from_impl {
@@ -71,6 +77,9 @@ nodeop_from_impl!(NodeOperator::TopK, topk::TopK);
 nodeop_from_impl!(NodeOperator::Trigger, trigger::Trigger);
 nodeop_from_impl!(NodeOperator::Rewrite, rewrite::Rewrite);
 nodeop_from_impl!(NodeOperator::Distinct, distinct::Distinct);
+nodeop_from_impl!(NodeOperator::Window, window::Window);
+nodeop_from_impl!(NodeOperator::Sketch, sketch::Sketch);
+nodeop_from_impl!(NodeOperator::Bitwise, bitwise::BitwiseOperator);
 
 macro_rules! impl_ingredient_fn_mut {
     ($self:ident, $fn:ident, $( $arg:ident ),* ) => {
@@ -89,6 +98,9 @@ macro_rules! impl_ingredient_fn_mut {
             NodeOperator::Trigger(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Window(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Sketch(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Bitwise(ref mut i) => i.$fn($($arg),*),
         }
     }
 }
@@ -110,6 +122,9 @@ macro_rules! impl_ingredient_fn_ref {
             NodeOperator::Trigger(ref i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref i) => i.$fn($($arg),*),
+            NodeOperator::Window(ref i) => i.$fn($($arg),*),
+            NodeOperator::Sketch(ref i) => i.$fn($($arg),*),
+            NodeOperator::Bitwise(ref i) => i.$fn($($arg),*),
         }
     }
 }
@@ -127,6 +142,9 @@ impl Ingredient for NodeOperator {
     fn suggest_indexes(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         impl_ingredient_fn_ref!(self, suggest_indexes, you)
     }
+    fn suggest_index_type(&self, on: NodeIndex) -> IndexType {
+        impl_ingredient_fn_ref!(self, suggest_index_type, on)
+    }
     fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>> {
         impl_ingredient_fn_ref!(self, resolve, i)
     }
@@ -223,12 +241,17 @@ impl Ingredient for NodeOperator {
     fn requires_full_materialization(&self) -> bool {
         impl_ingredient_fn_ref!(self, requires_full_materialization,)
     }
+    fn additive_column(&self) -> Option<usize> {
+        impl_ingredient_fn_ref!(self, additive_column,)
+    }
 }
 
 #[cfg(test)]
 pub mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
     use std::cell;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, VecDeque};
+    use std::env;
 
     use crate::node;
     use crate::prelude::*;
@@ -472,4 +495,79 @@ pub mod test {
                 .unwrap()
         }
     }
+
+    /// Deterministically simulates updates arriving at the node under test of a `MockGraph` over
+    /// several channels.
+    ///
+    /// Updates on the same channel are delivered in order, but which channel goes next is chosen
+    /// by a seeded random number generator. A failing interleaving can therefore be replayed
+    /// exactly by re-running with the same seed (see `Simulation::seeds`).
+    pub(crate) struct Simulation {
+        graph: MockGraph,
+        channels: Vec<VecDeque<(IndexPair, Record)>>,
+        rng: StdRng,
+    }
+
+    impl Simulation {
+        pub fn new(graph: MockGraph, seed: u64) -> Self {
+            Simulation {
+                graph,
+                channels: Vec::new(),
+                rng: StdRng::seed_from_u64(seed),
+            }
+        }
+
+        /// The seeds to simulate with: the one in `NORIA_SIM_SEED` if set, or a range otherwise.
+        pub fn seeds() -> Vec<u64> {
+            match env::var("NORIA_SIM_SEED") {
+                Ok(seed) => vec![seed.parse().expect("NORIA_SIM_SEED must be a number")],
+                Err(_) => (0..100).collect(),
+            }
+        }
+
+        /// Queue an update from base `src` on the given channel.
+        pub fn enqueue<R: Into<Record>>(&mut self, channel: usize, src: IndexPair, r: R) {
+            if self.channels.len() <= channel {
+                self.channels.resize_with(channel + 1, VecDeque::new);
+            }
+            self.channels[channel].push_back((src, r.into()));
+        }
+
+        /// Deliver all queued updates, and return the rows the node under test ends up emitting,
+        /// net of any retractions, in sorted order.
+        pub fn run(&mut self) -> Vec<Vec<DataType>> {
+            let mut net: HashMap<Vec<DataType>, isize> = HashMap::new();
+            loop {
+                let ready: Vec<_> = (0..self.channels.len())
+                    .filter(|&c| !self.channels[c].is_empty())
+                    .collect();
+                if ready.is_empty() {
+                    break;
+                }
+
+                let c = ready[self.rng.gen_range(0, ready.len())];
+                let (src, r) = self.channels[c].pop_front().unwrap();
+
+                // like in a domain, a base materializes an update before its children see it
+                if let Some(state) = self.graph.states.get_mut(*src) {
+                    state.process_records(&mut r.clone().into(), None);
+                }
+                let remember = self.graph.states.contains_key(*self.graph.nut.unwrap());
+                for r in self.graph.one_row(src, r, remember) {
+                    let (row, positive) = r.extract();
+                    *net.entry(row).or_insert(0) += if positive { 1 } else { -1 };
+                }
+            }
+
+            let mut rows = Vec::new();
+            for (row, n) in net {
+                assert!(n >= 0, "retracted {:?} more often than it was emitted", row);
+                for _ in 0..n {
+                    rows.push(row.clone());
+                }
+            }
+            rows.sort();
+            rows
+        }
+    }
 }
@@ -27,6 +27,14 @@ impl ProjectExpression {
     ) -> ProjectExpression {
         ProjectExpression { op, left, right }
     }
+
+    /// Whether the expression reads the given column of its input.
+    pub fn uses_column(&self, col: usize) -> bool {
+        [&self.left, &self.right].iter().any(|b| match **b {
+            ProjectExpressionBase::Column(c) => c == col,
+            ProjectExpressionBase::Literal(_) => false,
+        })
+    }
 }
 
 impl fmt::Display for ProjectExpressionBase {
@@ -98,6 +106,29 @@ impl Project {
             self.expressions.as_ref().map(Vec::as_slice).unwrap_or(&[]),
         )
     }
+
+    /// The row that the row `r` of the parent is projected to.
+    pub fn project(&self, r: &[DataType]) -> Vec<DataType> {
+        let emit = match self.emit {
+            Some(ref emit) => emit,
+            None => return r.to_vec(),
+        };
+        let mut new_r = Vec::with_capacity(r.len());
+
+        for &i in emit {
+            new_r.push(r[i].clone());
+        }
+
+        if let Some(ref e) = self.expressions {
+            new_r.extend(e.iter().map(|i| eval_expression(i, r)));
+        }
+
+        if let Some(ref a) = self.additional {
+            new_r.append(&mut a.clone());
+        }
+
+        new_r
+    }
 }
 
 fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
@@ -230,23 +261,9 @@ impl Ingredient for Project {
         _: &StateMap,
     ) -> ProcessingResult {
         debug_assert_eq!(from, *self.src);
-        if let Some(ref emit) = self.emit {
+        if self.emit.is_some() {
             for r in &mut *rs {
-                let mut new_r = Vec::with_capacity(r.len());
-
-                for &i in emit {
-                    new_r.push(r[i].clone());
-                }
-
-                if let Some(ref e) = self.expressions {
-                    new_r.extend(e.iter().map(|i| eval_expression(i, &r[..])));
-                }
-
-                if let Some(ref a) = self.additional {
-                    new_r.append(&mut a.clone());
-                }
-
-                **r = new_r;
+                **r = self.project(r);
             }
         }
 
@@ -0,0 +1,479 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+use crate::prelude::*;
+
+/// The approximate aggregates that a `Sketch` can compute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SketchKind {
+    /// The number of distinct values in each group, estimated with a HyperLogLog.
+    ApproxCountDistinct,
+    /// The given quantile (between 0 and 1) of the values in each group, estimated with a
+    /// log-bucketed histogram.
+    ApproxQuantile(f64),
+}
+
+/// log2 of the number of HyperLogLog registers, which gives a standard error of about 3%.
+const HLL_BITS: u32 = 10;
+/// The relative error of the values that quantile estimates are made of.
+const QUANTILE_ACCURACY: f64 = 0.01;
+
+/// A HyperLogLog that values can be removed from again.
+///
+/// Rather than just the largest rank seen by each register, every register counts how often it
+/// has seen each rank, so that removing a value can take the register back down. The rank of a
+/// value is logarithmic in the number of values, so the counts stay small.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Hll {
+    /// for each register, the number of values of each rank, without trailing zeros
+    registers: Vec<Vec<u64>>,
+}
+
+impl Hll {
+    fn update(&mut self, v: &DataType, positive: bool) {
+        if self.registers.is_empty() {
+            self.registers = vec![Vec::new(); 1 << HLL_BITS];
+        }
+
+        let mut hasher = DefaultHasher::new();
+        v.hash(&mut hasher);
+        let h = hasher.finish();
+        let register = &mut self.registers[(h >> (64 - HLL_BITS)) as usize];
+        let rank = ((h << HLL_BITS).leading_zeros()).min(64 - HLL_BITS) as usize + 1;
+
+        if positive {
+            if register.len() < rank {
+                register.resize(rank, 0);
+            }
+            register[rank - 1] += 1;
+        } else if let Some(n) = register.get_mut(rank - 1) {
+            *n -= 1;
+            while register.last() == Some(&0) {
+                register.pop();
+            }
+        }
+    }
+
+    fn estimate(&self) -> i64 {
+        if self.registers.is_empty() {
+            return 0;
+        }
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-(r.len() as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // small cardinalities are better estimated from the number of empty registers
+        let empty = self.registers.iter().filter(|r| r.is_empty()).count();
+        let estimate = if estimate <= 2.5 * m && empty != 0 {
+            m * (m / empty as f64).ln()
+        } else {
+            estimate
+        };
+        estimate.round() as i64
+    }
+}
+
+/// A histogram of values with exponentially growing buckets.
+///
+/// Every value in a bucket is within `QUANTILE_ACCURACY` (relative) of the bucket's midpoint, so
+/// quantiles are estimated with that relative error. Unlike t-digests, values can be removed again
+/// by decrementing their bucket, which is what negative records need.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Quantiles {
+    /// counts of positive values by bucket
+    positive: BTreeMap<i32, u64>,
+    /// counts of negative values by the bucket of their magnitude
+    negative: BTreeMap<i32, u64>,
+    zero: u64,
+}
+
+impl Quantiles {
+    fn gamma() -> f64 {
+        (1.0 + QUANTILE_ACCURACY) / (1.0 - QUANTILE_ACCURACY)
+    }
+
+    fn update(&mut self, v: f64, positive: bool) {
+        let (buckets, v) = if v > 0.0 {
+            (&mut self.positive, v)
+        } else if v < 0.0 {
+            (&mut self.negative, -v)
+        } else {
+            if positive {
+                self.zero += 1;
+            } else {
+                self.zero = self.zero.saturating_sub(1);
+            }
+            return;
+        };
+
+        let bucket = (v.ln() / Self::gamma().ln()).ceil() as i32;
+        if positive {
+            *buckets.entry(bucket).or_insert(0) += 1;
+        } else if let Some(n) = buckets.get_mut(&bucket) {
+            *n -= 1;
+            if *n == 0 {
+                buckets.remove(&bucket);
+            }
+        }
+    }
+
+    fn value(bucket: i32) -> f64 {
+        let gamma = Self::gamma();
+        2.0 * gamma.powi(bucket) / (gamma + 1.0)
+    }
+
+    fn quantile(&self, q: f64) -> Option<f64> {
+        let n =
+            self.negative.values().sum::<u64>() + self.zero + self.positive.values().sum::<u64>();
+        if n == 0 {
+            return None;
+        }
+
+        let rank = (q * (n - 1) as f64).round() as u64;
+        let mut seen = 0;
+        for (&bucket, &count) in self.negative.iter().rev() {
+            seen += count;
+            if seen > rank {
+                return Some(-Self::value(bucket));
+            }
+        }
+        seen += self.zero;
+        if seen > rank {
+            return Some(0.0);
+        }
+        for (&bucket, &count) in &self.positive {
+            seen += count;
+            if seen > rank {
+                return Some(Self::value(bucket));
+            }
+        }
+        unreachable!("rank is smaller than the number of values");
+    }
+}
+
+/// The sketch of a single group, and how many records it is over.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Group {
+    rows: usize,
+    hll: Hll,
+    quantiles: Quantiles,
+}
+
+/// An approximate aggregation that keeps a small, fixed-size sketch of each group.
+///
+/// Exact `COUNT(DISTINCT)`s and quantiles need to keep every distinct value of a group, which for
+/// high-cardinality columns takes a lot of state. `Sketch` instead estimates them from a sketch
+/// whose size hardly depends on the number of values: a HyperLogLog for distinct counts, and a
+/// log-bucketed histogram for quantiles. Both sketches support removing values, so negative
+/// records are handled without querying the parent.
+///
+/// The output records consist of the group columns followed by the estimate. `NULL` values are
+/// ignored, and groups that have no records left are removed.
+///
+/// The sketches are kept in memory alongside the operator's materialization, so it must be fully
+/// materialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sketch {
+    src: IndexPair,
+    us: Option<IndexPair>,
+
+    kind: SketchKind,
+    over: usize,
+    group: Vec<usize>,
+
+    groups: HashMap<Vec<DataType>, Group>,
+}
+
+impl Sketch {
+    /// Construct a new approximate aggregation.
+    ///
+    /// The aggregation will estimate `kind` over the values in column number `over` of its inputs
+    /// (i.e., of the `src` node in the graph), and use the columns in the `group_by` array as a
+    /// group identifier. The `over` column should not be in the `group_by` array.
+    pub fn new(src: NodeIndex, kind: SketchKind, over: usize, group_by: &[usize]) -> Self {
+        assert!(
+            !group_by.iter().any(|&i| i == over),
+            "cannot group by aggregation column"
+        );
+        if let SketchKind::ApproxQuantile(q) = kind {
+            assert!((0.0..=1.0).contains(&q), "quantile must be between 0 and 1");
+        }
+        Sketch {
+            src: src.into(),
+            us: None,
+            kind,
+            over,
+            group: group_by.into(),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// The aggregate that this operator estimates.
+    pub fn kind(&self) -> &SketchKind {
+        &self.kind
+    }
+
+    fn update(&self, g: &mut Group, r: &[DataType], positive: bool) {
+        if positive {
+            g.rows += 1;
+        } else {
+            g.rows -= 1;
+        }
+
+        let v = &r[self.over];
+        match self.kind {
+            SketchKind::ApproxCountDistinct => {
+                if !v.is_none() {
+                    g.hll.update(v, positive);
+                }
+            }
+            SketchKind::ApproxQuantile(_) => {
+                let v = match *v {
+                    DataType::None => return,
+                    DataType::UnsignedInt(n) => f64::from(n),
+                    DataType::UnsignedBigInt(n) => n as f64,
+                    ref v => f64::from(v),
+                };
+                g.quantiles.update(v, positive);
+            }
+        }
+    }
+
+    fn estimate(&self, g: &Group) -> DataType {
+        match self.kind {
+            SketchKind::ApproxCountDistinct => g.hll.estimate().into(),
+            SketchKind::ApproxQuantile(q) => g.quantiles.quantile(q).into(),
+        }
+    }
+}
+
+fn output(group: &[DataType], value: DataType) -> Vec<DataType> {
+    let mut row = Vec::with_capacity(group.len() + 1);
+    row.extend(group.iter().cloned());
+    row.push(value);
+    row
+}
+
+impl Ingredient for Sketch {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[self.src.as_global()];
+        assert!(
+            self.over < srcn.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.src.remap(remap);
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        // collect the records for each group, so that each group's estimate changes only once
+        let mut batches: BTreeMap<Vec<DataType>, Vec<Record>> = BTreeMap::new();
+        for r in rs {
+            let group: Vec<_> = self.group.iter().map(|&c| r[c].clone()).collect();
+            batches.entry(group).or_insert_with(Vec::new).push(r);
+        }
+
+        let mut out = Vec::new();
+        for (group, rs) in batches {
+            let mut g = self.groups.remove(&group).unwrap_or_default();
+            let old = if g.rows != 0 {
+                Some(self.estimate(&g))
+            } else {
+                None
+            };
+            for r in &rs {
+                debug_assert!(
+                    r.is_positive() || g.rows != 0,
+                    "negative for a group that has no records"
+                );
+                self.update(&mut g, r, r.is_positive());
+            }
+            let new = if g.rows != 0 {
+                Some(self.estimate(&g))
+            } else {
+                None
+            };
+
+            if old != new {
+                if let Some(old) = old {
+                    out.push(Record::Negative(output(&group, old)));
+                }
+                if let Some(new) = new {
+                    out.push(Record::Positive(output(&group, new)));
+                }
+            }
+            if g.rows != 0 {
+                self.groups.insert(group, g);
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // index by our primary key
+        Some((this, (0..self.group.len()).collect()))
+            .into_iter()
+            .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.group.len() {
+            return None;
+        }
+        Some(vec![(self.src.as_global(), self.group[col])])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        let op = match self.kind {
+            SketchKind::ApproxCountDistinct => "≈|δ|".to_owned(),
+            SketchKind::ApproxQuantile(q) => format!("≈q{}", q),
+        };
+        if !detailed {
+            return op;
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({}) γ[{}]", op, self.over, group_cols)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if column == self.group.len() {
+            vec![(self.src.as_global(), None)]
+        } else {
+            vec![(self.src.as_global(), Some(self.group[column]))]
+        }
+    }
+
+    fn is_selective(&self) -> bool {
+        true
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(kind: SketchKind) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["g", "v"]);
+        g.set_op(
+            "sketch",
+            &["g", "agg"],
+            Sketch::new(s.as_global(), kind, 1, &[0]),
+            true,
+        );
+        g
+    }
+
+    fn latest(rs: Records) -> Option<DataType> {
+        rs.into_iter()
+            .find(|r| r.is_positive())
+            .map(|r| r.extract().0[1].clone())
+    }
+
+    #[test]
+    fn it_describes() {
+        let s = Sketch::new(0.into(), SketchKind::ApproxCountDistinct, 1, &[0]);
+        assert_eq!(s.description(true), "≈|δ|(1) γ[0]");
+    }
+
+    #[test]
+    fn it_counts_distinct() {
+        let mut c = setup(SketchKind::ApproxCountDistinct);
+
+        let rs = c.narrow_one_row(vec![1.into(), 7.into()], true);
+        assert_eq!(latest(rs), Some(1.into()));
+
+        // duplicates are not counted
+        assert!(c.narrow_one_row(vec![1.into(), 7.into()], true).is_empty());
+
+        let rs: Vec<_> = (0..5000)
+            .map(|i| Record::Positive(vec![1.into(), i.into()]))
+            .collect();
+        let n: i64 = latest(c.narrow_one(rs, true)).unwrap().into();
+        assert!((n - 5000).abs() < 5000 / 10, "estimated {}", n);
+
+        // values can be taken out again
+        let rs: Vec<_> = (0..5000)
+            .map(|i| Record::Negative(vec![1.into(), i.into()]))
+            .collect();
+        let rs = c.narrow_one(rs, true);
+        assert_eq!(latest(rs), Some(1.into()));
+
+        // removing the last record of a group removes the group
+        c.narrow_one_row((vec![1.into(), 7.into()], false), true);
+        let rs = c.narrow_one_row((vec![1.into(), 7.into()], false), true);
+        assert_eq!(rs.len(), 1);
+        assert!(!rs.iter().next().unwrap().is_positive());
+    }
+
+    #[test]
+    fn it_estimates_quantiles() {
+        let mut c = setup(SketchKind::ApproxQuantile(0.5));
+
+        let rs: Vec<_> = (1..=1000)
+            .map(|i| Record::Positive(vec![1.into(), i.into()]))
+            .collect();
+        let median: f64 = latest(c.narrow_one(rs, true)).unwrap().into();
+        assert!((median - 500.0).abs() <= 500.0 * QUANTILE_ACCURACY * 2.0);
+
+        // removing the bottom half moves the median up
+        let rs: Vec<_> = (1..=500)
+            .map(|i| Record::Negative(vec![1.into(), i.into()]))
+            .collect();
+        let median: f64 = latest(c.narrow_one(rs, true)).unwrap().into();
+        assert!((median - 750.0).abs() <= 750.0 * QUANTILE_ACCURACY * 2.0);
+
+        // negative values and zeros are ordered as they should be
+        let mut c = setup(SketchKind::ApproxQuantile(0.0));
+        let rs = vec![
+            Record::Positive(vec![1.into(), 0.into()]),
+            Record::Positive(vec![1.into(), (-20).into()]),
+            Record::Positive(vec![1.into(), 3.into()]),
+        ];
+        let min: f64 = latest(c.narrow_one(rs, true)).unwrap().into();
+        assert!((min + 20.0).abs() <= 20.0 * QUANTILE_ACCURACY);
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ops::grouped::aggregate::Aggregation;
+use crate::prelude::*;
+
+/// Which windows a record falls into, and for how long windows are kept around.
+///
+/// Times are read from the `time` column, and are either integers or timestamps. Timestamps are
+/// turned into seconds since the epoch, so `size`, `hop`, and `retention` are in seconds for them,
+/// and in whatever unit the column uses otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowSpec {
+    /// The column holding the time of each record.
+    pub time: usize,
+    /// The length of each window.
+    pub size: i64,
+    /// The distance between the starts of consecutive windows.
+    ///
+    /// Windows overlap if this is smaller than `size`, in which case each record counts towards
+    /// several windows.
+    pub hop: i64,
+    /// How long to keep a window after it ends.
+    ///
+    /// A window is forgotten once the latest time seen so far is at least `retention` past its
+    /// end. Records that arrive for a window that has been forgotten are dropped.
+    pub retention: i64,
+}
+
+impl WindowSpec {
+    /// Non-overlapping windows of the given size.
+    pub fn tumbling(time: usize, size: i64, retention: i64) -> Self {
+        Self::hopping(time, size, size, retention)
+    }
+
+    /// Windows of the given size that start every `hop`.
+    pub fn hopping(time: usize, size: i64, hop: i64, retention: i64) -> Self {
+        assert!(
+            size > 0 && hop > 0,
+            "windows must have a positive size and hop"
+        );
+        assert!(retention >= 0, "retention cannot be negative");
+        WindowSpec {
+            time,
+            size,
+            hop,
+            retention,
+        }
+    }
+
+    /// The starts of all the windows that contain time `t`.
+    fn windows(&self, t: i64) -> impl Iterator<Item = i64> {
+        let hop = self.hop;
+        let first = (t - self.size).div_euclid(hop) + 1;
+        let last = t.div_euclid(hop);
+        (first..=last).map(move |k| k * hop)
+    }
+}
+
+/// The current state of a group within a single window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Bucket {
+    rows: usize,
+    value: i128,
+}
+
+/// An aggregation over time windows.
+///
+/// `Window` computes the same aggregates as `Aggregator`, but additionally groups records by the
+/// window(s) their time falls into. The output records consist of the group columns, followed by
+/// the start of the window, and finally the aggregated value. Groups that have no records left
+/// in a window are removed rather than reported as zero.
+///
+/// The operator tracks the latest time it has seen as a watermark, and once a window falls more
+/// than `WindowSpec::retention` behind the watermark, it revokes all of that window's output
+/// records. This lets views that aggregate over time forget old data instead of growing without
+/// bound.
+///
+/// To find the windows to revoke, the operator keeps the groups in each live window in memory
+/// alongside its materialization, and so it must be fully materialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Window {
+    src: IndexPair,
+    us: Option<IndexPair>,
+
+    op: Aggregation,
+    over: usize,
+    group: Vec<usize>,
+    spec: WindowSpec,
+
+    /// The latest time seen so far.
+    watermark: Option<i64>,
+    /// Every live window, and the groups that have records in it.
+    windows: BTreeMap<i64, HashMap<Vec<DataType>, Bucket>>,
+}
+
+impl Window {
+    /// Construct a new windowed aggregation.
+    ///
+    /// The aggregation will aggregate the value in column number `over` from its inputs (i.e.,
+    /// from the `src` node in the graph), and use the columns in the `group_by` array along with
+    /// the window as a group identifier. Neither `over` nor the time column should be in the
+    /// `group_by` array.
+    pub fn new(
+        src: NodeIndex,
+        op: Aggregation,
+        over: usize,
+        group_by: &[usize],
+        spec: WindowSpec,
+    ) -> Self {
+        assert!(
+            !group_by.iter().any(|&i| i == over || i == spec.time),
+            "cannot group by aggregation or time column"
+        );
+        Window {
+            src: src.into(),
+            us: None,
+            op,
+            over,
+            group: group_by.into(),
+            spec,
+            watermark: None,
+            windows: BTreeMap::new(),
+        }
+    }
+
+    fn time(&self, r: &[DataType]) -> Option<i64> {
+        match r[self.spec.time] {
+            DataType::None => None,
+            DataType::Timestamp(ts) => Some(ts.timestamp()),
+            ref t => Some(i64::from(t)),
+        }
+    }
+
+    fn to_diff(&self, r: &[DataType], pos: bool) -> i128 {
+        let v = match self.op {
+            Aggregation::COUNT => 1,
+            Aggregation::SUM => match r[self.over] {
+                DataType::Int(n) => i128::from(n),
+                DataType::UnsignedInt(n) => i128::from(n),
+                DataType::BigInt(n) => i128::from(n),
+                DataType::UnsignedBigInt(n) => i128::from(n),
+                DataType::None => 0,
+                ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
+            },
+        };
+        if pos {
+            v
+        } else {
+            -v
+        }
+    }
+
+    /// Windows that start before this have expired.
+    fn horizon(&self) -> Option<i64> {
+        self.watermark
+            .map(|w| w.saturating_sub(self.spec.retention + self.spec.size) + 1)
+    }
+}
+
+fn output(group: &[DataType], window: i64, value: i128) -> Vec<DataType> {
+    let mut row = Vec::with_capacity(group.len() + 2);
+    row.extend(group.iter().cloned());
+    row.push(window.into());
+    row.push(value.into());
+    row
+}
+
+impl Ingredient for Window {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[self.src.as_global()];
+        assert!(
+            self.over < srcn.fields().len() && self.spec.time < srcn.fields().len(),
+            "cannot aggregate over non-existing column"
+        );
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.src.remap(remap);
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        _: Option<&[usize]>,
+        _: &DomainNodes,
+        _: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        // move the watermark first, so that records in this batch for windows that it expires are
+        // treated as late
+        let latest = rs
+            .iter()
+            .filter(|r| r.is_positive())
+            .filter_map(|r| self.time(r))
+            .max();
+        if latest > self.watermark {
+            self.watermark = latest;
+        }
+        let horizon = self.horizon();
+
+        // collect the changes to each group in each window
+        let mut diffs: BTreeMap<(i64, Vec<DataType>), (isize, i128)> = BTreeMap::new();
+        for r in rs.iter() {
+            let t = match self.time(r) {
+                Some(t) => t,
+                None => continue,
+            };
+            let group: Vec<_> = self.group.iter().map(|&c| r[c].clone()).collect();
+            let diff = self.to_diff(r, r.is_positive());
+            let rows = if r.is_positive() { 1 } else { -1 };
+            for w in self.spec.windows(t) {
+                if horizon.map(|h| w < h).unwrap_or(false) {
+                    // too late
+                    continue;
+                }
+                let d = diffs.entry((w, group.clone())).or_insert((0, 0));
+                d.0 += rows;
+                d.1 += diff;
+            }
+        }
+
+        let mut out = Vec::new();
+        for ((w, group), (rows, diff)) in diffs {
+            let buckets = self.windows.entry(w).or_insert_with(HashMap::new);
+            let old = buckets.remove(&group);
+            let new = match old {
+                Some(ref b) => Bucket {
+                    rows: (b.rows as isize + rows) as usize,
+                    value: b.value + diff,
+                },
+                None => {
+                    debug_assert!(rows >= 0, "negative for a group that has no records");
+                    Bucket {
+                        rows: rows as usize,
+                        value: diff,
+                    }
+                }
+            };
+
+            match old {
+                Some(ref old) if old.rows == new.rows && old.value == new.value => {}
+                Some(ref old) => out.push(Record::Negative(output(&group, w, old.value))),
+                None => {}
+            }
+            if new.rows != 0 {
+                if old
+                    .as_ref()
+                    .map(|old| old.rows != new.rows || old.value != new.value)
+                    .unwrap_or(true)
+                {
+                    out.push(Record::Positive(output(&group, w, new.value)));
+                }
+                buckets.insert(group, new);
+            }
+            if buckets.is_empty() {
+                self.windows.remove(&w);
+            }
+        }
+
+        // forget windows that have fallen too far behind
+        if let Some(horizon) = horizon {
+            let live = self.windows.split_off(&horizon);
+            let expired = std::mem::replace(&mut self.windows, live);
+            for (w, buckets) in expired {
+                for (group, b) in buckets {
+                    out.push(Record::Negative(output(&group, w, b.value)));
+                }
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            ..Default::default()
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        // index by group and window
+        Some((this, (0..=self.group.len()).collect()))
+            .into_iter()
+            .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col >= self.group.len() {
+            return None;
+        }
+        Some(vec![(self.src.as_global(), self.group[col])])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        let op = match self.op {
+            Aggregation::COUNT => "|*|".to_owned(),
+            Aggregation::SUM => format!("𝛴({})", self.over),
+        };
+        if !detailed {
+            return format!("{} ⧗", op);
+        }
+
+        let group_cols = self
+            .group
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{} ⧗[{}: {}/{}, {}] γ[{}]",
+            op, self.spec.time, self.spec.size, self.spec.hop, self.spec.retention, group_cols
+        )
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if column < self.group.len() {
+            vec![(self.src.as_global(), Some(self.group[column]))]
+        } else {
+            vec![(self.src.as_global(), None)]
+        }
+    }
+
+    fn is_selective(&self) -> bool {
+        true
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(op: Aggregation, spec: WindowSpec) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["g", "t", "v"]);
+        g.set_op(
+            "window",
+            &["g", "window", "agg"],
+            Window::new(s.as_global(), op, 2, &[0], spec),
+            true,
+        );
+        g
+    }
+
+    fn row(g: i32, t: i64, v: i32) -> Vec<DataType> {
+        vec![g.into(), t.into(), v.into()]
+    }
+
+    fn sorted(rs: Records) -> Vec<(Vec<DataType>, bool)> {
+        let mut rs: Vec<_> = rs.into_iter().map(|r| r.extract()).collect();
+        rs.sort();
+        rs
+    }
+
+    #[test]
+    fn it_describes() {
+        let w = Window::new(
+            0.into(),
+            Aggregation::SUM,
+            2,
+            &[0],
+            WindowSpec::hopping(1, 10, 5, 20),
+        );
+        assert_eq!(w.description(true), "𝛴(2) ⧗[1: 10/5, 20] γ[0]");
+    }
+
+    #[test]
+    fn it_finds_windows() {
+        let spec = WindowSpec::tumbling(0, 10, 0);
+        assert_eq!(spec.windows(0).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(spec.windows(9).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(spec.windows(10).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(spec.windows(-1).collect::<Vec<_>>(), vec![-10]);
+
+        let spec = WindowSpec::hopping(0, 10, 5, 0);
+        assert_eq!(spec.windows(7).collect::<Vec<_>>(), vec![0, 5]);
+        assert_eq!(spec.windows(10).collect::<Vec<_>>(), vec![5, 10]);
+    }
+
+    #[test]
+    fn it_counts_per_window() {
+        let mut c = setup(Aggregation::COUNT, WindowSpec::tumbling(1, 10, 100));
+
+        let rs = c.narrow_one_row(row(1, 3, 0), true);
+        assert_eq!(sorted(rs), vec![(vec![1.into(), 0.into(), 1.into()], true)]);
+
+        // same window, so the count goes up
+        let rs = c.narrow_one_row(row(1, 7, 0), true);
+        assert_eq!(
+            sorted(rs),
+            vec![
+                (vec![1.into(), 0.into(), 1.into()], false),
+                (vec![1.into(), 0.into(), 2.into()], true),
+            ]
+        );
+
+        // a new window starts a new count
+        let rs = c.narrow_one_row(row(1, 12, 0), true);
+        assert_eq!(
+            sorted(rs),
+            vec![(vec![1.into(), 10.into(), 1.into()], true)]
+        );
+
+        // removing the last record of a group removes the group
+        let rs = c.narrow_one_row((row(1, 12, 0), false), true);
+        assert_eq!(
+            sorted(rs),
+            vec![(vec![1.into(), 10.into(), 1.into()], false)]
+        );
+    }
+
+    #[test]
+    fn it_sums_over_hopping_windows() {
+        let mut c = setup(Aggregation::SUM, WindowSpec::hopping(1, 10, 5, 100));
+
+        let rs = c.narrow_one_row(row(1, 7, 3), true);
+        assert_eq!(
+            sorted(rs),
+            vec![
+                (vec![1.into(), 0.into(), 3.into()], true),
+                (vec![1.into(), 5.into(), 3.into()], true),
+            ]
+        );
+
+        let rs = c.narrow_one_row(row(1, 11, 4), true);
+        assert_eq!(
+            sorted(rs),
+            vec![
+                (vec![1.into(), 5.into(), 3.into()], false),
+                (vec![1.into(), 5.into(), 7.into()], true),
+                (vec![1.into(), 10.into(), 4.into()], true),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_expires_old_windows() {
+        let mut c = setup(Aggregation::COUNT, WindowSpec::tumbling(1, 10, 5));
+
+        c.narrow_one_row(row(1, 3, 0), true);
+        c.narrow_one_row(row(2, 4, 0), true);
+
+        // window [0, 10) is kept until time 15
+        assert_eq!(c.narrow_one_row(row(1, 14, 0), true).len(), 1);
+        let rs = c.narrow_one_row(row(1, 15, 0), true);
+        assert_eq!(
+            sorted(rs),
+            vec![
+                (vec![1.into(), 0.into(), 1.into()], false),
+                (vec![1.into(), 10.into(), 1.into()], false),
+                (vec![1.into(), 10.into(), 2.into()], true),
+                (vec![2.into(), 0.into(), 1.into()], false),
+            ]
+        );
+
+        // records for the expired window are dropped
+        assert!(c.narrow_one_row(row(1, 9, 0), true).is_empty());
+        assert!(c.narrow_one_row((row(1, 3, 0), false), true).is_empty());
+    }
+}
@@ -9,6 +9,7 @@ use noria::internal::LocalOrNot;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net::SocketAddr;
+use std::time;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReplayPathSegment {
@@ -42,8 +43,12 @@ pub enum TriggerEndpoint {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum InitialState {
-    PartialLocal(Vec<(Vec<usize>, Vec<Tag>)>),
-    IndexedLocal(HashSet<Vec<usize>>),
+    PartialLocal(Vec<(Vec<usize>, IndexType, Vec<Tag>)>),
+    IndexedLocal {
+        indices: HashMap<Vec<usize>, IndexType>,
+        /// The indices whose lookups should be guarded by a Bloom filter.
+        bloom: HashSet<Vec<usize>>,
+    },
     PartialGlobal {
         gid: petgraph::graph::NodeIndex,
         cols: usize,
@@ -154,6 +159,22 @@ pub enum Packet {
         new_tag: Option<(Tag, NodeIndex)>,
     },
 
+    /// Change how soon writes to a Reader node become visible to reads.
+    UpdateRefreshPolicy {
+        node: LocalNodeIndex,
+        policy: noria::RefreshPolicy,
+    },
+
+    /// Limit how many forward updates per second the domain processes, or lift the limit.
+    UpdateRateLimit {
+        records_per_sec: Option<u64>,
+    },
+
+    /// Evict all keys of partial Reader nodes that have not been read from for `after`.
+    Hibernate {
+        after: time::Duration,
+    },
+
     /// Add a shard to a Sharder node.
     ///
     /// Note that this *must* be done *before* the sharder starts being used!
@@ -226,6 +247,27 @@ pub enum Packet {
 
     /// Ask domain to log its state size
     UpdateStateSize,
+
+    /// Request that a domain sample the state of each of its nodes, looking at no more than
+    /// `rows` rows of each, and send what it found on the control reply channel.
+    SampleState {
+        rows: usize,
+    },
+
+    /// Request that a domain work out the records that the base node `node` would produce for
+    /// `ops` given its current state, without applying them, and send them on the control reply
+    /// channel.
+    DryRun {
+        node: LocalNodeIndex,
+        ops: Vec<noria::TableOperation>,
+    },
+
+    /// Request that a domain send the rows of `node` that have the given value in each of the
+    /// given columns on the control reply channel, or an error if `node` has no state.
+    FindRows {
+        node: LocalNodeIndex,
+        columns: Vec<(usize, DataType)>,
+    },
 }
 
 impl Packet {
@@ -281,6 +323,16 @@ impl Packet {
         }
     }
 
+    /// The number of forward updates this packet carries, which is zero for anything but inputs
+    /// and regular messages.
+    pub fn num_updates(&self) -> usize {
+        match *self {
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.data.len(),
+            Packet::Message { ref data, .. } => data.len(),
+            _ => 0,
+        }
+    }
+
     pub(crate) fn is_regular(&self) -> bool {
         match *self {
             Packet::Message { .. } => true,
@@ -369,6 +421,35 @@ pub enum ControlReplyPacket {
         HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
     ),
     Booted(usize, SocketAddr),
+    StateSamples(HashMap<petgraph::graph::NodeIndex, StateSample>),
+    DryRun(Vec<Record>),
+    Rows(Result<Vec<Vec<DataType>>, String>),
+}
+
+/// What a domain found when it sampled the state of a node.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateSample {
+    pub rows: usize,
+    /// A sketch of the distinct values of each column among the sampled rows.
+    pub columns: Vec<HyperLogLog>,
+    pub indices: Vec<IndexSample>,
+}
+
+/// What a domain found when it sampled one index of a node's state.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IndexSample {
+    pub columns: Vec<usize>,
+    pub keys: usize,
+    /// The sampled keys with the most rows, and how many rows they have, most rows first.
+    pub hot_keys: Vec<(Vec<DataType>, usize)>,
+}
+
+/// The rows that writes to a base table left behind, on their way to a standby deployment.
+#[derive(Debug)]
+pub struct ShippedWrites {
+    /// The name of the base table.
+    pub table: String,
+    pub ops: Vec<noria::TableOperation>,
 }
 
 impl ControlReplyPacket {
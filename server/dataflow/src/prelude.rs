@@ -16,7 +16,7 @@ pub(crate) type Edge = ();
 
 // dataflow types
 pub(crate) use crate::payload::{ReplayPathSegment, SourceChannelIdentifier};
-pub(crate) use noria::Input;
+pub(crate) use noria::{AckLevel, Input};
 
 // domain local state
 pub(crate) use crate::state::{
@@ -30,12 +30,14 @@ pub(crate) type ReplicaAddr = (DomainIndex, usize);
 pub use crate::node::Node;
 pub use crate::ops::NodeOperator;
 pub use crate::payload::Packet;
+pub use crate::state::IndexType;
 pub use crate::Sharding;
 pub use common::*;
 pub use noria::internal::*;
 pub use petgraph::graph::NodeIndex;
 pub type Graph = petgraph::Graph<Node, Edge>;
 pub use crate::DurabilityMode;
+pub use crate::FsyncPolicy;
 pub use crate::PersistenceParameters;
 
 /// Channel coordinator type specialized for domains
@@ -126,6 +126,13 @@ where
     /// *compound* key, *not* that multiple columns should be independently indexed.
     fn suggest_indexes(&self, you: NodeIndex) -> HashMap<NodeIndex, Vec<usize>>;
 
+    /// The type of index that the index suggested on `on` by `suggest_indexes` should be.
+    ///
+    /// Operators that need to scan ranges of keys in that index should ask for an ordered one.
+    fn suggest_index_type(&self, _on: NodeIndex) -> IndexType {
+        IndexType::HashMap
+    }
+
     /// Resolve where the given field originates from. If the view is materialized, or the value is
     /// otherwise created by this view, None should be returned.
     fn resolve(&self, i: usize) -> Option<Vec<(NodeIndex, usize)>>;
@@ -153,6 +160,10 @@ where
     ///    π    |  Projection
     ///    ≡    |  Identity
     ///    T    |  Trigger
+    ///    ⧗    |  Window
+    ///    ≈    |  Sketch
+    ///   & ∧   |  Bitwise and
+    ///   ∨     |  Bitwise or
     fn description(&self, detailed: bool) -> String;
 
     /// Provide measurements of transient internal state that may be useful in debugging contexts.
@@ -279,4 +290,11 @@ where
     fn requires_full_materialization(&self) -> bool {
         false
     }
+
+    /// If the records of a group may be split between several instances of this operator, and
+    /// the value of the group is then found by adding up the value each instance emits, the
+    /// column that holds that value.
+    fn additive_column(&self) -> Option<usize> {
+        None
+    }
 }
@@ -0,0 +1,124 @@
+use ahash::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use crate::prelude::*;
+
+/// Bits of filter per key it is sized for. With `HASHES` hash functions, this gives a false
+/// positive rate of a little over 2%.
+const BITS_PER_KEY: usize = 8;
+const HASHES: u64 = 4;
+
+/// The smallest number of keys a filter is sized for.
+const MIN_KEYS: usize = 1024;
+
+/// A Bloom filter over the keys of an index.
+///
+/// A lookup for a key the filter has never seen can skip probing the index. Keys are never
+/// removed from the filter, so keys that have since been deleted only cause false positives. The
+/// filter does not grow by itself; once more keys have been inserted than it was sized for, the
+/// owner should rebuild it from the keys that are still present.
+pub(super) struct BloomFilter {
+    bits: Vec<u64>,
+    hasher: RandomState,
+    inserted: usize,
+    capacity: usize,
+}
+
+impl BloomFilter {
+    /// Make an empty filter sized for about `keys` keys.
+    pub(super) fn with_capacity(keys: usize) -> Self {
+        let capacity = keys.max(MIN_KEYS);
+        BloomFilter {
+            bits: vec![0; (capacity * BITS_PER_KEY + 63) / 64],
+            hasher: RandomState::new(),
+            inserted: 0,
+            capacity,
+        }
+    }
+
+    /// Whether more keys have been inserted than the filter was sized for.
+    pub(super) fn is_full(&self) -> bool {
+        self.inserted > self.capacity
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Hash a key given as the values of its columns.
+    pub(super) fn hash<'a, I>(&self, key: I) -> u64
+    where
+        I: IntoIterator<Item = &'a DataType>,
+    {
+        let mut h = self.hasher.build_hasher();
+        for v in key {
+            v.hash(&mut h);
+        }
+        h.finish()
+    }
+
+    /// Hash a lookup key, the same way as `hash` would hash its values.
+    pub(super) fn hash_key(&self, key: &KeyType) -> u64 {
+        match *key {
+            KeyType::Single(k) => self.hash(Some(k)),
+            KeyType::Double(k) => self.hash([k.0, k.1].iter().cloned()),
+            KeyType::Tri(k) => self.hash([k.0, k.1, k.2].iter().cloned()),
+            KeyType::Quad(k) => self.hash([k.0, k.1, k.2, k.3].iter().cloned()),
+            KeyType::Quin(k) => self.hash([k.0, k.1, k.2, k.3, k.4].iter().cloned()),
+            KeyType::Sex(k) => self.hash([k.0, k.1, k.2, k.3, k.4, k.5].iter().cloned()),
+        }
+    }
+
+    /// The bits that the key with the given hash maps to, using double hashing.
+    fn positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let nbits = self.bits.len() as u64 * 64;
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % nbits) as usize)
+    }
+
+    pub(super) fn insert(&mut self, hash: u64) {
+        for bit in self.positions(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.inserted += 1;
+    }
+
+    /// Whether the key with the given hash may have been inserted.
+    pub(super) fn may_contain(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_has_no_false_negatives() {
+        let mut f = BloomFilter::with_capacity(100);
+        let keys: Vec<DataType> = (0..100).map(DataType::from).collect();
+        for k in &keys {
+            let h = f.hash(Some(k));
+            f.insert(h);
+        }
+        assert!(keys.iter().all(|k| f.may_contain(f.hash(Some(k)))));
+        assert!(!f.is_full());
+
+        // misses are what the filter is for, so most of them should be caught
+        let misses = (100..1100)
+            .map(DataType::from)
+            .filter(|k| f.may_contain(f.hash(Some(k))))
+            .count();
+        assert!(misses < 100, "{} false positives", misses);
+    }
+
+    #[test]
+    fn it_hashes_lookup_keys_like_rows() {
+        let f = BloomFilter::with_capacity(1);
+        let row: Vec<DataType> = vec![1.into(), "a".into(), 2.into()];
+        let key = KeyType::Double((&row[0], &row[2]));
+        assert_eq!(f.hash_key(&key), f.hash([0, 2].iter().map(|&c| &row[c])));
+    }
+}
@@ -1,5 +1,8 @@
 use ahash::RandomState;
-use indexmap::IndexMap;
+use indexmap::{Equivalent, IndexMap};
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
 use std::rc::Rc;
 
 use super::mk_key::MakeKey;
@@ -16,21 +19,148 @@ pub(super) enum KeyedState {
     Quad(HashMap<(DataType, DataType, DataType, DataType), Rows>),
     Quin(HashMap<(DataType, DataType, DataType, DataType, DataType), Rows>),
     Sex(HashMap<(DataType, DataType, DataType, DataType, DataType, DataType), Rows>),
+    /// An ordered index, of any number of columns, that also supports range scans.
+    Ordered(BTreeMap<Vec<DataType>, Rows>),
+}
+
+/// A compound key made up of references, which can be looked up in a map keyed by the
+/// corresponding tuple of owned values without cloning the values first.
+///
+/// A tuple of references hashes the same way as the tuple of the values they point to.
+struct Borrowed<'a, T>(&'a T);
+
+impl<'a, T: Hash> Hash for Borrowed<'a, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<'a, 'b> Equivalent<(DataType, DataType)> for Borrowed<'a, (&'b DataType, &'b DataType)> {
+    fn equivalent(&self, key: &(DataType, DataType)) -> bool {
+        let k = self.0;
+        *k.0 == key.0 && *k.1 == key.1
+    }
+}
+
+impl<'a, 'b> Equivalent<(DataType, DataType, DataType)>
+    for Borrowed<'a, (&'b DataType, &'b DataType, &'b DataType)>
+{
+    fn equivalent(&self, key: &(DataType, DataType, DataType)) -> bool {
+        let k = self.0;
+        *k.0 == key.0 && *k.1 == key.1 && *k.2 == key.2
+    }
+}
+
+impl<'a, 'b> Equivalent<(DataType, DataType, DataType, DataType)>
+    for Borrowed<'a, (&'b DataType, &'b DataType, &'b DataType, &'b DataType)>
+{
+    fn equivalent(&self, key: &(DataType, DataType, DataType, DataType)) -> bool {
+        let k = self.0;
+        *k.0 == key.0 && *k.1 == key.1 && *k.2 == key.2 && *k.3 == key.3
+    }
+}
+
+impl<'a, 'b> Equivalent<(DataType, DataType, DataType, DataType, DataType)>
+    for Borrowed<
+        'a,
+        (
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+        ),
+    >
+{
+    fn equivalent(&self, key: &(DataType, DataType, DataType, DataType, DataType)) -> bool {
+        let k = self.0;
+        *k.0 == key.0 && *k.1 == key.1 && *k.2 == key.2 && *k.3 == key.3 && *k.4 == key.4
+    }
+}
+
+impl<'a, 'b> Equivalent<(DataType, DataType, DataType, DataType, DataType, DataType)>
+    for Borrowed<
+        'a,
+        (
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+            &'b DataType,
+        ),
+    >
+{
+    fn equivalent(
+        &self,
+        key: &(DataType, DataType, DataType, DataType, DataType, DataType),
+    ) -> bool {
+        let k = self.0;
+        *k.0 == key.0
+            && *k.1 == key.1
+            && *k.2 == key.2
+            && *k.3 == key.3
+            && *k.4 == key.4
+            && *k.5 == key.5
+    }
 }
 
 impl KeyedState {
+    /// Make an empty index of the given type on the given columns.
+    pub(super) fn new(columns: &[usize], index_type: IndexType) -> Self {
+        match index_type {
+            IndexType::HashMap => columns.into(),
+            IndexType::BTreeMap => KeyedState::Ordered(BTreeMap::new()),
+        }
+    }
+
+    pub(super) fn index_type(&self) -> IndexType {
+        match *self {
+            KeyedState::Ordered(..) => IndexType::BTreeMap,
+            _ => IndexType::HashMap,
+        }
+    }
+
+    /// The number of keys in the index, including keys that are present but have no rows.
+    pub(super) fn len(&self) -> usize {
+        match *self {
+            KeyedState::Single(ref m) => m.len(),
+            KeyedState::Double(ref m) => m.len(),
+            KeyedState::Tri(ref m) => m.len(),
+            KeyedState::Quad(ref m) => m.len(),
+            KeyedState::Quin(ref m) => m.len(),
+            KeyedState::Sex(ref m) => m.len(),
+            KeyedState::Ordered(ref m) => m.len(),
+        }
+    }
+
     pub(super) fn lookup<'a>(&'a self, key: &KeyType) -> Option<&'a Rows> {
         match (self, key) {
             (&KeyedState::Single(ref m), &KeyType::Single(k)) => m.get(k),
-            (&KeyedState::Double(ref m), &KeyType::Double(ref k)) => m.get(k),
-            (&KeyedState::Tri(ref m), &KeyType::Tri(ref k)) => m.get(k),
-            (&KeyedState::Quad(ref m), &KeyType::Quad(ref k)) => m.get(k),
-            (&KeyedState::Quin(ref m), &KeyType::Quin(ref k)) => m.get(k),
-            (&KeyedState::Sex(ref m), &KeyType::Sex(ref k)) => m.get(k),
+            (&KeyedState::Double(ref m), &KeyType::Double(ref k)) => m.get(&Borrowed(k)),
+            (&KeyedState::Tri(ref m), &KeyType::Tri(ref k)) => m.get(&Borrowed(k)),
+            (&KeyedState::Quad(ref m), &KeyType::Quad(ref k)) => m.get(&Borrowed(k)),
+            (&KeyedState::Quin(ref m), &KeyType::Quin(ref k)) => m.get(&Borrowed(k)),
+            (&KeyedState::Sex(ref m), &KeyType::Sex(ref k)) => m.get(&Borrowed(k)),
+            (&KeyedState::Ordered(ref m), &KeyType::Single(k)) => m.get(std::slice::from_ref(k)),
+            (&KeyedState::Ordered(ref m), k) => m.get(&ordered_key(k)[..]),
             _ => unreachable!(),
         }
     }
 
+    /// All the rows whose keys fall within the given bounds, in key order.
+    ///
+    /// Only ordered indices support range scans.
+    pub(super) fn lookup_range<'a>(
+        &'a self,
+        range: (Bound<&[DataType]>, Bound<&[DataType]>),
+    ) -> impl Iterator<Item = &'a Rows> + 'a {
+        match *self {
+            KeyedState::Ordered(ref m) => m.range::<[DataType], _>(range).map(|(_, rs)| rs),
+            _ => panic!("range lookup on an unordered index"),
+        }
+    }
+
     /// Remove all rows for a randomly chosen key seeded by `seed`, returning that key along with
     /// the number of bytes freed. Returns `None` if map is empty.
     pub(super) fn evict_with_seed(&mut self, seed: usize) -> Option<(u64, Vec<DataType>)> {
@@ -64,6 +194,17 @@ impl KeyedState {
                 m.swap_remove_index(index)
                     .map(|(k, rs)| (rs, vec![k.0, k.1, k.2, k.3, k.4, k.5]))
             }
+            KeyedState::Ordered(ref mut m) if !m.is_empty() => {
+                // a BTreeMap can't find its nth key without walking all the keys before it, so we
+                // evict from one of its two ends instead.
+                let key = if seed % 2 == 0 {
+                    m.keys().next()
+                } else {
+                    m.keys().next_back()
+                };
+                let key = key.cloned().unwrap();
+                m.remove(&key).map(|rs| (rs, key))
+            }
             _ => {
                 // map must be empty, so no point in trying to evict from it.
                 return None;
@@ -97,6 +238,7 @@ impl KeyedState {
             KeyedState::Sex(ref mut m) => {
                 m.swap_remove::<(DataType, _, _, _, _, _)>(&MakeKey::from_key(key))
             }
+            KeyedState::Ordered(ref mut m) => m.remove(key),
         }
         .map(|rows| {
             rows.iter()
@@ -108,6 +250,31 @@ impl KeyedState {
     }
 }
 
+/// The owned key for a lookup into an ordered index.
+pub(super) fn ordered_key(key: &KeyType) -> Vec<DataType> {
+    match *key {
+        KeyType::Single(k) => vec![k.clone()],
+        KeyType::Double(k) => vec![k.0.clone(), k.1.clone()],
+        KeyType::Tri(k) => vec![k.0.clone(), k.1.clone(), k.2.clone()],
+        KeyType::Quad(k) => vec![k.0.clone(), k.1.clone(), k.2.clone(), k.3.clone()],
+        KeyType::Quin(k) => vec![
+            k.0.clone(),
+            k.1.clone(),
+            k.2.clone(),
+            k.3.clone(),
+            k.4.clone(),
+        ],
+        KeyType::Sex(k) => vec![
+            k.0.clone(),
+            k.1.clone(),
+            k.2.clone(),
+            k.3.clone(),
+            k.4.clone(),
+            k.5.clone(),
+        ],
+    }
+}
+
 impl<'a> Into<KeyedState> for &'a [usize] {
     fn into(self) -> KeyedState {
         match self.len() {
@@ -1,12 +1,17 @@
 use std::collections::HashMap;
+use std::ops::Bound;
 use std::rc::Rc;
 
 use rand::{self, Rng};
 
+use crate::payload::{IndexSample, StateSample};
 use crate::prelude::*;
 use crate::state::single_state::SingleState;
 use common::SizeOf;
 
+/// The number of keys with the most rows that `MemoryState::sample` reports for each index.
+const HOT_KEYS: usize = 10;
+
 #[derive(Default)]
 pub struct MemoryState {
     state: Vec<SingleState>,
@@ -31,8 +36,12 @@ impl SizeOf for MemoryState {
 }
 
 impl State for MemoryState {
-    fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
-        let (i, exists) = if let Some(i) = self.state_for(columns) {
+    fn add_index(&mut self, columns: &[usize], index_type: IndexType, partial: Option<Vec<Tag>>) {
+        let existing = self
+            .state
+            .iter()
+            .position(|s| s.key() == columns && s.index_type() == index_type);
+        let (i, exists) = if let Some(i) = existing {
             // already keyed by this key; just adding tags
             (i, true)
         } else {
@@ -51,7 +60,7 @@ impl State for MemoryState {
         }
 
         self.state
-            .push(SingleState::new(columns, partial.is_some()));
+            .push(SingleState::new(columns, index_type, partial.is_some()));
 
         if !self.state.is_empty() && partial.is_none() {
             // we need to *construct* the index!
@@ -68,6 +77,14 @@ impl State for MemoryState {
         }
     }
 
+    fn add_bloom_filter(&mut self, columns: &[usize]) {
+        for s in &mut self.state {
+            if s.key() == columns {
+                s.add_bloom_filter();
+            }
+        }
+    }
+
     fn is_useful(&self) -> bool {
         !self.state.is_empty()
     }
@@ -139,10 +156,73 @@ impl State for MemoryState {
         self.state[index].lookup(key)
     }
 
+    fn lookup_range<'a>(
+        &'a self,
+        columns: &[usize],
+        range: (Bound<&[DataType]>, Bound<&[DataType]>),
+    ) -> LookupResult<'a> {
+        let index = match self
+            .state
+            .iter()
+            .position(|s| s.key() == columns && s.index_type() == IndexType::BTreeMap)
+        {
+            Some(index) => index,
+            None => return LookupResult::Missing,
+        };
+        match self.state[index].lookup_range(range) {
+            Some(rs) => LookupResult::Some(RecordResult::Gathered(rs)),
+            None => LookupResult::Missing,
+        }
+    }
+
     fn keys(&self) -> Vec<Vec<usize>> {
         self.state.iter().map(|s| s.key().to_vec()).collect()
     }
 
+    fn sample(&self, rows: usize) -> StateSample {
+        let mut sample = StateSample {
+            rows: self.rows(),
+            ..Default::default()
+        };
+
+        // every row is in the first index, so that is where the values are sampled from
+        if let Some(first) = self.state.first() {
+            for r in first.values().flat_map(|rs| rs.iter()).take(rows) {
+                if sample.columns.is_empty() {
+                    sample.columns = vec![HyperLogLog::new(); r.len()];
+                }
+                for (hll, v) in sample.columns.iter_mut().zip(r.iter()) {
+                    hll.insert(v);
+                }
+            }
+        }
+
+        for s in &self.state {
+            let mut sampled = 0;
+            let mut hot_keys: Vec<(Vec<DataType>, usize)> = s
+                .values()
+                .take_while(|rs| {
+                    let before = sampled;
+                    sampled += rs.len();
+                    before < rows
+                })
+                .filter_map(|rs| {
+                    // keys with no rows cannot be hot
+                    let r = rs.iter().next()?;
+                    Some((s.key().iter().map(|&c| r[c].clone()).collect(), rs.len()))
+                })
+                .collect();
+            hot_keys.sort_by(|a, b| b.1.cmp(&a.1));
+            hot_keys.truncate(HOT_KEYS);
+            sample.indices.push(IndexSample {
+                columns: s.key().to_vec(),
+                keys: s.keys(),
+                hot_keys,
+            });
+        }
+        sample
+    }
+
     fn cloned_records(&self) -> Vec<Vec<DataType>> {
         #[allow(clippy::ptr_arg)]
         fn fix<'a>(rs: &'a Rows) -> impl Iterator<Item = Vec<DataType>> + 'a {
@@ -284,4 +364,90 @@ mod tests {
             _ => unreachable!(),
         };
     }
+
+    #[test]
+    fn memory_state_compound_key() {
+        let mut state = MemoryState::default();
+        let row: Vec<DataType> = vec![10.into(), "Cat".into(), 20.into()];
+        state.add_key(&[0, 2], None);
+        insert(&mut state, row.clone());
+
+        match state.lookup(&[0, 2], &KeyType::from(&[row[0].clone(), row[2].clone()])) {
+            LookupResult::Some(RecordResult::Borrowed(rows)) => {
+                assert_eq!(&**rows.iter().next().unwrap(), &row)
+            }
+            _ => unreachable!(),
+        };
+        match state.lookup(&[0, 2], &KeyType::Double((&row[2], &row[0]))) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows.len(), 0),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn memory_state_range_lookup() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_index(&[1], IndexType::BTreeMap, None);
+        for i in (0..10).rev() {
+            insert(&mut state, vec![i.into(), (i * 10).into()]);
+        }
+
+        let lo: DataType = 20.into();
+        let hi: DataType = 50.into();
+        let range = (
+            Bound::Included(std::slice::from_ref(&lo)),
+            Bound::Excluded(std::slice::from_ref(&hi)),
+        );
+        match state.lookup_range(&[1], range) {
+            LookupResult::Some(rs) => {
+                let rs: Vec<_> = rs.into_iter().map(|r| r[0].clone()).collect();
+                assert_eq!(rs, vec![2.into(), 3.into(), 4.into()]);
+            }
+            _ => unreachable!(),
+        }
+
+        // point lookups work on the ordered index too
+        match state.lookup(&[1], &KeyType::Single(&hi)) {
+            LookupResult::Some(rs) => assert_eq!(rs.len(), 1),
+            _ => unreachable!(),
+        }
+
+        // columns without an ordered index can't be looked up by range
+        assert!(matches!(
+            state.lookup_range(&[0], range),
+            LookupResult::Missing
+        ));
+    }
+
+    #[test]
+    fn memory_state_sample() {
+        let mut state = MemoryState::default();
+        state.add_key(&[0], None);
+        state.add_key(&[1], None);
+        // column 0 is unique, and column 1 has two values, one of which is far more common
+        for i in 0..100 {
+            let c = if i < 90 { 1 } else { 2 };
+            insert(&mut state, vec![i.into(), c.into()]);
+        }
+
+        let sample = state.sample(1000);
+        assert_eq!(sample.rows, 100);
+        assert_eq!(sample.columns.len(), 2);
+        assert!((95..=105).contains(&sample.columns[0].estimate()));
+        assert_eq!(sample.columns[1].estimate(), 2);
+        assert_eq!(sample.indices[0].keys, 100);
+        assert_eq!(sample.indices[1].columns, vec![1]);
+        assert_eq!(sample.indices[1].keys, 2);
+        assert_eq!(
+            sample.indices[1].hot_keys,
+            vec![(vec![1.into()], 90), (vec![2.into()], 10)]
+        );
+
+        // a smaller sample still counts all the rows and keys
+        let sample = state.sample(10);
+        assert_eq!(sample.rows, 100);
+        assert_eq!(sample.indices[0].keys, 100);
+        assert!(sample.columns[0].estimate() <= 10);
+    }
 }
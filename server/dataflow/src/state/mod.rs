@@ -1,3 +1,4 @@
+mod bloom;
 mod keyed_state;
 mod memory_state;
 mod mk_key;
@@ -5,10 +6,12 @@ mod persistent_state;
 mod single_state;
 
 use std::borrow::Cow;
-use std::ops::Deref;
+use std::ops::{Bound, Deref};
 use std::rc::Rc;
+use std::time;
 use std::vec;
 
+use crate::payload::StateSample;
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
@@ -17,9 +20,38 @@ use hashbag::HashBag;
 pub(crate) use self::memory_state::MemoryState;
 pub(crate) use self::persistent_state::PersistentState;
 
+/// The data structure that backs an index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IndexType {
+    /// A hash map, which only supports looking up individual keys.
+    HashMap,
+    /// An ordered map, which also supports scanning ranges of keys.
+    BTreeMap,
+}
+
+impl Default for IndexType {
+    fn default() -> Self {
+        IndexType::HashMap
+    }
+}
+
 pub(crate) trait State: SizeOf + Send {
-    /// Add an index keyed by the given columns and replayed to by the given partial tags.
-    fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>);
+    /// Add an index of the given type keyed by the given columns and replayed to by the given
+    /// partial tags.
+    ///
+    /// If the state already has an index on those columns, only the tags are added.
+    fn add_index(&mut self, columns: &[usize], index_type: IndexType, partial: Option<Vec<Tag>>);
+
+    /// Add a hash index keyed by the given columns and replayed to by the given partial tags.
+    fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
+        self.add_index(columns, IndexType::HashMap, partial)
+    }
+
+    /// Guard lookups into the full indices on the given columns with a Bloom filter, so that
+    /// lookups for keys that are not present are cheap.
+    ///
+    /// States that cannot make use of a filter ignore this.
+    fn add_bloom_filter(&mut self, _columns: &[usize]) {}
 
     /// Returns whether this state is currently keyed on anything. If not, then it cannot store any
     /// infromation and is thus "not useful".
@@ -37,10 +69,43 @@ pub(crate) trait State: SizeOf + Send {
 
     fn lookup<'a>(&'a self, columns: &[usize], key: &KeyType) -> LookupResult<'a>;
 
+    /// Look up all the rows whose values in the given columns fall within `range`, ordered by
+    /// those values.
+    ///
+    /// The columns must have an ordered index (see [`IndexType::BTreeMap`]), or the lookup is
+    /// `Missing`. Since a partial index cannot tell whether a range covers any holes, range lookups
+    /// into one are always `Missing` too.
+    fn lookup_range<'a>(
+        &'a self,
+        columns: &[usize],
+        range: (Bound<&[DataType]>, Bound<&[DataType]>),
+    ) -> LookupResult<'a>;
+
     fn rows(&self) -> usize;
 
     fn keys(&self) -> Vec<Vec<usize>>;
 
+    /// Look at no more than `rows` rows to estimate what the state contains.
+    ///
+    /// States that cannot be sampled cheaply only report their number of rows.
+    fn sample(&self, _rows: usize) -> StateSample {
+        StateSample {
+            rows: self.rows(),
+            ..Default::default()
+        }
+    }
+
+    /// Make sure that all the records this state has processed are durable.
+    ///
+    /// States that are not persisted have nothing to do.
+    fn sync(&mut self) {}
+
+    /// How many of the records this state has processed are not yet known to be durable, and
+    /// when the oldest of them was processed.
+    fn unsynced(&self) -> Option<(usize, time::Instant)> {
+        None
+    }
+
     /// Return a copy of all records. Panics if the state is only partially materialized.
     fn cloned_records(&self) -> Vec<Vec<DataType>>;
 
@@ -103,6 +168,8 @@ impl SizeOf for Row {
 pub(crate) enum RecordResult<'a> {
     Borrowed(&'a HashBag<Row, RandomState>),
     Owned(Vec<Vec<DataType>>),
+    /// Rows borrowed from more than one place, such as the keys covered by a range lookup.
+    Gathered(Vec<Cow<'a, [DataType]>>),
 }
 
 impl<'a> RecordResult<'a> {
@@ -110,6 +177,7 @@ impl<'a> RecordResult<'a> {
         match *self {
             RecordResult::Borrowed(rs) => rs.len(),
             RecordResult::Owned(ref rs) => rs.len(),
+            RecordResult::Gathered(ref rs) => rs.len(),
         }
     }
 
@@ -117,6 +185,7 @@ impl<'a> RecordResult<'a> {
         match *self {
             RecordResult::Borrowed(rs) => rs.is_empty(),
             RecordResult::Owned(ref rs) => rs.is_empty(),
+            RecordResult::Gathered(ref rs) => rs.is_empty(),
         }
     }
 }
@@ -129,6 +198,7 @@ impl<'a> IntoIterator for RecordResult<'a> {
         match self {
             RecordResult::Borrowed(rs) => RecordResultIterator::Borrowed(rs.iter()),
             RecordResult::Owned(rs) => RecordResultIterator::Owned(rs.into_iter()),
+            RecordResult::Gathered(rs) => RecordResultIterator::Gathered(rs.into_iter()),
         }
     }
 }
@@ -136,6 +206,7 @@ impl<'a> IntoIterator for RecordResult<'a> {
 pub(crate) enum RecordResultIterator<'a> {
     Owned(vec::IntoIter<Vec<DataType>>),
     Borrowed(hashbag::Iter<'a, Row>),
+    Gathered(vec::IntoIter<Cow<'a, [DataType]>>),
 }
 
 impl<'a> Iterator for RecordResultIterator<'a> {
@@ -144,6 +215,7 @@ impl<'a> Iterator for RecordResultIterator<'a> {
         match self {
             RecordResultIterator::Borrowed(iter) => iter.next().map(|r| Cow::from(&r[..])),
             RecordResultIterator::Owned(iter) => iter.next().map(Cow::from),
+            RecordResultIterator::Gathered(iter) => iter.next(),
         }
     }
 }
@@ -1,12 +1,15 @@
 use bincode;
 use itertools::Itertools;
-use rocksdb::{self, PlainTableFactoryOptions, SliceTransform, WriteBatch};
+use rocksdb::{self, Direction, PlainTableFactoryOptions, SliceTransform, WriteBatch};
 use serde;
 use tempfile::{tempdir, TempDir};
 
 use crate::prelude::*;
+use crate::state::keyed_state::ordered_key;
 use crate::state::{RecordResult, State};
 use common::SizeOf;
+use std::ops::Bound;
+use std::time;
 
 // Incremented on each PersistentState initialization so that IndexSeq
 // can be used to create unique identifiers for rows.
@@ -21,6 +24,11 @@ const META_KEY: &[u8] = b"meta";
 // The indices themselves are stored in a column family each, with their position in
 // PersistentState::indices as name.
 const DEFAULT_CF: &str = "default";
+// Ordered indices keep their keys in an encoding whose byte order matches the order of the
+// indexed values, so that they can be scanned by range. Their column families are named by their
+// position followed by this suffix, and are configured for total-order iteration rather than for
+// prefix seeks.
+const ORDERED_SUFFIX: &str = ".ordered";
 
 // Maximum rows per WriteBatch when building new indices for existing rows.
 const INDEX_BATCH_SIZE: usize = 100_000;
@@ -36,11 +44,13 @@ struct PersistentMeta {
 struct PersistentIndex {
     column_family: String,
     columns: Vec<usize>,
+    ordered: bool,
 }
 
 /// PersistentState stores data in RocksDB.
 pub struct PersistentState {
     db_opts: rocksdb::Options,
+    ordered_opts: rocksdb::Options,
     // We don't really want DB to be an option, but doing so lets us drop it manually in
     // PersistenState's Drop by setting `self.db = None` - after which we can then discard the
     // persisted files if we want to.
@@ -53,6 +63,10 @@ pub struct PersistentState {
     seq: IndexSeq,
     epoch: IndexEpoch,
     has_unique_index: bool,
+    fsync: FsyncPolicy,
+    // The number of records written since the WAL was last synced, and when the first of them was
+    // written.
+    unsynced: Option<(usize, time::Instant)>,
     // With DurabilityMode::DeleteOnExit,
     // RocksDB files are stored in a temporary directory.
     _directory: Option<TempDir>,
@@ -77,10 +91,30 @@ impl State for PersistentState {
             }
         }
 
-        // Sync the writes to RocksDB's WAL:
+        // Sync the writes to RocksDB's WAL, unless they are to be synced later:
+        let sync = self.fsync == FsyncPolicy::EveryWrite;
         let mut opts = rocksdb::WriteOptions::default();
-        opts.set_sync(true);
+        opts.set_sync(sync);
         tokio::task::block_in_place(|| self.db.as_ref().unwrap().write_opt(batch, &opts)).unwrap();
+        if !sync {
+            let unsynced = self
+                .unsynced
+                .get_or_insert_with(|| (0, time::Instant::now()));
+            unsynced.0 += records.len();
+        }
+    }
+
+    fn sync(&mut self) {
+        if self.unsynced.take().is_none() {
+            return;
+        }
+
+        // writing the meta information is synced, which also syncs everything written before it
+        tokio::task::block_in_place(|| self.persist_meta());
+    }
+
+    fn unsynced(&self) -> Option<(usize, time::Instant)> {
+        self.unsynced
     }
 
     fn lookup(&self, columns: &[usize], key: &KeyType) -> LookupResult {
@@ -92,6 +126,16 @@ impl State for PersistentState {
             .expect("lookup on non-indexed column set");
         tokio::task::block_in_place(|| {
             let cf = db.cf_handle(&self.indices[index_id].column_family).unwrap();
+            if self.indices[index_id].ordered {
+                let prefix = Self::serialize_ordered(ordered_key(key).iter(), &[]);
+                let data = db
+                    .iterator_cf(cf, rocksdb::IteratorMode::From(&prefix, Direction::Forward))
+                    .take_while(|(k, _)| k.starts_with(&prefix))
+                    .map(|(_key, value)| bincode::deserialize(&*value).unwrap())
+                    .collect();
+                return LookupResult::Some(RecordResult::Owned(data));
+            }
+
             let prefix = Self::serialize_prefix(&key);
             let data = if index_id == 0 && self.has_unique_index {
                 // This is a primary key, so we know there's only one row to retrieve
@@ -114,7 +158,10 @@ impl State for PersistentState {
         })
     }
 
-    fn add_key(&mut self, columns: &[usize], partial: Option<Vec<Tag>>) {
+    // The first index holds the rows themselves, and is always keyed for prefix seeks. Any later
+    // index that is asked to be ordered keeps its keys in value order, so that it supports range
+    // lookups.
+    fn add_index(&mut self, columns: &[usize], index_type: IndexType, partial: Option<Vec<Tag>>) {
         assert!(partial.is_none(), "Bases can't be partial");
         let existing = self
             .indices
@@ -126,13 +173,23 @@ impl State for PersistentState {
         }
 
         let cols = Vec::from(columns);
+        let ordered = index_type == IndexType::BTreeMap && !self.indices.is_empty();
         // We'll store all the pointers (or values if this is index 0) for
         // this index in its own column family:
-        let index_id = self.indices.len().to_string();
+        let index_id = if ordered {
+            format!("{}{}", self.indices.len(), ORDERED_SUFFIX)
+        } else {
+            self.indices.len().to_string()
+        };
 
         tokio::task::block_in_place(|| {
             let db = self.db.as_mut().unwrap();
-            db.create_cf(&index_id, &self.db_opts).unwrap();
+            let opts = if ordered {
+                &self.ordered_opts
+            } else {
+                &self.db_opts
+            };
+            db.create_cf(&index_id, opts).unwrap();
 
             // Build the new index for existing values:
             if !self.indices.is_empty() {
@@ -142,8 +199,11 @@ impl State for PersistentState {
                     let mut batch = WriteBatch::default();
                     for (ref pk, ref value) in chunk {
                         let row: Vec<DataType> = bincode::deserialize(&value).unwrap();
-                        let index_key = Self::build_key(&row, columns);
-                        let key = Self::serialize_secondary(&index_key, pk);
+                        let key = if ordered {
+                            Self::serialize_ordered(columns.iter().map(|&c| &row[c]), pk)
+                        } else {
+                            Self::serialize_secondary(&Self::build_key(&row, columns), pk)
+                        };
                         let cf = db.cf_handle(&index_id).unwrap();
                         batch.put_cf(cf, &key, value);
                     }
@@ -155,6 +215,7 @@ impl State for PersistentState {
             self.indices.push(PersistentIndex {
                 columns: cols,
                 column_family: index_id.to_string(),
+                ordered,
             });
 
             self.persist_meta();
@@ -168,6 +229,54 @@ impl State for PersistentState {
             .collect()
     }
 
+    fn lookup_range<'a>(
+        &'a self,
+        columns: &[usize],
+        range: (Bound<&[DataType]>, Bound<&[DataType]>),
+    ) -> LookupResult<'a> {
+        let index = match self
+            .indices
+            .iter()
+            .find(|index| index.ordered && &index.columns[..] == columns)
+        {
+            Some(index) => index,
+            None => return LookupResult::Missing,
+        };
+
+        // every key in the index is the encoded value followed by the primary key of its row, so
+        // a key falls under a bound if it starts with the bound's encoding.
+        let encode = |b: Bound<&[DataType]>| match b {
+            Bound::Included(k) => Bound::Included(Self::serialize_ordered(k.iter(), &[])),
+            Bound::Excluded(k) => Bound::Excluded(Self::serialize_ordered(k.iter(), &[])),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let (lo, hi) = (encode(range.0), encode(range.1));
+        tokio::task::block_in_place(|| {
+            let db = self.db.as_ref().unwrap();
+            let cf = db.cf_handle(&index.column_family).unwrap();
+            let mode = match lo {
+                Bound::Included(ref k) | Bound::Excluded(ref k) => {
+                    rocksdb::IteratorMode::From(k, Direction::Forward)
+                }
+                Bound::Unbounded => rocksdb::IteratorMode::Start,
+            };
+            let rows = db
+                .iterator_cf(cf, mode)
+                .skip_while(|(k, _)| match lo {
+                    Bound::Excluded(ref lo) => k.starts_with(lo),
+                    _ => false,
+                })
+                .take_while(|(k, _)| match hi {
+                    Bound::Included(ref hi) => k[..] < hi[..] || k.starts_with(hi),
+                    Bound::Excluded(ref hi) => k[..] < hi[..],
+                    Bound::Unbounded => true,
+                })
+                .map(|(_, value)| bincode::deserialize(&*value).unwrap())
+                .collect();
+            LookupResult::Some(RecordResult::Owned(rows))
+        })
+    }
+
     fn cloned_records(&self) -> Vec<Vec<DataType>> {
         self.all_rows()
             .map(|(_, ref value)| bincode::deserialize(&value).unwrap())
@@ -235,7 +344,7 @@ impl PersistentState {
                 }
             };
 
-            let opts = Self::build_options(&name, params);
+            let opts = Self::build_options(&name, params, false);
             // We use a column for each index, and one for meta information.
             // When opening the DB the exact same column families needs to be used,
             // so we'll have to retrieve the existing ones first:
@@ -248,7 +357,11 @@ impl PersistentState {
                 column_families
                     .iter()
                     .map(|cf| {
-                        ColumnFamilyDescriptor::new(cf.clone(), Self::build_options(&name, &params))
+                        let ordered = cf.ends_with(ORDERED_SUFFIX);
+                        ColumnFamilyDescriptor::new(
+                            cf.clone(),
+                            Self::build_options(&name, &params, ordered),
+                        )
                     })
                     .collect()
             };
@@ -267,9 +380,21 @@ impl PersistentState {
                 .indices
                 .into_iter()
                 .enumerate()
-                .map(|(i, columns)| PersistentIndex {
-                    column_family: i.to_string(),
-                    columns,
+                .map(|(i, columns)| {
+                    let ordered = format!("{}{}", i, ORDERED_SUFFIX);
+                    if column_families.contains(&ordered) {
+                        PersistentIndex {
+                            column_family: ordered,
+                            columns,
+                            ordered: true,
+                        }
+                    } else {
+                        PersistentIndex {
+                            column_family: i.to_string(),
+                            columns,
+                            ordered: false,
+                        }
+                    }
                 })
                 .collect();
 
@@ -277,13 +402,20 @@ impl PersistentState {
             // family) we probably crashed while trying to build the last index (in Self::add_key), so
             // we'll throw away our progress and try re-building it again later:
             if column_families.len() - 1 > indices.len() {
-                db.drop_cf(&indices.len().to_string()).unwrap();
+                for cf in &column_families {
+                    if cf != DEFAULT_CF && !indices.iter().any(|i| &i.column_family == cf) {
+                        db.drop_cf(cf).unwrap();
+                    }
+                }
             }
 
             let mut state = Self {
+                ordered_opts: Self::build_options(&name, params, true),
                 seq: 0,
                 indices,
                 has_unique_index: primary_key.is_some(),
+                fsync: params.fsync,
+                unsynced: None,
                 epoch: meta.epoch,
                 db_opts: opts,
                 db: Some(db),
@@ -303,6 +435,7 @@ impl PersistentState {
                 let persistent_index = PersistentIndex {
                     column_family: "0".to_string(),
                     columns: primary_key.unwrap().to_vec(),
+                    ordered: false,
                 };
 
                 state.indices.push(persistent_index);
@@ -313,22 +446,30 @@ impl PersistentState {
         })
     }
 
-    fn build_options(name: &str, params: &PersistenceParameters) -> rocksdb::Options {
+    // Options for the database, and for the column families of its indices. Unless `ordered` is
+    // set, the column family is set up for prefix seeks only, and cannot be iterated by range.
+    fn build_options(
+        name: &str,
+        params: &PersistenceParameters,
+        ordered: bool,
+    ) -> rocksdb::Options {
         let mut opts = rocksdb::Options::default();
         opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
 
-        let user_key_length = 0; // variable key length
-        let bloom_bits_per_key = 10;
-        let hash_table_ratio = 0.75;
-        let index_sparseness = 16;
-        opts.set_plain_table_factory(&PlainTableFactoryOptions {
-            user_key_length,
-            bloom_bits_per_key,
-            hash_table_ratio,
-            index_sparseness,
-        });
+        if !ordered {
+            let user_key_length = 0; // variable key length
+            let bloom_bits_per_key = 10;
+            let hash_table_ratio = 0.75;
+            let index_sparseness = 16;
+            opts.set_plain_table_factory(&PlainTableFactoryOptions {
+                user_key_length,
+                bloom_bits_per_key,
+                hash_table_ratio,
+                index_sparseness,
+            });
+        }
 
         if let Some(ref path) = params.log_dir {
             // Append the db name to the WAL path to ensure
@@ -337,8 +478,10 @@ impl PersistentState {
         }
 
         // Create prefixes using `prefix_transform` on all new inserted keys:
-        let transform = SliceTransform::create("key", prefix_transform, Some(in_domain));
-        opts.set_prefix_extractor(transform);
+        if !ordered {
+            let transform = SliceTransform::create("key", prefix_transform, Some(in_domain));
+            opts.set_prefix_extractor(transform);
+        }
 
         // Assigns the number of threads for compactions and flushes in RocksDB.
         // Optimally we'd like to use env->SetBackgroundThreads(n, Env::HIGH)
@@ -363,11 +506,14 @@ impl PersistentState {
         // Keep up to 4 parallel memtables:
         opts.set_max_write_buffer_number(4);
 
-        // Use a hash linked list since we're doing prefix seeks.
+        // Use a hash linked list since we're doing prefix seeks. Ordered indices keep the
+        // default skip list, which can be iterated in key order.
         opts.set_allow_concurrent_memtable_write(false);
-        opts.set_memtable_factory(rocksdb::MemtableFactory::HashLinkList {
-            bucket_count: 1_000_000,
-        });
+        if !ordered {
+            opts.set_memtable_factory(rocksdb::MemtableFactory::HashLinkList {
+                bucket_count: 1_000_000,
+            });
+        }
 
         opts
     }
@@ -399,7 +545,9 @@ impl PersistentState {
         };
 
         let data = bincode::serialize(&meta).unwrap();
-        db.put(META_KEY, &data).unwrap();
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(true);
+        db.put_opt(META_KEY, &data, &opts).unwrap();
     }
 
     // Our RocksDB keys come in three forms, and are encoded as follows:
@@ -444,6 +592,70 @@ impl PersistentState {
         bytes
     }
 
+    // The key of row `r` in the secondary index `index`.
+    fn serialize_index_key(index: &PersistentIndex, r: &[DataType], raw_primary: &[u8]) -> Vec<u8> {
+        if index.ordered {
+            Self::serialize_ordered(index.columns.iter().map(|&c| &r[c]), raw_primary)
+        } else {
+            Self::serialize_secondary(&Self::build_key(r, &index.columns), raw_primary)
+        }
+    }
+
+    // Keys of ordered indices are encoded so that their byte order matches the order of their
+    // values (see `DataType`'s `Ord`), followed by the raw primary key of the row. Each value is a
+    // tag for its type, followed by:
+    //
+    // * integers as 16 big-endian bytes, with the sign bit flipped
+    // * reals as their integral and fractional parts, both big-endian with the sign bit flipped
+    // * text as its bytes, with 0 escaped as (0, 255), and terminated by (0, 1)
+    // * timestamps as their seconds and nanoseconds since the epoch, as for reals
+    // * NULL as nothing at all
+    //
+    // Since every encoded value ends where it must, no key is a prefix of another key with
+    // different values.
+    fn serialize_ordered<'a>(
+        key: impl IntoIterator<Item = &'a DataType>,
+        raw_primary: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for v in key {
+            match *v {
+                DataType::Int(..)
+                | DataType::UnsignedInt(..)
+                | DataType::BigInt(..)
+                | DataType::UnsignedBigInt(..) => {
+                    let n: i128 = v.into();
+                    bytes.push(0);
+                    bytes.extend_from_slice(&((n as u128) ^ (1 << 127)).to_be_bytes());
+                }
+                DataType::Real(i, f) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&((i as u64) ^ (1 << 63)).to_be_bytes());
+                    bytes.extend_from_slice(&((f as u32) ^ (1 << 31)).to_be_bytes());
+                }
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = v.into();
+                    bytes.push(2);
+                    for &b in s.as_bytes() {
+                        bytes.push(b);
+                        if b == 0 {
+                            bytes.push(255);
+                        }
+                    }
+                    bytes.extend_from_slice(&[0, 1]);
+                }
+                DataType::Timestamp(ts) => {
+                    bytes.push(3);
+                    bytes.extend_from_slice(&((ts.timestamp() as u64) ^ (1 << 63)).to_be_bytes());
+                    bytes.extend_from_slice(&ts.timestamp_subsec_nanos().to_be_bytes());
+                }
+                DataType::None => bytes.push(4),
+            }
+        }
+        bytes.extend_from_slice(raw_primary);
+        bytes
+    }
+
     // Filters out secondary indices to return an iterator for the actual key-value pairs.
     fn all_rows(&self) -> impl Iterator<Item = (Box<[u8]>, Box<[u8]>)> + '_ {
         let db = self.db.as_ref().unwrap();
@@ -480,9 +692,7 @@ impl PersistentState {
 
             // Then insert primary key pointers for all the secondary indices:
             for index in self.indices[1..].iter() {
-                // Construct a key with the index values, and serialize it with bincode:
-                let key = Self::build_key(&r, &index.columns);
-                let serialized_key = Self::serialize_secondary(&key, &serialized_pk);
+                let serialized_key = Self::serialize_index_key(index, r, &serialized_pk);
                 let cf = db.cf_handle(&index.column_family).unwrap();
                 batch.put_cf(cf, &serialized_key, &serialized_row);
             }
@@ -500,8 +710,7 @@ impl PersistentState {
 
                 // Then delete any references that point _exactly_ to that row:
                 for index in self.indices[1..].iter() {
-                    let key = Self::build_key(&r, &index.columns);
-                    let serialized_key = Self::serialize_secondary(&key, primary_key);
+                    let serialized_key = Self::serialize_index_key(index, r, primary_key);
                     let cf = db.cf_handle(&index.column_family).unwrap();
                     batch.delete_cf(cf, &serialized_key);
                 }
@@ -635,6 +844,26 @@ mod tests {
         )
     }
 
+    #[test]
+    fn persistent_state_syncs_on_demand() {
+        let mut params = PersistenceParameters::default();
+        params.fsync = FsyncPolicy::OsBuffered;
+        let mut state =
+            PersistentState::new(String::from("persistent_state_syncs"), Some(&[0]), &params);
+        assert_eq!(state.unsynced(), None);
+
+        insert(&mut state, vec![1.into(), 2.into()]);
+        insert(&mut state, vec![2.into(), 2.into()]);
+        assert_eq!(state.unsynced().map(|(n, _)| n), Some(2));
+
+        state.sync();
+        assert_eq!(state.unsynced(), None);
+        match state.lookup(&[0], &KeyType::Single(&1.into())) {
+            LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows.len(), 1),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn persistent_state_is_partial() {
         let state = setup_persistent("persistent_state_is_partial");
@@ -671,12 +900,12 @@ mod tests {
         state.add_key(columns, None);
         insert(&mut state, row.clone());
 
-        match state.lookup(columns, &KeyType::Double((1.into(), 2.into()))) {
+        match state.lookup(columns, &KeyType::Double((&1.into(), &2.into()))) {
             LookupResult::Some(RecordResult::Owned(rows)) => assert_eq!(rows.len(), 0),
             _ => unreachable!(),
         };
 
-        match state.lookup(columns, &KeyType::Double((10.into(), 20.into()))) {
+        match state.lookup(columns, &KeyType::Double((&10.into(), &20.into()))) {
             LookupResult::Some(RecordResult::Owned(rows)) => {
                 assert_eq!(rows[0], row);
             }
@@ -701,7 +930,7 @@ mod tests {
             _ => unreachable!(),
         }
 
-        match state.lookup(&[1, 2], &KeyType::Double(("Cat".into(), 1.into()))) {
+        match state.lookup(&[1, 2], &KeyType::Double((&"Cat".into(), &1.into()))) {
             LookupResult::Some(RecordResult::Owned(rows)) => {
                 assert_eq!(rows.len(), 2);
                 assert_eq!(&rows[0], &first);
@@ -725,7 +954,7 @@ mod tests {
         state.add_key(&[2], None);
         state.process_records(&mut vec![first.clone(), second.clone()].into(), None);
 
-        match state.lookup(pk, &KeyType::Double((1.into(), 2.into()))) {
+        match state.lookup(pk, &KeyType::Double((&1.into(), &2.into()))) {
             LookupResult::Some(RecordResult::Owned(rows)) => {
                 assert_eq!(rows.len(), 1);
                 assert_eq!(&rows[0], &first);
@@ -733,7 +962,7 @@ mod tests {
             _ => unreachable!(),
         }
 
-        match state.lookup(pk, &KeyType::Double((10.into(), 20.into()))) {
+        match state.lookup(pk, &KeyType::Double((&10.into(), &20.into()))) {
             LookupResult::Some(RecordResult::Owned(rows)) => {
                 assert_eq!(rows.len(), 1);
                 assert_eq!(&rows[0], &second);
@@ -741,7 +970,7 @@ mod tests {
             _ => unreachable!(),
         }
 
-        match state.lookup(pk, &KeyType::Double((1.into(), 20.into()))) {
+        match state.lookup(pk, &KeyType::Double((&1.into(), &20.into()))) {
             LookupResult::Some(RecordResult::Owned(rows)) => {
                 assert_eq!(rows.len(), 0);
             }
@@ -993,6 +1222,100 @@ mod tests {
         assert_eq!(size, 0);
     }
 
+    #[test]
+    fn persistent_state_range_lookup() {
+        let (_dir, name) = get_tmp_path();
+        let mut params = PersistenceParameters::default();
+        params.mode = DurabilityMode::Permanent;
+        let range_of =
+            |state: &PersistentState, lo: Bound<&[DataType]>, hi: Bound<&[DataType]>| match state
+                .lookup_range(&[1], (lo, hi))
+            {
+                LookupResult::Some(rs) => rs.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+                LookupResult::Missing => unreachable!(),
+            };
+        let (lo, hi) = ([DataType::from(-5)], [DataType::from(20)]);
+
+        {
+            let mut state = PersistentState::new(name.clone(), Some(&[0]), &params);
+            state.add_key(&[0], None);
+            for i in 0..10 {
+                insert(&mut state, vec![i.into(), ((i - 5) * 10).into()]);
+            }
+            // an index built over existing rows is ordered too
+            state.add_index(&[1], IndexType::BTreeMap, None);
+            insert(&mut state, vec![10.into(), "text".into()]);
+            insert(&mut state, vec![11.into(), DataType::None]);
+
+            assert_eq!(
+                range_of(&state, Bound::Included(&lo[..]), Bound::Included(&hi[..])),
+                vec![5.into(), 6.into(), 7.into()]
+            );
+            assert_eq!(
+                range_of(&state, Bound::Excluded(&lo[..]), Bound::Excluded(&hi[..])),
+                vec![5.into(), 6.into()]
+            );
+            assert_eq!(
+                range_of(&state, Bound::Unbounded, Bound::Excluded(&lo[..])),
+                vec![0.into(), 1.into(), 2.into(), 3.into(), 4.into()]
+            );
+
+            // deleted rows are gone from the ordered index
+            let mut rs: Records = vec![(vec![5.into(), 0.into()], false)].into();
+            state.process_records(&mut rs, None);
+
+            // point lookups work on the ordered index too
+            match state.lookup(&[1], &KeyType::Single(&"text".into())) {
+                LookupResult::Some(RecordResult::Owned(rs)) => assert_eq!(rs.len(), 1),
+                _ => unreachable!(),
+            }
+        }
+
+        // the index is still ordered after recovery
+        let state = PersistentState::new(name, Some(&[0]), &params);
+        assert_eq!(
+            range_of(&state, Bound::Excluded(&hi[..]), Bound::Unbounded),
+            vec![8.into(), 9.into(), 10.into(), 11.into()]
+        );
+        assert_eq!(
+            range_of(&state, Bound::Included(&lo[..]), Bound::Included(&hi[..])),
+            vec![6.into(), 7.into()]
+        );
+
+        // columns without an ordered index can't be looked up by range
+        assert!(matches!(
+            state.lookup_range(&[0], (Bound::Unbounded, Bound::Unbounded)),
+            LookupResult::Missing
+        ));
+    }
+
+    #[test]
+    fn persistent_state_ordered_keys() {
+        let values: Vec<DataType> = vec![
+            DataType::BigInt(std::i64::MIN),
+            (-1).into(),
+            0.into(),
+            DataType::UnsignedBigInt(std::u64::MAX),
+            DataType::Real(-1, 500_000_000),
+            DataType::Real(1, 0),
+            "".into(),
+            "a".into(),
+            "a\u{0}".into(),
+            "a very long piece of text".into(),
+            "ab".into(),
+            DataType::Timestamp(chrono::NaiveDateTime::from_timestamp(-1, 0)),
+            DataType::Timestamp(chrono::NaiveDateTime::from_timestamp(1, 1)),
+            DataType::None,
+        ];
+        for (a, b) in values.iter().zip(&values[1..]) {
+            assert!(a < b, "{:?} < {:?}", a, b);
+            let ka = PersistentState::serialize_ordered(vec![a, b], &[]);
+            let kb = PersistentState::serialize_ordered(vec![b, a], &[]);
+            assert!(ka < kb, "{:?} < {:?}", a, b);
+            assert!(!kb.starts_with(&PersistentState::serialize_ordered(vec![a], &[])));
+        }
+    }
+
     #[test]
     fn persistent_state_dangling_indices() {
         let (_dir, name) = get_tmp_path();
@@ -1147,7 +1470,7 @@ mod tests {
         let mut state = setup_persistent("persistent_state_prefix_transform");
         state.add_key(&[0], None);
         let data = (DataType::from(1), DataType::from(10));
-        let r = KeyType::Double(data.clone());
+        let r = KeyType::Double((&data.0, &data.1));
         let k = PersistentState::serialize_prefix(&r);
         let prefix = prefix_transform(&k);
         let size: u64 = bincode::deserialize(&prefix).unwrap();
@@ -1,8 +1,11 @@
+use super::bloom::BloomFilter;
 use super::mk_key::MakeKey;
 use crate::prelude::*;
 use crate::state::keyed_state::KeyedState;
 use common::SizeOf;
 use rand::prelude::*;
+use std::borrow::Cow;
+use std::ops::Bound;
 use std::rc::Rc;
 
 pub(super) struct SingleState {
@@ -10,6 +13,7 @@ pub(super) struct SingleState {
     state: KeyedState,
     partial: bool,
     rows: usize,
+    bloom: Option<BloomFilter>,
 }
 
 macro_rules! insert_row_match_impl {
@@ -38,19 +42,47 @@ macro_rules! remove_row_match_impl {
 }
 
 impl SingleState {
-    pub(super) fn new(columns: &[usize], partial: bool) -> Self {
+    pub(super) fn new(columns: &[usize], index_type: IndexType, partial: bool) -> Self {
         Self {
             key: Vec::from(columns),
-            state: columns.into(),
+            state: KeyedState::new(columns, index_type),
             partial,
             rows: 0,
+            bloom: None,
         }
     }
 
+    /// Keep a Bloom filter over the keys of this index, so that lookups for keys that are not
+    /// present can skip the index.
+    ///
+    /// Only full indices use the filter, since a key missing from a partial index may be a hole.
+    pub(super) fn add_bloom_filter(&mut self) {
+        if !self.partial && self.bloom.is_none() {
+            self.rebuild_bloom_filter(self.rows);
+        }
+    }
+
+    /// Build a fresh filter sized for `keys` keys from the keys currently present.
+    fn rebuild_bloom_filter(&mut self, keys: usize) {
+        let mut bloom = BloomFilter::with_capacity(keys);
+        for rs in self.values() {
+            // a key with no rows left is as good as absent
+            if let Some(r) = rs.iter().next() {
+                let h = bloom.hash(self.key.iter().map(|&c| &r[c]));
+                bloom.insert(h);
+            }
+        }
+        self.bloom = Some(bloom);
+    }
+
     /// Inserts the given record, or returns false if a hole was encountered (and the record hence
     /// not inserted).
     pub(super) fn insert_row(&mut self, r: Row) -> bool {
         use indexmap::map::Entry;
+        let hash = self
+            .bloom
+            .as_ref()
+            .map(|b| b.hash(self.key.iter().map(|&c| &r[c])));
         match self.state {
             KeyedState::Single(ref mut map) => {
                 // treat this specially to avoid the extra Vec
@@ -72,9 +104,31 @@ impl SingleState {
             KeyedState::Quad(ref mut map) => insert_row_match_impl!(self, r, map),
             KeyedState::Quin(ref mut map) => insert_row_match_impl!(self, r, map),
             KeyedState::Sex(ref mut map) => insert_row_match_impl!(self, r, map),
+            KeyedState::Ordered(ref mut map) => {
+                let key: Vec<_> = self.key.iter().map(|&c| r[c].clone()).collect();
+                match map.get_mut(&key) {
+                    Some(rs) => {
+                        rs.insert(r);
+                    }
+                    None if self.partial => return false,
+                    None => {
+                        map.insert(key, std::iter::once(r).collect());
+                    }
+                }
+            }
         }
 
         self.rows += 1;
+        let mut grow = None;
+        if let (Some(bloom), Some(hash)) = (self.bloom.as_mut(), hash) {
+            bloom.insert(hash);
+            if bloom.is_full() {
+                grow = Some(2 * bloom.capacity());
+            }
+        }
+        if let Some(keys) = grow {
+            self.rebuild_bloom_filter(keys);
+        }
         true
     }
 
@@ -127,11 +181,21 @@ impl SingleState {
             KeyedState::Sex(ref mut map) => {
                 remove_row_match_impl!(self, r, do_remove, map, (DataType, _, _, _, _, _))
             }
+            KeyedState::Ordered(ref mut map) => {
+                let key: Vec<_> = self.key.iter().map(|&c| r[c].clone()).collect();
+                if let Some(ref mut rs) = map.get_mut(&key) {
+                    return do_remove(&mut self.rows, rs);
+                }
+            }
         }
         None
     }
 
     pub(super) fn mark_filled(&mut self, key: Vec<DataType>) {
+        if let KeyedState::Ordered(ref mut map) = self.state {
+            assert!(map.insert(key, Rows::default()).is_none());
+            return;
+        }
         let mut key = key.into_iter();
         let replaced = match self.state {
             KeyedState::Single(ref mut map) => map.insert(key.next().unwrap(), Rows::default()),
@@ -176,6 +240,7 @@ impl SingleState {
                 ),
                 Rows::default(),
             ),
+            KeyedState::Ordered(..) => unreachable!(),
         };
         assert!(replaced.is_none());
     }
@@ -198,6 +263,7 @@ impl SingleState {
             KeyedState::Sex(ref mut m) => {
                 m.swap_remove::<(DataType, _, _, _, _, _)>(&MakeKey::from_key(key))
             }
+            KeyedState::Ordered(ref mut m) => m.remove(key),
         };
         // mark_hole should only be called on keys we called mark_filled on
         removed
@@ -210,6 +276,9 @@ impl SingleState {
 
     pub(super) fn clear(&mut self) {
         self.rows = 0;
+        if self.bloom.is_some() {
+            self.bloom = Some(BloomFilter::with_capacity(0));
+        }
         match self.state {
             KeyedState::Single(ref mut map) => map.clear(),
             KeyedState::Double(ref mut map) => map.clear(),
@@ -217,6 +286,7 @@ impl SingleState {
             KeyedState::Quad(ref mut map) => map.clear(),
             KeyedState::Quin(ref mut map) => map.clear(),
             KeyedState::Sex(ref mut map) => map.clear(),
+            KeyedState::Ordered(ref mut map) => map.clear(),
         };
     }
 
@@ -253,8 +323,12 @@ impl SingleState {
             KeyedState::Quad(ref map) => Box::new(map.values()),
             KeyedState::Quin(ref map) => Box::new(map.values()),
             KeyedState::Sex(ref map) => Box::new(map.values()),
+            KeyedState::Ordered(ref map) => Box::new(map.values()),
         }
     }
+    pub(super) fn index_type(&self) -> IndexType {
+        self.state.index_type()
+    }
     pub(super) fn key(&self) -> &[usize] {
         &self.key
     }
@@ -264,10 +338,37 @@ impl SingleState {
     pub(super) fn rows(&self) -> usize {
         self.rows
     }
+    pub(super) fn keys(&self) -> usize {
+        self.state.len()
+    }
     pub(super) fn is_empty(&self) -> bool {
         self.rows == 0
     }
+    /// All the rows whose keys fall within the given bounds, in key order.
+    ///
+    /// Returns `None` if the index is partial, since there is no telling whether the range
+    /// covers any holes.
+    pub(super) fn lookup_range<'a>(
+        &'a self,
+        range: (Bound<&[DataType]>, Bound<&[DataType]>),
+    ) -> Option<Vec<Cow<'a, [DataType]>>> {
+        if self.partial {
+            return None;
+        }
+        Some(
+            self.state
+                .lookup_range(range)
+                .flat_map(|rs| rs.iter().map(|r| Cow::from(&r[..])))
+                .collect(),
+        )
+    }
+
     pub(super) fn lookup<'a>(&'a self, key: &KeyType) -> LookupResult<'a> {
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(bloom.hash_key(key)) {
+                return LookupResult::Some(RecordResult::Owned(vec![]));
+            }
+        }
         if let Some(rs) = self.state.lookup(key) {
             LookupResult::Some(RecordResult::Borrowed(rs))
         } else if self.partial() {
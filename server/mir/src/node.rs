@@ -324,6 +324,51 @@ impl MirNode {
         &self.name
     }
 
+    /// The name of the dataflow node made for this node.
+    ///
+    /// Generated node names only say which query and which step of it a node belongs to, so the
+    /// dataflow node's name also says what the node does and which base tables it reads from,
+    /// like `q_3a5f_n2_join_article_user`. Base and leaf nodes keep their names, since tables and
+    /// views are known by those.
+    pub fn flow_node_name(&self) -> String {
+        match self.inner {
+            MirNodeType::Base { .. } | MirNodeType::Leaf { .. } | MirNodeType::Reuse { .. } => {
+                self.name.clone()
+            }
+            ref inner => {
+                let mut name = format!("{}_{}", self.name, inner.kind());
+                for table in self.source_tables() {
+                    name.push('_');
+                    name.push_str(&table);
+                }
+                name
+            }
+        }
+    }
+
+    /// The names of the base tables that this node reads from, in the order they are first
+    /// found in.
+    pub fn source_tables(&self) -> Vec<String> {
+        let mut tables = Vec::new();
+        let mut add = |node: &MirNode| {
+            for table in node.source_tables() {
+                if !tables.contains(&table) {
+                    tables.push(table);
+                }
+            }
+        };
+        match self.inner {
+            MirNodeType::Base { .. } => return vec![self.name.clone()],
+            MirNodeType::Reuse { ref node } => add(&node.borrow()),
+            _ => {
+                for a in &self.ancestors {
+                    add(&a.borrow());
+                }
+            }
+        }
+        tables
+    }
+
     pub fn referenced_columns(&self) -> Vec<Column> {
         // all projected columns
         let mut columns = self.columns.clone();
@@ -380,8 +425,9 @@ impl MirNode {
     /// same name on `Ingredient`.
     fn description(&self) -> String {
         format!(
-            "{}: {} / {} columns",
-            self.versioned_name(),
+            "{}_v{}: {} / {} columns",
+            self.flow_node_name(),
+            self.from_version,
             self.inner.description(),
             self.columns.len()
         )
@@ -477,10 +523,14 @@ pub enum MirNodeType {
     Reuse {
         node: MirNodeRef,
     },
-    /// leaf (reader) node, keys
+    /// leaf (reader) node, keys, the order that the rows of each key are returned in, how
+    /// many rows of each key are kept, and the columns that echo parameters of the projection
     Leaf {
         node: MirNodeRef,
         keys: Vec<Column>,
+        order: Option<Vec<(Column, OrderType)>>,
+        limit: Option<usize>,
+        echoed: Vec<Column>,
     },
     /// Rewrite node
     Rewrite {
@@ -495,6 +545,29 @@ impl MirNodeType {
         format!("{:?}", self)
     }
 
+    /// A short name for what nodes of this type do.
+    fn kind(&self) -> &'static str {
+        match *self {
+            MirNodeType::Aggregation { .. } => "aggregate",
+            MirNodeType::Base { .. } => "base",
+            MirNodeType::Extremum { .. } => "extremum",
+            MirNodeType::Filter { .. } => "filter",
+            MirNodeType::FilterAggregation { .. } => "filteraggregate",
+            MirNodeType::GroupConcat { .. } => "groupconcat",
+            MirNodeType::Identity => "identity",
+            MirNodeType::Join { .. } => "join",
+            MirNodeType::LeftJoin { .. } => "leftjoin",
+            MirNodeType::Latest { .. } => "latest",
+            MirNodeType::Project { .. } => "project",
+            MirNodeType::Union { .. } => "union",
+            MirNodeType::TopK { .. } => "topk",
+            MirNodeType::Distinct { .. } => "distinct",
+            MirNodeType::Reuse { ref node } => node.borrow().inner.kind(),
+            MirNodeType::Leaf { .. } => "leaf",
+            MirNodeType::Rewrite { .. } => "rewrite",
+        }
+    }
+
     fn add_column(&mut self, c: Column) {
         match *self {
             MirNodeType::Aggregation {
@@ -742,9 +815,24 @@ impl MirNodeType {
                 _ => false,
             },
             MirNodeType::Leaf {
-                keys: ref our_keys, ..
+                keys: ref our_keys,
+                order: ref our_order,
+                limit: our_limit,
+                echoed: ref our_echoed,
+                ..
             } => match *other {
-                MirNodeType::Leaf { ref keys, .. } => keys == our_keys,
+                MirNodeType::Leaf {
+                    ref keys,
+                    ref order,
+                    limit,
+                    ref echoed,
+                    ..
+                } => {
+                    keys == our_keys
+                        && order == our_order
+                        && limit == our_limit
+                        && echoed == our_echoed
+                }
                 _ => false,
             },
             MirNodeType::Union { emit: ref our_emit } => match *other {
@@ -874,6 +962,12 @@ impl Debug for MirNodeType {
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Any(_) => {
+                                Some(format!("f{} {}", i, escape(&format!("{}", cond))))
+                            }
+                            FilterCondition::Expression(..) | FilterCondition::InTuples(..) => {
+                                Some(escape(&format!("{}", cond)))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Error, Formatter};
 
 use crate::MirNodeRef;
@@ -32,6 +32,26 @@ impl MirQuery {
         }
     }
 
+    /// The nodes of this query, in topological order, found by walking up from its leaf.
+    ///
+    /// Unlike `topo_nodes`, this leaves out the nodes of other queries that hang off the nodes
+    /// that this query shares with them.
+    pub fn nodes(&self) -> Vec<MirNodeRef> {
+        fn visit(n: &MirNodeRef, seen: &mut HashSet<String>, nodes: &mut Vec<MirNodeRef>) {
+            if !seen.insert(n.borrow().versioned_name()) {
+                return;
+            }
+            for a in n.borrow().ancestors() {
+                visit(a, seen, nodes);
+            }
+            nodes.push(n.clone());
+        }
+
+        let mut nodes = Vec::new();
+        visit(&self.leaf, &mut HashSet::new(), &mut nodes);
+        nodes
+    }
+
     #[cfg(test)]
     pub fn topo_nodes(&self) -> Vec<MirNodeRef> {
         use std::collections::VecDeque;
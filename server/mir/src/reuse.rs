@@ -276,6 +276,9 @@ mod tests {
             MirNodeType::Leaf {
                 node: c.clone(),
                 keys: vec![Column::from("ba")],
+                order: None,
+                limit: None,
+                echoed: vec![],
             },
             vec![],
             vec![],
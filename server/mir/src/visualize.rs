@@ -193,6 +193,12 @@ impl GraphViz for MirNodeType {
                                     .collect::<Vec<_>>()
                                     .join(", ")
                             )),
+                            FilterCondition::Any(_) => {
+                                Some(format!("f{} {}", i, escape(&format!("{}", cond))))
+                            }
+                            FilterCondition::Expression(..) | FilterCondition::InTuples(..) => {
+                                Some(escape(&format!("{}", cond)))
+                            }
                         })
                         .collect::<Vec<_>>()
                         .as_slice()
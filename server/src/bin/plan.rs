@@ -0,0 +1,94 @@
+use clap::value_t_or_exit;
+use noria_server::Builder;
+use std::fs;
+use std::process;
+
+#[tokio::main]
+async fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("noria-plan")
+        .version("0.0.1")
+        .about(
+            "Prints how Noria would compute a query: its MIR graph, what it reuses, and how its \
+             dataflow nodes are sharded. Runs a throwaway in-process instance, so no cluster is \
+             needed.",
+        )
+        .arg(
+            Arg::with_name("schema")
+                .short("s")
+                .long("schema")
+                .takes_value(true)
+                .required(true)
+                .help("Recipe file with the base tables and any queries to plan against."),
+        )
+        .arg(
+            Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .takes_value(true)
+                .default_value("planned")
+                .help("Name to give the query."),
+        )
+        .arg(
+            Arg::with_name("shards")
+                .long("shards")
+                .takes_value(true)
+                .default_value("0")
+                .help("Shard the graph this many ways (0 = disable sharding)."),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the plan as JSON rather than as text."),
+        )
+        .arg(
+            Arg::with_name("query")
+                .required(true)
+                .help("The query to plan, or the name of a query in the schema."),
+        )
+        .get_matches();
+
+    let schema_file = matches.value_of("schema").unwrap();
+    let schema = fs::read_to_string(schema_file).unwrap_or_else(|e| {
+        eprintln!("failed to read schema from {}: {}", schema_file, e);
+        process::exit(1);
+    });
+    let query = matches.value_of("query").unwrap().trim();
+    let sharding = match value_t_or_exit!(matches, "shards", usize) {
+        0 => None,
+        x => Some(x),
+    };
+
+    let mut builder = Builder::default();
+    builder.set_sharding(sharding);
+    let (mut noria, _) = builder.start_local().await.unwrap();
+    if let Err(e) = noria.install_recipe(&schema).await {
+        eprintln!("failed to install schema: {}", e);
+        process::exit(1);
+    }
+
+    // a bare name refers to a query in the schema rather than to a new query
+    let name = if query.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        query.to_owned()
+    } else {
+        let name = matches.value_of("name").unwrap();
+        let q = format!("QUERY {}: {};", name, query.trim_end_matches(';'));
+        if let Err(e) = noria.extend_recipe(&q).await {
+            eprintln!("failed to add query: {}", e);
+            process::exit(1);
+        }
+        name.to_owned()
+    };
+
+    let plan = noria.query_plan(&name).await.unwrap_or_else(|e| {
+        eprintln!("failed to get plan for {}: {}", name, e);
+        process::exit(1);
+    });
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&plan).unwrap());
+    } else {
+        print!("{}", plan);
+    }
+
+    noria.shutdown();
+}
@@ -1,9 +1,12 @@
 use crate::handle::Handle;
+use crate::worker::shipping::Standby;
 use crate::Config;
-use crate::FrontierStrategy;
 use crate::ReuseConfigType;
+use crate::{BloomFilterStrategy, FrontierStrategy};
+use crate::{LeastLoaded, PlacementConstraint, PlacementPolicy};
 use dataflow::PersistenceParameters;
 use noria::consensus::{Authority, LocalAuthority};
+use std::collections::HashMap;
 use std::future::Future;
 use std::net::IpAddr;
 use std::sync::Arc;
@@ -15,6 +18,10 @@ pub struct Builder {
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
     listen_addr: IpAddr,
+    placement: Arc<dyn PlacementPolicy>,
+    labels: HashMap<String, String>,
+    pin_cores: bool,
+    standby: Option<Standby>,
     log: slog::Logger,
 }
 impl Default for Builder {
@@ -22,6 +29,10 @@ impl Default for Builder {
         Self {
             config: Config::default(),
             listen_addr: "127.0.0.1".parse().unwrap(),
+            placement: Arc::new(LeastLoaded),
+            labels: HashMap::new(),
+            pin_cores: false,
+            standby: None,
             log: slog::Logger::root(slog::Discard, o!()),
             memory_limit: None,
             memory_check_frequency: None,
@@ -43,21 +54,159 @@ impl Builder {
         self.config.domain_config.replay_batch_timeout = t;
     }
 
+    /// Set the number of records sent in each chunk when backfilling a new materialization.
+    pub fn set_full_replay_batch_size(&mut self, n: usize) {
+        self.config.domain_config.full_replay_batch_size = n;
+    }
+
+    /// Set how many backfill chunks may be queued at a domain ahead of regular updates.
+    ///
+    /// Smaller values keep write latencies flat while a large table is being backfilled, at the
+    /// cost of a slower migration. A value of 0 lets the backfill run ahead unbounded.
+    pub fn set_full_replay_window(&mut self, n: usize) {
+        self.config.domain_config.full_replay_window = n;
+    }
+
+    /// Set how long a busy domain may hold back updates for other domains to send more of them
+    /// together.
+    ///
+    /// Updates queued for the same destination are always sent as one batch. While a domain has
+    /// more input waiting, it may also wait up to this long before sending, which lets batches
+    /// grow further under load. An idle domain sends right away, so this adds no latency at low
+    /// load. The default is not to wait.
+    pub fn set_max_batch_delay(&mut self, delay: time::Duration) {
+        self.config.domain_config.max_batch_delay = delay;
+    }
+
+    /// Set how many threads each domain uses to prepare the chunks of a backfill.
+    ///
+    /// Each thread handles a disjoint range of the keys of the materialization being replayed.
+    pub fn set_full_replay_threads(&mut self, n: usize) {
+        self.config.domain_config.full_replay_threads = n;
+    }
+
+    /// Set the largest share of a domain's time that backfills may use while other work waits.
+    ///
+    /// Backfill chunks beyond this share are held back so that writes and the replays that reads
+    /// are waiting for are processed first. A value of 1 lets backfills run unthrottled.
+    pub fn set_background_replay_share(&mut self, share: f64) {
+        assert!(share > 0.0 && share <= 1.0);
+        self.config.domain_config.background_replay_share = share;
+    }
+
+    /// Set the suspicion level (phi) at which a worker whose heartbeats have stopped arriving is
+    /// considered to have failed.
+    ///
+    /// Higher values make false failovers less likely, but make it take longer to detect workers
+    /// that really have failed. The default is 8.
+    pub fn set_failure_threshold(&mut self, phi: f64) {
+        assert!(phi > 0.0);
+        self.config.failure_detector.threshold = phi;
+    }
+
+    /// Set how long beyond the usual heartbeat interval a worker may be silent (e.g., due to a GC
+    /// or IO pause) before it is suspected of having failed at all.
+    pub fn set_acceptable_heartbeat_pause(&mut self, pause: time::Duration) {
+        self.config.failure_detector.acceptable_pause = pause;
+    }
+
+    /// Set the most recipe changes that may wait to be applied at once.
+    ///
+    /// Further changes are turned away until the queue has room again, and clients retry them
+    /// after a short delay.
+    pub fn set_migration_queue_limit(&mut self, limit: usize) {
+        assert_ne!(limit, 0);
+        self.config.migration_queue_limit = Some(limit);
+    }
+
+    /// Compress the traffic between domains that run on different hosts at the given zstd level.
+    ///
+    /// This trades CPU time for bandwidth, which mostly pays off for replays across slow or
+    /// metered links. Traffic between domains on the same host is never compressed.
+    pub fn set_domain_compression(&mut self, level: i32) {
+        self.config.domain_compression = Some(level);
+    }
+
+    /// Drop the reader state of partially materialized views that have not been read for `after`.
+    ///
+    /// The views stay in the graph, and reads of a hibernating view fill the keys they need again
+    /// by upquery. Whether a view is hibernating is reported in its reader's statistics.
+    pub fn set_view_hibernation(&mut self, after: time::Duration) {
+        assert_ne!(after, time::Duration::from_secs(0));
+        self.config.view_hibernation = Some(after);
+    }
+
+    /// Resolve the files named by `INCLUDE 'file'` directives in recipes relative to `dir`.
+    ///
+    /// Only files inside `dir` may be included. Recipes with includes are rejected by default.
+    pub fn set_recipe_dir<P: Into<std::path::PathBuf>>(&mut self, dir: P) {
+        self.config.recipe_dir = Some(dir.into());
+    }
+
     /// Set the persistence parameters used by the system.
     pub fn set_persistence(&mut self, p: PersistenceParameters) {
         self.config.persistence = p;
     }
 
+    /// Ship every client write to a base table to the standby deployment at `standby`, which
+    /// applies them as they arrive.
+    ///
+    /// This keeps a warm standby that can take over if this deployment is lost. The standby must
+    /// have the same recipe installed. Writes are shipped once they have been applied here, as the
+    /// rows they left in each base, so the standby stores exactly the same rows, down to their
+    /// versions and timestamps. Clients do not wait for their writes to reach the standby, so
+    /// writes that were still on their way are lost if this deployment fails. Writes the standby
+    /// rejects, for example because it is down, are retried until it accepts them. If too many
+    /// writes are waiting to be shipped, bases stop taking writes until the standby catches up.
+    /// How many rows each worker has yet to ship is reported as `standby_lag` in the worker
+    /// statistics.
+    pub fn ship_writes_to<A: Authority + 'static>(&mut self, standby: Arc<A>) {
+        self.standby = Some(crate::worker::shipping::standby(standby));
+    }
+
     /// Disable partial materialization for all subsequent migrations
     pub fn disable_partial(&mut self) {
         self.config.partial_enabled = false;
     }
 
+    /// Spread the records of hot keys over all the shards of the aggregations they feed, for all
+    /// subsequent migrations.
+    ///
+    /// A key is hot if it alone makes up more than one shard's fair share of the recent records
+    /// sent to an aggregation. Without splitting, all of those records would be processed by the
+    /// single shard that owns the key. This only applies to `COUNT` and `SUM` aggregations that
+    /// feed views directly (or through projections), since their readers can add up what each
+    /// shard computed for a key. With partial materialization, upqueries for a key are sent to
+    /// every shard of the aggregation, and each shard is only replayed its own part of the key.
+    ///
+    /// Queries added later never build on an aggregation that splits hot keys, and cannot read
+    /// from its views.
+    pub fn enable_hot_key_splitting(&mut self) {
+        self.config.split_hot_keys = true;
+    }
+
+    /// Give each view `n` replicas of its reader, in addition to the reader itself, for all
+    /// subsequent migrations.
+    ///
+    /// Each replica is a domain of its own, so placement can put the replicas of a view on
+    /// different workers. Combined with a `PlacementConstraint::Spread` of reader domains over the
+    /// `zone` label, clients read from a replica in their own zone (see
+    /// `ControllerHandle::set_zone`), and fail over to the other replicas if it goes away. Every
+    /// replica keeps its own state, so each one adds to the memory that the view takes up.
+    pub fn set_reader_replicas(&mut self, n: usize) {
+        self.config.reader_replicas = n;
+    }
+
     /// Which nodes should be placed beyond the materialization frontier?
     pub fn set_frontier_strategy(&mut self, f: FrontierStrategy) {
         self.config.frontier_strategy = f;
     }
 
+    /// Which joins should guard their lookups with a Bloom filter?
+    pub fn set_bloom_filter_strategy(&mut self, b: BloomFilterStrategy) {
+        self.config.bloom_filter_strategy = b;
+    }
+
     /// Set sharding policy for all subsequent migrations; `None` disables
     pub fn set_sharding(&mut self, shards: Option<usize>) {
         self.config.sharding = shards;
@@ -83,6 +232,47 @@ impl Builder {
         self.listen_addr = listen_addr;
     }
 
+    /// Set the policy used to decide which worker each new domain is placed on.
+    ///
+    /// By default, domains are placed on the least loaded worker (see `LeastLoaded`).
+    pub fn set_placement_policy<P: PlacementPolicy + 'static>(&mut self, policy: P) {
+        self.placement = Arc::new(policy);
+    }
+
+    /// Restrict which workers domains may be placed on, based on the workers' labels.
+    pub fn add_placement_constraint(&mut self, constraint: PlacementConstraint) {
+        self.config.placement_constraints.push(constraint);
+    }
+
+    /// Give this worker a label (e.g., its zone, or whether it has an SSD) that placement
+    /// constraints can refer to.
+    pub fn set_label(&mut self, label: &str, value: &str) {
+        self.labels.insert(label.to_owned(), value.to_owned());
+    }
+
+    /// Run each domain on a thread of its own that is pinned to one of this worker's cores.
+    ///
+    /// Each domain gets its own runtime, so domains no longer migrate between cores or contend for
+    /// a shared scheduler. On machines with several NUMA nodes, all the shards of a domain are
+    /// pinned to cores on the same node, and the domain's state is allocated on that node. This
+    /// suits dedicated machines with at least as many cores as domains. By default, domains share a
+    /// pool of unpinned threads.
+    pub fn set_pin_cores(&mut self, pin: bool) {
+        self.pin_cores = pin;
+    }
+
+    /// Run the domains of each worker one at a time, in an order picked by a random number
+    /// generator seeded with `seed`.
+    ///
+    /// All the domains of a worker then share a single thread. Whenever several of them have
+    /// messages to process, the seed decides which one goes next, so an ordering-dependent bug
+    /// that shows up with one seed can be reproduced by running the same requests with that seed
+    /// again. Client requests, timers and traffic from other workers still arrive whenever they
+    /// do. This is meant for debugging, and takes precedence over `set_pin_cores`.
+    pub fn simulate_with_seed(&mut self, seed: u64) {
+        self.config.simulation_seed = Some(seed);
+    }
+
     /// Set the logger that the derived worker should use. By default, it uses `slog::Discard`.
     pub fn log_with(&mut self, log: slog::Logger) {
         self.log = log;
@@ -113,10 +303,17 @@ impl Builder {
             ref config,
             memory_limit,
             memory_check_frequency,
+            ref placement,
+            ref labels,
+            pin_cores,
+            ref standby,
             ref log,
         } = *self;
 
         let config = config.clone();
+        let placement = placement.clone();
+        let labels = labels.clone();
+        let standby = standby.clone();
         let log = log.clone();
 
         crate::startup::start_instance(
@@ -125,6 +322,10 @@ impl Builder {
             config,
             memory_limit,
             memory_check_frequency,
+            placement,
+            labels,
+            pin_cores,
+            standby,
             log,
         )
     }
@@ -0,0 +1,270 @@
+//! Fault injection, used to test Noria under adverse conditions.
+//!
+//! Every instance has a `Chaos` handle. In tests, it sees each coordination message the instance
+//! receives, each message its worker sends to the controller, and each packet sent between
+//! domains on the instance's worker. By default it lets everything through untouched. Tests can
+//! ask it to drop, delay, or duplicate coordination messages, to delay data-plane packets, or to
+//! cut the instance off from the rest of the cluster altogether. Workers are killed by dropping
+//! their `Handle`.
+//!
+//! The data-flow relies on the channels between domains being reliable and ordered, just like the
+//! TCP connections they stand in for, so data-plane packets are only ever held back, never
+//! dropped, duplicated, or reordered.
+//!
+//! Outside of tests, none of the traffic passes through the injector.
+#![cfg_attr(not(test), allow(dead_code))]
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The faults to inject into each coordination message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Faults {
+    /// The probability that a message is dropped.
+    pub(crate) drop: f64,
+    /// The probability that a message is delivered twice.
+    pub(crate) duplicate: f64,
+    /// Each delivery is delayed by a random duration up to this long.
+    pub(crate) max_delay: Duration,
+    /// Each packet between domains is held back by a random duration up to this long.
+    pub(crate) max_data_delay: Duration,
+}
+
+struct Inner {
+    faults: Faults,
+    isolated: bool,
+    rng: StdRng,
+}
+
+/// A handle to the fault injector of a single instance.
+#[derive(Clone)]
+pub(crate) struct Chaos {
+    // fast path for the common case of no faults at all
+    active: Arc<AtomicBool>,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Chaos::seeded(rand::random())
+    }
+}
+
+impl Chaos {
+    /// Make a fault injector whose decisions are determined by `seed`.
+    pub(crate) fn seeded(seed: u64) -> Self {
+        Chaos {
+            active: Arc::new(AtomicBool::new(false)),
+            inner: Arc::new(Mutex::new(Inner {
+                faults: Faults::default(),
+                isolated: false,
+                rng: StdRng::seed_from_u64(seed),
+            })),
+        }
+    }
+
+    fn update<F: FnOnce(&mut Inner)>(&self, f: F) {
+        let mut inner = self.inner.lock().unwrap();
+        f(&mut inner);
+        let active = inner.isolated || inner.faults != Faults::default();
+        self.active.store(active, Ordering::SeqCst);
+    }
+
+    /// Start injecting the given faults, and reseed the injector so that runs are reproducible.
+    pub(crate) fn inject(&self, faults: Faults, seed: u64) {
+        self.update(|inner| {
+            inner.faults = faults;
+            inner.rng = StdRng::seed_from_u64(seed);
+        });
+    }
+
+    /// Drop all coordination messages to and from this instance, and hold back all packets between
+    /// its domains until the instance is healed.
+    pub(crate) fn isolate(&self) {
+        self.update(|inner| inner.isolated = true);
+    }
+
+    /// Stop injecting faults, and reconnect the instance if it was isolated.
+    pub(crate) fn heal(&self) {
+        self.update(|inner| {
+            inner.faults = Faults::default();
+            inner.isolated = false;
+        });
+    }
+
+    /// Decide how a single message should be delivered.
+    ///
+    /// Returns the delay before each delivery of the message. The returned list is empty if the
+    /// message should be dropped, and has two entries if it should be duplicated.
+    pub(crate) fn deliveries(&self) -> Vec<Duration> {
+        if !self.active.load(Ordering::SeqCst) {
+            return vec![Duration::from_secs(0)];
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let Inner {
+            ref faults,
+            isolated,
+            ref mut rng,
+        } = *inner;
+        if isolated || rng.gen_bool(faults.drop) {
+            return Vec::new();
+        }
+
+        let copies = if rng.gen_bool(faults.duplicate) { 2 } else { 1 };
+        (0..copies)
+            .map(|_| faults.max_delay.mul_f64(rng.gen::<f64>()))
+            .collect()
+    }
+
+    /// Wait until the next packet between domains may be delivered.
+    pub(crate) async fn hold_data(&self) {
+        loop {
+            if !self.active.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let delay = {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.isolated {
+                    None
+                } else {
+                    let max = inner.faults.max_data_delay;
+                    Some(max.mul_f64(inner.rng.gen::<f64>()))
+                }
+            };
+            match delay {
+                Some(delay) => {
+                    if delay != Duration::from_secs(0) {
+                        tokio::time::delay_for(delay).await;
+                    }
+                    return;
+                }
+                None => {
+                    // wait for the partition to heal
+                    tokio::time::delay_for(Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Deliver `msg` as decided by `chaos`, using `send` for each delivery.
+///
+/// Deliveries that aren't delayed happen immediately, so that message order is preserved when no
+/// faults are being injected.
+pub(crate) fn deliver<T, F>(chaos: &Chaos, msg: T, send: F)
+where
+    T: Clone + Send + 'static,
+    F: Fn(T) + Clone + Send + 'static,
+{
+    for delay in chaos.deliveries() {
+        if delay == Duration::from_secs(0) {
+            send(msg.clone());
+        } else {
+            let msg = msg.clone();
+            let send = send.clone();
+            tokio::spawn(async move {
+                tokio::time::delay_for(delay).await;
+                send(msg);
+            });
+        }
+    }
+}
+
+/// Invariant checks to run against a cluster that faults have been injected into.
+#[cfg(test)]
+pub(crate) mod invariants {
+    use noria::{DataType, View};
+    use std::time::{Duration, Instant};
+
+    /// Check that the rows that `view` holds for `key` eventually become `expected`, in any order.
+    ///
+    /// Returns the last rows that were observed if the view does not converge within `timeout`.
+    pub(crate) async fn converges(
+        view: &mut View,
+        key: &[DataType],
+        mut expected: Vec<Vec<DataType>>,
+        timeout: Duration,
+    ) -> Result<(), Vec<Vec<DataType>>> {
+        expected.sort();
+        let start = Instant::now();
+        let mut last = Vec::new();
+        while start.elapsed() < timeout {
+            // a lookup may block forever if the replay it triggers was lost
+            let res = tokio::time::timeout(Duration::from_millis(500), view.lookup(key, true));
+            if let Ok(Ok(rows)) = res.await {
+                let mut rows: Vec<Vec<DataType>> = rows.into();
+                rows.sort();
+                if rows == expected {
+                    return Ok(());
+                }
+                last = rows;
+            }
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        }
+        Err(last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_passes_through_by_default() {
+        let chaos = Chaos::seeded(0);
+        for _ in 0..100 {
+            assert_eq!(chaos.deliveries(), vec![Duration::from_secs(0)]);
+        }
+    }
+
+    #[test]
+    fn it_is_reproducible() {
+        let faults = Faults {
+            drop: 0.3,
+            duplicate: 0.3,
+            max_delay: Duration::from_millis(100),
+            max_data_delay: Duration::from_secs(0),
+        };
+        let a = Chaos::default();
+        let b = Chaos::default();
+        a.inject(faults.clone(), 42);
+        b.inject(faults, 42);
+        let a: Vec<_> = (0..100).map(|_| a.deliveries()).collect();
+        let b: Vec<_> = (0..100).map(|_| b.deliveries()).collect();
+        assert_eq!(a, b);
+        assert!(a.iter().any(|d| d.is_empty()));
+        assert!(a.iter().any(|d| d.len() == 2));
+    }
+
+    #[test]
+    fn it_isolates_and_heals() {
+        let chaos = Chaos::seeded(0);
+        chaos.isolate();
+        assert!(chaos.deliveries().is_empty());
+        chaos.heal();
+        assert_eq!(chaos.deliveries(), vec![Duration::from_secs(0)]);
+    }
+
+    #[tokio::test]
+    async fn it_holds_data_until_healed() {
+        let chaos = Chaos::seeded(0);
+        chaos.hold_data().await;
+
+        chaos.isolate();
+        let held = tokio::time::timeout(Duration::from_millis(100), chaos.hold_data());
+        assert!(held.await.is_err());
+
+        let c = chaos.clone();
+        let held = tokio::spawn(async move { c.hold_data().await });
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+        chaos.heal();
+        tokio::time::timeout(Duration::from_secs(1), held)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Parameters for the failure detector the controller runs for each worker.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FailureDetectorConfig {
+    /// The suspicion level (phi) above which a worker is considered to have failed.
+    pub(crate) threshold: f64,
+    /// How many heartbeat inter-arrival times to base the expected interval on.
+    pub(crate) window: usize,
+    /// Lower bound on the standard deviation of heartbeat inter-arrival times, so that a worker
+    /// with perfectly regular heartbeats isn't declared dead the moment one is slightly late.
+    pub(crate) min_std_deviation: Duration,
+    /// How much longer than usual a heartbeat may take to arrive (e.g., due to a GC or IO pause)
+    /// before the worker becomes suspect at all.
+    pub(crate) acceptable_pause: Duration,
+}
+
+impl Default for FailureDetectorConfig {
+    fn default() -> Self {
+        FailureDetectorConfig {
+            threshold: 8.0,
+            window: 100,
+            min_std_deviation: Duration::from_millis(100),
+            acceptable_pause: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A phi-accrual failure detector (Hayashibara et al., SRDS 2004) for a single worker.
+///
+/// Rather than declaring a worker dead after a fixed timeout, the detector learns the
+/// distribution of the intervals between the worker's heartbeats, and expresses how unlikely it
+/// is that the worker is still alive given how long it has been since the last heartbeat. The
+/// suspicion level, phi, is `-log10` of that probability, so a phi of 8 means that the odds of
+/// the worker still being alive are about one in a hundred million.
+pub(super) struct PhiAccrual {
+    intervals: VecDeque<f64>,
+    last: Instant,
+    window: usize,
+    min_std_deviation: f64,
+    acceptable_pause: f64,
+}
+
+impl PhiAccrual {
+    /// Start tracking a worker that is expected to send a heartbeat every `heartbeat_every`.
+    pub(super) fn new(
+        config: &FailureDetectorConfig,
+        heartbeat_every: Duration,
+        now: Instant,
+    ) -> Self {
+        // bootstrap the history with a guess that has a fairly high variance, so that we don't
+        // immediately suspect workers before we've actually learned anything about them.
+        let expected = heartbeat_every.as_secs_f64();
+        let mut intervals = VecDeque::with_capacity(config.window);
+        intervals.push_back(expected - expected / 4.0);
+        intervals.push_back(expected + expected / 4.0);
+
+        PhiAccrual {
+            intervals,
+            last: now,
+            window: config.window.max(2),
+            min_std_deviation: config.min_std_deviation.as_secs_f64(),
+            acceptable_pause: config.acceptable_pause.as_secs_f64(),
+        }
+    }
+
+    /// Record that a heartbeat arrived at `now`.
+    pub(super) fn heartbeat(&mut self, now: Instant) {
+        let interval = now.saturating_duration_since(self.last).as_secs_f64();
+        if self.intervals.len() == self.window {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(interval);
+        self.last = now;
+    }
+
+    /// When the last heartbeat arrived.
+    pub(super) fn last_heartbeat(&self) -> Instant {
+        self.last
+    }
+
+    /// The current suspicion level for the worker.
+    pub(super) fn phi(&self, now: Instant) -> f64 {
+        let n = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / n;
+        let variance = self
+            .intervals
+            .iter()
+            .map(|i| (i - mean) * (i - mean))
+            .sum::<f64>()
+            / n;
+        let std_deviation = variance.sqrt().max(self.min_std_deviation);
+
+        // the probability that a heartbeat arrives later than this is approximated using a
+        // logistic approximation of the normal distribution's cdf, which is both cheap and well
+        // behaved far out in the tail.
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        let y = (elapsed - mean - self.acceptable_pause) / std_deviation;
+        let e = (-y * (1.5976 + 0.070_566 * y * y)).exp();
+        if elapsed > mean + self.acceptable_pause {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FailureDetectorConfig {
+        FailureDetectorConfig {
+            threshold: 8.0,
+            window: 10,
+            min_std_deviation: Duration::from_millis(100),
+            acceptable_pause: Duration::from_millis(0),
+        }
+    }
+
+    #[test]
+    fn it_trusts_regular_heartbeats() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut d = PhiAccrual::new(&config(), second, start);
+        for i in 1..=20 {
+            d.heartbeat(start + second * i);
+        }
+
+        let last = d.last_heartbeat();
+        assert!(d.phi(last) < 1.0);
+        assert!(d.phi(last + second) < 1.0);
+        assert!(d.phi(last + second * 3) > 8.0);
+    }
+
+    #[test]
+    fn it_adapts_to_irregular_heartbeats() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut regular = PhiAccrual::new(&config(), second, start);
+        let mut irregular = PhiAccrual::new(&config(), second, start);
+        let mut t = start;
+        for i in 1..=20 {
+            regular.heartbeat(start + second * i);
+            // alternate between short and long pauses, with the same average
+            t += if i % 2 == 0 {
+                Duration::from_millis(200)
+            } else {
+                Duration::from_millis(1800)
+            };
+            irregular.heartbeat(t);
+        }
+
+        // a worker that has always been flaky is given more leeway
+        let late = Duration::from_millis(2500);
+        let phi_regular = regular.phi(regular.last_heartbeat() + late);
+        let phi_irregular = irregular.phi(irregular.last_heartbeat() + late);
+        assert!(phi_regular > 8.0);
+        assert!(phi_irregular < 8.0);
+    }
+
+    #[test]
+    fn it_tolerates_acceptable_pauses() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut c = config();
+        c.acceptable_pause = Duration::from_secs(3);
+        let mut d = PhiAccrual::new(&c, second, start);
+        for i in 1..=20 {
+            d.heartbeat(start + second * i);
+        }
+
+        let last = d.last_heartbeat();
+        assert!(d.phi(last + second * 3) < 1.0);
+        assert!(d.phi(last + second * 6) > 8.0);
+    }
+}
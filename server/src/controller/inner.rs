@@ -1,28 +1,48 @@
 use crate::controller::domain_handle::{DomainHandle, DomainShardHandle};
+use crate::controller::failure::{FailureDetectorConfig, PhiAccrual};
 use crate::controller::migrate::materialization::Materializations;
-use crate::controller::recipe::Schema;
+use crate::controller::migrate::progress::MigrationProgress;
+use crate::controller::placement::{
+    self, DomainKind, DomainPlacement, PlacementConstraint, PlacementPolicy, WorkerCandidate,
+};
+use crate::controller::recipe::{self, Schema};
 use crate::controller::schema;
+use crate::controller::sql;
 use crate::controller::{ControllerState, Migration, Recipe};
 use crate::controller::{Worker, WorkerIdentifier};
-use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
+use crate::coordination::{
+    negotiate_version, CoordinationMessage, CoordinationPayload, DomainDescriptor,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use dataflow::payload::{ControlReplyPacket, StateSample};
 use dataflow::prelude::*;
-use dataflow::{node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig};
+use dataflow::{node, ops, prelude::Packet, DomainBuilder, DomainConfig};
 use futures_util::stream::StreamExt;
 use hyper::{self, Method, StatusCode};
-use nom_sql::ColumnSpecification;
+use nom_sql::{ColumnSpecification, SqlQuery};
 use noria::builders::*;
 use noria::channel::tcp::{SendError, TcpSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
-use noria::debug::stats::{DomainStats, GraphStats, NodeStats};
-use noria::ActivationResult;
-use petgraph::visit::Bfs;
+use noria::debug::plan::{DataflowNodePlan, QueryPlan, QueryReuse};
+use noria::debug::stats::{
+    DomainStats, GraphStats, IndexStats, NodeStats, StateStats, WorkerStats,
+};
+use noria::error::NoriaError;
+use noria::merge::{Combine, Merge};
+use noria::{
+    ActivationResult, ColumnDescription, Dependencies, DryRunResult, NodeName, PlannedView,
+    Provenance, RecipeValidation, RefreshPolicy, TableDescription, TableOperation, ViewDescription,
+    WorkerConfigUpdate, ZONE_LABEL,
+};
+use petgraph::visit::{Bfs, Reversed};
 use slog::Logger;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::mem;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use std::{cell, io, time};
+use std::{cell, cmp, io, time};
 
 /// `Controller` is the core component of the alternate Soup implementation.
 ///
@@ -58,16 +78,40 @@ pub(super) struct ControllerInner {
 
     pub(super) epoch: Epoch,
 
-    pending_recovery: Option<(Vec<String>, usize)>,
+    pending_recovery: Option<RecipeRecovery>,
+    /// The text of recent recipe versions, as persisted in the controller state.
+    recipe_history: BTreeMap<usize, String>,
+    /// Where the previous leader ran each domain shard. Used to restore that placement while
+    /// recovering, so that domains come back up next to their logs. The domains themselves are
+    /// rebuilt from scratch.
+    previous_placement: HashMap<usize, Vec<SocketAddr>>,
 
     quorum: usize,
     heartbeat_every: Duration,
     healthcheck_every: Duration,
+    failure_detector: FailureDetectorConfig,
     last_checked_workers: Instant,
 
     log: slog::Logger,
 
     pub(in crate::controller) replies: DomainReplies,
+    pub(in crate::controller) migration_progress: Arc<Mutex<MigrationProgress>>,
+    placement: Arc<dyn PlacementPolicy>,
+    placement_constraints: Vec<PlacementConstraint>,
+
+    /// What was found the last time the state of each node was sampled.
+    pub(super) state_stats: HashMap<NodeIndex, StateStats>,
+    /// Whether the records of hot keys are spread over the shards of additive nodes.
+    pub(super) split_hot_keys: bool,
+    /// How many replicas of its reader each new view gets.
+    pub(super) reader_replicas: usize,
+    /// How long partial views may go unread before their reader state is dropped.
+    hibernate_after: Option<Duration>,
+    /// The directory that `INCLUDE` directives are resolved in; they are rejected without one.
+    recipe_dir: Option<PathBuf>,
+    last_hibernation_check: Instant,
+    /// The changes made to the runtime parameters of workers so far.
+    worker_config: WorkerConfigUpdate,
 }
 
 pub(in crate::controller) struct DomainReplies(
@@ -97,6 +141,18 @@ impl DomainReplies {
         }
     }
 
+    /// Wait for the replies to a `Packet::StateSizeProbe`, and return the total number of rows.
+    pub(in crate::controller) async fn wait_for_state_sizes(&mut self, d: &DomainHandle) -> usize {
+        let mut rows = 0;
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::StateSize(n, _) => rows += n,
+                r => unreachable!("got unexpected non-state-size control reply: {:?}", r),
+            }
+        }
+        rows
+    }
+
     async fn wait_for_statistics(
         &mut self,
         d: &DomainHandle,
@@ -110,12 +166,138 @@ impl DomainReplies {
         }
         stats
     }
+
+    async fn wait_for_state_samples(
+        &mut self,
+        d: &DomainHandle,
+    ) -> Vec<HashMap<NodeIndex, StateSample>> {
+        let mut samples = Vec::with_capacity(d.shards());
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::StateSamples(s) => samples.push(s),
+                r => unreachable!("got unexpected non-sample control reply: {:?}", r),
+            }
+        }
+        samples
+    }
+
+    /// Wait for the replies to a `Packet::DryRun`, and return the records of all the shards.
+    async fn wait_for_dry_run(&mut self, d: &DomainHandle) -> Vec<Record> {
+        let mut records = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::DryRun(rs) => records.extend(rs),
+                r => unreachable!("got unexpected non-dry-run control reply: {:?}", r),
+            }
+        }
+        records
+    }
+
+    /// Wait for the replies to a `Packet::FindRows`, and return the rows of all the shards, or the
+    /// first error a shard replied with.
+    async fn wait_for_rows(&mut self, d: &DomainHandle) -> Result<Vec<Vec<DataType>>, String> {
+        let mut rows = Vec::new();
+        for r in self.read_n_domain_replies(d.shards()).await {
+            match r {
+                ControlReplyPacket::Rows(rs) => rows.extend(rs?),
+                r => unreachable!("got unexpected non-rows control reply: {:?}", r),
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// The most rows of each node's state that are looked at when collecting state statistics.
+const STATE_SAMPLE_ROWS: usize = 10_000;
+
+/// The most hot keys that are kept for each index.
+const HOT_KEYS: usize = 10;
+
+/// The longest time between checks for views that have gone unread for long enough to hibernate.
+const HIBERNATION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The persisted recipes, recipe version, security configuration, and staged generations that a
+/// new leader restores once enough workers have joined.
+type RecipeRecovery = (
+    Vec<String>,
+    usize,
+    Option<String>,
+    HashMap<usize, HashMap<String, String>>,
+);
+
+/// The most recipe versions whose text is kept in the persisted state for rollbacks.
+const RECIPE_HISTORY: usize = 64;
+
+/// Classify an error from planning a recipe.
+fn planning_error(e: String) -> NoriaError {
+    if e.starts_with(sql::UNSUPPORTED) {
+        NoriaError::UnsupportedSql(e[sql::UNSUPPORTED.len()..].to_owned())
+    } else {
+        NoriaError::Planning(e)
+    }
+}
+
+/// Plan queries with `f`, and report the panics that planning has on some SQL that Noria does not
+/// support as errors, so that they do not take the controller down with them.
+fn plan<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, NoriaError> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(r) => r.map_err(planning_error),
+        Err(panic) => Err(NoriaError::UnsupportedSql(panic_message(panic))),
+    }
+}
+
+/// What a panic caught while planning a recipe was about.
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_owned())
+}
+
+/// Fold what was found in another shard of a node's state into `into`.
+fn merge_samples(into: &mut StateSample, other: StateSample) {
+    into.rows += other.rows;
+    if into.columns.is_empty() {
+        into.columns = other.columns;
+    } else {
+        for (c, o) in into.columns.iter_mut().zip(&other.columns) {
+            c.merge(o);
+        }
+    }
+    for index in other.indices {
+        match into.indices.iter_mut().find(|i| i.columns == index.columns) {
+            Some(i) => {
+                i.keys += index.keys;
+                for (key, n) in index.hot_keys {
+                    match i.hot_keys.iter_mut().find(|(k, _)| *k == key) {
+                        Some(hot) => hot.1 += n,
+                        None => i.hot_keys.push((key, n)),
+                    }
+                }
+                i.hot_keys.sort_by(|a, b| b.1.cmp(&a.1));
+                i.hot_keys.truncate(HOT_KEYS);
+            }
+            None => into.indices.push(index),
+        }
+    }
 }
 
 pub(super) fn graphviz(
     graph: &Graph,
     detailed: bool,
     materializations: &Materializations,
+) -> String {
+    annotated_graphviz(graph, detailed, materializations, &HashMap::new())
+}
+
+/// Like `graphviz`, but with an extra row of text added to the description of every node that
+/// has an entry in `annotations`.
+pub(super) fn annotated_graphviz(
+    graph: &Graph,
+    detailed: bool,
+    materializations: &Materializations,
+    annotations: &HashMap<NodeIndex, String>,
 ) -> String {
     let mut s = String::new();
 
@@ -140,7 +322,12 @@ pub(super) fn graphviz(
         let materialization_status = materializations.get_status(index, node);
         indentln(&mut s);
         s.push_str(&format!("n{}", index.index()));
-        s.push_str(&node.describe(index, detailed, materialization_status));
+        s.push_str(&node.describe(
+            index,
+            detailed,
+            materialization_status,
+            annotations.get(&index).map(String::as_str),
+        ));
     }
 
     // edges.
@@ -193,7 +380,7 @@ impl ControllerInner {
         query: Option<String>,
         body: hyper::body::Bytes,
         authority: &Arc<A>,
-    ) -> Result<Result<String, String>, StatusCode> {
+    ) -> Result<Result<String, NoriaError>, StatusCode> {
         use serde_json as json;
 
         match (&method, path.as_ref()) {
@@ -205,12 +392,19 @@ impl ControllerInner {
             (&Method::POST, "/graphviz") => {
                 return Ok(Ok(json::to_string(&self.graphviz(true)).unwrap()));
             }
+            (&Method::POST, "/node_names") => {
+                return Ok(Ok(json::to_string(&self.node_names()).unwrap()));
+            }
             (&Method::GET, "/get_statistics") => {
                 return Ok(Ok(json::to_string(&self.get_statistics()).unwrap()));
             }
             (&Method::POST, "/get_statistics") => {
                 return Ok(Ok(json::to_string(&self.get_statistics()).unwrap()));
             }
+            (&Method::GET, "/annotated_graph") => return Ok(Ok(self.annotated_graphviz())),
+            (&Method::POST, "/annotated_graphviz") => {
+                return Ok(Ok(json::to_string(&self.annotated_graphviz()).unwrap()));
+            }
             _ => {}
         }
 
@@ -225,6 +419,7 @@ impl ControllerInner {
             (Method::POST, "/inputs") => Ok(Ok(json::to_string(&self.inputs()).unwrap())),
             (Method::POST, "/outputs") => Ok(Ok(json::to_string(&self.outputs()).unwrap())),
             (Method::GET, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
+            (Method::POST, "/workers") => Ok(Ok(json::to_string(&self.worker_stats()).unwrap())),
             (Method::GET, "/nodes") => {
                 // TODO(malte): this is a pretty yucky hack, but hyper doesn't provide easy access
                 // to individual query variables unfortunately. We'll probably want to factor this
@@ -264,65 +459,239 @@ impl ControllerInner {
                 .map(|args| Ok(json::to_string(&self.table_builder(args)).unwrap())),
             (Method::POST, "/view_builder") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
-                .map(|args| Ok(json::to_string(&self.view_builder(args)).unwrap())),
+                .map(|args: &str| {
+                    self.build_deferred_view(args)
+                        .map(|_| json::to_string(&self.view_builder(args)).unwrap())
+                }),
+            (Method::POST, "/describe_table") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.describe_table(&args)).unwrap())),
+            (Method::POST, "/describe_view") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args: String| Ok(json::to_string(&self.describe_view(&args)).unwrap())),
+            (Method::POST, "/list_dependencies") => {
+                Ok(Ok(json::to_string(&self.list_dependencies()).unwrap()))
+            }
+            (Method::POST, "/validate_recipe") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.validate_recipe(args)).unwrap())),
             (Method::POST, "/extend_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
                     self.extend_recipe(authority, args)
                         .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
                 }),
             (Method::POST, "/install_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
                     self.install_recipe(authority, args)
                         .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
                 }),
             (Method::POST, "/set_security_config") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
-                    self.set_security_config(args)
+                    self.set_security_config(authority, args)
                         .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
                 }),
             (Method::POST, "/create_universe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
                     self.create_universe(args)
                         .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/stage_view") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.stage_view(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/promote_view") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.promote_view(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/stage_generation") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.stage_generation(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/switch_generation") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.switch_generation(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/rollback") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.rollback(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/state_statistics") => Ok(Ok(json::to_string(
+                &self.collect_state_statistics(),
+            )
+            .unwrap())),
+            (Method::POST, "/set_view_refresh") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_view_refresh(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/dry_run") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.dry_run(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/query_plan") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.query_plan(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/provenance") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.provenance(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/set_domain_rate_limit") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.set_domain_rate_limit(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/update_worker_config") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.update_worker_config(args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
+                }),
+            (Method::POST, "/remove_query") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| {
+                    self.remove_query(authority, args)
+                        .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
                 }),
+            (Method::POST, "/query_once") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| self.query_once(args).map(|r| json::to_string(&r).unwrap())),
             (Method::POST, "/remove_node") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
                     self.remove_nodes(vec![args].as_slice())
                         .map(|r| json::to_string(&r).unwrap())
+                        .map_err(NoriaError::from)
                 }),
             _ => Err(StatusCode::NOT_FOUND),
         }
     }
 
-    pub(super) fn handle_register(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
-        let (remote, read_listen_addr) = if let CoordinationPayload::Register {
-            addr: remote,
-            read_listen_addr,
-            ..
-        } = msg.payload
-        {
-            (remote, read_listen_addr)
+    /// Check that `msg` was sent by a worker that is part of the current epoch.
+    ///
+    /// A worker that is still attached to a previous controller may be running domains that the
+    /// current controller knows nothing about, so we must not let it take part.
+    fn is_current(&self, msg: &CoordinationMessage) -> bool {
+        if msg.epoch != self.epoch {
+            warn!(self.log, "ignoring message from worker in another epoch";
+                  "worker" => ?msg.source, "epoch" => ?msg.epoch);
+            false
         } else {
-            unreachable!();
-        };
+            true
+        }
+    }
+
+    pub(super) fn handle_register(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
+        if !self.is_current(&msg) {
+            return Ok(());
+        }
+
+        let (remote, read_listen_addr, labels, min_version, max_version) =
+            if let CoordinationPayload::Register {
+                addr: remote,
+                read_listen_addr,
+                labels,
+                version,
+                min_version,
+                ..
+            } = msg.payload
+            {
+                (remote, read_listen_addr, labels, min_version, version)
+            } else {
+                unreachable!();
+            };
 
         info!(
             self.log,
             "new worker registered from {:?}, which listens on {:?}", msg.source, remote
         );
 
-        let sender = TcpSender::connect(&remote)?;
-        let ws = Worker::new(sender);
+        let mut sender = TcpSender::connect(&remote)?;
+        let version = match negotiate_version(min_version, max_version) {
+            Some(version) => version,
+            None => {
+                error!(self.log, "refusing worker with incompatible protocol version";
+                       "worker" => ?msg.source,
+                       "versions" => format!("{}..={}", min_version, max_version),
+                       "supported" => format!("{}..={}", MIN_PROTOCOL_VERSION, PROTOCOL_VERSION));
+                let src = sender.local_addr().unwrap();
+                sender
+                    .send(CoordinationMessage {
+                        epoch: self.epoch,
+                        source: src,
+                        payload: CoordinationPayload::Rejected(format!(
+                            "controller speaks protocol versions {}..={}, worker speaks {}..={}",
+                            MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, min_version, max_version
+                        )),
+                    })
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+                return Ok(());
+            }
+        };
+        if version < PROTOCOL_VERSION {
+            warn!(self.log, "worker speaks an older protocol version";
+                  "worker" => ?msg.source, "version" => version);
+        }
+
+        let detector =
+            PhiAccrual::new(&self.failure_detector, self.heartbeat_every, Instant::now());
+        let mut ws = Worker::new(sender, labels, detector, version);
+        if self.worker_config != WorkerConfigUpdate::default() {
+            // the worker starts out with the parameters it was started with
+            let src = ws.sender.local_addr().unwrap();
+            ws.sender
+                .send(CoordinationMessage {
+                    epoch: self.epoch,
+                    source: src,
+                    payload: CoordinationPayload::UpdateConfig(self.worker_config.clone()),
+                })
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+        }
         self.workers.insert(msg.source, ws);
         self.read_addrs.insert(msg.source, read_listen_addr);
 
         if self.workers.len() >= self.quorum {
-            if let Some((recipes, recipe_version)) = self.pending_recovery.take() {
+            if let Some((recipes, recipe_version, security_config, generations)) =
+                self.pending_recovery.take()
+            {
                 assert_eq!(self.workers.len(), self.quorum);
                 assert_eq!(self.recipe.version(), 0);
                 assert!(recipe_version + 1 >= recipes.len());
@@ -332,37 +701,64 @@ impl ControllerInner {
                     recipe_version + 1 - recipes.len(),
                     Some(self.log.clone()),
                 );
+                if let Some(config) = security_config {
+                    self.recipe.set_security_config(&config);
+                }
                 for r in recipes {
                     self.apply_recipe(self.recipe.clone().extend(&r).unwrap())
                         .unwrap();
                 }
+                self.recipe.set_generations(generations);
+
+                // the old placement doesn't say anything about domains added from here on
+                self.previous_placement.clear();
             }
         }
 
         Ok(())
     }
 
+    /// Where each shard of each domain is currently running, by the worker's listen address.
+    fn placement_snapshot(&self) -> HashMap<usize, Vec<SocketAddr>> {
+        self.domains
+            .iter()
+            .filter_map(|(di, dh)| {
+                let workers: Option<Vec<_>> = (0..dh.shards())
+                    .map(|shard| {
+                        self.workers
+                            .get(&dh.assignment(shard))
+                            .and_then(|w| w.sender.peer_addr().ok())
+                    })
+                    .collect();
+                Some((di.index(), workers?))
+            })
+            .collect()
+    }
+
     fn check_worker_liveness(&mut self) {
         let mut any_failed = false;
 
         // check if there are any newly failed workers
+        let now = Instant::now();
+        let threshold = self.failure_detector.threshold;
         if self.last_checked_workers.elapsed() > self.healthcheck_every {
             for (_addr, ws) in self.workers.iter() {
-                if ws.healthy && ws.last_heartbeat.elapsed() > self.heartbeat_every * 4 {
+                if ws.healthy && ws.detector.phi(now) > threshold {
                     any_failed = true;
                 }
             }
-            self.last_checked_workers = Instant::now();
+            self.last_checked_workers = now;
         }
 
-        // if we have newly failed workers, iterate again to find all workers that have missed >= 3
-        // heartbeats. This is necessary so that we correctly handle correlated failures of
+        // if we have newly failed workers, iterate again to find all workers that are somewhat
+        // less suspect. This is necessary so that we correctly handle correlated failures of
         // workers.
         if any_failed {
             let mut failed = Vec::new();
             for (addr, ws) in self.workers.iter_mut() {
-                if ws.healthy && ws.last_heartbeat.elapsed() > self.heartbeat_every * 3 {
-                    error!(self.log, "worker at {:?} has failed!", addr);
+                let phi = ws.detector.phi(now);
+                if ws.healthy && phi > threshold * 0.75 {
+                    error!(self.log, "worker at {:?} has failed!", addr; "phi" => phi);
                     ws.healthy = false;
                     failed.push(addr.clone());
                 }
@@ -400,7 +796,34 @@ impl ControllerInner {
             .expect("failed to activate original recipe");
     }
 
+    /// Classify a domain by the nodes it contains, for the purposes of placement.
+    fn domain_kind<I>(&self, nodes: I) -> DomainKind
+    where
+        I: IntoIterator<Item = NodeIndex>,
+    {
+        let mut kind = DomainKind::Internal;
+        for ni in nodes {
+            let n = &self.ingredients[ni];
+            if n.is_base() {
+                return DomainKind::Base;
+            } else if n.is_reader() {
+                kind = DomainKind::Reader;
+            }
+        }
+        kind
+    }
+
     pub(super) fn handle_heartbeat(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
+        if !self.is_current(&msg) {
+            return Ok(());
+        }
+
+        let load = if let CoordinationPayload::Heartbeat(load) = msg.payload {
+            load
+        } else {
+            unreachable!();
+        };
+
         match self.workers.get_mut(&msg.source) {
             None => crit!(
                 self.log,
@@ -408,19 +831,46 @@ impl ControllerInner {
                 msg.source
             ),
             Some(ref mut ws) => {
-                ws.last_heartbeat = Instant::now();
+                ws.detector.heartbeat(Instant::now());
+                ws.load = load;
             }
         }
 
         self.check_worker_liveness();
+        self.hibernate_idle_views();
         Ok(())
     }
 
+    /// Have domains drop the reader state of partial views that have not been read for as long
+    /// as the hibernation policy allows, if it is time to check for them.
+    fn hibernate_idle_views(&mut self) {
+        let after = match self.hibernate_after {
+            Some(after) => after,
+            None => return,
+        };
+        if self.last_hibernation_check.elapsed() < cmp::min(after / 2, HIBERNATION_CHECK_INTERVAL) {
+            return;
+        }
+        self.last_hibernation_check = Instant::now();
+
+        for dh in self.domains.values_mut() {
+            if let Err(e) = dh.send_to_healthy(Box::new(Packet::Hibernate { after }), &self.workers)
+            {
+                warn!(
+                    self.log,
+                    "failed to ask domain to hibernate idle views: {:?}", e
+                );
+            }
+        }
+    }
+
     /// Construct `ControllerInner` with a specified listening interface
     pub(super) fn new(
         log: slog::Logger,
         state: ControllerState,
         drx: tokio::sync::mpsc::UnboundedReceiver<ControlReplyPacket>,
+        migration_progress: Arc<Mutex<MigrationProgress>>,
+        placement: Arc<dyn PlacementPolicy>,
     ) -> Self {
         let mut g = petgraph::Graph::new();
         let source = g.add_node(node::Node::new(
@@ -434,12 +884,18 @@ impl ControllerInner {
             materializations.disable_partial()
         }
         materializations.set_frontier_strategy(state.config.frontier_strategy);
+        materializations.set_bloom_filter_strategy(state.config.bloom_filter_strategy);
 
         let cc = Arc::new(ChannelCoordinator::new());
         assert_ne!(state.config.quorum, 0);
 
         let pending_recovery = if !state.recipes.is_empty() {
-            Some((state.recipes, state.recipe_version))
+            Some((
+                state.recipes,
+                state.recipe_version,
+                state.security_config,
+                state.recipe_generations,
+            ))
         } else {
             None
         };
@@ -458,6 +914,7 @@ impl ControllerInner {
             persistence: state.config.persistence,
             heartbeat_every: state.config.heartbeat_every,
             healthcheck_every: state.config.healthcheck_every,
+            failure_detector: state.config.failure_detector,
             recipe,
             quorum: state.config.quorum,
             log,
@@ -473,9 +930,21 @@ impl ControllerInner {
             workers: HashMap::default(),
 
             pending_recovery,
+            recipe_history: state.recipe_history,
+            previous_placement: state.placement,
             last_checked_workers: Instant::now(),
 
             replies: DomainReplies(drx),
+            migration_progress,
+            placement,
+            placement_constraints: state.config.placement_constraints,
+            state_stats: HashMap::new(),
+            split_hot_keys: state.config.split_hot_keys,
+            reader_replicas: state.config.reader_replicas,
+            hibernate_after: state.config.view_hibernation,
+            recipe_dir: state.config.recipe_dir.clone(),
+            last_hibernation_check: Instant::now(),
+            worker_config: WorkerConfigUpdate::default(),
         }
     }
 
@@ -507,8 +976,17 @@ impl ControllerInner {
         log: &Logger,
         nodes: Vec<(NodeIndex, bool)>,
     ) -> DomainHandle {
+        // every shard of a domain must be assigned exactly once, since two copies of a domain
+        // would both produce output. workers also refuse duplicate assignments within an epoch.
+        assert!(
+            !self.domains.contains_key(&idx),
+            "domain {} is already placed",
+            idx.index()
+        );
+
         // TODO: can we just redirect all domain traffic through the worker's connection?
         let mut assignments = Vec::new();
+        let kind = self.domain_kind(nodes.iter().map(|&(ni, _)| ni));
         let mut nodes = Some(
             nodes
                 .into_iter()
@@ -520,8 +998,39 @@ impl ControllerInner {
                 .collect(),
         );
 
-        // TODO(malte): simple round-robin placement for the moment
-        let mut wi = self.workers.iter_mut();
+        // the policy sees the healthy workers in a stable order, and how many domain shards each of
+        // them is running already.
+        let mut candidates: Vec<_> = self
+            .workers
+            .iter()
+            .filter(|(_, w)| w.healthy)
+            .map(|(&addr, w)| WorkerCandidate {
+                addr,
+                labels: w.labels.clone(),
+                load: w.load.clone(),
+                domains: 0,
+                kinds: HashMap::new(),
+            })
+            .collect();
+        candidates.sort_by_key(|c| c.addr);
+        for (di, dh) in &self.domains {
+            let kind = self
+                .domain_nodes
+                .get(di)
+                .map(|nodes| self.domain_kind(nodes.iter().cloned()))
+                .unwrap_or(DomainKind::Internal);
+            for shard in 0..dh.shards() {
+                let wi = dh.assignment(shard);
+                if let Some(c) = candidates.iter_mut().find(|c| c.addr == wi) {
+                    c.domains += 1;
+                    *c.kinds.entry(kind).or_insert(0) += 1;
+                }
+            }
+        }
+        assert!(
+            !candidates.is_empty(),
+            "no healthy workers to place domain on"
+        );
 
         // Send `AssignDomain` to each shard of the given domain
         for i in 0..num_shards.unwrap_or(1) {
@@ -540,15 +1049,41 @@ impl ControllerInner {
                 persistence_parameters: self.persistence.clone(),
             };
 
-            let (identifier, w) = loop {
-                if let Some((i, w)) = wi.next() {
-                    if w.healthy {
-                        break (*i, w);
-                    }
-                } else {
-                    wi = self.workers.iter_mut();
+            let placement = DomainPlacement {
+                domain: idx.index(),
+                shard: i,
+                kind,
+            };
+            let eligible = placement::eligible_workers(
+                &self.placement_constraints,
+                &placement,
+                &candidates,
+                log,
+            );
+            // if we're recovering from a controller failover, put the shard back where it was
+            let previous = self
+                .previous_placement
+                .get(&idx.index())
+                .and_then(|workers| workers.get(i))
+                .and_then(|&prev| {
+                    let workers = &self.workers;
+                    eligible.iter().cloned().find(|&c| {
+                        workers[&candidates[c].addr].sender.peer_addr().ok() == Some(prev)
+                    })
+                });
+            let chosen = match previous {
+                Some(c) => c,
+                None => {
+                    let eligible_candidates: Vec<_> =
+                        eligible.iter().map(|&c| candidates[c].clone()).collect();
+                    eligible[self.placement.place(&placement, &eligible_candidates)]
                 }
             };
+            let candidate = &mut candidates[chosen];
+            candidate.domains += 1;
+            *candidate.kinds.entry(kind).or_insert(0) += 1;
+            let identifier = candidate.addr;
+            let w = self.workers.get_mut(&identifier).unwrap();
 
             // send domain to worker
             info!(
@@ -665,6 +1200,7 @@ impl ControllerInner {
         };
         let r = f(&mut m);
         m.commit();
+        self.migration_progress.lock().unwrap().finish();
         r
     }
 
@@ -675,6 +1211,7 @@ impl ControllerInner {
         F: FnOnce(&mut Migration) -> T,
     {
         info!(self.log, "starting migration");
+        self.migration_progress.lock().unwrap().start();
         let miglog = self.log.new(o!());
         let mut m = Migration {
             mainline: self,
@@ -730,12 +1267,137 @@ impl ControllerInner {
             .collect()
     }
 
-    fn find_view_for(&self, node: NodeIndex, name: &str) -> Option<NodeIndex> {
+    /// The names of the queries whose results each node helps compute, including the aliases
+    /// of the queries, in order.
+    fn node_queries(&self) -> HashMap<NodeIndex, Vec<String>> {
+        let mut aliases: HashMap<&str, Vec<&str>> = HashMap::new();
+        for alias in self.recipe.aliases() {
+            if let Some(query) = self.recipe.resolve_alias(alias) {
+                aliases.entry(query).or_default().push(alias);
+            }
+        }
+
+        let mut queries: HashMap<NodeIndex, BTreeSet<String>> = HashMap::new();
+        for reader in self
+            .ingredients
+            .externals(petgraph::EdgeDirection::Outgoing)
+            .filter(|&n| self.ingredients[n].is_reader())
+        {
+            let name = self.ingredients[reader].name();
+            let names: Vec<_> = aliases
+                .get(name)
+                .into_iter()
+                .flatten()
+                .cloned()
+                .chain(Some(name))
+                .collect();
+            let mut bfs = Bfs::new(Reversed(&self.ingredients), reader);
+            while let Some(n) = bfs.next(Reversed(&self.ingredients)) {
+                if n != self.source {
+                    let qs = queries.entry(n).or_default();
+                    qs.extend(names.iter().map(|&q| q.to_owned()));
+                }
+            }
+        }
+        queries
+            .into_iter()
+            .map(|(n, qs)| (n, qs.into_iter().collect()))
+            .collect()
+    }
+
+    /// The name of each node, and the names of the queries it helps compute.
+    fn node_names(&self) -> HashMap<NodeIndex, NodeName> {
+        let mut queries = self.node_queries();
+        self.ingredients
+            .node_indices()
+            .filter(|&n| n != self.source && !self.ingredients[n].is_dropped())
+            .map(|n| {
+                let name = NodeName {
+                    name: self.ingredients[n].name().to_owned(),
+                    queries: queries.remove(&n).unwrap_or_default(),
+                };
+                (n, name)
+            })
+            .collect()
+    }
+
+    /// How the named query or base table is computed: its MIR graph, what it reused when it was
+    /// added, and the dataflow nodes, shards and domains that compute it.
+    fn query_plan(&self, name: String) -> Result<QueryPlan, String> {
+        let leaf = self.recipe.node_addr_for(&name)?;
+        let sql_inc = self.recipe.sql_inc();
+        let (reuse, mir) = match self.recipe.resolve_alias(&name) {
+            // an alias for an identical query has no plan of its own
+            Some(query) if query != name => (
+                QueryReuse::ExactMatch(query.to_owned()),
+                sql_inc.get_query_plan(query).map(|p| p.1),
+            ),
+            _ => match sql_inc.get_query_plan(&name) {
+                Some((QueryReuse::ExactMatch(existing), _)) => {
+                    let mir = sql_inc.get_query_plan(&existing).map(|p| p.1);
+                    (QueryReuse::ExactMatch(existing), mir)
+                }
+                Some((reuse, mir)) => (reuse, Some(mir)),
+                None => (QueryReuse::None, None),
+            },
+        };
+
+        // walk up from the query's reader, or its leaf if it has none, such as for base tables
+        let start = self.find_reader(&name).unwrap_or(leaf);
+        let mut nodes = Vec::new();
+        let mut bfs = Bfs::new(Reversed(&self.ingredients), start);
+        while let Some(ni) = bfs.next(Reversed(&self.ingredients)) {
+            if ni != self.source {
+                nodes.push(ni);
+            }
+        }
+        // nodes are only ever added after their parents
+        nodes.sort();
+
+        let dataflow = nodes
+            .into_iter()
+            .map(|ni| {
+                let n = &self.ingredients[ni];
+                let (sharded_by, shards) = match n.sharded_by() {
+                    Sharding::ByColumn(c, shards) => (Some(n.fields()[c].clone()), shards),
+                    Sharding::Random(shards) => (None, shards),
+                    Sharding::None | Sharding::ForcedNone => (None, 1),
+                };
+                DataflowNodePlan {
+                    index: ni,
+                    name: n.name().to_owned(),
+                    operator: n.description(true),
+                    parents: self
+                        .ingredients
+                        .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                        .filter(|&p| p != self.source)
+                        .collect(),
+                    sharded_by,
+                    shards,
+                    domain: if n.has_domain() {
+                        Some(n.domain())
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect();
+
+        Ok(QueryPlan {
+            name,
+            reuse,
+            mir: mir.unwrap_or_default(),
+            dataflow,
+        })
+    }
+
+    fn find_views_for(&self, node: NodeIndex, name: &str) -> Vec<NodeIndex> {
         // reader should be a child of the given node. however, due to sharding, it may not be an
         // *immediate* child. furthermore, once we go beyond depth 1, we may accidentally hit an
         // *unrelated* reader node. to account for this, readers keep track of what node they are
         // "for", and we simply search for the appropriate reader by that metric. since we know
         // that the reader must be relatively close, a BFS search is the way to go.
+        let mut readers = Vec::new();
         let mut bfs = Bfs::new(&self.ingredients, node);
         while let Some(child) = bfs.next(&self.ingredients) {
             if self.ingredients[child]
@@ -743,15 +1405,21 @@ impl ControllerInner {
                 .unwrap_or(false)
                 && self.ingredients[child].name() == name
             {
-                return Some(child);
+                readers.push(child);
             }
         }
-        None
+        // the reader that was added first comes first, followed by its replicas
+        readers.sort();
+        readers
     }
 
-    /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
-    /// (already maintained) reader node called `name`.
-    fn view_builder(&self, name: &str) -> Option<ViewBuilder> {
+    /// Find the reader node that serves reads for the view called `name`.
+    fn find_reader(&self, name: &str) -> Option<NodeIndex> {
+        self.find_readers(name).into_iter().next()
+    }
+
+    /// Find all the replicas of the reader for the view called `name`, first reader first.
+    fn find_readers(&self, name: &str) -> Vec<NodeIndex> {
         // first try to resolve the node via the recipe, which handles aliasing between identical
         // queries.
         let node = match self.recipe.node_addr_for(name) {
@@ -759,7 +1427,10 @@ impl ControllerInner {
             Err(_) => {
                 // if the recipe doesn't know about this query, traverse the graph.
                 // we need this do deal with manually constructed graphs (e.g., in tests).
-                *self.outputs().get(name)?
+                match self.outputs().get(name) {
+                    Some(&ni) => ni,
+                    None => return Vec::new(),
+                }
             }
         };
 
@@ -767,31 +1438,454 @@ impl ControllerInner {
             None => name,
             Some(alias) => alias,
         };
-        self.find_view_for(node, name).map(|r| {
-            let domain = self.ingredients[r].domain();
-            let columns = self.ingredients[r].fields().to_vec();
-            let schema = self.view_schema(r);
-            let shards = (0..self.domains[&domain].shards())
-                .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
-                .collect();
-
-            ViewBuilder {
-                node: r,
-                columns,
-                schema,
-                shards,
-            }
-        })
+        self.find_views_for(node, name)
     }
 
-    fn view_schema(&self, view_ni: NodeIndex) -> Option<Vec<ColumnSpecification>> {
-        let n = &self.ingredients[view_ni];
-        let schema: Vec<_> = (0..n.fields().len())
-            .map(|i| schema::column_schema(&self.ingredients, view_ni, &self.recipe, i, &self.log))
-            .collect();
-
-        if schema.iter().any(Option::is_none) {
-            None
+    /// Set how soon writes to the view called `name` become visible to reads.
+    fn set_view_refresh(
+        &mut self,
+        (name, policy): (String, RefreshPolicy),
+    ) -> Result<(), NoriaError> {
+        let readers = self.find_readers(&name);
+        if readers.is_empty() {
+            return Err(NoriaError::NotFound(format!("view {}", name)));
+        }
+        for reader in readers {
+            // remember the policy in the graph too, so that the reader keeps it if it is rebuilt
+            self.ingredients[reader]
+                .with_reader_mut(|r| r.set_refresh_policy(policy))
+                .unwrap();
+            let node = self.ingredients[reader].local_addr();
+            let domain = self.ingredients[reader].domain();
+            self.domains
+                .get_mut(&domain)
+                .unwrap()
+                .send_to_healthy(
+                    Box::new(Packet::UpdateRefreshPolicy { node, policy }),
+                    &self.workers,
+                )
+                .map_err(|e| format!("failed to update refresh policy: {:?}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Cap how many updates per second each shard of `domain` processes, or lift the cap.
+    fn set_domain_rate_limit(
+        &mut self,
+        (domain, records_per_sec): (DomainIndex, Option<u64>),
+    ) -> Result<(), NoriaError> {
+        if records_per_sec == Some(0) {
+            return Err("rate limit must be positive".to_owned().into());
+        }
+        self.domains
+            .get_mut(&domain)
+            .ok_or_else(|| NoriaError::NotFound(format!("domain {}", domain.index())))?
+            .send_to_healthy(
+                Box::new(Packet::UpdateRateLimit { records_per_sec }),
+                &self.workers,
+            )
+            .map_err(|e| format!("failed to update rate limit: {:?}", e).into())
+    }
+
+    /// Work out what applying `ops` to the base node `base` would change, without applying them.
+    fn dry_run(
+        &mut self,
+        (base, ops): (NodeIndex, Vec<TableOperation>),
+    ) -> Result<DryRunResult, NoriaError> {
+        let (node, domain, key, shard_by, dropped) = match self.ingredients.node_weight(base) {
+            Some(n) if n.is_base() => {
+                let b = n.get_base().unwrap();
+                let key = b.key().map(<[usize]>::to_vec).unwrap_or_default();
+                let shard_by = match n.sharded_by() {
+                    Sharding::ByColumn(col, _) => Some(col),
+                    _ => None,
+                };
+                (n.local_addr(), n.domain(), key, shard_by, b.get_dropped())
+            }
+            _ => return Err(NoriaError::NotFound(format!("table {}", base.index()))),
+        };
+
+        // each shard previews the operations that it would be sent if they were written
+        let dh = self.domains.get_mut(&domain).unwrap();
+        let nshards = dh.shards();
+        let mut shard_ops = vec![Vec::new(); nshards];
+        for op in ops {
+            let shard = shard_by.filter(|_| nshards > 1).map(|col| {
+                let v = match op {
+                    TableOperation::Insert(ref row)
+                    | TableOperation::InsertOrUpdate { ref row, .. } => Some(&row[col]),
+                    TableOperation::Delete { key: ref k }
+                    | TableOperation::Purge { key: ref k }
+                    | TableOperation::Update { key: ref k, .. }
+                    | TableOperation::UpdateIf { key: ref k, .. }
+                    | TableOperation::Replicate {
+                        key: ref k,
+                        row: None,
+                    } => key.iter().position(|&c| c == col).map(|i| &k[i]),
+                    TableOperation::Replicate {
+                        row: Some(ref row), ..
+                    } => Some(&row[col]),
+                };
+                v.map(|v| noria::shard_by(v, nshards))
+            });
+            match shard {
+                None => shard_ops[0].push(op),
+                Some(Some(shard)) => shard_ops[shard].push(op),
+                Some(None) => {
+                    // the shards that do not have the row will ignore the operation
+                    for ops in &mut shard_ops {
+                        ops.push(op.clone());
+                    }
+                }
+            }
+        }
+        for (shard, ops) in shard_ops.into_iter().enumerate() {
+            dh.send_to_healthy_shard(shard, Box::new(Packet::DryRun { node, ops }), &self.workers)
+                .map_err(|e| format!("failed to send dry run: {:?}", e))?;
+        }
+        let records = futures_executor::block_on(self.replies.wait_for_dry_run(dh));
+
+        let mut result = DryRunResult::default();
+        for r in &records {
+            // clients do not see the columns that have been dropped
+            let row = r
+                .iter()
+                .enumerate()
+                .filter(|&(c, _)| !dropped.contains_key(c))
+                .map(|(_, v)| v.clone())
+                .collect();
+            if r.is_positive() {
+                result.added.push(row);
+            } else {
+                result.removed.push(row);
+            }
+        }
+        if records.is_empty() {
+            return Ok(result);
+        }
+
+        // the changed rows are pushed through the filters and projections below the table, which
+        // need no state to tell what they emit. What other operators, such as joins and
+        // aggregations, emit is not known, so everything below them may change.
+        let mut deltas: HashMap<NodeIndex, Option<Vec<Record>>> = HashMap::new();
+        deltas.insert(base, Some(records.clone()));
+        let mut topo = petgraph::visit::Topo::new(&self.ingredients);
+        while let Some(ni) = topo.next(&self.ingredients) {
+            if ni == base {
+                continue;
+            }
+            let parents: Vec<_> = self
+                .ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                .filter_map(|p| deltas.get(&p))
+                .collect();
+            if parents.is_empty() {
+                continue;
+            }
+            let n = &self.ingredients[ni];
+            let delta: Option<Vec<Record>> = if parents
+                .iter()
+                .all(|d| d.as_ref().map(Vec::is_empty).unwrap_or(false))
+            {
+                Some(Vec::new())
+            } else {
+                match parents[..] {
+                    [&Some(ref rs)] if n.is_internal() => match **n {
+                        ops::NodeOperator::Filter(ref f) => {
+                            Some(rs.iter().filter(|r| f.admits(r)).cloned().collect())
+                        }
+                        ops::NodeOperator::Project(ref p) => Some(
+                            rs.iter()
+                                .map(|r| Record::from((p.project(r), r.is_positive())))
+                                .collect(),
+                        ),
+                        ops::NodeOperator::Identity(_) => Some(rs.clone()),
+                        ops::NodeOperator::Union(ref u) if u.is_shard_merger() => Some(rs.clone()),
+                        _ => None,
+                    },
+                    // the other nodes pass on the rows of their one parent unchanged
+                    [&Some(ref rs)] if !n.is_base() => Some(rs.clone()),
+                    _ => None,
+                }
+            };
+
+            // views that filter out every changed row do not change
+            let unchanged = delta.as_ref().map(Vec::is_empty).unwrap_or(false);
+            let reader = n.with_reader(|r| (r.is_for(), r.key().map(<[usize]>::to_vec)));
+            if let (false, Ok((view, key))) = (unchanged, reader) {
+                let keys = match delta {
+                    Some(ref rs) => key.map(|key| {
+                        let mut keys = Vec::new();
+                        for r in rs {
+                            let k: Vec<_> = key.iter().map(|&c| r[c].clone()).collect();
+                            if !keys.contains(&k) {
+                                keys.push(k);
+                            }
+                        }
+                        keys
+                    }),
+                    None => key
+                        .and_then(|key| {
+                            key.into_iter()
+                                .map(|c| self.column_source(base, view, c))
+                                .collect::<Option<Vec<_>>>()
+                        })
+                        .map(|cols| {
+                            let mut keys = Vec::new();
+                            for r in &records {
+                                let k: Vec<_> = cols.iter().map(|&c| r[c].clone()).collect();
+                                if !keys.contains(&k) {
+                                    keys.push(k);
+                                }
+                            }
+                            keys
+                        }),
+                };
+                result.views.insert(n.name().to_owned(), keys);
+            }
+            deltas.insert(ni, delta);
+        }
+        Ok(result)
+    }
+
+    /// Find the base table rows that `row`, a row of the view called `name`, was computed from.
+    ///
+    /// The values of the row are followed back through each operator to the columns of its
+    /// parents that they came from, and the rows of each base table that have the values that
+    /// reach it are looked up.
+    fn provenance(
+        &mut self,
+        (name, row): (String, Vec<DataType>),
+    ) -> Result<Provenance, NoriaError> {
+        let reader = self
+            .find_reader(&name)
+            .ok_or_else(|| NoriaError::NotFound(format!("view {}", name)))?;
+        let view = self.ingredients[reader]
+            .with_reader(|r| r.is_for())
+            .unwrap();
+        let ncols = self.ingredients[view].fields().len();
+        if row.len() > ncols {
+            return Err(format!("view {} has only {} columns", name, ncols).into());
+        }
+
+        let mut result = Provenance {
+            exact: true,
+            ..Default::default()
+        };
+        // the values that the rows of each base found along the way must have
+        let mut lookups = Vec::new();
+        let mut stack = vec![(view, row.into_iter().enumerate().collect::<Vec<_>>())];
+        while let Some((ni, values)) = stack.pop() {
+            let n = &self.ingredients[ni];
+            if n.is_base() {
+                lookups.push((ni, values));
+            } else if n.is_internal() {
+                // base rows that have the values that reach them may still not have made it
+                // through a filter, or may differ from the row in the columns a projection drops
+                let narrows = match **n {
+                    ops::NodeOperator::Filter(_) => true,
+                    ops::NodeOperator::Project(ref p) => {
+                        let (emit, _, _) = p.emits();
+                        let pcols = self.ingredients[n.ancestors()[0]].fields().len();
+                        (0..pcols).any(|c| !emit.contains(&c))
+                    }
+                    _ => false,
+                };
+                if narrows {
+                    result.exact = false;
+                }
+                let mut parents: HashMap<_, Vec<_>> =
+                    n.ancestors().into_iter().map(|p| (p, Vec::new())).collect();
+                for (c, v) in values {
+                    let pcs = n.parent_columns(c);
+                    if pcs.iter().all(|&(_, pc)| pc.is_none()) {
+                        // the operator computed this value, so any parent row may have added to it
+                        result.exact = false;
+                    }
+                    for (p, pc) in pcs {
+                        if let (Some(pc), Some(pvs)) = (pc, parents.get_mut(&p)) {
+                            pvs.push((pc, v.clone()));
+                        }
+                    }
+                }
+                stack.extend(parents);
+            } else {
+                // the other nodes pass on the rows of their parents unchanged
+                for p in self
+                    .ingredients
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                {
+                    stack.push((p, values.clone()));
+                }
+            }
+        }
+
+        for (base, columns) in lookups {
+            let n = &self.ingredients[base];
+            let (node, dropped) = (n.local_addr(), n.get_base().unwrap().get_dropped());
+            let dh = self.domains.get_mut(&n.domain()).unwrap();
+            dh.send_to_healthy(Box::new(Packet::FindRows { node, columns }), &self.workers)
+                .map_err(|e| format!("failed to look up base rows: {:?}", e))?;
+            let found = futures_executor::block_on(self.replies.wait_for_rows(dh))
+                .map_err(|e| format!("failed to look up base rows: {}", e))?;
+            let rows = result.tables.entry(n.name().to_owned()).or_default();
+            for r in found {
+                // clients do not see the columns that have been dropped
+                let r: Vec<_> = r
+                    .into_iter()
+                    .enumerate()
+                    .filter(|&(c, _)| !dropped.contains_key(c))
+                    .map(|(_, v)| v)
+                    .collect();
+                if !rows.contains(&r) {
+                    rows.push(r);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Find the column of the base node `base` that column `col` of `ni` holds values of, if any.
+    fn column_source(&self, base: NodeIndex, ni: NodeIndex, col: usize) -> Option<usize> {
+        let n = &self.ingredients[ni];
+        if ni == base {
+            Some(col)
+        } else if n.is_base() {
+            None
+        } else if n.is_internal() {
+            n.parent_columns(col)
+                .into_iter()
+                .filter(|&(p, _)| p != ni)
+                .find_map(|(p, pc)| self.column_source(base, p, pc?))
+        } else {
+            // the other nodes pass on the columns of their one parent
+            let p = self
+                .ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                .next()?;
+            self.column_source(base, p, col)
+        }
+    }
+
+    /// Change runtime parameters of all workers, and remember them for workers that join later.
+    fn update_worker_config(&mut self, update: WorkerConfigUpdate) -> Result<(), NoriaError> {
+        if let Some(ref level) = update.log_level {
+            level
+                .parse::<slog::Level>()
+                .map_err(|_| format!("unknown log level {}", level))?;
+        }
+        if update.eviction_interval == Some(Duration::from_millis(0)) {
+            return Err("eviction interval must be positive".to_owned().into());
+        }
+
+        self.worker_config.merge(&update);
+        for (addr, w) in &mut self.workers {
+            let src = w.sender.local_addr().unwrap();
+            w.sender
+                .send(CoordinationMessage {
+                    epoch: self.epoch,
+                    source: src,
+                    payload: CoordinationPayload::UpdateConfig(update.clone()),
+                })
+                .map_err(|e| format!("failed to update worker {:?}: {:?}", addr, e))?;
+        }
+        Ok(())
+    }
+
+    /// Add the nodes of `name` to the graph if it is a lazy view that is being read for the first
+    /// time.
+    fn build_deferred_view(&mut self, name: &str) -> Result<(), NoriaError> {
+        if !self.recipe.is_deferred(name) {
+            return Ok(());
+        }
+
+        info!(self.log, "building lazy view on first read"; "view" => name);
+        let mut recipe = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let r = self.migrate(|mig| plan(|| recipe.activate_deferred(name, mig)));
+        self.recipe = recipe;
+        r.map(|_| ())
+    }
+
+    /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
+    /// (already maintained) reader node called `name`.
+    fn view_builder(&self, name: &str) -> Option<ViewBuilder> {
+        let readers = self.find_readers(name);
+        readers.first().cloned().map(|r| {
+            let domain = self.ingredients[r].domain();
+            let columns = self.ingredients[r].fields().to_vec();
+            let schema = self.view_schema(r);
+            let shards = (0..self.domains[&domain].shards())
+                .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
+                .collect();
+            let replicas = if readers.len() > 1 {
+                readers.iter().map(|&r| self.reader_replica(r)).collect()
+            } else {
+                Vec::new()
+            };
+
+            // if the rows of a key may be spread over several rows, the client must add them up
+            let merge = self.ingredients[r]
+                .with_reader(|r| r.split())
+                .unwrap()
+                .map(|col| Merge::Combine {
+                    group: (0..columns.len()).filter(|&c| c != col).collect(),
+                    aggregates: vec![(col, Combine::Sum)],
+                })
+                .unwrap_or_default();
+
+            let order = self.ingredients[r]
+                .with_reader(|r| r.order().to_vec())
+                .unwrap();
+            let limit = self.ingredients[r].with_reader(|r| r.limit()).unwrap();
+            let echoed = self.ingredients[r]
+                .with_reader(|r| r.echoed().to_vec())
+                .unwrap();
+            let key_columns = self.ingredients[r]
+                .with_reader(|r| r.key().map(|k| k.to_vec()).unwrap_or_default())
+                .unwrap();
+
+            ViewBuilder {
+                node: r,
+                columns,
+                schema,
+                shards,
+                merge,
+                order,
+                limit,
+                echoed,
+                key_columns,
+                replicas,
+            }
+        })
+    }
+
+    /// Where each shard of the reader `r` runs, and in which zone.
+    fn reader_replica(&self, r: NodeIndex) -> ReaderReplica {
+        let dh = &self.domains[&self.ingredients[r].domain()];
+        let workers: Vec<_> = (0..dh.shards()).map(|i| dh.assignment(i)).collect();
+        ReaderReplica {
+            node: r,
+            shards: workers.iter().map(|wi| self.read_addrs[wi]).collect(),
+            zones: workers
+                .iter()
+                .map(|wi| {
+                    self.workers
+                        .get(wi)
+                        .and_then(|w| w.labels.get(ZONE_LABEL))
+                        .cloned()
+                })
+                .collect(),
+        }
+    }
+
+    fn view_schema(&self, view_ni: NodeIndex) -> Option<Vec<ColumnSpecification>> {
+        let n = &self.ingredients[view_ni];
+        let schema: Vec<_> = (0..n.fields().len())
+            .map(|i| schema::column_schema(&self.ingredients, view_ni, &self.recipe, i, &self.log))
+            .collect();
+
+        if schema.iter().any(Option::is_none) {
+            None
         } else {
             Some(schema.into_iter().map(Option::unwrap).collect())
         }
@@ -804,6 +1898,9 @@ impl ControllerInner {
             Ok(ni) => ni,
             Err(_) => *self.inputs().get(base)?,
         };
+        if !self.ingredients[ni].is_base() {
+            return self.writable_view_builder(base);
+        }
         let node = &self.ingredients[ni];
 
         trace!(self.log, "creating table"; "for" => base);
@@ -813,8 +1910,12 @@ impl ControllerInner {
             .remove(&ni)
             .unwrap_or_else(Vec::new);
         let mut is_primary = false;
+        let shard_by = match self.ingredients[ni].sharded_by() {
+            Sharding::ByColumn(col, _) => Some(col),
+            _ => None,
+        };
         if key.is_empty() {
-            if let Sharding::ByColumn(col, _) = self.ingredients[ni].sharded_by() {
+            if let Some(col) = shard_by {
                 key = vec![col];
             }
         } else {
@@ -843,6 +1944,17 @@ impl ControllerInner {
             columns.len(),
             node.fields().len() - base_operator.get_dropped().len()
         );
+        // the base numbers columns including the dropped ones, but clients do not see those
+        let dropped = base_operator.get_dropped();
+        let mut generated: Vec<_> = base_operator
+            .timestamp_columns()
+            .map(|(created, updated)| vec![created, updated])
+            .unwrap_or_default()
+            .into_iter()
+            .chain(base_operator.tombstone_column())
+            .map(|c| c - dropped.keys().filter(|&d| d < c).count())
+            .collect();
+        generated.sort();
         let schema = self.recipe.schema_for(base).map(|s| match s {
             Schema::Table(s) => s,
             _ => panic!("non-base schema {:?} returned for table '{}'", s, base),
@@ -854,13 +1966,182 @@ impl ControllerInner {
             addr: node.local_addr(),
             key,
             key_is_primary: is_primary,
-            dropped: base_operator.get_dropped(),
+            shard_by,
+            dropped,
+            generated,
             table_name: node.name().to_owned(),
             columns,
             schema,
+            view: None,
+        })
+    }
+
+    /// Obtain a `TableBuilder` that writes through the view called `name` to the base table it
+    /// reads from.
+    ///
+    /// This is only possible for views that filter and project the columns of a single base
+    /// table with a primary key, and that include all of the key's columns.
+    fn writable_view_builder(&self, name: &str) -> Option<TableBuilder> {
+        let reader = self.find_reader(name)?;
+        let view = self.ingredients[reader].with_reader(|r| r.is_for()).ok()?;
+
+        // follow each of the view's columns up to the base table
+        let mut ni = view;
+        let mut columns: Vec<_> = (0..self.ingredients[view].fields().len())
+            .map(Some)
+            .collect();
+        while !self.ingredients[ni].is_base() {
+            let n = &self.ingredients[ni];
+            if n.is_internal() {
+                match **n {
+                    ops::NodeOperator::Project(_)
+                    | ops::NodeOperator::Filter(_)
+                    | ops::NodeOperator::Identity(_) => {}
+                    _ => return None,
+                }
+                columns = columns
+                    .into_iter()
+                    .map(|c| match n.parent_columns(c?)[..] {
+                        [(_, Some(pc))] => Some(pc),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            let mut parents = self
+                .ingredients
+                .neighbors_directed(ni, petgraph::EdgeDirection::Incoming);
+            ni = parents.next()?;
+            if parents.next().is_some() {
+                return None;
+            }
+        }
+
+        let mut tb = self.table_builder(self.ingredients[ni].name())?;
+        if !tb.key_is_primary || tb.key.is_empty() {
+            return None;
+        }
+        // clients number the base's columns without the ones that have been dropped
+        let base_columns: Vec<_> = columns
+            .into_iter()
+            .map(|c| {
+                let c = c?;
+                if tb.dropped.contains_key(c) {
+                    return None;
+                }
+                Some(c - tb.dropped.keys().filter(|&d| d < c).count())
+            })
+            .collect();
+        if !tb.key.iter().all(|k| base_columns.contains(&Some(*k))) {
+            return None;
+        }
+
+        tb.view = Some(ViewColumns {
+            name: name.to_owned(),
+            columns: self.ingredients[reader].fields().to_vec(),
+            base_columns,
+        });
+        Some(tb)
+    }
+
+    /// Describe the columns and key of the base table `name`.
+    fn describe_table(&self, name: &str) -> Option<TableDescription> {
+        let tb = self.table_builder(name)?;
+        let schema = tb.schema;
+        let columns = tb
+            .columns
+            .into_iter()
+            .map(|column| {
+                let sql_type = schema.as_ref().and_then(|s| {
+                    s.fields
+                        .iter()
+                        .find(|f| f.column.name == column)
+                        .map(|f| f.sql_type.clone())
+                });
+                ColumnDescription {
+                    name: column,
+                    sql_type,
+                }
+            })
+            .collect();
+        Some(TableDescription {
+            name: tb.table_name,
+            columns,
+            key: tb.key,
+            key_is_primary: tb.key_is_primary,
         })
     }
 
+    /// Describe the columns and parameters of the view `name`.
+    fn describe_view(&self, name: &str) -> Option<ViewDescription> {
+        let r = self.find_reader(name)?;
+        let columns = self.ingredients[r]
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ColumnDescription {
+                name: name.clone(),
+                sql_type: schema::column_schema(&self.ingredients, r, &self.recipe, i, &self.log)
+                    .map(|cs| cs.sql_type),
+            })
+            .collect();
+        let parameters = self.ingredients[r]
+            .with_reader(|r| r.key().map(<[usize]>::to_vec))
+            .unwrap()
+            .unwrap_or_default();
+        let order = self.ingredients[r]
+            .with_reader(|r| r.order().to_vec())
+            .unwrap();
+        Some(ViewDescription {
+            name: name.to_owned(),
+            columns,
+            parameters,
+            order,
+        })
+    }
+
+    /// Find the tables and views that each table and view reads from directly.
+    fn list_dependencies(&self) -> Dependencies {
+        let inputs = self.inputs();
+        let outputs = self.outputs();
+        let mut named: HashMap<NodeIndex, &str> = HashMap::new();
+        for (name, &ni) in inputs.iter().chain(outputs.iter()) {
+            named.entry(ni).or_insert(name);
+        }
+
+        let mut deps: Dependencies = inputs
+            .keys()
+            .map(|name| (name.clone(), Default::default()))
+            .collect();
+        for (name, &ni) in &outputs {
+            let mut reads = BTreeSet::new();
+            let mut visited = HashSet::new();
+            // a view that reads a base table directly is materialized at the base itself
+            let mut stack = if self.ingredients[ni].is_base() {
+                vec![ni]
+            } else {
+                self.ingredients
+                    .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .collect()
+            };
+            while let Some(n) = stack.pop() {
+                if n == self.source || !visited.insert(n) {
+                    continue;
+                }
+                match named.get(&n) {
+                    Some(&dep) => {
+                        reads.insert(dep.to_owned());
+                    }
+                    None => stack.extend(
+                        self.ingredients
+                            .neighbors_directed(n, petgraph::EdgeDirection::Incoming),
+                    ),
+                }
+            }
+            deps.insert(name.clone(), reads);
+        }
+        deps
+    }
+
     /// Get statistics about the time spent processing different parts of the graph.
     fn get_statistics(&mut self) -> GraphStats {
         trace!(self.log, "asked to get statistics");
@@ -882,13 +2163,108 @@ impl ControllerInner {
             })
             .collect();
 
-        GraphStats { domains }
+        let mut stats = GraphStats { domains };
+        let queries = self.node_queries();
+        for (_, nodes) in stats.domains.values_mut() {
+            for (ni, ns) in nodes.iter_mut() {
+                ns.queries = queries.get(ni).cloned().unwrap_or_default();
+            }
+        }
+        stats
+    }
+
+    /// Sample the state of every node, and remember what was found so that later migrations can
+    /// plan with it.
+    fn collect_state_statistics(&mut self) -> HashMap<NodeIndex, StateStats> {
+        trace!(self.log, "asked to collect state statistics");
+        let workers = &self.workers;
+        let replies = &mut self.replies;
+        let mut samples: HashMap<NodeIndex, StateSample> = HashMap::new();
+        for s in self.domains.values_mut() {
+            s.send_to_healthy(
+                Box::new(Packet::SampleState {
+                    rows: STATE_SAMPLE_ROWS,
+                }),
+                workers,
+            )
+            .unwrap();
+            // the shards of a node each hold some of its rows
+            for shard in futures_executor::block_on(replies.wait_for_state_samples(&s)) {
+                for (ni, sample) in shard {
+                    match samples.get_mut(&ni) {
+                        Some(merged) => merge_samples(merged, sample),
+                        None => {
+                            samples.insert(ni, sample);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.state_stats = samples
+            .into_iter()
+            .map(|(ni, sample)| {
+                let stats = StateStats {
+                    rows: sample.rows,
+                    distinct: sample.columns.iter().map(|c| c.estimate()).collect(),
+                    indices: sample
+                        .indices
+                        .into_iter()
+                        .map(|i| IndexStats {
+                            columns: i.columns,
+                            keys: i.keys,
+                            hot_keys: i.hot_keys,
+                        })
+                        .collect(),
+                };
+                (ni, stats)
+            })
+            .collect();
+
+        // the join planner orders joins by the sizes of the tables involved
+        let table_rows = self
+            .state_stats
+            .iter()
+            .filter(|&(&ni, _)| self.ingredients[ni].is_base())
+            .map(|(&ni, s)| (self.ingredients[ni].name().to_owned(), s.rows))
+            .collect();
+        self.recipe.set_table_rows(table_rows);
+
+        self.state_stats.clone()
+    }
+
+    fn worker_stats(&self) -> Vec<WorkerStats> {
+        let mut stats: Vec<_> = self
+            .workers
+            .iter()
+            .map(|(&addr, w)| WorkerStats {
+                addr,
+                healthy: w.healthy,
+                labels: w.labels.clone(),
+                version: w.version,
+                last_heartbeat: w.detector.last_heartbeat().elapsed().as_millis() as u64,
+                cpu: w.load.cpu,
+                memory: w.load.memory,
+                rss: w.load.rss,
+                disk_free: w.load.disk_free,
+                queues: w.load.queues.clone(),
+                standby_lag: w.load.standby_lag,
+            })
+            .collect();
+        stats.sort_by_key(|w| w.addr);
+        stats
     }
 
     fn get_instances(&self) -> Vec<(WorkerIdentifier, bool, Duration)> {
         self.workers
             .iter()
-            .map(|(&id, ref status)| (id, status.healthy, status.last_heartbeat.elapsed()))
+            .map(|(&id, ref status)| {
+                (
+                    id,
+                    status.healthy,
+                    status.detector.last_heartbeat().elapsed(),
+                )
+            })
             .collect()
     }
 
@@ -1001,16 +2377,27 @@ impl ControllerInner {
         Ok(())
     }
 
-    fn set_security_config(&mut self, p: String) -> Result<(), String> {
+    fn set_security_config<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        p: String,
+    ) -> Result<(), String> {
         self.recipe.set_security_config(&p);
-        Ok(())
+        authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.security_config = Some(p.clone());
+                    Ok(state)
+                }
+            })
+            .map(|_| ())
+            .map_err(|_| "Failed to persist security config".to_owned())
     }
 
-    fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, String> {
-        let r = self.migrate(|mig| {
-            new.activate(mig)
-                .map_err(|e| format!("failed to activate recipe: {}", e))
-        });
+    fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, NoriaError> {
+        let r = self.migrate(|mig| plan(|| new.activate(mig)));
 
         match r {
             Ok(ref ra) => {
@@ -1055,6 +2442,22 @@ impl ControllerInner {
                 }
 
                 self.recipe = new;
+
+                // the readers of an aggregation that splits hot keys add up the partial rows its
+                // shards compute for a key, but nodes that later queries add below it would not
+                let split: Vec<_> = self
+                    .ingredients
+                    .node_indices()
+                    .filter_map(|ni| {
+                        self.ingredients[ni]
+                            .with_reader(|r| r.split().map(|_| r.is_for()))
+                            .ok()
+                            .flatten()
+                    })
+                    .collect();
+                for query in self.recipe.queries_for_nodes(split) {
+                    self.recipe.forbid_reuse_of_split(&query);
+                }
             }
             Err(ref e) => {
                 crit!(self.log, "failed to apply recipe: {}", e);
@@ -1071,12 +2474,22 @@ impl ControllerInner {
         &mut self,
         authority: &Arc<A>,
         add_txt: String,
-    ) -> Result<ActivationResult, String> {
+    ) -> Result<ActivationResult, NoriaError> {
+        // the files that the recipe includes are read now, so that what is persisted stands on
+        // its own
+        let add_txt =
+            recipe::expand_includes(&add_txt, self.recipe_dir.as_deref()).map_err(|e| {
+                crit!(self.log, "failed to extend recipe: {:?}", e);
+                NoriaError::Parse(e)
+            })?;
+
         // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
         let new = mem::replace(&mut self.recipe, Recipe::blank(None));
         match new.extend(&add_txt) {
             Ok(new) => {
                 let activation_result = self.apply_recipe(new);
+                self.record_recipe_version();
+                let placement = self.placement_snapshot();
                 if authority
                     .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
                         None => unreachable!(),
@@ -1084,12 +2497,15 @@ impl ControllerInner {
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes.push(add_txt.clone());
+                            state.recipe_history = self.recipe_history.clone();
+                            state.recipe_generations = self.recipe.generations().clone();
+                            state.placement = placement.clone();
                             Ok(state)
                         }
                     })
                     .is_err()
                 {
-                    return Err("Failed to persist recipe extension".to_owned());
+                    return Err("Failed to persist recipe extension".to_owned().into());
                 }
 
                 activation_result
@@ -1098,7 +2514,7 @@ impl ControllerInner {
                 // need to restore the old recipe
                 crit!(self.log, "failed to extend recipe: {:?}", e);
                 self.recipe = old;
-                Err("failed to extend recipe".to_owned())
+                Err(NoriaError::Parse(e))
             }
         }
     }
@@ -1107,12 +2523,19 @@ impl ControllerInner {
         &mut self,
         authority: &Arc<A>,
         r_txt: String,
-    ) -> Result<ActivationResult, String> {
+    ) -> Result<ActivationResult, NoriaError> {
+        let r_txt = recipe::expand_includes(&r_txt, self.recipe_dir.as_deref()).map_err(|e| {
+            crit!(self.log, "failed to parse recipe: {:?}", e);
+            NoriaError::Parse(e)
+        })?;
+
         match Recipe::from_str(&r_txt, Some(self.log.clone())) {
             Ok(r) => {
                 let old = mem::replace(&mut self.recipe, Recipe::blank(None));
                 let new = old.replace(r).unwrap();
                 let activation_result = self.apply_recipe(new);
+                self.record_recipe_version();
+                let placement = self.placement_snapshot();
                 if authority
                     .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
                         None => unreachable!(),
@@ -1120,29 +2543,525 @@ impl ControllerInner {
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes = vec![r_txt.clone()];
+                            state.recipe_history = self.recipe_history.clone();
+                            state.recipe_generations = self.recipe.generations().clone();
+                            state.placement = placement.clone();
                             Ok(state)
                         }
                     })
                     .is_err()
                 {
-                    return Err("Failed to persist recipe installation".to_owned());
+                    return Err("Failed to persist recipe installation".to_owned().into());
                 }
                 activation_result
             }
             Err(e) => {
                 crit!(self.log, "failed to parse recipe: {:?}", e);
-                Err("failed to parse recipe".to_owned())
+                Err(NoriaError::Parse(e))
+            }
+        }
+    }
+
+    /// Plan `r_txt` as a replacement for the current recipe without changing the graph, and
+    /// report any problems along with the views it would provide.
+    ///
+    /// The recipe is planned from scratch in an empty graph that is swapped in for the duration of
+    /// the check, and the migration that builds it is never committed.
+    fn validate_recipe(&mut self, r_txt: String) -> RecipeValidation {
+        let mut validation = RecipeValidation::default();
+        let candidate = match recipe::expand_includes(&r_txt, self.recipe_dir.as_deref())
+            .and_then(|r_txt| Recipe::from_str(&r_txt, Some(self.log.clone())))
+        {
+            Ok(r) => r,
+            Err(e) => {
+                validation.errors.push(NoriaError::Parse(e));
+                return validation;
+            }
+        };
+
+        let current: Vec<(Option<String>, SqlQuery)> = self
+            .recipe
+            .expressions()
+            .into_iter()
+            .map(|(n, q)| (n.cloned(), q.clone()))
+            .collect();
+        let table_rows: HashMap<String, usize> = self
+            .state_stats
+            .iter()
+            .filter(|&(&ni, _)| self.ingredients[ni].is_base())
+            .map(|(&ni, s)| (self.ingredients[ni].name().to_owned(), s.rows))
+            .collect();
+
+        let mut scratch = petgraph::Graph::new();
+        let source = scratch.add_node(node::Node::new(
+            "source",
+            &["because-type-inference"],
+            node::special::Source,
+        ));
+        let graph = mem::replace(&mut self.ingredients, scratch);
+        let source = mem::replace(&mut self.source, source);
+        let recipe = mem::replace(&mut self.recipe, candidate);
+
+        // planning panics on some SQL that it does not support, so catch those too
+        let log = self.log.new(o!("validating" => true));
+        let activated = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut candidate = mem::replace(&mut self.recipe, Recipe::blank(None));
+            let mut m = Migration {
+                mainline: self,
+                added: Default::default(),
+                columns: Default::default(),
+                readers: Default::default(),
+                context: Default::default(),
+                start: time::Instant::now(),
+                log,
+            };
+            let r = candidate.activate(&mut m);
+            drop(m);
+            self.recipe = candidate;
+            r
+        }));
+
+        match activated {
+            Ok(Ok(ra)) => {
+                for (name, &leaf) in &ra.new_nodes {
+                    if self.ingredients[leaf].is_base() {
+                        continue;
+                    }
+                    // queries that are only used by other queries have no reader
+                    let view = match self.describe_view(name) {
+                        Some(view) => view,
+                        None => continue,
+                    };
+                    for c in view.columns.iter().filter(|c| c.sql_type.is_none()) {
+                        validation.warnings.push(format!(
+                            "cannot infer the type of column {} of {}",
+                            c.name, name
+                        ));
+                    }
+
+                    let query = self
+                        .recipe
+                        .expressions()
+                        .into_iter()
+                        .find(|&(n, _)| n == Some(name))
+                        .map(|(_, q)| q.clone());
+                    let new = !current
+                        .iter()
+                        .any(|(n, q)| n.as_ref() == Some(name) && Some(q) == query.as_ref());
+                    let estimated_rows = if new {
+                        let mut bfs = Bfs::new(Reversed(&self.ingredients), leaf);
+                        let mut rows = Some(0);
+                        while let Some(n) = bfs.next(Reversed(&self.ingredients)) {
+                            if self.ingredients[n].is_base() {
+                                rows = rows.and_then(|rows: usize| {
+                                    Some(rows.max(*table_rows.get(self.ingredients[n].name())?))
+                                });
+                            }
+                        }
+                        rows
+                    } else {
+                        None
+                    };
+                    validation.views.push(PlannedView {
+                        view,
+                        new,
+                        estimated_rows,
+                    });
+                }
+                validation
+                    .views
+                    .sort_by(|a, b| a.view.name.cmp(&b.view.name));
+            }
+            Ok(Err(e)) => validation.errors.push(planning_error(e)),
+            Err(panic) => validation
+                .errors
+                .push(NoriaError::UnsupportedSql(panic_message(panic))),
+        }
+
+        self.ingredients = graph;
+        self.source = source;
+        self.recipe = recipe;
+        validation
+    }
+
+    /// Return the graph to the state it was in at recipe version `version`.
+    ///
+    /// Like any other recipe change, this happens in a single migration: queries added since
+    /// `version` are only torn down once the queries it re-adds are ready. Versions from before
+    /// the controller started are restored from the persisted recipe history, which holds the
+    /// latest `RECIPE_HISTORY` versions.
+    fn rollback<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        version: usize,
+    ) -> Result<ActivationResult, NoriaError> {
+        // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = match old.rollback(version) {
+            Ok(new) => new,
+            Err((old, e)) => {
+                // the version may predate this controller, in which case only its text is known
+                let target = match self.recipe_history.get(&version) {
+                    Some(text) if version < old.version() => {
+                        Recipe::from_str(text, Some(self.log.clone()))
+                    }
+                    _ => Err(e),
+                };
+                match target {
+                    Ok(target) => old.replace(target).unwrap(),
+                    Err(e) => {
+                        warn!(self.log, "failed to roll back recipe: {}", e);
+                        self.recipe = old;
+                        return Err(e.into());
+                    }
+                }
+            }
+        };
+        let activation_result = self.apply_recipe(new);
+        if self.persist_current_recipe(authority).is_err() {
+            return Err("Failed to persist recipe rollback".to_owned().into());
+        }
+        activation_result
+    }
+
+    fn remove_query<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        qname: String,
+    ) -> Result<(), NoriaError> {
+        // a query whose leaf feeds other queries can't go away on its own. other queries may hang
+        // off the leaf behind egress, ingress or sharder nodes, so any operator below the leaf
+        // means the query is still in use; the only other thing we expect is the query's reader.
+        let leaf = self
+            .recipe
+            .node_addr_for(&qname)
+            .map_err(|_| NoriaError::NotFound(format!("query {}", qname)))?;
+        let mut used = false;
+        let mut bfs = Bfs::new(&self.ingredients, leaf);
+        while let Some(child) = bfs.next(&self.ingredients) {
+            if child != leaf && self.ingredients[child].is_internal() {
+                used = true;
+                break;
+            }
+        }
+        if used {
+            return Err(format!("query \"{}\" is still used by other queries", qname).into());
+        }
+
+        // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = match old.remove(&qname) {
+            Ok(new) => new,
+            Err((old, e)) => {
+                warn!(self.log, "failed to remove query {}: {}", qname, e);
+                self.recipe = old;
+                return Err(e.into());
+            }
+        };
+        self.apply_recipe(new)?;
+        if self.persist_current_recipe(authority).is_err() {
+            return Err("Failed to persist query removal".to_owned().into());
+        }
+        Ok(())
+    }
+
+    /// Run `query`, a single `SELECT`, once with `params` for its placeholders, and return the
+    /// names of its columns along with its rows.
+    ///
+    /// The query is evaluated directly over the current rows of the tables that it reads, so no
+    /// dataflow is added for it, and the recipe does not change.
+    fn query_once(
+        &mut self,
+        (query, params): (String, Vec<DataType>),
+    ) -> Result<(Vec<String>, Vec<Vec<DataType>>), NoriaError> {
+        let query = query.trim().trim_end_matches(';');
+        let parsed =
+            Recipe::from_str(&format!("QUERY q: {};", query), None).map_err(NoriaError::Parse)?;
+        let q = match parsed.expressions()[..] {
+            [(_, q)] => (*q).clone(),
+            _ => {
+                return Err(planning_error(sql::unsupported(
+                    "running anything but a single SELECT once",
+                )))
+            }
+        };
+        let q = plan(|| self.recipe.sql_inc().rewrite_adhoc(q))?;
+
+        let mut tables = HashMap::new();
+        for name in sql::adhoc::tables(&q).map_err(planning_error)? {
+            let ni = self
+                .recipe
+                .node_addr_for(&name)
+                .ok()
+                .filter(|&ni| self.ingredients[ni].is_base())
+                .ok_or_else(|| {
+                    planning_error(sql::unsupported(format!(
+                        "running a query over view {} once",
+                        name
+                    )))
+                })?;
+            let n = &self.ingredients[ni];
+            let (node, dropped) = (n.local_addr(), n.get_base().unwrap().get_dropped());
+            let columns = n
+                .fields()
+                .iter()
+                .enumerate()
+                .filter(|&(c, _)| !dropped.contains_key(c))
+                .map(|(_, f)| f.clone())
+                .collect();
+            let dh = self.domains.get_mut(&n.domain()).unwrap();
+            dh.send_to_healthy(
+                Box::new(Packet::FindRows {
+                    node,
+                    columns: vec![],
+                }),
+                &self.workers,
+            )
+            .map_err(|e| format!("failed to read table {}: {:?}", name, e))?;
+            let rows = futures_executor::block_on(self.replies.wait_for_rows(dh))
+                .map_err(|e| format!("failed to read table {}: {}", name, e))?
+                .into_iter()
+                .map(|r| {
+                    // clients do not see the columns that have been dropped
+                    r.into_iter()
+                        .enumerate()
+                        .filter(|&(c, _)| !dropped.contains_key(c))
+                        .map(|(_, v)| v)
+                        .collect()
+                })
+                .collect();
+            tables.insert(name, sql::adhoc::Relation { columns, rows });
+        }
+
+        plan(|| sql::adhoc::execute(&q, &params, &tables))
+    }
+
+    /// Stage `query` as the next version of the named query `qname`. The new version is installed
+    /// and backfilled alongside the current one, under the returned name, until it is promoted
+    /// with `promote_view` (or removed with `remove_query`).
+    fn stage_view<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (qname, query): (String, String),
+    ) -> Result<String, NoriaError> {
+        let staged = format!("{}_v{}", qname, self.recipe.version() + 1);
+
+        // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = match old.stage(&qname, &staged, &query) {
+            Ok(new) => new,
+            Err((old, e)) => {
+                warn!(self.log, "failed to stage new version of {}: {}", qname, e);
+                self.recipe = old;
+                return Err(e.into());
+            }
+        };
+        self.apply_recipe(new)?;
+        if self.persist_current_recipe(authority).is_err() {
+            return Err("Failed to persist staged view".to_owned().into());
+        }
+        Ok(staged)
+    }
+
+    /// Atomically make the staged view `staged` serve reads for `qname`, and tear down the
+    /// version that served them before.
+    fn promote_view<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        (qname, staged): (String, String),
+    ) -> Result<ActivationResult, NoriaError> {
+        // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = match old.promote(&qname, &staged) {
+            Ok(new) => new,
+            Err((old, e)) => {
+                warn!(self.log, "failed to promote {} to {}: {}", staged, qname, e);
+                self.recipe = old;
+                return Err(e.into());
+            }
+        };
+        let activation_result = self.apply_recipe(new);
+        if self.persist_current_recipe(authority).is_err() {
+            return Err("Failed to persist view promotion".to_owned().into());
+        }
+        activation_result
+    }
+
+    /// Build a new generation of the graph from `r_txt` next to the current one, sharing its base
+    /// tables. Returns the generation number to pass to `switch_generation`.
+    ///
+    /// By the time this returns, the new generation's full materializations have been backfilled.
+    fn stage_generation<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        r_txt: String,
+    ) -> Result<usize, NoriaError> {
+        let generation = self.recipe.version() + 1;
+
+        // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = match old.stage_generation(generation, &r_txt) {
+            Ok(new) => new,
+            Err((old, e)) => {
+                warn!(self.log, "failed to stage generation {}: {}", generation, e);
+                self.recipe = old;
+                return Err(e.into());
+            }
+        };
+        self.apply_recipe(new)?;
+        if self.persist_current_recipe(authority).is_err() {
+            return Err("Failed to persist staged generation".to_owned().into());
+        }
+        Ok(generation)
+    }
+
+    /// Atomically point all query names at generation `generation`, and tear down the queries of
+    /// every other generation.
+    fn switch_generation<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        generation: usize,
+    ) -> Result<ActivationResult, NoriaError> {
+        // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
+        let old = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let new = match old.switch_generation(generation) {
+            Ok(new) => new,
+            Err((old, e)) => {
+                warn!(
+                    self.log,
+                    "failed to switch to generation {}: {}", generation, e
+                );
+                self.recipe = old;
+                return Err(e.into());
             }
+        };
+        let activation_result = self.apply_recipe(new);
+        if self.persist_current_recipe(authority).is_err() {
+            return Err("Failed to persist generation switch".to_owned().into());
+        }
+        activation_result
+    }
+
+    /// Replace the persisted recipe log with the current recipe. This is needed for recipe
+    /// changes that can't be expressed as an extension of the previous recipe.
+    fn persist_current_recipe<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+    ) -> Result<(), ()> {
+        self.record_recipe_version();
+        let placement = self.placement_snapshot();
+        authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.recipe_version = self.recipe.version();
+                    state.recipes = vec![self.recipe.to_text()];
+                    state.recipe_history = self.recipe_history.clone();
+                    state.recipe_generations = self.recipe.generations().clone();
+                    state.security_config = self.recipe.security_config_text().map(String::from);
+                    state.placement = placement.clone();
+                    Ok(state)
+                }
+            })
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    /// Remember the text of the current recipe version, so that it can be rolled back to even
+    /// after the controller restarts. Only the latest `RECIPE_HISTORY` versions are kept.
+    fn record_recipe_version(&mut self) {
+        self.recipe_history
+            .insert(self.recipe.version(), self.recipe.to_text());
+        while self.recipe_history.len() > RECIPE_HISTORY {
+            let oldest = *self.recipe_history.keys().next().unwrap();
+            self.recipe_history.remove(&oldest);
         }
     }
 
     fn graphviz(&self, detailed: bool) -> String {
-        graphviz(&self.ingredients, detailed, &self.materializations)
+        if !detailed {
+            return graphviz(&self.ingredients, detailed, &self.materializations);
+        }
+        let annotations = self
+            .node_queries()
+            .into_iter()
+            .map(|(ni, qs)| (ni, format!("queries: {}", qs.join(", "))))
+            .collect();
+        annotated_graphviz(
+            &self.ingredients,
+            detailed,
+            &self.materializations,
+            &annotations,
+        )
     }
 
-    fn remove_leaf(&mut self, mut leaf: NodeIndex) -> Result<(), String> {
+    /// Produce a detailed graphviz description of the graph where each node is annotated with its
+    /// shard count, materialization type, state size, and throughput, as reported by the domains.
+    fn annotated_graphviz(&mut self) -> String {
+        struct Totals {
+            shards: usize,
+            materialized: &'static str,
+            mem_size: u64,
+            records: u64,
+            process_time: u64,
+        }
+
+        let stats = self.get_statistics();
+        let queries = self.node_queries();
+        let mut totals: HashMap<NodeIndex, Totals> = HashMap::new();
+        for (&(di, _), (_, nodes)) in stats.iter() {
+            let shards = self.domains[&di].shards();
+            for (&ni, ns) in nodes {
+                let t = totals.entry(ni).or_insert_with(|| Totals {
+                    shards,
+                    materialized: match ns.materialized {
+                        MaterializationStatus::Not => "none",
+                        MaterializationStatus::Partial { .. } => "partial",
+                        MaterializationStatus::Full => "full",
+                    },
+                    mem_size: 0,
+                    records: 0,
+                    process_time: 0,
+                });
+                t.mem_size += ns.mem_size;
+                t.records += ns.records;
+                t.process_time += ns.process_time;
+            }
+        }
+
+        let annotations = totals
+            .into_iter()
+            .map(|(ni, t)| {
+                // records per second of processing time, summed across shards
+                let throughput = if t.process_time == 0 {
+                    0.0
+                } else {
+                    t.records as f64 / (t.process_time as f64 / 1_000_000_000.0)
+                };
+                let mut annotation = format!(
+                    "shards: {} \\n state: {} ({} bytes) \\n throughput: {:.0} rec/s ({} records)",
+                    t.shards, t.materialized, t.mem_size, throughput, t.records
+                );
+                if let Some(qs) = queries.get(&ni) {
+                    annotation.push_str(&format!(" \\n queries: {}", qs.join(", ")));
+                }
+                (ni, annotation)
+            })
+            .collect();
+
+        annotated_graphviz(
+            &self.ingredients,
+            true,
+            &self.materializations,
+            &annotations,
+        )
+    }
+
+    fn remove_leaf(&mut self, leaf: NodeIndex) -> Result<(), String> {
         let mut removals = vec![];
         let start = leaf;
+        let mut leaves = vec![leaf];
         assert!(!self.ingredients[leaf].is_source());
 
         info!(
@@ -1157,48 +3076,46 @@ impl ControllerInner {
             .count();
         if nchildren > 0 {
             // This query leaf node has children -- typically, these are readers, but they can also
-            // include egress nodes or other, dependent queries. We need to find the actual reader,
-            // and remove that.
-            if nchildren != 1 {
-                crit!(
-                    self.log,
-                    "cannot remove node {}, as it still has multiple children",
-                    leaf.index()
-                );
-                unreachable!();
-            }
-
+            // include egress nodes or other, dependent queries. We need to find the actual readers
+            // (there is one for each replica), and remove those.
             let mut readers = Vec::new();
             let mut bfs = Bfs::new(&self.ingredients, leaf);
             while let Some(child) = bfs.next(&self.ingredients) {
                 let n = &self.ingredients[child];
                 if n.with_reader(|r| r.is_for() == leaf) == Ok(true) {
                     readers.push(child);
+                } else if child != leaf && n.is_internal() {
+                    crit!(
+                        self.log,
+                        "cannot remove node {}, as other queries still depend on it",
+                        leaf.index()
+                    );
+                    unreachable!();
                 }
             }
 
-            // nodes can have only one reader attached
-            assert_eq!(readers.len(), 1);
-            let reader = readers[0];
+            // nodes have one reader attached, along with any replicas of it
+            assert!(!readers.is_empty());
             debug!(
                 self.log,
                 "Removing query leaf \"{}\"", self.ingredients[leaf].name();
                 "node" => leaf.index(),
-                "really" => reader.index(),
+                "really" => ?readers,
             );
-            removals.push(reader);
-            leaf = reader;
+            leaves = readers;
         }
 
-        // `node` now does not have any children any more
-        assert_eq!(
-            self.ingredients
-                .neighbors_directed(leaf, petgraph::EdgeDirection::Outgoing)
-                .count(),
-            0
-        );
+        // none of the nodes we start from have any children
+        for &leaf in &leaves {
+            assert_eq!(
+                self.ingredients
+                    .neighbors_directed(leaf, petgraph::EdgeDirection::Outgoing)
+                    .count(),
+                0
+            );
+        }
 
-        let mut nodes = vec![leaf];
+        let mut nodes = leaves;
         while let Some(node) = nodes.pop() {
             let mut parents = self
                 .ingredients
@@ -6,6 +6,7 @@
 //! module).
 
 use crate::controller::domain_handle::DomainHandle;
+use crate::controller::migrate::progress::MigrationProgress;
 use crate::controller::{
     inner::{graphviz, DomainReplies},
     keys,
@@ -18,6 +19,7 @@ use slog::Logger;
 use std::collections::{HashMap, HashSet};
 use std::mem;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 mod plan;
 
@@ -46,15 +48,44 @@ impl Default for FrontierStrategy {
     }
 }
 
+/// Strategy for determining which joins should guard their lookups with a Bloom filter.
+///
+/// A join that looks up a key the other side does not have can then tell so without probing the
+/// other side's state. This pays off for joins whose lookups mostly miss. Only lookups into full
+/// materializations are guarded, since a key missing from a partial one may just be a hole.
+///
+/// Note that no matter what this is set to, all joins whose name starts with `BLOOM_` get a
+/// Bloom filter.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BloomFilterStrategy {
+    /// Guard only the joins named `BLOOM_*` (this is the default).
+    None,
+    /// Guard all joins.
+    AllJoins,
+    /// Guard all joins whose name contains the given string.
+    Match(String),
+}
+
+impl Default for BloomFilterStrategy {
+    fn default() -> Self {
+        BloomFilterStrategy::None
+    }
+}
+
 pub(in crate::controller) struct Materializations {
     log: Logger,
 
     have: HashMap<NodeIndex, Indices>,
     added: HashMap<NodeIndex, Indices>,
+    /// The indices in `have` that must be ordered, because some operator scans ranges of them.
+    ordered: HashMap<NodeIndex, Indices>,
+    /// The indices in `have` whose lookups are guarded by a Bloom filter.
+    bloom: HashMap<NodeIndex, Indices>,
 
     partial: HashSet<NodeIndex>,
     partial_enabled: bool,
     frontier_strategy: FrontierStrategy,
+    bloom_strategy: BloomFilterStrategy,
 
     tag_generator: AtomicUsize,
 }
@@ -67,10 +98,13 @@ impl Materializations {
 
             have: HashMap::default(),
             added: HashMap::default(),
+            ordered: HashMap::default(),
+            bloom: HashMap::default(),
 
             partial: HashSet::default(),
             partial_enabled: true,
             frontier_strategy: FrontierStrategy::None,
+            bloom_strategy: BloomFilterStrategy::None,
 
             tag_generator: AtomicUsize::default(),
         }
@@ -90,6 +124,11 @@ impl Materializations {
     pub(in crate::controller) fn set_frontier_strategy(&mut self, f: FrontierStrategy) {
         self.frontier_strategy = f;
     }
+
+    /// Which joins should guard their lookups with a Bloom filter?
+    pub(in crate::controller) fn set_bloom_filter_strategy(&mut self, b: BloomFilterStrategy) {
+        self.bloom_strategy = b;
+    }
 }
 
 impl Materializations {
@@ -97,6 +136,37 @@ impl Materializations {
         Tag::new(self.tag_generator.fetch_add(1, Ordering::SeqCst) as u32)
     }
 
+    /// Whether lookups by the given node should be guarded by a Bloom filter.
+    fn wants_bloom_filter(&self, n: &Node) -> bool {
+        if !n.is_internal() || !n.is_join() {
+            return false;
+        }
+        if n.name().starts_with("BLOOM_") {
+            return true;
+        }
+        match self.bloom_strategy {
+            BloomFilterStrategy::None => false,
+            BloomFilterStrategy::AllJoins => true,
+            BloomFilterStrategy::Match(ref m) => n.name().contains(m),
+        }
+    }
+
+    /// Which of the given indices of the given node should be guarded by a Bloom filter.
+    fn bloom_filters(&self, ni: NodeIndex, indices: &Indices) -> Indices {
+        match self.bloom.get(&ni) {
+            Some(bloom) => indices.intersection(bloom).cloned().collect(),
+            None => Indices::default(),
+        }
+    }
+
+    /// The type of index to build on the given columns of the given node.
+    fn index_type(&self, ni: NodeIndex, columns: &[usize]) -> IndexType {
+        match self.ordered.get(&ni) {
+            Some(ordered) if ordered.contains(columns) => IndexType::BTreeMap,
+            _ => IndexType::HashMap,
+        }
+    }
+
     /// Extend the current set of materializations with any additional materializations needed to
     /// satisfy indexing obligations in the given set of (new) nodes.
     #[allow(clippy::cognitive_complexity)]
@@ -139,6 +209,12 @@ impl Materializations {
         // Holds all replay obligations. Keyed by the node whose *parent* should be materialized.
         let mut replay_obligations = HashMap::new();
 
+        // Holds the lookup obligations that need an ordered index.
+        let mut ordered_obligations: HashMap<_, Indices> = HashMap::new();
+
+        // Holds the lookup obligations that should be guarded by a Bloom filter.
+        let mut bloom_obligations: HashMap<_, Indices> = HashMap::new();
+
         // Find indices we need to add.
         for &ni in new {
             let n = &graph[ni];
@@ -168,12 +244,34 @@ impl Materializations {
                 indices.insert(ni, (vec![0], true));
             }
 
+            // bases with a ttl find their expired rows with a range scan over the ttl column
+            if let Some((col, _)) = n.get_base().and_then(|b| b.ttl()) {
+                ordered_obligations.entry(ni).or_default().insert(vec![col]);
+                lookup_obligations
+                    .entry(ni)
+                    .or_insert_with(HashSet::new)
+                    .insert(vec![col]);
+            }
+
             for (ni, (cols, lookup)) in indices {
                 trace!(self.log, "new indexing obligation";
                        "node" => ni.index(),
                        "columns" => ?cols,
                        "lookup" => lookup);
 
+                if lookup && n.suggest_index_type(ni) == IndexType::BTreeMap {
+                    ordered_obligations
+                        .entry(ni)
+                        .or_default()
+                        .insert(cols.clone());
+                }
+                if lookup && self.wants_bloom_filter(n) {
+                    bloom_obligations
+                        .entry(ni)
+                        .or_default()
+                        .insert(cols.clone());
+                }
+
                 if lookup {
                     lookup_obligations
                         .entry(ni)
@@ -238,6 +336,9 @@ impl Materializations {
         // it and the nearest full materialization (because the intermediate ones haven't been
         // marked as materialized yet).
         for (ni, mut indices) in lookup_obligations {
+            let mut ordered = ordered_obligations.remove(&ni).unwrap_or_default();
+            let mut bloom = bloom_obligations.remove(&ni).unwrap_or_default();
+
             // we want to find the closest materialization that allows lookups (i.e., counting
             // query-through operators).
             let mut mi = ni;
@@ -264,6 +365,8 @@ impl Materializations {
                        "to" => parent.index());
                 mi = parent;
                 indices = map_indices(m, mi, &indices).unwrap();
+                ordered = map_indices(m, mi, &ordered).unwrap();
+                bloom = map_indices(m, mi, &bloom).unwrap();
                 m = &graph[mi];
             }
 
@@ -274,6 +377,13 @@ impl Materializations {
                     "columns" => ?columns,
                 );
 
+                if ordered.contains(&columns) {
+                    self.ordered.entry(mi).or_default().insert(columns.clone());
+                }
+                if bloom.contains(&columns) {
+                    self.bloom.entry(mi).or_default().insert(columns.clone());
+                }
+
                 if self.have.entry(mi).or_default().insert(columns.clone()) {
                     // also add a replay obligation to enable partial
                     replay_obligations
@@ -335,6 +445,12 @@ impl Materializations {
                 able = false;
             }
 
+            // a range lookup cannot tell whether it covers any holes
+            if self.ordered.contains_key(&ni) {
+                warn!(self.log, "full because of ordered index"; "node" => ni.index());
+                able = false;
+            }
+
             // we are already fully materialized, so can't be made partial
             if !new.contains(&ni)
                 && self.added.get(&ni).map(|i| i.len()).unwrap_or(0)
@@ -485,6 +601,7 @@ impl Materializations {
         domains: &mut HashMap<DomainIndex, DomainHandle>,
         workers: &HashMap<WorkerIdentifier, Worker>,
         replies: &mut DomainReplies,
+        progress: &Mutex<MigrationProgress>,
     ) {
         self.extend(graph, new);
 
@@ -524,6 +641,7 @@ impl Materializations {
 
         // Mark nodes as beyond the frontier as dictated by the strategy
         for &ni in new {
+            let cached = self.partial.contains(&ni) && feeds_only_cached_views(graph, ni);
             let n = graph.node_weight_mut(ni).unwrap();
 
             if (self.have.contains_key(&ni) || n.is_reader()) && !self.partial.contains(&ni) {
@@ -544,6 +662,12 @@ impl Materializations {
                 continue;
             }
 
+            // cached views are computed when they are read, and only their readers keep results
+            if cached {
+                n.purge = true;
+                continue;
+            }
+
             // For all other strategies, we only want to deal with partial indices
             if !self.partial.contains(&ni) {
                 continue;
@@ -817,7 +941,16 @@ impl Materializations {
                     .send_to_healthy(
                         Box::new(Packet::PrepareState {
                             node: n.local_addr(),
-                            state: InitialState::IndexedLocal(index_on),
+                            state: InitialState::IndexedLocal {
+                                bloom: self.bloom_filters(node, &index_on),
+                                indices: index_on
+                                    .into_iter()
+                                    .map(|cols| {
+                                        let index_type = self.index_type(node, &cols);
+                                        (cols, index_type)
+                                    })
+                                    .collect(),
+                            },
                         }),
                         workers,
                     )
@@ -826,6 +959,7 @@ impl Materializations {
         }
 
         // then, we start prepping new nodes
+        progress.lock().unwrap().replaying(make.len());
         for ni in make {
             let n = &graph[ni];
             let mut index_on = self
@@ -861,12 +995,28 @@ impl Materializations {
             futures_executor::block_on(replies.wait_for_acks(&domain));
             trace!(self.log, "node ready"; "node" => ni.index());
 
+            let mut records = 0;
             if reconstructed {
                 info!(self.log, "reconstruction completed";
                 "ms" => start.elapsed().as_millis(),
                 "node" => ni.index(),
                 );
+
+                // readers keep their state outside of the domain, so we can only count rows for
+                // other nodes.
+                if !n.is_reader() {
+                    domain
+                        .send_to_healthy(
+                            Box::new(Packet::StateSizeProbe {
+                                node: n.local_addr(),
+                            }),
+                            workers,
+                        )
+                        .unwrap();
+                    records = futures_executor::block_on(replies.wait_for_state_sizes(&domain));
+                }
             }
+            progress.lock().unwrap().replayed(records);
         }
 
         self.added.clear();
@@ -996,3 +1146,18 @@ impl Materializations {
         }
     }
 }
+
+/// Whether all the readers that `ni` feeds, directly or through other nodes, belong to cached
+/// views, and there is at least one such reader.
+fn feeds_only_cached_views(graph: &Graph, ni: NodeIndex) -> bool {
+    let mut readers = 0;
+    let mut stack = vec![ni];
+    while let Some(n) = stack.pop() {
+        match graph[n].with_reader(|r| r.is_cached()) {
+            Ok(true) => readers += 1,
+            Ok(false) => return false,
+            Err(()) => stack.extend(graph.neighbors_directed(n, petgraph::EdgeDirection::Outgoing)),
+        }
+    }
+    readers != 0
+}
@@ -359,6 +359,9 @@ impl<'a> Plan<'a> {
                             let src_sharding = self.graph[segments[0].1[0].0].sharded_by();
                             let shards = src_sharding.shards().unwrap_or(1);
                             let lookup_key_to_shard = match src_sharding {
+                                // the records of a key may be on any shard, as when an
+                                // aggregation splits its hot keys, so all shards are asked and
+                                // the shard merger on the path unions their answers
                                 Sharding::Random(..) => None,
                                 Sharding::ByColumn(c, _) => {
                                     let lookup_key =
@@ -537,15 +540,37 @@ impl<'a> Plan<'a> {
             .unwrap_or_else(|| {
                 // not a reader
                 if self.partial {
+                    let m = &self.m;
+                    let node = self.node;
                     let indices = self
                         .tags
                         .drain()
-                        .map(|(k, paths)| (k, paths.into_iter().map(|(tag, _)| tag).collect()))
+                        .map(|(k, paths)| {
+                            let index_type = m.index_type(node, &k);
+                            (
+                                k,
+                                index_type,
+                                paths.into_iter().map(|(tag, _)| tag).collect(),
+                            )
+                        })
                         .collect();
                     InitialState::PartialLocal(indices)
                 } else {
-                    let indices = self.tags.drain().map(|(k, _)| k).collect();
-                    InitialState::IndexedLocal(indices)
+                    let m = &self.m;
+                    let node = self.node;
+                    let indices: HashMap<_, _> = self
+                        .tags
+                        .drain()
+                        .map(|(k, _)| {
+                            let index_type = m.index_type(node, &k);
+                            (k, index_type)
+                        })
+                        .collect();
+                    let keys = indices.keys().cloned().collect();
+                    InitialState::IndexedLocal {
+                        bloom: m.bloom_filters(node, &keys),
+                        indices,
+                    }
                 }
             });
 
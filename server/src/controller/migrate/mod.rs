@@ -23,8 +23,10 @@
 use crate::controller::ControllerInner;
 use dataflow::prelude::*;
 use dataflow::{node, prelude::Packet};
+use nom_sql::OrderType;
 use std::collections::{HashMap, HashSet};
-use std::time::Instant;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use petgraph;
 use slog;
@@ -32,6 +34,8 @@ use slog;
 mod assignment;
 mod augmentation;
 pub(crate) mod materialization;
+pub(crate) mod progress;
+pub(crate) mod queue;
 mod routing;
 mod sharding;
 
@@ -50,7 +54,8 @@ pub struct Migration<'a> {
     pub(super) mainline: &'a mut ControllerInner,
     pub(super) added: HashSet<NodeIndex>,
     pub(super) columns: Vec<(NodeIndex, ColumnChange)>,
-    pub(super) readers: HashMap<NodeIndex, NodeIndex>,
+    /// The readers of each node, starting with the one that clients are told about first.
+    pub(super) readers: HashMap<NodeIndex, Vec<NodeIndex>>,
 
     pub(super) start: Instant,
     pub(super) log: slog::Logger,
@@ -111,6 +116,11 @@ impl<'a> Migration<'a> {
         S2: ToString,
         FS: IntoIterator<Item = S2>,
     {
+        assert!(
+            b.ttl().is_none() || b.key().is_some(),
+            "bases with a ttl must have a primary key"
+        );
+
         // add to the graph
         let ni = self
             .mainline
@@ -207,6 +217,92 @@ impl<'a> Migration<'a> {
         col_i1
     }
 
+    /// Expire rows from a base node added in this migration once the time in `column` lies more
+    /// than `ttl` in the past.
+    pub(in crate::controller) fn set_ttl(
+        &mut self,
+        node: NodeIndex,
+        column: usize,
+        ttl: Duration,
+    ) -> Result<(), String> {
+        let base = &mut self.mainline.ingredients[node];
+        if !self.added.contains(&node) {
+            return Err(format!(
+                "cannot change the ttl of existing table {}",
+                base.name()
+            ));
+        }
+        let base = base.get_base_mut().unwrap();
+        if base.key().is_none() {
+            return Err("tables with a ttl must have a primary key".to_owned());
+        }
+        *base = mem::take(base).with_ttl(column, ttl);
+        Ok(())
+    }
+
+    /// Keep a version counter for each row of a base node added in this migration in `column`.
+    pub(in crate::controller) fn set_version_column(
+        &mut self,
+        node: NodeIndex,
+        column: usize,
+    ) -> Result<(), String> {
+        let base = &mut self.mainline.ingredients[node];
+        if !self.added.contains(&node) {
+            return Err(format!(
+                "cannot add a version column to existing table {}",
+                base.name()
+            ));
+        }
+        let base = base.get_base_mut().unwrap();
+        if base.key().is_none() {
+            return Err("tables with a version column must have a primary key".to_owned());
+        }
+        *base = mem::take(base).with_version_column(column);
+        Ok(())
+    }
+
+    /// Record when each row of a base node added in this migration was created in column
+    /// `created`, and when it was last changed in column `updated`.
+    pub(in crate::controller) fn set_timestamp_columns(
+        &mut self,
+        node: NodeIndex,
+        created: usize,
+        updated: usize,
+    ) -> Result<(), String> {
+        let base = &mut self.mainline.ingredients[node];
+        if !self.added.contains(&node) {
+            return Err(format!(
+                "cannot add timestamp columns to existing table {}",
+                base.name()
+            ));
+        }
+        let base = base.get_base_mut().unwrap();
+        *base = mem::take(base).with_timestamp_columns(created, updated);
+        Ok(())
+    }
+
+    /// Mark the rows deleted from a base node added in this migration in `column`, rather than
+    /// removing them.
+    pub(in crate::controller) fn set_tombstone_column(
+        &mut self,
+        node: NodeIndex,
+        column: usize,
+    ) -> Result<(), String> {
+        let base = &mut self.mainline.ingredients[node];
+        if !self.added.contains(&node) {
+            return Err(format!(
+                "cannot make existing table {} use soft deletes",
+                base.name()
+            ));
+        }
+        let base = base.get_base_mut().unwrap();
+        if base.key().is_none() {
+            return Err("tables with soft deletes must have a primary key".to_owned());
+        }
+        *base = mem::take(base).with_tombstone_column(column);
+        Ok(())
+    }
+
     /// Drop a column from a base node.
     // crate viz for tests
     pub fn drop_column(&mut self, node: NodeIndex, column: usize) {
@@ -233,20 +329,37 @@ impl<'a> Migration<'a> {
     fn ensure_reader_for(&mut self, n: NodeIndex, name: Option<String>) {
         use std::collections::hash_map::Entry;
         if let Entry::Vacant(e) = self.readers.entry(n) {
-            // make a reader
-            let r = node::special::Reader::new(n);
-            let mut r = if let Some(name) = name {
-                self.mainline.ingredients[n].named_mirror(r, name)
-            } else {
-                self.mainline.ingredients[n].mirror(r)
-            };
-            if r.name().starts_with("SHALLOW_") {
-                r.purge = true;
+            // make a reader, along with any replicas of it. since readers always get a domain of
+            // their own, each replica can be placed on a different worker.
+            let mut readers = Vec::with_capacity(1 + self.mainline.reader_replicas);
+            for _ in 0..=self.mainline.reader_replicas {
+                let r = node::special::Reader::new(n);
+                let mut r = if let Some(ref name) = name {
+                    self.mainline.ingredients[n].named_mirror(r, name.clone())
+                } else {
+                    self.mainline.ingredients[n].mirror(r)
+                };
+                if r.name().starts_with("SHALLOW_") {
+                    r.purge = true;
+                }
+                let r = self.mainline.ingredients.add_node(r);
+                self.mainline.ingredients.add_edge(n, r, ());
+                self.added.insert(r);
+                readers.push(r);
             }
-            let r = self.mainline.ingredients.add_node(r);
-            self.mainline.ingredients.add_edge(n, r, ());
-            self.added.insert(r);
-            e.insert(r);
+            e.insert(readers);
+        }
+    }
+
+    /// Apply `f` to every replica of the reader for node `n`.
+    fn with_readers_mut<F>(&mut self, n: NodeIndex, mut f: F)
+    where
+        F: FnMut(&mut node::special::Reader),
+    {
+        for &ri in &self.readers[&n] {
+            self.mainline.ingredients[ri]
+                .with_reader_mut(|r| f(r))
+                .unwrap();
         }
     }
 
@@ -255,13 +368,8 @@ impl<'a> Migration<'a> {
     /// To query into the maintained state, use `ControllerInner::get_getter`.
     pub fn maintain_anonymous(&mut self, n: NodeIndex, key: &[usize]) -> NodeIndex {
         self.ensure_reader_for(n, None);
-        let ri = self.readers[&n];
-
-        self.mainline.ingredients[ri]
-            .with_reader_mut(|r| r.set_key(key))
-            .unwrap();
-
-        ri
+        self.with_readers_mut(n, |r| r.set_key(key));
+        self.readers[&n][0]
     }
 
     /// Set up the given node such that its output can be efficiently queried.
@@ -269,12 +377,38 @@ impl<'a> Migration<'a> {
     /// To query into the maintained state, use `ControllerInner::get_getter`.
     pub fn maintain(&mut self, name: String, n: NodeIndex, key: &[usize]) {
         self.ensure_reader_for(n, Some(name));
+        self.with_readers_mut(n, |r| r.set_key(key));
+    }
+
+    /// Have clients sort the rows of each key they read from the reader for node `n` by the
+    /// given columns.
+    pub fn set_reader_order(&mut self, n: NodeIndex, order: Vec<(usize, OrderType)>) {
+        self.with_readers_mut(n, |r| r.set_order(order.clone()));
+    }
+
+    /// Record that the query behind the reader for node `n` only keeps the first `limit` rows of
+    /// each key.
+    pub fn set_reader_limit(&mut self, n: NodeIndex, limit: usize) {
+        self.with_readers_mut(n, |r| r.set_limit(limit));
+    }
+
+    /// Have clients fill the given columns of the reader for node `n` with the values bound to
+    /// the parameters of the query's projection.
+    pub fn set_reader_echoed(&mut self, n: NodeIndex, columns: Vec<usize>) {
+        self.with_readers_mut(n, |r| r.set_echoed(columns.clone()));
+    }
 
-        let ri = self.readers[&n];
+    /// Have the reader for node `n` only hold on to keys until they have been read, as the
+    /// node merely passes on the rows of a base table by its key.
+    pub fn set_reader_straight_through(&mut self, n: NodeIndex) {
+        self.with_readers_mut(n, |r| r.set_straight_through());
+    }
 
-        self.mainline.ingredients[ri]
-            .with_reader_mut(|r| r.set_key(key))
-            .unwrap();
+    /// Have the reader for node `n` keep the results it is replayed until writes evict them. The
+    /// view's own partial state is placed beyond the materialization frontier, so that results
+    /// are computed when they are read rather than maintained.
+    pub fn set_reader_cached(&mut self, n: NodeIndex) {
+        self.with_readers_mut(n, |r| r.set_cached());
     }
 
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
@@ -294,8 +428,15 @@ impl<'a> Migration<'a> {
 
         // Shard the graph as desired
         let mut swapped0 = if let Some(shards) = mainline.sharding {
-            let (t, swapped) =
-                sharding::shard(&log, &mut mainline.ingredients, &mut new, &topo, shards);
+            let (t, swapped) = sharding::shard(
+                &log,
+                &mut mainline.ingredients,
+                &mut new,
+                &topo,
+                shards,
+                &mainline.state_stats,
+                mainline.split_hot_keys,
+            );
             topo = t;
 
             swapped
@@ -503,6 +644,7 @@ impl<'a> Migration<'a> {
             }
 
             let nodes = uninformed_domain_nodes.remove(&domain).unwrap();
+            mainline.migration_progress.lock().unwrap().booting(domain);
             let d = mainline.place_domain(
                 domain,
                 mainline.ingredients[nodes[0].0].sharded_by().shards(),
@@ -510,6 +652,7 @@ impl<'a> Migration<'a> {
                 nodes,
             );
             mainline.domains.insert(domain, d);
+            mainline.migration_progress.lock().unwrap().booted();
         }
 
         // Add any new nodes to existing domains (they'll also ignore all updates for now)
@@ -579,6 +722,7 @@ impl<'a> Migration<'a> {
             &mut mainline.domains,
             &mainline.workers,
             &mut mainline.replies,
+            &mainline.migration_progress,
         );
 
         warn!(log, "migration completed"; "ms" => start.elapsed().as_millis());
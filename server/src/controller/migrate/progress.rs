@@ -0,0 +1,118 @@
+//! Tracking of migration progress.
+//!
+//! The controller cannot answer requests while it is in the middle of a migration, so progress is
+//! recorded in a `MigrationProgress` that is shared with the external API server, which reports
+//! it directly.
+
+use dataflow::prelude::DomainIndex;
+use noria::debug::migration::MigrationStatus;
+use std::time::Instant;
+
+#[derive(Default)]
+pub(crate) struct MigrationProgress {
+    started: Option<Instant>,
+    replay_started: Option<Instant>,
+    finished: Option<Instant>,
+    status: MigrationStatus,
+}
+
+impl MigrationProgress {
+    pub(in crate::controller) fn start(&mut self) {
+        *self = MigrationProgress {
+            started: Some(Instant::now()),
+            status: MigrationStatus {
+                in_progress: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+    }
+
+    pub(super) fn booting(&mut self, domain: DomainIndex) {
+        self.status.new_domains.push(domain);
+    }
+
+    pub(super) fn booted(&mut self) {
+        self.status.domains_booted += 1;
+    }
+
+    pub(super) fn replaying(&mut self, materializations: usize) {
+        self.replay_started = Some(Instant::now());
+        self.status.materializations = materializations;
+    }
+
+    pub(super) fn replayed(&mut self, records: usize) {
+        self.status.materializations_done += 1;
+        self.status.records_replayed += records;
+    }
+
+    pub(in crate::controller) fn finish(&mut self) {
+        self.finished = Some(Instant::now());
+        self.status.in_progress = false;
+    }
+
+    /// The current status, with elapsed time and the remaining time estimate brought up to date.
+    pub(crate) fn status(&self) -> MigrationStatus {
+        let now = self.finished.unwrap_or_else(Instant::now);
+        let mut status = self.status.clone();
+        if let Some(started) = self.started {
+            status.elapsed = now.duration_since(started).as_millis() as u64;
+        }
+
+        status.eta = if !status.in_progress {
+            Some(0)
+        } else if let Some(replay_started) = self.replay_started {
+            let done = status.materializations_done as u64;
+            let left = (status.materializations - status.materializations_done) as u64;
+            if done == 0 {
+                None
+            } else {
+                let per = now.duration_since(replay_started).as_millis() as u64 / done;
+                Some(per * left)
+            }
+        } else {
+            None
+        };
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_tracks_progress() {
+        let mut p = MigrationProgress::default();
+        assert!(!p.status().in_progress);
+
+        p.start();
+        p.booting(DomainIndex::from(0));
+        p.booting(DomainIndex::from(1));
+        p.booted();
+        let s = p.status();
+        assert!(s.in_progress);
+        assert_eq!(s.new_domains.len(), 2);
+        assert_eq!(s.domains_booted, 1);
+        assert_eq!(s.eta, None);
+
+        p.booted();
+        p.replaying(4);
+        p.replayed(10);
+        p.replayed(0);
+        let s = p.status();
+        assert_eq!(s.materializations, 4);
+        assert_eq!(s.materializations_done, 2);
+        assert_eq!(s.records_replayed, 10);
+        assert!(s.eta.is_some());
+
+        p.finish();
+        let s = p.status();
+        assert!(!s.in_progress);
+        assert_eq!(s.eta, Some(0));
+
+        // a new migration resets progress
+        p.start();
+        assert_eq!(p.status().new_domains.len(), 0);
+    }
+}
@@ -0,0 +1,82 @@
+//! Queueing of recipe changes.
+//!
+//! The controller applies recipe changes one at a time, in the order in which the external API
+//! server hands them over. The changes that are waiting their turn are counted in a
+//! `MigrationQueue` that is shared with the external API server, which reports it directly, and
+//! turns changes away once too many are waiting. Clients retry those after a short delay.
+
+use noria::debug::migration::MigrationQueueStatus;
+use std::sync::{Arc, Mutex};
+
+/// The paths of external requests that change the recipe.
+pub(crate) const RECIPE_CHANGES: &[&str] = &["/extend_recipe", "/install_recipe"];
+
+#[derive(Default)]
+pub(crate) struct MigrationQueue {
+    status: MigrationQueueStatus,
+}
+
+/// A recipe change's place in the queue, which it leaves when this is dropped.
+pub(crate) struct QueueSlot(Arc<Mutex<MigrationQueue>>);
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock().unwrap();
+        queue.status.queued -= 1;
+        queue.status.completed += 1;
+    }
+}
+
+impl MigrationQueue {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        MigrationQueue {
+            status: MigrationQueueStatus {
+                limit,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Queue a recipe change, unless the queue is full.
+    pub(crate) fn enter(queue: &Arc<Mutex<MigrationQueue>>) -> Option<QueueSlot> {
+        let mut q = queue.lock().unwrap();
+        if let Some(limit) = q.status.limit {
+            if q.status.queued >= limit {
+                q.status.deferred += 1;
+                return None;
+            }
+        }
+        q.status.queued += 1;
+        Some(QueueSlot(Arc::clone(queue)))
+    }
+
+    pub(crate) fn status(&self) -> MigrationQueueStatus {
+        self.status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_turns_away_changes_once_full() {
+        let queue = Arc::new(Mutex::new(MigrationQueue::new(Some(2))));
+        let first = MigrationQueue::enter(&queue).unwrap();
+        let second = MigrationQueue::enter(&queue).unwrap();
+        assert!(MigrationQueue::enter(&queue).is_none());
+        let s = queue.lock().unwrap().status();
+        assert_eq!(s.queued, 2);
+        assert_eq!(s.deferred, 1);
+
+        // leaving the queue makes room for another change
+        drop(first);
+        let third = MigrationQueue::enter(&queue).unwrap();
+        drop(second);
+        drop(third);
+        let s = queue.lock().unwrap().status();
+        assert_eq!(s.queued, 0);
+        assert_eq!(s.completed, 3);
+        assert_eq!(s.limit, Some(2));
+    }
+}
@@ -1,6 +1,7 @@
 use dataflow::node;
 use dataflow::ops;
 use dataflow::prelude::*;
+use noria::debug::stats::StateStats;
 use petgraph;
 use petgraph::graph::NodeIndex;
 use slog::Logger;
@@ -13,6 +14,8 @@ pub fn shard(
     new: &mut HashSet<NodeIndex>,
     topo_list: &[NodeIndex],
     sharding_factor: usize,
+    stats: &HashMap<NodeIndex, StateStats>,
+    split_hot_keys: bool,
 ) -> (Vec<NodeIndex>, HashMap<(NodeIndex, NodeIndex), NodeIndex>) {
     // we must keep track of changes we make to the parent of a node, since this remapping must be
     // communicated to the nodes so they know the true identifier of their parent in the graph.
@@ -218,14 +221,34 @@ pub fn shard(
                         }
                     }
 
+                    // sharding by a column with fewer distinct values than there are shards
+                    // leaves some shards with nothing to do, and the rest with more than their
+                    // share.
+                    if ok {
+                        let skewed = want_sharding_input.iter().find(|&(ni, &col)| {
+                            stats
+                                .get(ni)
+                                .and_then(|s| s.distinct(col))
+                                .map(|d| d < sharding_factor as u64)
+                                .unwrap_or(false)
+                        });
+                        if let Some((ni, col)) = skewed {
+                            warn!(log, "not sharding self-lookup node; too few distinct keys";
+                                  "node" => ?node,
+                                  "wants" => want_sharding,
+                                  "input" => ?(ni, col));
+                            ok = false;
+                        }
+                    }
+
                     if ok {
                         // we can shard ourselves and our inputs by a single column!
-                        let s = Sharding::ByColumn(want_sharding, sharding_factor);
+                        let mut s = Sharding::ByColumn(want_sharding, sharding_factor);
                         info!(log, "sharding node doing self-lookup";
                               "node" => ?node,
                               "sharding" => ?s);
 
-                        for (ni, col) in want_sharding_input {
+                        for (&ni, &col) in &want_sharding_input {
                             let need_sharding = Sharding::ByColumn(col, sharding_factor);
                             if input_shardings[&ni] != need_sharding {
                                 // input is sharded by different key -- need shuffle
@@ -234,6 +257,27 @@ pub fn shard(
                             }
                         }
 
+                        // the records of a hot key can only be spread over our shards if every
+                        // input reaches us through a sharder of our own.
+                        let sharders: Option<Vec<_>> = want_sharding_input
+                            .keys()
+                            .map(|&ni| swaps.get(&(node, ni)).cloned())
+                            .collect();
+                        let readers = split_readers(graph, node);
+                        if let (true, Some(sharders), Some(readers)) =
+                            (split_hot_keys, sharders, readers)
+                        {
+                            info!(log, "splitting hot keys of additive node"; "node" => ?node);
+                            for sharder in sharders {
+                                graph[sharder].with_sharder_mut(|s| s.split_hot_keys());
+                            }
+                            for (reader, col) in readers {
+                                graph[reader].with_reader_mut(|r| r.set_split(col)).unwrap();
+                            }
+                            // a key's rows may now be on any of our shards
+                            s = Sharding::Random(sharding_factor);
+                        }
+
                         graph.node_weight_mut(node).unwrap().shard_by(s);
                         continue;
                     }
@@ -623,6 +667,48 @@ pub fn shard(
     (topo_list, swaps)
 }
 
+/// Find the readers below `node`, and the column of each that holds the values of `node`'s
+/// additive column.
+///
+/// If the records of a key are split across the shards of `node`, each shard emits a row with
+/// part of the key's value, and only readers know to add those parts up. So this returns `None`
+/// unless `node` is additive, and all the nodes between it and its readers are projections that
+/// pass the additive column through untouched.
+fn split_readers(graph: &Graph, node: NodeIndex) -> Option<Vec<(NodeIndex, usize)>> {
+    let mut readers = Vec::new();
+    let mut pending = vec![(node, graph[node].additive_column()?)];
+    while let Some((n, col)) = pending.pop() {
+        for child in graph.neighbors_directed(n, petgraph::EdgeDirection::Outgoing) {
+            let c = &graph[child];
+            if c.is_reader() {
+                readers.push((child, col));
+                continue;
+            }
+            if !c.is_internal() {
+                return None;
+            }
+            match **c {
+                ops::NodeOperator::Project(ref p) => {
+                    let (emit, _, expressions) = p.emits();
+                    let uses_col = expressions.iter().any(|e| e.uses_column(col));
+                    let mut passed = emit.iter().enumerate().filter(|&(_, &pc)| pc == col);
+                    match (passed.next(), passed.next()) {
+                        (Some((out, _)), None) if !uses_col => pending.push((child, out)),
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    if readers.is_empty() {
+        None
+    } else {
+        Some(readers)
+    }
+}
+
 /// Modify the graph such that the path between `src` and `dst` shuffles the input such that the
 /// records received by `dst` are sharded by sharding `to`.
 fn reshard(
@@ -747,11 +833,16 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
             if in_node.is_sharder() {
                 // ancestor is a sharder, so its output sharding must match ours
                 in_node.with_sharder(|s| {
-                    let in_sharding = remap(
-                        n,
-                        in_ni,
-                        Sharding::ByColumn(s.sharded_by(), sharding_factor),
-                    );
+                    let in_sharding = if s.splits_hot_keys() {
+                        // the rows of hot keys go to any shard
+                        Sharding::Random(sharding_factor)
+                    } else {
+                        remap(
+                            n,
+                            in_ni,
+                            Sharding::ByColumn(s.sharded_by(), sharding_factor),
+                        )
+                    };
                     if in_sharding != n.sharded_by() {
                         crit!(
                             log,
@@ -76,7 +76,7 @@ fn mir_node_to_flow_parts(
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
 ) -> FlowNode {
-    let name = mir_node.name.clone();
+    let name = mir_node.flow_node_name();
     match mir_node.flow_node {
         None => {
             #[allow(clippy::let_and_return)]
@@ -212,10 +212,16 @@ fn mir_node_to_flow_parts(
                     let parent = mir_node.ancestors[0].clone();
                     make_latest_node(&name, parent, mir_node.columns.as_slice(), group_by, mig)
                 }
-                MirNodeType::Leaf { ref keys, .. } => {
+                MirNodeType::Leaf {
+                    ref keys,
+                    ref order,
+                    limit,
+                    ref echoed,
+                    ..
+                } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
-                    materialize_leaf_node(&parent, name, keys, mig);
+                    materialize_leaf_node(&parent, name, keys, order, limit, echoed, mig);
                     // TODO(malte): below is yucky, but required to satisfy the type system:
                     // each match arm must return a `FlowNode`, so we use the parent's one
                     // here.
@@ -654,42 +660,40 @@ fn make_join_node(
         proj_cols.len()
     );
 
-    assert_eq!(on_left.len(), 1, "no support for multiple column joins");
-    assert_eq!(on_right.len(), 1, "no support for multiple column joins");
-
-    // this assumes the columns we want to join on appear first in the list
-    // of projected columns. this is fine for joins against different tables
-    // since we assume unique column names in each table. however, this is
-    // not correct for joins against the same table, for example:
-    // SELECT r1.a as a1, r2.a as a2 from r as r1, r as r2 where r1.a = r2.b and r2.a = r1.b;
-    //
-    // the `r1.a = r2.b` join predicate will create a join node with columns: r1.a, r1.b, r2.a, r2,b
-    // however, because the way we deal with aliases, we can't distinguish between `r1.a` and `r2.a`
-    // at this point in the codebase, so the `r2.a = r1.b` will join on the wrong `a` column.
-    let left_join_col_id = left
-        .borrow()
-        .columns
+    // join columns are found by name, which relies on the columns of each parent being distinct.
+    // this holds for joins against the same table too, since each aliased instance of a
+    // self-joined table is planned as a relation of its own.
+    let join_col_ids: Vec<(usize, usize)> = on_left
         .iter()
-        .position(|lc| lc == on_left.first().unwrap())
-        .unwrap_or_else(|| {
-            panic!(
-                "missing left-side join column {:#?} in {:#?}",
-                on_left.first().unwrap(),
-                left.borrow().columns
-            )
-        });
-    let right_join_col_id = right
-        .borrow()
-        .columns
-        .iter()
-        .position(|rc| rc == on_right.first().unwrap())
-        .unwrap_or_else(|| {
-            panic!(
-                "missing right-side join column {:#?} in {:#?}",
-                on_left.first().unwrap(),
-                right.borrow().columns
-            )
-        });
+        .zip(on_right)
+        .map(|(l, r)| {
+            let left_id = left
+                .borrow()
+                .columns
+                .iter()
+                .position(|lc| lc == l)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "missing left-side join column {:#?} in {:#?}",
+                        l,
+                        left.borrow().columns
+                    )
+                });
+            let right_id = right
+                .borrow()
+                .columns
+                .iter()
+                .position(|rc| rc == r)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "missing right-side join column {:#?} in {:#?}",
+                        r,
+                        right.borrow().columns
+                    )
+                });
+            (left_id, right_id)
+        })
+        .collect();
 
     let mut from_left = 0;
     let mut from_right = 0;
@@ -699,9 +703,9 @@ fn make_join_node(
         .iter()
         .enumerate()
         .filter_map(|(i, c)| {
-            if i == left_join_col_id {
+            if let Some(&(_, right_id)) = join_col_ids.iter().find(|&&(left_id, _)| left_id == i) {
                 from_left += 1;
-                Some(JoinSource::B(i, right_join_col_id))
+                Some(JoinSource::B(i, right_id))
             } else if projected_cols_left.contains(c) {
                 from_left += 1;
                 Some(JoinSource::L(i))
@@ -918,6 +922,9 @@ fn materialize_leaf_node(
     parent: &MirNodeRef,
     name: String,
     key_cols: &[Column],
+    order: &Option<Vec<(Column, OrderType)>>,
+    limit: Option<usize>,
+    echoed: &[Column],
     mig: &mut Migration,
 ) {
     let na = parent.borrow().flow_node_addr().unwrap();
@@ -939,4 +946,61 @@ fn materialize_leaf_node(
         // if no key specified, default to the first column
         mig.maintain(name, na, &[0]);
     }
+
+    if let Some(ref order) = *order {
+        let order = order
+            .iter()
+            .map(|&(ref c, ref o)| (parent.borrow().column_id_for_column(c, None), o.clone()))
+            .collect();
+        mig.set_reader_order(na, order);
+    }
+    if let Some(limit) = limit {
+        mig.set_reader_limit(na, limit);
+    }
+    if !echoed.is_empty() {
+        let echoed = echoed
+            .iter()
+            .map(|c| parent.borrow().column_id_for_column(c, None))
+            .collect();
+        mig.set_reader_echoed(na, echoed);
+    }
+}
+
+/// Whether `node` only filters and projects the rows of a single base table, and `key_cols` are
+/// that table's primary key, so that reading a key needs nothing but a lookup in the base table.
+pub(super) fn is_straight_through(node: &MirNodeRef, key_cols: &[Column]) -> bool {
+    if key_cols.is_empty() {
+        return false;
+    }
+
+    let mut node = node.clone();
+    let mut key_cols = key_cols.to_vec();
+    loop {
+        let next = {
+            let n = node.borrow();
+            match n.inner {
+                MirNodeType::Base { ref keys, .. } => {
+                    return keys.len() == key_cols.len()
+                        && key_cols
+                            .iter()
+                            .all(|c| keys.iter().any(|k| k.name == c.name));
+                }
+                MirNodeType::Reuse { ref node } => node.clone(),
+                MirNodeType::Filter { .. } | MirNodeType::Identity => n.ancestors()[0].clone(),
+                MirNodeType::Project { ref emit, .. } => {
+                    // key columns must be passed on from the parent, rather than computed
+                    for c in key_cols.iter_mut() {
+                        let i = n.column_id_for_column(c, None);
+                        if i >= emit.len() {
+                            return false;
+                        }
+                        *c = emit[i].clone();
+                    }
+                    n.ancestors()[0].clone()
+                }
+                _ => return false,
+            }
+        };
+        node = next;
+    }
 }
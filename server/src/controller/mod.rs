@@ -1,5 +1,8 @@
+use crate::controller::failure::PhiAccrual;
 use crate::controller::inner::ControllerInner;
+use crate::controller::migrate::progress::MigrationProgress;
 use crate::controller::migrate::Migration;
+use crate::controller::placement::{PlacementPolicy, WorkerLoad};
 use crate::controller::recipe::Recipe;
 use crate::coordination::CoordinationMessage;
 use crate::coordination::CoordinationPayload;
@@ -17,18 +20,20 @@ use hyper::{self, StatusCode};
 use noria::channel::TcpSender;
 use noria::consensus::{Authority, Epoch, STATE_KEY};
 use noria::ControllerDescriptor;
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time;
 use stream_cancel::Valve;
 use tokio::sync::mpsc::UnboundedSender;
 
 mod domain_handle;
+pub(crate) mod failure;
 mod inner;
 mod keys;
 pub(crate) mod migrate; // crate viz for tests
 mod mir_to_flow;
+pub(crate) mod placement;
 pub(crate) mod recipe; // crate viz for tests
 mod schema;
 mod security;
@@ -41,19 +46,50 @@ pub(crate) struct ControllerState {
 
     recipe_version: usize,
     recipes: Vec<String>,
+    /// The security configuration that the recipes were installed with, if any.
+    #[serde(default)]
+    security_config: Option<String>,
+    /// The full text of the most recent recipe versions, so that they can still be rolled back
+    /// to after the controller restarts.
+    #[serde(default)]
+    recipe_history: BTreeMap<usize, String>,
+    /// The queries staged for each generation of the recipe that has not been switched to yet.
+    #[serde(default)]
+    recipe_generations: HashMap<usize, HashMap<String, String>>,
+
+    /// The workers (by listen address) that ran each shard of each domain, as of the last time
+    /// the state was written. A new leader uses this to put domains back where they were.
+    ///
+    /// Note that this is only a placement hint: workers drop their domains when the leader
+    /// changes, so a new leader still rebuilds every domain (from its log, for persisted bases).
+    /// Handing running domains over to the new leader is not supported.
+    #[serde(default)]
+    placement: HashMap<usize, Vec<SocketAddr>>,
 }
 
 struct Worker {
     healthy: bool,
-    last_heartbeat: time::Instant,
+    labels: HashMap<String, String>,
+    detector: PhiAccrual,
+    load: WorkerLoad,
+    /// The protocol version agreed on with the worker when it registered.
+    version: u32,
     sender: TcpSender<CoordinationMessage>,
 }
 
 impl Worker {
-    fn new(sender: TcpSender<CoordinationMessage>) -> Self {
+    fn new(
+        sender: TcpSender<CoordinationMessage>,
+        labels: HashMap<String, String>,
+        detector: PhiAccrual,
+        version: u32,
+    ) -> Self {
         Worker {
             healthy: true,
-            last_heartbeat: time::Instant::now(),
+            labels,
+            detector,
+            load: WorkerLoad::default(),
+            version,
             sender,
         }
     }
@@ -71,6 +107,8 @@ pub(super) async fn main<A: Authority + 'static>(
     log: slog::Logger,
     authority: Arc<A>,
     tx: tokio::sync::mpsc::UnboundedSender<Event>,
+    migration_progress: Arc<Mutex<MigrationProgress>>,
+    placement: Arc<dyn PlacementPolicy>,
 ) {
     let (dtx, drx) = tokio::sync::mpsc::unbounded_channel();
 
@@ -111,7 +149,7 @@ pub(super) async fn main<A: Authority + 'static>(
                         });
                     }
                 }
-                CoordinationPayload::Heartbeat => {
+                CoordinationPayload::Heartbeat(..) => {
                     if let Some(ref mut ctrl) = controller {
                         tokio::task::block_in_place(|| ctrl.handle_heartbeat(msg).unwrap());
                     }
@@ -159,7 +197,13 @@ pub(super) async fn main<A: Authority + 'static>(
                 let c = campaign.take().unwrap();
                 tokio::task::block_in_place(move || c.join().unwrap());
                 let drx = drx.take().unwrap();
-                controller = Some(ControllerInner::new(log.clone(), state, drx));
+                controller = Some(ControllerInner::new(
+                    log.clone(),
+                    state,
+                    drx,
+                    migration_progress.clone(),
+                    placement.clone(),
+                ));
             }
             Event::CampaignError(e) => {
                 panic!("{:?}", e);
@@ -262,6 +306,10 @@ fn instance_campaign<A: Authority + 'static>(
                         epoch,
                         recipe_version: 0,
                         recipes: vec![],
+                        security_config: None,
+                        recipe_history: BTreeMap::new(),
+                        recipe_generations: HashMap::new(),
+                        placement: HashMap::new(),
                     }),
                     Some(ref state) if state.epoch > epoch => Err(()),
                     Some(mut state) => {
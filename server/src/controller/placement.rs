@@ -0,0 +1,330 @@
+use noria::debug::stats::DomainQueue;
+use slog::Logger;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Resource usage a worker reports to the controller along with each heartbeat.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkerLoad {
+    /// One-minute load average of the worker's host.
+    pub cpu: f64,
+    /// Estimated number of bytes held in the state of the domains the worker runs.
+    pub memory: u64,
+    /// Resident memory of the worker's process, in bytes.
+    pub rss: u64,
+    /// Free space on the disk that base tables are persisted to, in bytes, if it is known.
+    pub disk_free: Option<u64>,
+    /// How many packets each domain shard on the worker has yet to deal with.
+    pub queues: Vec<DomainQueue>,
+    /// Rows written to bases on the worker that have yet to be applied to the standby deployment.
+    #[serde(default)]
+    pub standby_lag: u64,
+}
+
+/// What the controller knows about a healthy worker when it has to place a domain shard.
+#[derive(Clone, Debug)]
+pub struct WorkerCandidate {
+    /// The address the worker registered from.
+    pub addr: SocketAddr,
+    /// The labels the worker registered with.
+    pub labels: HashMap<String, String>,
+    /// The load the worker most recently reported.
+    pub load: WorkerLoad,
+    /// Number of domain shards currently assigned to the worker, including any placed earlier in
+    /// the same migration.
+    pub domains: usize,
+    /// The number of those domain shards that are of each kind.
+    pub kinds: HashMap<DomainKind, usize>,
+}
+
+/// The role a domain plays in the data-flow graph, as far as placement is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DomainKind {
+    /// The domain holds a base table.
+    Base,
+    /// The domain holds a view that clients read from.
+    Reader,
+    /// The domain only holds internal operators.
+    Internal,
+}
+
+/// A domain shard that needs to be placed on a worker.
+#[derive(Clone, Debug)]
+pub struct DomainPlacement {
+    /// The index of the domain.
+    pub domain: usize,
+    /// The shard of the domain being placed.
+    pub shard: usize,
+    /// The role of the domain.
+    pub kind: DomainKind,
+}
+
+/// Restricts which workers the controller may place domains on, based on worker labels.
+///
+/// Constraints are applied before the `PlacementPolicy` is consulted. If no worker satisfies a
+/// constraint, the constraint is ignored for that placement, and a warning is logged.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PlacementConstraint {
+    /// Only place domains of the given kind (or all domains, if `None`) on workers whose label
+    /// `label` is set to `value`.
+    Require {
+        /// The kind of domain the constraint applies to.
+        kind: Option<DomainKind>,
+        /// The label to look at.
+        label: String,
+        /// The value the label must have.
+        value: String,
+    },
+    /// Spread domains of the given kind (or all domains, if `None`) as evenly as possible across
+    /// the distinct values of the worker label `label`.
+    Spread {
+        /// The kind of domain the constraint applies to.
+        kind: Option<DomainKind>,
+        /// The label whose values the domains should be spread across.
+        label: String,
+    },
+}
+
+impl PlacementConstraint {
+    fn applies_to(&self, domain: &DomainPlacement) -> bool {
+        let kind = match *self {
+            PlacementConstraint::Require { kind, .. }
+            | PlacementConstraint::Spread { kind, .. } => kind,
+        };
+        kind.map(|k| k == domain.kind).unwrap_or(true)
+    }
+
+    /// Narrow down `eligible` (indices into `workers`) to the workers that satisfy the constraint.
+    fn narrow(&self, workers: &[WorkerCandidate], eligible: &[usize]) -> Vec<usize> {
+        match *self {
+            PlacementConstraint::Require {
+                ref label,
+                ref value,
+                ..
+            } => eligible
+                .iter()
+                .cloned()
+                .filter(|&i| workers[i].labels.get(label) == Some(value))
+                .collect(),
+            PlacementConstraint::Spread { kind, ref label } => {
+                // how many matching domains are already placed for each value of the label?
+                let mut placed: HashMap<Option<&String>, usize> = HashMap::new();
+                for w in workers {
+                    let n = match kind {
+                        Some(kind) => w.kinds.get(&kind).cloned().unwrap_or(0),
+                        None => w.domains,
+                    };
+                    *placed.entry(w.labels.get(label)).or_insert(0) += n;
+                }
+
+                let fewest = eligible
+                    .iter()
+                    .map(|&i| placed[&workers[i].labels.get(label)])
+                    .min();
+                eligible
+                    .iter()
+                    .cloned()
+                    .filter(|&i| Some(placed[&workers[i].labels.get(label)]) == fewest)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Determine which of `workers` `domain` may be placed on given `constraints`.
+///
+/// Returns indices into `workers`, and is never empty as long as `workers` is not.
+pub(crate) fn eligible_workers(
+    constraints: &[PlacementConstraint],
+    domain: &DomainPlacement,
+    workers: &[WorkerCandidate],
+    log: &Logger,
+) -> Vec<usize> {
+    let mut eligible: Vec<_> = (0..workers.len()).collect();
+    for c in constraints.iter().filter(|c| c.applies_to(domain)) {
+        let narrowed = c.narrow(workers, &eligible);
+        if narrowed.is_empty() {
+            warn!(log, "no worker satisfies placement constraint {:?}", c;
+                  "domain" => domain.domain, "shard" => domain.shard);
+        } else {
+            eligible = narrowed;
+        }
+    }
+    eligible
+}
+
+/// Decides which worker each new domain shard is assigned to.
+///
+/// Operators can plug in their own strategy with `Builder::set_placement_policy`.
+pub trait PlacementPolicy: Send + Sync {
+    /// Choose a worker for the given domain shard.
+    ///
+    /// `workers` contains every healthy worker that satisfies the configured
+    /// `PlacementConstraint`s, ordered by address, and is never empty. The returned value is an
+    /// index into `workers`.
+    fn place(&self, domain: &DomainPlacement, workers: &[WorkerCandidate]) -> usize;
+}
+
+/// Assigns domain shards to workers in turn, ignoring their load.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl PlacementPolicy for RoundRobin {
+    fn place(&self, _: &DomainPlacement, workers: &[WorkerCandidate]) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % workers.len()
+    }
+}
+
+/// Assigns each domain shard to the least loaded worker.
+///
+/// A worker's load is the sum of its CPU load, its state size, and its number of domains, each
+/// relative to the largest such value among all the workers. Ties go to the first worker.
+#[derive(Debug, Default)]
+pub struct LeastLoaded;
+
+impl PlacementPolicy for LeastLoaded {
+    fn place(&self, _: &DomainPlacement, workers: &[WorkerCandidate]) -> usize {
+        let max_cpu = workers.iter().map(|w| w.load.cpu).fold(0.0, f64::max);
+        let max_memory = workers.iter().map(|w| w.load.memory).max().unwrap_or(0);
+        let max_domains = workers.iter().map(|w| w.domains).max().unwrap_or(0);
+
+        let relative = |v: f64, max: f64| if max > 0.0 { v / max } else { 0.0 };
+        let score = |w: &WorkerCandidate| {
+            relative(w.load.cpu, max_cpu)
+                + relative(w.load.memory as f64, max_memory as f64)
+                + relative(w.domains as f64, max_domains as f64)
+        };
+
+        let mut best = 0;
+        let mut best_score = score(&workers[0]);
+        for (i, w) in workers.iter().enumerate().skip(1) {
+            let s = score(w);
+            if s < best_score {
+                best = i;
+                best_score = s;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(port: u16, cpu: f64, memory: u64, domains: usize) -> WorkerCandidate {
+        WorkerCandidate {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            labels: HashMap::new(),
+            load: WorkerLoad {
+                cpu,
+                memory,
+                ..Default::default()
+            },
+            domains,
+            kinds: HashMap::new(),
+        }
+    }
+
+    fn labeled(port: u16, labels: &[(&str, &str)], readers: usize) -> WorkerCandidate {
+        let mut c = candidate(port, 0.0, 0, readers);
+        c.labels = labels
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        c.kinds.insert(DomainKind::Reader, readers);
+        c
+    }
+
+    fn domain(kind: DomainKind) -> DomainPlacement {
+        DomainPlacement {
+            domain: 0,
+            shard: 0,
+            kind,
+        }
+    }
+
+    #[test]
+    fn it_round_robins() {
+        let workers = vec![candidate(1, 0.0, 0, 0), candidate(2, 0.0, 0, 0)];
+        let rr = RoundRobin::default();
+        let d = domain(DomainKind::Internal);
+        assert_eq!(rr.place(&d, &workers), 0);
+        assert_eq!(rr.place(&d, &workers), 1);
+        assert_eq!(rr.place(&d, &workers), 0);
+    }
+
+    #[test]
+    fn it_prefers_least_loaded() {
+        let ll = LeastLoaded;
+        let d = domain(DomainKind::Internal);
+
+        // idle workers are filled up evenly
+        let workers = vec![candidate(1, 0.0, 0, 1), candidate(2, 0.0, 0, 0)];
+        assert_eq!(ll.place(&d, &workers), 1);
+
+        // a worker with much more state is avoided even if it has fewer domains
+        let workers = vec![candidate(1, 0.1, 1 << 30, 1), candidate(2, 0.1, 1 << 10, 2)];
+        assert_eq!(ll.place(&d, &workers), 1);
+
+        // as is a busy one
+        let workers = vec![candidate(1, 0.9, 0, 1), candidate(2, 0.1, 0, 1)];
+        assert_eq!(ll.place(&d, &workers), 1);
+    }
+
+    #[test]
+    fn it_applies_constraints() {
+        let log = slog::Logger::root(slog::Discard, o!());
+        let workers = vec![
+            labeled(1, &[("zone", "a"), ("ssd", "yes")], 1),
+            labeled(2, &[("zone", "a")], 0),
+            labeled(3, &[("zone", "b")], 1),
+            labeled(4, &[("zone", "c"), ("ssd", "yes")], 0),
+        ];
+        let ssd = PlacementConstraint::Require {
+            kind: Some(DomainKind::Base),
+            label: "ssd".to_owned(),
+            value: "yes".to_owned(),
+        };
+        let spread = PlacementConstraint::Spread {
+            kind: Some(DomainKind::Reader),
+            label: "zone".to_owned(),
+        };
+        let constraints = vec![ssd, spread];
+
+        // base domains only go to ssd workers
+        let base = domain(DomainKind::Base);
+        assert_eq!(
+            eligible_workers(&constraints, &base, &workers, &log),
+            vec![0, 3]
+        );
+
+        // readers go to the zone with the fewest readers
+        let reader = domain(DomainKind::Reader);
+        assert_eq!(
+            eligible_workers(&constraints, &reader, &workers, &log),
+            vec![3]
+        );
+
+        // other domains can go anywhere
+        let internal = domain(DomainKind::Internal);
+        assert_eq!(
+            eligible_workers(&constraints, &internal, &workers, &log),
+            vec![0, 1, 2, 3]
+        );
+
+        // unsatisfiable constraints are ignored
+        let gpu = PlacementConstraint::Require {
+            kind: None,
+            label: "gpu".to_owned(),
+            value: "yes".to_owned(),
+        };
+        assert_eq!(
+            eligible_workers(&[gpu], &internal, &workers, &log),
+            vec![0, 1, 2, 3]
+        );
+    }
+}
@@ -1,19 +1,28 @@
 use crate::controller::security::SecurityConfig;
-use crate::controller::sql::SqlIncorporator;
+use crate::controller::sql::{SqlIncorporator, LIMIT_PARAMETER};
 use crate::controller::Migration;
 use crate::ReuseConfigType;
+use ::mir::query::QueryFlowParts;
 use dataflow::ops::trigger::Trigger;
 use dataflow::ops::trigger::TriggerEvent;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
-use nom_sql::SqlQuery;
+use nom_sql::{
+    CaseWhenExpression, Column, ColumnOrLiteral, ColumnSpecification, CompoundSelectOperator,
+    CompoundSelectStatement, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, FieldValueExpression, FunctionArguments, GroupByClause,
+    JoinConstraint, Literal, LiteralExpression, Operator, SelectStatement, SqlQuery, SqlType,
+};
 use noria::ActivationResult;
 use petgraph::graph::NodeIndex;
 
 use nom_sql::CreateTableStatement;
 use slog;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::time::Duration;
 use std::vec::Vec;
 
 type QueryID = u64;
@@ -28,6 +37,15 @@ pub(crate) struct Recipe {
     expression_order: Vec<QueryID>,
     /// Named read/write expression aliases, mapping to queries in `expressions`.
     aliases: HashMap<String, QueryID>,
+    /// Row expiry settings for base tables, by table name.
+    table_options: HashMap<String, TableOptions>,
+    /// Options for queries, by query name.
+    view_options: HashMap<String, ViewOptions>,
+    /// Lazy views that have not been read yet, and so have no nodes in the graph.
+    deferred: HashSet<QueryID>,
+    /// The queries staged for each generation, as a map from the name that each query takes over
+    /// when the generation is switched to to the name it is staged under.
+    generations: HashMap<usize, HashMap<String, String>>,
     /// Security configuration
     security_config: Option<SecurityConfig>,
 
@@ -50,11 +68,1193 @@ impl PartialEq for Recipe {
         self.expressions == other.expressions
             && self.expression_order == other.expression_order
             && self.aliases == other.aliases
+            && self.table_options == other.table_options
+            && self.view_options == other.view_options
+            && self.generations == other.generations
             && self.version == other.version
             && self.prior == other.prior
     }
 }
 
+/// Options for a base table, given as `WITH (...)` after its `CREATE TABLE` statement.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct TableOptions {
+    ttl: Option<TableTtl>,
+    /// The column that holds each row's version, given as `version_column = '...'`.
+    version_column: Option<String>,
+    /// Whether the table has `_created_at` and `_updated_at` columns that the base fills in, given
+    /// as `timestamps = 'true'`.
+    timestamps: bool,
+    /// Whether deletes only mark rows as deleted in a `_deleted` column, given as
+    /// `soft_delete = 'true'`.
+    soft_delete: bool,
+}
+
+/// The columns that `timestamps = 'true'` adds to a table.
+const CREATED_AT: &str = "_created_at";
+const UPDATED_AT: &str = "_updated_at";
+/// The column that `soft_delete = 'true'` adds to a table.
+const DELETED: &str = "_deleted";
+
+/// Row expiry for a base table, given as `ttl = '7 days'` in its table options. Rows expire by
+/// the column named with `ttl_column = '...'`, or by the table's first `TIMESTAMP` column if none
+/// is named.
+#[derive(Clone, Debug, PartialEq)]
+struct TableTtl {
+    ttl: Duration,
+    /// the ttl as written, for rendering the table back into recipe text
+    text: String,
+    column: Option<String>,
+}
+
+impl TableOptions {
+    fn parse(options: &str) -> Result<TableOptions, String> {
+        let mut ttl = None;
+        let mut ttl_column = None;
+        let mut version_column = None;
+        let mut timestamps = false;
+        let mut soft_delete = false;
+        for option in options.split(',') {
+            let mut kv = option.splitn(2, '=');
+            let key = kv.next().unwrap().trim().to_ascii_lowercase();
+            let value = match kv.next() {
+                Some(v) => v.trim().trim_matches(|c| c == '\'' || c == '"').to_owned(),
+                None => return Err(format!("table option \"{}\" has no value", option.trim())),
+            };
+            match &*key {
+                "ttl" => ttl = Some(value),
+                "ttl_column" => ttl_column = Some(value),
+                "version_column" => version_column = Some(value),
+                "timestamps" => timestamps = parse_flag(&key, &value)?,
+                "soft_delete" => soft_delete = parse_flag(&key, &value)?,
+                _ => return Err(format!("unknown table option \"{}\"", key)),
+            }
+        }
+
+        let ttl = match ttl {
+            Some(text) => Some(TableTtl {
+                ttl: parse_duration(&text)?,
+                text,
+                column: ttl_column,
+            }),
+            None if ttl_column.is_some() => {
+                return Err("table option \"ttl_column\" requires a ttl".to_owned())
+            }
+            None => None,
+        };
+        Ok(TableOptions {
+            ttl,
+            version_column,
+            timestamps,
+            soft_delete,
+        })
+    }
+
+    fn render(&self) -> String {
+        let mut options = Vec::new();
+        if let Some(ref ttl) = self.ttl {
+            options.push(format!("ttl = '{}'", ttl.text));
+            if let Some(ref c) = ttl.column {
+                options.push(format!("ttl_column = '{}'", c));
+            }
+        }
+        if let Some(ref c) = self.version_column {
+            options.push(format!("version_column = '{}'", c));
+        }
+        if self.timestamps {
+            options.push("timestamps = 'true'".to_owned());
+        }
+        if self.soft_delete {
+            options.push("soft_delete = 'true'".to_owned());
+        }
+        format!("WITH ({})", options.join(", "))
+    }
+}
+
+/// Options for a query, given as `WITH (...)` after a named `QUERY`.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ViewOptions {
+    /// Whether the query is computed when it is read, and its results cached by its reader until
+    /// writes to the tables below it evict them, given as `cache = 'true'`.
+    cache: bool,
+    /// Whether the query's nodes are only added to the graph when it is first read, given as
+    /// `lazy = 'true'`.
+    lazy: bool,
+    /// Whether the query's reader only holds on to keys until they have been read, given as
+    /// `straight_through = 'true'`. Only views that filter and project a base table by its primary
+    /// key can be straight-through.
+    straight_through: bool,
+}
+
+impl ViewOptions {
+    fn parse(options: &str) -> Result<ViewOptions, String> {
+        let mut cache = false;
+        let mut lazy = false;
+        let mut straight_through = false;
+        for option in options.split(',') {
+            let mut kv = option.splitn(2, '=');
+            let key = kv.next().unwrap().trim().to_ascii_lowercase();
+            let value = match kv.next() {
+                Some(v) => v.trim().trim_matches(|c| c == '\'' || c == '"').to_owned(),
+                None => return Err(format!("view option \"{}\" has no value", option.trim())),
+            };
+            match &*key {
+                "cache" => cache = parse_flag(&key, &value)?,
+                "lazy" => lazy = parse_flag(&key, &value)?,
+                "straight_through" => straight_through = parse_flag(&key, &value)?,
+                _ => return Err(format!("unknown view option \"{}\"", key)),
+            }
+        }
+        if cache && straight_through {
+            return Err("a view cannot be both cached and straight-through".to_owned());
+        }
+        Ok(ViewOptions {
+            cache,
+            lazy,
+            straight_through,
+        })
+    }
+
+    fn render(&self) -> String {
+        let mut options = Vec::new();
+        if self.cache {
+            options.push("cache = 'true'".to_owned());
+        }
+        if self.lazy {
+            options.push("lazy = 'true'".to_owned());
+        }
+        if self.straight_through {
+            options.push("straight_through = 'true'".to_owned());
+        }
+        format!("WITH ({})", options.join(", "))
+    }
+}
+
+/// Parse on/off table options.
+fn parse_flag(key: &str, value: &str) -> Result<bool, String> {
+    match &*value.to_ascii_lowercase() {
+        "true" | "on" => Ok(true),
+        "false" | "off" => Ok(false),
+        _ => Err(format!("invalid {} option \"{}\"", key, value)),
+    }
+}
+
+/// Parse durations like `30 seconds`, `1 hour`, or `7 days`.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| text.len());
+    let n: u64 = text[..split]
+        .parse()
+        .map_err(|_| format!("invalid duration \"{}\"", text))?;
+    let secs = match text[split..]
+        .trim()
+        .to_ascii_lowercase()
+        .trim_end_matches('s')
+    {
+        "" | "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration \"{}\"", text)),
+    };
+    Ok(Duration::from_secs(n * secs))
+}
+
+/// A statement of a recipe, without its comments.
+struct Statement {
+    text: String,
+    /// The line and column in the recipe of each byte of `text`.
+    positions: Vec<(usize, usize)>,
+}
+
+impl Statement {
+    /// The line and column in the recipe of byte `offset` of `rewritten`, which is the statement's
+    /// text after it was rewritten for nom-sql. Offsets within a rewritten part of the statement
+    /// are placed at the start of the first rewritten part.
+    fn position(&self, rewritten: &str, offset: usize) -> (usize, usize) {
+        let (text, rewritten) = (self.text.as_bytes(), rewritten.as_bytes());
+        let prefix = text
+            .iter()
+            .zip(rewritten)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = text
+            .iter()
+            .rev()
+            .zip(rewritten.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let at = if offset <= prefix {
+            offset
+        } else if rewritten.len() - offset <= suffix {
+            text.len() - (rewritten.len() - offset)
+        } else {
+            prefix
+        };
+        self.positions
+            .get(at)
+            .or_else(|| self.positions.last())
+            .cloned()
+            .unwrap_or((1, 1))
+    }
+}
+
+/// Split a recipe into its statements, dropping `#`, `--` and `/* */` comments along the way.
+///
+/// A statement ends with the line that ends in a semicolon, or with the end of the recipe. The
+/// lines of a statement are joined by spaces.
+fn split_statements(recipe_text: &str) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    let mut text = String::new();
+    let mut positions = Vec::new();
+    let mut finish = |text: &mut String, positions: &mut Vec<(usize, usize)>| {
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len();
+        if start < end {
+            statements.push(Statement {
+                text: text[start..end].to_owned(),
+                positions: positions[start..end].to_vec(),
+            });
+        }
+        text.clear();
+        positions.clear();
+    };
+
+    let (mut line, mut column) = (1, 0);
+    let mut quote = None;
+    let mut line_comment = false;
+    let mut block_comment = false;
+    let mut chars = recipe_text.chars().peekable();
+    while let Some(c) = chars.next() {
+        column += 1;
+        if c == '\n' {
+            line_comment = false;
+            if !block_comment {
+                if quote.is_none() && text.trim_end().ends_with(';') {
+                    finish(&mut text, &mut positions);
+                } else {
+                    text.push(' ');
+                    positions.push((line, column));
+                }
+            }
+            line += 1;
+            column = 0;
+            continue;
+        }
+
+        if line_comment {
+            continue;
+        }
+        if block_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                column += 1;
+                block_comment = false;
+            }
+            continue;
+        }
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None if c == '#' => {
+                line_comment = true;
+                continue;
+            }
+            None if c == '-' && chars.peek() == Some(&'-') => {
+                line_comment = true;
+                continue;
+            }
+            None if c == '/' && chars.peek() == Some(&'*') => {
+                chars.next();
+                column += 1;
+                block_comment = true;
+                continue;
+            }
+            None => {}
+        }
+        text.push(c);
+        positions.extend(std::iter::repeat((line, column)).take(c.len_utf8()));
+    }
+    finish(&mut text, &mut positions);
+    statements
+}
+
+/// The file that `stmt` includes, if it is an `INCLUDE 'file'` directive.
+fn include_path(stmt: &str) -> Option<&str> {
+    if !keyword_at(stmt, 0, "include") {
+        return None;
+    }
+    let path = stmt["include".len()..]
+        .trim()
+        .trim_end_matches(';')
+        .trim_end();
+    let quoted = |q: char| path.len() >= 2 && path.starts_with(q) && path.ends_with(q);
+    if quoted('\'') || quoted('"') {
+        Some(&path[1..path.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Replace the `INCLUDE 'file'` directives of a recipe with the statements of the files they
+/// name, which are resolved relative to `dir`. Files resolve their own includes relative to the
+/// directory they are in. Includes are only allowed if a `dir` is given, and may only name
+/// relative paths to files inside it.
+///
+/// Each file is checked for parse errors on its own, so that errors are reported with the file
+/// and line they are at. Since the files are not the client's own, their contents are not quoted
+/// in errors. A recipe without includes is returned as is.
+pub(in crate::controller) fn expand_includes(
+    recipe_text: &str,
+    dir: Option<&Path>,
+) -> Result<String, String> {
+    fn expand(
+        recipe_text: &str,
+        dir: &Path,
+        root: &Path,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<String, String> {
+        let statements = split_statements(recipe_text);
+        let is_file = !including.is_empty();
+        if is_file {
+            for s in statements
+                .iter()
+                .filter(|s| include_path(&s.text).is_none())
+            {
+                if Recipe::parse_statements(std::iter::once(s)).is_err() {
+                    let (line, column) = s.positions[0];
+                    return Err(format!(
+                        "line {}, column {}: failed to parse statement",
+                        line, column
+                    ));
+                }
+            }
+        } else {
+            Recipe::parse_statements(
+                statements
+                    .iter()
+                    .filter(|s| include_path(&s.text).is_none()),
+            )?;
+        }
+
+        let mut expanded = Vec::with_capacity(statements.len());
+        for s in &statements {
+            let (line, _) = s.positions[0];
+            let path = match include_path(&s.text) {
+                Some(path) if Path::new(path).is_absolute() => {
+                    return Err(format!(
+                        "line {}: cannot include {}: included paths must be relative",
+                        line, path
+                    ));
+                }
+                Some(path) => dir.join(path),
+                None => {
+                    // statements are joined by line breaks, so each must end its line
+                    if s.text.ends_with(';') {
+                        expanded.push(s.text.clone());
+                    } else {
+                        expanded.push(format!("{};", s.text));
+                    }
+                    continue;
+                }
+            };
+            let canonical = fs::canonicalize(&path).map_err(|e| {
+                format!("line {}: failed to include {}: {}", line, path.display(), e)
+            })?;
+            if !canonical.starts_with(root) {
+                return Err(format!(
+                    "line {}: cannot include {}: it is outside the recipe directory",
+                    line,
+                    path.display()
+                ));
+            }
+            let text = fs::read_to_string(&canonical).map_err(|e| {
+                format!("line {}: failed to include {}: {}", line, path.display(), e)
+            })?;
+            if including.contains(&canonical) {
+                return Err(format!("line {}: {} includes itself", line, path.display()));
+            }
+
+            let dir = canonical.parent().unwrap_or(root).to_owned();
+            including.push(canonical);
+            let included = expand(&text, &dir, root, including)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            including.pop();
+            expanded.push(included);
+        }
+        Ok(expanded.join("\n"))
+    }
+
+    if split_statements(recipe_text)
+        .iter()
+        .all(|s| include_path(&s.text).is_none())
+    {
+        return Ok(recipe_text.to_owned());
+    }
+    let dir = dir.ok_or_else(|| {
+        "INCLUDE is disabled; the controller must be given a recipe directory to resolve it in"
+            .to_owned()
+    })?;
+    let root = fs::canonicalize(dir)
+        .map_err(|e| format!("failed to open recipe directory {}: {}", dir.display(), e))?;
+    expand(recipe_text, &root, &root, &mut Vec::new())
+}
+
+/// Split `WITH (...)` table or view options off the end of a statement, since nom-sql does not
+/// parse them.
+fn split_options(q: &str) -> (String, Option<String>) {
+    let stmt = q.trim_end().trim_end_matches(';').trim_end();
+    if !stmt.ends_with(')') {
+        return (q.to_owned(), None);
+    }
+
+    // find the parenthesis that opens the trailing group
+    let mut depth = 0;
+    let mut open = None;
+    for (i, c) in stmt.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    open = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let open = match open {
+        Some(open) => open,
+        None => return (q.to_owned(), None),
+    };
+
+    let before = stmt[..open].trim_end();
+    if before.len() < 4 || !before[before.len() - 4..].eq_ignore_ascii_case("with") {
+        return (q.to_owned(), None);
+    }
+    let before = &before[..before.len() - 4];
+    if !before.ends_with(|c: char| c.is_whitespace() || c == ')') {
+        return (q.to_owned(), None);
+    }
+
+    let options = stmt[open + 1..stmt.len() - 1].to_owned();
+    (format!("{};", before.trim_end()), Some(options))
+}
+
+/// Replace the `?` of `LIMIT ?` and `OFFSET ?` with `LIMIT_PARAMETER`, since nom-sql only parses
+/// literal limits.
+fn replace_limit_parameters(q: &str) -> String {
+    let mut out = String::with_capacity(q.len());
+    let mut quote = None;
+    for c in q.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None if c == '?' => {
+                let before = out.trim_end();
+                let word = &before[before
+                    .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .map(|i| i + 1)
+                    .unwrap_or(0)..];
+                if word.eq_ignore_ascii_case("limit") || word.eq_ignore_ascii_case("offset") {
+                    out.push_str(&LIMIT_PARAMETER.to_string());
+                    continue;
+                }
+            }
+            None => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// The characters of `q` that are not inside quotes, along with their byte offsets.
+fn unquoted_chars(q: &str) -> Vec<(usize, char)> {
+    let mut chars = Vec::new();
+    let mut quote = None;
+    for (i, c) in q.char_indices() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None => chars.push((i, c)),
+        }
+    }
+    chars
+}
+
+/// Whether `q` has the keyword `word` at byte offset `at`.
+fn keyword_at(q: &str, at: usize, word: &str) -> bool {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    q.get(at..at + word.len())
+        .map(|w| w.eq_ignore_ascii_case(word))
+        .unwrap_or(false)
+        && !q[..at].chars().next_back().map(is_ident).unwrap_or(false)
+        && !q[at + word.len()..]
+            .chars()
+            .next()
+            .map(is_ident)
+            .unwrap_or(false)
+}
+
+/// A token of a statement. Quoted strings and identifiers are kept whole, so that what they
+/// contain is never mistaken for syntax.
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    /// The byte offset of the token in the statement.
+    at: usize,
+    text: &'a str,
+    quoted: bool,
+}
+
+impl<'a> Token<'a> {
+    /// Whether this is the unquoted keyword or symbol `s`.
+    fn is(&self, s: &str) -> bool {
+        !self.quoted && self.text.eq_ignore_ascii_case(s)
+    }
+
+    fn end(&self) -> usize {
+        self.at + self.text.len()
+    }
+}
+
+fn quoted(input: &str) -> nom::IResult<&str, &str> {
+    use nom::branch::alt;
+    use nom::bytes::complete::{is_not, tag};
+    use nom::character::complete::{anychar, char};
+    use nom::combinator::recognize;
+    use nom::multi::many0;
+    use nom::sequence::{delimited, preceded};
+    let escaped = || recognize(preceded(char('\\'), anychar));
+    alt((
+        recognize(delimited(
+            char('\''),
+            many0(alt((is_not("'\\"), escaped(), tag("''")))),
+            char('\''),
+        )),
+        recognize(delimited(
+            char('"'),
+            many0(alt((is_not("\"\\"), escaped(), tag("\"\"")))),
+            char('"'),
+        )),
+        recognize(delimited(
+            char('`'),
+            many0(alt((is_not("`"), tag("``")))),
+            char('`'),
+        )),
+    ))(input)
+}
+
+fn token(input: &str) -> nom::IResult<&str, (&str, bool)> {
+    use nom::branch::alt;
+    use nom::bytes::complete::{tag, take_while1};
+    use nom::character::complete::anychar;
+    use nom::combinator::{map, recognize};
+    alt((
+        map(quoted, |t| (t, true)),
+        map(
+            alt((
+                take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+                tag("!="),
+                tag("<>"),
+                tag("<="),
+                tag(">="),
+                recognize(anychar),
+            )),
+            |t| (t, false),
+        ),
+    ))(input)
+}
+
+/// The tokens of `q`. Tokenizing stops at an unterminated quote, which nom-sql reports.
+fn tokenize(q: &str) -> Vec<Token> {
+    use nom::character::complete::multispace0;
+    let mut tokens = Vec::new();
+    let mut rest = q;
+    loop {
+        rest = match multispace0::<_, (&str, nom::error::ErrorKind)>(rest) {
+            Ok((rest, _)) => rest,
+            Err(_) => break,
+        };
+        let at = q.len() - rest.len();
+        match token(rest) {
+            Ok((r, (text, quoted))) => {
+                tokens.push(Token { at, text, quoted });
+                rest = r;
+            }
+            Err(_) => break,
+        }
+    }
+    tokens
+}
+
+/// The index in `tokens` of the parenthesis that closes the one at index `open`.
+fn closing_token(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (k, t) in tokens.iter().enumerate().skip(open) {
+        if t.is("(") {
+            depth += 1;
+        } else if t.is(")") {
+            depth -= 1;
+            if depth == 0 {
+                return Some(k);
+            }
+        }
+    }
+    None
+}
+
+/// Parse a condition with nom-sql, as the `WHERE` clause of a query.
+fn parse_condition(cond: &str) -> Result<ConditionExpression, String> {
+    match sql_parser::parse_query(&format!("SELECT * FROM t WHERE {}", cond)) {
+        Ok(SqlQuery::Select(SelectStatement {
+            where_clause: Some(c),
+            group_by: None,
+            order: None,
+            limit: None,
+            ..
+        })) => Ok(c),
+        _ => Err(format!("failed to parse condition \"{}\"", cond)),
+    }
+}
+
+/// A `FILTER (WHERE condition)` clause on the aggregate in field `field` of a `SELECT`.
+#[derive(Clone, Debug)]
+struct FilterClause {
+    field: usize,
+    condition: String,
+}
+
+/// Cut the `FILTER (WHERE cond)` clauses that follow aggregates in the field list of `q`, since
+/// nom-sql does not parse them. Once the rest of the query is parsed, `apply_filter_clauses` puts
+/// them back. Clauses anywhere else are left for nom-sql to reject.
+fn split_filter_clauses(q: &str) -> (String, Vec<FilterClause>) {
+    let tokens = tokenize(q);
+    let select = match tokens.iter().position(|t| t.is("select")) {
+        Some(k) => k,
+        None => return (q.to_owned(), Vec::new()),
+    };
+
+    let mut rest = String::with_capacity(q.len());
+    let mut copied = 0;
+    let mut filters = Vec::new();
+    let (mut field, mut depth) = (0, 0);
+    let mut k = select + 1;
+    while k < tokens.len() {
+        let t = tokens[k];
+        if t.is("(") {
+            depth += 1;
+        } else if t.is(")") {
+            depth -= 1;
+        } else if depth == 0 && t.is(",") {
+            field += 1;
+        } else if depth == 0 && t.is("from") {
+            break;
+        } else if depth == 0
+            && t.is("filter")
+            && tokens[k - 1].is(")")
+            && tokens.get(k + 1).map(|t| t.is("(")).unwrap_or(false)
+            && tokens.get(k + 2).map(|t| t.is("where")).unwrap_or(false)
+        {
+            if let Some(close) = closing_token(&tokens, k + 1) {
+                filters.push(FilterClause {
+                    field,
+                    condition: q[tokens[k + 2].end()..tokens[close].at].trim().to_owned(),
+                });
+                rest.push_str(&q[copied..tokens[k - 1].end()]);
+                copied = tokens[close].end();
+                k = close + 1;
+                continue;
+            }
+        }
+        k += 1;
+    }
+    rest.push_str(&q[copied..]);
+    (rest, filters)
+}
+
+/// Turn each filtered aggregate `AGG(args) FILTER (WHERE cond)` of `q` into
+/// `AGG(CASE WHEN cond THEN args END)`, which is planned as a filtered aggregation. `COUNT(*)`
+/// counts `CASE WHEN cond THEN 1 END`.
+fn apply_filter_clauses(q: &mut SqlQuery, filters: Vec<FilterClause>) -> Result<(), String> {
+    use nom_sql::FunctionExpression::*;
+
+    let sq = match *q {
+        SqlQuery::Select(ref mut sq) => sq,
+        _ => return Err("FILTER is only supported on aggregates of a SELECT".to_owned()),
+    };
+    for filter in filters {
+        let condition = parse_condition(&filter.condition)?;
+        let f = match sq.fields.get_mut(filter.field) {
+            Some(&mut FieldDefinitionExpression::Col(Column {
+                function: Some(ref mut f),
+                ..
+            })) => f,
+            _ => return Err("FILTER must follow an aggregate function".to_owned()),
+        };
+        let case = |then_expr| {
+            FunctionArguments::Conditional(CaseWhenExpression {
+                condition: condition.clone(),
+                then_expr,
+                else_expr: None,
+            })
+        };
+        let then = |arg: &FunctionArguments| match *arg {
+            FunctionArguments::Column(ref c) => Ok(case(ColumnOrLiteral::Column(c.clone()))),
+            FunctionArguments::Conditional(_) => {
+                Err("FILTER is not supported on aggregations over CASE WHEN".to_owned())
+            }
+        };
+        **f = match **f {
+            CountStar => Count(case(ColumnOrLiteral::Literal(Literal::Integer(1))), false),
+            Count(ref arg, distinct) => Count(then(arg)?, distinct),
+            Sum(ref arg, distinct) => Sum(then(arg)?, distinct),
+            Avg(ref arg, distinct) => Avg(then(arg)?, distinct),
+            Min(ref arg) => Min(then(arg)?),
+            Max(ref arg) => Max(then(arg)?),
+            GroupConcat(ref arg, ref separator) => GroupConcat(then(arg)?, separator.clone()),
+            ref f => return Err(format!("FILTER is not supported on {}", f)),
+        };
+    }
+    Ok(())
+}
+
+/// The comma-separated items between the parentheses at indices `open` and `close` of `tokens`.
+fn group_items<'a>(q: &'a str, tokens: &[Token], open: usize, close: usize) -> Vec<&'a str> {
+    let mut items = Vec::new();
+    let mut start = tokens[open].end();
+    let mut depth = 0;
+    for t in &tokens[open + 1..close] {
+        if t.is("(") {
+            depth += 1;
+        } else if t.is(")") {
+            depth -= 1;
+        } else if depth == 0 && t.is(",") {
+            items.push(q[start..t.at].trim());
+            start = t.end();
+        }
+    }
+    items.push(q[start..tokens[close].at].trim());
+    items
+}
+
+/// The column that stands in for a row comparison while the rest of its query is parsed.
+const ROW_COMPARISON: &str = "__row_comparison";
+
+/// A row comparison that was cut from a statement: the row is compared to each of `tuples`, and
+/// matches if it equals any of them, or, if `negated`, none of them.
+#[derive(Clone, Debug)]
+struct RowComparison {
+    row: Vec<String>,
+    tuples: Vec<Vec<String>>,
+    negated: bool,
+}
+
+/// Replace the row comparisons of `q`, which nom-sql does not parse, with placeholder conditions
+/// on `ROW_COMPARISON`. Once the rest of the query is parsed, `apply_row_comparisons` puts them
+/// back. `(a, b) = (?, ?)` is compared to one tuple, `(a, b) IN ((1, 2), (3, 4))` to several,
+/// and `!=`, `<>` and `NOT IN` are their negations.
+fn split_row_comparisons(q: &str) -> (String, Vec<RowComparison>) {
+    let tokens = tokenize(q);
+    let mut rest = String::with_capacity(q.len());
+    let mut copied = 0;
+    let mut rows = Vec::new();
+    let mut k = 0;
+    while k < tokens.len() {
+        let open = k;
+        k += 1;
+        if !tokens[open].is("(") {
+            continue;
+        }
+
+        // the row must not be the arguments of a function
+        if let Some(before) = open.checked_sub(1).map(|b| tokens[b]) {
+            let word = before.quoted
+                || before
+                    .text
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if word
+                && !["where", "and", "or", "not", "on", "having"]
+                    .iter()
+                    .any(|w| before.is(w))
+            {
+                continue;
+            }
+        }
+        let close = match closing_token(&tokens, open) {
+            Some(close) => close,
+            None => break,
+        };
+        let row = group_items(q, &tokens, open, close);
+        if row.len() < 2 {
+            continue;
+        }
+
+        // and be compared to another row, or be in a list of rows
+        let (negated, list, other_open) = match tokens.get(close + 1) {
+            Some(t) if t.is("=") => (false, false, close + 2),
+            Some(t) if t.is("!=") || t.is("<>") => (true, false, close + 2),
+            Some(t) if t.is("in") => (false, true, close + 2),
+            Some(t) if t.is("not") && tokens.get(close + 2).map(|t| t.is("in")) == Some(true) => {
+                (true, true, close + 3)
+            }
+            _ => continue,
+        };
+        if !tokens.get(other_open).map(|t| t.is("(")).unwrap_or(false) {
+            continue;
+        }
+        let other_close = match closing_token(&tokens, other_open) {
+            Some(other_close) => other_close,
+            None => break,
+        };
+        let tuples = if list {
+            // each item of the list is a parenthesized tuple
+            let mut tuples = Vec::new();
+            let mut t = other_open + 1;
+            while t < other_close && tokens[t].is("(") {
+                let end = match closing_token(&tokens, t) {
+                    Some(end) => end,
+                    None => break,
+                };
+                tuples.push(group_items(q, &tokens, t, end));
+                t = end + 1;
+                if t < other_close && tokens[t].is(",") {
+                    t += 1;
+                }
+            }
+            if t != other_close {
+                continue;
+            }
+            tuples
+        } else {
+            vec![group_items(q, &tokens, other_open, other_close)]
+        };
+        if tuples.is_empty() || tuples.iter().any(|t| t.len() != row.len()) {
+            continue;
+        }
+
+        rest.push_str(&q[copied..tokens[open].at]);
+        rest.push_str(&format!("{} = {}", ROW_COMPARISON, rows.len()));
+        copied = tokens[other_close].end();
+        rows.push(RowComparison {
+            row: row.into_iter().map(String::from).collect(),
+            tuples: tuples
+                .into_iter()
+                .map(|t| t.into_iter().map(String::from).collect())
+                .collect(),
+            negated,
+        });
+        k = other_close + 1;
+    }
+    rest.push_str(&q[copied..]);
+    (rest, rows)
+}
+
+/// Join `conditions` with `operator`.
+fn join_conditions(
+    operator: Operator,
+    conditions: Vec<ConditionExpression>,
+) -> ConditionExpression {
+    let mut conditions = conditions.into_iter().rev();
+    let last = conditions.next().expect("no conditions to join");
+    conditions.fold(last, |right, left| {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: operator.clone(),
+            left: Box::new(left),
+            right: Box::new(right),
+        })
+    })
+}
+
+impl RowComparison {
+    /// The comparisons of the row's columns: `(a, b) = (?, ?)` is `(a = ? AND b = ?)`, which is
+    /// looked up by a composite key, and `(a, b) IN ((1, 2), (3, 4))` is
+    /// `((a = 1 AND b = 2) OR (a = 3 AND b = 4))`, which is planned as a single filter.
+    fn condition(&self) -> Result<ConditionExpression, String> {
+        let compare = |tuple: &[String], operator: Operator, join: Operator| {
+            self.row
+                .iter()
+                .zip(tuple)
+                .map(|(column, value)| {
+                    parse_condition(&format!("{} = {}", column, value)).and_then(|ce| match ce {
+                        ConditionExpression::ComparisonOp(ct) => {
+                            Ok(ConditionExpression::ComparisonOp(ConditionTree {
+                                operator: operator.clone(),
+                                ..ct
+                            }))
+                        }
+                        _ => Err(format!("failed to compare \"{}\" to \"{}\"", column, value)),
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(|comparisons| join_conditions(join, comparisons))
+        };
+        let bracketed = |ce: ConditionExpression| ConditionExpression::Bracketed(Box::new(ce));
+        Ok(match (&self.tuples[..], self.negated) {
+            (&[ref tuple], false) => {
+                bracketed(compare(&tuple[..], Operator::Equal, Operator::And)?)
+            }
+            (&[ref tuple], true) => {
+                bracketed(compare(&tuple[..], Operator::NotEqual, Operator::Or)?)
+            }
+            (tuples, negated) => {
+                let any = tuples
+                    .iter()
+                    .map(|t| compare(&t[..], Operator::Equal, Operator::And).map(bracketed))
+                    .collect::<Result<Vec<_>, String>>()?;
+                let any = bracketed(join_conditions(Operator::Or, any));
+                if negated {
+                    ConditionExpression::NegationOp(Box::new(any))
+                } else {
+                    any
+                }
+            }
+        })
+    }
+}
+
+/// Replace the placeholder conditions that `split_row_comparisons` left in `ce` with the
+/// comparisons in `rows`.
+fn apply_row_comparisons_condition(
+    ce: &mut ConditionExpression,
+    rows: &mut [Option<ConditionExpression>],
+) {
+    let row = match *ce {
+        ConditionExpression::ComparisonOp(ConditionTree {
+            ref left,
+            ref right,
+            ..
+        }) => match (&**left, &**right) {
+            (
+                &ConditionExpression::Base(ConditionBase::Field(ref c)),
+                &ConditionExpression::Base(ConditionBase::Literal(ref l)),
+            ) if c.name == ROW_COMPARISON && c.table.is_none() => match *l {
+                Literal::Integer(i) => rows.get_mut(i as usize).and_then(Option::take),
+                Literal::UnsignedInteger(i) => rows.get_mut(i as usize).and_then(Option::take),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(row) = row {
+        *ce = row;
+        return;
+    }
+    match *ce {
+        ConditionExpression::LogicalOp(ref mut ct)
+        | ConditionExpression::ComparisonOp(ref mut ct) => {
+            apply_row_comparisons_condition(&mut ct.left, rows);
+            apply_row_comparisons_condition(&mut ct.right, rows);
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => {
+            apply_row_comparisons_condition(inner, rows);
+        }
+        ConditionExpression::Base(ConditionBase::NestedSelect(ref mut sq)) => {
+            apply_row_comparisons_select(sq, rows);
+        }
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => {}
+    }
+}
+
+fn apply_row_comparisons_select(
+    sq: &mut SelectStatement,
+    rows: &mut [Option<ConditionExpression>],
+) {
+    use nom_sql::FunctionExpression::*;
+
+    for field in sq.fields.iter_mut() {
+        if let FieldDefinitionExpression::Col(Column {
+            function: Some(ref mut f),
+            ..
+        }) = *field
+        {
+            match **f {
+                Count(FunctionArguments::Conditional(ref mut cwe), _)
+                | Sum(FunctionArguments::Conditional(ref mut cwe), _)
+                | Avg(FunctionArguments::Conditional(ref mut cwe), _)
+                | Min(FunctionArguments::Conditional(ref mut cwe))
+                | Max(FunctionArguments::Conditional(ref mut cwe))
+                | GroupConcat(FunctionArguments::Conditional(ref mut cwe), _) => {
+                    apply_row_comparisons_condition(&mut cwe.condition, rows);
+                }
+                _ => {}
+            }
+        }
+    }
+    for jc in sq.join.iter_mut() {
+        if let JoinConstraint::On(ref mut ce) = jc.constraint {
+            apply_row_comparisons_condition(ce, rows);
+        }
+    }
+    if let Some(ref mut ce) = sq.where_clause {
+        apply_row_comparisons_condition(ce, rows);
+    }
+    if let Some(GroupByClause {
+        having: Some(ref mut ce),
+        ..
+    }) = sq.group_by
+    {
+        apply_row_comparisons_condition(ce, rows);
+    }
+}
+
+/// Put the row comparisons that `split_row_comparisons` cut from `q` back into its conditions.
+fn apply_row_comparisons(q: &mut SqlQuery, rows: &[RowComparison]) -> Result<(), String> {
+    let mut conditions = rows
+        .iter()
+        .map(|r| r.condition().map(Some))
+        .collect::<Result<Vec<_>, _>>()?;
+    match *q {
+        SqlQuery::Select(ref mut sq) => apply_row_comparisons_select(sq, &mut conditions),
+        SqlQuery::CompoundSelect(ref mut csq) => {
+            for &mut (_, ref mut sq) in csq.selects.iter_mut() {
+                apply_row_comparisons_select(sq, &mut conditions);
+            }
+        }
+        _ => {}
+    }
+    if conditions.iter().any(Option::is_some) {
+        return Err(
+            "row comparisons are only supported in WHERE, HAVING, ON, and FILTER clauses"
+                .to_owned(),
+        );
+    }
+    Ok(())
+}
+
+/// Remove a `WITH ROLLUP` from the `GROUP BY` clause of `q`, since nom-sql does not parse it.
+/// Returns whether there was one, in which case the parsed query is expanded with
+/// `expand_rollup`.
+fn split_rollup(q: &str) -> (String, bool) {
+    let chars = unquoted_chars(q);
+    for (k, &(i, _)) in chars.iter().enumerate() {
+        if !keyword_at(q, i, "with") {
+            continue;
+        }
+        let rest = chars[k + "with".len()..]
+            .iter()
+            .find(|&&(_, c)| !c.is_whitespace());
+        if let Some(&(j, _)) = rest {
+            if keyword_at(q, j, "rollup") {
+                let after = q[j + "rollup".len()..].trim_start();
+                let sep = if after.is_empty() || after.starts_with(';') {
+                    ""
+                } else {
+                    " "
+                };
+                return (format!("{}{}{}", q[..i].trim_end(), sep, after), true);
+            }
+        }
+    }
+    (q.to_owned(), false)
+}
+
+/// The name of the column that a rollup adds to tell its grouping sets apart. It holds the number
+/// of trailing `GROUP BY` columns that are rolled up in each row.
+const GROUPING_ID: &str = "grouping_id";
+
+/// Expand `SELECT ... GROUP BY c1, ..., cn WITH ROLLUP` into the union of the same query grouped
+/// by `c1, ..., ck` for every `k` from `n` down to `0`. Each branch projects `NULL` in place of
+/// the columns it does not group by, and adds a `grouping_id` column so that the subtotals can be
+/// told apart from the rows that have `NULL` values.
+fn expand_rollup(sq: SelectStatement) -> Result<SqlQuery, String> {
+    let group_by = match sq.group_by {
+        Some(ref gb) => gb.columns.clone(),
+        None => return Err("WITH ROLLUP requires a GROUP BY".to_owned()),
+    };
+    let is_group_column = |c: &Column, cols: &[Column]| {
+        c.function.is_none()
+            && cols.iter().any(|g| {
+                g.name == c.name && (g.table.is_none() || c.table.is_none() || g.table == c.table)
+            })
+    };
+
+    let n = group_by.len();
+    let selects = (0..=n)
+        .rev()
+        .map(|k| {
+            let mut branch = sq.clone();
+            branch.order = None;
+            branch.limit = None;
+            for field in branch.fields.iter_mut() {
+                let rolled_up = match *field {
+                    FieldDefinitionExpression::Col(ref c) => {
+                        if is_group_column(c, &group_by[k..]) && !is_group_column(c, &group_by[..k])
+                        {
+                            Some(c.alias.clone().unwrap_or_else(|| c.name.clone()))
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+                if let Some(name) = rolled_up {
+                    *field = FieldDefinitionExpression::Value(FieldValueExpression::Literal(
+                        LiteralExpression {
+                            value: Literal::Null,
+                            alias: Some(name),
+                        },
+                    ));
+                }
+            }
+            branch.fields.push(FieldDefinitionExpression::Value(
+                FieldValueExpression::Literal(LiteralExpression {
+                    value: Literal::Integer((n - k) as i64),
+                    alias: Some(GROUPING_ID.to_owned()),
+                }),
+            ));
+            branch.group_by = if k == 0 {
+                None
+            } else {
+                Some(GroupByClause {
+                    columns: group_by[..k].to_vec(),
+                    having: sq.group_by.as_ref().unwrap().having.clone(),
+                })
+            };
+            let op = if k == n {
+                None
+            } else {
+                Some(CompoundSelectOperator::Union)
+            };
+            (op, branch)
+        })
+        .collect();
+
+    Ok(SqlQuery::CompoundSelect(CompoundSelectStatement {
+        selects,
+        order: sq.order,
+        limit: sq.limit,
+    }))
+}
+
+/// Add the columns that the base maintains itself to a table, unless it already declares them (as
+/// it does when the table is read back from rendered recipe text).
+fn add_generated_columns(ctq: &mut CreateTableStatement, options: &TableOptions) {
+    let mut columns = Vec::new();
+    if options.timestamps {
+        columns.push((CREATED_AT, SqlType::Timestamp));
+        columns.push((UPDATED_AT, SqlType::Timestamp));
+    }
+    if options.soft_delete {
+        columns.push((DELETED, SqlType::Int(32)));
+    }
+    for (name, sql_type) in columns {
+        if ctq.fields.iter().all(|f| f.column.name != name) {
+            let column = Column::from(&*format!("{}.{}", ctq.table.name, name));
+            ctq.fields.push(ColumnSpecification::new(column, sql_type));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(super) enum Schema {
     Table(CreateTableStatement),
@@ -119,6 +1319,28 @@ fn query_expr(input: &str) -> nom::IResult<&str, (bool, Option<&str>, SqlQuery)>
     ))
 }
 
+/// Render a single recipe expression as recipe text.
+fn render_expression(
+    name: Option<&str>,
+    q: &SqlQuery,
+    public: bool,
+    options: Option<String>,
+) -> String {
+    let q = q
+        .to_string()
+        .replace(&format!("LIMIT {}", LIMIT_PARAMETER), "LIMIT ?")
+        .replace(&format!("OFFSET {}", LIMIT_PARAMETER), "OFFSET ?");
+    let q = match options {
+        Some(options) => format!("{} {}", q, options),
+        None => q,
+    };
+    match name {
+        Some(n) if public => format!("QUERY {}: {};", n, q),
+        Some(n) => format!("{}: {};", n, q),
+        None => format!("{};", q),
+    }
+}
+
 fn query_exprs(input: &str) -> nom::IResult<&str, Vec<(bool, Option<&str>, SqlQuery)>> {
     nom::multi::many1(query_expr)(input)
 }
@@ -134,7 +1356,7 @@ impl Recipe {
     }
 
     /// Return active aliases for expressions
-    fn aliases(&self) -> Vec<&str> {
+    pub(in crate::controller) fn aliases(&self) -> Vec<&str> {
         self.aliases.keys().map(String::as_str).collect()
     }
 
@@ -146,6 +1368,10 @@ impl Recipe {
             expressions: HashMap::default(),
             expression_order: Vec::default(),
             aliases: HashMap::default(),
+            table_options: HashMap::default(),
+            view_options: HashMap::default(),
+            deferred: HashSet::default(),
+            generations: HashMap::default(),
             version: 0,
             prior: None,
             inc: match log {
@@ -185,6 +1411,20 @@ impl Recipe {
         self.inc.as_mut().unwrap().enable_reuse(reuse_type)
     }
 
+    /// Tell the planner how many rows each base table was last found to hold.
+    pub(in crate::controller) fn set_table_rows(&mut self, rows: HashMap<String, usize>) {
+        if let Some(ref mut inc) = self.inc {
+            inc.set_table_rows(rows);
+        }
+    }
+
+    /// Stop offering the nodes of query `name`, whose aggregation splits hot keys, for reuse.
+    pub(in crate::controller) fn forbid_reuse_of_split(&mut self, name: &str) {
+        if let Some(ref mut inc) = self.inc {
+            inc.forbid_reuse_of_split(name);
+        }
+    }
+
     pub(in crate::controller) fn resolve_alias(&self, alias: &str) -> Option<&str> {
         self.aliases.get(alias).map(|ref qid| {
             let (ref internal_qn, _, _) = self.expressions[qid];
@@ -234,24 +1474,36 @@ impl Recipe {
         self.security_config = Some(config);
     }
 
+    /// The text of the recipe's security configuration, if it has one.
+    pub(in crate::controller) fn security_config_text(&self) -> Option<&str> {
+        self.security_config.as_ref().map(SecurityConfig::text)
+    }
+
+    /// The queries staged for each generation that has not been switched to yet.
+    pub(in crate::controller) fn generations(&self) -> &HashMap<usize, HashMap<String, String>> {
+        &self.generations
+    }
+
+    /// Restore the staged generations of a recipe that was recovered from its text.
+    pub(in crate::controller) fn set_generations(
+        &mut self,
+        generations: HashMap<usize, HashMap<String, String>>,
+    ) {
+        self.generations = generations;
+    }
+
     /// Creates a recipe from a set of SQL queries in a string (e.g., read from a file).
     /// Note that the recipe is not backed by a Soup data-flow graph until `activate` is called on
     /// it.
     // crate viz for tests
     pub(crate) fn from_str(recipe_text: &str, log: Option<slog::Logger>) -> Result<Recipe, String> {
-        // remove comment lines
-        let lines: Vec<String> = recipe_text
-            .lines()
-            .map(str::trim)
-            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("--"))
-            .map(String::from)
-            .collect();
-        let cleaned_recipe_text = lines.join("\n");
-
         // parse and compute differences to current recipe
-        let parsed_queries = Recipe::parse(&cleaned_recipe_text)?;
+        let (parsed_queries, table_options, view_options) = Recipe::parse(recipe_text)?;
 
-        Ok(Recipe::from_queries(parsed_queries, log))
+        let mut recipe = Recipe::from_queries(parsed_queries, log);
+        recipe.table_options = table_options;
+        recipe.view_options = view_options;
+        Ok(recipe)
     }
 
     /// Creates a recipe from a set of pre-parsed `SqlQuery` structures.
@@ -304,6 +1556,10 @@ impl Recipe {
             expressions,
             expression_order,
             aliases,
+            table_options: HashMap::default(),
+            view_options: HashMap::default(),
+            deferred: HashSet::default(),
+            generations: HashMap::default(),
             security_config: None,
             version: 0,
             prior: None,
@@ -325,6 +1581,9 @@ impl Recipe {
             removed_leaves: Vec::default(),
             expressions_added: 0,
             expressions_removed: 0,
+            version: self.version,
+            duplicates: HashMap::default(),
+            deferred: Vec::new(),
         };
 
         if self.security_config.is_some() {
@@ -402,8 +1661,26 @@ impl Recipe {
             removed_leaves: Vec::default(),
             expressions_added: added.len(),
             expressions_removed: removed.len(),
+            version: self.version,
+            duplicates: HashMap::default(),
+            deferred: Vec::new(),
         };
 
+        // names that this recipe gives to queries it already had under another name
+        for (name, qid) in &self.aliases {
+            let known = self
+                .prior
+                .as_ref()
+                .map(|pr| pr.aliases.contains_key(name))
+                .unwrap_or(false);
+            match self.expressions[qid].0 {
+                Some(ref internal) if !known && internal != name => {
+                    result.duplicates.insert(name.clone(), internal.clone());
+                }
+                _ => (),
+            }
+        }
+
         // upgrade schema version *before* applying changes, so that new queries are correctly
         // tagged with the new version. If this recipe was just created, there is no need to
         // upgrade the schema version, as the SqlIncorporator's version will still be at zero.
@@ -453,13 +1730,60 @@ impl Recipe {
         // returned to the caller (who may use them to obtain mutators and getters)
         for qid in added {
             let (n, q, is_leaf) = self.expressions[&qid].clone();
+            let table = match q {
+                SqlQuery::CreateTable(ref ctq) => Some(ctq.clone()),
+                _ => None,
+            };
+
+            // lazy views are only checked against the schema for now, and added once read
+            if self.view_options_for(n.as_ref()).lazy {
+                self.inc.as_ref().unwrap().check_query(&q)?;
+                self.deferred.insert(qid);
+                result.deferred.push(n.unwrap());
+                continue;
+            }
 
             // add the query
-            let qfp = self
-                .inc
-                .as_mut()
-                .unwrap()
-                .add_parsed_query(q, n.clone(), is_leaf, mig)?;
+            let qfp = self.add_query(q, n.clone(), is_leaf, mig)?;
+
+            if let Some(ctq) = table {
+                let options = self.table_options.get(&ctq.table.name);
+                if let Some(ttl) = options.and_then(|o| o.ttl.as_ref()) {
+                    let column = match ttl.column {
+                        Some(ref c) => ctq.fields.iter().position(|f| f.column.name == *c),
+                        None => ctq
+                            .fields
+                            .iter()
+                            .position(|f| f.sql_type == SqlType::Timestamp),
+                    };
+                    let column = column.ok_or_else(|| {
+                        format!("no column to expire rows of {} by", ctq.table.name)
+                    })?;
+                    mig.set_ttl(qfp.query_leaf, column, ttl.ttl)?;
+                }
+                if let Some(c) = options.and_then(|o| o.version_column.as_ref()) {
+                    let column = ctq
+                        .fields
+                        .iter()
+                        .position(|f| f.column.name == *c)
+                        .ok_or_else(|| format!("{} has no version column {}", ctq.table.name, c))?;
+                    mig.set_version_column(qfp.query_leaf, column)?;
+                }
+                let position = |name: &str| ctq.fields.iter().position(|f| f.column.name == name);
+                if options.map(|o| o.timestamps).unwrap_or(false) {
+                    let created = position(CREATED_AT).unwrap();
+                    let updated = position(UPDATED_AT).unwrap();
+                    mig.set_timestamp_columns(qfp.query_leaf, created, updated)?;
+                }
+                if options.map(|o| o.soft_delete).unwrap_or(false) {
+                    mig.set_tombstone_column(qfp.query_leaf, position(DELETED).unwrap())?;
+                    // queries added from here on hide the deleted rows
+                    self.inc
+                        .as_mut()
+                        .unwrap()
+                        .set_tombstone_column(&ctq.table.name, DELETED);
+                }
+            }
 
             // If the user provided us with a query name, use that.
             // If not, use the name internally used by the QFP.
@@ -467,12 +1791,19 @@ impl Recipe {
                 Some(name) => name,
                 None => qfp.name.clone(),
             };
+            if query_name != qfp.name {
+                // the query is structurally identical to an existing view
+                result
+                    .duplicates
+                    .insert(query_name.clone(), qfp.name.clone());
+            }
 
             result.new_nodes.insert(query_name, qfp.query_leaf);
         }
 
         result.removed_leaves = removed
             .iter()
+            .filter(|qid| !self.prior.as_ref().unwrap().deferred.contains(qid))
             .filter_map(|qid| {
                 let (ref n, ref q, _) = self.prior.as_ref().unwrap().expressions[qid];
                 match q {
@@ -506,6 +1837,79 @@ impl Recipe {
         Ok(result)
     }
 
+    /// Add the nodes for query `q` to the graph, following its view options.
+    fn add_query(
+        &mut self,
+        q: SqlQuery,
+        n: Option<String>,
+        is_leaf: bool,
+        mig: &mut Migration,
+    ) -> Result<QueryFlowParts, String> {
+        let inc = self.inc.as_mut().unwrap();
+        match n {
+            Some(name)
+                if self
+                    .view_options
+                    .get(&name)
+                    .map(|o| o.cache)
+                    .unwrap_or(false) =>
+            {
+                let qfp = inc.add_cached_query(q, name, is_leaf, mig)?;
+                if is_leaf {
+                    mig.set_reader_cached(qfp.query_leaf);
+                }
+                Ok(qfp)
+            }
+            Some(name)
+                if self
+                    .view_options
+                    .get(&name)
+                    .map(|o| o.straight_through)
+                    .unwrap_or(false) =>
+            {
+                let qfp = inc.add_straight_through_query(q, name, is_leaf, mig)?;
+                mig.set_reader_straight_through(qfp.query_leaf);
+                Ok(qfp)
+            }
+            n => inc.add_parsed_query(q, n, is_leaf, mig),
+        }
+    }
+
+    fn view_options_for(&self, name: Option<&String>) -> ViewOptions {
+        name.and_then(|n| self.view_options.get(n))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Whether `name` is a lazy view that has not been read yet.
+    pub(in crate::controller) fn is_deferred(&self, name: &str) -> bool {
+        self.aliases
+            .get(name)
+            .map(|qid| self.deferred.contains(qid))
+            .unwrap_or(false)
+    }
+
+    /// Add the nodes of the lazy view `name` to the graph carried by `mig`, since it is now read.
+    pub(in crate::controller) fn activate_deferred(
+        &mut self,
+        name: &str,
+        mig: &mut Migration,
+    ) -> Result<NodeIndex, String> {
+        let qid = match self.aliases.get(name) {
+            Some(qid) if self.deferred.contains(qid) => *qid,
+            _ => {
+                return Err(format!(
+                    "{} is not a lazy view that is still to be built",
+                    name
+                ))
+            }
+        };
+        let (n, q, is_leaf) = self.expressions[&qid].clone();
+        let qfp = self.add_query(q, n, is_leaf, mig)?;
+        self.deferred.remove(&qid);
+        Ok(qfp.query_leaf)
+    }
+
     /// Work out the delta between two recipes.
     /// Returns two sets of `QueryID` -> `SqlQuery` mappings:
     /// (1) those queries present in `self`, but not in `other`; and
@@ -536,41 +1940,61 @@ impl Recipe {
             .collect()
     }
 
-    /// Append the queries in the `additions` argument to this recipe. This will attempt to parse
-    /// `additions`, and if successful, will extend the recipe. No expressions are removed from the
-    /// recipe; use `replace` if removal of unused expressions is desired.
-    /// Consumes `self` and returns a replacement recipe.
-    // crate viz for tests
-    pub(crate) fn extend(mut self, additions: &str) -> Result<Recipe, (Recipe, String)> {
-        // parse and compute differences to current recipe
-        let add_rp = match Recipe::from_str(additions, None) {
-            Ok(rp) => rp,
-            Err(e) => return Err((self, e)),
-        };
-        let (added, _) = add_rp.compute_delta(&self);
-
-        // move the incorporator state from the old recipe to the new one
+    /// Build the next version of this recipe, containing the same expressions, with `self` as
+    /// its prior. The incorporator state moves to the new recipe.
+    fn successor(mut self) -> Recipe {
         let prior_inc = self.inc.take();
-
-        // build new recipe as clone of old one
-        let mut new = Recipe {
+        Recipe {
             expressions: self.expressions.clone(),
             expression_order: self.expression_order.clone(),
             aliases: self.aliases.clone(),
+            table_options: self.table_options.clone(),
+            view_options: self.view_options.clone(),
+            deferred: self.deferred.clone(),
+            generations: self.generations.clone(),
             version: self.version + 1,
             inc: prior_inc,
             log: self.log.clone(),
             security_config: self.security_config.clone(),
             // retain the old recipe for future reference
             prior: Some(Box::new(self)),
+        }
+    }
+
+    /// Append the queries in the `additions` argument to this recipe. This will attempt to parse
+    /// `additions`, and if successful, will extend the recipe. No expressions are removed from the
+    /// recipe; use `replace` if removal of unused expressions is desired.
+    /// Consumes `self` and returns a replacement recipe.
+    // crate viz for tests
+    pub(crate) fn extend(self, additions: &str) -> Result<Recipe, (Recipe, String)> {
+        // parse and compute differences to current recipe
+        let add_rp = match Recipe::from_str(additions, None) {
+            Ok(rp) => rp,
+            Err(e) => return Err((self, e)),
         };
+        let (added, _) = add_rp.compute_delta(&self);
+
+        // build new recipe as clone of old one
+        let mut new = self.successor();
 
         // apply changes
         for qid in added {
             let q = add_rp.expressions[&qid].clone();
+            if let SqlQuery::CreateTable(ref ctq) = q.1 {
+                match add_rp.table_options.get(&ctq.table.name) {
+                    Some(o) => new.table_options.insert(ctq.table.name.clone(), o.clone()),
+                    None => new.table_options.remove(&ctq.table.name),
+                };
+            }
             new.expressions.insert(qid, q);
             new.expression_order.push(qid);
         }
+        for n in add_rp.aliases.keys() {
+            match add_rp.view_options.get(n) {
+                Some(o) => new.view_options.insert(n.clone(), o.clone()),
+                None => new.view_options.remove(n),
+            };
+        }
 
         for (n, qid) in &add_rp.aliases {
             assert!(
@@ -601,54 +2025,134 @@ impl Recipe {
         self.inc = Some(new_inc);
     }
 
-    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, SqlQuery, bool)>, String> {
-        let lines: Vec<&str> = recipe_text
-            .lines()
-            .filter(|l| !l.is_empty() && !l.starts_with('#'))
-            .map(|l| {
-                // remove inline comments, too
-                match l.find('#') {
-                    None => l.trim(),
-                    Some(pos) => &l[0..pos - 1].trim(),
-                }
-            })
-            .collect();
-        let mut query_strings = Vec::new();
-        let mut q = String::new();
-
-        let linecount = lines.len();
-        let mut i = 1;
-        for l in lines {
-            if !l.ends_with(';') && i < linecount {
-                q.push_str(l);
-                q.push_str(" ");
-            } else {
-                // either line ends with semicolor, or it does not and this is the last line
-                // in both cases, we're at the end of the query
-                q.push_str(l);
-                query_strings.push(q);
-                q = String::new();
-            }
-            i += 1;
+    #[allow(clippy::type_complexity)]
+    fn parse(
+        recipe_text: &str,
+    ) -> Result<
+        (
+            Vec<(Option<String>, SqlQuery, bool)>,
+            HashMap<String, TableOptions>,
+            HashMap<String, ViewOptions>,
+        ),
+        String,
+    > {
+        let statements = split_statements(recipe_text);
+        if let Some(s) = statements.iter().find(|s| include_path(&s.text).is_some()) {
+            return Err(format!(
+                "line {}: INCLUDE is only supported in recipes given to the controller",
+                s.positions[0].0
+            ));
         }
+        Recipe::parse_statements(statements.iter())
+    }
+
+    fn parse_statements<'a>(
+        statements: impl Iterator<Item = &'a Statement>,
+    ) -> Result<
+        (
+            Vec<(Option<String>, SqlQuery, bool)>,
+            HashMap<String, TableOptions>,
+            HashMap<String, ViewOptions>,
+        ),
+        String,
+    > {
+        // table options, limit parameters, row comparisons, FILTER clauses, and rollups are not
+        // understood by nom-sql, so we handle them ourselves
+        let query_strings = statements
+            .map(|s| {
+                let (q, rows) = split_row_comparisons(&replace_limit_parameters(&s.text));
+                let (q, filters) = split_filter_clauses(&q);
+                let (q, rollup) = split_rollup(&q);
+                let (q, options) = split_options(&q);
+                (s, q, options, rows, filters, rollup)
+            })
+            .collect::<Vec<_>>();
 
+        let mut table_options = HashMap::new();
+        let mut view_options = HashMap::new();
         let parsed_queries = query_strings.iter().fold(
             Vec::new(),
-            |mut acc: Vec<Result<(bool, Option<&str>, SqlQuery), String>>, q| {
+            |mut acc: Vec<Result<(bool, Option<&str>, SqlQuery), String>>,
+             (s, q, options, rows, filters, rollup)| {
                 match query_exprs(q) {
                     Result::Err(e) => {
                         // we got a parse error
-                        acc.push(Err(format!("Query \"{}\", parse error: {}", q, e)));
+                        let rest = match &e {
+                            nom::Err::Error((i, _)) | nom::Err::Failure((i, _)) => i.len(),
+                            nom::Err::Incomplete(_) => 0,
+                        };
+                        let (line, column) = s.position(q, q.len() - rest);
+                        acc.push(Err(format!(
+                            "Query \"{}\", parse error at line {}, column {}: {}",
+                            q, line, column, e
+                        )));
                     }
-                    Result::Ok((remainder, parsed)) => {
+                    Result::Ok((remainder, _)) if !remainder.is_empty() => {
                         // should have consumed all input
-                        assert!(
-                            remainder.is_empty(),
-                            format!(
-                                "failed to parse the complete recipe; left with: {}",
-                                remainder
-                            )
-                        );
+                        let (line, column) = s.position(q, q.len() - remainder.len());
+                        acc.push(Err(format!(
+                            "Query \"{}\", parse error at line {}, column {}: unexpected \"{}\"",
+                            q, line, column, remainder
+                        )));
+                    }
+                    Result::Ok((_, mut parsed)) => {
+                        if let Some(options) = options {
+                            match parsed[..] {
+                                [(_, _, SqlQuery::CreateTable(ref mut ctq))] => {
+                                    match TableOptions::parse(options) {
+                                        Ok(options) => {
+                                            add_generated_columns(ctq, &options);
+                                            table_options.insert(ctq.table.name.clone(), options);
+                                        }
+                                        Err(e) => acc.push(Err(e)),
+                                    }
+                                }
+                                [(true, Some(name), SqlQuery::Select(_))]
+                                | [(true, Some(name), SqlQuery::CompoundSelect(_))] => {
+                                    match ViewOptions::parse(options) {
+                                        Ok(options) => {
+                                            view_options.insert(name.to_owned(), options);
+                                        }
+                                        Err(e) => acc.push(Err(e)),
+                                    }
+                                }
+                                _ => acc.push(Err(format!(
+                                    "Query \"{}\": options are only supported on CREATE TABLE and named QUERY statements",
+                                    q
+                                ))),
+                            }
+                        }
+                        if !filters.is_empty() {
+                            let applied = match parsed[..] {
+                                [(_, _, ref mut p)] => apply_filter_clauses(p, filters.clone()),
+                                _ => Err("FILTER is only supported on aggregates of a SELECT"
+                                    .to_owned()),
+                            };
+                            if let Err(e) = applied {
+                                acc.push(Err(format!("Query \"{}\": {}", q, e)));
+                            }
+                        }
+                        if !rows.is_empty() {
+                            let applied = match parsed[..] {
+                                [(_, _, ref mut p)] => apply_row_comparisons(p, rows),
+                                _ => Err("row comparisons are only supported in queries".to_owned()),
+                            };
+                            if let Err(e) = applied {
+                                acc.push(Err(format!("Query \"{}\": {}", q, e)));
+                            }
+                        }
+                        if *rollup {
+                            for p in parsed.iter_mut() {
+                                let expanded = match p.2 {
+                                    SqlQuery::Select(ref sq) => expand_rollup(sq.clone()),
+                                    _ => Err("WITH ROLLUP is only supported on SELECT".to_owned()),
+                                };
+                                match expanded {
+                                    Ok(q) => p.2 = q,
+                                    Err(e) => acc.push(Err(format!("Query \"{}\": {}", q, e))),
+                                }
+                            }
+                        }
                         acc.extend(parsed.into_iter().map(|p| Ok(p)).collect::<Vec<_>>());
                     }
                 }
@@ -656,13 +2160,11 @@ impl Recipe {
             },
         );
 
-        Ok(parsed_queries
+        let parsed_queries = parsed_queries
             .into_iter()
-            .map(|pr| {
-                let pr = pr.unwrap();
-                (pr.1.map(String::from), pr.2, pr.0)
-            })
-            .collect::<Vec<_>>())
+            .map(|pr| pr.map(|pr| (pr.1.map(String::from), pr.2, pr.0)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((parsed_queries, table_options, view_options))
     }
 
     /// Returns the predecessor from which this `Recipe` was migrated to.
@@ -689,6 +2191,289 @@ impl Recipe {
         false
     }
 
+    /// Remove the named query from this recipe, producing a new version of the recipe. Base tables
+    /// cannot be removed this way.
+    /// Consumes `self` and returns a replacement recipe; on error, `self` is handed back.
+    pub(super) fn remove(self, qname: &str) -> Result<Recipe, (Recipe, String)> {
+        let qid = match self.aliases.get(qname) {
+            Some(&qid) => qid,
+            None => return Err((self, format!("query \"{}\" does not exist", qname))),
+        };
+        if let (_, SqlQuery::CreateTable(_), _) = self.expressions[&qid] {
+            return Err((self, format!("\"{}\" is a base table, not a query", qname)));
+        }
+
+        let mut new = self.successor();
+        new.remove_query(qname);
+        // other names for the same query must go too
+        new.aliases.retain(|_, q| *q != qid);
+
+        Ok(new)
+    }
+
+    /// Add `query` as a new version of the existing named query `qname`, installed side-by-side
+    /// with the current version under the name `staged`. Use `promote` to make the staged query
+    /// take over `qname`.
+    /// Consumes `self` and returns a replacement recipe; on error, `self` is handed back.
+    pub(super) fn stage(
+        self,
+        qname: &str,
+        staged: &str,
+        query: &str,
+    ) -> Result<Recipe, (Recipe, String)> {
+        let current = match self.aliases.get(qname).cloned() {
+            Some(qid) => match self.expressions[&qid] {
+                (_, SqlQuery::CreateTable(_), _) => {
+                    let e = format!("\"{}\" is a base table, not a query", qname);
+                    return Err((self, e));
+                }
+                (_, _, public) => (qid, public),
+            },
+            None => {
+                let e = format!("query \"{}\" does not exist", qname);
+                return Err((self, e));
+            }
+        };
+        if self.aliases.contains_key(staged) {
+            let e = format!("\"{}\" already exists", staged);
+            return Err((self, e));
+        }
+
+        let addition = format!(
+            "{}{}: {}",
+            if current.1 { "QUERY " } else { "" },
+            staged,
+            query.trim()
+        );
+        let qid = match Recipe::from_str(&addition, None) {
+            Ok(ref rp) if rp.aliases.len() == 1 => rp.aliases[staged],
+            Ok(_) => return Err((self, "expected exactly one query".to_owned())),
+            Err(e) => return Err((self, e)),
+        };
+        if qid == current.0 {
+            let e = format!("staged query is identical to \"{}\"", qname);
+            return Err((self, e));
+        }
+
+        self.extend(&addition)
+    }
+
+    /// Make the staged query `staged` (see `stage`) take over the name `qname`, and remove the
+    /// query that previously went by that name.
+    /// Consumes `self` and returns a replacement recipe; on error, `self` is handed back.
+    pub(super) fn promote(self, qname: &str, staged: &str) -> Result<Recipe, (Recipe, String)> {
+        let current = self.aliases.get(qname).cloned();
+        let next = self.aliases.get(staged).cloned();
+        let (old_qid, new_qid) = match (current, next) {
+            (Some(o), Some(n)) if o != n => (o, n),
+            (Some(_), Some(_)) => {
+                let e = format!("\"{}\" is already the current version", staged);
+                return Err((self, e));
+            }
+            _ => {
+                let e = format!("either \"{}\" or \"{}\" does not exist", qname, staged);
+                return Err((self, e));
+            }
+        };
+        if let (_, SqlQuery::CreateTable(_), _) = self.expressions[&old_qid] {
+            return Err((self, format!("\"{}\" is a base table, not a query", qname)));
+        }
+
+        let mut new = self.successor();
+        new.expressions.remove(&old_qid);
+        new.expression_order.retain(|&q| q != old_qid);
+        new.aliases.retain(|_, q| *q != old_qid);
+        // the staged query keeps its internal name; `qname` now resolves to it
+        new.aliases.insert(qname.to_owned(), new_qid);
+
+        Ok(new)
+    }
+
+    /// Add the queries in `recipe_text` as generation `generation` of this recipe. Each named
+    /// query `q` is added under the name `q_g<generation>`, alongside any current version of
+    /// `q`. Base tables are shared between generations: tables that already exist must be
+    /// identical, and new tables are added right away. Use `switch_generation` to cut over.
+    ///
+    /// Queries in a generation should only read from base tables, since references to other
+    /// queries by name resolve to the current generation.
+    /// Consumes `self` and returns a replacement recipe; on error, `self` is handed back.
+    pub(super) fn stage_generation(
+        self,
+        generation: usize,
+        recipe_text: &str,
+    ) -> Result<Recipe, (Recipe, String)> {
+        let rp = match Recipe::from_str(recipe_text, None) {
+            Ok(rp) => rp,
+            Err(e) => return Err((self, e)),
+        };
+
+        let mut additions = Vec::new();
+        let mut staged = HashMap::new();
+        for qid in &rp.expression_order {
+            let (ref n, ref q, public) = rp.expressions[qid];
+            if let SqlQuery::CreateTable(ref ctq) = *q {
+                let existing = self.expressions.iter().find_map(|(eqid, e)| match e.1 {
+                    SqlQuery::CreateTable(ref ectq) if ectq.table.name == ctq.table.name => {
+                        Some(*eqid)
+                    }
+                    _ => None,
+                });
+                match existing {
+                    Some(eqid) if eqid == *qid => {}
+                    Some(_) => {
+                        let e = format!("table {} differs from the current one", ctq.table.name);
+                        return Err((self, e));
+                    }
+                    None => additions.push(render_expression(
+                        None,
+                        q,
+                        public,
+                        rp.rendered_options(None, q),
+                    )),
+                }
+            } else {
+                match n {
+                    Some(n) => {
+                        let staged_name = format!("{}_g{}", n, generation);
+                        let options = rp.rendered_options(Some(n), q);
+                        additions.push(render_expression(Some(&staged_name), q, public, options));
+                        staged.insert(n.clone(), staged_name);
+                    }
+                    None => {
+                        let e = format!("all queries in a generation must be named: {}", q);
+                        return Err((self, e));
+                    }
+                }
+            }
+        }
+
+        let mut new = self.extend(&additions.join("\n"))?;
+        new.generations
+            .entry(generation)
+            .or_default()
+            .extend(staged);
+        Ok(new)
+    }
+
+    /// Make the queries staged as generation `generation` (see `stage_generation`) take over the
+    /// names they were staged for, and remove all other named queries.
+    /// Consumes `self` and returns a replacement recipe; on error, `self` is handed back.
+    pub(super) fn switch_generation(self, generation: usize) -> Result<Recipe, (Recipe, String)> {
+        // staged queries may have been removed since
+        let staged: Vec<(String, QueryID)> = self
+            .generations
+            .get(&generation)
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, staged)| Some((name.clone(), *self.aliases.get(staged)?)))
+            .collect();
+        if staged.is_empty() {
+            let e = format!("generation {} has not been staged", generation);
+            return Err((self, e));
+        }
+
+        let keep: HashSet<QueryID> = staged.iter().map(|&(_, qid)| qid).collect();
+        let mut new = self.successor();
+        let removed: Vec<QueryID> = new
+            .expression_order
+            .iter()
+            .cloned()
+            .filter(|qid| match new.expressions[qid] {
+                (_, SqlQuery::CreateTable(_), _) => false,
+                // unnamed expressions are not part of any generation
+                (None, _, _) => false,
+                _ => !keep.contains(qid),
+            })
+            .collect();
+        for qid in &removed {
+            new.expressions.remove(qid);
+            new.aliases.retain(|_, q| q != qid);
+        }
+        new.expression_order.retain(|qid| !removed.contains(qid));
+        for (name, qid) in staged {
+            new.aliases.insert(name, qid);
+        }
+        // the queries of any other generation are gone now
+        new.generations.clear();
+
+        Ok(new)
+    }
+
+    /// Produce a new version of this recipe that contains the same expressions as the earlier
+    /// version `version` of it. Activating the result removes any queries added since then, and
+    /// re-adds any that were removed.
+    /// Consumes `self` and returns a replacement recipe; on error, `self` is handed back.
+    pub(super) fn rollback(self, version: usize) -> Result<Recipe, (Recipe, String)> {
+        let target = {
+            let mut r = self.prior();
+            while let Some(pr) = r {
+                if pr.version == version {
+                    break;
+                }
+                r = pr.prior();
+            }
+            r.map(|pr| Recipe {
+                expressions: pr.expressions.clone(),
+                expression_order: pr.expression_order.clone(),
+                aliases: pr.aliases.clone(),
+                table_options: pr.table_options.clone(),
+                view_options: pr.view_options.clone(),
+                generations: pr.generations.clone(),
+                ..Recipe::blank(Some(self.log.clone()))
+            })
+        };
+
+        match target {
+            Some(target) => Ok(self.replace(target).unwrap()),
+            None => {
+                let e = format!(
+                    "recipe version {} is not a prior version of v{}",
+                    version, self.version
+                );
+                Err((self, e))
+            }
+        }
+    }
+
+    /// Render the expressions in this recipe back into recipe text that `Recipe::from_str` can
+    /// parse. The security configuration is not recipe text; see `security_config_text`.
+    pub(super) fn to_text(&self) -> String {
+        let mut lines = Vec::new();
+        for qid in &self.expression_order {
+            let (ref n, ref q, public) = self.expressions[qid];
+            // extra aliases are emitted first, so that re-parsing retains the expression's own name
+            let mut aliases: Vec<_> = self
+                .aliases
+                .iter()
+                .filter(|&(a, aq)| aq == qid && Some(a) != n.as_ref())
+                .map(|(a, _)| a)
+                .collect();
+            aliases.sort();
+            for a in aliases {
+                let options = self.rendered_options(Some(a), q);
+                lines.push(render_expression(Some(a), q, public, options));
+            }
+            let n = n.as_ref().map(String::as_str);
+            lines.push(render_expression(n, q, public, self.rendered_options(n, q)));
+        }
+        lines.join("\n")
+    }
+
+    /// The `WITH (...)` options of the expression `q` named `name`, as recipe text, if it has any.
+    fn rendered_options(&self, name: Option<&str>, q: &SqlQuery) -> Option<String> {
+        match *q {
+            SqlQuery::CreateTable(ref ctq) => self
+                .table_options
+                .get(&ctq.table.name)
+                .filter(|o| **o != TableOptions::default())
+                .map(TableOptions::render),
+            _ => name
+                .and_then(|n| self.view_options.get(n))
+                .filter(|o| **o != ViewOptions::default())
+                .map(ViewOptions::render),
+        }
+    }
+
     /// Replace this recipe with a new one, retaining queries that exist in both. Any queries only
     /// contained in `new` (but not in `self`) will be added; any contained in `self`, but not in
     /// `new` will be removed.
@@ -700,6 +2485,13 @@ impl Recipe {
         let prior_inc = self.inc.take();
         // retain security configuration
         new.security_config = self.security_config.take();
+        // lazy views that are kept have still not been built
+        new.deferred = self
+            .deferred
+            .iter()
+            .filter(|qid| new.expressions.contains_key(qid))
+            .cloned()
+            .collect();
         // retain the old recipe for future reference
         new.prior = Some(Box::new(self));
         // retain the previous `SqlIncorporator` state
@@ -808,6 +2600,277 @@ mod tests {
         assert_eq!(removed[0], q1_id);
     }
 
+    #[test]
+    fn it_parses_version_columns() {
+        let r = Recipe::from_str(
+            "CREATE TABLE a (id int, v int, PRIMARY KEY(id)) WITH (version_column = 'v');
+             CREATE TABLE b (id int, ts timestamp, v int) WITH (ttl = '1 day', version_column = 'v');",
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.table_options["a"].version_column, Some("v".to_owned()));
+        assert!(r.table_options["a"].ttl.is_none());
+        assert_eq!(r.table_options["b"].version_column, Some("v".to_owned()));
+        assert!(r.table_options["b"].ttl.is_some());
+
+        let r2 = Recipe::from_str(&r.to_text(), None).unwrap();
+        assert_eq!(r2.table_options, r.table_options);
+    }
+
+    #[test]
+    fn it_adds_timestamp_columns() {
+        let r = Recipe::from_str(
+            "CREATE TABLE a (id int, x int, PRIMARY KEY(id)) WITH (timestamps = 'true');",
+            None,
+        )
+        .unwrap();
+        assert!(r.table_options["a"].timestamps);
+        let columns = |r: &Recipe| {
+            r.expressions
+                .values()
+                .find_map(|(_, q, _)| match q {
+                    SqlQuery::CreateTable(ref ctq) => Some(
+                        ctq.fields
+                            .iter()
+                            .map(|f| (f.column.name.clone(), f.sql_type.clone()))
+                            .collect::<Vec<_>>(),
+                    ),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_eq!(
+            columns(&r)[2..],
+            [
+                ("_created_at".to_owned(), SqlType::Timestamp),
+                ("_updated_at".to_owned(), SqlType::Timestamp),
+            ]
+        );
+
+        // the columns are not added again when the table is read back
+        let r2 = Recipe::from_str(&r.to_text(), None).unwrap();
+        assert_eq!(r2.table_options, r.table_options);
+        assert_eq!(columns(&r2), columns(&r));
+
+        assert!(
+            Recipe::from_str("CREATE TABLE b (id int) WITH (timestamps = 'maybe');", None).is_err()
+        );
+    }
+
+    #[test]
+    fn it_parses_limit_parameters() {
+        let q =
+            "QUERY feed: SELECT id FROM post WHERE author = ? ORDER BY id DESC LIMIT ? OFFSET ?;";
+        let r = Recipe::from_str(
+            &format!("CREATE TABLE post (id int, author int);\n{}", q),
+            None,
+        )
+        .unwrap();
+        let limit = r
+            .expressions
+            .values()
+            .find_map(|(_, q, _)| match q {
+                SqlQuery::Select(ref sq) => sq.limit.clone(),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(limit.limit, LIMIT_PARAMETER);
+        assert_eq!(limit.offset, LIMIT_PARAMETER);
+
+        // the parameters are rendered back as they were written
+        assert!(r.to_text().contains("LIMIT ? OFFSET ?"));
+
+        // but question marks elsewhere are left alone
+        assert_eq!(
+            replace_limit_parameters("SELECT a FROM b WHERE c = '? limit ?' AND d = ?"),
+            "SELECT a FROM b WHERE c = '? limit ?' AND d = ?"
+        );
+    }
+
+    #[test]
+    fn it_parses_rollups() {
+        assert_eq!(
+            split_rollup("SELECT a.x, COUNT(a.y) FROM a GROUP BY a.x with  rollup;"),
+            (
+                "SELECT a.x, COUNT(a.y) FROM a GROUP BY a.x;".to_owned(),
+                true
+            )
+        );
+        let q = "SELECT a.x FROM a WHERE a.y = 'with rollup';";
+        assert_eq!(split_rollup(q), (q.to_owned(), false));
+
+        let r = Recipe::from_str(
+            "CREATE TABLE sales (region int, city int, amount int);
+             report: SELECT region, city, SUM(amount) AS total FROM sales \
+                     GROUP BY region, city WITH ROLLUP;",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            r.expressions[&r.aliases["report"]].1,
+            sql_parser::parse_query(
+                "SELECT region, city, SUM(amount) AS total, 0 AS grouping_id FROM sales \
+                     GROUP BY region, city \
+                 UNION SELECT region, NULL AS city, SUM(amount) AS total, 1 AS grouping_id \
+                     FROM sales GROUP BY region \
+                 UNION SELECT NULL AS region, NULL AS city, SUM(amount) AS total, \
+                     2 AS grouping_id FROM sales;"
+            )
+            .unwrap()
+        );
+
+        // a rollup needs something to roll up
+        assert!(Recipe::from_str(
+            "CREATE TABLE sales (region int, amount int);
+             report: SELECT SUM(amount) AS total FROM sales WITH ROLLUP;",
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_parses_filter_clauses() {
+        let parse = |q: &str| {
+            let (rest, filters) = split_filter_clauses(q);
+            let mut parsed = sql_parser::parse_query(&rest).unwrap();
+            apply_filter_clauses(&mut parsed, filters).map(|_| parsed)
+        };
+        assert_eq!(
+            parse(
+                "SELECT COUNT(*) FILTER (WHERE a.x = 1) AS c, SUM(a.y) filter(where (a.x > 2)) \
+                 FROM a GROUP BY a.z;"
+            ),
+            Ok(sql_parser::parse_query(
+                "SELECT COUNT(CASE WHEN a.x = 1 THEN 1 END) AS c, \
+                 SUM(CASE WHEN (a.x > 2) THEN a.y END) FROM a GROUP BY a.z;"
+            )
+            .unwrap())
+        );
+
+        // columns called filter, and the word in strings, are left alone
+        let q = "SELECT a.filter FROM a WHERE a.x = 'COUNT(*) FILTER (WHERE 1)';";
+        assert_eq!(split_filter_clauses(q).0, q);
+        assert!(split_filter_clauses(q).1.is_empty());
+
+        // and a FILTER on anything but an aggregate is an error
+        assert!(Recipe::from_str(
+            "CREATE TABLE a (x int, y int);
+             c: SELECT a.x FILTER (WHERE a.y = 1) FROM a;",
+            None,
+        )
+        .is_err());
+
+        let r = Recipe::from_str(
+            "CREATE TABLE a (x int, y int);
+             c: SELECT COUNT(*) FILTER (WHERE a.x = 1) AS c FROM a GROUP BY a.y;",
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.expressions.len(), 2);
+    }
+
+    #[test]
+    fn it_parses_row_comparisons() {
+        let parse = |q: &str| {
+            let (rest, rows) = split_row_comparisons(q);
+            let mut parsed = sql_parser::parse_query(&rest).unwrap();
+            apply_row_comparisons(&mut parsed, &rows).map(|_| parsed)
+        };
+        let same = |q: &str, expected: &str| {
+            assert_eq!(parse(q), Ok(sql_parser::parse_query(expected).unwrap()));
+        };
+        same(
+            "SELECT a.x FROM a WHERE (a.y, a.z) = (?, ?);",
+            "SELECT a.x FROM a WHERE (a.y = ? AND a.z = ?);",
+        );
+        same(
+            "SELECT a.x FROM a WHERE (a.y,a.z) IN ((1, 'b'), (3,'d'));",
+            "SELECT a.x FROM a WHERE ((a.y = 1 AND a.z = 'b') OR (a.y = 3 AND a.z = 'd'));",
+        );
+        same(
+            "SELECT a.x FROM a WHERE a.x = 1 AND (a.y, a.z) <> (1, 2);",
+            "SELECT a.x FROM a WHERE a.x = 1 AND (a.y != 1 OR a.z != 2);",
+        );
+        same(
+            "SELECT a.x FROM a WHERE (a.y, a.z) NOT IN ((1, 2));",
+            "SELECT a.x FROM a WHERE NOT ((a.y = 1 AND a.z = 2));",
+        );
+
+        // function arguments, lists, and rows in strings are left alone
+        let q = "SELECT a.x FROM a WHERE a.y IN (1, 2) AND a.z = '(1, 2) = (3, 4)';";
+        assert_eq!(split_row_comparisons(q).0, q);
+        assert!(split_row_comparisons(q).1.is_empty());
+
+        let r = Recipe::from_str(
+            "CREATE TABLE a (x int, y int, z int);
+             QUERY q: SELECT a.x FROM a WHERE (a.y, a.z) = (?, ?);",
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.expressions.len(), 2);
+    }
+
+    #[test]
+    fn it_parses_table_ttls() {
+        let r = Recipe::from_str(
+            "CREATE TABLE a (id int, ts timestamp) WITH (ttl = '7 days');
+             CREATE TABLE b (id int, ts int) with (TTL = '90 minutes', ttl_column = 'ts');
+             CREATE TABLE c (id int);",
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.expressions.len(), 3);
+        let ttl = |t: &str| r.table_options[t].ttl.clone().unwrap();
+        assert_eq!(ttl("a").ttl, Duration::from_secs(7 * 24 * 60 * 60));
+        assert_eq!(ttl("a").column, None);
+        assert_eq!(ttl("b").ttl, Duration::from_secs(90 * 60));
+        assert_eq!(ttl("b").column, Some("ts".to_owned()));
+        assert!(!r.table_options.contains_key("c"));
+
+        // the options survive a round-trip through recipe text
+        let r2 = Recipe::from_str(&r.to_text(), None).unwrap();
+        assert_eq!(r2.table_options, r.table_options);
+
+        assert!(Recipe::from_str("CREATE TABLE a (id int) WITH (ttl = 'soon');", None).is_err());
+        assert!(
+            Recipe::from_str("CREATE TABLE a (id int) WITH (ttl_column = 'id');", None).is_err()
+        );
+        assert!(Recipe::from_str("CREATE TABLE a (id int) WITH (size = '1');", None).is_err());
+    }
+
+    #[test]
+    fn it_parses_view_options() {
+        let r = Recipe::from_str(
+            "CREATE TABLE a (id int, x int);
+             QUERY q: SELECT x FROM a WHERE id = ? WITH (cache = 'true');
+             QUERY p: SELECT id FROM a WHERE x = ?;
+             QUERY l: SELECT id FROM a WITH (lazy = 'true', cache = 'false');
+             QUERY s: SELECT x FROM a WHERE id = ? WITH (straight_through = 'true');",
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.expressions.len(), 5);
+        assert!(r.view_options["q"].cache);
+        assert!(!r.view_options["q"].lazy);
+        assert!(!r.view_options.contains_key("p"));
+        assert!(r.view_options["l"].lazy);
+        assert!(!r.view_options["l"].cache);
+        assert!(r.view_options["s"].straight_through);
+        assert!(!r.view_options["q"].straight_through);
+
+        // the options survive a round-trip through recipe text
+        let r2 = Recipe::from_str(&r.to_text(), None).unwrap();
+        assert_eq!(r2.view_options, r.view_options);
+
+        assert!(Recipe::from_str("SELECT x FROM a WITH (cache = 'true');", None).is_err());
+        assert!(Recipe::from_str("QUERY q: SELECT x FROM a WITH (ttl = '1 day');", None).is_err());
+        assert!(Recipe::from_str(
+            "QUERY q: SELECT x FROM a WHERE id = ? WITH (cache = 'true', straight_through = 'true');",
+            None
+        )
+        .is_err());
+    }
+
     #[test]
     fn it_replaces() {
         let r0 = Recipe::blank(None);
@@ -896,4 +2959,320 @@ mod tests {
         let r1 = r0.replace(r1_t).unwrap();
         assert_eq!(r1.expressions.len(), 2);
     }
+
+    #[test]
+    fn it_reports_parse_errors() {
+        let err = Recipe::from_str("CREATE TABL b (a int);", None).unwrap_err();
+        assert!(err.contains("parse error"), "{}", err);
+    }
+
+    #[test]
+    fn it_reports_trailing_garbage() {
+        let err =
+            Recipe::from_str("QUERY q: SELECT a FROM b LOCK IN SHARE MODE;", None).unwrap_err();
+        assert!(err.contains("parse error"), "{}", err);
+    }
+
+    #[test]
+    fn it_reports_parse_error_positions() {
+        let err = Recipe::from_str(
+            "CREATE TABLE b (a int);\n\n  QUERY q: SELECT a\n    FROM b LOCK IN SHARE MODE;",
+            None,
+        )
+        .unwrap_err();
+        assert!(err.contains("line 4"), "{}", err);
+    }
+
+    #[test]
+    fn it_ignores_comments() {
+        let r_txt = "-- the schema\n\
+                     CREATE TABLE b (a int, /* the key */ c varchar(10)); # trailing\n\
+                     /* a block\n\
+                        comment; spanning lines */\n\
+                     QUERY q: SELECT a FROM b\n\
+                         WHERE c = '-- not # a comment'; -- done\n";
+        let r = Recipe::from_str(r_txt, None).unwrap();
+        assert_eq!(r.expressions.len(), 2);
+        assert_eq!(r.aliases.get("q"), Some(&r.expression_order[1]));
+    }
+
+    #[test]
+    fn it_expands_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("schema")).unwrap();
+        fs::write(
+            dir.path().join("schema/tables.sql"),
+            "CREATE TABLE b (a int, c int);\nINCLUDE 'more.sql';\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("schema/more.sql"),
+            "-- more tables\nCREATE TABLE d (a int);",
+        )
+        .unwrap();
+
+        // a recipe without includes is left alone
+        let plain = "CREATE TABLE b (a int);\n";
+        assert_eq!(expand_includes(plain, Some(dir.path())).unwrap(), plain);
+
+        let r_txt =
+            "INCLUDE 'schema/tables.sql';\nQUERY q: SELECT b.a FROM b JOIN d ON (b.a = d.a);";
+        let expanded = expand_includes(r_txt, Some(dir.path())).unwrap();
+        let r = Recipe::from_str(&expanded, None).unwrap();
+        assert_eq!(r.expressions.len(), 3);
+
+        // only the controller resolves includes
+        assert!(Recipe::from_str(r_txt, None).is_err());
+
+        let err = expand_includes("INCLUDE 'missing.sql';", Some(dir.path())).unwrap_err();
+        assert!(err.contains("missing.sql"), "{}", err);
+
+        fs::write(
+            dir.path().join("schema/more.sql"),
+            "INCLUDE \"tables.sql\";",
+        )
+        .unwrap();
+        let err = expand_includes(r_txt, Some(dir.path())).unwrap_err();
+        assert!(err.contains("includes itself"), "{}", err);
+
+        fs::write(
+            dir.path().join("schema/more.sql"),
+            "\nCREATE TABL d (a int);",
+        )
+        .unwrap();
+        let err = expand_includes(r_txt, Some(dir.path())).unwrap_err();
+        assert!(err.contains("more.sql: line 2"), "{}", err);
+        assert!(!err.contains("TABL"), "{}", err);
+
+        // includes are disabled without a recipe directory
+        let err = expand_includes(r_txt, None).unwrap_err();
+        assert!(err.contains("INCLUDE is disabled"), "{}", err);
+        assert_eq!(expand_includes(plain, None).unwrap(), plain);
+    }
+
+    #[test]
+    fn it_confines_includes_to_the_recipe_dir() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.sql"), "CREATE TABL secret;").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("recipes")).unwrap();
+        let root = dir.path().join("recipes");
+        fs::write(root.join("tables.sql"), "CREATE TABLE b (a int);").unwrap();
+
+        let secret = outside.path().join("secret.sql");
+        let r_txt = format!("INCLUDE '{}';", secret.display());
+        let err = expand_includes(&r_txt, Some(&root)).unwrap_err();
+        assert!(err.contains("must be relative"), "{}", err);
+
+        fs::copy(&secret, dir.path().join("secret.sql")).unwrap();
+        let err = expand_includes("INCLUDE '../secret.sql';", Some(&root)).unwrap_err();
+        assert!(err.contains("outside the recipe directory"), "{}", err);
+        assert!(!err.contains("TABL"), "{}", err);
+
+        // files may still climb out of subdirectories, as long as they stay inside
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/all.sql"), "INCLUDE '../tables.sql';").unwrap();
+        let expanded = expand_includes("INCLUDE 'sub/all.sql';", Some(&root)).unwrap();
+        assert_eq!(
+            Recipe::from_str(&expanded, None).unwrap().expressions.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn it_keeps_security_config_across_removals() {
+        let config = r#"{"groups": [], "policies": []}"#;
+        let mut r1 =
+            Recipe::from_str("CREATE TABLE b (a int);\nQUERY qa: SELECT a FROM b;", None).unwrap();
+        assert_eq!(r1.security_config_text(), None);
+        r1.set_security_config(config);
+
+        // the recipe text that a removal is persisted as has no room for the security config,
+        // so it has to be persisted on its own
+        let r2 = r1.remove("qa").unwrap();
+        assert!(!r2.to_text().contains("policies"));
+        assert_eq!(r2.security_config_text(), Some(config));
+    }
+
+    #[test]
+    fn it_removes_queries() {
+        let r0 = Recipe::blank(None);
+
+        let r1_txt = "CREATE TABLE b (a int, c int);\n\
+                      QUERY qa: SELECT a FROM b;\n\
+                      QUERY qb: SELECT a, c FROM b WHERE a = 42;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        assert_eq!(r1.version, 1);
+        assert_eq!(r1.expressions.len(), 3);
+
+        // base tables can't be removed
+        let r1 = match r1.remove("b") {
+            Ok(_) => unreachable!(),
+            Err((r1, _)) => r1,
+        };
+        // nor can queries that don't exist
+        let r1 = match r1.remove("qc") {
+            Ok(_) => unreachable!(),
+            Err((r1, _)) => r1,
+        };
+        assert_eq!(r1.version, 1);
+
+        let r2 = r1.remove("qb").unwrap();
+        assert_eq!(r2.version, 2);
+        assert_eq!(r2.expressions.len(), 2);
+        assert!(r2.aliases.contains_key("qa"));
+        assert!(!r2.aliases.contains_key("qb"));
+
+        // the delta to the prior version is the removed query
+        let (added, removed) = r2.compute_delta(r2.prior().unwrap());
+        assert_eq!(added.len(), 0);
+        assert_eq!(removed.len(), 1);
+
+        // the remaining recipe can be re-parsed from its text form
+        let r2_txt = r2.to_text();
+        assert!(r2_txt.contains("QUERY qa: "));
+        assert!(!r2_txt.contains("qb"));
+        let r3 = Recipe::from_str(&r2_txt, None).unwrap();
+        assert_eq!(r3.expressions.len(), 2);
+        assert!(r3.aliases.contains_key("qa"));
+    }
+
+    #[test]
+    fn it_rolls_back() {
+        let r0 = Recipe::blank(None);
+
+        let r1_txt = "CREATE TABLE b (a int, c int);\nQUERY qa: SELECT a FROM b;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        assert_eq!(r1.version, 1);
+
+        let r2 = r1.extend("QUERY qb: SELECT c FROM b;").unwrap();
+        assert_eq!(r2.version, 2);
+        assert_eq!(r2.expressions.len(), 3);
+
+        // can't roll back to a version that never existed
+        let r2 = match r2.rollback(7) {
+            Ok(_) => unreachable!(),
+            Err((r2, _)) => r2,
+        };
+
+        // rolling back produces a *new* version with the old expressions
+        let r3 = r2.rollback(1).unwrap();
+        assert_eq!(r3.version, 3);
+        assert_eq!(r3.expressions.len(), 2);
+        assert!(r3.aliases.contains_key("qa"));
+        assert!(!r3.aliases.contains_key("qb"));
+
+        let (added, removed) = r3.compute_delta(r3.prior().unwrap());
+        assert_eq!(added.len(), 0);
+        assert_eq!(removed.len(), 1);
+    }
+
+    #[test]
+    fn it_stages_and_promotes() {
+        let r0 = Recipe::blank(None);
+
+        let r1_txt = "CREATE TABLE b (a int, c int);\nQUERY qa: SELECT a FROM b WHERE c = ?;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        let old_qid = r1.aliases["qa"];
+
+        // can't stage an identical query, or a replacement for a table
+        let r1 = match r1.stage("qa", "qa_next", "SELECT a FROM b WHERE c = ?;") {
+            Ok(_) => unreachable!(),
+            Err((r1, _)) => r1,
+        };
+        let r1 = match r1.stage("b", "b_next", "SELECT a FROM b;") {
+            Ok(_) => unreachable!(),
+            Err((r1, _)) => r1,
+        };
+
+        let r2 = r1
+            .stage("qa", "qa_next", "SELECT a, c FROM b WHERE c = ?;")
+            .unwrap();
+        assert_eq!(r2.version, 2);
+        assert_eq!(r2.expressions.len(), 3);
+        assert_eq!(r2.aliases["qa"], old_qid);
+        let new_qid = r2.aliases["qa_next"];
+
+        let r3 = r2.promote("qa", "qa_next").unwrap();
+        assert_eq!(r3.version, 3);
+        assert_eq!(r3.expressions.len(), 2);
+        assert_eq!(r3.aliases["qa"], new_qid);
+        assert_eq!(r3.resolve_alias("qa"), Some("qa_next"));
+
+        let (added, removed) = r3.compute_delta(r3.prior().unwrap());
+        assert_eq!(added.len(), 0);
+        assert_eq!(removed, vec![old_qid]);
+
+        // the promoted name survives a round-trip through text
+        let r4 = Recipe::from_str(&r3.to_text(), None).unwrap();
+        assert_eq!(r4.resolve_alias("qa"), Some("qa_next"));
+    }
+
+    #[test]
+    fn it_switches_generations() {
+        let r0 = Recipe::blank(None);
+
+        let r1_txt = "CREATE TABLE b (a int, c int);\n\
+                      QUERY qa: SELECT a FROM b WHERE c = ?;\n\
+                      QUERY qb: SELECT c FROM b WHERE a = ?;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        let qb = r1.aliases["qb"];
+
+        // tables must match the ones already present
+        let r1 = match r1.stage_generation(2, "CREATE TABLE b (a int, x int);") {
+            Ok(_) => unreachable!(),
+            Err((r1, _)) => r1,
+        };
+
+        let r2_txt = "CREATE TABLE b (a int, c int);\n\
+                      CREATE TABLE d (x int);\n\
+                      QUERY qa: SELECT a, c FROM b WHERE c = ?;\n\
+                      QUERY qb: SELECT c FROM b WHERE a = ?;\n\
+                      QUERY qd: SELECT x FROM d;";
+        let r2 = r1.stage_generation(2, r2_txt).unwrap();
+        assert_eq!(r2.version, 2);
+        // the new table, and the one changed query (qb is shared), and qd
+        assert_eq!(r2.expressions.len(), 6);
+        assert_eq!(r2.aliases["qb_g2"], qb);
+        assert!(r2.aliases.contains_key("qa_g2"));
+        assert!(r2.aliases.contains_key("qd_g2"));
+
+        let r2 = match r2.switch_generation(7) {
+            Ok(_) => unreachable!(),
+            Err((r2, _)) => r2,
+        };
+        let r3 = r2.switch_generation(2).unwrap();
+        assert_eq!(r3.version, 3);
+        assert_eq!(r3.expressions.len(), 5);
+        assert_eq!(r3.aliases["qa"], r3.aliases["qa_g2"]);
+        assert_eq!(r3.aliases["qb"], qb);
+        assert_eq!(r3.aliases["qd"], r3.aliases["qd_g2"]);
+
+        assert!(r3.generations.is_empty());
+
+        let (added, removed) = r3.compute_delta(r3.prior().unwrap());
+        assert_eq!(added.len(), 0);
+        assert_eq!(removed.len(), 1);
+    }
+
+    #[test]
+    fn it_only_switches_to_staged_generations() {
+        let r0 = Recipe::blank(None);
+
+        // a query that merely looks like it was staged is not part of a generation
+        let r1_txt = "CREATE TABLE b (a int, c int);\n\
+                      QUERY qa: SELECT a FROM b WHERE c = ?;\n\
+                      QUERY qa_g2: SELECT c FROM b WHERE a = ?;";
+        let r1_t = Recipe::from_str(r1_txt, None).unwrap();
+        let r1 = r0.replace(r1_t).unwrap();
+        let r1 = match r1.switch_generation(2) {
+            Ok(_) => unreachable!(),
+            Err((r1, _)) => r1,
+        };
+        assert_eq!(r1.resolve_alias("qa"), Some("qa"));
+    }
 }
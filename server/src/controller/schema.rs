@@ -80,6 +80,30 @@ fn type_for_internal_column(
                 unreachable!();
             }
         }
+        ops::NodeOperator::Window(_) => {
+            // both the window start and the aggregated value are integral
+            Some(SqlType::Bigint(64))
+        }
+        ops::NodeOperator::Sketch(ref o) => {
+            // the estimate is always emitted last
+            assert_eq!(column_index, node.fields().len() - 1);
+            match *o.kind() {
+                ops::sketch::SketchKind::ApproxCountDistinct => Some(SqlType::Bigint(64)),
+                ops::sketch::SketchKind::ApproxQuantile(_) => Some(SqlType::Real),
+            }
+        }
+        ops::NodeOperator::Bitwise(ref o) => {
+            // the aggregated value is always emitted last
+            assert_eq!(column_index, node.fields().len() - 1);
+            match o.op() {
+                ops::bitwise::Bitwise::BitAnd | ops::bitwise::Bitwise::BitOr => {
+                    Some(SqlType::Bigint(64))
+                }
+                ops::bitwise::Bitwise::BoolAnd | ops::bitwise::Bitwise::BoolOr => {
+                    Some(SqlType::Bool)
+                }
+            }
+        }
         ops::NodeOperator::Join(_) => {
             // join doesn't "generate" columns, but they may come from one of the other
             // ancestors; so keep iterating to try the other paths
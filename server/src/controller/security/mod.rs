@@ -12,6 +12,8 @@ use crate::controller::security::policy::Policy;
 pub struct SecurityConfig {
     pub groups: HashMap<String, Group>,
     policies: Vec<Policy>,
+    /// The configuration as it was given, so that it can be persisted.
+    text: String,
 }
 
 impl SecurityConfig {
@@ -33,9 +35,14 @@ impl SecurityConfig {
         SecurityConfig {
             groups: groups_map,
             policies,
+            text: policy_text.to_owned(),
         }
     }
 
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
     pub fn policies(&self) -> &[Policy] {
         self.policies.as_slice()
     }
@@ -0,0 +1,671 @@
+//! Running a query once over the current contents of the tables it reads, without adding any
+//! dataflow for it.
+//!
+//! This is what `query_once` uses. The query is evaluated directly over the rows of its tables, so
+//! it only supports the common shapes of queries that are run by hand: joins of tables, filters,
+//! projections, simple aggregations, `DISTINCT`, `ORDER BY`, and `LIMIT`. Queries that go beyond
+//! that are reported as unsupported, and should be added to the recipe as a view instead.
+
+use dataflow::ops::filter::Value;
+use dataflow::prelude::DataType;
+use nom_sql::{
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, FieldValueExpression, FunctionArguments, FunctionExpression,
+    JoinConstraint, JoinOperator, JoinRightSide, Literal, Operator, OrderType, SelectStatement,
+    Table,
+};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::{unsupported, LIMIT_PARAMETER};
+
+/// The current contents of a table, and the names of its columns.
+pub(in crate::controller) struct Relation {
+    pub(in crate::controller) columns: Vec<String>,
+    pub(in crate::controller) rows: Vec<Vec<DataType>>,
+}
+
+/// The names of the tables that `q` reads.
+pub(in crate::controller) fn tables(q: &SelectStatement) -> Result<Vec<String>, String> {
+    let mut tables: Vec<String> = q.tables.iter().map(|t| t.name.clone()).collect();
+    for jc in &q.join {
+        match jc.right {
+            JoinRightSide::Table(ref t) => tables.push(t.name.clone()),
+            _ => return Err(unsupported("joins with anything but a table")),
+        }
+    }
+    tables.sort();
+    tables.dedup();
+    Ok(tables)
+}
+
+/// Evaluate `q` with `params` for its placeholders over the rows of `tables`, and return the
+/// names of its columns along with its rows.
+pub(in crate::controller) fn execute(
+    q: &SelectStatement,
+    params: &[DataType],
+    tables: &HashMap<String, Relation>,
+) -> Result<(Vec<String>, Vec<Vec<DataType>>), String> {
+    let mut params = params.iter();
+
+    // the first table of the query, and any others that it lists, are joined as a cross product
+    // that the WHERE clause then filters
+    let mut scope = Scope::default();
+    let mut rows = vec![vec![]];
+    for t in &q.tables {
+        let r = scope.add(t, tables)?;
+        rows = rows
+            .into_iter()
+            .flat_map(|row| r.rows.iter().map(move |rr| concat(&row, rr)))
+            .collect();
+    }
+
+    for jc in &q.join {
+        let t = match jc.right {
+            JoinRightSide::Table(ref t) => t,
+            _ => return Err(unsupported("joins with anything but a table")),
+        };
+        let left_join = match jc.operator {
+            JoinOperator::Join | JoinOperator::InnerJoin => false,
+            JoinOperator::LeftJoin | JoinOperator::LeftOuterJoin => true,
+            ref op => return Err(unsupported(format!("{}", op))),
+        };
+        let r = scope.add(t, tables)?;
+        let on = match jc.constraint {
+            JoinConstraint::On(ref ce) => scope.condition(ce, &mut params)?,
+            JoinConstraint::Using(_) => return Err(unsupported("JOIN ... USING")),
+        };
+        let mut joined = Vec::new();
+        for row in rows {
+            let before = joined.len();
+            for rr in &r.rows {
+                let row = concat(&row, rr);
+                if on.eval(&row) == Some(true) {
+                    joined.push(row);
+                }
+            }
+            if left_join && joined.len() == before {
+                joined.push(concat(&row, &vec![DataType::None; r.columns.len()]));
+            }
+        }
+        rows = joined;
+    }
+
+    if let Some(ref ce) = q.where_clause {
+        let filter = scope.condition(ce, &mut params)?;
+        rows.retain(|row| filter.eval(row) == Some(true));
+    }
+    if params.next().is_some() {
+        return Err("query has fewer parameters than were given".to_owned());
+    }
+
+    // work out what each output column is computed from
+    let mut columns = Vec::new();
+    let mut fields = Vec::new();
+    for f in &q.fields {
+        match *f {
+            FieldDefinitionExpression::All => {
+                for (i, c) in scope.columns.iter().enumerate() {
+                    columns.push(c.name.clone());
+                    fields.push(Field::Value(Value::Column(i)));
+                }
+            }
+            FieldDefinitionExpression::AllInTable(ref t) => {
+                for (i, c) in scope.columns.iter().enumerate() {
+                    if c.relation == *t {
+                        columns.push(c.name.clone());
+                        fields.push(Field::Value(Value::Column(i)));
+                    }
+                }
+            }
+            FieldDefinitionExpression::Col(ref c) => {
+                columns.push(c.alias.clone().unwrap_or_else(|| c.name.clone()));
+                fields.push(match c.function {
+                    Some(ref f) => Field::Aggregate(scope.aggregate(f)?),
+                    None => Field::Value(Value::Column(scope.find(c)?)),
+                });
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Literal(ref l)) => {
+                columns.push(l.alias.clone().unwrap_or_else(|| l.value.to_string()));
+                fields.push(Field::Value(Value::Constant(DataType::from(&l.value))));
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref ae)) => {
+                columns.push(ae.alias.clone().unwrap_or_else(|| ae.to_string()));
+                fields.push(Field::Value(Value::Expression(
+                    ae.op.clone(),
+                    Box::new(scope.arithmetic_base(&ae.left)?),
+                    Box::new(scope.arithmetic_base(&ae.right)?),
+                )));
+            }
+        }
+    }
+
+    let grouped = q.group_by.is_some()
+        || fields.iter().any(|f| match *f {
+            Field::Aggregate(_) => true,
+            Field::Value(_) => false,
+        });
+    let mut out: Vec<Vec<DataType>> = if grouped {
+        let mut group_cols = Vec::new();
+        if let Some(ref gb) = q.group_by {
+            if gb.having.is_some() {
+                return Err(unsupported("HAVING"));
+            }
+            for c in &gb.columns {
+                group_cols.push(scope.find(c)?);
+            }
+        }
+        let mut groups: Vec<Vec<&Vec<DataType>>> = Vec::new();
+        let mut group_of: HashMap<Vec<&DataType>, usize> = HashMap::new();
+        for row in &rows {
+            let key = group_cols.iter().map(|&c| &row[c]).collect();
+            let i = *group_of.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[i].push(row);
+        }
+        // an aggregation over all rows has a result even if there are no rows
+        if groups.is_empty() && group_cols.is_empty() {
+            groups.push(Vec::new());
+        }
+        groups
+            .into_iter()
+            .map(|group| {
+                fields
+                    .iter()
+                    .map(|f| match *f {
+                        Field::Aggregate(ref a) => a.eval(&group),
+                        Field::Value(ref v) => match group.first() {
+                            Some(row) => v.eval(row).into_owned(),
+                            None => DataType::None,
+                        },
+                    })
+                    .collect()
+            })
+            .collect()
+    } else {
+        rows.iter()
+            .map(|row| {
+                fields
+                    .iter()
+                    .map(|f| match *f {
+                        Field::Value(ref v) => v.eval(row).into_owned(),
+                        Field::Aggregate(_) => unreachable!(),
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+
+    if q.distinct {
+        let mut seen = std::collections::HashSet::new();
+        out.retain(|row| seen.insert(row.clone()));
+    }
+
+    if let Some(ref order) = q.order {
+        let mut by = Vec::new();
+        for (c, o) in &order.columns {
+            let i = columns.iter().position(|oc| *oc == c.name).ok_or_else(|| {
+                unsupported(format!("ORDER BY a column that is not selected: {}", c))
+            })?;
+            by.push((i, *o == OrderType::OrderDescending));
+        }
+        out.sort_by(|a, b| {
+            by.iter()
+                .map(|&(i, desc)| {
+                    let o = a[i].cmp(&b[i]);
+                    if desc {
+                        o.reverse()
+                    } else {
+                        o
+                    }
+                })
+                .find(|&o| o != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+
+    if let Some(ref limit) = q.limit {
+        if limit.limit == LIMIT_PARAMETER || limit.offset == LIMIT_PARAMETER {
+            return Err(unsupported("LIMIT ?"));
+        }
+        out = out
+            .into_iter()
+            .skip(limit.offset as usize)
+            .take(limit.limit as usize)
+            .collect();
+    }
+
+    Ok((columns, out))
+}
+
+fn concat(left: &[DataType], right: &[DataType]) -> Vec<DataType> {
+    let mut row = Vec::with_capacity(left.len() + right.len());
+    row.extend_from_slice(left);
+    row.extend_from_slice(right);
+    row
+}
+
+/// A column of the rows that the query is evaluated over.
+struct ScopeColumn {
+    /// The name that the query refers to the column's table by, which is its alias if it has one.
+    relation: String,
+    table: String,
+    name: String,
+}
+
+/// The columns of the rows that the query is evaluated over, in order.
+#[derive(Default)]
+struct Scope {
+    columns: Vec<ScopeColumn>,
+}
+
+impl Scope {
+    /// Add the columns of table `t` to the scope, and return its rows.
+    fn add<'a>(
+        &mut self,
+        t: &Table,
+        tables: &'a HashMap<String, Relation>,
+    ) -> Result<&'a Relation, String> {
+        let r = tables
+            .get(&t.name)
+            .ok_or_else(|| format!("query refers to unknown table \"{}\"", t.name))?;
+        let relation = t.alias.clone().unwrap_or_else(|| t.name.clone());
+        self.columns
+            .extend(r.columns.iter().map(|name| ScopeColumn {
+                relation: relation.clone(),
+                table: t.name.clone(),
+                name: name.clone(),
+            }));
+        Ok(r)
+    }
+
+    /// The index of the column that `c` refers to.
+    fn find(&self, c: &Column) -> Result<usize, String> {
+        // a column's table may be given by its alias or, if that is unambiguous, by its name
+        let matching = |by_name: bool| {
+            self.columns
+                .iter()
+                .enumerate()
+                .filter(|(_, sc)| {
+                    sc.name == c.name
+                        && match c.table {
+                            None => true,
+                            Some(ref t) => *t == sc.relation || (by_name && *t == sc.table),
+                        }
+                })
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        let mut found = matching(false);
+        if found.is_empty() {
+            found = matching(true);
+        }
+        match found[..] {
+            [i] => Ok(i),
+            [] => Err(format!("query refers to unknown column {}", c)),
+            _ => Err(format!("column {} is ambiguous", c)),
+        }
+    }
+
+    fn arithmetic_base(&self, b: &ArithmeticBase) -> Result<Value, String> {
+        Ok(match *b {
+            ArithmeticBase::Column(ref c) => Value::Column(self.find(c)?),
+            ArithmeticBase::Scalar(ref l) => Value::Constant(DataType::from(l)),
+        })
+    }
+
+    /// The value that one side of a comparison stands for.
+    fn value<'a>(
+        &self,
+        ce: &ConditionExpression,
+        params: &mut impl Iterator<Item = &'a DataType>,
+    ) -> Result<Value, String> {
+        Ok(match *ce {
+            ConditionExpression::Base(ConditionBase::Field(ref c)) => Value::Column(self.find(c)?),
+            ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => {
+                Value::Constant(
+                    params
+                        .next()
+                        .ok_or_else(|| "query has more parameters than were given".to_owned())?
+                        .clone(),
+                )
+            }
+            ConditionExpression::Base(ConditionBase::Literal(ref l)) => {
+                Value::Constant(DataType::from(l))
+            }
+            ConditionExpression::Arithmetic(ref ae) => Value::Expression(
+                ae.op.clone(),
+                Box::new(self.arithmetic_base(&ae.left)?),
+                Box::new(self.arithmetic_base(&ae.right)?),
+            ),
+            ConditionExpression::Bracketed(ref inner) => self.value(inner, params)?,
+            _ => return Err(unsupported(format!("comparisons with {}", ce))),
+        })
+    }
+
+    /// The condition that `ce` stands for.
+    fn condition<'a>(
+        &self,
+        ce: &ConditionExpression,
+        params: &mut impl Iterator<Item = &'a DataType>,
+    ) -> Result<Condition, String> {
+        Ok(match *ce {
+            ConditionExpression::LogicalOp(ConditionTree {
+                ref operator,
+                ref left,
+                ref right,
+            }) => {
+                let (left, right) = (
+                    Box::new(self.condition(left, params)?),
+                    Box::new(self.condition(right, params)?),
+                );
+                match *operator {
+                    Operator::And => Condition::And(left, right),
+                    Operator::Or => Condition::Or(left, right),
+                    ref op => return Err(unsupported(format!("logical operator {}", op))),
+                }
+            }
+            ConditionExpression::ComparisonOp(ConditionTree {
+                ref operator,
+                ref left,
+                ref right,
+            }) => match (operator, &**right) {
+                (Operator::In, ConditionExpression::Base(ConditionBase::LiteralList(ref ls))) => {
+                    Condition::In(
+                        self.value(left, params)?,
+                        ls.iter().map(DataType::from).collect(),
+                    )
+                }
+                (Operator::Equal, _)
+                | (Operator::NotEqual, _)
+                | (Operator::Greater, _)
+                | (Operator::GreaterOrEqual, _)
+                | (Operator::Less, _)
+                | (Operator::LessOrEqual, _) => Condition::Compare(
+                    self.value(left, params)?,
+                    operator.clone(),
+                    self.value(right, params)?,
+                ),
+                _ => return Err(unsupported(format!("comparison operator {}", operator))),
+            },
+            ConditionExpression::NegationOp(ref inner) => {
+                Condition::Not(Box::new(self.condition(inner, params)?))
+            }
+            ConditionExpression::Bracketed(ref inner) => self.condition(inner, params)?,
+            _ => return Err(unsupported(format!("condition {}", ce))),
+        })
+    }
+
+    fn aggregate(&self, f: &FunctionExpression) -> Result<Aggregate, String> {
+        use nom_sql::FunctionExpression::*;
+
+        let column = |arg: &FunctionArguments| match *arg {
+            FunctionArguments::Column(ref c) => self.find(c),
+            FunctionArguments::Conditional(_) => {
+                Err(unsupported("aggregations over CASE WHEN in query_once"))
+            }
+        };
+        Ok(match *f {
+            CountStar => Aggregate::CountStar,
+            Count(ref arg, distinct) => Aggregate::Count(column(arg)?, distinct),
+            Sum(ref arg, distinct) => Aggregate::Sum(column(arg)?, distinct),
+            Avg(ref arg, distinct) => Aggregate::Avg(column(arg)?, distinct),
+            Min(ref arg) => Aggregate::Min(column(arg)?),
+            Max(ref arg) => Aggregate::Max(column(arg)?),
+            ref f => return Err(unsupported(format!("{} in query_once", f))),
+        })
+    }
+}
+
+/// A condition on a row, which is unknown when it compares a `NULL`.
+enum Condition {
+    Compare(Value, Operator, Value),
+    In(Value, Vec<DataType>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    fn eval(&self, r: &[DataType]) -> Option<bool> {
+        match *self {
+            Condition::Compare(ref left, ref op, ref right) => {
+                let (left, right) = (left.eval(r), right.eval(r));
+                let (left, right) = (&*left, &*right);
+                if left.is_none() || right.is_none() {
+                    return None;
+                }
+                Some(match *op {
+                    Operator::Equal => left == right,
+                    Operator::NotEqual => left != right,
+                    Operator::Greater => left > right,
+                    Operator::GreaterOrEqual => left >= right,
+                    Operator::Less => left < right,
+                    Operator::LessOrEqual => left <= right,
+                    _ => unreachable!(),
+                })
+            }
+            Condition::In(ref v, ref list) => {
+                let v = v.eval(r);
+                if v.is_none() {
+                    None
+                } else {
+                    Some(list.contains(&*v))
+                }
+            }
+            Condition::And(ref left, ref right) => match (left.eval(r), right.eval(r)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Condition::Or(ref left, ref right) => match (left.eval(r), right.eval(r)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Condition::Not(ref inner) => inner.eval(r).map(|b| !b),
+        }
+    }
+}
+
+/// An output column of a query.
+enum Field {
+    Value(Value),
+    Aggregate(Aggregate),
+}
+
+/// An aggregation over a column of the rows of a group. Aggregations other than `COUNT(*)` leave
+/// out `NULL`s, and those that take a flag only consider distinct values if it is set.
+enum Aggregate {
+    CountStar,
+    Count(usize, bool),
+    Sum(usize, bool),
+    Avg(usize, bool),
+    Min(usize),
+    Max(usize),
+}
+
+impl Aggregate {
+    fn eval(&self, group: &[&Vec<DataType>]) -> DataType {
+        let values = |c: usize, distinct: bool| {
+            let mut vs: Vec<&DataType> = group
+                .iter()
+                .map(|r| &r[c])
+                .filter(|v| !v.is_none())
+                .collect();
+            if distinct {
+                vs.sort();
+                vs.dedup();
+            }
+            vs
+        };
+        let sum = |vs: &[&DataType]| vs.iter().skip(1).fold((*vs[0]).clone(), |acc, &v| &acc + v);
+        match *self {
+            Aggregate::CountStar => group.len().into(),
+            Aggregate::Count(c, distinct) => values(c, distinct).len().into(),
+            Aggregate::Sum(c, distinct) => {
+                let vs = values(c, distinct);
+                if vs.is_empty() {
+                    DataType::None
+                } else {
+                    sum(&vs)
+                }
+            }
+            Aggregate::Avg(c, distinct) => {
+                let vs = values(c, distinct);
+                if vs.is_empty() {
+                    DataType::None
+                } else {
+                    (f64::from(&sum(&vs)) / vs.len() as f64).into()
+                }
+            }
+            Aggregate::Min(c) => values(c, false)
+                .into_iter()
+                .min()
+                .cloned()
+                .unwrap_or(DataType::None),
+            Aggregate::Max(c) => values(c, false)
+                .into_iter()
+                .max()
+                .cloned()
+                .unwrap_or(DataType::None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom_sql::parser::parse_query;
+    use nom_sql::SqlQuery;
+
+    fn fixture() -> HashMap<String, Relation> {
+        let mut tables = HashMap::new();
+        tables.insert(
+            "article".to_owned(),
+            Relation {
+                columns: vec!["id".to_owned(), "author".to_owned(), "title".to_owned()],
+                rows: vec![
+                    vec![1.into(), 1.into(), "a".into()],
+                    vec![2.into(), 1.into(), "b".into()],
+                    vec![3.into(), 2.into(), "c".into()],
+                    vec![4.into(), DataType::None, "d".into()],
+                ],
+            },
+        );
+        tables.insert(
+            "author".to_owned(),
+            Relation {
+                columns: vec!["id".to_owned(), "name".to_owned()],
+                rows: vec![vec![1.into(), "x".into()], vec![3.into(), "z".into()]],
+            },
+        );
+        tables
+    }
+
+    fn run(q: &str, params: &[DataType]) -> Result<Vec<Vec<DataType>>, String> {
+        let q = match parse_query(q).unwrap() {
+            SqlQuery::Select(q) => q,
+            _ => unreachable!(),
+        };
+        execute(&q, params, &fixture()).map(|(_, rows)| rows)
+    }
+
+    #[test]
+    fn filters_and_projects() {
+        assert_eq!(
+            run(
+                "SELECT title FROM article WHERE id > ? AND author = 1;",
+                &[1.into()]
+            )
+            .unwrap(),
+            vec![vec!["b".into()]]
+        );
+        assert_eq!(
+            run(
+                "SELECT id FROM article WHERE NOT (author = 1) ORDER BY id DESC;",
+                &[]
+            )
+            .unwrap(),
+            vec![vec![3.into()]]
+        );
+        assert_eq!(
+            run(
+                "SELECT id FROM article WHERE id IN (1, 4, 5) ORDER BY id;",
+                &[]
+            )
+            .unwrap(),
+            vec![vec![1.into()], vec![4.into()]]
+        );
+        assert_eq!(
+            run("SELECT id FROM article ORDER BY id LIMIT 2 OFFSET 1;", &[]).unwrap(),
+            vec![vec![2.into()], vec![3.into()]]
+        );
+        assert!(run("SELECT id FROM article WHERE id = ?;", &[]).is_err());
+        assert!(run(
+            "SELECT id FROM article WHERE id = ?;",
+            &[1.into(), 2.into()]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn joins() {
+        assert_eq!(
+            run(
+                "SELECT article.id, author.name FROM article \
+                 JOIN author ON (article.author = author.id) ORDER BY id;",
+                &[]
+            )
+            .unwrap(),
+            vec![vec![1.into(), "x".into()], vec![2.into(), "x".into()]]
+        );
+        assert_eq!(
+            run(
+                "SELECT article.id, author.name FROM article \
+                 LEFT JOIN author ON (article.author = author.id) ORDER BY id;",
+                &[]
+            )
+            .unwrap(),
+            vec![
+                vec![1.into(), "x".into()],
+                vec![2.into(), "x".into()],
+                vec![3.into(), DataType::None],
+                vec![4.into(), DataType::None],
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregates() {
+        assert_eq!(
+            run("SELECT COUNT(*), COUNT(author), MAX(id) FROM article;", &[]).unwrap(),
+            vec![vec![4.into(), 3.into(), 4.into()]]
+        );
+        assert_eq!(
+            run(
+                "SELECT author, COUNT(*) AS n FROM article WHERE id < 4 \
+                 GROUP BY author ORDER BY author;",
+                &[]
+            )
+            .unwrap(),
+            vec![vec![1.into(), 2.into()], vec![2.into(), 1.into()]]
+        );
+        // aggregations over no rows still produce a row
+        assert_eq!(
+            run("SELECT COUNT(*), SUM(id) FROM article WHERE id > 10;", &[]).unwrap(),
+            vec![vec![0.into(), DataType::None]]
+        );
+        assert_eq!(
+            run(
+                "SELECT COUNT(*) FROM article WHERE id > 10 GROUP BY author;",
+                &[]
+            )
+            .unwrap(),
+            Vec::<Vec<DataType>>::new()
+        );
+    }
+}
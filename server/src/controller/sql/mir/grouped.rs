@@ -1,10 +1,10 @@
 use crate::controller::sql::mir::SqlToMirConverter;
-use crate::controller::sql::query_graph::{QueryGraph, QueryGraphEdge};
+use crate::controller::sql::query_graph::{OutputColumn, QueryGraph, QueryGraphEdge};
 use mir::{Column, MirNodeRef};
 use nom_sql::FunctionExpression::*;
 use nom_sql::{
-    self, CaseWhenExpression, ColumnOrLiteral, ConditionExpression, FunctionArguments,
-    FunctionExpression,
+    self, ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ConditionExpression,
+    FunctionArguments, FunctionExpression,
 };
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
@@ -41,6 +41,31 @@ fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Column
     }
 }
 
+/// The arithmetic expressions in the field list of a query that it groups by (through their
+/// aliases), along with those aliases.
+pub(super) fn group_by_expressions(qg: &QueryGraph) -> Vec<(String, ArithmeticExpression)> {
+    let grouped: Vec<&nom_sql::Column> = qg
+        .edges
+        .values()
+        .flat_map(|e| match *e {
+            QueryGraphEdge::GroupBy(ref cols) => {
+                cols.iter().filter(|c| c.table.is_none()).collect()
+            }
+            _ => vec![],
+        })
+        .collect();
+
+    qg.columns
+        .iter()
+        .filter_map(|oc| match *oc {
+            OutputColumn::Arithmetic(ref ac) if grouped.iter().any(|c| c.name == ac.name) => {
+                Some((ac.name.clone(), ac.expression.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
 // Move predicates above grouped_by nodes
 pub(super) fn make_predicates_above_grouped<'a>(
     mir_converter: &SqlToMirConverter,
@@ -114,6 +139,37 @@ pub(super) fn make_grouped(
                 })
                 .collect();
 
+            // grouping by an expression needs the expression to be computed first
+            let group_exprs = group_by_expressions(qg);
+            let agg_parent = if group_exprs.is_empty() {
+                prev_node.clone()
+            } else {
+                let parent = match *prev_node {
+                    Some(ref node) => node.clone(),
+                    None => {
+                        let over_col =
+                            target_columns_from_computed_column(&computed_cols_cgn.columns[0]);
+                        node_for_rel[over_col.table.as_ref().unwrap().as_str()].clone()
+                    }
+                };
+                let passthru_cols: Vec<_> = parent.borrow().columns().to_vec();
+                let proj = mir_converter.make_project_node(
+                    &format!("{}_n{}", name, node_count),
+                    parent,
+                    passthru_cols.iter().collect(),
+                    group_exprs,
+                    vec![],
+                    false,
+                );
+                node_count += 1;
+                func_nodes.push(proj.clone());
+                Some(proj)
+            };
+
+            // every aggregate is computed over the same input, and the results are then joined
+            // together on their group columns
+            let mut aggregates: Vec<(MirNodeRef, Vec<Column>)> = Vec::new();
+
             for computed_col in computed_cols_cgn.columns.iter() {
                 let computed_col = if is_reconcile {
                     let func = computed_col.function.as_ref().unwrap();
@@ -165,7 +221,7 @@ pub(super) fn make_grouped(
                 let over_col = target_columns_from_computed_column(&computed_col);
                 let over_table = over_col.table.as_ref().unwrap().as_str();
 
-                let parent_node = match *prev_node {
+                let parent_node = match agg_parent {
                     // If no explicit parent node is specified, we extract
                     // the base node from the "over" column's specification
                     None => node_for_rel[over_table].clone(),
@@ -183,8 +239,8 @@ pub(super) fn make_grouped(
                     for e in &gb_edges {
                         match **e {
                             QueryGraphEdge::GroupBy(ref gbc) => {
-                                let table = gbc.first().unwrap().table.as_ref().unwrap();
-                                assert!(gbc.iter().all(|c| c.table.as_ref().unwrap() == table));
+                                let table = &gbc.first().unwrap().table;
+                                assert!(gbc.iter().all(|c| c.table == *table));
                                 gb_cols.extend(gbc);
                             }
                             _ => unreachable!(),
@@ -274,10 +330,28 @@ pub(super) fn make_grouped(
                     parent_node,
                 );
 
-                *prev_node = Some(nodes.last().unwrap().clone());
+                aggregates.push((nodes.last().unwrap().clone(), group_cols));
                 node_count += nodes.len();
                 func_nodes.extend(nodes);
             }
+
+            let mut aggregates = aggregates.into_iter();
+            let (mut joined, group_cols) = aggregates.next().unwrap();
+            for (agg, agg_group_cols) in aggregates {
+                assert_eq!(
+                    group_cols, agg_group_cols,
+                    "aggregates in the same query must have the same groups"
+                );
+                joined = mir_converter.make_aggregate_join_node(
+                    &format!("{}_n{}", name, node_count),
+                    joined,
+                    agg,
+                    &group_cols,
+                );
+                node_count += 1;
+                func_nodes.push(joined.clone());
+            }
+            *prev_node = Some(joined);
         }
     }
 
@@ -4,17 +4,19 @@ use mir::{Column, MirNodeRef};
 use noria::DataType;
 use petgraph::graph::NodeIndex;
 // TODO(malte): remove if possible
-use dataflow::ops::filter::FilterCondition;
+use dataflow::ops::filter::{self, FilterCondition};
 use dataflow::ops::join::JoinType;
 
-use crate::controller::sql::query_graph::{OutputColumn, QueryGraph};
+use crate::controller::sql::passes::alias_removal::relation_table;
+use crate::controller::sql::passes::negation_removal::negate;
+use crate::controller::sql::query_graph::{LiteralColumn, OutputColumn, QueryGraph};
 use crate::controller::sql::query_signature::Signature;
 use nom_sql::{
-    ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
+    ArithmeticBase, ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
     CompoundSelectOperator, ConditionBase, ConditionExpression, ConditionTree, Literal, Operator,
     SqlQuery, TableKey,
 };
-use nom_sql::{LimitClause, OrderClause, SelectStatement};
+use nom_sql::{LimitClause, OrderClause, OrderType, SelectStatement};
 
 use slog;
 use std::collections::{HashMap, HashSet};
@@ -23,7 +25,7 @@ use std::ops::Deref;
 use std::vec::Vec;
 
 use crate::controller::sql::security::Universe;
-use crate::controller::sql::UniverseId;
+use crate::controller::sql::{UniverseId, DISJUNCTION_KEY, LIMIT_PARAMETER, MAX_PAGE_ROWS};
 
 mod grouped;
 mod join;
@@ -36,6 +38,51 @@ fn sanitize_leaf_column(c: &mut Column, view_name: &str) {
     c.aliases = vec![];
 }
 
+/// The columns an `ORDER BY` clause sorts by, in MIR form.
+pub(super) fn order_columns(order: &Option<OrderClause>) -> Option<Vec<(Column, OrderType)>> {
+    order.as_ref().map(|o| {
+        o.columns
+            .iter()
+            .map(|(c, o)| (Column::from(c), o.clone()))
+            .collect()
+    })
+}
+
+/// The number of rows of each key that a `LIMIT` keeps. For `LIMIT ?`, this is the bound that
+/// the pages read from the view must fall within.
+fn limit_rows(limit: &LimitClause) -> usize {
+    if limit.limit == LIMIT_PARAMETER {
+        MAX_PAGE_ROWS
+    } else {
+        limit.limit as usize
+    }
+}
+
+/// Check that a `LIMIT` clause can be planned.
+pub(super) fn check_limit(limit: &LimitClause, order: &Option<OrderClause>) -> Result<(), String> {
+    if limit.limit == LIMIT_PARAMETER {
+        // pages are only well-defined if the rows of each key have an order
+        if order.is_none() {
+            return Err("LIMIT ? requires an ORDER BY".to_owned());
+        }
+    } else if limit.offset == LIMIT_PARAMETER {
+        return Err("OFFSET ? requires LIMIT ?".to_owned());
+    }
+    Ok(())
+}
+
+/// Find the column among `columns` that an `ORDER BY` column refers to. Columns that are not
+/// qualified with a table may also refer to an output column by its (aliased) name.
+fn find_order_column<'a>(columns: &'a [Column], c: &Column) -> Option<&'a Column> {
+    columns.iter().find(|pc| *pc == c).or_else(|| {
+        if c.table.is_none() {
+            columns.iter().find(|pc| pc.name == c.name)
+        } else {
+            None
+        }
+    })
+}
+
 /// Returns all collumns used in a predicate
 fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
     use nom_sql::ConditionExpression::*;
@@ -52,6 +99,13 @@ fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
         Bracketed(ref ce) => {
             cols.extend(predicate_columns(&ce));
         }
+        Arithmetic(ref ae) => {
+            for b in &[&ae.left, &ae.right] {
+                if let ArithmeticBase::Column(ref c) = **b {
+                    cols.insert(Column::from(c));
+                }
+            }
+        }
         NegationOp(_) => unreachable!("negations should have been eliminated"),
         _ => (),
     }
@@ -59,6 +113,133 @@ fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
     cols
 }
 
+// Collects the disjuncts of an OR predicate.
+fn collect_disjuncts<'a>(ce: &'a ConditionExpression, out: &mut Vec<&'a ConditionExpression>) {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::Or,
+            ref left,
+            ref right,
+        }) => {
+            collect_disjuncts(left, out);
+            collect_disjuncts(right, out);
+        }
+        ConditionExpression::Bracketed(ref inner) => collect_disjuncts(inner, out),
+        _ => out.push(ce),
+    }
+}
+
+// Returns the disjuncts as comparisons if they all compare the same column.
+fn same_column_comparisons<'a>(
+    disjuncts: &[&'a ConditionExpression],
+) -> Option<Vec<&'a ConditionTree>> {
+    let mut column = None;
+    let mut cts = Vec::new();
+    for d in disjuncts {
+        let ct = match **d {
+            ConditionExpression::ComparisonOp(ref ct) => ct,
+            _ => return None,
+        };
+        let c = match *ct.left {
+            ConditionExpression::Base(ConditionBase::Field(ref c)) => c,
+            _ => return None,
+        };
+        match *ct.right {
+            ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => return None,
+            ConditionExpression::Base(ConditionBase::Literal(_))
+            | ConditionExpression::Base(ConditionBase::LiteralList(_))
+            | ConditionExpression::Base(ConditionBase::Field(_)) => (),
+            _ => return None,
+        }
+        if *column.get_or_insert(c) != c {
+            return None;
+        }
+        cts.push(ct);
+    }
+    Some(cts)
+}
+
+// Collects the conjuncts of an AND predicate.
+fn collect_conjuncts<'a>(ce: &'a ConditionExpression, out: &mut Vec<&'a ConditionExpression>) {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            ref left,
+            ref right,
+        }) => {
+            collect_conjuncts(left, out);
+            collect_conjuncts(right, out);
+        }
+        ConditionExpression::Bracketed(ref inner) => collect_conjuncts(inner, out),
+        _ => out.push(ce),
+    }
+}
+
+// Returns the columns and the tuples of literals that they are compared to if every disjunct
+// compares the same columns for equality, as row comparisons like `(a, b) IN ((1, 2), (3, 4))`
+// do.
+fn tuple_equalities<'a>(
+    disjuncts: &[&'a ConditionExpression],
+) -> Option<(Vec<&'a nom_sql::Column>, Vec<Vec<&'a Literal>>)> {
+    let mut columns: Option<Vec<&nom_sql::Column>> = None;
+    let mut tuples = Vec::new();
+    for d in disjuncts {
+        let mut conjuncts = Vec::new();
+        collect_conjuncts(d, &mut conjuncts);
+        let mut equalities = Vec::new();
+        for c in conjuncts {
+            match *c {
+                ConditionExpression::ComparisonOp(ConditionTree {
+                    operator: Operator::Equal,
+                    ref left,
+                    ref right,
+                }) => match (left.as_ref(), right.as_ref()) {
+                    (
+                        ConditionExpression::Base(ConditionBase::Field(_)),
+                        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+                    ) => return None,
+                    (
+                        ConditionExpression::Base(ConditionBase::Field(ref f)),
+                        ConditionExpression::Base(ConditionBase::Literal(ref l)),
+                    ) => equalities.push((f, l)),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+
+        let columns = columns.get_or_insert_with(|| equalities.iter().map(|e| e.0).collect());
+        if columns.len() < 2 || equalities.len() != columns.len() {
+            return None;
+        }
+        let tuple = columns
+            .iter()
+            .map(|c| equalities.iter().find(|e| e.0 == *c).map(|e| e.1))
+            .collect::<Option<Vec<_>>>()?;
+        tuples.push(tuple);
+    }
+    Some((columns?, tuples))
+}
+
+// Collects the equalities that make up a (possibly conjunctive) join predicate.
+fn join_equalities<'a>(jp: &'a ConditionTree, out: &mut Vec<&'a ConditionTree>) {
+    if jp.operator == Operator::And {
+        for side in &[&jp.left, &jp.right] {
+            let mut side: &ConditionExpression = side;
+            while let ConditionExpression::Bracketed(ref inner) = *side {
+                side = inner;
+            }
+            match *side {
+                ConditionExpression::ComparisonOp(ref ct)
+                | ConditionExpression::LogicalOp(ref ct) => join_equalities(ct, out),
+                _ => unimplemented!(),
+            }
+        }
+    } else {
+        out.push(jp);
+    }
+}
+
 fn value_columns_needed_for_predicates(
     value_columns: &[OutputColumn],
     predicates: &[ConditionExpression],
@@ -196,6 +377,46 @@ impl SqlToMirConverter {
         }
     }
 
+    /// Converts a side of a comparison into the value that it compares against, looking up the
+    /// columns that it refers to among the `columns` of `n`.
+    fn to_filter_value(
+        &self,
+        ce: &ConditionExpression,
+        columns: &[Column],
+        n: &MirNodeRef,
+    ) -> filter::Value {
+        // NOTE(jon): what if two columns share a name, but differ in .table?
+        let column = |f: &nom_sql::Column| {
+            let pos = columns
+                .iter()
+                .rposition(|c| *c == Column::from(f))
+                .or_else(|| columns.iter().rposition(|c| c.name == f.name))
+                .unwrap_or_else(|| panic!("filter refers to unknown column {}", f));
+            filter::Value::Column(n.borrow().column_id_for_column(&columns[pos], None))
+        };
+        let base = |b: &ArithmeticBase| match *b {
+            ArithmeticBase::Column(ref f) => column(f),
+            ArithmeticBase::Scalar(ref l) => filter::Value::Constant(DataType::from(l)),
+        };
+
+        match *ce {
+            ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => {
+                unimplemented!()
+            }
+            ConditionExpression::Base(ConditionBase::Literal(ref l)) => {
+                filter::Value::Constant(DataType::from(l.clone()))
+            }
+            ConditionExpression::Base(ConditionBase::Field(ref f)) => column(f),
+            ConditionExpression::Arithmetic(ref ae) => filter::Value::Expression(
+                ae.op.clone(),
+                Box::new(base(&ae.left)),
+                Box::new(base(&ae.right)),
+            ),
+            ConditionExpression::Bracketed(ref inner) => self.to_filter_value(inner, columns, n),
+            _ => unimplemented!(),
+        }
+    }
+
     /// Converts a condition tree stored in the `ConditionExpr` returned by the SQL parser
     /// and adds its to a vector of conditions.
     fn to_conditions(
@@ -206,42 +427,38 @@ impl SqlToMirConverter {
     ) -> Vec<(usize, FilterCondition)> {
         use std::cmp::max;
 
-        // TODO(malte): we only support one level of condition nesting at this point :(
-        let l = match *ct.left.as_ref() {
-            ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
-            _ => unimplemented!(),
-        };
-        use dataflow::ops::filter;
         let f = match *ct.right.as_ref() {
-            ConditionExpression::Base(ConditionBase::Literal(Literal::Integer(ref i))) => {
-                FilterCondition::Comparison(
-                    ct.operator.clone(),
-                    filter::Value::Constant(DataType::from(*i)),
-                )
-            }
-            ConditionExpression::Base(ConditionBase::Literal(Literal::String(ref s))) => {
-                FilterCondition::Comparison(
-                    ct.operator.clone(),
-                    filter::Value::Constant(DataType::from(s.clone())),
-                )
-            }
-            ConditionExpression::Base(ConditionBase::Literal(Literal::Null)) => {
-                FilterCondition::Comparison(
-                    ct.operator.clone(),
-                    filter::Value::Constant(DataType::None),
-                )
-            }
             ConditionExpression::Base(ConditionBase::LiteralList(ref ll)) => {
                 FilterCondition::In(ll.iter().map(|l| DataType::from(l.clone())).collect())
             }
-            ConditionExpression::Base(ConditionBase::Field(ref f)) => {
-                // NOTE(jon): the uwnrap here is almost certainly wrong given the business
-                // that goes on further down where it appens a column in magical circumstances.
-                // also, what if two columns share a name, but differ in .table?
-                let fi = columns.iter().rposition(|c| *c.name == f.name).unwrap();
-                FilterCondition::Comparison(ct.operator.clone(), filter::Value::Column(fi))
+            ref right => FilterCondition::Comparison(
+                ct.operator.clone(),
+                self.to_filter_value(right, columns, n),
+            ),
+        };
+
+        // TODO(malte): we only support one level of condition nesting at this point :(
+        let l = match *ct.left.as_ref() {
+            ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
+            ref left => {
+                // an expression compares the same way whatever column the condition is on, so
+                // it goes on the first column that it reads
+                let left = self.to_filter_value(left, columns, n);
+                let right = match f {
+                    FilterCondition::Comparison(_, right) => right,
+                    _ => unimplemented!(),
+                };
+                let column = left
+                    .columns()
+                    .into_iter()
+                    .chain(right.columns())
+                    .next()
+                    .unwrap_or(0);
+                return vec![(
+                    column,
+                    FilterCondition::Expression(left, ct.operator.clone(), right),
+                )];
             }
-            _ => unimplemented!(),
         };
 
         let absolute_column_ids: Vec<usize> = columns
@@ -335,6 +552,9 @@ impl SqlToMirConverter {
             MirNodeType::Leaf {
                 node: parent.clone(),
                 keys: Vec::from(params),
+                order: None,
+                limit: None,
+                echoed: vec![],
             },
             vec![n],
             vec![],
@@ -412,6 +632,14 @@ impl SqlToMirConverter {
         }
 
         let leaf_node = if has_leaf {
+            // the union's columns are named after those of its first query, which is what the
+            // ORDER BY of a compound query refers to
+            let order = order_columns(order).map(|order| {
+                order
+                    .into_iter()
+                    .filter_map(|(c, o)| Some((find_order_column(&columns, &c)?.clone(), o)))
+                    .collect()
+            });
             MirNode::new(
                 name,
                 self.schema_version,
@@ -419,6 +647,9 @@ impl SqlToMirConverter {
                 MirNodeType::Leaf {
                     node: final_node.clone(),
                     keys: vec![],
+                    order,
+                    limit: limit.as_ref().map(limit_rows),
+                    echoed: vec![],
                 },
                 vec![final_node.clone()],
                 vec![],
@@ -981,6 +1212,105 @@ impl SqlToMirConverter {
         )
     }
 
+    fn make_disjunction_filter_node(
+        &self,
+        name: &str,
+        parent: MirNodeRef,
+        cts: &[&ConditionTree],
+    ) -> MirNodeRef {
+        let mut fields = parent.borrow().columns().to_vec();
+
+        let mut column = None;
+        let mut conditions = Vec::new();
+        for ct in cts {
+            let (col, cond) = self.to_conditions(ct, &mut fields, &parent).pop().unwrap();
+            column = Some(col);
+            conditions.push(cond);
+        }
+        let filter = vec![(column.unwrap(), FilterCondition::Any(conditions))];
+        trace!(
+            self.log,
+            "Added filter node {} with condition {:?}",
+            name,
+            filter
+        );
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            MirNodeType::Filter { conditions: filter },
+            vec![parent.clone()],
+            vec![],
+        )
+    }
+
+    fn make_tuple_filter_node(
+        &self,
+        name: &str,
+        parent: MirNodeRef,
+        columns: &[&nom_sql::Column],
+        tuples: &[Vec<&Literal>],
+    ) -> MirNodeRef {
+        let fields = parent.borrow().columns().to_vec();
+
+        let columns: Vec<usize> = columns
+            .iter()
+            .map(|&c| {
+                let field = ConditionExpression::Base(ConditionBase::Field(c.clone()));
+                match self.to_filter_value(&field, &fields, &parent) {
+                    filter::Value::Column(i) => i,
+                    _ => unreachable!(),
+                }
+            })
+            .collect();
+        let tuples = tuples
+            .iter()
+            .map(|t| t.iter().map(|&l| DataType::from(l)).collect())
+            .collect();
+        let filter = vec![(columns[0], FilterCondition::InTuples(columns, tuples))];
+        trace!(
+            self.log,
+            "Added filter node {} with condition {:?}",
+            name,
+            filter
+        );
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            MirNodeType::Filter { conditions: filter },
+            vec![parent.clone()],
+            vec![],
+        )
+    }
+
+    /// Make a projection that passes on all columns of `parent_node`, and also emits `column` as
+    /// the key column of a view over a disjunction of parameters.
+    fn make_disjunction_key_node(
+        &self,
+        name: &str,
+        parent_node: MirNodeRef,
+        column: &Column,
+    ) -> MirNodeRef {
+        let mut emit = parent_node.borrow().columns().to_vec();
+        let mut fields = emit.clone();
+        emit.push(column.clone());
+        fields.push(Column::new(None, DISJUNCTION_KEY));
+
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            MirNodeType::Project {
+                emit,
+                literals: vec![],
+                arithmetic: vec![],
+            },
+            vec![parent_node],
+            vec![],
+        )
+    }
+
     fn make_function_node(
         &self,
         name: &str,
@@ -1229,6 +1559,44 @@ impl SqlToMirConverter {
         }
     }
 
+    /// Join the outputs of two aggregations over the same groups, so that a query can compute
+    /// several aggregates. The group columns appear in the output only once.
+    fn make_aggregate_join_node(
+        &self,
+        name: &str,
+        left_node: MirNodeRef,
+        right_node: MirNodeRef,
+        group_by: &[Column],
+    ) -> MirNodeRef {
+        let fields: Vec<Column> = left_node
+            .borrow()
+            .columns()
+            .iter()
+            .cloned()
+            .chain(
+                right_node
+                    .borrow()
+                    .columns()
+                    .iter()
+                    .filter(|c| !group_by.contains(c))
+                    .cloned(),
+            )
+            .collect();
+
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields.clone(),
+            MirNodeType::Join {
+                on_left: group_by.to_vec(),
+                on_right: group_by.to_vec(),
+                project: fields,
+            },
+            vec![left_node, right_node],
+            vec![],
+        )
+    }
+
     fn make_join_node(
         &self,
         name: &str,
@@ -1243,7 +1611,7 @@ impl SqlToMirConverter {
         // automatic column pull-down to retrieve the remaining columns required.
         let projected_cols_left = left_node.borrow().columns().to_vec();
         let projected_cols_right = right_node.borrow().columns().to_vec();
-        let fields = projected_cols_left
+        let mut fields = projected_cols_left
             .into_iter()
             .chain(projected_cols_right.into_iter())
             .collect::<Vec<Column>>();
@@ -1253,44 +1621,49 @@ impl SqlToMirConverter {
         let mut left_join_columns = Vec::new();
         let mut right_join_columns = Vec::new();
 
-        // equi-join only
-        assert!(jp.operator == Operator::Equal || jp.operator == Operator::In);
-        let mut l_col = match *jp.left {
-            ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
-            _ => unimplemented!(),
-        };
-        let r_col = match *jp.right {
-            ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
-            _ => unimplemented!(),
-        };
+        // a conjunction of equalities joins on all of their columns at once
+        let mut jps = Vec::new();
+        join_equalities(jp, &mut jps);
+        for jp in jps {
+            // equi-join only
+            assert!(jp.operator == Operator::Equal || jp.operator == Operator::In);
+            let mut l_col = match *jp.left {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
+                _ => unimplemented!(),
+            };
+            let r_col = match *jp.right {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
+                _ => unimplemented!(),
+            };
 
-        // don't duplicate the join column in the output, but instead add aliases to the columns
-        // that represent it going forward (viz., the left-side join column)
-        l_col.add_alias(&r_col);
-        // add the alias to all instances of `l_col` in `fields` (there might be more than one
-        // if `l_col` is explicitly projected multiple times)
-        let fields: Vec<Column> = fields
-            .into_iter()
-            .filter_map(|mut f| {
-                if f == r_col {
-                    // drop instances of right-side column
-                    None
-                } else if f == l_col {
-                    // add alias for right-side column to any left-side column
-                    // N.B.: since `l_col` is already aliased, need to check this *after* checking
-                    // for equivalence with `r_col` (by now, `l_col` == `r_col` via alias), so
-                    // `f == l_col` also triggers if `f` is in `l_col.aliases`.
-                    f.add_alias(&r_col);
-                    Some(f)
-                } else {
-                    // keep unaffected columns
-                    Some(f)
-                }
-            })
-            .collect();
+            // don't duplicate the join column in the output, but instead add aliases to the
+            // columns that represent it going forward (viz., the left-side join column)
+            l_col.add_alias(&r_col);
+            // add the alias to all instances of `l_col` in `fields` (there might be more than one
+            // if `l_col` is explicitly projected multiple times)
+            fields = fields
+                .into_iter()
+                .filter_map(|mut f| {
+                    if f == r_col {
+                        // drop instances of right-side column
+                        None
+                    } else if f == l_col {
+                        // add alias for right-side column to any left-side column
+                        // N.B.: since `l_col` is already aliased, need to check this *after*
+                        // checking for equivalence with `r_col` (by now, `l_col` == `r_col` via
+                        // alias), so `f == l_col` also triggers if `f` is in `l_col.aliases`.
+                        f.add_alias(&r_col);
+                        Some(f)
+                    } else {
+                        // keep unaffected columns
+                        Some(f)
+                    }
+                })
+                .collect();
 
-        left_join_columns.push(l_col);
-        right_join_columns.push(r_col);
+            left_join_columns.push(l_col);
+            right_join_columns.push(r_col);
+        }
 
         assert_eq!(left_join_columns.len(), right_join_columns.len());
         let inner = match kind {
@@ -1386,6 +1759,34 @@ impl SqlToMirConverter {
         )
     }
 
+    /// Make a projection that emits all columns of `parent_node` as columns of `relation`, so
+    /// that the instances of a table in a self-join have distinct columns.
+    fn make_relation_alias_node(
+        &self,
+        name: &str,
+        relation: &str,
+        parent_node: MirNodeRef,
+    ) -> MirNodeRef {
+        let emit = parent_node.borrow().columns().to_vec();
+        let fields = emit
+            .iter()
+            .map(|c| Column::new(Some(relation), &c.name))
+            .collect();
+
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            MirNodeType::Project {
+                emit,
+                literals: vec![],
+                arithmetic: vec![],
+            },
+            vec![parent_node],
+            vec![],
+        )
+    }
+
     fn make_distinct_node(
         &self,
         name: &str,
@@ -1415,18 +1816,10 @@ impl SqlToMirConverter {
         limit: &LimitClause,
     ) -> MirNodeRef {
         let combined_columns = parent.borrow().columns().to_vec();
+        let order = order_columns(order);
 
-        let order = match *order {
-            Some(ref o) => Some(
-                o.columns
-                    .iter()
-                    .map(|(c, o)| (Column::from(c), o.clone()))
-                    .collect(),
-            ),
-            None => None,
-        };
-
-        assert_eq!(limit.offset, 0); // Non-zero offset not supported
+        // Non-zero offset not supported, except as a parameter that each lookup gives
+        assert!(limit.offset == 0 || limit.offset == LIMIT_PARAMETER);
 
         // make the new operator and record its metadata
         MirNode::new(
@@ -1436,7 +1829,7 @@ impl SqlToMirConverter {
             MirNodeType::TopK {
                 order,
                 group_by: group_by.into_iter().cloned().collect(),
-                k: limit.limit as usize,
+                k: limit_rows(limit),
                 offset: 0,
             },
             vec![parent.clone()],
@@ -1456,49 +1849,74 @@ impl SqlToMirConverter {
         let mut pred_nodes: Vec<MirNodeRef> = Vec::new();
         let output_cols = parent.borrow().columns().to_vec();
         match *ce {
-            LogicalOp(ref ct) => {
-                let (left, right);
-                match ct.operator {
-                    Operator::And => {
-                        left = self.make_predicate_nodes(name, parent.clone(), &*ct.left, nc);
-
-                        right = self.make_predicate_nodes(
-                            name,
-                            left.last().unwrap().clone(),
-                            &*ct.right,
-                            nc + left.len(),
-                        );
+            LogicalOp(ref ct) => match ct.operator {
+                Operator::And => {
+                    let left = self.make_predicate_nodes(name, parent.clone(), &*ct.left, nc);
 
-                        pred_nodes.extend(left.clone());
-                        pred_nodes.extend(right.clone());
-                    }
-                    Operator::Or => {
-                        left = self.make_predicate_nodes(name, parent.clone(), &*ct.left, nc);
+                    let right = self.make_predicate_nodes(
+                        name,
+                        left.last().unwrap().clone(),
+                        &*ct.right,
+                        nc + left.len(),
+                    );
 
-                        right = self.make_predicate_nodes(
-                            name,
-                            parent.clone(),
-                            &*ct.right,
-                            nc + left.len(),
+                    pred_nodes.extend(left);
+                    pred_nodes.extend(right);
+                }
+                Operator::Or => {
+                    let mut disjuncts = Vec::new();
+                    collect_disjuncts(ce, &mut disjuncts);
+
+                    if let Some((columns, tuples)) = tuple_equalities(&disjuncts) {
+                        // row comparisons are evaluated by a single filter over all the columns
+                        let f = self.make_tuple_filter_node(
+                            &format!("{}_f{}", name, nc),
+                            parent,
+                            &columns,
+                            &tuples,
                         );
-
+                        pred_nodes.push(f);
+                    } else if let Some(cts) = same_column_comparisons(&disjuncts) {
+                        // comparisons on a single column are evaluated by a single filter
+                        let f = self.make_disjunction_filter_node(
+                            &format!("{}_f{}", name, nc),
+                            parent,
+                            &cts,
+                        );
+                        pred_nodes.push(f);
+                    } else {
                         debug!(self.log, "Creating union node for `or` predicate");
 
-                        let last_left = left.last().unwrap().clone();
-                        let last_right = right.last().unwrap().clone();
+                        // every disjunct gets a branch of filters, and the branches are unioned.
+                        // so that rows that match several disjuncts are only emitted once, each
+                        // branch excludes the rows that the branches before it match.
+                        let mut branches = Vec::new();
+                        let mut nc = nc;
+                        for (i, d) in disjuncts.iter().enumerate() {
+                            let branch = disjuncts[..i].iter().fold((*d).clone(), |acc, prev| {
+                                LogicalOp(ConditionTree {
+                                    operator: Operator::And,
+                                    left: Box::new(acc),
+                                    right: Box::new(negate(prev)),
+                                })
+                            });
+                            let nodes =
+                                self.make_predicate_nodes(name, parent.clone(), &branch, nc);
+                            nc += nodes.len();
+                            branches.push(nodes.last().unwrap().clone());
+                            pred_nodes.extend(nodes);
+                        }
+
                         let union = self.make_union_from_same_base(
-                            &format!("{}_un", name),
-                            vec![last_left, last_right],
+                            &format!("{}_un{}", name, nc),
+                            branches,
                             output_cols,
                         );
-
-                        pred_nodes.extend(left.clone());
-                        pred_nodes.extend(right.clone());
                         pred_nodes.push(union);
                     }
-                    _ => unreachable!("LogicalOp operator is {:?}", ct.operator),
                 }
-            }
+                _ => unreachable!("LogicalOp operator is {:?}", ct.operator),
+            },
             ComparisonOp(ref ct) => {
                 // currently, we only support filter-like
                 // comparison operations, no nested-selections
@@ -1621,6 +2039,7 @@ impl SqlToMirConverter {
         String,
     > {
         // TODO: make this take &self!
+        use crate::controller::sql::mir::grouped::group_by_expressions;
         use crate::controller::sql::mir::grouped::make_grouped;
         use crate::controller::sql::mir::grouped::make_predicates_above_grouped;
         use crate::controller::sql::mir::join::make_joins;
@@ -1649,15 +2068,49 @@ impl SqlToMirConverter {
             let mut base_nodes: Vec<MirNodeRef> = Vec::new();
             let mut sorted_rels: Vec<&str> = qg.relations.keys().map(String::as_str).collect();
             sorted_rels.sort();
+            let mut alias_nodes: Vec<MirNodeRef> = Vec::new();
             for rel in &sorted_rels {
                 if *rel == "computed_columns" {
                     continue;
                 }
 
-                let base_for_rel = self.get_view(rel)?;
+                let table = relation_table(rel);
+                let base_for_rel = match base_nodes.iter().find(|n| n.borrow().name() == table) {
+                    Some(n) => n.clone(),
+                    None => {
+                        let n = self.get_view(table)?;
+                        base_nodes.push(n.clone());
+                        n
+                    }
+                };
+
+                let mut node = if table != *rel {
+                    // each instance of a self-joined table reads the table under its own name
+                    let alias_node = self.make_relation_alias_node(
+                        &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                        rel,
+                        base_for_rel,
+                    );
+                    new_node_count += 1;
+                    alias_nodes.push(alias_node.clone());
+                    alias_node
+                } else {
+                    base_for_rel
+                };
 
-                base_nodes.push(base_for_rel.clone());
-                node_for_rel.insert(*rel, base_for_rel);
+                // the relation's rows are filtered by its join predicates before it is joined
+                for p in &qg.relations[*rel].join_predicates {
+                    let fns = self.make_predicate_nodes(
+                        &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                        node,
+                        p,
+                        0,
+                    );
+                    new_node_count += fns.len();
+                    node = fns.last().unwrap().clone();
+                    alias_nodes.extend(fns);
+                }
+                node_for_rel.insert(*rel, node);
             }
 
             let join_nodes = make_joins(
@@ -1764,6 +2217,7 @@ impl SqlToMirConverter {
 
             nodes_added = base_nodes
                 .into_iter()
+                .chain(alias_nodes.into_iter())
                 .chain(join_nodes.into_iter())
                 .chain(predicates_above_group_by_nodes.into_iter())
                 .chain(policy_nodes.into_iter())
@@ -1879,6 +2333,70 @@ impl SqlToMirConverter {
                     predicate_nodes.extend(fns);
                 }
 
+                // 5. A disjunction of parameters becomes one branch per parameter column, each of
+                //    which exposes that column as the key of the view
+                if !qg.parameter_disjunction.is_empty() {
+                    if !func_nodes.is_empty() || st.limit.is_some() {
+                        return Err(crate::controller::sql::unsupported(
+                            "disjunctions of parameters are not supported with aggregations or \
+                             LIMIT",
+                        ));
+                    }
+
+                    let parent = match prev_node {
+                        Some(ref pn) => pn.clone(),
+                        None => node_for_rel[sorted_rels.last().unwrap()].clone(),
+                    };
+                    let mut branches = Vec::new();
+                    for (i, column) in qg.parameter_disjunction.iter().enumerate() {
+                        let mut branch = parent.clone();
+                        // rows that an earlier branch also matches under the same key are left
+                        // to that branch, so that every row is returned once
+                        for earlier in &qg.parameter_disjunction[..i] {
+                            let ct = ConditionTree {
+                                operator: Operator::NotEqual,
+                                left: Box::new(ConditionExpression::Base(ConditionBase::Field(
+                                    earlier.clone(),
+                                ))),
+                                right: Box::new(ConditionExpression::Base(ConditionBase::Field(
+                                    column.clone(),
+                                ))),
+                            };
+                            branch = self.make_filter_node(
+                                &format!(
+                                    "q_{:x}_n{}{}",
+                                    qg.signature().hash,
+                                    new_node_count,
+                                    uformat
+                                ),
+                                branch,
+                                &ct,
+                            );
+                            new_node_count += 1;
+                            predicate_nodes.push(branch.clone());
+                        }
+
+                        branch = self.make_disjunction_key_node(
+                            &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                            branch,
+                            &Column::from(column),
+                        );
+                        new_node_count += 1;
+                        predicate_nodes.push(branch.clone());
+                        branches.push(branch);
+                    }
+
+                    let columns = branches[0].borrow().columns().to_vec();
+                    let union = self.make_union_from_same_base(
+                        &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                        branches,
+                        columns,
+                    );
+                    new_node_count += 1;
+                    predicate_nodes.push(union.clone());
+                    prev_node = Some(union);
+                }
+
                 // 6. Get the final node
                 let mut final_node: MirNodeRef = if prev_node.is_some() {
                     prev_node.unwrap().clone()
@@ -1987,18 +2505,50 @@ impl SqlToMirConverter {
                     projected_columns.push(pc);
                 }
             }
+            let disjunction_key = if qg.parameter_disjunction.is_empty() {
+                None
+            } else {
+                let key = Column::new(None, DISJUNCTION_KEY);
+                projected_columns.push(key.clone());
+                Some(key)
+            };
 
-            // We may already have added some of the arithmetic and literal columns
+            // the reader sorts the rows of each key by the ORDER BY columns, so those must be
+            // projected too
+            let order = if has_leaf {
+                order_columns(&st.order)
+            } else {
+                None
+            };
+            let order = order.map(|order| {
+                order
+                    .into_iter()
+                    .map(|(c, o)| match find_order_column(&projected_columns, &c) {
+                        Some(pc) => (pc.clone(), o),
+                        None => {
+                            projected_columns.push(c.clone());
+                            (c, o)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            });
+
+            // We may already have added some of the arithmetic and literal columns, either for
+            // predicates or to group by them
             let (_, already_computed): (Vec<_>, Vec<_>) =
                 value_columns_needed_for_predicates(&qg.columns, &qg.global_predicates)
                     .into_iter()
                     .unzip();
+            let grouped_exprs: Vec<String> = group_by_expressions(qg)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
             let projected_arithmetic: Vec<(String, ArithmeticExpression)> = qg
                 .columns
                 .iter()
                 .filter_map(|oc| match *oc {
                     OutputColumn::Arithmetic(ref ac) => {
-                        if !already_computed.contains(oc) {
+                        if !already_computed.contains(oc) && !grouped_exprs.contains(&ac.name) {
                             Some((ac.name.clone(), ac.expression.clone()))
                         } else {
                             projected_columns.push(Column::new(None, &ac.name));
@@ -2017,7 +2567,12 @@ impl SqlToMirConverter {
                     OutputColumn::Data(_) => None,
                     OutputColumn::Literal(ref lc) => {
                         if !already_computed.contains(oc) {
-                            Some((lc.name.clone(), DataType::from(&lc.value)))
+                            // the reader's clients fill in the values of parameters
+                            let value = match lc.value {
+                                Literal::Placeholder => DataType::None,
+                                ref l => DataType::from(l),
+                            };
+                            Some((lc.name.clone(), value))
                         } else {
                             projected_columns.push(Column::new(None, &lc.name));
                             None
@@ -2027,7 +2582,8 @@ impl SqlToMirConverter {
                 .collect();
 
             // if this query does not have any parameters, we must add a bogokey
-            let has_bogokey = if has_leaf && qg.parameters().is_empty() {
+            let has_bogokey = if has_leaf && qg.parameters().is_empty() && disjunction_key.is_none()
+            {
                 // only add the bogokey if we haven't already added it prior to a TopK above
                 if !projected_columns.contains(&Column::new(None, "bogokey")) {
                     projected_literals.push(("bogokey".into(), DataType::from(0 as i32)));
@@ -2071,9 +2627,26 @@ impl SqlToMirConverter {
                 let query_params = if has_bogokey {
                     vec![Column::new(None, "bogokey")]
                 } else {
-                    qg.parameters().into_iter().map(Column::from).collect()
+                    qg.parameters()
+                        .into_iter()
+                        .map(Column::from)
+                        .chain(disjunction_key)
+                        .collect()
                 };
 
+                let echoed = qg
+                    .columns
+                    .iter()
+                    .filter_map(|oc| match *oc {
+                        OutputColumn::Literal(LiteralColumn {
+                            ref name,
+                            value: Literal::Placeholder,
+                            ..
+                        }) => Some(Column::new(None, name)),
+                        _ => None,
+                    })
+                    .collect();
+
                 let leaf_node = MirNode::new(
                     name,
                     self.schema_version,
@@ -2081,6 +2654,9 @@ impl SqlToMirConverter {
                     MirNodeType::Leaf {
                         node: leaf_project_node.clone(),
                         keys: query_params,
+                        order,
+                        limit: st.limit.as_ref().map(limit_rows),
+                        echoed,
                     },
                     vec![leaf_project_node.clone()],
                     vec![],
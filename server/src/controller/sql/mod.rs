@@ -1,3 +1,4 @@
+pub(super) mod adhoc;
 mod mir;
 mod passes;
 mod query_graph;
@@ -10,7 +11,7 @@ use self::mir::SqlToMirConverter;
 use self::query_graph::{to_query_graph, QueryGraph};
 use self::query_signature::Signature;
 use self::reuse::ReuseConfig;
-use super::mir_to_flow::mir_query_to_flow_parts;
+use super::mir_to_flow::{is_straight_through, mir_query_to_flow_parts};
 use crate::controller::Migration;
 use crate::ReuseConfigType;
 use ::mir::query::{MirQuery, QueryFlowParts};
@@ -19,20 +20,45 @@ use ::mir::Column;
 use ::mir::MirNodeRef;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
-use nom_sql::{ArithmeticBase, CreateTableStatement, SqlQuery};
+use nom_sql::{ArithmeticBase, CreateTableStatement, Literal, SqlQuery};
 use nom_sql::{CompoundSelectOperator, CompoundSelectStatement, SelectStatement};
+use noria::debug::plan::{MirNodePlan, QueryReuse};
 use petgraph::graph::NodeIndex;
 
 use slog;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::str;
 use std::vec::Vec;
 
+/// The limit and offset that `LIMIT ?` and `OFFSET ?` are parsed into, since nom-sql only parses
+/// literal limits. Each lookup then gives its own.
+pub(in crate::controller) const LIMIT_PARAMETER: u64 = std::u64::MAX;
+
+/// How many rows of each key the views of `LIMIT ?` queries keep, which bounds the pages that can
+/// be read from them.
+pub(in crate::controller) const MAX_PAGE_ROWS: usize = 100;
+
+/// Errors from planning a query start with this if the query uses SQL that Noria does not
+/// support, rather than being wrong in itself.
+pub(in crate::controller) const UNSUPPORTED: &str = "unsupported SQL: ";
+
+/// The error for a query that uses SQL that Noria does not support.
+pub(in crate::controller) fn unsupported<D: std::fmt::Display>(what: D) -> String {
+    format!("{}{}", UNSUPPORTED, what)
+}
+
+/// The key column of the views of queries with a disjunction of parameters (`a = ? OR b = ?`),
+/// which holds the value that one of the disjunction's columns matched.
+pub(in crate::controller) const DISJUNCTION_KEY: &str = "disjunction_key";
+
 type UniverseId = (DataType, Option<DataType>);
 
 #[derive(Clone, Debug)]
 enum QueryGraphReuse {
-    ExactMatch(MirNodeRef),
+    /// name of the existing query, and its leaf
+    ExactMatch(String, MirNodeRef),
     ExtendExisting(Vec<(u64, UniverseId)>),
     /// (node, columns to re-project if necessary, parameters)
     ReaderOntoExisting(MirNodeRef, Option<Vec<Column>>, Vec<Column>),
@@ -65,6 +91,25 @@ pub(crate) struct SqlIncorporator {
     /// Active universes mapped to the group they belong to.
     /// If an user universe, mapped to None.
     universes: HashMap<Option<DataType>, Vec<UniverseId>>,
+
+    /// How many rows each base table held when state statistics were last collected.
+    table_rows: HashMap<String, usize>,
+
+    /// The tombstone column of each base table that uses soft deletes.
+    tombstones: HashMap<String, String>,
+
+    /// The query graphs of queries whose nodes must not be reused by other queries: those of a
+    /// cached view are beyond the materialization frontier, the reader of a straight-through view
+    /// forgets keys once they have been read, and an aggregation that splits hot keys emits
+    /// partial rows for them.
+    unshared: HashSet<u64>,
+
+    /// The views whose aggregations spread the records of hot keys over their shards. Only their
+    /// readers add up the rows that each shard computes for a key, so no query may read from them.
+    split_views: HashSet<String>,
+
+    /// What each query reused of the queries that were already there when it was added.
+    reuse_decisions: HashMap<String, QueryReuse>,
 }
 
 impl Default for SqlIncorporator {
@@ -87,6 +132,11 @@ impl Default for SqlIncorporator {
 
             reuse_type: ReuseConfigType::Finkelstein,
             universes: HashMap::default(),
+            table_rows: HashMap::default(),
+            tombstones: HashMap::default(),
+            unshared: HashSet::default(),
+            split_views: HashSet::default(),
+            reuse_decisions: HashMap::default(),
         }
     }
 }
@@ -114,6 +164,27 @@ impl SqlIncorporator {
         self.reuse_type = reuse_type;
     }
 
+    /// Set how many rows each base table is known to hold, so that new queries can join the
+    /// smallest tables first.
+    pub(super) fn set_table_rows(&mut self, rows: HashMap<String, usize>) {
+        self.table_rows = rows;
+    }
+
+    /// Stop offering the nodes of query `name` to queries added from now on, since its aggregation
+    /// spreads the records of hot keys over its shards.
+    pub(super) fn forbid_reuse_of_split(&mut self, name: &str) {
+        if let Some(&qg_hash) = self.named_queries.get(name) {
+            self.unshared.insert(qg_hash);
+        }
+        self.split_views.insert(name.to_owned());
+    }
+
+    /// Hide the rows of base table `table` that are marked as deleted in column `column` from
+    /// queries added from now on.
+    pub(super) fn set_tombstone_column(&mut self, table: &str, column: &str) {
+        self.tombstones.insert(table.to_owned(), column.to_owned());
+    }
+
     /// Incorporates a single query into via the flow graph migration in `mig`. The `query`
     /// argument is a string that holds a parameterized SQL query, and the `name` argument supplies
     /// an optional name for the query. If no `name` is specified, the table name is used in the
@@ -152,6 +223,97 @@ impl SqlIncorporator {
         }
     }
 
+    /// Like `add_parsed_query`, but for a cached view, which neither reuses the nodes of other
+    /// queries nor lets them reuse its own.
+    pub(super) fn add_cached_query(
+        &mut self,
+        query: SqlQuery,
+        name: String,
+        is_leaf: bool,
+        mig: &mut Migration,
+    ) -> Result<QueryFlowParts, String> {
+        let reuse_type = mem::replace(&mut self.reuse_type, ReuseConfigType::NoReuse);
+        let qfp = self.nodes_for_named_query(query, name.clone(), is_leaf, mig);
+        self.reuse_type = reuse_type;
+        if let Some(&qg_hash) = self.named_queries.get(&name) {
+            self.unshared.insert(qg_hash);
+        }
+        qfp
+    }
+
+    /// Like `add_cached_query`, but for a straight-through view, whose reader only holds on to keys
+    /// until they have been read. The view must only filter and project the rows of a base table
+    /// that it looks up by primary key, so that reading a key needs nothing but a lookup in the
+    /// base table.
+    pub(super) fn add_straight_through_query(
+        &mut self,
+        query: SqlQuery,
+        name: String,
+        is_leaf: bool,
+        mig: &mut Migration,
+    ) -> Result<QueryFlowParts, String> {
+        use ::mir::node::MirNodeType;
+
+        let qfp = self.add_cached_query(query, name.clone(), is_leaf, mig)?;
+        let leaf = self
+            .mir_queries
+            .values()
+            .find(|mq| mq.name == name)
+            .map(|mq| mq.leaf.clone());
+        let eligible = is_leaf
+            && leaf
+                .map(|leaf| {
+                    let leaf = leaf.borrow();
+                    match leaf.inner {
+                        MirNodeType::Leaf {
+                            ref keys,
+                            order: None,
+                            limit: None,
+                            ..
+                        } => is_straight_through(&leaf.ancestors()[0], keys),
+                        _ => false,
+                    }
+                })
+                .unwrap_or(false);
+        if !eligible {
+            return Err(format!(
+                "view \"{}\" cannot be straight-through: it must only filter and project a base \
+                 table by its primary key, without ORDER BY or LIMIT",
+                name
+            ));
+        }
+        Ok(qfp)
+    }
+
+    /// Check that the tables and views that `q` reads from exist, without adding it to the graph.
+    pub(super) fn check_query(&self, q: &SqlQuery) -> Result<(), String> {
+        use query_utils::ReferredTables;
+        for t in &q.referred_tables() {
+            if !self.view_schemas.contains_key(&t.name) {
+                return Err(format!("query refers to unknown table \"{}\"", t.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepare `q` to be run once with `adhoc::execute`: the soft-deleted rows of its tables are
+    /// hidden, as they would be for a view, and its columns are qualified with their tables.
+    pub(super) fn rewrite_adhoc(&self, q: SqlQuery) -> Result<SelectStatement, String> {
+        use passes::implied_tables::ImpliedTableExpansion;
+        use passes::star_expansion::StarExpansion;
+        use passes::tombstones::TombstoneFiltering;
+
+        self.check_query(&q)?;
+        match q
+            .filter_tombstones(&self.tombstones)
+            .expand_stars(&self.view_schemas)
+            .expand_implied_tables(&self.view_schemas)
+        {
+            SqlQuery::Select(q) => Ok(q),
+            _ => Err(unsupported("running anything but a single SELECT once")),
+        }
+    }
+
     pub(super) fn get_base_schema(&self, name: &str) -> Option<CreateTableStatement> {
         self.base_schemas.get(name).cloned()
     }
@@ -160,6 +322,51 @@ impl SqlIncorporator {
         self.view_schemas.get(name).cloned()
     }
 
+    /// The MIR graph of the named query, and what the query reused when it was added.
+    ///
+    /// Queries that exactly match an existing query have no MIR graph of their own, and so only
+    /// have their reuse decision reported.
+    pub(super) fn get_query_plan(&self, name: &str) -> Option<(QueryReuse, Vec<MirNodePlan>)> {
+        let reuse = self
+            .reuse_decisions
+            .get(name)
+            .cloned()
+            .unwrap_or(QueryReuse::None);
+        let mir = self
+            .base_mir_queries
+            .get(name)
+            .or_else(|| self.mir_queries.values().find(|mq| mq.name == name));
+        let mir = match mir {
+            Some(mir) => mir,
+            None if self.reuse_decisions.contains_key(name) => return Some((reuse, vec![])),
+            None => return None,
+        };
+
+        let nodes = mir
+            .nodes()
+            .into_iter()
+            .map(|n| {
+                let n = n.borrow();
+                MirNodePlan {
+                    name: n.versioned_name(),
+                    operator: format!("{:?}", n.inner),
+                    columns: n.columns().iter().map(|c| c.name.clone()).collect(),
+                    ancestors: n
+                        .ancestors()
+                        .iter()
+                        .map(|a| a.borrow().versioned_name())
+                        .collect(),
+                    flow_node: n.flow_node.as_ref().map(::mir::FlowNode::address),
+                    reused: match n.flow_node {
+                        Some(::mir::FlowNode::Existing(_)) => true,
+                        _ => false,
+                    },
+                }
+            })
+            .collect();
+        Some((reuse, nodes))
+    }
+
     #[cfg(test)]
     fn get_flow_node_address(&self, name: &str, v: usize) -> Option<NodeIndex> {
         self.mir_converter.get_flow_node_address(name, v)
@@ -190,17 +397,27 @@ impl SqlIncorporator {
         query_name: &str,
         universe: UniverseId,
         st: &SelectStatement,
-    ) -> (QueryGraph, QueryGraphReuse) {
+    ) -> Result<(QueryGraph, QueryGraphReuse), String> {
         debug!(self.log, "Making QG for \"{}\"", query_name);
         trace!(self.log, "Query \"{}\": {:#?}", query_name, st);
 
-        let mut qg = match to_query_graph(st) {
-            Ok(qg) => qg,
-            Err(e) => panic!(e),
-        };
+        let mut qg = to_query_graph(st)?;
+        if !self.table_rows.is_empty() {
+            qg.order_joins_by_size(&self.table_rows);
+        }
 
         trace!(self.log, "QG for \"{}\": {:#?}", query_name, qg);
+        Ok(self.reuse_for_query_graph(query_name, universe, st, qg))
+    }
 
+    /// Decide what existing queries, if any, the query with query graph `qg` can reuse.
+    fn reuse_for_query_graph(
+        &mut self,
+        query_name: &str,
+        universe: UniverseId,
+        st: &SelectStatement,
+        mut qg: QueryGraph,
+    ) -> (QueryGraph, QueryGraphReuse) {
         // if reuse is disabled, we're done
         if self.reuse_type == ReuseConfigType::NoReuse {
             return (qg, QueryGraphReuse::None);
@@ -210,6 +427,10 @@ impl SqlIncorporator {
         // TODO(malte): make this an O(1) lookup by QG signature
         let qg_hash = qg.signature().hash;
         match self.mir_queries.get(&(qg_hash, universe.clone())) {
+            // query graphs do not capture ORDER BY, so queries that sort their results always get
+            // a reader of their own
+            Some(_) if st.order.is_some() => (),
+            Some(_) if self.unshared.contains(&qg_hash) => (),
             None => (),
             Some(ref mir_query) => {
                 let existing_qg = self
@@ -238,7 +459,10 @@ impl SqlIncorporator {
                         existing_qg,
                     );
 
-                    return (qg, QueryGraphReuse::ExactMatch(mir_query.leaf.clone()));
+                    return (
+                        qg,
+                        QueryGraphReuse::ExactMatch(mir_query.name.clone(), mir_query.leaf.clone()),
+                    );
                 } else if existing_qg.signature() == qg.signature()
                     && existing_qg.parameters() != qg.parameters()
                 {
@@ -253,7 +477,7 @@ impl SqlIncorporator {
                     // and literals (col/col is a join predicate and associated with the join edge,
                     // col/param is stored in qg.params), we will not be inhibited by the fact that
                     // the queries have different parameters.
-                    let mut predicates_match = true;
+                    let mut predicates_match = existing_qg.same_join_predicates(&qg);
                     for (r, n) in qg.relations.iter() {
                         for p in n.predicates.iter() {
                             if !existing_qg.relations.contains_key(r)
@@ -266,9 +490,10 @@ impl SqlIncorporator {
 
                     // if any of our columns are grouped expressions, we can't reuse here, since
                     // the difference in parameters means that there is a difference in the implied
-                    // GROUP BY clause
+                    // GROUP BY clause. Columns that echo parameters need a projection of their
+                    // own, too.
                     let no_grouped_columns = qg.columns.iter().all(|c| match *c {
-                        OutputColumn::Literal(_) => true,
+                        OutputColumn::Literal(ref lc) => lc.value != Literal::Placeholder,
                         OutputColumn::Arithmetic(ref ac) => {
                             let mut is_function = false;
                             if let ArithmeticBase::Column(ref c) = ac.expression.left {
@@ -344,7 +569,9 @@ impl SqlIncorporator {
         let reuse_config = ReuseConfig::new(self.reuse_type.clone());
 
         // Find a promising set of query graphs
-        let reuse_candidates = reuse_config.reuse_candidates(&mut qg, &self.query_graphs);
+        let mut reuse_candidates = reuse_config.reuse_candidates(&mut qg, &self.query_graphs);
+        reuse_candidates
+            .retain(|c| !self.unshared.contains(&(c.1).0) && (c.1).1.same_join_predicates(&qg));
 
         if !reuse_candidates.is_empty() {
             info!(
@@ -458,6 +685,10 @@ impl SqlIncorporator {
             })
             .collect();
 
+        if let Some(ref limit) = query.limit {
+            self::mir::check_limit(limit, &query.order)?;
+        }
+
         let mut combined_mir_query = self.mir_converter.compound_query_to_mir(
             query_name,
             subqueries?.iter().collect(),
@@ -483,12 +714,33 @@ impl SqlIncorporator {
         is_leaf: bool,
         mig: &mut Migration,
     ) -> Result<(QueryFlowParts, Option<MirQuery>), String> {
-        let (qg, reuse) = self.consider_query_graph(&query_name, mig.universe(), sq);
+        if let Some(ref limit) = sq.limit {
+            self::mir::check_limit(limit, &sq.order)?;
+        }
+
+        let (qg, reuse) = self.consider_query_graph(&query_name, mig.universe(), sq)?;
+        let decision = match reuse {
+            QueryGraphReuse::ExactMatch(ref existing, _) => {
+                QueryReuse::ExactMatch(existing.clone())
+            }
+            QueryGraphReuse::ExtendExisting(ref mqs) => QueryReuse::ExtendExisting(
+                mqs.iter()
+                    .filter_map(|m| self.mir_queries.get(m))
+                    .map(|mq| mq.name.clone())
+                    .collect(),
+            ),
+            QueryGraphReuse::ReaderOntoExisting(ref mn, _, _) => {
+                QueryReuse::ReaderOntoExisting(mn.borrow().versioned_name())
+            }
+            QueryGraphReuse::None => QueryReuse::None,
+        };
+        self.reuse_decisions.insert(query_name.to_owned(), decision);
         Ok(match reuse {
-            QueryGraphReuse::ExactMatch(mn) => {
+            QueryGraphReuse::ExactMatch(existing, mn) => {
                 let flow_node = mn.borrow().flow_node.as_ref().unwrap().address();
+                // the query is served by the existing view, and so goes by its name
                 let qfp = QueryFlowParts {
-                    name: String::from(query_name),
+                    name: existing,
                     new_nodes: vec![],
                     reused_nodes: vec![flow_node],
                     query_leaf: flow_node,
@@ -571,6 +823,7 @@ impl SqlIncorporator {
             .leaf_addresses
             .remove(query_name)
             .expect("tried to remove unknown query");
+        self.reuse_decisions.remove(query_name);
 
         let qg_hash = self
             .named_queries
@@ -597,6 +850,7 @@ impl SqlIncorporator {
             self.mir_queries.remove(&(qg_hash, mig.universe())).unwrap();
             self.query_graphs.remove(&qg_hash).unwrap();
             self.view_schemas.remove(query_name).unwrap();
+            self.split_views.remove(query_name);
 
             // trigger reader node removal
             Some(nodeid)
@@ -611,6 +865,7 @@ impl SqlIncorporator {
             self.mir_queries.remove(&(qg_hash, mig.universe())).unwrap();
             self.query_graphs.remove(&qg_hash).unwrap();
             self.view_schemas.remove(query_name).unwrap();
+            self.split_views.remove(query_name);
 
             None
         }
@@ -776,11 +1031,13 @@ impl SqlIncorporator {
 
         use passes::alias_removal::AliasRemoval;
         use passes::count_star_rewrite::CountStarRewrite;
+        use passes::filter_aggregates::FilterAggregateRewrite;
         use passes::implied_tables::ImpliedTableExpansion;
         use passes::key_def_coalescing::KeyDefinitionCoalescing;
         use passes::negation_removal::NegationRemoval;
         use passes::star_expansion::StarExpansion;
         use passes::subqueries::SubQueries;
+        use passes::tombstones::TombstoneFiltering;
         use query_utils::ReferredTables;
 
         // need to increment here so that each subquery has a unique name.
@@ -847,19 +1104,42 @@ impl SqlIncorporator {
                     if !self.view_schemas.contains_key(&t.name) {
                         return Err(format!("query refers to unknown table \"{}\"", t.name));
                     }
+                    if self.split_views.contains(&t.name) {
+                        return Err(unsupported(format!(
+                            "reading from view \"{}\", whose aggregation splits hot keys",
+                            t.name
+                        )));
+                    }
                 }
             }
         }
 
         // Run some standard rewrite passes on the query. This makes the later work easier,
         // as we no longer have to consider complications like aliases.
+        let fq = fq.expand_table_aliases(mig.context());
+
+        // Instances of self-joined tables are separate relations with the schema of their table.
+        let self_joined = passes::alias_removal::self_join_relations(&fq);
+        let mut view_schemas = Cow::Borrowed(&self.view_schemas);
+        for rel in self_joined {
+            let table = passes::alias_removal::relation_table(&rel);
+            let schema = match view_schemas.get(table) {
+                Some(schema) => schema.clone(),
+                None => return Err(format!("query refers to unknown table \"{}\"", table)),
+            };
+            view_schemas.to_mut().insert(rel, schema);
+        }
+
+        // tombstones are filtered before stars are expanded, since only the columns that a query
+        // selects explicitly show that it wants to see deleted rows
         Ok(fq
-            .expand_table_aliases(mig.context())
             .remove_negation()
             .coalesce_key_definitions()
-            .expand_stars(&self.view_schemas)
-            .expand_implied_tables(&self.view_schemas)
-            .rewrite_count_star(&self.view_schemas))
+            .filter_tombstones(&self.tombstones)
+            .expand_stars(&view_schemas)
+            .expand_implied_tables(&view_schemas)
+            .rewrite_filter_aggregates()
+            .rewrite_count_star(&view_schemas))
     }
 
     fn nodes_for_named_query(
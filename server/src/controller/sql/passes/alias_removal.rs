@@ -1,6 +1,6 @@
 use nom_sql::{
-    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    JoinConstraint, JoinRightSide, SqlQuery,
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, JoinConstraint, JoinRightSide, SqlQuery, Table,
 };
 
 use std::collections::HashMap;
@@ -8,33 +8,66 @@ use std::collections::HashMap;
 use dataflow::prelude::DataType;
 
 pub trait AliasRemoval {
+    /// Replace table aliases with the names of the tables they refer to. A table that appears
+    /// more than once in a query (i.e., a self-join) instead becomes one relation per alias,
+    /// named by `self_join_relation`, so that its instances remain distinct.
     fn expand_table_aliases(self, context: &HashMap<String, DataType>) -> SqlQuery;
 }
 
+const SELF_JOIN_SEPARATOR: char = '@';
+
+/// The name of the relation that an aliased instance of a self-joined table is planned as.
+pub fn self_join_relation(table: &str, alias: &str) -> String {
+    format!("{}{}{}", table, SELF_JOIN_SEPARATOR, alias)
+}
+
+/// The table that a relation reads from; this is the relation itself unless it stands for an
+/// aliased instance of a self-joined table.
+pub fn relation_table(relation: &str) -> &str {
+    relation.split(SELF_JOIN_SEPARATOR).next().unwrap()
+}
+
+/// The relations in a query that stand for aliased instances of self-joined tables.
+pub fn self_join_relations(q: &SqlQuery) -> Vec<String> {
+    let mut relations = Vec::new();
+    if let SqlQuery::Select(ref sq) = *q {
+        let joined = sq.join.iter().flat_map(|jc| match jc.right {
+            JoinRightSide::Table(ref t) => vec![t],
+            JoinRightSide::Tables(ref ts) => ts.iter().collect(),
+            _ => vec![],
+        });
+        for t in sq.tables.iter().chain(joined) {
+            if relation_table(&t.name) != t.name && !relations.contains(&t.name) {
+                relations.push(t.name.clone());
+            }
+        }
+    }
+    relations
+}
+
 fn rewrite_conditional(
     table_aliases: &HashMap<String, String>,
     ce: ConditionExpression,
 ) -> ConditionExpression {
-    let translate_column = |f: Column| {
-        let new_f = match f.table {
-            None => f,
-            Some(t) => Column {
-                name: f.name,
-                alias: f.alias,
-                table: if table_aliases.contains_key(&t) {
-                    Some(table_aliases[&t].clone())
-                } else {
-                    Some(t)
-                },
-                function: None,
+    let translate_column = |f: Column| match f.table {
+        None => f,
+        Some(t) => Column {
+            name: f.name,
+            alias: f.alias,
+            table: if table_aliases.contains_key(&t) {
+                Some(table_aliases[&t].clone())
+            } else {
+                Some(t)
             },
-        };
-        ConditionExpression::Base(ConditionBase::Field(new_f))
+            function: None,
+        },
     };
 
     let translate_ct_arm = |bce: Box<ConditionExpression>| -> Box<ConditionExpression> {
         let new_ce = match *bce {
-            ConditionExpression::Base(ConditionBase::Field(f)) => translate_column(f),
+            ConditionExpression::Base(ConditionBase::Field(f)) => {
+                ConditionExpression::Base(ConditionBase::Field(translate_column(f)))
+            }
             ConditionExpression::Base(b) => ConditionExpression::Base(b),
             x => rewrite_conditional(table_aliases, x),
         };
@@ -62,6 +95,17 @@ fn rewrite_conditional(
             };
             ConditionExpression::LogicalOp(rewritten_ct)
         }
+        ConditionExpression::Arithmetic(mut ae) => {
+            for b in &mut [&mut ae.left, &mut ae.right] {
+                if let ArithmeticBase::Column(ref mut c) = **b {
+                    *c = translate_column(c.clone());
+                }
+            }
+            ConditionExpression::Arithmetic(ae)
+        }
+        ConditionExpression::Bracketed(inner) => {
+            ConditionExpression::Bracketed(Box::new(rewrite_conditional(table_aliases, *inner)))
+        }
         x => x,
     }
 }
@@ -72,6 +116,24 @@ impl AliasRemoval for SqlQuery {
 
         match self {
             SqlQuery::Select(mut sq) => {
+                // Tables that appear more than once must keep their instances apart
+                let mut occurrences: HashMap<String, usize> = HashMap::new();
+                for t in &sq.tables {
+                    *occurrences.entry(t.name.clone()).or_default() += 1;
+                }
+                for jc in &sq.join {
+                    match jc.right {
+                        JoinRightSide::Table(ref t) => {
+                            *occurrences.entry(t.name.clone()).or_default() += 1
+                        }
+                        JoinRightSide::Tables(ref ts) => {
+                            for t in ts {
+                                *occurrences.entry(t.name.clone()).or_default() += 1;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
                 {
                     // Collect table aliases
                     let mut add_alias = |alias: &str, name: &str| {
@@ -97,6 +159,28 @@ impl AliasRemoval for SqlQuery {
                         }
                     }
 
+                    // Give each aliased instance of a self-joined table its own relation
+                    let mut rename = |t: &mut Table| {
+                        if occurrences[&t.name] > 1 {
+                            if let Some(a) = t.alias.take() {
+                                t.name = self_join_relation(&t.name, &a);
+                                add_alias(&a, &t.name);
+                            }
+                        }
+                    };
+                    for t in &mut sq.tables {
+                        rename(t);
+                    }
+                    for jc in &mut sq.join {
+                        match jc.right {
+                            JoinRightSide::Table(ref mut t) => rename(t),
+                            JoinRightSide::Tables(ref mut ts) => {
+                                ts.iter_mut().for_each(&mut rename)
+                            }
+                            _ => (),
+                        }
+                    }
+
                     for t in &mut sq.tables {
                         match t.alias {
                             None => (),
@@ -240,4 +324,37 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn it_keeps_self_joined_tables_apart() {
+        use super::{relation_table, self_join_relations};
+        use nom_sql::parser::parse_query;
+        use nom_sql::JoinRightSide;
+
+        let q = parse_query(
+            "SELECT f2.followee FROM follows AS f1 \
+             JOIN follows AS f2 ON (f1.followee = f2.follower) WHERE f1.follower = ?;",
+        )
+        .unwrap();
+        let res = q.expand_table_aliases(&HashMap::new());
+        match res {
+            SqlQuery::Select(ref tq) => {
+                assert_eq!(tq.tables, vec![Table::from("follows@f1")]);
+                assert_eq!(
+                    tq.join[0].right,
+                    JoinRightSide::Table(Table::from("follows@f2"))
+                );
+                assert_eq!(
+                    tq.fields,
+                    vec![FieldDefinitionExpression::Col(Column::from(
+                        "follows@f2.followee"
+                    ))]
+                );
+            }
+            _ => panic!(),
+        }
+        assert_eq!(self_join_relations(&res), vec!["follows@f1", "follows@f2"]);
+        assert_eq!(relation_table("follows@f2"), "follows");
+        assert_eq!(relation_table("follows"), "follows");
+    }
 }
@@ -1,6 +1,6 @@
 use nom_sql::{
-    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    FunctionArguments, SqlQuery, Table,
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, FunctionArguments, SqlQuery, Table,
 };
 
 use std::collections::HashMap;
@@ -31,6 +31,15 @@ fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
             if let ConditionExpression::Base(ConditionBase::Field(ref f)) = **right {
                 cols.push(f.clone());
             }
+            for side in &[left, right] {
+                if let ConditionExpression::Arithmetic(ref ae) = ***side {
+                    for b in &[&ae.left, &ae.right] {
+                        if let ArithmeticBase::Column(ref c) = **b {
+                            cols.push(c.clone());
+                        }
+                    }
+                }
+            }
 
             cols
         }
@@ -0,0 +1,150 @@
+use nom_sql::{
+    CaseWhenExpression, Column, ColumnOrLiteral, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, FunctionArguments, FunctionExpression, Literal, SelectStatement,
+    SqlQuery,
+};
+
+pub trait FilterAggregateRewrite {
+    /// Rewrite filtered aggregations whose `THEN` branch is a literal into ones over a column, as
+    /// the filtered aggregation operators only aggregate over columns.
+    ///
+    /// `SUM(CASE WHEN cond THEN 1 ELSE 0 END)` counts the rows that match `cond`, and is rewritten
+    /// to `COUNT(CASE WHEN cond THEN col END)`, where `col` is a column of `cond`. A `COUNT` does
+    /// not look at the value it counts, so `COUNT(CASE WHEN cond THEN 1 END)` becomes the same.
+    fn rewrite_filter_aggregates(self) -> SqlQuery;
+}
+
+fn first_condition_column(ce: &ConditionExpression) -> Option<&Column> {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            ref left,
+            ref right,
+            ..
+        })
+        | ConditionExpression::ComparisonOp(ConditionTree {
+            ref left,
+            ref right,
+            ..
+        }) => first_condition_column(left).or_else(|| first_condition_column(right)),
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            first_condition_column(inner)
+        }
+        ConditionExpression::Base(ConditionBase::Field(ref f)) => Some(f),
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => None,
+    }
+}
+
+fn is_integer(l: &Literal, n: i64) -> bool {
+    match *l {
+        Literal::Integer(i) => i == n,
+        Literal::UnsignedInteger(i) => i as i64 == n,
+        _ => false,
+    }
+}
+
+fn rewrite_function(f: &mut FunctionExpression) {
+    use nom_sql::FunctionExpression::*;
+
+    let (cwe, count) = match *f {
+        Sum(FunctionArguments::Conditional(ref cwe), false) => {
+            let then_one = match cwe.then_expr {
+                ColumnOrLiteral::Literal(ref l) => is_integer(l, 1),
+                ColumnOrLiteral::Column(_) => false,
+            };
+            let else_zero = match cwe.else_expr {
+                None => true,
+                Some(ColumnOrLiteral::Literal(ref l)) => is_integer(l, 0),
+                Some(ColumnOrLiteral::Column(_)) => false,
+            };
+            if !then_one || !else_zero {
+                return;
+            }
+            (cwe, false)
+        }
+        Count(FunctionArguments::Conditional(ref cwe), false) => match cwe.then_expr {
+            ColumnOrLiteral::Literal(_) => (cwe, true),
+            ColumnOrLiteral::Column(_) => return,
+        },
+        _ => return,
+    };
+
+    let col = match first_condition_column(&cwe.condition) {
+        Some(col) => col.clone(),
+        None => return,
+    };
+    // an `ELSE 0` of a sum adds nothing, but a count also counts the rows that take it
+    let else_expr = if count { cwe.else_expr.clone() } else { None };
+    *f = Count(
+        FunctionArguments::Conditional(CaseWhenExpression {
+            condition: cwe.condition.clone(),
+            then_expr: ColumnOrLiteral::Column(col),
+            else_expr,
+        }),
+        false,
+    );
+}
+
+fn rewrite_select(mut sq: SelectStatement) -> SelectStatement {
+    for field in sq.fields.iter_mut() {
+        if let FieldDefinitionExpression::Col(Column {
+            function: Some(ref mut f),
+            ..
+        }) = *field
+        {
+            rewrite_function(f);
+        }
+    }
+    sq
+}
+
+impl FilterAggregateRewrite for SqlQuery {
+    fn rewrite_filter_aggregates(self) -> SqlQuery {
+        match self {
+            SqlQuery::Select(sq) => SqlQuery::Select(rewrite_select(sq)),
+            SqlQuery::CompoundSelect(mut csq) => {
+                csq.selects = csq
+                    .selects
+                    .into_iter()
+                    .map(|(op, sq)| (op, rewrite_select(sq)))
+                    .collect();
+                SqlQuery::CompoundSelect(csq)
+            }
+            // nothing to do for other types of queries
+            x => x,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilterAggregateRewrite;
+    use nom_sql::parser::parse_query;
+
+    #[test]
+    fn it_rewrites_literal_filter_aggregates() {
+        let rewrite = |q: &str| parse_query(q).unwrap().rewrite_filter_aggregates();
+        let count = parse_query(
+            "SELECT COUNT(CASE WHEN votes.aid = 5 THEN votes.aid END) AS c FROM votes;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rewrite("SELECT SUM(CASE WHEN votes.aid = 5 THEN 1 ELSE 0 END) AS c FROM votes;"),
+            count
+        );
+        assert_eq!(
+            rewrite("SELECT SUM(CASE WHEN votes.aid = 5 THEN 1 END) AS c FROM votes;"),
+            count
+        );
+        assert_eq!(
+            rewrite("SELECT COUNT(CASE WHEN votes.aid = 5 THEN 1 END) AS c FROM votes;"),
+            count
+        );
+
+        // sums of anything else are left alone
+        let q = "SELECT SUM(CASE WHEN votes.aid = 5 THEN 2 ELSE 0 END) AS c FROM votes;";
+        assert_eq!(rewrite(q), parse_query(q).unwrap());
+        let q = "SELECT SUM(CASE WHEN votes.aid = 5 THEN votes.sign END) AS c FROM votes;";
+        assert_eq!(rewrite(q), parse_query(q).unwrap());
+    }
+}
@@ -50,6 +50,19 @@ where
             left: Box::new(rewrite_conditional(expand_columns, *left, avail_tables)),
             right: Box::new(rewrite_conditional(expand_columns, *right, avail_tables)),
         }),
+        Arithmetic(mut ae) => {
+            for b in &mut [&mut ae.left, &mut ae.right] {
+                if let ArithmeticBase::Column(ref mut c) = **b {
+                    *c = expand_columns(c.clone(), avail_tables);
+                }
+            }
+            Arithmetic(ae)
+        }
+        Bracketed(inner) => Bracketed(Box::new(rewrite_conditional(
+            expand_columns,
+            *inner,
+            avail_tables,
+        ))),
         x => x,
     }
 }
@@ -1,7 +1,9 @@
 pub mod alias_removal;
 pub mod count_star_rewrite;
+pub mod filter_aggregates;
 pub mod implied_tables;
 pub mod key_def_coalescing;
 pub mod negation_removal;
 pub mod star_expansion;
 pub mod subqueries;
+pub mod tombstones;
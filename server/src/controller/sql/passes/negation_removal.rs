@@ -8,8 +8,45 @@ pub trait NegationRemoval {
     fn remove_negation(self) -> SqlQuery;
 }
 
+/// The negation of `ce`, with the negation pushed down into its comparisons.
+pub fn negate(ce: &ConditionExpression) -> ConditionExpression {
+    let mut ce = ce.clone();
+    normalize_condition_expr(&mut ce, true);
+    ce
+}
+
+// `x NOT IN (a, b)` is `x != a AND x != b`
+fn not_in(ct: &ConditionTree) -> ConditionExpression {
+    let values = match *ct.right {
+        ConditionExpression::Base(ConditionBase::LiteralList(ref ll)) => ll,
+        _ => unimplemented!(),
+    };
+    values
+        .iter()
+        .map(|l| {
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::NotEqual,
+                left: ct.left.clone(),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Literal(l.clone()))),
+            })
+        })
+        .fold(None, |acc, ne| match acc {
+            None => Some(ne),
+            Some(acc) => Some(ConditionExpression::LogicalOp(ConditionTree {
+                operator: Operator::And,
+                left: Box::new(acc),
+                right: Box::new(ne),
+            })),
+        })
+        .expect("NOT IN of an empty list")
+}
+
 fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
     match *ce {
+        ConditionExpression::ComparisonOp(ref ct) if negate && ct.operator == Operator::In => {
+            let inequalities = not_in(ct);
+            *ce = inequalities;
+        }
         ConditionExpression::LogicalOp(ConditionTree {
             ref mut operator,
             ref mut left,
@@ -61,8 +98,7 @@ fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
         ConditionExpression::Bracketed(ref mut inner) => {
             normalize_condition_expr(inner, negate);
         }
-        ConditionExpression::Base(_) => {}
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => {}
     }
 }
 
@@ -122,4 +158,33 @@ mod tests {
         normalize_condition_expr(&mut expr, false);
         assert_eq!(expr, target);
     }
+
+    #[test]
+    fn it_negates_in_lists() {
+        let field = || Box::new(ConditionExpression::Base(ConditionBase::Field("a".into())));
+        let ne = |i| {
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::NotEqual,
+                left: field(),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                    Literal::Integer(i),
+                ))),
+            })
+        };
+        let expr = ConditionExpression::ComparisonOp(ConditionTree {
+            operator: Operator::In,
+            left: field(),
+            right: Box::new(ConditionExpression::Base(ConditionBase::LiteralList(vec![
+                Literal::Integer(1),
+                Literal::Integer(2),
+            ]))),
+        });
+
+        let target = ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(ne(1)),
+            right: Box::new(ne(2)),
+        });
+        assert_eq!(negate(&expr), target);
+    }
 }
@@ -0,0 +1,237 @@
+use nom_sql::{
+    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
+    JoinConstraint, JoinOperator, JoinRightSide, Literal, Operator, SelectStatement, SqlQuery,
+};
+
+use std::collections::HashMap;
+
+use super::alias_removal::relation_table;
+
+pub trait TombstoneFiltering {
+    /// Hide the soft-deleted rows of the tables in `tombstones`, which maps each table that uses
+    /// soft deletes to its tombstone column.
+    fn filter_tombstones(self, tombstones: &HashMap<String, String>) -> SqlQuery;
+}
+
+/// Whether `f` refers to `col`. This pass runs before columns are qualified with their tables,
+/// so a column without a table may refer to `col` too.
+fn is_column(f: &Column, col: &Column) -> bool {
+    f.name == col.name && (f.table.is_none() || f.table == col.table)
+}
+
+fn condition_mentions(ce: &ConditionExpression, col: &Column) -> bool {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            ref left,
+            ref right,
+            ..
+        })
+        | ConditionExpression::ComparisonOp(ConditionTree {
+            ref left,
+            ref right,
+            ..
+        }) => condition_mentions(left, col) || condition_mentions(right, col),
+        ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+            condition_mentions(inner, col)
+        }
+        ConditionExpression::Base(ConditionBase::Field(ref f)) => is_column(f, col),
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => false,
+    }
+}
+
+/// Whether the query looks at the tombstone column `col` itself. Columns that are only selected
+/// through a `*` do not count, which is why this pass must run before stars are expanded.
+fn mentions(sq: &SelectStatement, col: &Column) -> bool {
+    let in_fields = sq.fields.iter().any(|f| match *f {
+        FieldDefinitionExpression::Col(ref c) => is_column(c, col),
+        _ => false,
+    });
+    let in_joins = sq.join.iter().any(|jc| match jc.constraint {
+        JoinConstraint::On(ref ce) => condition_mentions(ce, col),
+        JoinConstraint::Using(ref cs) => cs.iter().any(|c| c.name == col.name),
+    });
+    let in_where = sq
+        .where_clause
+        .as_ref()
+        .map(|w| condition_mentions(w, col))
+        .unwrap_or(false);
+    in_fields || in_joins || in_where
+}
+
+fn and(left: ConditionExpression, right: ConditionExpression) -> ConditionExpression {
+    ConditionExpression::LogicalOp(ConditionTree {
+        operator: Operator::And,
+        left: Box::new(left),
+        right: Box::new(right),
+    })
+}
+
+fn comparison(left: Column, operator: Operator, right: ConditionBase) -> ConditionExpression {
+    ConditionExpression::ComparisonOp(ConditionTree {
+        operator,
+        left: Box::new(ConditionExpression::Base(ConditionBase::Field(left))),
+        right: Box::new(ConditionExpression::Base(right)),
+    })
+}
+
+fn filter_select(mut sq: SelectStatement, tombstones: &HashMap<String, String>) -> SelectStatement {
+    let tombstone = |table: &str| {
+        tombstones.get(relation_table(table)).map(|c| Column {
+            name: c.clone(),
+            alias: None,
+            table: Some(table.to_owned()),
+            function: None,
+        })
+    };
+    let live = |column: Column| {
+        comparison(
+            column,
+            Operator::Equal,
+            ConditionBase::Literal(Literal::Integer(0)),
+        )
+    };
+
+    // the rows of tables that are joined as a whole are filtered in the WHERE clause
+    let mut tables: Vec<_> = sq.tables.iter().map(|t| t.name.clone()).collect();
+    for jc in &sq.join {
+        match (&jc.operator, &jc.right) {
+            (JoinOperator::Join, JoinRightSide::Table(t))
+            | (JoinOperator::InnerJoin, JoinRightSide::Table(t)) => tables.push(t.name.clone()),
+            _ => {}
+        }
+    }
+    for table in tables {
+        let column = match tombstone(&table) {
+            Some(c) => c,
+            None => continue,
+        };
+        // queries that look at the tombstone column themselves get to see deleted rows, so that
+        // applications can list (and restore) them
+        if mentions(&sq, &column) {
+            continue;
+        }
+        sq.where_clause = Some(match sq.where_clause.take() {
+            Some(w) => and(w, live(column)),
+            None => live(column),
+        });
+    }
+
+    // but filtering the table on the right of a LEFT JOIN in the WHERE clause would also drop the
+    // rows that it has no match for, so those are filtered in the join condition instead
+    let prev_table = sq.tables.last().map(|t| t.name.clone());
+    for i in 0..sq.join.len() {
+        let column = match (&sq.join[i].operator, &sq.join[i].right) {
+            (JoinOperator::LeftJoin, JoinRightSide::Table(t))
+            | (JoinOperator::LeftOuterJoin, JoinRightSide::Table(t)) => match tombstone(&t.name) {
+                Some(c) => c,
+                None => continue,
+            },
+            _ => continue,
+        };
+        if mentions(&sq, &column) {
+            continue;
+        }
+        let jc = &mut sq.join[i];
+        let on = match jc.constraint {
+            JoinConstraint::On(ref ce) => ce.clone(),
+            JoinConstraint::Using(ref cs) => {
+                // spell out the equalities, so that the filter can be added to them
+                let right = column.table.clone();
+                let eq = |c: &Column| {
+                    comparison(
+                        Column {
+                            table: prev_table.clone(),
+                            ..c.clone()
+                        },
+                        Operator::Equal,
+                        ConditionBase::Field(Column {
+                            table: right.clone(),
+                            ..c.clone()
+                        }),
+                    )
+                };
+                let mut eqs = cs.iter().map(eq);
+                let first = match eqs.next() {
+                    Some(first) => first,
+                    None => continue,
+                };
+                eqs.fold(first, and)
+            }
+        };
+        jc.constraint = JoinConstraint::On(and(on, live(column)));
+    }
+    sq
+}
+
+impl TombstoneFiltering for SqlQuery {
+    fn filter_tombstones(self, tombstones: &HashMap<String, String>) -> SqlQuery {
+        if tombstones.is_empty() {
+            return self;
+        }
+        match self {
+            SqlQuery::Select(sq) => SqlQuery::Select(filter_select(sq, tombstones)),
+            SqlQuery::CompoundSelect(mut csq) => {
+                csq.selects = csq
+                    .selects
+                    .into_iter()
+                    .map(|(op, sq)| (op, filter_select(sq, tombstones)))
+                    .collect();
+                SqlQuery::CompoundSelect(csq)
+            }
+            // nothing to do for other types of queries
+            _ => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TombstoneFiltering;
+    use nom_sql::parser::parse_query;
+    use std::collections::HashMap;
+
+    #[test]
+    fn it_filters_tombstones() {
+        let mut tombstones = HashMap::new();
+        tombstones.insert("a".to_owned(), "_deleted".to_owned());
+        let filter = |q: &str| parse_query(q).unwrap().filter_tombstones(&tombstones);
+
+        assert_eq!(
+            filter("SELECT a.x FROM a WHERE a.y = ?;"),
+            parse_query("SELECT a.x FROM a WHERE a.y = ? AND a._deleted = 0;").unwrap()
+        );
+        assert_eq!(
+            filter("SELECT a.x, b.x FROM a JOIN b ON (a.y = b.y);"),
+            parse_query("SELECT a.x, b.x FROM a JOIN b ON (a.y = b.y) WHERE a._deleted = 0;")
+                .unwrap()
+        );
+
+        // selecting all columns does not count as looking at the tombstones
+        assert_eq!(
+            filter("SELECT * FROM a WHERE a.y = ?;"),
+            parse_query("SELECT * FROM a WHERE a.y = ? AND a._deleted = 0;").unwrap()
+        );
+
+        // tables on the right of a LEFT JOIN are filtered in the join condition
+        assert_eq!(
+            filter("SELECT b.x, a.x FROM b LEFT JOIN a ON (b.y = a.y);"),
+            parse_query("SELECT b.x, a.x FROM b LEFT JOIN a ON (b.y = a.y AND a._deleted = 0);")
+                .unwrap()
+        );
+        assert_eq!(
+            filter("SELECT b.x, a.x FROM b LEFT JOIN a USING (y);"),
+            parse_query("SELECT b.x, a.x FROM b LEFT JOIN a ON (b.y = a.y AND a._deleted = 0);")
+                .unwrap()
+        );
+
+        // queries that look at the tombstones are left alone
+        let q = "SELECT a.x FROM a WHERE a._deleted = 1;";
+        assert_eq!(filter(q), parse_query(q).unwrap());
+        let q = "SELECT x FROM a WHERE _deleted = 1;";
+        assert_eq!(filter(q), parse_query(q).unwrap());
+
+        // as are tables without them
+        let q = "SELECT b.x FROM b WHERE b.y = ?;";
+        assert_eq!(filter(q), parse_query(q).unwrap());
+    }
+}
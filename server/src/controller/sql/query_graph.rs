@@ -139,6 +139,10 @@ pub struct JoinRef {
 pub struct QueryGraphNode {
     pub rel_name: String,
     pub predicates: Vec<ConditionExpression>,
+    /// Comparisons with literals in the `ON` clause that joins this relation into the query. They
+    /// filter the relation's rows before it is joined, so that for a `LEFT JOIN`, rows of the
+    /// left-hand side are kept even if none of the rows they match pass.
+    pub join_predicates: Vec<ConditionExpression>,
     pub columns: Vec<Column>,
     pub parameters: Vec<Column>,
 }
@@ -164,6 +168,9 @@ pub struct QueryGraph {
     pub join_order: Vec<JoinRef>,
     /// Global predicates (not associated with a particular relation)
     pub global_predicates: Vec<ConditionExpression>,
+    /// Columns compared to parameters in a disjunction (`a = ? OR b = ?`). All of them are
+    /// looked up with the same key.
+    pub parameter_disjunction: Vec<Column>,
 }
 
 impl QueryGraph {
@@ -174,9 +181,28 @@ impl QueryGraph {
             columns: Vec::new(),
             join_order: Vec::new(),
             global_predicates: Vec::new(),
+            parameter_disjunction: Vec::new(),
         }
     }
 
+    /// Reorder the inner joins of the query so that the joins between the smallest tables come
+    /// first, given how many rows each table is known to hold.
+    ///
+    /// Tables of unknown size are assumed to be larger than any other. Queries with outer joins
+    /// are left alone, since reordering those could change their results.
+    pub fn order_joins_by_size(&mut self, table_rows: &HashMap<String, usize>) {
+        if self.edges.values().any(|e| match *e {
+            QueryGraphEdge::LeftJoin(_) => true,
+            _ => false,
+        }) {
+            return;
+        }
+
+        let size = |t: &str| table_rows.get(t).cloned().unwrap_or(usize::max_value());
+        self.join_order
+            .sort_by_key(|j| size(&j.src).saturating_add(size(&j.dst)));
+    }
+
     /// Returns the set of columns on which this query is parameterized. They can come from
     /// multiple tables involved in the query.
     pub fn parameters<'a>(&'a self) -> Vec<&'a Column> {
@@ -188,6 +214,20 @@ impl QueryGraph {
             })
     }
 
+    /// Whether the relations of both query graphs are filtered by the same join predicates. The
+    /// nodes of one query can only be reused for the other if they are, since those filters are
+    /// applied below the joins.
+    pub fn same_join_predicates(&self, other: &QueryGraph) -> bool {
+        let implied_by = |a: &QueryGraph, b: &QueryGraph| {
+            a.relations.iter().all(|(rel, qgn)| {
+                qgn.join_predicates.is_empty()
+                    || b.relations.get(rel).map(|n| &n.join_predicates)
+                        == Some(&qgn.join_predicates)
+            })
+        };
+        implied_by(self, other) && implied_by(other, self)
+    }
+
     pub fn exact_hash(&self) -> u64 {
         use std::collections::hash_map::DefaultHasher;
 
@@ -215,6 +255,7 @@ impl Hash for QueryGraph {
         self.columns.hash(state);
         self.join_order.hash(state);
         self.global_predicates.hash(state);
+        self.parameter_disjunction.hash(state);
     }
 }
 
@@ -243,6 +284,133 @@ fn split_conjunctions(ces: Vec<ConditionExpression>) -> Vec<ConditionExpression>
     new_ces
 }
 
+// The columns of a disjunction of parameter equalities (`a = ? OR b = ?`), if `ce` is one.
+fn parameter_disjuncts(ce: &ConditionExpression) -> Option<Vec<Column>> {
+    match *ce {
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::Or,
+            ref left,
+            ref right,
+        }) => {
+            let mut columns = parameter_disjuncts(left)?;
+            columns.extend(parameter_disjuncts(right)?);
+            Some(columns)
+        }
+        ConditionExpression::Bracketed(ref inner) => parameter_disjuncts(inner),
+        ConditionExpression::ComparisonOp(ConditionTree {
+            operator: Operator::Equal,
+            ref left,
+            ref right,
+        }) => match (left.as_ref(), right.as_ref()) {
+            (
+                ConditionExpression::Base(ConditionBase::Field(ref c)),
+                ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+            ) => Some(vec![c.clone()]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Separates a disjunction of parameters from the rest of a WHERE clause, since it is planned as
+// one keyed branch per parameter rather than as a predicate.
+fn split_parameter_disjunction(
+    cond: &ConditionExpression,
+) -> Result<(Option<ConditionExpression>, Vec<Column>), String> {
+    let mut rest = Vec::new();
+    let mut disjunction = Vec::new();
+    for ce in split_conjunctions(vec![cond.clone()]) {
+        match parameter_disjuncts(&ce) {
+            Some(columns) if columns.len() > 1 => {
+                if !disjunction.is_empty() {
+                    return Err(super::unsupported(
+                        "queries can have at most one disjunction of parameters",
+                    ));
+                }
+                disjunction = columns;
+            }
+            _ => rest.push(ce),
+        }
+    }
+
+    if disjunction.is_empty() {
+        return Ok((Some(cond.clone()), disjunction));
+    }
+    let rest = rest.into_iter().fold(None, |acc, ce| match acc {
+        None => Some(ce),
+        Some(acc) => Some(ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(acc),
+            right: Box::new(ce),
+        })),
+    });
+    Ok((rest, disjunction))
+}
+
+// Combines two join predicates into one that requires both of them.
+fn conjoin(left: ConditionTree, right: ConditionTree) -> ConditionTree {
+    let wrap = |ct: ConditionTree| {
+        Box::new(if ct.operator == Operator::And {
+            ConditionExpression::LogicalOp(ct)
+        } else {
+            ConditionExpression::ComparisonOp(ct)
+        })
+    };
+    ConditionTree {
+        operator: Operator::And,
+        left: wrap(left),
+        right: wrap(right),
+    }
+}
+
+// The columns that a side of a comparison reads, if it is a column, a literal or an arithmetic
+// expression over those.
+fn operand_columns(ce: &ConditionExpression) -> Option<Vec<&Column>> {
+    match *ce {
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => Some(vec![c]),
+        ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) => None,
+        ConditionExpression::Base(ConditionBase::Literal(_)) => Some(vec![]),
+        ConditionExpression::Arithmetic(ref ae) => Some(
+            [&ae.left, &ae.right]
+                .iter()
+                .filter_map(|b| match **b {
+                    ArithmeticBase::Column(ref c) => Some(c),
+                    ArithmeticBase::Scalar(_) => None,
+                })
+                .collect(),
+        ),
+        ConditionExpression::Bracketed(ref inner) => operand_columns(inner),
+        _ => None,
+    }
+}
+
+// Comparisons that involve an expression, or that compare two columns of the same table, are
+// filters rather than join predicates or parameters. Returns the only table such a comparison
+// reads, or `None` if it reads several tables or none.
+fn computed_comparison_table<'a>(ct: &'a ConditionTree) -> Option<Option<&'a str>> {
+    let is_expression = |ce: &ConditionExpression| match *ce {
+        ConditionExpression::Arithmetic(_) | ConditionExpression::Bracketed(_) => true,
+        _ => false,
+    };
+    let left = operand_columns(&ct.left)?;
+    let right = operand_columns(&ct.right)?;
+    let same_table = match (left.as_slice(), right.as_slice()) {
+        ([l], [r]) => l.table.is_some() && l.table == r.table,
+        _ => false,
+    };
+    if !(is_expression(&ct.left) || is_expression(&ct.right) || same_table) {
+        return None;
+    }
+
+    let mut tables = left.iter().chain(right.iter()).map(|c| c.table.as_ref());
+    let first = tables.next().and_then(|t| t);
+    if first.is_some() && tables.all(|t| t.map(String::as_str) == first.map(String::as_str)) {
+        Some(first.map(String::as_str))
+    } else {
+        Some(None)
+    }
+}
+
 // 1. Extract any predicates with placeholder parameters. We push these down to the edge
 //    nodes, since we cannot instantiate the parameters inside the data flow graph (except for
 //    non-materialized nodes).
@@ -256,7 +424,7 @@ fn classify_conditionals(
     join: &mut Vec<ConditionTree>,
     global: &mut Vec<ConditionExpression>,
     params: &mut Vec<Column>,
-) {
+) -> Result<(), String> {
     // Handling OR and AND expressions requires some care as there are some corner cases.
     //    a) we don't support OR expressions with predicates with placeholder parameters,
     //       because these expressions are meaningless in the Soup context.
@@ -287,7 +455,7 @@ fn classify_conditionals(
                 &mut new_join,
                 &mut new_global,
                 &mut new_params,
-            );
+            )?;
             classify_conditionals(
                 ct.right.as_ref(),
                 tables,
@@ -295,7 +463,7 @@ fn classify_conditionals(
                 &mut new_join,
                 &mut new_global,
                 &mut new_params,
-            );
+            )?;
 
             match ct.operator {
                 Operator::And => {
@@ -326,14 +494,14 @@ fn classify_conditionals(
                     global.extend(new_global);
                 }
                 Operator::Or => {
-                    assert!(
-                        new_join.is_empty(),
-                        "can't handle OR expressions between join predicates"
-                    );
-                    assert!(
-                        new_params.is_empty(),
-                        "can't handle OR expressions between query parameter predicates"
-                    );
+                    if !new_join.is_empty() {
+                        return Err(super::unsupported("OR between join predicates"));
+                    }
+                    if !new_params.is_empty() {
+                        return Err(super::unsupported(
+                            "OR between comparisons with query parameters",
+                        ));
+                    }
                     if new_local.keys().len() == 1 && new_global.is_empty() {
                         // OR over a single table => local predicate
                         let (t, ces) = new_local.into_iter().next().unwrap();
@@ -358,6 +526,23 @@ fn classify_conditionals(
             params.extend(new_params);
         }
         ConditionExpression::ComparisonOp(ref ct) => {
+            // comparisons that compute values from a row are local predicates if the row is of a
+            // single table, and global ones otherwise
+            if let Some(table) = computed_comparison_table(ct) {
+                match table {
+                    Some(t) if tables.contains(&Table::from(t)) => {
+                        local.entry(t.to_owned()).or_default().push(ce.clone())
+                    }
+                    _ => global.push(ce.clone()),
+                }
+                return Ok(());
+            }
+            if let ConditionExpression::Arithmetic(_) = *ct.left {
+                return Err(super::unsupported(
+                    "comparing expressions to query parameters",
+                ));
+            }
+
             // atomic selection predicate
             if let ConditionExpression::Base(ref l) = *ct.left.as_ref() {
                 if let ConditionExpression::Base(ref r) = *ct.right.as_ref() {
@@ -387,8 +572,9 @@ fn classify_conditionals(
                                         }
                                         join.push(join_ct);
                                     } else {
-                                        // non-equi-join?
-                                        unimplemented!();
+                                        return Err(super::unsupported(
+                                            "comma joins on anything but equality",
+                                        ));
                                     }
                                 } else {
                                     // not a comma join, just an ordinary comparison with a
@@ -398,7 +584,9 @@ fn classify_conditionals(
                                     global.push(ce.clone());
                                 }
                             } else {
-                                panic!("left hand side of comparison must be field");
+                                return Err(super::unsupported(
+                                    "comparisons of columns with anything but columns",
+                                ));
                             }
                         }
                         // right-hand side is a placeholder, so this must be a query parameter
@@ -423,7 +611,9 @@ fn classify_conditionals(
                             }
                         }
                         ConditionBase::LiteralList(_) => (),
-                        ConditionBase::NestedSelect(_) => unimplemented!(),
+                        ConditionBase::NestedSelect(_) => {
+                            return Err(super::unsupported("nested SELECTs in conditions"))
+                        }
                     }
                 };
             };
@@ -439,7 +629,10 @@ fn classify_conditionals(
                 &mut new_join,
                 global,
                 &mut new_params,
-            );
+            )?;
+            for (t, ces) in new_local {
+                local.entry(t).or_default().extend(ces);
+            }
             join.extend(new_join);
             params.extend(new_params);
         }
@@ -451,8 +644,11 @@ fn classify_conditionals(
         ConditionExpression::NegationOp(_) => {
             panic!("negation should have been removed earlier");
         }
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Arithmetic(_) => {
+            return Err(super::unsupported("arithmetic expressions as conditions"))
+        }
     }
+    Ok(())
 }
 
 #[allow(clippy::cognitive_complexity)]
@@ -465,6 +661,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
             QueryGraphNode {
                 rel_name: rel.clone(),
                 predicates: preds,
+                join_predicates: Vec::new(),
                 columns: st
                     .fields
                     .iter()
@@ -521,7 +718,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     );
                 }
             }
-            _ => unimplemented!(),
+            _ => return Err(super::unsupported("joins with anything but a table")),
         }
     }
 
@@ -551,86 +748,118 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                         let mut tables_mentioned: Vec<String> =
                             cond.referred_tables().into_iter().map(|t| t.name).collect();
 
-                        match *cond {
-                            ConditionExpression::ComparisonOp(ref ct) => {
-                                if tables_mentioned.len() == 2 {
-                                    // tables can appear in any order in the join predicate, but
-                                    // we cannot just rely on that order, since it may lead us to
-                                    // flip LEFT JOINs by accident (yes, this happened)
-                                    if tables_mentioned[1] != table.name {
-                                        // tables are in the wrong order in join predicate, swap
-                                        tables_mentioned.swap(0, 1);
-                                        assert_eq!(tables_mentioned[1], table.name);
-                                    }
-                                    left_table = tables_mentioned.remove(0);
-                                    right_table = tables_mentioned.remove(0);
-                                } else if tables_mentioned.len() == 1 {
-                                    // just one table mentioned --> this is a self-join
-                                    left_table = tables_mentioned.remove(0);
-                                    right_table = left_table.clone();
-                                } else {
-                                    unreachable!("more than 2 tables mentioned in join condition!");
-                                };
-
-                                // the condition tree might specify tables in opposite order to
-                                // their join order in the query; if so, flip them
-                                // TODO(malte): this only deals with simple, flat join
-                                // conditions for now.
-                                let l = match *ct.left.as_ref() {
-                                    ConditionExpression::Base(ConditionBase::Field(ref f)) => f,
-                                    _ => unimplemented!(),
-                                };
-                                let r = match *ct.right.as_ref() {
-                                    ConditionExpression::Base(ConditionBase::Field(ref f)) => f,
-                                    _ => unimplemented!(),
-                                };
-                                if *l.table.as_ref().unwrap() == right_table
-                                    && *r.table.as_ref().unwrap() == left_table
+                        if tables_mentioned.len() == 2 {
+                            // tables can appear in any order in the join predicate, but we cannot
+                            // just rely on that order, since it may lead us to flip LEFT JOINs by
+                            // accident (yes, this happened)
+                            if tables_mentioned[1] != table.name {
+                                // tables are in the wrong order in join predicate, swap
+                                tables_mentioned.swap(0, 1);
+                                assert_eq!(tables_mentioned[1], table.name);
+                            }
+                            left_table = tables_mentioned.remove(0);
+                            right_table = tables_mentioned.remove(0);
+                        } else if tables_mentioned.len() == 1 {
+                            // just one table mentioned --> this is a self-join
+                            left_table = tables_mentioned.remove(0);
+                            right_table = left_table.clone();
+                        } else {
+                            unreachable!("more than 2 tables mentioned in join condition!");
+                        };
+
+                        // a conjunction of equalities joins on all of their columns at once,
+                        // while comparisons of the joined table's columns with literals filter
+                        // it before the join
+                        let mut preds = Vec::new();
+                        for ce in split_conjunctions(vec![cond.clone()]) {
+                            let ct = match ce {
+                                ConditionExpression::ComparisonOp(ct) => ct,
+                                _ => {
+                                    return Err(super::unsupported(
+                                        "join conditions that are not comparisons",
+                                    ))
+                                }
+                            };
+                            let (l, r) = match (ct.left.as_ref(), ct.right.as_ref()) {
+                                (
+                                    ConditionExpression::Base(ConditionBase::Field(l)),
+                                    ConditionExpression::Base(ConditionBase::Field(r)),
+                                ) => (l, r),
+                                (
+                                    ConditionExpression::Base(ConditionBase::Field(c)),
+                                    ConditionExpression::Base(ConditionBase::Literal(l)),
+                                ) if c.table.as_ref() == Some(&table.name)
+                                    && *l != Literal::Placeholder =>
                                 {
-                                    ConditionTree {
-                                        operator: ct.operator.clone(),
-                                        left: ct.right.clone(),
-                                        right: ct.left.clone(),
-                                    }
-                                } else {
-                                    ct.clone()
+                                    qg.relations
+                                        .get_mut(&table.name)
+                                        .unwrap()
+                                        .join_predicates
+                                        .push(ConditionExpression::ComparisonOp(ct));
+                                    continue;
                                 }
+                                _ => {
+                                    return Err(super::unsupported(
+                                        "join conditions on anything but columns",
+                                    ))
+                                }
+                            };
+                            // the condition tree might specify tables in opposite order to their
+                            // join order in the query; if so, flip them
+                            if *l.table.as_ref().unwrap() == right_table
+                                && *r.table.as_ref().unwrap() == left_table
+                            {
+                                preds.push(ConditionTree {
+                                    operator: ct.operator.clone(),
+                                    left: ct.right.clone(),
+                                    right: ct.left.clone(),
+                                });
+                            } else {
+                                preds.push(ct);
                             }
-                            _ => panic!("join condition is not a comparison!"),
                         }
+                        let mut preds = preds.into_iter();
+                        let first = preds.next().ok_or_else(|| {
+                            super::unsupported("join conditions without a comparison of columns")
+                        })?;
+                        preds.fold(first, conjoin)
                     }
                     JoinConstraint::Using(ref cols) => {
-                        assert_eq!(cols.len(), 1);
-                        let col = cols.iter().next().unwrap();
-
                         left_table = prev_table.as_ref().unwrap().clone();
                         right_table = table.name.clone();
 
-                        ConditionTree {
+                        let mut preds = cols.iter().map(|col| ConditionTree {
                             operator: Operator::Equal,
                             left: wrapcol(&left_table, &col.name),
                             right: wrapcol(&right_table, &col.name),
-                        }
+                        });
+                        let first = preds.next().unwrap();
+                        preds.fold(first, conjoin)
                     }
                 };
 
                 // add edge for join
-                let mut _e = qg
-                    .edges
+                let edge = match jc.operator {
+                    JoinOperator::LeftJoin => QueryGraphEdge::LeftJoin(vec![join_pred]),
+                    JoinOperator::Join | JoinOperator::InnerJoin => {
+                        QueryGraphEdge::Join(vec![join_pred])
+                    }
+                    ref op => return Err(super::unsupported(format!("{}", op))),
+                };
+                qg.edges
                     .entry((left_table.clone(), right_table.clone()))
-                    .or_insert_with(|| match jc.operator {
-                        JoinOperator::LeftJoin => QueryGraphEdge::LeftJoin(vec![join_pred]),
-                        JoinOperator::Join | JoinOperator::InnerJoin => {
-                            QueryGraphEdge::Join(vec![join_pred])
-                        }
-                        _ => unimplemented!(),
-                    });
+                    .or_insert(edge);
             }
-            _ => unimplemented!(),
+            _ => return Err(super::unsupported("joins with anything but a table")),
         }
     }
 
-    if let Some(ref cond) = st.where_clause {
+    let (where_clause, parameter_disjunction) = match st.where_clause {
+        Some(ref cond) => split_parameter_disjunction(cond)?,
+        None => (None, Vec::new()),
+    };
+
+    if let Some(ref cond) = where_clause {
         let mut local_predicates = HashMap::new();
         let mut global_predicates = Vec::new();
         let mut query_parameters = Vec::new();
@@ -642,7 +871,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
             &mut join_predicates,
             &mut global_predicates,
             &mut query_parameters,
-        );
+        )?;
 
         for (_, ces) in local_predicates.iter_mut() {
             *ces = split_conjunctions(ces.clone());
@@ -676,12 +905,28 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                         .entry(r.table.clone().unwrap())
                         .or_insert_with(|| new_node(r.table.clone().unwrap(), Vec::new(), st));
 
+                    // predicates between tables that are already joined add to the key of that
+                    // join, rather than joining the tables again
+                    let (l_table, r_table) = (l.table.clone().unwrap(), r.table.clone().unwrap());
+                    let (key, jp) = if qg.edges.contains_key(&(r_table.clone(), l_table.clone())) {
+                        let flipped = ConditionTree {
+                            operator: jp.operator.clone(),
+                            left: jp.right.clone(),
+                            right: jp.left.clone(),
+                        };
+                        ((r_table, l_table), flipped)
+                    } else {
+                        ((l_table, r_table), jp.clone())
+                    };
                     let e = qg
                         .edges
-                        .entry((l.table.clone().unwrap(), r.table.clone().unwrap()))
+                        .entry(key)
                         .or_insert_with(|| QueryGraphEdge::Join(vec![]));
                     match *e {
-                        QueryGraphEdge::Join(ref mut preds) => preds.push(jp.clone()),
+                        QueryGraphEdge::Join(ref mut preds) => match preds.pop() {
+                            Some(prev) => preds.push(conjoin(prev, jp)),
+                            None => preds.push(jp),
+                        },
                         _ => panic!("Expected join edge for join condition {:#?}", jp),
                     };
                 }
@@ -712,6 +957,20 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         qg.global_predicates = global_predicates;
     }
 
+    // 5. Columns compared to parameters in a disjunction must also appear in the leaf node, but
+    //    they make up a single key rather than one each.
+    for column in &parameter_disjunction {
+        let rel = column
+            .table
+            .as_ref()
+            .and_then(|table| qg.relations.get_mut(table))
+            .ok_or_else(|| format!("parameter column {} is not in the query graph", column.name))?;
+        if !rel.columns.contains(column) {
+            rel.columns.push(column.clone());
+        }
+    }
+    qg.parameter_disjunction = parameter_disjunction;
+
     // Adds a computed column to the query graph if the given column has a function:
     let add_computed_column = |query_graph: &mut QueryGraph, column: &Column| {
         match column.function {
@@ -731,15 +990,23 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
 
     // 4. Add query graph nodes for any computed columns, which won't be represented in the
     //    nodes corresponding to individual relations.
+    let mut placeholders = 0;
     for field in st.fields.iter() {
         match *field {
             FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => {
                 panic!("Stars should have been expanded by now!")
             }
             FieldDefinitionExpression::Value(FieldValueExpression::Literal(ref l)) => {
+                // parameters of the projection are told apart by their position
+                if l.value == Literal::Placeholder {
+                    placeholders += 1;
+                }
                 qg.columns.push(OutputColumn::Literal(LiteralColumn {
                     name: match l.alias {
                         Some(ref a) => a.to_string(),
+                        None if l.value == Literal::Placeholder && placeholders > 1 => {
+                            format!("?{}", placeholders)
+                        }
                         None => l.value.to_string(),
                     },
                     table: None,
@@ -772,12 +1039,17 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         None => (),
         Some(ref clause) => {
             for column in &clause.columns {
-                // add an edge for each relation whose columns appear in the GROUP BY clause
+                // add an edge for each relation whose columns appear in the GROUP BY clause.
+                // columns without a table name an arithmetic expression in the field list, which
+                // is computed before grouping.
                 let e = qg
                     .edges
                     .entry((
                         String::from("computed_columns"),
-                        column.table.as_ref().unwrap().clone(),
+                        column
+                            .table
+                            .clone()
+                            .unwrap_or_else(|| String::from("computed_columns")),
                     ))
                     .or_insert_with(|| QueryGraphEdge::GroupBy(vec![]));
                 match *e {
@@ -102,7 +102,7 @@ impl Signature for QueryGraph {
         let mut attrs = HashSet::<&Column>::new();
         let mut attrs_vec = Vec::<&Column>::new();
         for n in self.relations.values() {
-            for p in &n.predicates {
+            for p in n.predicates.iter().chain(&n.join_predicates) {
                 match *p {
                     ComparisonOp(ref ct) | LogicalOp(ref ct) => {
                         for c in &ct.contained_columns() {
@@ -147,6 +147,12 @@ impl Signature for QueryGraph {
             }
         }
 
+        // So are the columns compared to parameters in a disjunction
+        for c in &self.parameter_disjunction {
+            attrs_vec.push(c);
+            attrs.insert(c);
+        }
+
         // Compute attributes part of hash
         attrs_vec.sort();
         for a in &attrs_vec {
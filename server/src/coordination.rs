@@ -1,9 +1,34 @@
+use crate::controller::placement::WorkerLoad;
 use dataflow::prelude::*;
 use dataflow::DomainBuilder;
 use noria::consensus::Epoch;
+use noria::WorkerConfigUpdate;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 
+/// The version of the coordination protocol this binary speaks.
+///
+/// This is bumped whenever workers and controllers of the old and new version can no longer
+/// understand each other's messages, or each other's domains.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest version of the coordination protocol this binary still speaks.
+///
+/// Controllers accept workers whose range of supported versions overlaps with their own, so that
+/// a cluster can be upgraded one machine at a time.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The newest protocol version spoken both by this binary and by a peer that speaks the versions
+/// `min..=max`, if there is one.
+pub fn negotiate_version(min: u32, max: u32) -> Option<u32> {
+    let version = u32::min(max, PROTOCOL_VERSION);
+    if version >= u32::max(min, MIN_PROTOCOL_VERSION) {
+        Some(version)
+    } else {
+        None
+    }
+}
+
 /// Coordination-layer message wrapper; adds a mandatory `source` field to each message.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CoordinationMessage {
@@ -26,11 +51,19 @@ pub enum CoordinationPayload {
         read_listen_addr: SocketAddr,
         /// Which log files are stored locally on the worker.
         log_files: Vec<String>,
+        /// Labels describing the worker, used to constrain domain placement.
+        labels: HashMap<String, String>,
+        /// The newest protocol version the worker speaks.
+        version: u32,
+        /// The oldest protocol version the worker speaks.
+        min_version: u32,
     },
+    /// The controller refuses to work with a worker, for the given reason.
+    Rejected(String),
     /// Worker going offline.
     Deregister,
-    /// Worker is still alive.
-    Heartbeat,
+    /// Worker is still alive, and is this busy.
+    Heartbeat(WorkerLoad),
     /// Assign a new domain for a worker to run.
     AssignDomain(DomainBuilder),
     /// Remove a running domain from a worker.
@@ -39,6 +72,8 @@ pub enum CoordinationPayload {
     DomainBooted(DomainDescriptor),
     /// Create a new security universe.
     CreateUniverse(HashMap<String, DataType>),
+    /// Change runtime parameters of a worker.
+    UpdateConfig(WorkerConfigUpdate),
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
@@ -65,3 +100,28 @@ impl DomainDescriptor {
         self.addr
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_newest_common_version() {
+        assert_eq!(
+            negotiate_version(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            Some(PROTOCOL_VERSION)
+        );
+        // a newer peer that still speaks our version
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION, PROTOCOL_VERSION + 1),
+            Some(PROTOCOL_VERSION)
+        );
+        // a newer peer that no longer does
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2),
+            None
+        );
+        // an older peer that we no longer speak to
+        assert_eq!(negotiate_version(0, MIN_PROTOCOL_VERSION - 1), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::chaos::Chaos;
 use crate::controller::migrate::Migration;
 use crate::startup::Event;
 use dataflow::prelude::*;
@@ -14,6 +15,8 @@ pub struct Handle<A: Authority + 'static> {
     #[allow(dead_code)]
     event_tx: Option<tokio::sync::mpsc::UnboundedSender<Event>>,
     kill: Option<Trigger>,
+    #[allow(dead_code)]
+    chaos: Chaos,
 }
 
 impl<A: Authority> Deref for Handle<A> {
@@ -34,15 +37,23 @@ impl<A: Authority + 'static> Handle<A> {
         authority: Arc<A>,
         event_tx: tokio::sync::mpsc::UnboundedSender<Event>,
         kill: Trigger,
+        chaos: Chaos,
     ) -> Result<Self, failure::Error> {
         let c = ControllerHandle::make(authority).await?;
         Ok(Handle {
             c: Some(c),
             event_tx: Some(event_tx),
             kill: Some(kill),
+            chaos,
         })
     }
 
+    /// The fault injector for coordination messages to and from this instance.
+    #[cfg(test)]
+    pub(crate) fn chaos(&self) -> &Chaos {
+        &self.chaos
+    }
+
     #[cfg(test)]
     pub(super) async fn backend_ready(&mut self) {
         use std::time;
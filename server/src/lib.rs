@@ -358,6 +358,7 @@ extern crate serde_derive;
 extern crate slog;
 
 mod builder;
+mod chaos;
 mod controller;
 mod coordination;
 mod handle;
@@ -378,8 +379,12 @@ pub enum ReuseConfigType {
 
 pub use crate::builder::Builder;
 pub use crate::handle::Handle;
-pub use controller::migrate::materialization::FrontierStrategy;
-pub use dataflow::{DurabilityMode, PersistenceParameters};
+pub use controller::migrate::materialization::{BloomFilterStrategy, FrontierStrategy};
+pub use controller::placement::{
+    DomainKind, DomainPlacement, LeastLoaded, PlacementConstraint, PlacementPolicy, RoundRobin,
+    WorkerCandidate, WorkerLoad,
+};
+pub use dataflow::{DurabilityMode, FsyncPolicy, PersistenceParameters};
 pub use noria::consensus::LocalAuthority;
 pub use noria::*;
 pub use petgraph::graph::NodeIndex;
@@ -391,6 +396,7 @@ pub mod manual {
     pub use dataflow::ops;
 }
 
+use controller::failure::FailureDetectorConfig;
 use dataflow::DomainConfig;
 use std::time;
 
@@ -398,14 +404,37 @@ use std::time;
 pub(crate) struct Config {
     pub(crate) sharding: Option<usize>,
     pub(crate) partial_enabled: bool,
+    #[serde(default)]
+    pub(crate) split_hot_keys: bool,
+    /// how many replicas of its reader each view gets in addition to the reader itself
+    #[serde(default)]
+    pub(crate) reader_replicas: usize,
     pub(crate) frontier_strategy: FrontierStrategy,
+    pub(crate) bloom_filter_strategy: BloomFilterStrategy,
     pub(crate) domain_config: DomainConfig,
     pub(crate) persistence: PersistenceParameters,
     pub(crate) heartbeat_every: time::Duration,
     pub(crate) healthcheck_every: time::Duration,
+    pub(crate) failure_detector: FailureDetectorConfig,
     pub(crate) quorum: usize,
     pub(crate) reuse: ReuseConfigType,
     pub(crate) threads: Option<usize>,
+    pub(crate) placement_constraints: Vec<PlacementConstraint>,
+    /// the most recipe changes that may wait to be applied at once
+    #[serde(default)]
+    pub(crate) migration_queue_limit: Option<usize>,
+    /// the zstd level at which traffic between domains on different hosts is compressed
+    #[serde(default)]
+    pub(crate) domain_compression: Option<i32>,
+    /// how long a partial view may go unread before its reader state is dropped
+    #[serde(default)]
+    pub(crate) view_hibernation: Option<time::Duration>,
+    /// the directory that the `INCLUDE` directives of recipes are resolved in, if any
+    #[serde(default)]
+    pub(crate) recipe_dir: Option<std::path::PathBuf>,
+    /// the seed that workers schedule their domains with, if they run them as a simulation
+    #[serde(default)]
+    pub(crate) simulation_seed: Option<u64>,
 }
 impl Default for Config {
     fn default() -> Self {
@@ -415,20 +444,39 @@ impl Default for Config {
             #[cfg(not(test))]
             sharding: None,
             partial_enabled: true,
+            split_hot_keys: false,
+            reader_replicas: 0,
             frontier_strategy: Default::default(),
+            bloom_filter_strategy: Default::default(),
             domain_config: DomainConfig {
                 concurrent_replays: 512,
                 replay_batch_timeout: time::Duration::new(0, 100_000),
+                full_replay_batch_size: 256,
+                full_replay_window: 16,
+                full_replay_threads: 1,
+                background_replay_share: 0.5,
+                max_batch_delay: time::Duration::from_millis(0),
             },
             persistence: Default::default(),
             heartbeat_every: time::Duration::from_secs(1),
-            healthcheck_every: time::Duration::from_secs(10),
+            // a failed worker is only noticed at the next health check, so checking every 10s (as
+            // we used to) would put a floor of up to 10s under the detector's much quicker
+            // verdict. a check only evaluates each worker's phi, which is cheap, and the detector
+            // rather than the check interval now decides how long a worker may stay silent.
+            healthcheck_every: time::Duration::from_secs(1),
+            failure_detector: Default::default(),
             quorum: 1,
             reuse: ReuseConfigType::Finkelstein,
             #[cfg(any(debug_assertions, test))]
             threads: Some(2),
             #[cfg(not(any(debug_assertions, test)))]
             threads: None,
+            placement_constraints: Vec::new(),
+            migration_queue_limit: None,
+            domain_compression: None,
+            view_hibernation: None,
+            recipe_dir: None,
+            simulation_seed: None,
         }
     }
 }
@@ -93,6 +93,34 @@ fn main() {
                 .default_value("1")
                 .help("Number of workers to wait for before starting (including this one)."),
         )
+        .arg(
+            Arg::with_name("hibernate")
+                .long("hibernate-after")
+                .takes_value(true)
+                .value_name("HOURS")
+                .help("Drop the reader state of partial views that have not been read for this many hours."),
+        )
+        .arg(
+            Arg::with_name("recipe-dir")
+                .long("recipe-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Directory that INCLUDE directives in recipes are resolved in (INCLUDE is disabled otherwise)."),
+        )
+        .arg(
+            Arg::with_name("migration-queue")
+                .long("migration-queue")
+                .takes_value(true)
+                .default_value("0")
+                .help("Number of recipe changes that may wait to be applied at once [0 = unlimited]."),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress-domain-traffic")
+                .takes_value(true)
+                .value_name("LEVEL")
+                .help("Compress traffic between domains on different hosts at this zstd level."),
+        )
         .arg(
             Arg::with_name("shards")
                 .long("shards")
@@ -100,6 +128,19 @@ fn main() {
                 .default_value("0")
                 .help("Shard the graph this many ways (0 = disable sharding)."),
         )
+        .arg(
+            Arg::with_name("label")
+                .long("label")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Label this worker for placement constraints [key=value]."),
+        )
+        .arg(
+            Arg::with_name("pin-cores")
+                .long("pin-cores")
+                .help("Run each domain on its own thread, pinned to a core."),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -117,6 +158,7 @@ fn main() {
     let memory = value_t_or_exit!(matches, "memory", usize);
     let memory_check_freq = value_t_or_exit!(matches, "memory_check_freq", u64);
     let quorum = value_t_or_exit!(matches, "quorum", usize);
+    let migration_queue = value_t_or_exit!(matches, "migration-queue", usize);
     let persistence_threads = value_t_or_exit!(matches, "persistence-threads", i32);
     let flush_ns = value_t_or_exit!(matches, "flush-timeout", u32);
     let sharding = match value_t_or_exit!(matches, "shards", usize) {
@@ -135,12 +177,37 @@ fn main() {
     }
     builder.set_sharding(sharding);
     builder.set_quorum(quorum);
+    if migration_queue > 0 {
+        builder.set_migration_queue_limit(migration_queue);
+    }
+    if matches.is_present("hibernate") {
+        let hours = value_t_or_exit!(matches, "hibernate", u64);
+        builder.set_view_hibernation(Duration::from_secs(hours * 60 * 60));
+    }
+    if let Some(dir) = matches.value_of("recipe-dir") {
+        builder.set_recipe_dir(dir);
+    }
+    if matches.is_present("compress") {
+        builder.set_domain_compression(value_t_or_exit!(matches, "compress", i32));
+    }
     if matches.is_present("nopartial") {
         builder.disable_partial();
     }
     if matches.is_present("noreuse") {
         builder.set_reuse(ReuseConfigType::NoReuse);
     }
+    if matches.is_present("pin-cores") {
+        builder.set_pin_cores(true);
+    }
+    for label in matches.values_of("label").into_iter().flatten() {
+        let mut kv = label.splitn(2, '=');
+        let key = kv.next().unwrap();
+        let value = kv.next().unwrap_or_else(|| {
+            eprintln!("labels must be of the form key=value, got {}", label);
+            std::process::exit(1);
+        });
+        builder.set_label(key, value);
+    }
 
     let mut persistence_params = noria_server::PersistenceParameters::new(
         match durability {
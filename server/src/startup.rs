@@ -1,3 +1,6 @@
+use crate::controller::migrate::progress::MigrationProgress;
+use crate::controller::migrate::queue::{MigrationQueue, RECIPE_CHANGES};
+use crate::controller::placement::PlacementPolicy;
 use crate::controller::ControllerState;
 use crate::coordination::{CoordinationMessage, CoordinationPayload};
 use async_bincode::AsyncBincodeReader;
@@ -9,10 +12,12 @@ use futures_util::{
 };
 use hyper::{self, header::CONTENT_TYPE, Method, StatusCode};
 use noria::consensus::Authority;
+use noria::error::NoriaError;
 use noria::ControllerDescriptor;
+use std::collections::HashMap;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time;
 use std::{
     future::Future,
@@ -22,7 +27,9 @@ use std::{
 use stream_cancel::Valve;
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::chaos::Chaos;
 use crate::handle::Handle;
+use crate::worker::shipping::Standby;
 use crate::Config;
 
 #[allow(clippy::large_enum_variant)]
@@ -33,7 +40,7 @@ pub(crate) enum Event {
         String,
         Option<String>,
         hyper::body::Bytes,
-        tokio::sync::oneshot::Sender<Result<Result<String, String>, StatusCode>>,
+        tokio::sync::oneshot::Sender<Result<Result<String, NoriaError>, StatusCode>>,
     ),
     LeaderChange(ControllerState, ControllerDescriptor),
     WonLeaderElection(ControllerState),
@@ -70,6 +77,10 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     config: Config,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    placement: Arc<dyn PlacementPolicy>,
+    labels: HashMap<String, String>,
+    pin_cores: bool,
+    standby: Option<Standby>,
     log: slog::Logger,
 ) -> Result<(Handle<A>, impl Future<Output = ()> + Unpin + Send), failure::Error> {
     let (trigger, valve) = Valve::new();
@@ -92,6 +103,14 @@ pub(super) async fn start_instance<A: Authority + 'static>(
     let cport = tokio::net::TcpListener::bind(SocketAddr::new(listen_addr, 0)).await?;
     let caddr = cport.local_addr()?;
 
+    // migration progress is reported by the external server directly, since the controller is
+    // busy while migrating.
+    let migration_progress = Arc::new(Mutex::new(MigrationProgress::default()));
+    // so is the queue of recipe changes that are waiting for the controller
+    let migration_queue = Arc::new(Mutex::new(MigrationQueue::new(
+        config.migration_queue_limit,
+    )));
+
     // set up different loops for the controller "part" and the worker "part" of us. this is
     // necessary because sometimes the two need to communicate (e.g., for migrations), and if they
     // were in a single loop, that could deadlock.
@@ -114,6 +133,8 @@ pub(super) async fn start_instance<A: Authority + 'static>(
             tx.clone(),
             xport,
             authority.clone(),
+            migration_progress.clone(),
+            migration_queue,
         )
         .map_err(move |e| {
             warn!(ext_log, "external request failed: {:?}", e);
@@ -121,33 +142,30 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         .map(|_| ()),
     );
 
+    let chaos = Chaos::default();
+
     // first, a loop that just forwards to the appropriate place
     let a = alive.clone();
+    #[cfg_attr(not(test), allow(unused_variables))]
+    let c = chaos.clone();
     tokio::spawn(async move {
         let _alive = a;
         let ctx = ctrl_tx;
         let wtx = worker_tx;
         while let Some(e) = rx.next().await {
-            let snd = match e {
-                Event::InternalMessage(ref msg) => match msg.payload {
-                    CoordinationPayload::Deregister => ctx.send(e),
-                    CoordinationPayload::RemoveDomain => wtx.send(e),
-                    CoordinationPayload::AssignDomain(..) => wtx.send(e),
-                    CoordinationPayload::DomainBooted(..) => wtx.send(e),
-                    CoordinationPayload::Register { .. } => ctx.send(e),
-                    CoordinationPayload::Heartbeat => ctx.send(e),
-                    CoordinationPayload::CreateUniverse(..) => ctx.send(e),
-                },
-                Event::ExternalRequest(..) => ctx.send(e),
-                Event::ManualMigration { .. } => ctx.send(e),
-                Event::LeaderChange(..) => wtx.send(e),
-                Event::WonLeaderElection(..) => ctx.send(e),
-                Event::CampaignError(..) => ctx.send(e),
+            let e = match e {
+                // in tests, coordination messages pass through the fault injector on their way in
                 #[cfg(test)]
-                Event::IsReady(..) => ctx.send(e),
+                Event::InternalMessage(msg) => {
+                    let (ctx, wtx) = (ctx.clone(), wtx.clone());
+                    crate::chaos::deliver(&c, msg, move |msg| {
+                        forward(Event::InternalMessage(msg), &ctx, &wtx)
+                    });
+                    continue;
+                }
+                e => e,
             };
-            // needed for https://gist.github.com/nikomatsakis/fee0e47e14c09c4202316d8ea51e50a0
-            snd.unwrap();
+            forward(e, &ctx, &wtx);
         }
     });
 
@@ -167,6 +185,8 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         log.clone(),
         authority.clone(),
         tx.clone(),
+        migration_progress,
+        placement,
     ));
     tokio::spawn(crate::worker::main(
         alive.clone(),
@@ -175,13 +195,43 @@ pub(super) async fn start_instance<A: Authority + 'static>(
         waddr,
         memory_limit,
         memory_check_frequency,
+        labels,
+        pin_cores,
+        standby,
+        chaos.clone(),
         log.clone(),
     ));
 
-    let h = Handle::new(authority, tx, trigger).await?;
+    let h = Handle::new(authority, tx, trigger, chaos).await?;
     Ok((h, done.into_future().map(|_| {})))
 }
 
+/// Send an event to whichever of the controller and the worker is supposed to handle it.
+fn forward(e: Event, ctx: &UnboundedSender<Event>, wtx: &UnboundedSender<Event>) {
+    let snd = match e {
+        Event::InternalMessage(ref msg) => match msg.payload {
+            CoordinationPayload::Deregister => ctx.send(e),
+            CoordinationPayload::RemoveDomain => wtx.send(e),
+            CoordinationPayload::AssignDomain(..) => wtx.send(e),
+            CoordinationPayload::DomainBooted(..) => wtx.send(e),
+            CoordinationPayload::UpdateConfig(..) => wtx.send(e),
+            CoordinationPayload::Rejected(..) => wtx.send(e),
+            CoordinationPayload::Register { .. } => ctx.send(e),
+            CoordinationPayload::Heartbeat(..) => ctx.send(e),
+            CoordinationPayload::CreateUniverse(..) => ctx.send(e),
+        },
+        Event::ExternalRequest(..) => ctx.send(e),
+        Event::ManualMigration { .. } => ctx.send(e),
+        Event::LeaderChange(..) => wtx.send(e),
+        Event::WonLeaderElection(..) => ctx.send(e),
+        Event::CampaignError(..) => ctx.send(e),
+        #[cfg(test)]
+        Event::IsReady(..) => ctx.send(e),
+    };
+    // needed for https://gist.github.com/nikomatsakis/fee0e47e14c09c4202316d8ea51e50a0
+    snd.unwrap();
+}
+
 async fn listen_internal(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
@@ -222,6 +272,8 @@ struct ExternalServer<A: Authority>(
     tokio::sync::mpsc::Sender<()>,
     UnboundedSender<Event>,
     Arc<A>,
+    Arc<Mutex<MigrationProgress>>,
+    Arc<Mutex<MigrationQueue>>,
 );
 
 async fn listen_external<A: Authority + 'static>(
@@ -230,6 +282,8 @@ async fn listen_external<A: Authority + 'static>(
     event_tx: UnboundedSender<Event>,
     mut on: tokio::net::TcpListener,
     authority: Arc<A>,
+    migration_progress: Arc<Mutex<MigrationProgress>>,
+    migration_queue: Arc<Mutex<MigrationQueue>>,
 ) -> Result<(), hyper::Error> {
     let on = valve.wrap(on.incoming());
     use hyper::{service::make_service_fn, Body, Request, Response};
@@ -237,7 +291,13 @@ async fn listen_external<A: Authority + 'static>(
     impl<A: Authority> Clone for ExternalServer<A> {
         // Needed due to #26925
         fn clone(&self) -> Self {
-            ExternalServer(self.0.clone(), self.1.clone(), self.2.clone())
+            ExternalServer(
+                self.0.clone(),
+                self.1.clone(),
+                self.2.clone(),
+                self.3.clone(),
+                self.4.clone(),
+            )
         }
     }
 
@@ -275,12 +335,47 @@ async fn listen_external<A: Authority + 'static>(
                 }
             }
 
+            if req.uri().path() == "/migration_status" {
+                // answered here rather than by the controller, which may be busy migrating
+                let status = self.3.lock().unwrap().status();
+                let res = res
+                    .header("Content-Type", "application/json; charset=utf-8")
+                    .body(hyper::Body::from(serde_json::to_string(&status).unwrap()));
+                return Box::pin(async move { Ok(res.unwrap()) });
+            }
+
+            if req.uri().path() == "/migration_queue" {
+                let status = self.4.lock().unwrap().status();
+                let res = res
+                    .header("Content-Type", "application/json; charset=utf-8")
+                    .body(hyper::Body::from(serde_json::to_string(&status).unwrap()));
+                return Box::pin(async move { Ok(res.unwrap()) });
+            }
+
+            // recipe changes wait for their turn in the queue, unless it is full, in which case
+            // clients are told to come back later
+            let slot = if RECIPE_CHANGES.contains(&req.uri().path()) {
+                match MigrationQueue::enter(&self.4) {
+                    Some(slot) => Some(slot),
+                    None => {
+                        let res = res
+                            .status(StatusCode::TOO_MANY_REQUESTS)
+                            .header("Content-Type", "text/plain; charset=utf-8")
+                            .body(hyper::Body::from("too many queued recipe changes"));
+                        return Box::pin(async move { Ok(res.unwrap()) });
+                    }
+                }
+            } else {
+                None
+            };
+
             let method = req.method().clone();
             let path = req.uri().path().to_string();
             let query = req.uri().query().map(ToOwned::to_owned);
             let event_tx = self.1.clone();
 
             Box::pin(async move {
+                let _slot = slot;
                 let body = hyper::body::to_bytes(req.into_body()).await?;
                 let (tx, rx) = tokio::sync::oneshot::channel();
 
@@ -300,8 +395,8 @@ async fn listen_external<A: Authority + 'static>(
                                 .body(hyper::Body::from(reply)),
                             Ok(Err(reply)) => res
                                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                .header("Content-Type", "text/plain; charset=utf-8")
-                                .body(hyper::Body::from(reply)),
+                                .header("Content-Type", "application/json; charset=utf-8")
+                                .body(hyper::Body::from(serde_json::to_string(&reply).unwrap())),
                             Err(status_code) => res.status(status_code).body(hyper::Body::empty()),
                         };
                         Ok(res.unwrap())
@@ -315,7 +410,13 @@ async fn listen_external<A: Authority + 'static>(
         }
     }
 
-    let service = ExternalServer(alive, event_tx, authority);
+    let service = ExternalServer(
+        alive,
+        event_tx,
+        authority,
+        migration_progress,
+        migration_queue,
+    );
     hyper::server::Server::builder(hyper::server::accept::from_stream(on))
         .serve(make_service_fn(move |_| {
             let s = service.clone();
@@ -1,21 +1,28 @@
+use crate::chaos::Chaos;
+use crate::controller::placement::WorkerLoad;
 use crate::controller::ControllerState;
-use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
+use crate::coordination::{
+    CoordinationMessage, CoordinationPayload, DomainDescriptor, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
 use crate::startup::Event;
 use async_bincode::AsyncBincodeWriter;
 use dataflow::{DomainBuilder, Packet};
 use futures_util::{future::FutureExt, future::TryFutureExt, sink::SinkExt, stream::StreamExt};
 use noria::channel;
 use noria::consensus::Epoch;
+use noria::debug::stats::DomainQueue;
 use noria::internal::DomainIndex;
-use noria::ControllerDescriptor;
+use noria::{ControllerDescriptor, WorkerConfigUpdate};
 use replica::ReplicaAddr;
-use slog;
-use std::collections::HashMap;
+use slog::{self, Drain};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
+    atomic::{AtomicU64, AtomicUsize, Ordering},
     Arc, Mutex,
 };
 use std::time::{self, Duration};
@@ -25,15 +32,94 @@ use tokio::sync::mpsc::UnboundedSender;
 
 mod readers;
 mod replica;
+pub(crate) mod shipping;
+mod simulation;
+mod throttle;
+mod topology;
 
 type ChannelCoordinator = channel::ChannelCoordinator<ReplicaAddr, Box<Packet>>;
 
+/// How often the eviction task checks whether it has been given an eviction interval.
+const EVICTION_IDLE_POLL: Duration = Duration::from_secs(1);
+
+/// How many batches of writes the domains of a worker may have handed to the task that ships
+/// them to the standby before they have to wait for it to catch up.
+const SHIPPING_QUEUE: usize = 1024;
+
+/// Parameters of a worker that the controller may change while the worker runs.
+struct Runtime {
+    memory_limit: Mutex<Option<usize>>,
+    evict_every: Mutex<Option<Duration>>,
+    /// Overrides the batch delay of all domains, in nanoseconds. `u64::MAX` if not overridden.
+    max_batch_delay: AtomicU64,
+    /// The least severe log level that is let through, as given by `slog::Level::as_usize`.
+    log_level: Arc<AtomicUsize>,
+}
+
+impl Runtime {
+    fn new(memory_limit: Option<usize>, evict_every: Option<Duration>) -> Self {
+        Runtime {
+            memory_limit: Mutex::new(memory_limit),
+            evict_every: Mutex::new(evict_every),
+            max_batch_delay: AtomicU64::new(u64::max_value()),
+            log_level: Arc::new(AtomicUsize::new(slog::Level::Trace.as_usize())),
+        }
+    }
+
+    /// Wrap `log` so that it only lets through messages at the current log level.
+    fn filter(&self, log: slog::Logger) -> slog::Logger {
+        let level = self.log_level.clone();
+        let drain = slog::Filter::new(log, move |r: &slog::Record| {
+            r.level().as_usize() <= level.load(Ordering::Relaxed)
+        });
+        slog::Logger::root(drain.ignore_res(), o!())
+    }
+
+    fn update(&self, update: &WorkerConfigUpdate) {
+        if let Some(limit) = update.memory_limit {
+            *self.memory_limit.lock().unwrap() = if limit == 0 { None } else { Some(limit) };
+        }
+        if let Some(every) = update.eviction_interval {
+            *self.evict_every.lock().unwrap() = Some(every);
+        }
+        if let Some(delay) = update.max_batch_delay {
+            let nanos = delay.as_nanos().min(u128::from(u64::max_value() - 1)) as u64;
+            self.max_batch_delay.store(nanos, Ordering::Relaxed);
+        }
+        if let Some(level) = update
+            .log_level
+            .as_ref()
+            .and_then(|l| l.parse::<slog::Level>().ok())
+        {
+            self.log_level.store(level.as_usize(), Ordering::Relaxed);
+        }
+    }
+
+    fn memory_limit(&self) -> Option<usize> {
+        *self.memory_limit.lock().unwrap()
+    }
+
+    fn evict_every(&self) -> Option<Duration> {
+        *self.evict_every.lock().unwrap()
+    }
+
+    /// The batch delay that domains should use instead of their own, if any.
+    fn max_batch_delay(&self) -> Option<Duration> {
+        match self.max_batch_delay.load(Ordering::Relaxed) {
+            n if n == u64::max_value() => None,
+            n => Some(Duration::from_nanos(n)),
+        }
+    }
+}
+
 enum InstanceState {
     Pining,
     Active {
         epoch: Epoch,
         trigger: Trigger,
         add_domain: UnboundedSender<DomainBuilder>,
+        /// Domain shards we have been asked to run in this epoch.
+        assigned: HashSet<(DomainIndex, usize)>,
     },
 }
 
@@ -41,7 +127,152 @@ impl InstanceState {
     fn take(&mut self) -> Self {
         ::std::mem::replace(self, InstanceState::Pining)
     }
+
+    /// Shut down all our domains, and stop accepting connections to them.
+    ///
+    /// This is used when we discover that a newer controller exists that we never heard about.
+    /// Whatever that controller thinks we are running, it is not what we *are* running, so the
+    /// only safe thing to do is to stop serving until we learn about the new leader.
+    fn fence(&mut self, log: &slog::Logger) {
+        if let InstanceState::Active {
+            add_domain,
+            trigger,
+            ..
+        } = self.take()
+        {
+            warn!(log, "missed a change of controller; dropping all domains");
+            drop(add_domain);
+            trigger.cancel();
+        }
+    }
 }
+/// Messages from a controller that we have not connected to yet.
+///
+/// A new controller may assign domains to us before we learn that it has taken over. Those
+/// messages are held until we connect to it, and are then handled as if they had just arrived.
+#[derive(Debug)]
+struct Deferred<E, M> {
+    msgs: Vec<(E, M)>,
+}
+
+impl<E, M> Default for Deferred<E, M> {
+    fn default() -> Self {
+        Deferred { msgs: Vec::new() }
+    }
+}
+
+impl<E: Copy + Ord, M> Deferred<E, M> {
+    fn defer(&mut self, epoch: E, msg: M) {
+        self.msgs.push((epoch, msg));
+    }
+
+    /// Take the messages of `epoch`, in the order they arrived in. Messages from older epochs are
+    /// dropped, and those from newer ones are kept.
+    fn take(&mut self, epoch: E) -> Vec<M> {
+        let mut taken = Vec::new();
+        let mut kept = Vec::new();
+        for (e, msg) in self.msgs.drain(..) {
+            if e == epoch {
+                taken.push(msg);
+            } else if e > epoch {
+                kept.push((e, msg));
+            }
+        }
+        self.msgs = kept;
+        taken
+    }
+}
+
+fn handle_message(
+    worker_state: &mut InstanceState,
+    deferred: &mut Deferred<Epoch, CoordinationMessage>,
+    msg: CoordinationMessage,
+    coord: &ChannelCoordinator,
+    runtime: &Runtime,
+    log: &slog::Logger,
+) {
+    // a message from a newer controller means that we missed a change of leader. we can't serve
+    // that controller until we have connected to it, so we stop serving the old one and hold on
+    // to the message until then.
+    let from_newer = match *worker_state {
+        InstanceState::Active { epoch, .. } => msg.epoch > epoch,
+        InstanceState::Pining => true,
+    };
+    match msg.payload {
+        CoordinationPayload::AssignDomain(..) | CoordinationPayload::DomainBooted(..)
+            if from_newer =>
+        {
+            worker_state.fence(log);
+            debug!(log, "holding on to message from controller we are not connected to";
+                   "epoch" => ?msg.epoch);
+            deferred.defer(msg.epoch, msg);
+            return;
+        }
+        _ => {}
+    }
+
+    match msg.payload {
+        CoordinationPayload::RemoveDomain => {
+            unimplemented!();
+        }
+        CoordinationPayload::AssignDomain(d) => {
+            if let InstanceState::Active {
+                epoch,
+                ref mut add_domain,
+                ref mut assigned,
+                ..
+            } = *worker_state
+            {
+                let shard = d.shard.unwrap_or(0);
+                if msg.epoch < epoch {
+                    warn!(log, "ignoring domain assignment from old controller";
+                          "domain" => d.index.index(), "shard" => shard);
+                } else if !assigned.insert((d.index, shard)) {
+                    // running two copies of a domain would duplicate its output
+                    error!(
+                        log,
+                        "refusing to run domain {}.{} twice",
+                        d.index.index(),
+                        shard
+                    );
+                } else {
+                    add_domain.send(d).unwrap_or_else(|d| {
+                        panic!("could not add new domain {:?}", d);
+                    });
+                }
+            }
+        }
+        CoordinationPayload::DomainBooted(dd) => {
+            if let InstanceState::Active { epoch, .. } = *worker_state {
+                if epoch == msg.epoch {
+                    let domain = dd.domain();
+                    let shard = dd.shard();
+                    let addr = dd.addr();
+                    trace!(
+                        log,
+                        "found that domain {}.{} is at {:?}",
+                        domain.index(),
+                        shard,
+                        addr
+                    );
+                    coord.insert_remote((domain, shard), addr);
+                }
+            }
+        }
+        CoordinationPayload::Rejected(reason) => {
+            // running domains for a controller that does not understand us could only
+            // produce wrong results
+            crit!(log, "controller refused this worker: {}", reason);
+            worker_state.fence(log);
+        }
+        CoordinationPayload::UpdateConfig(update) => {
+            info!(log, "updating runtime parameters"; "update" => ?update);
+            runtime.update(&update);
+        }
+        _ => unreachable!(),
+    }
+}
+
 pub(super) async fn main(
     alive: tokio::sync::mpsc::Sender<()>,
     mut worker_rx: tokio::sync::mpsc::UnboundedReceiver<Event>,
@@ -49,54 +280,30 @@ pub(super) async fn main(
     waddr: SocketAddr,
     memory_limit: Option<usize>,
     memory_check_frequency: Option<time::Duration>,
+    labels: HashMap<String, String>,
+    pin_cores: bool,
+    standby: Option<shipping::Standby>,
+    chaos: Chaos,
     log: slog::Logger,
 ) {
     // shared df state
     let coord = Arc::new(ChannelCoordinator::new());
 
+    let runtime = Arc::new(Runtime::new(memory_limit, memory_check_frequency));
+    let log = runtime.filter(log);
+
     let mut worker_state = InstanceState::Pining;
-    let log = log.clone();
+    let mut deferred = Deferred::default();
     while let Some(e) = worker_rx.next().await {
         match e {
-            Event::InternalMessage(msg) => match msg.payload {
-                CoordinationPayload::RemoveDomain => {
-                    unimplemented!();
-                }
-                CoordinationPayload::AssignDomain(d) => {
-                    if let InstanceState::Active {
-                        epoch,
-                        ref mut add_domain,
-                        ..
-                    } = worker_state
-                    {
-                        if epoch == msg.epoch {
-                            add_domain.send(d).unwrap_or_else(|d| {
-                                panic!("could not add new domain {:?}", d);
-                            });
-                        }
-                    } else {
-                        unreachable!();
-                    }
-                }
-                CoordinationPayload::DomainBooted(dd) => {
-                    if let InstanceState::Active { epoch, .. } = worker_state {
-                        if epoch == msg.epoch {
-                            let domain = dd.domain();
-                            let shard = dd.shard();
-                            let addr = dd.addr();
-                            trace!(
-                                log,
-                                "found that domain {}.{} is at {:?}",
-                                domain.index(),
-                                shard,
-                                addr
-                            );
-                            coord.insert_remote((domain, shard), addr);
-                        }
-                    }
-                }
-                _ => unreachable!(),
-            },
+            Event::InternalMessage(msg) => handle_message(
+                &mut worker_state,
+                &mut deferred,
+                msg,
+                &coord,
+                &runtime,
+                &log,
+            ),
             Event::LeaderChange(state, descriptor) => {
                 if let InstanceState::Active {
                     add_domain,
@@ -136,12 +343,16 @@ pub(super) async fn main(
                     alive.clone(),
                     valve,
                     log.clone(),
-                    (memory_limit, memory_check_frequency),
+                    runtime.clone(),
+                    labels.clone(),
+                    chaos.clone(),
                     &state,
                     &descriptor,
                     waddr,
                     coord.clone(),
                     listen_addr,
+                    pin_cores,
+                    standby.clone(),
                     rep_rx,
                 )
                 .await;
@@ -155,8 +366,21 @@ pub(super) async fn main(
                         epoch: state.epoch,
                         add_domain: rep_tx,
                         trigger,
+                        assigned: HashSet::new(),
                     };
                     warn!(log, "Connected to new leader");
+
+                    // the new leader may have sent us domains before we heard about it
+                    for msg in deferred.take(state.epoch) {
+                        handle_message(
+                            &mut worker_state,
+                            &mut deferred,
+                            msg,
+                            &coord,
+                            &runtime,
+                            &log,
+                        );
+                    }
                 }
             }
             e => unreachable!("{:?} is not a worker event", e),
@@ -175,12 +399,16 @@ async fn listen_df<'a>(
     alive: tokio::sync::mpsc::Sender<()>,
     valve: Valve,
     log: slog::Logger,
-    (memory_limit, evict_every): (Option<usize>, Option<Duration>),
+    runtime: Arc<Runtime>,
+    labels: HashMap<String, String>,
+    #[cfg_attr(not(test), allow(unused_variables))] chaos: Chaos,
     state: &'a ControllerState,
     desc: &'a ControllerDescriptor,
     waddr: SocketAddr,
     coord: Arc<ChannelCoordinator>,
     on: IpAddr,
+    pin_cores: bool,
+    standby: Option<shipping::Standby>,
     mut replicas: tokio::sync::mpsc::UnboundedReceiver<DomainBuilder>,
 ) -> Result<(), failure::Error> {
     // first, try to connect to controller
@@ -200,6 +428,7 @@ async fn listen_df<'a>(
 
     // extract important things from state config
     let epoch = state.epoch;
+    coord.set_compression(state.config.domain_compression);
     let heartbeat_every = state.config.heartbeat_every;
 
     let (ctrl_tx, mut ctrl_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -210,6 +439,22 @@ async fn listen_df<'a>(
     let raddr = rport.local_addr()?;
     info!(log, "listening for reads"; "on" => ?raddr);
 
+    // in tests, messages to the controller pass through the fault injector on their way out
+    #[cfg(test)]
+    let mut ctrl_rx = {
+        let (out_tx, out_rx) = tokio::sync::mpsc::unbounded_channel();
+        let chaos = chaos.clone();
+        tokio::spawn(async move {
+            while let Some(cm) = ctrl_rx.next().await {
+                let out_tx = out_tx.clone();
+                crate::chaos::deliver(&chaos, cm, move |cm| {
+                    let _ = out_tx.send(cm);
+                });
+            }
+        });
+        out_rx
+    };
+
     // start controller message handler
     let mut ctrl = AsyncBincodeWriter::from(ctrl).for_async();
     let a = alive.clone();
@@ -239,6 +484,15 @@ async fn listen_df<'a>(
         readers.clone(),
     ));
 
+    let state_sizes = Arc::new(Mutex::new(HashMap::new()));
+    let queues = Arc::new(Mutex::new(HashMap::new()));
+    let disk = state
+        .config
+        .persistence
+        .log_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+
     // and tell the controller about us
     let mut timer = valve.wrap(tokio::time::interval_at(
         tokio::time::Instant::now() + heartbeat_every,
@@ -246,40 +500,57 @@ async fn listen_df<'a>(
     ));
     let a = alive.clone();
     let ctx = ctrl_tx.clone();
+    let sizes = state_sizes.clone();
+    let qs = queues.clone();
+    let standby_lag = Arc::new(AtomicUsize::new(0));
+    let lag = standby_lag.clone();
     tokio::spawn(async move {
         let _alive = a;
         let _ = ctx.send(CoordinationPayload::Register {
             addr: waddr,
             read_listen_addr: raddr,
             log_files,
+            labels,
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
         });
 
         // start sending heartbeats
         while let Some(_) = timer.next().await {
-            if let Err(_) = ctx.send(CoordinationPayload::Heartbeat) {
+            let load = tokio::task::block_in_place(|| current_load(&sizes, &qs, &lag, &disk));
+            if let Err(_) = ctx.send(CoordinationPayload::Heartbeat(load)) {
                 // if we error we're probably just shutting down
                 break;
             }
         }
     });
 
-    let state_sizes = Arc::new(Mutex::new(HashMap::new()));
-    if let Some(evict_every) = evict_every {
+    {
+        // the eviction interval may be changed while we run, so we pick it anew each round
         let log = log.clone();
         let coord = coord.clone();
         let mut domain_senders = HashMap::new();
         let state_sizes = state_sizes.clone();
-        let mut timer = valve.wrap(tokio::time::interval_at(
-            tokio::time::Instant::now() + evict_every,
-            evict_every,
-        ));
+        let rt = runtime.clone();
+        let mut timer = valve.wrap(Box::pin(futures_util::stream::unfold(
+            rt,
+            |rt| async move {
+                let every = rt.evict_every();
+                tokio::time::delay_for(every.unwrap_or(EVICTION_IDLE_POLL)).await;
+                Some((every.is_some(), rt))
+            },
+        )));
         let a = alive.clone();
+        let runtime = runtime.clone();
         tokio::spawn(async move {
             let _alive = a;
-            while let Some(_) = timer.next().await {
+            while let Some(evict) = timer.next().await {
+                if !evict {
+                    continue;
+                }
                 do_eviction(
                     &log,
-                    memory_limit,
+                    runtime.memory_limit(),
                     &mut domain_senders,
                     &coord,
                     &state_sizes,
@@ -289,8 +560,46 @@ async fn listen_df<'a>(
         });
     }
 
+    // writes to bases are shipped to the standby by a single task, so that they are applied in
+    // the order the domains saw them.
+    let shipper = standby.map(|standby| {
+        let (tx, rx) = tokio::sync::mpsc::channel(SHIPPING_QUEUE);
+        tokio::spawn(shipping::ship(
+            standby,
+            valve.wrap(rx),
+            standby_lag.clone(),
+            log.clone(),
+        ));
+        tx
+    });
+
     // Now we're ready to accept new domains.
     let dcaddr = desc.domain_addr;
+    let simulation = match state.config.simulation_seed {
+        Some(seed) => {
+            info!(log, "scheduling domains as a simulation"; "seed" => seed);
+            Some(simulation::Simulation::start(seed)?)
+        }
+        None => None,
+    };
+    let mut topology = if pin_cores && simulation.is_none() {
+        match core_affinity::get_core_ids().filter(|cores| !cores.is_empty()) {
+            Some(cores) => {
+                let topology = topology::Topology::detect(cores);
+                info!(log, "pinning domains to cores"; "numa_nodes" => topology.nodes());
+                Some(topology)
+            }
+            None => {
+                warn!(
+                    log,
+                    "could not find the cores of this machine; not pinning domains"
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
     tokio::spawn(
         async move {
             let alive = alive;
@@ -298,10 +607,12 @@ async fn listen_df<'a>(
                 let idx = d.index;
                 let shard = d.shard.unwrap_or(0);
 
-                let on = tokio::net::TcpListener::bind(&SocketAddr::new(on, 0)).await?;
+                // the listener is registered with the runtime the domain ends up running on
+                let on = std::net::TcpListener::bind(&SocketAddr::new(on, 0))?;
                 let addr = on.local_addr()?;
 
                 let state_size = Arc::new(AtomicUsize::new(0));
+                let queued = Arc::new(AtomicUsize::new(0));
                 let d = tokio::task::block_in_place(|| {
                     d.build(
                         log.clone(),
@@ -310,11 +621,29 @@ async fn listen_df<'a>(
                         dcaddr,
                         &valve,
                         state_size.clone(),
+                        shipper.clone(),
                     )
                 });
 
                 let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
+                // in tests, packets between domains on this worker pass through the fault injector
+                #[cfg(test)]
+                let rx = {
+                    let (held_tx, held_rx) = tokio::sync::mpsc::unbounded_channel();
+                    let chaos = chaos.clone();
+                    let mut rx = rx;
+                    tokio::spawn(async move {
+                        while let Some(p) = rx.next().await {
+                            chaos.hold_data().await;
+                            if held_tx.send(p).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    held_rx
+                };
+
                 // need to register the domain with the local channel coordinator.
                 // local first to ensure that we don't unnecessarily give away remote for a
                 // local thing if there's a race
@@ -322,26 +651,47 @@ async fn listen_df<'a>(
                 coord.insert_remote((idx, shard), addr);
 
                 tokio::task::block_in_place(|| {
-                    state_sizes.lock().unwrap().insert((idx, shard), state_size)
+                    state_sizes.lock().unwrap().insert((idx, shard), state_size);
+                    queues.lock().unwrap().insert((idx, shard), queued.clone());
                 });
 
-                let replica = replica::Replica::new(
-                    &valve,
-                    d,
-                    on,
-                    rx,
-                    ctrl_tx.clone(),
-                    log.clone(),
-                    coord.clone(),
-                );
-                let a = alive.clone();
-                tokio::spawn(async move {
-                    let _alive = a;
-                    let log = replica.log.clone();
-                    if let Err(e) = replica.await {
-                        crit!(log, "replica failure: {:?}", e);
+                let run = {
+                    let alive = alive.clone();
+                    let (valve, ctrl_tx, log, coord, runtime) = (
+                        valve.clone(),
+                        ctrl_tx.clone(),
+                        log.clone(),
+                        coord.clone(),
+                        runtime.clone(),
+                    );
+                    async move {
+                        let _alive = alive;
+                        let on = match tokio::net::TcpListener::from_std(on) {
+                            Ok(on) => on,
+                            Err(e) => {
+                                crit!(log, "could not listen for domain traffic: {:?}", e);
+                                return;
+                            }
+                        };
+                        let replica = replica::Replica::new(
+                            &valve, d, on, rx, ctrl_tx, log, coord, runtime, queued,
+                        );
+                        let log = replica.log.clone();
+                        if let Err(e) = replica.await {
+                            crit!(log, "replica failure: {:?}", e);
+                        }
                     }
-                });
+                };
+                if let Some(ref simulation) = simulation {
+                    simulation.spawn(run);
+                } else if let Some(ref mut topology) = topology {
+                    // the domain's state is allocated by the thread that runs it, and so ends up
+                    // on the same NUMA node as the core we pick here.
+                    let core = topology.core_for(idx);
+                    run_pinned(format!("domain{}.{}", idx.index(), shard), core, run)?;
+                } else {
+                    tokio::spawn(run);
+                }
 
                 info!(
                     log,
@@ -370,6 +720,105 @@ async fn listen_df<'a>(
     Ok(())
 }
 
+/// Run a domain on a runtime of its own, all of whose threads are pinned to the given core.
+///
+/// The runtime has a single worker thread, so the domain never moves between cores, and does not
+/// contend with other domains for the scheduler. Blocking calls the domain makes (for example, to
+/// RocksDB) happen on threads that are pinned to the same core.
+fn run_pinned(
+    name: String,
+    core: core_affinity::CoreId,
+    run: impl std::future::Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let mut rt = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .core_threads(1)
+        .enable_all()
+        .thread_name(name.clone())
+        .on_thread_start(move || core_affinity::set_for_current(core))
+        .build()?;
+    std::thread::Builder::new().name(name).spawn(move || {
+        core_affinity::set_for_current(core);
+        // the domain runs on the runtime's worker, not on this thread, so that it may block
+        let _ = rt.block_on(rt.spawn(run));
+    })?;
+    Ok(())
+}
+
+/// Measure the load on this worker, to be reported to the controller for domain placement.
+fn current_load(
+    state_sizes: &Mutex<HashMap<(DomainIndex, usize), Arc<AtomicUsize>>>,
+    queues: &Mutex<HashMap<(DomainIndex, usize), Arc<AtomicUsize>>>,
+    standby_lag: &AtomicUsize,
+    disk: &Path,
+) -> WorkerLoad {
+    let memory = state_sizes
+        .lock()
+        .unwrap()
+        .values()
+        .map(|s| s.load(Ordering::Acquire) as u64)
+        .sum();
+
+    // the load average is only available on linux. elsewhere, we just leave it at zero, and let
+    // placement be decided by memory use and domain counts alone.
+    let cpu = fs::read_to_string("/proc/loadavg")
+        .ok()
+        .and_then(|l| l.split_whitespace().next().and_then(|l| l.parse().ok()))
+        .unwrap_or(0.0);
+
+    // likewise for the resident memory of the process, which is given in kB.
+    let rss = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|s| {
+            s.lines()
+                .find(|l| l.starts_with("VmRSS:"))
+                .and_then(|l| l.split_whitespace().nth(1).and_then(|kb| kb.parse().ok()))
+        })
+        .map(|kb: u64| kb * 1024)
+        .unwrap_or(0);
+
+    let mut queues: Vec<_> = queues
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&(domain, shard), q)| DomainQueue {
+            domain,
+            shard,
+            queued: q.load(Ordering::Relaxed),
+        })
+        .collect();
+    queues.sort_by_key(|q| (q.domain, q.shard));
+
+    WorkerLoad {
+        cpu,
+        memory,
+        rss,
+        disk_free: disk_free(disk),
+        queues,
+        standby_lag: standby_lag.load(Ordering::Acquire) as u64,
+    }
+}
+
+// the widths of the statvfs fields differ between platforms
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn disk_free(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn disk_free(_: &Path) -> Option<u64> {
+    None
+}
+
 #[allow(clippy::type_complexity)]
 async fn do_eviction(
     log: &slog::Logger,
@@ -477,3 +926,129 @@ async fn do_eviction(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noria::consensus::{Authority, LocalAuthority};
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_runs_pinned_domains_on_their_core() {
+        let core = match core_affinity::get_core_ids().and_then(|cores| cores.last().cloned()) {
+            Some(core) => core,
+            None => return,
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        run_pinned(String::from("pinned-test"), core, async move {
+            let name = std::thread::current().name().map(String::from);
+            let cpu = unsafe { libc::sched_getcpu() };
+            let blocking_cpu = tokio::task::spawn_blocking(|| unsafe { libc::sched_getcpu() })
+                .await
+                .unwrap();
+            tx.send((name, cpu, blocking_cpu)).unwrap();
+        })
+        .unwrap();
+
+        let (name, cpu, blocking_cpu) = rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+        assert_eq!(name.as_deref(), Some("pinned-test"));
+        assert_eq!(cpu, core.id as i32);
+        assert_eq!(blocking_cpu, core.id as i32);
+    }
+
+    #[test]
+    fn it_defers_by_epoch() {
+        let mut d = Deferred::default();
+        d.defer(1, "a");
+        d.defer(2, "b");
+        d.defer(0, "c");
+        d.defer(1, "d");
+
+        assert_eq!(d.take(1), vec!["a", "d"]);
+        // the message from the older epoch is gone, the one from the newer epoch remains
+        assert_eq!(d.take(0), Vec::<&str>::new());
+        assert_eq!(d.take(2), vec!["b"]);
+        assert_eq!(d.take(2), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn it_fences_and_replays_messages_from_newer_controller() {
+        let authority = LocalAuthority::new();
+        let old = authority.become_leader(vec![]).unwrap().unwrap();
+        authority.surrender_leadership().unwrap();
+        let new = authority.become_leader(vec![]).unwrap().unwrap();
+
+        let log = slog::Logger::root(slog::Discard, o!());
+        let coord = ChannelCoordinator::new();
+        let runtime = Runtime::new(None, None);
+        let mut deferred = Deferred::default();
+        let active = |epoch| {
+            let (trigger, _) = Valve::new();
+            let (add_domain, _) = tokio::sync::mpsc::unbounded_channel();
+            InstanceState::Active {
+                epoch,
+                trigger,
+                add_domain,
+                assigned: HashSet::new(),
+            }
+        };
+        let booted = |epoch, domain: usize| CoordinationMessage {
+            source: "127.0.0.1:0".parse().unwrap(),
+            epoch,
+            payload: CoordinationPayload::DomainBooted(DomainDescriptor::new(
+                DomainIndex::from(domain),
+                0,
+                "127.0.0.1:1234".parse().unwrap(),
+            )),
+        };
+        let d0 = (DomainIndex::from(0), 0);
+        let d1 = (DomainIndex::from(1), 0);
+
+        // a message from the new controller makes us stop serving the old one
+        let mut state = active(old);
+        handle_message(
+            &mut state,
+            &mut deferred,
+            booted(new, 0),
+            &coord,
+            &runtime,
+            &log,
+        );
+        assert!(matches!(state, InstanceState::Pining));
+        assert!(!coord.has(&d0));
+
+        // until we are connected again, messages are held on to
+        handle_message(
+            &mut state,
+            &mut deferred,
+            booted(new, 1),
+            &coord,
+            &runtime,
+            &log,
+        );
+        assert!(!coord.has(&d1));
+
+        // and once we are connected to the new controller, they are handled in order
+        let mut state = active(new);
+        for msg in deferred.take(new) {
+            handle_message(&mut state, &mut deferred, msg, &coord, &runtime, &log);
+        }
+        assert!(coord.has(&d0));
+        assert!(coord.has(&d1));
+
+        // messages from the old controller no longer have any effect
+        let d2 = (DomainIndex::from(2), 0);
+        handle_message(
+            &mut state,
+            &mut deferred,
+            booted(old, 2),
+            &coord,
+            &runtime,
+            &log,
+        );
+        assert!(matches!(state, InstanceState::Active { .. }));
+        assert!(!coord.has(&d2));
+        assert!(deferred.take(new).is_empty());
+    }
+}
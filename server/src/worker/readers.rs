@@ -15,7 +15,11 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::mem;
 use std::time;
-use std::{future::Future, task::Poll};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Poll, Waker},
+};
 use stream_cancel::Valve;
 use tokio::task_local;
 use tokio_tower::multiplex::server;
@@ -92,15 +96,22 @@ pub(super) async fn listen(
                     // the loop will take care of looking for the next request
                     } else {
                         // we have a pending request, but it is still blocked
-                        // time for us to wait...
+                        // time for us to wait until either the retry timer fires, or a replay for
+                        // one of the keys we're waiting for completes, whichever comes first.
+                        let mut first = true;
                         futures_util::future::poll_fn(|cx| {
                             // we need the poll_fn so we can get the waker
+                            if !first {
+                                // we were woken up by one or the other
+                                return Poll::Ready(());
+                            }
+                            first = false;
                             retry.restart(RETRY_TIMEOUT, cx.waker());
-                            Poll::Ready(())
+                            blocking.notify_on_fill(cx.waker());
+                            // make sure the timer has registered our waker
+                            Pin::new(&mut retry).poll(cx).map(|_| ())
                         })
                         .await;
-                        // we need `(&mut )` here so that we can re-use it
-                        (&mut retry).await;
                     }
                 } else {
                     // no point in waiting for a timer if we've got nothing to wait for
@@ -255,9 +266,14 @@ fn handle_message(
                 Ok(reply) => Either::Left(Either::Left(future::ready(Ok(reply)))),
                 Err((keys, ret, pending)) => {
                     if !block {
+                        // tell the client which keys missed, so it can tell them apart from keys
+                        // that genuinely have no rows
                         Either::Left(Either::Left(future::ready(Ok(Tagged {
                             tag,
-                            v: ReadReply::Normal(Ok(ret)),
+                            v: ReadReply::Partial {
+                                rows: ret,
+                                misses: pending,
+                            },
                         }))))
                     } else {
                         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -305,6 +321,116 @@ fn handle_message(
                 v: ReadReply::Size(size),
             })))
         }
+        ReadQuery::Count { target, keys } => {
+            let counts = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                let mut counts = Vec::with_capacity(keys.len());
+                let mut missed = Vec::new();
+                for key in &keys {
+                    match reader.try_find_and(key, |rs| rs.len()) {
+                        Ok((Some(n), _)) => counts.push(Some(n)),
+                        Ok((None, _)) => {
+                            counts.push(None);
+                            missed.push(&key[..]);
+                        }
+                        Err(()) => return Err(()),
+                    }
+                }
+
+                // the client will come back for the keys that missed
+                if !missed.is_empty() {
+                    reader.trigger(missed.into_iter());
+                }
+                Ok(counts)
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Counts(counts),
+            })))
+        }
+        ReadQuery::Scan {
+            target,
+            after,
+            limit,
+        } => {
+            let chunk = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.scan_and(after.as_deref(), limit, |rows| {
+                    serialize(rows.collect::<Vec<_>>())
+                })
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Chunk(chunk),
+            })))
+        }
+        ReadQuery::Changes {
+            target,
+            since,
+            limit,
+        } => {
+            let changes = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                reader.changes(since, limit)
+            });
+
+            Either::Right(future::ready(Ok(Tagged {
+                tag,
+                v: ReadReply::Changes(changes),
+            })))
+        }
+        ReadQuery::Page {
+            target,
+            key,
+            order,
+            offset,
+            limit,
+        } => {
+            let page = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = readers_cache.entry(target).or_insert_with(|| {
+                    let readers = s.lock().unwrap();
+                    readers.get(&target).unwrap().clone()
+                });
+
+                let page = reader.try_find_and(&key, |rs| {
+                    let mut rows: Vec<_> = rs.iter().collect();
+                    rows.sort_by(|a, b| noria::cmp_rows(&order, a, b));
+                    serialize(rows.into_iter().skip(offset).take(limit))
+                });
+                match page {
+                    Ok((Some(rows), _)) => ReadReply::Normal(Ok(vec![rows])),
+                    Ok((None, _)) => {
+                        // the client will come back once the replay has filled the key
+                        reader.trigger(std::iter::once(&key[..]));
+                        ReadReply::Partial {
+                            rows: vec![SerializedReadReplyBatch::empty()],
+                            misses: vec![0],
+                        }
+                    }
+                    Err(()) => ReadReply::Normal(Err(())),
+                }
+            });
+
+            Either::Right(future::ready(Ok(Tagged { tag, v: page })))
+        }
     }
 }
 
@@ -341,6 +467,15 @@ impl std::fmt::Debug for BlockingRead {
 }
 
 impl BlockingRead {
+    /// Wake `waker` once a replay for one of the keys we are waiting for completes.
+    fn notify_on_fill(&self, waker: &Waker) {
+        READERS.with(|readers_cache| {
+            if let Some(reader) = readers_cache.borrow().get(&self.target) {
+                reader.notify_on_fill(self.keys.iter().map(Vec::as_slice), waker);
+            }
+        })
+    }
+
     fn check(&mut self) -> Poll<Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> {
         READERS.with(|readers_cache| {
             let mut readers_cache = readers_cache.borrow_mut();
@@ -534,6 +669,36 @@ mod readreply {
         ));
     }
 
+    #[test]
+    fn rtt_partial() {
+        let data = vec![vec![vec![DataType::from(1)]], vec![]];
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::<SerializedReadReplyBatch>::Partial {
+                    rows: data.iter().map(|d| super::serialize(d)).collect(),
+                    misses: vec![1],
+                },
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Partial { rows, misses },
+                tag: 32,
+            } => {
+                assert_eq!(misses, vec![1]);
+                assert_eq!(rows.len(), data.len());
+                for (got, expected) in rows.into_iter().zip(data.into_iter()) {
+                    assert_eq!(&*got, &expected);
+                }
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
     #[test]
     fn rtt_size() {
         let got: Tagged<ReadReply> = bincode::deserialize(
@@ -1,7 +1,22 @@
 /// Only allow processing this many inputs in a domain before we handle timer events, acks, etc.
 const FORCE_INPUT_YIELD_EVERY: usize = 32;
 
-use super::ChannelCoordinator;
+/// Size of the read buffer of connections from other domains.
+///
+/// Packets between domains are small and numerous, and peers flush everything they have queued
+/// for us at once, so a large buffer lets us pick up many packets with a single read.
+const DOMAIN_READ_BUFFER: usize = 2 * 1024 * 1024;
+
+/// Size of the read buffer of connections from clients writing to a base.
+///
+/// Clients also batch their writes, though not as aggressively as domains do.
+const BASE_READ_BUFFER: usize = 256 * 1024;
+
+/// Size of the write buffer of incoming connections. We only ever send acks back on these.
+const ACK_WRITE_BUFFER: usize = 4 * 1024;
+
+use super::throttle::{self, RateLimiter};
+use super::{ChannelCoordinator, Runtime};
 use crate::coordination::CoordinationPayload;
 use ahash::{AHashMap, AHashSet};
 use async_bincode::AsyncDestination;
@@ -17,7 +32,7 @@ use futures_util::{
     sink::Sink,
     stream::{futures_unordered::FuturesUnordered, Stream},
 };
-use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE};
+use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE, CONNECTION_FROM_DOMAIN_COMPRESSED};
 use noria::internal::DomainIndex;
 use noria::internal::LocalOrNot;
 use noria::{Input, Tagged};
@@ -25,6 +40,7 @@ use pin_project::pin_project;
 use slog;
 use std::collections::{HashMap, VecDeque};
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time;
 use std::{
@@ -61,6 +77,11 @@ pub(super) struct Replica {
 
     retry: Option<Box<Packet>>,
 
+    /// Packets held back by the domain's rate limit, in the order they arrived.
+    held: VecDeque<Box<Packet>>,
+    limiter: Option<RateLimiter>,
+    throttle_wake: Option<tokio::time::Delay>,
+
     #[pin]
     refresh_sizes: tokio::time::Interval,
 
@@ -97,6 +118,14 @@ pub(super) struct Replica {
     timeout: Strawpoll<async_timer::oneshot::Timer>,
     timed_out: bool,
 
+    /// How long sends to other domains may be held back while we still have input to process.
+    max_batch_delay: time::Duration,
+    /// Parameters of the worker that may override `max_batch_delay`.
+    runtime: Arc<Runtime>,
+
+    /// The number of packets held back or waiting to be sent, for the worker's heartbeats.
+    queued: Arc<AtomicUsize>,
+
     out: Outboxes,
 }
 
@@ -109,14 +138,21 @@ impl Replica {
         ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
         log: slog::Logger,
         cc: Arc<ChannelCoordinator>,
+        runtime: Arc<Runtime>,
+        queued: Arc<AtomicUsize>,
     ) -> Self {
         let id = domain.id();
         let id = format!("{}.{}", id.0.index(), id.1);
         domain.booted(on.local_addr().unwrap());
+        let max_batch_delay = domain.max_batch_delay();
+        let coalesced = domain.coalesced_updates();
         Replica {
             coord: cc,
             domain,
             retry: None,
+            held: VecDeque::new(),
+            limiter: None,
+            throttle_wake: None,
             valve: valve.clone(),
             incoming: Strawpoll::from(on),
             first_byte: FuturesUnordered::new(),
@@ -124,12 +160,15 @@ impl Replica {
             log: log.new(o! {"id" => id}),
             inputs: Default::default(),
             outputs: Default::default(),
-            out: Outboxes::new(ctrl_tx),
+            out: Outboxes::new(ctrl_tx, coalesced),
             timeout: Strawpoll::from(async_timer::oneshot::Timer::new(time::Duration::from_secs(
                 3600,
             ))),
             refresh_sizes: tokio::time::interval(time::Duration::from_millis(500)),
             timed_out: false,
+            max_batch_delay,
+            runtime,
+            queued,
         }
     }
 
@@ -261,6 +300,7 @@ impl Replica {
 
         let cc = this.coord;
         let outputs = this.outputs;
+        let log = this.log;
 
         // just like in try_acks:
         // first, queue up any additional writes we have to do
@@ -272,6 +312,12 @@ impl Replica {
 
             let &mut (ref mut tx, ref mut pending) = outputs.entry(ri).or_insert_with(|| {
                 while !cc.has(&ri) {}
+                // domains on this worker get packets handed to them directly, without going
+                // through serialization or the network stack.
+                debug!(log, "connecting to domain";
+                       "domain" => ri.0.index(),
+                       "shard" => ri.1,
+                       "local" => cc.is_local(&ri).unwrap_or(false));
                 let tx = cc.builder_for(&ri).unwrap().build_async().unwrap();
                 (tx, true)
             });
@@ -305,6 +351,9 @@ impl Replica {
         if !err.is_empty() {
             return Err(err.swap_remove(0).into());
         }
+        if this.out.domains.values().all(VecDeque::is_empty) {
+            this.out.oldest = None;
+        }
 
         // then, try to do any sends that are still pending
         for &mut (ref mut tx, ref mut pending) in outputs.values_mut() {
@@ -367,7 +416,8 @@ impl Replica {
             };
             let is_base = tag == CONNECTION_FROM_BASE;
 
-            debug!(this.log, "established new connection"; "base" => ?is_base);
+            debug!(this.log, "established new connection";
+                   "base" => ?is_base, "compressed" => tag == CONNECTION_FROM_DOMAIN_COMPRESSED);
             let slot = this.inputs.stream_entry();
             let token = slot.token();
             let epoch = if let Some(e) = this.out.connections.get_mut(token) {
@@ -390,7 +440,10 @@ impl Replica {
             }
             let tcp = if is_base {
                 DualTcpStream::upgrade(
-                    tokio::io::BufStream::new(stream),
+                    tokio::io::BufStream::from(BufReader::with_capacity(
+                        BASE_READ_BUFFER,
+                        BufWriter::with_capacity(ACK_WRITE_BUFFER, stream),
+                    )),
                     move |Tagged { v: input, tag }| {
                         Box::new(Packet::Input {
                             inner: input,
@@ -400,11 +453,15 @@ impl Replica {
                     },
                 )
             } else {
-                tokio::io::BufStream::from(BufReader::with_capacity(
-                    2 * 1024 * 1024,
-                    BufWriter::with_capacity(4 * 1024, stream),
-                ))
-                .into()
+                let stream = tokio::io::BufStream::from(BufReader::with_capacity(
+                    DOMAIN_READ_BUFFER,
+                    BufWriter::with_capacity(ACK_WRITE_BUFFER, stream),
+                ));
+                if tag == CONNECTION_FROM_DOMAIN_COMPRESSED {
+                    DualTcpStream::compressed(stream)
+                } else {
+                    stream.into()
+                }
             };
             slot.insert(tcp);
         }
@@ -453,6 +510,9 @@ struct Outboxes {
     // messages for other domains
     domains: AHashMap<ReplicaAddr, VecDeque<Box<Packet>>>,
 
+    // when the oldest message in `domains` was queued
+    oldest: Option<time::Instant>,
+
     // connection state for each stream
     connections: slab::Slab<ConnState>,
 
@@ -461,10 +521,16 @@ struct Outboxes {
 
     // for sending messages to the controller
     ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
+
+    // how many messages were merged into a message queued before them
+    coalesced: Arc<AtomicUsize>,
 }
 
 impl Outboxes {
-    fn new(ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>) -> Self {
+    fn new(
+        ctrl_tx: tokio::sync::mpsc::UnboundedSender<CoordinationPayload>,
+        coalesced: Arc<AtomicUsize>,
+    ) -> Self {
         let mut connections = slab::Slab::new();
 
         // index 0 is reserved
@@ -479,11 +545,24 @@ impl Outboxes {
             domains: Default::default(),
             connections,
             pending: Default::default(),
+            oldest: None,
             ctrl_tx,
+            coalesced,
             dirty: false,
         }
     }
 
+    /// Whether to hold off on sending the messages queued for other domains.
+    ///
+    /// While the domain has more input waiting, messages are held back until the oldest of them
+    /// has been queued for `max_batch_delay`, so that later updates can be merged into them.
+    fn hold_back(&self, busy: bool, max_batch_delay: time::Duration, now: time::Instant) -> bool {
+        busy && self
+            .oldest
+            .map(|t| now.saturating_duration_since(t) < max_batch_delay)
+            .unwrap_or(false)
+    }
+
     fn saw_input(&mut self, token: usize, epoch: usize) {
         let mut c = &mut self.connections[token];
         if c.epoch == epoch {
@@ -534,9 +613,35 @@ impl Executor for Outboxes {
             .expect("asked to send to controller, but controller has gone away");
     }
 
-    fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
+    fn send(&mut self, dest: ReplicaAddr, mut m: Box<Packet>) {
         self.dirty = true;
-        self.domains.entry(dest).or_default().push_back(m);
+        if self.oldest.is_none() {
+            self.oldest = Some(time::Instant::now());
+        }
+
+        // coalesce consecutive updates along the same edge, so that the more updates pile up
+        // between flushes, the fewer (and larger) packets we send.
+        let q = self.domains.entry(dest).or_default();
+        if let Some(last) = q.back_mut() {
+            if let (
+                Packet::Message {
+                    link: ref last_link,
+                    data: ref mut last_data,
+                },
+                Packet::Message {
+                    ref link,
+                    ref mut data,
+                },
+            ) = (&mut **last, &mut *m)
+            {
+                if last_link == link {
+                    last_data.append(data);
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+        q.push_back(m);
     }
 }
 
@@ -615,12 +720,42 @@ impl Future for Replica {
                     .on_event(out, PollEvent::Process(p),));
             }
 
+            // then, process as many of the packets held back by the rate limit as it now allows
+            let now = time::Instant::now();
+            if d.rate_limit() != this.limiter.as_ref().map(RateLimiter::rate) {
+                *this.limiter = d.rate_limit().map(|rate| RateLimiter::new(rate, now));
+            }
+            while let Some(p) = this.held.front() {
+                let n = p.num_updates();
+                if let Some(ref mut l) = *this.limiter {
+                    if !l.try_take(n, now) {
+                        break;
+                    }
+                }
+                let p = this.held.pop_front().unwrap();
+                process!(*this.retry, out, p, |p| d
+                    .on_event(out, PollEvent::Process(p),));
+            }
+            if this.held.len() >= throttle::MAX_HELD_BACK {
+                // don't read any more until we've caught up a little
+                local_done = true;
+                remote_done = true;
+            }
+            if d.poll_shipped(cx).is_pending() {
+                // nor until the standby has caught up; we'll be woken up once it has room
+                local_done = true;
+                remote_done = true;
+            }
+
             for _ in 0..FORCE_INPUT_YIELD_EVERY {
                 if !local_done && (check_local || remote_done) {
                     match this.locals.poll_recv(cx) {
                         Poll::Ready(Some(packet)) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
+                            let limiter = this.limiter.as_mut();
+                            if let Some(packet) = throttle::admit(this.held, limiter, packet, now) {
+                                process!(*this.retry, out, packet, |p| d
+                                    .on_event(out, PollEvent::Process(p),));
+                            }
                         }
                         Poll::Ready(None) => {
                             // local input stream finished
@@ -637,8 +772,11 @@ impl Future for Replica {
                 if !remote_done && (!check_local || local_done) {
                     match this.inputs.as_mut().poll_next(cx) {
                         Poll::Ready(Some((StreamYield::Item(Ok(packet)), _))) => {
-                            process!(*this.retry, out, packet, |p| d
-                                .on_event(out, PollEvent::Process(p),));
+                            let limiter = this.limiter.as_mut();
+                            if let Some(packet) = throttle::admit(this.held, limiter, packet, now) {
+                                process!(*this.retry, out, packet, |p| d
+                                    .on_event(out, PollEvent::Process(p),));
+                            }
                         }
                         Poll::Ready(Some((StreamYield::Finished(f), streami))) => {
                             if out.try_retire(streami) {
@@ -675,15 +813,56 @@ impl Future for Replica {
                     break;
                 }
 
+                if this.held.len() >= throttle::MAX_HELD_BACK {
+                    // we'll be woken up again once the rate limit lets more packets through
+                    local_done = true;
+                    remote_done = true;
+                    break;
+                }
+
+                if d.poll_shipped(cx).is_pending() {
+                    // we'll be woken up again once the shipper has room for more writes
+                    local_done = true;
+                    remote_done = true;
+                    break;
+                }
+
                 // alternate between input sources
                 check_local = !check_local;
             }
 
-            // send to downstream
+            d.set_queue_depth(this.held.len());
+            if this.held.is_empty() {
+                *this.throttle_wake = None;
+            } else if let Some(ref l) = *this.limiter {
+                // make sure we wake up again once the rate limit lets more packets through
+                let mut wake = tokio::time::delay_for(l.wait());
+                if Pin::new(&mut wake).poll(cx).is_ready() {
+                    cx.waker().wake_by_ref();
+                } else {
+                    *this.throttle_wake = Some(wake);
+                }
+            } else {
+                // the limit was lifted while packets were held back
+                cx.waker().wake_by_ref();
+            }
+
+            // send to downstream, unless we have more input waiting and may hold off a little
+            // longer to send more at once.
             // TODO: send fail == exiting?
-            self.as_mut()
-                .try_flush(cx)
-                .context("downstream flush (after)")?;
+            let busy = !local_done || !remote_done;
+            let max_batch_delay = self
+                .runtime
+                .max_batch_delay()
+                .unwrap_or(self.max_batch_delay);
+            let hold = self
+                .out
+                .hold_back(busy, max_batch_delay, time::Instant::now());
+            if !hold {
+                self.as_mut()
+                    .try_flush(cx)
+                    .context("downstream flush (after)")?;
+            }
 
             // send acks
             self.as_mut().try_acks(cx)?;
@@ -733,7 +912,67 @@ impl Future for Replica {
                 break;
             }
 
+            let queued =
+                self.held.len() + self.out.domains.values().map(VecDeque::len).sum::<usize>();
+            self.queued.store(queued, Ordering::Relaxed);
+
             break Poll::Pending;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dataflow::prelude::Link;
+    use noria::internal::LocalNodeIndex;
+
+    fn outboxes() -> Outboxes {
+        let (ctrl_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        Outboxes::new(ctrl_tx, Default::default())
+    }
+
+    #[test]
+    fn holds_back_updates_for_at_most_the_batch_delay() {
+        let mut out = outboxes();
+        let delay = time::Duration::from_millis(5);
+        let now = time::Instant::now();
+
+        // nothing is queued yet
+        assert!(!out.hold_back(true, delay, now));
+
+        out.oldest = Some(now);
+        assert!(out.hold_back(true, delay, now));
+        assert!(out.hold_back(true, delay, now + time::Duration::from_millis(4)));
+        assert!(!out.hold_back(true, delay, now + delay));
+
+        // a domain with nothing else to do, or without a delay, sends right away
+        assert!(!out.hold_back(false, delay, now));
+        assert!(!out.hold_back(true, time::Duration::from_millis(0), now));
+    }
+
+    #[test]
+    fn coalesces_updates_along_the_same_edge() {
+        let mut out = outboxes();
+        let link =
+            |src, dst| unsafe { Link::new(LocalNodeIndex::make(src), LocalNodeIndex::make(dst)) };
+        let message = |link, x: i32| {
+            Box::new(Packet::Message {
+                link,
+                data: vec![vec![DataType::from(x)]].into_iter().collect(),
+            })
+        };
+        let dest = (0.into(), 0);
+
+        out.send(dest, message(link(0, 1), 1));
+        out.send(dest, message(link(0, 1), 2));
+        out.send(dest, message(link(0, 2), 3));
+        out.send(dest, message(link(0, 2), 4));
+
+        let q = &out.domains[&dest];
+        assert_eq!(q.len(), 2);
+        assert_eq!(q[0].num_updates(), 2);
+        assert_eq!(q[1].num_updates(), 2);
+        assert_eq!(out.coalesced.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,147 @@
+use dataflow::payload::ShippedWrites;
+use futures_util::{
+    future::BoxFuture,
+    stream::{Stream, StreamExt},
+};
+use noria::consensus::Authority;
+use noria::{ControllerHandle, Table, TableOperation};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time;
+use tokio::time::Instant;
+
+/// Finds the table with a given name in the standby deployment that writes are shipped to.
+pub(crate) type Standby =
+    Arc<dyn Fn(String) -> BoxFuture<'static, Result<Table, failure::Error>> + Send + Sync>;
+
+/// Make a `Standby` that finds tables through the controller of the deployment at `authority`.
+pub(crate) fn standby<A: Authority + 'static>(authority: Arc<A>) -> Standby {
+    Arc::new(move |table: String| -> BoxFuture<'static, _> {
+        let authority = Arc::clone(&authority);
+        Box::pin(async move {
+            let mut ch = ControllerHandle::make(authority).await?;
+            ch.table(&table).await
+        })
+    })
+}
+
+/// How many rows the shipper holds on to for tables that the standby has not yet accepted them for
+/// before it stops taking in new writes. Once it stops, the channel from the domains fills up,
+/// and the domains wait for room in it before they apply any more writes.
+const MAX_BACKLOG: usize = 100_000;
+
+/// How long the shipper first waits before it tries again to apply writes the standby rejected.
+/// The wait doubles with every failed attempt, up to `MAX_RETRY`.
+const MIN_RETRY: time::Duration = time::Duration::from_millis(100);
+const MAX_RETRY: time::Duration = time::Duration::from_secs(5);
+
+/// The writes to one table that have yet to be applied to the standby, oldest first.
+struct Backlog {
+    ops: VecDeque<Vec<TableOperation>>,
+    retry_at: Instant,
+    backoff: time::Duration,
+}
+
+/// Apply the writes shipped by the domains of this worker to the standby, in the order they were
+/// shipped.
+///
+/// Writes that the standby does not accept are kept, and retried with exponential backoff, so
+/// the standby only misses writes that were still held here when this worker shut down. Writes
+/// to each table are applied in order, but a table whose writes fail does not hold up the others
+/// until `MAX_BACKLOG` rows are waiting. `lag` is kept at the number of rows that have been
+/// received but not yet applied to the standby.
+pub(super) async fn ship(
+    standby: Standby,
+    mut writes: impl Stream<Item = ShippedWrites> + Unpin,
+    lag: Arc<AtomicUsize>,
+    log: slog::Logger,
+) {
+    let mut tables: HashMap<String, Table> = HashMap::new();
+    let mut backlogs: HashMap<String, Backlog> = HashMap::new();
+    let mut open = true;
+    loop {
+        let now = Instant::now();
+        for (table, backlog) in backlogs.iter_mut().filter(|(_, b)| b.retry_at <= now) {
+            while let Some(ops) = backlog.ops.front() {
+                let n = ops.len();
+                match apply(&standby, &mut tables, table, ops.clone()).await {
+                    Ok(()) => {
+                        backlog.ops.pop_front();
+                        backlog.backoff = MIN_RETRY;
+                        lag.fetch_sub(n, Ordering::AcqRel);
+                    }
+                    Err(e) => {
+                        warn!(log, "failed to ship writes to standby; will retry";
+                              "table" => table, "error" => ?e, "in" => ?backlog.backoff);
+                        backlog.retry_at = Instant::now() + backlog.backoff;
+                        backlog.backoff = cmp::min(backlog.backoff * 2, MAX_RETRY);
+                        break;
+                    }
+                }
+            }
+        }
+        backlogs.retain(|_, b| !b.ops.is_empty());
+
+        if !open {
+            if !backlogs.is_empty() {
+                error!(log, "shutting down with writes that were not shipped to standby";
+                       "rows" => lag.load(Ordering::Acquire));
+            }
+            return;
+        }
+
+        let retry_at = backlogs.values().map(|b| b.retry_at).min();
+        let next = if lag.load(Ordering::Acquire) < MAX_BACKLOG {
+            match retry_at {
+                Some(at) => tokio::select! {
+                    w = writes.next() => Some(w),
+                    _ = tokio::time::delay_until(at) => None,
+                },
+                None => Some(writes.next().await),
+            }
+        } else {
+            // the backlog is only full if there is something to retry
+            tokio::time::delay_until(retry_at.expect("full backlog is empty")).await;
+            None
+        };
+
+        match next {
+            Some(Some(ShippedWrites { table, ops })) => {
+                lag.fetch_add(ops.len(), Ordering::AcqRel);
+                backlogs
+                    .entry(table)
+                    .or_insert_with(|| Backlog {
+                        ops: VecDeque::new(),
+                        retry_at: Instant::now(),
+                        backoff: MIN_RETRY,
+                    })
+                    .ops
+                    .push_back(ops);
+            }
+            Some(None) => open = false,
+            None => {}
+        }
+    }
+}
+
+/// Apply writes to a table in the standby, finding the table first if we have not done so yet.
+async fn apply(
+    standby: &Standby,
+    tables: &mut HashMap<String, Table>,
+    table: &str,
+    ops: Vec<TableOperation>,
+) -> Result<(), failure::Error> {
+    if !tables.contains_key(table) {
+        let t = standby(table.to_owned()).await?;
+        tables.insert(table.to_owned(), t);
+    }
+
+    let res = tables.get_mut(table).unwrap().perform_all(ops).await;
+    if res.is_err() {
+        // the table may have moved since we found it, so we look it up again on the next attempt
+        tables.remove(table);
+    }
+    Ok(res?)
+}
@@ -0,0 +1,153 @@
+//! Deterministic scheduling of the domains of a worker.
+//!
+//! In a simulation, all the domains of a worker are driven by a single task on a thread of their
+//! own, so no two domains ever run at the same time. Whenever several domains have work to do,
+//! the one that goes next is picked by a random number generator seeded with the simulation's
+//! seed. Running the same requests with the same seed therefore has the domains process the
+//! messages they exchange in the same order, while different seeds try different interleavings.
+//! Clients, timers and other workers are not simulated, so an interleaving only repeats if they
+//! behave the same way too.
+
+use futures_util::task::{waker, ArcWake, AtomicWaker};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// How many times the scheduler polls a domain before it yields to the runtime, so that the
+/// runtime gets to notice I/O and expired timers.
+const STEPS_PER_YIELD: usize = 64;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Hands domains to the thread that runs the simulation.
+pub(super) struct Simulation {
+    tasks: UnboundedSender<Task>,
+}
+
+impl Simulation {
+    /// Start the thread that runs the domains of a simulation with the given seed.
+    pub(super) fn start(seed: u64) -> io::Result<Self> {
+        let mut rt = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .enable_all()
+            .thread_name("simulation")
+            .build()?;
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let scheduler = Scheduler {
+            new: Some(rx),
+            tasks: Vec::new(),
+            woken: Default::default(),
+            rng: StdRng::seed_from_u64(seed),
+        };
+        std::thread::Builder::new()
+            .name("simulation".to_owned())
+            .spawn(move || {
+                // the domains run on the runtime's worker, not on this thread, so that they may
+                // block
+                let _ = rt.block_on(rt.spawn(scheduler));
+            })?;
+        Ok(Simulation { tasks: tx })
+    }
+
+    /// Run a domain as part of the simulation.
+    pub(super) fn spawn(&self, domain: impl Future<Output = ()> + Send + 'static) {
+        // the scheduler only stops once the worker has gone away
+        let _ = self.tasks.send(Box::pin(domain));
+    }
+}
+
+/// The domains that have been woken up since they were last polled.
+#[derive(Default)]
+struct Woken {
+    tasks: Mutex<BTreeSet<usize>>,
+    scheduler: AtomicWaker,
+}
+
+struct TaskWaker {
+    task: usize,
+    woken: Arc<Woken>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.tasks.lock().unwrap().insert(arc_self.task);
+        arc_self.woken.scheduler.wake();
+    }
+}
+
+struct Scheduler {
+    new: Option<UnboundedReceiver<Task>>,
+    tasks: Vec<Option<Task>>,
+    woken: Arc<Woken>,
+    rng: StdRng,
+}
+
+impl Scheduler {
+    /// Pick the next domain to poll among those that have been woken up.
+    fn next(&mut self) -> Option<usize> {
+        let mut woken = self.woken.tasks.lock().unwrap();
+        if woken.is_empty() {
+            return None;
+        }
+        let task = *woken
+            .iter()
+            .nth(self.rng.gen_range(0, woken.len()))
+            .unwrap();
+        woken.remove(&task);
+        Some(task)
+    }
+}
+
+impl Future for Scheduler {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.woken.scheduler.register(cx.waker());
+
+        // domains are numbered in the order the worker was given them
+        while let Some(ref mut new) = this.new {
+            match new.poll_recv(cx) {
+                Poll::Ready(Some(task)) => {
+                    this.woken.tasks.lock().unwrap().insert(this.tasks.len());
+                    this.tasks.push(Some(task));
+                }
+                Poll::Ready(None) => this.new = None,
+                Poll::Pending => break,
+            }
+        }
+
+        for _ in 0..STEPS_PER_YIELD {
+            let task = match this.next() {
+                Some(task) => task,
+                None if this.new.is_none() && this.tasks.iter().all(Option::is_none) => {
+                    return Poll::Ready(());
+                }
+                None => return Poll::Pending,
+            };
+            if let Some(ref mut domain) = this.tasks[task] {
+                let waker = waker(Arc::new(TaskWaker {
+                    task,
+                    woken: Arc::clone(&this.woken),
+                }));
+                if domain
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    this.tasks[task] = None;
+                }
+            }
+        }
+
+        // there is more to do, but we let the runtime catch up first
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -0,0 +1,125 @@
+use dataflow::Packet;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The most packets a rate limited domain holds back before it stops reading new ones.
+///
+/// Once a domain stops reading, its senders are eventually blocked too, so a domain that cannot
+/// keep up with its limit pushes back on its inputs instead of buffering without bound.
+pub(super) const MAX_HELD_BACK: usize = 1024;
+
+/// A token bucket that caps how many updates a domain processes per second.
+///
+/// Up to a tenth of a second's worth of updates may be processed in a burst. A packet is let
+/// through as long as there are any tokens left, even if it carries more updates than that; the
+/// packets after it then have to wait until the bucket has been refilled.
+#[derive(Debug)]
+pub(super) struct RateLimiter {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub(super) fn new(rate: u64, now: Instant) -> Self {
+        assert_ne!(rate, 0);
+        RateLimiter {
+            rate,
+            tokens: Self::burst(rate),
+            last: now,
+        }
+    }
+
+    fn burst(rate: u64) -> f64 {
+        (rate as f64 / 10.0).max(1.0)
+    }
+
+    /// The number of updates per second this limiter lets through.
+    pub(super) fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = now;
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(Self::burst(self.rate));
+    }
+
+    /// Take tokens for `n` updates, if they may be processed now.
+    ///
+    /// Packets that carry no updates are never held back by the limiter itself.
+    pub(super) fn try_take(&mut self, n: usize, now: Instant) -> bool {
+        if n == 0 {
+            return true;
+        }
+        self.refill(now);
+        if self.tokens <= 0.0 {
+            return false;
+        }
+        self.tokens -= n as f64;
+        true
+    }
+
+    /// How long until there are tokens to take again.
+    pub(super) fn wait(&self) -> Duration {
+        if self.tokens > 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate as f64)
+        }
+    }
+}
+
+/// Decide whether a packet that just arrived may be processed right away.
+///
+/// Packets have to wait behind any packet that is already held back, so that a domain still sees
+/// its packets in the order they arrived. The exception is statistics requests, which should be
+/// answered even while the domain is throttled. Returns the packet if it may be processed now.
+pub(super) fn admit(
+    held: &mut VecDeque<Box<Packet>>,
+    limiter: Option<&mut RateLimiter>,
+    packet: Box<Packet>,
+    now: Instant,
+) -> Option<Box<Packet>> {
+    if let Packet::GetStatistics = *packet {
+        return Some(packet);
+    }
+    if held.is_empty() {
+        match limiter {
+            None => return Some(packet),
+            Some(l) if l.try_take(packet.num_updates(), now) => return Some(packet),
+            Some(_) => {}
+        }
+    }
+    held.push_back(packet);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_limits_updates_per_second() {
+        let start = Instant::now();
+        let mut l = RateLimiter::new(100, start);
+        // a tenth of a second's worth of updates may go through at once
+        assert!(l.try_take(6, start));
+        assert!(l.try_take(6, start));
+        assert!(!l.try_take(1, start));
+        assert!(l.try_take(0, start));
+        assert!(l.wait() > Duration::from_millis(29));
+        assert!(l.wait() <= Duration::from_millis(30));
+
+        // after the wait, the bucket has tokens again
+        let later = start + l.wait();
+        assert!(l.try_take(1, later));
+        assert!(!l.try_take(1, later));
+
+        // and it never holds more than the burst
+        let much_later = later + Duration::from_secs(10);
+        assert!(l.try_take(10, much_later));
+        assert!(!l.try_take(1, much_later));
+    }
+}
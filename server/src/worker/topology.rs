@@ -0,0 +1,155 @@
+use core_affinity::CoreId;
+use noria::internal::DomainIndex;
+use std::fs;
+
+/// The cores of this machine, grouped by the NUMA node they belong to.
+///
+/// Domains that are pinned to a core allocate their state from that core's node, since pages are
+/// placed on the node of the thread that first touches them. Picking cores with the topology in
+/// mind therefore also decides where each domain's state lives.
+#[derive(Debug)]
+pub(super) struct Topology {
+    /// The cores of each node, and the index of the next core to hand out on that node.
+    nodes: Vec<(Vec<CoreId>, usize)>,
+}
+
+impl Topology {
+    /// Find out which cores belong to which NUMA node.
+    ///
+    /// If the topology cannot be read (e.g., because this is not Linux), all the cores are
+    /// assumed to belong to a single node.
+    pub(super) fn detect(cores: Vec<CoreId>) -> Self {
+        let mut found: Vec<(usize, Vec<usize>)> = Vec::new();
+        if let Ok(entries) = fs::read_dir("/sys/devices/system/node") {
+            found = entries
+                .filter_map(Result::ok)
+                .filter_map(|e| {
+                    let name = e.file_name().into_string().ok()?;
+                    if !name.starts_with("node") {
+                        return None;
+                    }
+                    let node = name["node".len()..].parse().ok()?;
+                    let cpus = fs::read_to_string(e.path().join("cpulist")).ok()?;
+                    Some((node, parse_cpulist(&cpus)))
+                })
+                .collect();
+            found.sort();
+        }
+
+        Topology {
+            nodes: group(cores, found.into_iter().map(|(_, cpus)| cpus))
+                .into_iter()
+                .map(|cs| (cs, 0))
+                .collect(),
+        }
+    }
+
+    /// The number of NUMA nodes.
+    pub(super) fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Pick the core that a shard of the given domain should be pinned to.
+    ///
+    /// All the shards of a domain are kept on the same node, and domains are spread across the
+    /// nodes by their index. Within a node, cores are handed out round-robin.
+    pub(super) fn core_for(&mut self, domain: DomainIndex) -> CoreId {
+        let n = self.nodes.len();
+        let (ref cores, ref mut next) = self.nodes[domain.index() % n];
+        let core = cores[*next % cores.len()];
+        *next += 1;
+        core
+    }
+}
+
+/// Group `cores` by the node whose CPU list includes them.
+///
+/// Nodes none of whose CPUs are in `cores` are left out, and any cores that are on none of the
+/// nodes go on a node of their own.
+fn group(cores: Vec<CoreId>, nodes: impl IntoIterator<Item = Vec<usize>>) -> Vec<Vec<CoreId>> {
+    let mut grouped: Vec<Vec<CoreId>> = nodes
+        .into_iter()
+        .map(|cpus| {
+            cores
+                .iter()
+                .filter(|c| cpus.contains(&c.id))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .filter(|cs| !cs.is_empty())
+        .collect();
+
+    let unplaced: Vec<_> = cores
+        .into_iter()
+        .filter(|c| !grouped.iter().any(|cs| cs.iter().any(|p| p.id == c.id)))
+        .collect();
+    if !unplaced.is_empty() {
+        grouped.push(unplaced);
+    }
+    grouped
+}
+
+/// Parse a Linux CPU list, such as `0-3,8,10-11`.
+fn parse_cpulist(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let mut ends = range.splitn(2, '-').map(|c| c.trim().parse::<usize>());
+        match (ends.next(), ends.next()) {
+            (Some(Ok(lo)), Some(Ok(hi))) => cpus.extend(lo..=hi),
+            (Some(Ok(cpu)), None) => cpus.push(cpu),
+            _ => {}
+        }
+    }
+    cpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_cpulists() {
+        assert_eq!(parse_cpulist("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpulist("5"), vec![5]);
+        assert_eq!(parse_cpulist("\n"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn it_groups_cores_by_node() {
+        let core = |id| CoreId { id };
+        let ids = |nodes: Vec<Vec<CoreId>>| {
+            nodes
+                .into_iter()
+                .map(|cs| cs.into_iter().map(|c| c.id).collect())
+                .collect::<Vec<Vec<_>>>()
+        };
+        let cores = || vec![core(0), core(1), core(2), core(3)];
+
+        assert_eq!(
+            ids(group(cores(), vec![vec![0, 1], vec![2, 3], vec![4, 5]])),
+            vec![vec![0, 1], vec![2, 3]]
+        );
+        // cores on no node we know of go on a node of their own
+        assert_eq!(
+            ids(group(cores(), vec![vec![0, 1]])),
+            vec![vec![0, 1], vec![2, 3]]
+        );
+        // without a topology, all cores are on one node
+        assert_eq!(ids(group(cores(), vec![])), vec![vec![0, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn it_keeps_domain_shards_on_one_node() {
+        let core = |id| CoreId { id };
+        let mut t = Topology {
+            nodes: vec![(vec![core(0), core(1)], 0), (vec![core(2), core(3)], 0)],
+        };
+        assert_eq!(t.nodes(), 2);
+        let d0 = DomainIndex::from(0);
+        let d1 = DomainIndex::from(1);
+        assert_eq!(t.core_for(d0).id, 0);
+        assert_eq!(t.core_for(d0).id, 1);
+        assert_eq!(t.core_for(d1).id, 2);
+        assert_eq!(t.core_for(d0).id, 0);
+    }
+}
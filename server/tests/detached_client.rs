@@ -0,0 +1,65 @@
+//! Tests for the detached client handles, the shim that must let the Tokio 0.2 client be awaited
+//! from executors other than the runtime that Noria itself runs on.
+
+use noria_server::detached::DetachedControllerHandle;
+use noria_server::{Builder, LocalAuthority};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn detached_client_works_on_tokio_1() {
+    // the server runs on its own runtime, just like it would in a separate process
+    let mut rt = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    let authority = Arc::new(LocalAuthority::new());
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    let (g, _) = rt.block_on(builder.start(authority.clone())).unwrap();
+
+    let mut db = DetachedControllerHandle::make(authority).unwrap();
+    db.set_timeout(Some(Duration::from_secs(10)));
+    let client = tokio1::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    client.block_on(async {
+        db.install_recipe(
+            "CREATE TABLE Article (aid int, title varchar(255), PRIMARY KEY(aid));
+             QUERY ArticleById: SELECT aid, title FROM Article WHERE aid = ?;",
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.inputs().await.unwrap().len(), 1);
+
+        let mut article = db.table("Article").await.unwrap();
+        let mut by_id = db.view("ArticleById").await.unwrap();
+        article
+            .insert(vec![1.into(), "I love Soup".into()])
+            .await
+            .unwrap();
+        tokio1::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            by_id.lookup(&[1.into()], true).await.unwrap(),
+            vec![vec![1.into(), "I love Soup".into()]]
+        );
+
+        // handles can be moved to tasks of the other runtime
+        let mut by_id2 = by_id.clone();
+        tokio1::spawn(async move {
+            assert_eq!(by_id2.lookup(&[1.into()], true).await.unwrap().len(), 1);
+        })
+        .await
+        .unwrap();
+
+        article.delete(vec![1.into()]).await.unwrap();
+        tokio1::time::sleep(Duration::from_millis(200)).await;
+        assert!(by_id.lookup(&[1.into()], true).await.unwrap().is_empty());
+    });
+
+    drop(client);
+    drop(db);
+    rt.block_on(async move { drop(g) });
+}
@@ -0,0 +1,388 @@
+//! Randomized consistency testing of Noria against SQLite.
+//!
+//! Each case generates a random schema, a random set of queries over it, and a random sequence of
+//! inserts, updates and deletes. The writes are applied both to Noria and to an in-memory SQLite
+//! database, and every query must eventually return for each key what SQLite returns for the
+//! same statement. Failing cases print the seed that produced them; set `ORACLE_SEED` to re-run
+//! just that case, and `ORACLE_CASES` to run more.
+//!
+//! Where Noria deliberately answers differently from SQL, the difference is listed in
+//! `KNOWN_DIFFERENCES` rather than modelled, and results that only differ in such a way are
+//! counted and reported instead of failing the case.
+
+use noria_server::{Builder, DataType, Modification};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::time::{Duration, Instant};
+
+/// Every column other than the primary key holds a value below `KEYS`, so that joins match and
+/// each key has several rows.
+const KEYS: i64 = 5;
+
+type Row = Vec<Option<i64>>;
+
+struct Table {
+    name: String,
+    /// The columns after the primary key, which is always called `<name>_id`.
+    columns: Vec<String>,
+}
+
+impl Table {
+    fn id(&self) -> String {
+        format!("{}_id", self.name)
+    }
+
+    fn create(&self) -> String {
+        let columns: Vec<_> = self.columns.iter().map(|c| format!("{} int", c)).collect();
+        format!(
+            "CREATE TABLE {} ({} int, {}, PRIMARY KEY({}));",
+            self.name,
+            self.id(),
+            columns.join(", "),
+            self.id()
+        )
+    }
+}
+
+fn generate_schema(rng: &mut StdRng) -> Vec<Table> {
+    (0..rng.gen_range(2, 4))
+        .map(|t| {
+            let name = format!("t{}", t);
+            let columns = (0..rng.gen_range(2, 4))
+                .map(|c| format!("{}_c{}", name, c))
+                .collect();
+            Table { name, columns }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shape {
+    Filter,
+    Join,
+    LeftJoin,
+    Count,
+    Sum,
+    JoinCount,
+}
+
+/// A parameterized query whose first column is the key it is looked up by.
+#[derive(Debug)]
+struct Query {
+    name: String,
+    shape: Shape,
+    sql: String,
+}
+
+impl Query {
+    fn aggregates(&self) -> bool {
+        match self.shape {
+            Shape::Count | Shape::Sum | Shape::JoinCount => true,
+            _ => false,
+        }
+    }
+}
+
+fn generate_query(rng: &mut StdRng, i: usize, schema: &[Table]) -> Query {
+    let shapes = [
+        Shape::Filter,
+        Shape::Join,
+        Shape::LeftJoin,
+        Shape::Count,
+        Shape::Sum,
+        Shape::JoinCount,
+    ];
+    let shape = *shapes.choose(rng).unwrap();
+    let mut tables: Vec<&Table> = schema.iter().collect();
+    tables.shuffle(rng);
+    let (l, r) = (tables[0], tables[1]);
+    // two different columns of each table, so that no column is selected twice
+    let columns = |rng: &mut StdRng, t: &Table| {
+        let cs: Vec<_> = t.columns.choose_multiple(rng, 2).cloned().collect();
+        (cs[0].clone(), cs[1].clone())
+    };
+    let (k, x) = columns(rng, l);
+    let (j, y) = columns(rng, r);
+    let (lt, rt, lid, rid) = (&l.name, &r.name, l.id(), r.id());
+
+    let sql = match shape {
+        Shape::Filter => format!(
+            "SELECT {k}, {lid}, {x} FROM {lt} WHERE {k} = ? AND {x} > {c}",
+            k = k,
+            lid = lid,
+            x = x,
+            lt = lt,
+            c = rng.gen_range(0, KEYS),
+        ),
+        Shape::Join | Shape::LeftJoin => format!(
+            "SELECT {lt}.{k}, {lt}.{lid}, {rt}.{rid}, {rt}.{y} \
+             FROM {lt} {join} {rt} ON ({lt}.{k} = {rt}.{j}) WHERE {lt}.{k} = ?",
+            k = k,
+            lid = lid,
+            rid = rid,
+            y = y,
+            j = j,
+            lt = lt,
+            rt = rt,
+            join = if shape == Shape::Join {
+                "JOIN"
+            } else {
+                "LEFT JOIN"
+            },
+        ),
+        Shape::Count => format!(
+            "SELECT {k}, COUNT({lid}) AS n FROM {lt} WHERE {k} = ? GROUP BY {k}",
+            k = k,
+            lid = lid,
+            lt = lt,
+        ),
+        Shape::Sum => format!(
+            "SELECT {k}, SUM({x}) AS s FROM {lt} WHERE {k} = ? GROUP BY {k}",
+            k = k,
+            x = x,
+            lt = lt,
+        ),
+        Shape::JoinCount => format!(
+            "SELECT {lt}.{k}, COUNT({rt}.{rid}) AS n \
+             FROM {lt} JOIN {rt} ON ({lt}.{k} = {rt}.{j}) WHERE {lt}.{k} = ? GROUP BY {lt}.{k}",
+            k = k,
+            rid = rid,
+            j = j,
+            lt = lt,
+            rt = rt,
+        ),
+    };
+    Query {
+        name: format!("q{}", i),
+        shape,
+        sql,
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Insert(usize, Vec<i64>),
+    /// Set the given column (counting the primary key) of the row with the given id.
+    Update(usize, i64, usize, i64),
+    Delete(usize, i64),
+}
+
+fn generate_writes(rng: &mut StdRng, schema: &[Table]) -> Vec<Op> {
+    let mut ops = Vec::new();
+    let mut live: Vec<(usize, i64)> = Vec::new();
+    for id in 0..rng.gen_range(10, 80) {
+        let existing = if live.is_empty() {
+            None
+        } else {
+            Some(rng.gen_range(0, live.len()))
+        };
+        match (existing, rng.gen_range(0, 10)) {
+            (Some(i), 0..=1) => {
+                let (t, id) = live.swap_remove(i);
+                ops.push(Op::Delete(t, id));
+            }
+            (Some(i), 2..=3) => {
+                let (t, id) = live[i];
+                let column = rng.gen_range(1, schema[t].columns.len() + 1);
+                ops.push(Op::Update(t, id, column, rng.gen_range(0, KEYS)));
+            }
+            _ => {
+                let t = rng.gen_range(0, schema.len());
+                let mut row = vec![id];
+                row.extend((0..schema[t].columns.len()).map(|_| rng.gen_range(0, KEYS)));
+                live.push((t, id));
+                ops.push(Op::Insert(t, row));
+            }
+        }
+    }
+    ops
+}
+
+fn sqlite_write(db: &Connection, schema: &[Table], op: &Op) -> rusqlite::Result<usize> {
+    match *op {
+        Op::Insert(t, ref row) => {
+            let marks = vec!["?"; row.len()].join(", ");
+            db.execute(
+                &format!("INSERT INTO {} VALUES ({})", schema[t].name, marks),
+                row,
+            )
+        }
+        Op::Update(t, id, column, value) => db.execute(
+            &format!(
+                "UPDATE {} SET {} = ? WHERE {} = ?",
+                schema[t].name,
+                schema[t].columns[column - 1],
+                schema[t].id()
+            ),
+            params![value, id],
+        ),
+        Op::Delete(t, id) => db.execute(
+            &format!(
+                "DELETE FROM {} WHERE {} = ?",
+                schema[t].name,
+                schema[t].id()
+            ),
+            params![id],
+        ),
+    }
+}
+
+fn sqlite_read(db: &Connection, query: &Query, key: i64) -> rusqlite::Result<Vec<Row>> {
+    let mut stmt = db.prepare(&query.sql)?;
+    let ncols = stmt.column_count();
+    let rows = stmt.query_map(params![key], |row| {
+        (0..ncols)
+            .map(|i| row.get::<_, Option<i64>>(i))
+            .collect::<rusqlite::Result<Row>>()
+    })?;
+    let mut rows = rows.collect::<rusqlite::Result<Vec<Row>>>()?;
+    rows.sort();
+    Ok(rows)
+}
+
+/// A way in which Noria knowingly answers a query differently from SQL.
+struct KnownDifference {
+    name: &'static str,
+    /// Whether Noria returning `noria` where SQL returns `sql` is down to this difference.
+    applies: fn(&Query, &[Row], &[Row]) -> bool,
+}
+
+const KNOWN_DIFFERENCES: &[KnownDifference] = &[KnownDifference {
+    name: "empty aggregation groups",
+    applies: empty_group,
+}];
+
+/// Once Noria has computed an aggregation group, it keeps it around with a count or sum of zero
+/// after its last row is deleted, while SQL no longer returns the group at all.
+fn empty_group(query: &Query, noria: &[Row], sql: &[Row]) -> bool {
+    query.aggregates() && sql.is_empty() && noria.len() == 1 && noria[0][1] == Some(0)
+}
+
+fn normalize(rows: Vec<Vec<DataType>>) -> Vec<Row> {
+    let mut rows: Vec<Row> = rows
+        .into_iter()
+        .map(|row| {
+            row.iter()
+                .map(|v| match *v {
+                    DataType::None => None,
+                    ref v => Some(i64::from(v)),
+                })
+                .collect()
+        })
+        .collect();
+    rows.sort();
+    rows
+}
+
+async fn run_case(seed: u64, excluded: &mut BTreeMap<&'static str, usize>) -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let schema = generate_schema(&mut rng);
+    let queries: Vec<_> = (0..rng.gen_range(3, 8))
+        .map(|i| generate_query(&mut rng, i, &schema))
+        .collect();
+    let ops = generate_writes(&mut rng, &schema);
+
+    let db = Connection::open_in_memory().unwrap();
+    let mut recipe = String::new();
+    for table in &schema {
+        db.execute(&table.create(), rusqlite::NO_PARAMS)
+            .map_err(|e| format!("SQLite rejected {}: {}", table.create(), e))?;
+        recipe.push_str(&table.create());
+        recipe.push('\n');
+    }
+    for query in &queries {
+        recipe.push_str(&format!("QUERY {}: {};\n", query.name, query.sql));
+    }
+
+    let mut builder = Builder::default();
+    builder.set_sharding(if rng.gen() { Some(2) } else { None });
+    let (mut g, _) = builder.start_local().await.unwrap();
+    g.install_recipe(&recipe)
+        .await
+        .map_err(|e| format!("failed to install recipe {}: {:?}", recipe, e))?;
+    let mut tables = HashMap::new();
+    for table in &schema {
+        tables.insert(table.name.clone(), g.table(&table.name).await.unwrap());
+    }
+
+    // check halfway through as well, so that later writes hit partially materialized state
+    let (first, second) = ops.split_at(ops.len() / 2);
+    for ops in &[first, second] {
+        for op in ops.iter() {
+            sqlite_write(&db, &schema, op).map_err(|e| format!("SQLite: {:?}: {}", op, e))?;
+            let res = match *op {
+                Op::Insert(t, ref row) => {
+                    let row: Vec<DataType> = row.iter().map(|&v| v.into()).collect();
+                    tables.get_mut(&schema[t].name).unwrap().insert(row).await
+                }
+                Op::Update(t, id, column, value) => {
+                    let set = vec![(column, Modification::Set(value.into()))];
+                    let table = tables.get_mut(&schema[t].name).unwrap();
+                    table.update(vec![id.into()], set).await
+                }
+                Op::Delete(t, id) => {
+                    let table = tables.get_mut(&schema[t].name).unwrap();
+                    table.delete(vec![id.into()]).await
+                }
+            };
+            res.map_err(|e| format!("{:?} failed: {:?}", op, e))?;
+        }
+
+        for query in &queries {
+            let mut view = g.view(&query.name).await.unwrap();
+            for key in 0..KEYS {
+                let expected = sqlite_read(&db, query, key)
+                    .map_err(|e| format!("SQLite: {}: {}", query.sql, e))?;
+                let start = Instant::now();
+                loop {
+                    let got = view.lookup(&[key.into()], true).await.unwrap();
+                    let got = normalize(got.into());
+                    if got == expected {
+                        break;
+                    }
+                    let known = KNOWN_DIFFERENCES
+                        .iter()
+                        .find(|d| (d.applies)(query, &got, &expected));
+                    if let Some(known) = known {
+                        *excluded.entry(known.name).or_insert(0) += 1;
+                        break;
+                    }
+                    if start.elapsed() > Duration::from_secs(5) {
+                        return Err(format!(
+                            "{} for key {} returned {:?}, SQLite returned {:?}",
+                            query.sql, key, got, expected
+                        ));
+                    }
+                    tokio::time::delay_for(Duration::from_millis(50)).await;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn oracle_consistency() {
+    let seeds: Vec<u64> = match env::var("ORACLE_SEED") {
+        Ok(seed) => vec![seed.parse().expect("ORACLE_SEED must be a number")],
+        Err(_) => {
+            let cases = env::var("ORACLE_CASES")
+                .map(|n| n.parse().expect("ORACLE_CASES must be a number"))
+                .unwrap_or(5);
+            (0..cases).map(|_| rand::random()).collect()
+        }
+    };
+
+    let mut excluded = BTreeMap::new();
+    for seed in seeds {
+        if let Err(e) = run_case(seed, &mut excluded).await {
+            panic!("oracle mismatch with ORACLE_SEED={}: {}", seed, e);
+        }
+    }
+    for (difference, n) in excluded {
+        eprintln!("ignored {} results that differ by {}", n, difference);
+    }
+}
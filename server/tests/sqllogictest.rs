@@ -0,0 +1,370 @@
+//! A runner for test files in the sqllogictest format.
+//!
+//! Every `.slt` file in `tests/slt` (or in the directory named by `SLT_PATH`) is run against a
+//! fresh local Noria instance. `CREATE TABLE` statements extend the recipe, `INSERT` and `DELETE`
+//! statements are turned into writes through `Table` handles, and each `query` record is installed
+//! as a new view and read through a `View` handle.
+//!
+//! Since queries become views, only the parts of the format that make sense for Noria are
+//! supported: `statement ok`/`statement error`, `query` records with `nosort`, `rowsort`, and
+//! `valuesort`, `skipif`/`onlyif` conditions (Noria's database name is `noria`), and `halt`.
+//! Records with hashed results are skipped.
+//!
+//! As in the original format, expected results are given one value per line. A query's results
+//! may instead be given one row per line, with the values separated by whitespace, by writing
+//! `---- rows` rather than `----` above them. Values in such rows cannot contain whitespace.
+
+use nom_sql::{ConditionBase, ConditionExpression, ConditionTree, Literal, Operator, SqlQuery};
+use noria_server::{Builder, ControllerHandle, DataType, LocalAuthority};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+enum SortMode {
+    NoSort,
+    RowSort,
+    ValueSort,
+}
+
+#[derive(Debug)]
+enum Record {
+    Statement {
+        sql: String,
+        ok: bool,
+    },
+    Query {
+        sql: String,
+        types: Vec<char>,
+        sort: SortMode,
+        /// Whether `expected` has one row, rather than one value, per line.
+        rowwise: bool,
+        expected: Vec<String>,
+    },
+    Halt,
+}
+
+/// Parse a sqllogictest file into the records that apply to Noria, along with their line numbers.
+fn parse(contents: &str) -> Result<Vec<(usize, Record)>, String> {
+    let mut records = Vec::new();
+    let mut lines = contents.lines().enumerate().peekable();
+    let mut skip = false;
+    while let Some((i, line)) = lines.next() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words: Vec<_> = line.split_whitespace().collect();
+        let mut body = Vec::new();
+        let mut expected = Vec::new();
+        let mut in_results = false;
+        let mut rowwise = false;
+        if words[0] == "statement" || words[0] == "query" {
+            while let Some(&(_, l)) = lines.peek() {
+                let l = l.trim_end();
+                lines.next();
+                if l.is_empty() {
+                    break;
+                } else if l == "----" {
+                    in_results = true;
+                } else if l == "---- rows" {
+                    in_results = true;
+                    rowwise = true;
+                } else if in_results {
+                    expected.push(l.to_owned());
+                } else {
+                    body.push(l);
+                }
+            }
+        }
+        let sql = body.join("\n");
+
+        let record = match words[0] {
+            "skipif" => {
+                skip = skip || words.get(1) == Some(&"noria");
+                continue;
+            }
+            "onlyif" => {
+                skip = skip || words.get(1) != Some(&"noria");
+                continue;
+            }
+            "hash-threshold" => continue,
+            "halt" => Record::Halt,
+            "statement" => Record::Statement {
+                sql,
+                ok: match words.get(1) {
+                    Some(&"ok") => true,
+                    Some(&"error") => false,
+                    other => return Err(format!("line {}: bad statement mode {:?}", i + 1, other)),
+                },
+            },
+            "query" => {
+                let types = words
+                    .get(1)
+                    .map(|t| t.chars().collect())
+                    .unwrap_or_default();
+                let sort = match words.get(2) {
+                    None | Some(&"nosort") => SortMode::NoSort,
+                    Some(&"rowsort") => SortMode::RowSort,
+                    Some(&"valuesort") => SortMode::ValueSort,
+                    Some(other) => return Err(format!("line {}: bad sort mode {}", i + 1, other)),
+                };
+                if expected.iter().any(|l| l.contains("values hashing to")) {
+                    skip = true;
+                }
+                Record::Query {
+                    sql,
+                    types,
+                    sort,
+                    rowwise,
+                    expected,
+                }
+            }
+            other => return Err(format!("line {}: unknown record type {}", i + 1, other)),
+        };
+
+        if !skip {
+            records.push((i + 1, record));
+        }
+        skip = false;
+    }
+    Ok(records)
+}
+
+fn format_value(v: &DataType, t: char) -> String {
+    match *v {
+        DataType::None => "NULL".to_owned(),
+        _ => match t {
+            'I' => i64::from(v).to_string(),
+            'R' => format!("{:.3}", f64::from(v)),
+            _ => match *v {
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = v.into();
+                    if s.is_empty() {
+                        "(empty)".to_owned()
+                    } else {
+                        s.to_owned()
+                    }
+                }
+                _ => v.to_string(),
+            },
+        },
+    }
+}
+
+fn literal_column<'a>(cond: &'a ConditionExpression) -> Option<(&'a str, &'a Literal)> {
+    if let ConditionExpression::ComparisonOp(ConditionTree {
+        operator: Operator::Equal,
+        ref left,
+        ref right,
+    }) = *cond
+    {
+        if let (
+            ConditionExpression::Base(ConditionBase::Field(ref c)),
+            ConditionExpression::Base(ConditionBase::Literal(ref l)),
+        ) = (&**left, &**right)
+        {
+            return Some((&c.name, l));
+        }
+    }
+    None
+}
+
+struct Runner {
+    g: ControllerHandle<LocalAuthority>,
+    queries: usize,
+}
+
+impl Runner {
+    async fn statement(&mut self, sql: &str) -> Result<(), String> {
+        let q = nom_sql::parse_query(sql).map_err(|e| format!("failed to parse: {}", e))?;
+        match q {
+            SqlQuery::CreateTable(_) | SqlQuery::CreateView(_) => self
+                .g
+                .extend_recipe(&format!("{};", sql.trim_end_matches(';')))
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("{:?}", e)),
+            SqlQuery::Insert(ins) => {
+                let mut t = self
+                    .g
+                    .table(&ins.table.name)
+                    .await
+                    .map_err(|e| format!("{:?}", e))?;
+                let columns = t.columns().to_vec();
+                for values in ins.data {
+                    let mut row = vec![DataType::None; columns.len()];
+                    match ins.fields {
+                        Some(ref fields) => {
+                            for (f, v) in fields.iter().zip(values) {
+                                let i = columns
+                                    .iter()
+                                    .position(|c| c == &f.name)
+                                    .ok_or_else(|| format!("no column {}", f.name))?;
+                                row[i] = v.into();
+                            }
+                        }
+                        None => {
+                            for (i, v) in values.into_iter().enumerate() {
+                                row[i] = v.into();
+                            }
+                        }
+                    }
+                    t.insert(row).await.map_err(|e| format!("{:?}", e))?;
+                }
+                Ok(())
+            }
+            SqlQuery::Delete(del) => {
+                // only deletes by key are supported, since that's all a Table can do
+                let key = del
+                    .where_clause
+                    .as_ref()
+                    .and_then(literal_column)
+                    .ok_or_else(|| "only DELETE ... WHERE key = value is supported".to_owned())?;
+                let mut t = self
+                    .g
+                    .table(&del.table.name)
+                    .await
+                    .map_err(|e| format!("{:?}", e))?;
+                t.delete(vec![DataType::from(key.1)])
+                    .await
+                    .map_err(|e| format!("{:?}", e))
+            }
+            q => Err(format!("unsupported statement: {:?}", q)),
+        }
+    }
+
+    async fn query(
+        &mut self,
+        sql: &str,
+        types: &[char],
+        sort: &SortMode,
+    ) -> Result<Vec<String>, String> {
+        self.queries += 1;
+        let name = format!("slt_{}", self.queries);
+        self.g
+            .extend_recipe(&format!("QUERY {}: {};", name, sql.trim_end_matches(';')))
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        let mut view = self.g.view(&name).await.map_err(|e| format!("{:?}", e))?;
+
+        // views without parameters are keyed by a constant
+        let rows: Vec<Vec<DataType>> = view
+            .lookup(&[0.into()], true)
+            .await
+            .map_err(|e| format!("{:?}", e))?
+            .into();
+        let mut rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| {
+                // any columns beyond those in the result types are internal to Noria
+                row.iter()
+                    .zip(types)
+                    .map(|(v, &t)| format_value(v, t))
+                    .collect()
+            })
+            .collect();
+
+        match *sort {
+            SortMode::NoSort => {}
+            SortMode::RowSort => rows.sort(),
+            SortMode::ValueSort => {
+                let mut values: Vec<_> = rows.into_iter().flatten().collect();
+                values.sort();
+                return Ok(values);
+            }
+        }
+        Ok(rows.into_iter().flatten().collect())
+    }
+}
+
+async fn run_file(path: &Path) -> Result<(), String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let records = parse(&contents)?;
+
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    let (g, _) = builder.start_local().await.unwrap();
+    let mut runner = Runner {
+        g: (*g).clone(),
+        queries: 0,
+    };
+
+    for (line, record) in records {
+        match record {
+            Record::Halt => break,
+            Record::Statement { sql, ok } => match (runner.statement(&sql).await, ok) {
+                (Ok(()), true) | (Err(_), false) => {}
+                (Ok(()), false) => {
+                    return Err(format!("line {}: statement should have failed", line))
+                }
+                (Err(e), true) => return Err(format!("line {}: statement failed: {}", line, e)),
+            },
+            Record::Query {
+                sql,
+                types,
+                sort,
+                rowwise,
+                expected,
+            } => {
+                let got = runner
+                    .query(&sql, &types, &sort)
+                    .await
+                    .map_err(|e| format!("line {}: query failed: {}", line, e))?;
+
+                let expected: Vec<String> = if rowwise {
+                    let mut values = Vec::new();
+                    for l in &expected {
+                        let row: Vec<_> = l.split_whitespace().map(String::from).collect();
+                        if row.len() != types.len() {
+                            return Err(format!(
+                                "line {}: expected row {:?} does not have {} values",
+                                line,
+                                l,
+                                types.len()
+                            ));
+                        }
+                        values.extend(row);
+                    }
+                    values
+                } else {
+                    expected
+                };
+                if got != expected {
+                    return Err(format!(
+                        "line {}: query returned {:?}, expected {:?}",
+                        line, got, expected
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn sqllogictest() {
+    let dir = std::env::var("SLT_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests")
+                .join("slt")
+        });
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "slt").unwrap_or(false))
+        .collect();
+    files.sort();
+
+    let mut failed = Vec::new();
+    for file in files {
+        if let Err(e) = run_file(&file).await {
+            eprintln!("{}: {}", file.display(), e);
+            failed.push(file);
+        }
+    }
+    assert!(failed.is_empty(), "failed: {:?}", failed);
+}
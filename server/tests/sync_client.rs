@@ -0,0 +1,92 @@
+//! Tests for the blocking client facade, which must be used from outside of any runtime.
+
+use noria_server::sync::SyncControllerHandle;
+use noria_server::{Builder, LocalAuthority};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn sync_client_works() {
+    // the server runs on its own runtime, just like it would in a separate process
+    let mut rt = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    let authority = Arc::new(LocalAuthority::new());
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    let (g, _) = rt.block_on(builder.start(authority.clone())).unwrap();
+
+    let mut db = SyncControllerHandle::make(authority).unwrap();
+    db.install_recipe(
+        "CREATE TABLE Article (aid int, title varchar(255), PRIMARY KEY(aid));
+         QUERY ArticleById: SELECT aid, title FROM Article WHERE aid = ?;",
+    )
+    .unwrap();
+    assert_eq!(db.inputs().unwrap().len(), 1);
+    assert_eq!(db.outputs().unwrap().len(), 1);
+
+    let mut article = db.table("Article").unwrap();
+    let mut by_id = db.view("ArticleById").unwrap();
+    article
+        .insert(vec![1.into(), "I love Soup".into()])
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        by_id.lookup(&[1.into()], true).unwrap(),
+        vec![vec![1.into(), "I love Soup".into()]]
+    );
+
+    // handles can be used from other threads too
+    let mut by_id2 = by_id.clone();
+    thread::spawn(move || {
+        assert_eq!(by_id2.lookup(&[1.into()], true).unwrap().len(), 1);
+    })
+    .join()
+    .unwrap();
+
+    article.delete(vec![1.into()]).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert!(by_id.lookup(&[1.into()], true).unwrap().is_empty());
+
+    drop(db);
+    rt.block_on(async move { drop(g) });
+}
+
+#[test]
+fn sync_client_blocks_concurrently() {
+    let mut rt = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    let authority = Arc::new(LocalAuthority::new());
+    let mut builder = Builder::default();
+    builder.set_sharding(None);
+    let (g, _) = rt.block_on(builder.start(authority.clone())).unwrap();
+
+    let db = SyncControllerHandle::make(authority).unwrap();
+
+    // a slow call on one thread does not hold up calls on other threads
+    let slow = db.clone();
+    let waiter = thread::spawn(move || {
+        slow.run(tokio::time::delay_for(Duration::from_secs(2)));
+    });
+    thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    assert_eq!(db.run(async { 42 }), 42);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    waiter.join().unwrap();
+
+    // and a panic on one thread does not break the handles on other threads
+    let panicky = db.clone();
+    assert!(thread::spawn(move || panicky.run(async { panic!("boom") }))
+        .join()
+        .is_err());
+    assert_eq!(db.run(async { 42 }), 42);
+
+    drop(db);
+    rt.block_on(async move { drop(g) });
+}